@@ -17,6 +17,11 @@ echo "$(cat /var/lib/zerotier-one/authtoken.secret)"
 echo "=========================================="
 echo ""
 
+# Headless setup: the local ZeroTier token is used unless one is given
+if [ -n "$TIERDROP_ADMIN_USER" ] && [ -z "$TIERDROP_ZT_TOKEN" ]; then
+    export TIERDROP_ZT_TOKEN="$(cat /var/lib/zerotier-one/authtoken.secret)"
+fi
+
 # Start TierDrop (bind to all interfaces for container networking)
 export TIERDROP_BIND="0.0.0.0:8000"
 exec tierdrop
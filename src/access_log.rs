@@ -0,0 +1,55 @@
+//! Optional log line per HTTP request: method, path, status, duration, user and client address.
+//!
+//! Complements the audit log, which records what changed but not every page or API call.
+//! Written at info level to the `tierdrop::access` target, inside the request's ID span.
+
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use axum::body::Body;
+use axum::extract::{ConnectInfo, State};
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::sessions::ClientInfo;
+use crate::state::AppState;
+
+/// Who made the request, handed back out of the auth middleware on the response
+#[derive(Clone)]
+pub struct AccessUser {
+    pub username: String,
+    pub api_key: bool,
+}
+
+pub async fn access_log_middleware(State(state): State<AppState>, request: Request<Body>, next: Next) -> Response {
+    let enabled = state.config.read().await.as_ref().is_some_and(|c| c.access_log);
+    if !enabled {
+        return next.run(request).await;
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|c| c.0);
+    let client = ClientInfo::new(request.headers(), peer);
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    let user = match response.extensions().get::<AccessUser>() {
+        Some(AccessUser { username, api_key: true }) => format!("{} (API key)", username),
+        Some(AccessUser { username, api_key: false }) => username.clone(),
+        None => "-".to_string(),
+    };
+    tracing::info!(
+        target: "tierdrop::access",
+        "{} {} {} {}ms user={} ip={}",
+        method,
+        path,
+        response.status().as_u16(),
+        started.elapsed().as_millis(),
+        user,
+        client.display_ip()
+    );
+    response
+}
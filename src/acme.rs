@@ -0,0 +1,476 @@
+//! Certificates from Let's Encrypt or another ACME CA (RFC 8555), validated with
+//! TLS-ALPN-01 (RFC 8737) on TierDrop's own HTTPS port.
+//!
+//! The CA connects to port 443 of each domain, so TierDrop must be reachable there. The
+//! account key, certificate and its key are kept in `acme/` in the data directory.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use rcgen::{CertificateParams, CustomExtension, KeyPair};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair as _, ECDSA_P256_SHA256_FIXED_SIGNING};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::PrivatePkcs8KeyDer;
+use rustls::sign::CertifiedKey;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::state;
+use crate::tls::{self, CertResolver};
+
+pub const LETS_ENCRYPT_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+/// Renew when the certificate has less than this left
+const RENEW_BEFORE_DAYS: i64 = 30;
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone)]
+pub struct AcmeSettings {
+    pub domains: Vec<String>,
+    pub email: Option<String>,
+    pub directory: String,
+}
+
+impl AcmeSettings {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(domains) = std::env::var("TIERDROP_ACME_DOMAINS") else {
+            return Ok(None);
+        };
+        let domains: Vec<String> = domains
+            .split(',')
+            .map(|d| d.trim().to_ascii_lowercase())
+            .filter(|d| !d.is_empty())
+            .collect();
+        if domains.is_empty() {
+            return Ok(None);
+        }
+        for domain in &domains {
+            let valid = domain.contains('.')
+                && domain.split('.').all(|label| {
+                    !label.is_empty() && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+                });
+            if !valid {
+                return Err(format!("TIERDROP_ACME_DOMAINS: {} is not a domain name", domain));
+            }
+        }
+        let email = std::env::var("TIERDROP_ACME_EMAIL").ok().map(|e| e.trim().to_string()).filter(|e| !e.is_empty());
+        if email.as_ref().is_some_and(|e| !e.contains('@')) {
+            return Err("TIERDROP_ACME_EMAIL must be an email address".to_string());
+        }
+        let directory = std::env::var("TIERDROP_ACME_DIRECTORY")
+            .ok()
+            .map(|d| d.trim().to_string())
+            .filter(|d| !d.is_empty())
+            .unwrap_or_else(|| LETS_ENCRYPT_DIRECTORY.to_string());
+        Ok(Some(Self { domains, email, directory }))
+    }
+}
+
+pub fn acme_dir() -> PathBuf {
+    state::data_dir().join("acme")
+}
+
+fn cert_path() -> PathBuf {
+    acme_dir().join("cert.pem")
+}
+
+fn key_path() -> PathBuf {
+    acme_dir().join("key.pem")
+}
+
+/// Domains the stored certificate was issued for, one per line
+fn domains_path() -> PathBuf {
+    acme_dir().join("domains")
+}
+
+/// The stored certificate, if it was issued for the configured domains
+pub fn cached_certificate(settings: &AcmeSettings) -> Option<(CertifiedKey, Option<DateTime<Utc>>)> {
+    let domains = std::fs::read_to_string(domains_path()).ok()?;
+    if domains.lines().collect::<Vec<_>>() != settings.domains {
+        return None;
+    }
+    let cert = std::fs::read(cert_path()).ok()?;
+    let key = std::fs::read(key_path()).ok()?;
+    match tls::load_pem(&cert, &key) {
+        Ok(loaded) => Some(loaded),
+        Err(e) => {
+            tracing::warn!("Ignoring stored ACME certificate: {}", e);
+            None
+        }
+    }
+}
+
+/// Obtain a certificate when there is none or it is due for renewal, then check twice a day
+pub async fn maintain(settings: AcmeSettings, resolver: Arc<CertResolver>) {
+    loop {
+        let due = match cached_certificate(&settings) {
+            Some((_, Some(not_after))) => not_after - Utc::now() < chrono::Duration::days(RENEW_BEFORE_DAYS),
+            _ => true,
+        };
+        let wait = if !due {
+            CHECK_INTERVAL
+        } else {
+            tracing::info!("Requesting a TLS certificate for {} from {}", settings.domains.join(", "), settings.directory);
+            let result = obtain(&settings, &resolver).await;
+            resolver.clear_challenges();
+            match result.and_then(|(cert, key)| store(&settings, &cert, &key).map(|_| (cert, key))) {
+                Ok((cert, key)) => match tls::load_pem(cert.as_bytes(), key.as_bytes()) {
+                    Ok((certified, not_after)) => {
+                        resolver.set(certified);
+                        match not_after {
+                            Some(t) => tracing::info!("Obtained TLS certificate, valid until {}", t.format("%Y-%m-%d")),
+                            None => tracing::info!("Obtained TLS certificate"),
+                        }
+                        CHECK_INTERVAL
+                    }
+                    Err(e) => {
+                        tracing::error!("The CA returned an unusable certificate: {}", e);
+                        RETRY_INTERVAL
+                    }
+                },
+                Err(e) => {
+                    tracing::error!("Failed to obtain a TLS certificate: {}", e);
+                    RETRY_INTERVAL
+                }
+            }
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+fn store(settings: &AcmeSettings, cert: &str, key: &str) -> Result<(), String> {
+    std::fs::create_dir_all(acme_dir()).map_err(|e| format!("Failed to create {}: {}", acme_dir().display(), e))?;
+    write_private(&key_path(), key.as_bytes())?;
+    std::fs::write(cert_path(), cert).map_err(|e| format!("Failed to write {}: {}", cert_path().display(), e))?;
+    std::fs::write(domains_path(), settings.domains.join("\n"))
+        .map_err(|e| format!("Failed to write {}: {}", domains_path().display(), e))
+}
+
+/// Write a key file only the service user can read
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    std::io::Write::write_all(&mut file, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// The ACME account key, created on first use
+fn account_key() -> Result<EcdsaKeyPair, String> {
+    let path = acme_dir().join("account.pem");
+    let rng = SystemRandom::new();
+    let pkcs8 = match std::fs::read(&path) {
+        Ok(pem) => PrivatePkcs8KeyDer::from_pem_slice(&pem)
+            .map_err(|e| format!("Invalid account key in {}: {}", path.display(), e))?
+            .secret_pkcs8_der()
+            .to_vec(),
+        Err(_) => {
+            let document = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                .map_err(|_| "Failed to generate an account key")?;
+            std::fs::create_dir_all(acme_dir()).map_err(|e| format!("Failed to create {}: {}", acme_dir().display(), e))?;
+            let pem = pem_encode("PRIVATE KEY", document.as_ref());
+            write_private(&path, pem.as_bytes())?;
+            document.as_ref().to_vec()
+        }
+    };
+    EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng).map_err(|_| "Invalid account key".to_string())
+}
+
+fn pem_encode(label: &str, der: &[u8]) -> String {
+    let body = base64::engine::general_purpose::STANDARD.encode(der);
+    let lines: Vec<&str> = body.as_bytes().chunks(64).map(|c| std::str::from_utf8(c).unwrap_or_default()).collect();
+    format!("-----BEGIN {}-----\n{}\n-----END {}-----\n", label, lines.join("\n"), label)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    #[serde(default)]
+    certificate: Option<String>,
+    #[serde(default)]
+    error: Option<Problem>,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    #[serde(default)]
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    error: Option<Problem>,
+}
+
+/// ACME error document (RFC 7807)
+#[derive(Deserialize)]
+struct Problem {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    detail: String,
+}
+
+/// An ACME account session: signs requests and tracks the replay nonce
+struct Client {
+    http: reqwest::Client,
+    directory: Directory,
+    key: EcdsaKeyPair,
+    jwk: serde_json::Value,
+    /// Account URL, used as the key ID once registered
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+impl Client {
+    async fn new(directory_url: &str) -> Result<Self, String> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .unwrap_or_default();
+        let directory = http
+            .get(directory_url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("ACME directory request failed: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid ACME directory: {}", e))?;
+        let key = account_key()?;
+        // Uncompressed P-256 point: 0x04 || x || y
+        let point = key.public_key().as_ref();
+        let jwk = json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+            "y": URL_SAFE_NO_PAD.encode(&point[33..65]),
+        });
+        Ok(Self { http, directory, key, jwk, kid: None, nonce: None })
+    }
+
+    /// RFC 7638 thumbprint; members in lexicographic order without whitespace
+    fn thumbprint(&self) -> String {
+        let canonical = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            self.jwk["x"].as_str().unwrap_or_default(),
+            self.jwk["y"].as_str().unwrap_or_default()
+        );
+        URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()))
+    }
+
+    async fn fresh_nonce(&self) -> Result<String, String> {
+        let resp = self
+            .http
+            .head(&self.directory.new_nonce)
+            .send()
+            .await
+            .map_err(|e| format!("ACME nonce request failed: {}", e))?;
+        replay_nonce(&resp).ok_or_else(|| "ACME server sent no nonce".to_string())
+    }
+
+    /// Signed POST; `None` payload is a POST-as-GET. Retries once on a stale nonce.
+    async fn post(&mut self, url: &str, payload: Option<&serde_json::Value>) -> Result<reqwest::Response, String> {
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => self.fresh_nonce().await?,
+            };
+            let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+            match &self.kid {
+                Some(kid) => protected["kid"] = json!(kid),
+                None => protected["jwk"] = self.jwk.clone(),
+            }
+            let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+            let payload = payload.map(|p| URL_SAFE_NO_PAD.encode(p.to_string())).unwrap_or_default();
+            let signature = self
+                .key
+                .sign(&SystemRandom::new(), format!("{}.{}", protected, payload).as_bytes())
+                .map_err(|_| "Failed to sign ACME request")?;
+            let body = json!({
+                "protected": protected,
+                "payload": payload,
+                "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+            });
+
+            let resp = self
+                .http
+                .post(url)
+                .header("Content-Type", "application/jose+json")
+                .body(body.to_string())
+                .send()
+                .await
+                .map_err(|e| format!("ACME request failed: {}", e))?;
+            self.nonce = replay_nonce(&resp);
+            if resp.status().is_success() {
+                return Ok(resp);
+            }
+
+            let status = resp.status();
+            let problem: Option<Problem> = resp.json().await.ok();
+            if !retried && problem.as_ref().is_some_and(|p| p.kind.ends_with(":badNonce")) {
+                retried = true;
+                continue;
+            }
+            return Err(match problem {
+                Some(p) if !p.detail.is_empty() => format!("{} ({})", p.detail, status),
+                _ => format!("ACME server returned {}", status),
+            });
+        }
+    }
+
+    async fn post_json<T: serde::de::DeserializeOwned>(
+        &mut self,
+        url: &str,
+        payload: Option<&serde_json::Value>,
+    ) -> Result<T, String> {
+        self.post(url, payload)
+            .await?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid ACME response: {}", e))
+    }
+
+    /// Create the account, or look up the existing one for this key
+    async fn register(&mut self, email: Option<&str>) -> Result<(), String> {
+        let mut payload = json!({ "termsOfServiceAgreed": true });
+        if let Some(email) = email {
+            payload["contact"] = json!([format!("mailto:{}", email)]);
+        }
+        let url = self.directory.new_account.clone();
+        let resp = self.post(&url, Some(&payload)).await?;
+        self.kid = Some(location(&resp).ok_or("ACME server sent no account URL")?);
+        Ok(())
+    }
+}
+
+fn replay_nonce(resp: &reqwest::Response) -> Option<String> {
+    resp.headers().get("replay-nonce")?.to_str().ok().map(str::to_string)
+}
+
+fn location(resp: &reqwest::Response) -> Option<String> {
+    resp.headers().get("location")?.to_str().ok().map(str::to_string)
+}
+
+/// Self-signed certificate proving control of `domain` to a TLS-ALPN-01 validator
+fn challenge_certificate(domain: &str, key_authorization: &str) -> Result<CertifiedKey, String> {
+    let key = KeyPair::generate().map_err(|e| format!("Failed to generate a challenge key: {}", e))?;
+    let mut params = CertificateParams::new(vec![domain.to_string()]).map_err(|e| e.to_string())?;
+    params.custom_extensions = vec![CustomExtension::new_acme_identifier(&Sha256::digest(key_authorization.as_bytes()))];
+    let cert = params.self_signed(&key).map_err(|e| format!("Failed to create a challenge certificate: {}", e))?;
+    // Not `load_pem`: certificate parsers reject the critical acmeIdentifier extension
+    tls::certified_key(vec![cert.der().clone()], key.serialize_pem().as_bytes())
+}
+
+/// Poll until the server has finished with an order or authorization
+async fn poll<T, F>(client: &mut Client, url: &str, pending: F) -> Result<T, String>
+where
+    T: serde::de::DeserializeOwned,
+    F: Fn(&T) -> bool,
+{
+    for _ in 0..30 {
+        let item: T = client.post_json(url, None).await?;
+        if !pending(&item) {
+            return Ok(item);
+        }
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+    Err("Timed out waiting for the ACME server".to_string())
+}
+
+/// Run an order through to a certificate. Returns the PEM chain and its private key.
+async fn obtain(settings: &AcmeSettings, resolver: &CertResolver) -> Result<(String, String), String> {
+    let mut client = Client::new(&settings.directory).await?;
+    client.register(settings.email.as_deref()).await?;
+
+    let identifiers: Vec<_> = settings.domains.iter().map(|d| json!({ "type": "dns", "value": d })).collect();
+    let new_order = client.directory.new_order.clone();
+    let resp = client.post(&new_order, Some(&json!({ "identifiers": identifiers }))).await?;
+    let order_url = location(&resp).ok_or("ACME server sent no order URL")?;
+    let order: Order = resp.json().await.map_err(|e| format!("Invalid ACME order: {}", e))?;
+
+    let thumbprint = client.thumbprint();
+    for authz_url in &order.authorizations {
+        let authz: Authorization = client.post_json(authz_url, None).await?;
+        if authz.status == "valid" {
+            continue;
+        }
+        let domain = authz.identifier.value;
+        let challenge = authz
+            .challenges
+            .iter()
+            .find(|c| c.kind == "tls-alpn-01")
+            .ok_or_else(|| format!("The CA offers no TLS-ALPN-01 challenge for {}", domain))?;
+        let key_authorization = format!("{}.{}", challenge.token, thumbprint);
+        resolver.add_challenge(&domain, challenge_certificate(&domain, &key_authorization)?);
+
+        client.post(&challenge.url, Some(&json!({}))).await?;
+        let authz: Authorization = poll(&mut client, authz_url, |a: &Authorization| a.status == "pending").await?;
+        if authz.status != "valid" {
+            let reason = authz
+                .challenges
+                .into_iter()
+                .find_map(|c| c.error)
+                .map(|p| p.detail)
+                .unwrap_or_else(|| authz.status.clone());
+            return Err(format!("Validation of {} failed: {}", domain, reason));
+        }
+    }
+
+    let key = KeyPair::generate().map_err(|e| format!("Failed to generate a certificate key: {}", e))?;
+    let csr = CertificateParams::new(settings.domains.clone())
+        .and_then(|params| params.serialize_request(&key))
+        .map_err(|e| format!("Failed to create a certificate request: {}", e))?;
+    client
+        .post(&order.finalize, Some(&json!({ "csr": URL_SAFE_NO_PAD.encode(csr.der()) })))
+        .await?;
+
+    let order: Order = poll(&mut client, &order_url, |o: &Order| matches!(o.status.as_str(), "pending" | "ready" | "processing")).await?;
+    let certificate_url = match (order.status.as_str(), order.certificate) {
+        ("valid", Some(url)) => url,
+        (status, _) => {
+            let reason = order.error.map(|p| p.detail).unwrap_or_else(|| status.to_string());
+            return Err(format!("Order failed: {}", reason));
+        }
+    };
+    let chain = client
+        .post(&certificate_url, None)
+        .await?
+        .text()
+        .await
+        .map_err(|e| format!("Failed to download the certificate: {}", e))?;
+    Ok((chain, key.serialize_pem()))
+}
@@ -0,0 +1,153 @@
+//! Member agents: a small script on a member that reports what the device is, since a node ID
+//! alone says nothing about it. Each network has its own token, so a token copied off one
+//! device can only report for members of that network.
+
+use std::collections::HashMap;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::state::Config;
+
+/// Every agent token starts with this, to tell it apart from personal API keys
+pub const TOKEN_PREFIX: &str = "tda_";
+
+/// How stale `reported_at` may get before an unchanged report saves the config to update it
+const REPORTED_AT_RESOLUTION_MINUTES: i64 = 5;
+
+/// Longest hostname, OS or version kept
+const MAX_FIELD_LEN: usize = 128;
+
+/// What a member's agent last said about the device
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AgentReport {
+    pub nwid: String,
+    pub hostname: String,
+    pub os: String,
+    /// ZeroTier version as the device reports it
+    pub zt_version: String,
+    pub reported_at: DateTime<Utc>,
+}
+
+impl AgentReport {
+    pub fn display_reported_at(&self) -> String {
+        crate::preferences::format_time(self.reported_at)
+    }
+
+    /// Hostname, OS and version on one line, leaving out whatever wasn't reported
+    pub fn summary(&self) -> String {
+        let version = (!self.zt_version.is_empty()).then(|| format!("ZT {}", self.zt_version));
+        [Some(self.hostname.clone()), Some(self.os.clone()), version]
+            .into_iter()
+            .flatten()
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(" · ")
+    }
+}
+
+/// Body of POST /api/v1/agent/report
+#[derive(Deserialize)]
+pub struct ReportRequest {
+    pub member_id: String,
+    #[serde(default)]
+    pub hostname: String,
+    #[serde(default)]
+    pub os: String,
+    #[serde(default)]
+    pub zt_version: String,
+}
+
+/// Create a token, returning its hash to store and the token to show once
+pub fn generate_token() -> (String, String) {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let token = format!("{}{}", TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(secret));
+    (hash_token(&token), token)
+}
+
+fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Key of a report in `Config::agent_reports`. A member in several networks has a report for
+/// each, as each network's agent token may only speak for that network.
+fn report_key(nwid: &str, member_id: &str) -> String {
+    format!("{}-{}", nwid, member_id)
+}
+
+/// A reported field trimmed to one printable line of reasonable length
+fn clean(value: &str) -> String {
+    value
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_FIELD_LEN)
+        .collect()
+}
+
+impl Config {
+    /// The network `token` reports for
+    pub fn agent_network(&self, token: &str) -> Option<String> {
+        let hash = hash_token(token);
+        self.agent_tokens
+            .iter()
+            .find(|(_, h)| **h == hash)
+            .map(|(nwid, _)| nwid.clone())
+    }
+
+    /// Record a report for a member of `nwid`. Returns whether anything changed enough to save.
+    pub fn record_agent_report(&mut self, nwid: &str, request: &ReportRequest) -> bool {
+        let now = Utc::now();
+        let report = AgentReport {
+            nwid: nwid.to_string(),
+            hostname: clean(&request.hostname),
+            os: clean(&request.os),
+            zt_version: clean(&request.zt_version),
+            reported_at: now,
+        };
+        let key = report_key(nwid, &request.member_id.trim().to_ascii_lowercase());
+        let unchanged = self.agent_reports.get(&key).is_some_and(|old| {
+            old.hostname == report.hostname
+                && old.os == report.os
+                && old.zt_version == report.zt_version
+                && now - old.reported_at <= Duration::minutes(REPORTED_AT_RESOLUTION_MINUTES)
+        });
+        if unchanged {
+            return false;
+        }
+        self.agent_reports.insert(key, report);
+        true
+    }
+
+    /// What the member's agent last reported for `nwid`
+    pub fn agent_report(&self, nwid: &str, member_id: &str) -> Option<&AgentReport> {
+        self.agent_reports.get(&report_key(nwid, member_id))
+    }
+
+    /// The reports for `nwid`, by member ID
+    pub fn agent_reports_for(&self, nwid: &str) -> HashMap<String, AgentReport> {
+        let prefix = report_key(nwid, "");
+        self.agent_reports
+            .iter()
+            .filter_map(|(key, report)| Some((key.strip_prefix(&prefix)?.to_string(), report.clone())))
+            .collect()
+    }
+
+    /// Forget every report for `nwid`, once its token is revoked or the network deleted.
+    /// Returns whether any were dropped.
+    pub fn drop_agent_reports(&mut self, nwid: &str) -> bool {
+        let before = self.agent_reports.len();
+        self.agent_reports.retain(|_, report| report.nwid != nwid);
+        self.agent_reports.len() != before
+    }
+
+    /// Forget a member's report for `nwid`, once the member is deleted
+    pub fn drop_agent_report(&mut self, nwid: &str, member_id: &str) -> bool {
+        self.agent_reports.remove(&report_key(nwid, member_id)).is_some()
+    }
+}
@@ -0,0 +1,94 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::http::header::AUTHORIZATION;
+use axum::http::HeaderMap;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::state::Config;
+
+/// Every key starts with this so it is recognisable in scripts and secret scanners
+const KEY_PREFIX: &str = "td_";
+
+/// How stale `last_used` may get before a request saves the config to update it
+const LAST_USED_RESOLUTION_MINUTES: i64 = 5;
+
+/// A personal API key. Requests made with it act as the owning user, with their permissions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// SHA-256 of the full key, base64url. The key itself is only shown once, when created.
+    pub hash: String,
+    /// First characters of the key, to tell keys apart
+    pub hint: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    /// Create a key, returning it with the secret to show the user
+    pub fn generate(name: String) -> (Self, String) {
+        let mut id = [0u8; 9];
+        OsRng.fill_bytes(&mut id);
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        let key = format!("{}{}", KEY_PREFIX, URL_SAFE_NO_PAD.encode(secret));
+        let api_key = Self {
+            id: URL_SAFE_NO_PAD.encode(id),
+            name,
+            hash: hash_key(&key),
+            hint: key.chars().take(KEY_PREFIX.len() + 6).collect(),
+            created_at: Utc::now(),
+            last_used: None,
+        };
+        (api_key, key)
+    }
+
+    pub fn display_created_at(&self) -> String {
+        crate::preferences::format_time(self.created_at)
+    }
+
+    pub fn display_last_used(&self) -> String {
+        self.last_used
+            .map(crate::preferences::format_time)
+            .unwrap_or_else(|| "Never".to_string())
+    }
+}
+
+fn hash_key(key: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(key.as_bytes()))
+}
+
+/// The key from an `Authorization: Bearer` header, if the request carries one
+pub fn bearer_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|k| k.starts_with(KEY_PREFIX))
+}
+
+impl Config {
+    /// Find the user owning `key` and note that it was used. Returns the user ID and whether
+    /// `last_used` moved enough to be worth saving.
+    pub fn use_api_key(&mut self, key: &str) -> Option<(u64, bool)> {
+        let hash = hash_key(key);
+        let now = Utc::now();
+        self.users.iter_mut().find_map(|u| {
+            let api_key = u.api_keys.iter_mut().find(|k| k.hash == hash)?;
+            let stale = api_key
+                .last_used
+                .is_none_or(|t| now - t > Duration::minutes(LAST_USED_RESOLUTION_MINUTES));
+            if stale {
+                api_key.last_used = Some(now);
+            }
+            Some((u.id, stale))
+        })
+    }
+}
@@ -1,28 +1,57 @@
+use axum::extract::DefaultBodyLimit;
 use axum::middleware;
 use axum::routing::{delete, get, post};
 use axum::Router;
+use tower::Layer;
 use tower_sessions::cookie::time::Duration;
 use tower_sessions::{Expiry, MemoryStore, SessionManagerLayer};
 
+use crate::access_log;
 use crate::assets::serve_static;
 use crate::auth;
-use crate::routes::{backup, controller, dashboard, health, settings};
+use crate::base_path;
+use crate::branding;
+use crate::cookies::{self, CookieSettings};
+use crate::etag;
+use crate::request_id;
+use crate::routes::{agent, approve, backup, controller, dashboard, error, health, join, migrate, planet, settings, share, zerotier};
+use crate::sessions;
 use crate::sse;
+use crate::ws;
 use crate::state::AppState;
 
-pub fn build_router(state: AppState) -> Router {
+pub fn build_router(state: AppState, cookie_settings: &CookieSettings) -> Router {
     let session_store = MemoryStore::default();
     let session_layer = SessionManagerLayer::new(session_store)
-        .with_secure(false) // Allow HTTP for local use
-        .with_expiry(Expiry::OnInactivity(Duration::minutes(30)));
+        .with_name(cookie_settings.name.clone())
+        .with_path(if base_path::get().is_empty() { "/" } else { base_path::get() })
+        .with_same_site(cookie_settings.same_site)
+        .with_secure(cookie_settings.secure == cookies::SecureMode::Always)
+        .with_expiry(Expiry::OnInactivity(Duration::minutes(sessions::DEFAULT_IDLE_MINUTES)));
+
+    // Partials HTMX refreshes answer 304 when nothing changed
+    let etag_layer = middleware::from_fn(etag::etag_middleware);
 
     // Routes that require authentication
     let protected = Router::new()
         .route("/", get(dashboard::dashboard))
         .route("/events", get(sse::sse_handler))
+        .route("/ws", get(ws::ws_handler))
         // Dashboard partials
-        .route("/partials/dashboard", get(dashboard::dashboard_partial))
-        .route("/partials/networks", get(dashboard::dashboard_networks_partial))
+        .route("/partials/dashboard", get(dashboard::dashboard_partial).layer(etag_layer.clone()))
+        .route("/partials/networks", get(dashboard::dashboard_networks_partial).layer(etag_layer.clone()))
+        .route("/partials/identity-alerts", get(dashboard::identity_alerts_partial).layer(etag_layer.clone()))
+        .route("/partials/roots", get(dashboard::roots_partial).layer(etag_layer.clone()))
+        .route("/partials/release-notice", get(dashboard::release_notice_partial).layer(etag_layer.clone()))
+        .route("/partials/controller-error", get(dashboard::controller_error_partial).layer(etag_layer.clone()))
+        .route("/partials/queued-edits", get(dashboard::queued_edits_partial).layer(etag_layer.clone()))
+        .route("/queued-edits/{id}/dismiss", post(dashboard::dismiss_queued_edit))
+        .route("/partials/metrics", get(dashboard::metrics_partial).layer(etag_layer.clone()))
+        .route("/api/metrics", get(dashboard::metrics_json))
+        .route(
+            "/identity-alerts/{id}/acknowledge",
+            post(dashboard::acknowledge_identity_alert),
+        )
         // Controller pages
         .route("/controller/create", post(controller::create_network))
         .route(
@@ -46,6 +75,10 @@ pub fn build_router(state: AppState) -> Router {
             "/controller/{nwid}/broadcast-settings",
             post(controller::update_broadcast_settings),
         )
+        .route(
+            "/controller/{nwid}/sso",
+            post(controller::update_sso_settings),
+        )
         .route("/controller/{nwid}/pools", post(controller::add_pool))
         .route(
             "/controller/{nwid}/pools/remove",
@@ -61,11 +94,86 @@ pub fn build_router(state: AppState) -> Router {
             "/controller/{nwid}/dns/remove",
             post(controller::remove_dns),
         )
+        .route(
+            "/controller/{nwid}/dns/domain",
+            post(controller::set_dns_domain),
+        )
+        .route(
+            "/controller/{nwid}/dns/domain/remove",
+            post(controller::remove_dns_domain),
+        )
         .route(
             "/controller/{nwid}/flow-rules",
             post(controller::update_flow_rules),
         )
+        .route(
+            "/controller/rule-snippets",
+            get(controller::list_rule_snippets).post(controller::create_rule_snippet),
+        )
+        .route(
+            "/controller/rule-snippets/{id}",
+            delete(controller::delete_rule_snippet),
+        )
+        .route(
+            "/controller/{nwid}/state-diff",
+            get(controller::state_diff),
+        )
+        .route(
+            "/controller/{nwid}/flow-rules/definitions",
+            get(controller::get_rule_definitions).post(controller::save_rule_definitions),
+        )
+        .route(
+            "/controller/{nwid}/flow-rules/validate",
+            post(controller::validate_flow_rules),
+        )
+        .route(
+            "/controller/{nwid}/flow-rules/simulator",
+            get(controller::rule_simulator),
+        )
+        .route(
+            "/controller/{nwid}/flow-rules/simulate",
+            post(controller::simulate_rules),
+        )
         // Controller member actions
+        .route(
+            "/controller/{nwid}/invites",
+            get(controller::invites_panel).post(controller::create_invite),
+        )
+        .route(
+            "/controller/{nwid}/invites/{token}",
+            delete(controller::revoke_invite),
+        )
+        .route(
+            "/controller/{nwid}/agent-token",
+            get(controller::member_agent_panel)
+                .post(controller::generate_agent_token)
+                .delete(controller::revoke_agent_token),
+        )
+        .route(
+            "/controller/{nwid}/shares",
+            get(controller::share_links_panel).post(controller::create_share_link),
+        )
+        .route(
+            "/controller/{nwid}/shares/{id}",
+            delete(controller::revoke_share_link),
+        )
+        .route(
+            "/controller/{nwid}/join-requests/{member_id}/approve",
+            post(controller::approve_join_request),
+        )
+        .route(
+            "/controller/{nwid}/join-requests/{member_id}/reject",
+            post(controller::reject_join_request),
+        )
+        .route("/controller/{nwid}/export", get(controller::export_network))
+        .route(
+            "/controller/{nwid}/members/csv",
+            get(controller::export_members_csv),
+        )
+        .route(
+            "/controller/{nwid}/members/hosts",
+            get(controller::export_members_hosts),
+        )
         .route(
             "/controller/{nwid}/members/add",
             post(controller::add_member),
@@ -82,33 +190,143 @@ pub fn build_router(state: AppState) -> Router {
             "/controller/{nwid}/members/{member_id}/update",
             post(controller::update_member),
         )
+        .route(
+            "/controller/{nwid}/reassign-ips",
+            get(controller::reassign_ips_modal).post(controller::reassign_ips),
+        )
+        .route(
+            "/controller/{nwid}/members/{member_id}/fix-ips",
+            post(controller::fix_member_ips),
+        )
         .route(
             "/controller/{nwid}/members/{member_id}",
             delete(controller::delete_member),
         )
+        .route("/controller/{nwid}/history", get(controller::network_history))
         // Controller SSE partials
         .route(
             "/controller/partials/{nwid}/members",
-            get(controller::ctrl_member_list_partial),
+            get(controller::ctrl_member_list_partial).layer(etag_layer.clone()),
+        )
+        .route(
+            "/controller/partials/{nwid}/members/{member_id}",
+            get(controller::ctrl_member_row_partial).layer(etag_layer.clone()),
         )
         // Settings and backup
         .route("/settings", get(settings::settings_page))
         .route("/settings/password", post(settings::change_password))
+        .route("/settings/email", post(settings::update_email))
+        .route("/settings/preferences", get(settings::preferences_panel).post(settings::update_preferences))
+        .route("/settings/preferences/theme", post(settings::update_theme_preference))
         .route("/settings/username", post(settings::change_username))
         .route("/settings/backup/export", post(backup::export_backup))
         .route("/settings/backup/restore", post(backup::restore_backup))
+        .route("/settings/backup/retention", post(settings::update_backup_retention))
+        .route("/settings/backup/contents", post(settings::update_backup_contents))
+        .route("/settings/backup/history", get(backup::backup_history))
+        .route("/settings/backup/history/{id}/download", get(backup::download_backup))
+        .route("/settings/backup/restore/confirm", post(backup::confirm_restore))
+        .route("/settings/backup/restore/cancel", post(backup::cancel_restore))
+        .route("/settings/backup/restart-zerotier", post(backup::restart_zerotier))
+        .route("/settings/zerotier-service", get(zerotier::service_panel))
+        .route("/settings/zerotier-service/restart", post(zerotier::restart_service))
+        .route("/settings/zerotier-service/start", post(zerotier::start_service))
+        .route("/settings/planet", get(planet::planet_panel).post(planet::upload_planet))
+        .route("/settings/planet/restore", post(planet::restore_planet))
+        .route("/settings/planet/moons", post(planet::upload_moon))
+        .route("/settings/planet/moons/{id}", delete(planet::remove_moon))
+        .route("/settings/migrate/preview", post(migrate::preview_migration))
+        .route("/settings/migrate/import", post(migrate::import_migration))
+        .route("/settings/migrate/cancel", post(migrate::cancel_migration))
+        .route("/settings/migrate/names", post(migrate::import_names))
+        .route("/settings/migrate/central/networks", post(migrate::central_networks))
+        .route("/settings/migrate/central/preview", post(migrate::central_preview))
+        // Backup and restore for scripts authenticating with an API key
+        .route("/api/backup", get(backup::export_backup))
+        .route(
+            "/api/restore",
+            post(backup::api_restore).layer(DefaultBodyLimit::max(backup::MAX_RESTORE_UPLOAD)),
+        )
         // User management (admin only)
         .route("/settings/users", get(settings::users_list))
         .route("/settings/users/create", post(settings::create_user))
+        .route("/settings/users/export", get(settings::export_users))
+        .route("/settings/users/import", post(settings::import_users))
+        .route("/settings/users/require-2fa", post(settings::update_require_2fa))
+        .route("/settings/users/sessions", post(settings::update_session_settings))
         .route("/settings/users/{id}/modal", get(settings::user_modal))
         .route("/settings/users/{id}/update", post(settings::update_user))
+        .route("/settings/users/{id}/toggle-disabled", post(settings::toggle_user_disabled))
         .route("/settings/users/{id}", delete(settings::delete_user))
+        .route("/settings/groups", get(settings::groups_list))
+        .route("/settings/groups/create", post(settings::create_group))
+        .route("/settings/groups/{id}/modal", get(settings::group_modal))
+        .route("/settings/groups/{id}/update", post(settings::update_group))
+        .route("/settings/groups/{id}", delete(settings::delete_group))
+        .route("/settings/network-groups", get(settings::network_groups_list))
+        .route("/settings/network-groups/create", post(settings::create_network_group))
+        .route("/settings/network-groups/{id}/modal", get(settings::network_group_modal))
+        .route("/settings/network-groups/{id}/update", post(settings::update_network_group))
+        .route("/settings/network-groups/{id}", delete(settings::delete_network_group))
+        .route("/settings/notifications", get(settings::notifications_panel))
+        .route(
+            "/settings/notifications/channels",
+            post(settings::create_notification_channel),
+        )
+        .route(
+            "/settings/notifications/channels/{id}",
+            delete(settings::delete_notification_channel),
+        )
+        .route(
+            "/settings/notifications/routes",
+            post(settings::create_notification_route),
+        )
+        .route(
+            "/settings/notifications/routes/{id}",
+            delete(settings::delete_notification_route),
+        )
+        .route(
+            "/settings/notifications/approval-links",
+            post(settings::update_approval_links),
+        )
+        .route(
+            "/settings/notifications/monitoring",
+            post(settings::update_monitoring),
+        )
+        .route("/settings/releases", get(settings::releases_panel).post(settings::update_release_check))
+        .route("/settings/consistency", get(settings::consistency_panel))
+        .route("/settings/consistency/check", post(settings::run_consistency_check))
+        .route("/settings/consistency/cleanup", post(settings::cleanup_consistency))
+        .route("/settings/dns", get(settings::dns_server_panel).post(settings::update_dns_server))
+        .route("/settings/schedule", get(settings::schedule_panel).post(settings::create_scheduled_job))
+        .route("/settings/schedule/{id}", delete(settings::delete_scheduled_job))
+        .route(
+            "/settings/schedule/networks/{nwid}/enable",
+            post(settings::enable_disabled_network),
+        )
+        .route("/settings/audit", get(settings::audit_log_panel))
+        .route("/settings/audit/access-log", post(settings::update_access_log))
+        .route("/settings/logins", get(settings::login_history_panel))
+        .route("/settings/security", get(settings::security_panel))
+        .route("/settings/oidc", get(settings::oidc_panel).post(settings::update_oidc))
+        .route("/settings/smtp", get(settings::smtp_panel).post(settings::update_smtp))
+        .route("/settings/branding", get(settings::branding_panel).post(settings::update_branding))
+        .route("/settings/smtp/test", post(settings::test_smtp))
         // 2FA settings
         .route("/settings/2fa/setup", get(settings::totp_setup_modal))
         .route("/settings/2fa/enable", post(settings::totp_enable))
         .route("/settings/2fa/disable-modal", get(settings::totp_disable_modal))
         .route("/settings/2fa/disable", post(settings::totp_disable))
         .route("/settings/2fa/status", get(settings::totp_status))
+        .route("/settings/passkeys", get(settings::passkeys_panel).post(settings::passkey_register))
+        .route("/settings/passkeys/options", post(settings::passkey_register_options))
+        .route("/settings/passkeys/{id}", delete(settings::delete_passkey))
+        .route("/settings/api-keys", get(settings::api_keys_panel).post(settings::create_api_key))
+        .route("/settings/api-keys/{id}", delete(settings::delete_api_key))
+        .route("/settings/sessions", get(settings::sessions_panel))
+        .route("/settings/sessions/all", get(settings::all_sessions_panel))
+        .route("/settings/sessions/logout-all", post(settings::logout_everywhere))
+        .route("/settings/sessions/{id}", delete(settings::revoke_session))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth::auth_middleware,
@@ -123,12 +341,35 @@ pub fn build_router(state: AppState) -> Router {
         .route("/login", post(auth::login_submit))
         .route("/login/2fa", get(auth::login_2fa_page))
         .route("/login/2fa", post(auth::login_2fa_submit))
+        .route("/login/passkey/options", post(auth::passkey_login_options))
+        .route("/login/passkey", post(auth::passkey_login))
+        .route("/login/oidc", get(auth::oidc_login))
+        .route("/login/oidc/callback", get(auth::oidc_callback))
         .route("/logout", get(auth::logout))
+        .route("/forgot-password", get(auth::forgot_password_page).post(auth::forgot_password_submit))
+        .route("/reset-password", get(auth::reset_password_page).post(auth::reset_password_submit))
+        .route("/join/{token}", get(join::join_page).post(join::join_confirm))
+        .route("/join/{token}/request", post(join::join_request))
+        .route(
+            "/approve/{nwid}/{member_id}",
+            get(approve::approve_link).post(approve::approve_confirm),
+        )
+        .route("/share/{id}/{sig}", get(share::share_page))
+        .route("/api/v1/agent/report", post(agent::report))
+        .route("/branding/logo", get(branding::logo))
         .route("/static/{*path}", get(serve_static));
 
-    Router::new()
+    let app = Router::new()
         .merge(protected)
         .merge(public)
+        .fallback(error::not_found)
         .layer(session_layer)
-        .with_state(state)
+        .layer(middleware::from_fn_with_state(cookie_settings.secure, cookies::secure_over_https_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), access_log::access_log_middleware))
+        .layer(middleware::from_fn(request_id::request_id_middleware))
+        .layer(middleware::from_fn(error::error_page_middleware))
+        .with_state(state);
+
+    // The base path has to be stripped before routing, so it wraps the finished router
+    Router::new().fallback_service(middleware::from_fn(base_path::strip_prefix_middleware).layer(app))
 }
@@ -0,0 +1,131 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::state::Config;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// One-click approval links added to new-member notifications
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApprovalLinkSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Externally reachable TierDrop URL the links point at, e.g. https://tierdrop.example.com
+    #[serde(default)]
+    pub base_url: String,
+    #[serde(default = "default_valid_hours")]
+    pub valid_hours: u32,
+}
+
+fn default_valid_hours() -> u32 {
+    24
+}
+
+impl Default for ApprovalLinkSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            base_url: String::new(),
+            valid_hours: default_valid_hours(),
+        }
+    }
+}
+
+/// Random key for signing approval links, hex encoded
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex(&bytes)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn mac(secret: &str, nwid: &str, member_id: &str, expires: i64) -> HmacSha256 {
+    // HMAC accepts keys of any length
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC key");
+    mac.update(format!("approve:{}:{}:{}", nwid, member_id, expires).as_bytes());
+    mac
+}
+
+/// Sign an approval for one member on one network
+pub fn sign(secret: &str, nwid: &str, member_id: &str, expires: i64) -> String {
+    hex(&mac(secret, nwid, member_id, expires).finalize().into_bytes())
+}
+
+/// Decode a hex HMAC-SHA256 signature. Only exactly 64 hex digits are accepted, so each
+/// signature has one spelling apart from letter case.
+pub fn decode_signature(signature: &str) -> Option<Vec<u8>> {
+    if signature.len() != 64 || !signature.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..signature.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&signature[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Check a link's signature and expiry
+pub fn verify(secret: &str, nwid: &str, member_id: &str, expires: i64, signature: &str) -> Result<(), String> {
+    let valid = decode_signature(signature).is_some_and(|b| mac(secret, nwid, member_id, expires).verify_slice(&b).is_ok());
+    if secret.is_empty() || !valid {
+        return Err("This approval link is not valid.".to_string());
+    }
+    if expires < Utc::now().timestamp() {
+        return Err("This approval link has expired.".to_string());
+    }
+    Ok(())
+}
+
+/// Approval URL for a pending member, or None when links are disabled
+pub fn approval_url(settings: &ApprovalLinkSettings, secret: &str, nwid: &str, member_id: &str) -> Option<String> {
+    if !settings.enabled || settings.base_url.is_empty() || secret.is_empty() {
+        return None;
+    }
+    let expires = (Utc::now() + Duration::hours(settings.valid_hours as i64)).timestamp();
+    Some(format!(
+        "{}/approve/{}/{}?expires={}&sig={}",
+        settings.base_url.trim_end_matches('/'),
+        nwid,
+        member_id,
+        expires,
+        sign(secret, nwid, member_id, expires)
+    ))
+}
+
+/// Key a used link is remembered under: the decoded signature re-encoded, so another
+/// spelling of the same signature doesn't count as a new link
+fn used_link_key(signature: &str) -> Option<String> {
+    decode_signature(signature).map(|b| hex(&b))
+}
+
+impl Config {
+    /// Claim an approval link for one use. False when it was used already.
+    pub fn claim_approval_link(&mut self, signature: &str, expires: i64) -> bool {
+        let now = Utc::now().timestamp();
+        self.used_approval_links.retain(|_, until| *until >= now);
+        let Some(key) = used_link_key(signature) else {
+            return false;
+        };
+        if self.used_approval_links.contains_key(&key) {
+            return false;
+        }
+        self.used_approval_links.insert(key, expires);
+        true
+    }
+
+    /// Give a claimed link back, after approving with it failed
+    pub fn release_approval_link(&mut self, signature: &str) {
+        if let Some(key) = used_link_key(signature) {
+            self.used_approval_links.remove(&key);
+        }
+    }
+
+    pub fn approval_link_used(&self, signature: &str) -> bool {
+        used_link_key(signature).is_none_or(|key| self.used_approval_links.contains_key(&key))
+    }
+}
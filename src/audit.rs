@@ -0,0 +1,90 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::sessions::ClientInfo;
+use crate::state::Config;
+
+/// Oldest entries are dropped beyond this
+const MAX_AUDIT_ENTRIES: usize = 1000;
+const MAX_LOGIN_EVENTS: usize = 1000;
+
+/// Actor recorded for actions TierDrop takes on its own
+pub const SYSTEM_ACTOR: &str = "system";
+
+/// A recorded change to the controller made by a user or by TierDrop itself
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    pub actor: String,
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nwid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub member_id: Option<String>,
+    #[serde(default)]
+    pub detail: String,
+}
+
+impl AuditEntry {
+    pub fn display_at(&self) -> String {
+        crate::preferences::format_time_exact(self.at)
+    }
+}
+
+/// A sign-in attempt, successful or not
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoginEvent {
+    pub at: DateTime<Utc>,
+    /// Name as typed, or the matched user's name
+    pub username: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<u64>,
+    pub success: bool,
+    /// e.g. "Password", "Password + TOTP", "Passkey", "SSO"
+    pub method: String,
+    /// Why a failed attempt was refused
+    #[serde(default)]
+    pub detail: String,
+    #[serde(flatten)]
+    pub client: ClientInfo,
+}
+
+impl LoginEvent {
+    pub fn display_at(&self) -> String {
+        crate::preferences::format_time_exact(self.at)
+    }
+}
+
+impl Config {
+    /// Append an audit entry. The caller saves the config.
+    pub fn audit(
+        &mut self,
+        actor: &str,
+        action: &str,
+        nwid: Option<&str>,
+        member_id: Option<&str>,
+        detail: String,
+    ) {
+        self.audit_log.push(AuditEntry {
+            at: Utc::now(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            nwid: nwid.map(str::to_string),
+            member_id: member_id.map(str::to_string),
+            detail,
+        });
+        if self.audit_log.len() > MAX_AUDIT_ENTRIES {
+            let excess = self.audit_log.len() - MAX_AUDIT_ENTRIES;
+            self.audit_log.drain(..excess);
+        }
+    }
+
+    /// Append a sign-in attempt. The caller saves the config.
+    pub fn record_login(&mut self, event: LoginEvent) {
+        self.login_history.push(event);
+        if self.login_history.len() > MAX_LOGIN_EVENTS {
+            let excess = self.login_history.len() - MAX_LOGIN_EVENTS;
+            self.login_history.drain(..excess);
+        }
+    }
+}
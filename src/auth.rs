@@ -1,19 +1,40 @@
+use std::net::SocketAddr;
+
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
-use axum::extract::State;
-use axum::http::{Request, StatusCode};
+use axum::extract::{ConnectInfo, State};
+use axum::http::header::SET_COOKIE;
+use axum::http::{HeaderMap, Request, StatusCode};
 use axum::middleware::Next;
-use axum::response::{IntoResponse, Redirect, Response};
+use axum::response::{Html, IntoResponse, Redirect, Response};
 use axum::Form;
+use axum::extract::Query;
+use axum::Json;
 use serde::Deserialize;
-use tower_sessions::Session;
+use tower_sessions::cookie::time::Duration;
+use tower_sessions::{Expiry, Session};
+use tracing::{info, warn};
 
+use crate::access_log::AccessUser;
+use crate::api_keys;
+use crate::audit::LoginEvent;
+use crate::base_path;
+use crate::oidc::{self, PendingLogin};
+use crate::passkeys::{self, AssertionResponse, PendingChallenge, RequestHost};
+use crate::permissions;
+use crate::preferences::{self, Landing};
+use crate::routes::error::error_response;
+use crate::sessions::{ClientInfo, DEFAULT_IDLE_MINUTES};
 use crate::state::{AppState, Config, User};
+use crate::throttle::{self, Action};
 
 const SESSION_USER_ID_KEY: &str = "user_id";
 const SESSION_2FA_PENDING_KEY: &str = "2fa_pending";
+const SESSION_PASSKEY_LOGIN_KEY: &str = "passkey_login";
+const SESSION_REMEMBER_KEY: &str = "remember_me";
+const SESSION_SID_KEY: &str = "sid";
 
 /// Hash a password with Argon2id
 pub fn hash_password(password: &str) -> Result<String, String> {
@@ -48,11 +69,72 @@ pub async fn get_2fa_pending_user_id(session: &Session) -> Option<u64> {
     session.get::<u64>(SESSION_2FA_PENDING_KEY).await.ok().flatten()
 }
 
+/// Mark the session as logged in. "Remember me" swaps the default inactivity window for
+/// `remember_me_days`.
+/// Returns the URL of the user's landing page, to send them to after signing in
+async fn start_session(session: &Session, state: &AppState, user_id: u64, remember: bool) -> String {
+    let (days, landing) = {
+        let config = state.config.read().await;
+        let landing = config
+            .as_ref()
+            .and_then(|c| c.load_user(user_id))
+            .map(|u| match &u.preferences.landing {
+                // A network they can no longer see falls back to the dashboard
+                Landing::Network { nwid } if !permissions::can_read(&u, nwid) => "/".to_string(),
+                landing => landing.path(),
+            })
+            .unwrap_or_else(|| "/".to_string());
+        (config.as_ref().map(|c| c.remember_me_days).unwrap_or(0), landing)
+    };
+    if remember && days > 0 {
+        session.set_expiry(Some(Expiry::OnInactivity(Duration::days(days as i64))));
+    }
+    session.remove::<bool>(SESSION_REMEMBER_KEY).await.unwrap_or_default();
+    session.remove::<String>(SESSION_SID_KEY).await.unwrap_or_default();
+    session
+        .insert(SESSION_USER_ID_KEY, user_id)
+        .await
+        .unwrap_or_default();
+    base_path::url(&landing)
+}
+
+/// Add a sign-in attempt to the login history; failures also go to the server log
+async fn record_login(
+    state: &AppState,
+    client: &ClientInfo,
+    username: &str,
+    user_id: Option<u64>,
+    method: &str,
+    failure: Option<&str>,
+) {
+    if let Some(reason) = failure {
+        warn!("Failed sign-in for '{}' from {}: {}", username, client.display_ip(), reason);
+    }
+    let mut config = state.config.write().await;
+    if let Some(ref mut c) = *config {
+        c.record_login(LoginEvent {
+            at: chrono::Utc::now(),
+            username: username.to_string(),
+            user_id,
+            success: failure.is_none(),
+            method: method.to_string(),
+            detail: failure.unwrap_or_default().to_string(),
+            client: client.clone(),
+        });
+        let _ = c.save();
+    }
+}
+
+/// Registry ID of the current session, once the middleware has seen it
+pub async fn get_session_sid(session: &Session) -> Option<String> {
+    session.get::<String>(SESSION_SID_KEY).await.ok().flatten()
+}
+
 /// Get the current user from session + config
 pub async fn get_current_user(session: &Session, state: &AppState) -> Option<User> {
     let user_id = get_session_user_id(session).await?;
     let config = state.config.read().await;
-    config.as_ref()?.find_user_by_id(user_id).cloned()
+    config.as_ref()?.load_user(user_id).filter(|u| !u.disabled)
 }
 
 /// Check if user is authenticated (has valid session)
@@ -71,16 +153,111 @@ pub async fn auth_middleware(
     next: Next,
 ) -> Response {
     if !state.is_configured().await {
-        return Redirect::to("/setup").into_response();
+        return Redirect::to(&base_path::url("/setup")).into_response();
+    }
+
+    // Scripts authenticate with a personal API key instead of a session
+    if let Some(key) = api_keys::bearer_key(request.headers()).map(str::to_string) {
+        let user = {
+            let mut config = state.config.write().await;
+            config.as_mut().and_then(|c| {
+                let (user_id, used) = c.use_api_key(&key)?;
+                if used {
+                    if let Err(e) = c.save() {
+                        warn!("Failed to save API key usage: {}", e);
+                    }
+                }
+                c.load_user(user_id).filter(|u| !u.disabled && !u.needs_2fa_enrollment(c))
+            })
+        };
+        let Some(user) = user else {
+            return error_response(StatusCode::UNAUTHORIZED, "Invalid API key");
+        };
+        // A leaked key must not be able to take over the account it belongs to
+        if !is_api_key_path(request.uri().path()) {
+            return error_response(StatusCode::FORBIDDEN, "API keys cannot change account security settings");
+        }
+        let access_user = AccessUser { username: user.username.clone(), api_key: true };
+        let preferences = user.preferences.clone();
+        request.extensions_mut().insert(user);
+        let mut response = preferences::scope(preferences, next.run(request)).await;
+        response.extensions_mut().insert(access_user);
+        return response;
     }
 
     if let Some(user) = get_current_user(&session, &state).await {
+        // Every login is registered so it can be listed and revoked; unknown IDs were revoked
+        match get_session_sid(&session).await {
+            Some(sid) => {
+                if !state.sessions.touch(&sid, user.id).await {
+                    session.flush().await.unwrap_or_default();
+                    return Redirect::to(&base_path::url("/login")).into_response();
+                }
+            }
+            None => {
+                let idle_timeout = match session.expiry() {
+                    Some(Expiry::OnInactivity(d)) => chrono::Duration::seconds(d.whole_seconds()),
+                    _ => chrono::Duration::minutes(DEFAULT_IDLE_MINUTES),
+                };
+                let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|c| c.0);
+                let client = ClientInfo::new(request.headers(), peer);
+                let sid = state.sessions.register(user.id, client, idle_timeout).await;
+                session.insert(SESSION_SID_KEY, sid).await.unwrap_or_default();
+            }
+        }
+
+        // Until a required second factor is set up, only the enrollment pages are reachable
+        let must_enroll = {
+            let config = state.config.read().await;
+            config.as_ref().is_some_and(|c| user.needs_2fa_enrollment(c))
+        };
+        if must_enroll && !is_enrollment_path(request.uri().path()) {
+            return Redirect::to(&base_path::url("/settings")).into_response();
+        }
+
         // Store user in request extensions for easy access in handlers
+        let access_user = AccessUser { username: user.username.clone(), api_key: false };
+        let preferences = user.preferences.clone();
         request.extensions_mut().insert(user);
-        next.run(request).await
+        let mut response = preferences::scope(preferences, next.run(request)).await;
+        response.extensions_mut().insert(access_user);
+        response
     } else {
-        Redirect::to("/login").into_response()
+        Redirect::to(&base_path::url("/login")).into_response()
+    }
+}
+
+/// Settings sections an API key may use. Everything else under /settings (password, email,
+/// two-factor, passkeys, API keys, sessions, users, sign-in and email setup) needs a session.
+const API_KEY_SETTINGS_PATHS: &[&str] = &[
+    "/settings/backup",
+    "/settings/zerotier-service",
+    "/settings/planet",
+    "/settings/migrate",
+    "/settings/groups",
+    "/settings/network-groups",
+    "/settings/notifications",
+    "/settings/releases",
+    "/settings/consistency",
+    "/settings/dns",
+    "/settings/schedule",
+    "/settings/audit",
+    "/settings/logins",
+];
+
+/// Routes an API key may reach: everything outside /settings, and the listed settings sections
+fn is_api_key_path(path: &str) -> bool {
+    if path != "/settings" && !path.starts_with("/settings/") {
+        return true;
     }
+    API_KEY_SETTINGS_PATHS
+        .iter()
+        .any(|p| path == *p || path.strip_prefix(p).is_some_and(|rest| rest.starts_with('/')))
+}
+
+/// Routes a user may reach while "require 2FA" is waiting on them
+fn is_enrollment_path(path: &str) -> bool {
+    path == "/settings" || path.starts_with("/settings/2fa/") || path.starts_with("/settings/passkeys")
 }
 
 // ---- Setup ----
@@ -102,7 +279,7 @@ pub struct SetupForm {
 /// GET /setup
 pub async fn setup_page(State(state): State<AppState>) -> Response {
     if state.is_configured().await {
-        return Redirect::to("/login").into_response();
+        return Redirect::to(&base_path::url("/login")).into_response();
     }
     SetupTemplate { error: None }.into_response()
 }
@@ -113,48 +290,45 @@ pub async fn setup_submit(
     Form(form): Form<SetupForm>,
 ) -> Response {
     if state.is_configured().await {
-        return Redirect::to("/login").into_response();
+        return Redirect::to(&base_path::url("/login")).into_response();
     }
 
-    let username = form.username.trim().to_string();
-    if username.is_empty() {
+    if form.password != form.password_confirm {
         return SetupTemplate {
-            error: Some("Username is required.".to_string()),
+            error: Some("Passwords do not match.".to_string()),
         }
         .into_response();
     }
 
-    if form.password.len() < 8 {
-        return SetupTemplate {
-            error: Some("Password must be at least 8 characters.".to_string()),
-        }
-        .into_response();
-    }
+    let config = match initial_config(&form.username, &form.password, &form.zt_token) {
+        Ok(c) => c,
+        Err(e) => return SetupTemplate { error: Some(e) }.into_response(),
+    };
 
-    if form.password != form.password_confirm {
+    if let Err(e) = state.configure(config).await {
         return SetupTemplate {
-            error: Some("Passwords do not match.".to_string()),
+            error: Some(format!("Failed to save configuration: {}", e)),
         }
         .into_response();
     }
 
-    let zt_token = form.zt_token.trim().to_string();
+    Redirect::to(&base_path::url("/login")).into_response()
+}
+
+/// A fresh config with one admin, as created by the setup wizard or bootstrap settings
+pub fn initial_config(username: &str, password: &str, zt_token: &str) -> Result<Config, String> {
+    let username = username.trim().to_string();
+    if username.is_empty() {
+        return Err("Username is required.".to_string());
+    }
+    if password.len() < 8 {
+        return Err("Password must be at least 8 characters.".to_string());
+    }
+    let zt_token = zt_token.trim().to_string();
     if zt_token.is_empty() {
-        return SetupTemplate {
-            error: Some("ZeroTier auth token is required.".to_string()),
-        }
-        .into_response();
+        return Err("ZeroTier auth token is required.".to_string());
     }
-
-    let password_hash = match hash_password(&form.password) {
-        Ok(h) => h,
-        Err(e) => {
-            return SetupTemplate {
-                error: Some(format!("Internal error: {}", e)),
-            }
-            .into_response();
-        }
-    };
+    let password_hash = hash_password(password).map_err(|e| format!("Internal error: {}", e))?;
 
     let zt_base_url =
         std::env::var("ZT_BASE_URL").unwrap_or_else(|_| "http://localhost:9993".to_string());
@@ -162,27 +336,55 @@ pub async fn setup_submit(
     // Create the first admin user with ID 1
     let admin_user = User::new_admin(1, username, password_hash);
 
-    let config = Config {
+    Ok(Config {
         username: None,
         password_hash: None,
         users: vec![admin_user],
         next_user_id: 2,
+        groups: Vec::new(),
+        next_group_id: 1,
+        network_groups: Vec::new(),
+        next_network_group_id: 1,
         zt_token,
         zt_base_url,
         member_names: std::collections::HashMap::new(),
         rules_source: std::collections::HashMap::new(),
         member_descriptions: std::collections::HashMap::new(),
+        monitored_members: std::collections::HashSet::new(),
         network_descriptions: std::collections::HashMap::new(),
-    };
-
-    if let Err(e) = state.configure(config).await {
-        return SetupTemplate {
-            error: Some(format!("Failed to save configuration: {}", e)),
-        }
-        .into_response();
-    }
-
-    Redirect::to("/login").into_response()
+        network_owners: std::collections::HashMap::new(),
+        notifications: Default::default(),
+        rule_snippets: Vec::new(),
+        rule_definitions: std::collections::HashMap::new(),
+        member_identities: std::collections::HashMap::new(),
+        identity_alerts: Vec::new(),
+        dns_server: Default::default(),
+        invites: Vec::new(),
+        join_requests: Vec::new(),
+        share_links: Vec::new(),
+        next_share_link_id: 1,
+        agent_tokens: std::collections::HashMap::new(),
+        agent_reports: std::collections::HashMap::new(),
+        approval_secret: crate::approvals::generate_secret(),
+        used_approval_links: Default::default(),
+        guest_access: Vec::new(),
+        audit_log: Vec::new(),
+        login_history: Vec::new(),
+        scheduled_jobs: Vec::new(),
+        backup_retention: Default::default(),
+        backup_contents: Default::default(),
+        backup_history: Vec::new(),
+        next_job_id: 1,
+        disabled_networks: std::collections::HashMap::new(),
+        oidc: Default::default(),
+        require_2fa: false,
+        remember_me_days: 30,
+        smtp: Default::default(),
+        password_resets: Vec::new(),
+        access_log: false,
+        release_check: false,
+        branding: Default::default(),
+    })
 }
 
 // ---- Login ----
@@ -191,12 +393,37 @@ pub async fn setup_submit(
 #[template(path = "login.html")]
 pub struct LoginTemplate {
     pub error: Option<String>,
+    /// Single sign-on button label, when SSO is set up
+    pub sso_label: Option<String>,
+    /// Offer passkey sign-in
+    pub passkeys: bool,
+    /// Days a "remember me" session lasts; 0 hides the option
+    pub remember_me_days: u32,
+    /// Offer "Forgot password?"
+    pub password_reset: bool,
+}
+
+impl LoginTemplate {
+    async fn new(state: &AppState, error: Option<String>) -> Self {
+        let config = state.config.read().await;
+        let sso_label = config
+            .as_ref()
+            .filter(|c| c.oidc.is_ready())
+            .map(|c| c.oidc.button_label.clone());
+        let passkeys = config
+            .as_ref()
+            .is_some_and(|c| c.users.iter().any(|u| !u.passkeys.is_empty()));
+        let remember_me_days = config.as_ref().map(|c| c.remember_me_days).unwrap_or(0);
+        let password_reset = config.as_ref().is_some_and(|c| c.smtp.is_ready());
+        Self { error, sso_label, passkeys, remember_me_days, password_reset }
+    }
 }
 
 #[derive(Deserialize)]
 pub struct LoginForm {
     pub username: String,
     pub password: String,
+    pub remember: Option<String>,
 }
 
 /// GET /login
@@ -205,62 +432,207 @@ pub async fn login_page(
     session: Session,
 ) -> Response {
     if !state.is_configured().await {
-        return Redirect::to("/setup").into_response();
+        return Redirect::to(&base_path::url("/setup")).into_response();
     }
 
     if is_authenticated(&session, &state).await {
-        return Redirect::to("/").into_response();
+        return Redirect::to(&base_path::url("/")).into_response();
     }
 
-    LoginTemplate { error: None }.into_response()
+    LoginTemplate::new(&state, None).await.into_response()
 }
 
 /// POST /login
 pub async fn login_submit(
     session: Session,
     State(state): State<AppState>,
+    client: ClientInfo,
     Form(form): Form<LoginForm>,
 ) -> Response {
-    let config = state.config.read().await;
-    let config = match config.as_ref() {
+    if let Err(wait) = throttle::check(Action::Login, client.ip.as_deref()) {
+        record_login(&state, &client, form.username.trim(), None, "Password", Some("Too many attempts")).await;
+        let tmpl = LoginTemplate::new(&state, Some(throttle::retry_message(wait))).await;
+        return (StatusCode::TOO_MANY_REQUESTS, tmpl).into_response();
+    }
+
+    let guard = state.config.read().await;
+    let config = match guard.as_ref() {
         Some(c) => c,
-        None => return Redirect::to("/setup").into_response(),
+        None => return Redirect::to(&base_path::url("/setup")).into_response(),
     };
 
     let username = form.username.trim();
+    let remember = form.remember.is_some();
 
     // Find user by username
-    if let Some(user) = config.find_user_by_username(username) {
-        if verify_password(&form.password, &user.password_hash) {
-            // Check if 2FA is enabled
-            if user.totp_enabled && user.totp_secret.is_some() {
-                // Store user ID in pending 2FA state
-                session
-                    .insert(SESSION_2FA_PENDING_KEY, user.id)
-                    .await
-                    .unwrap_or_default();
-                return Redirect::to("/login/2fa").into_response();
-            }
+    let user = config.find_user_by_username(username);
+    let known_id = user.map(|u| u.id);
+    let matched = user
+        .filter(|user| verify_password(&form.password, &user.password_hash))
+        .map(|user| (user.id, user.has_second_factor(), user.disabled));
+    drop(guard);
 
-            // No 2FA - complete login directly
+    if matches!(matched, Some((_, _, true))) {
+        record_login(&state, &client, username, known_id, "Password", Some("Account disabled")).await;
+        let tmpl = LoginTemplate::new(&state, Some("This account is disabled.".to_string())).await;
+        return (StatusCode::FORBIDDEN, tmpl).into_response();
+    }
+
+    if let Some((user_id, has_second_factor, _)) = matched {
+        throttle::clear(Action::Login, client.ip.as_deref());
+        // Check if 2FA is enabled; passkeys count as a second factor too
+        if has_second_factor {
+            // Store user ID in pending 2FA state, and the remember-me choice for after it
             session
-                .insert(SESSION_USER_ID_KEY, user.id)
+                .insert(SESSION_2FA_PENDING_KEY, user_id)
                 .await
                 .unwrap_or_default();
-            return Redirect::to("/").into_response();
+            session.insert(SESSION_REMEMBER_KEY, remember).await.unwrap_or_default();
+            return Redirect::to(&base_path::url("/login/2fa")).into_response();
         }
+
+        // No 2FA - complete login directly
+        record_login(&state, &client, username, Some(user_id), "Password", None).await;
+        let landing = start_session(&session, &state, user_id, remember).await;
+        return Redirect::to(&landing).into_response();
     }
 
-    let tmpl = LoginTemplate {
-        error: Some("Invalid username or password.".to_string()),
-    };
+    let reason = if known_id.is_some() { "Wrong password" } else { "Unknown user" };
+    throttle::record(Action::Login, client.ip.as_deref());
+    record_login(&state, &client, username, known_id, "Password", Some(reason)).await;
+    let tmpl = LoginTemplate::new(&state, Some("Invalid username or password.".to_string())).await;
     (StatusCode::UNAUTHORIZED, tmpl).into_response()
 }
 
+// ---- Single Sign-On ----
+
+async fn sso_error(state: &AppState, message: String) -> Response {
+    (StatusCode::UNAUTHORIZED, LoginTemplate::new(state, Some(message)).await).into_response()
+}
+
+/// GET /login/oidc - Send the browser to the identity provider
+pub async fn oidc_login(State(state): State<AppState>) -> Response {
+    let settings = {
+        let config = state.config.read().await;
+        match config.as_ref() {
+            Some(c) if c.oidc.is_ready() => c.oidc.clone(),
+            _ => return Redirect::to(&base_path::url("/login")).into_response(),
+        }
+    };
+
+    match oidc::begin_login(&settings).await {
+        Ok((url, pending)) => ([(SET_COOKIE, pending.cookie(&settings))], Redirect::to(&url)).into_response(),
+        Err(e) => {
+            warn!("Single sign-on unavailable: {}", e);
+            sso_error(&state, "Single sign-on is unavailable. Try again later.".to_string()).await
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// GET /login/oidc/callback - Finish single sign-on and log the user in
+pub async fn oidc_callback(
+    State(state): State<AppState>,
+    session: Session,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Query(query): Query<OidcCallbackQuery>,
+) -> Response {
+    // Each login cookie is good for one callback, whatever the outcome
+    let settings = {
+        let config = state.config.read().await;
+        config.as_ref().map(|c| c.oidc.clone()).unwrap_or_default()
+    };
+    let pending = PendingLogin::from_headers(&headers);
+    let mut response = finish_oidc_login(&state, &session, &client, pending, query).await;
+    if let Ok(cookie) = PendingLogin::clear_cookie(&settings).parse() {
+        response.headers_mut().append(SET_COOKIE, cookie);
+    }
+    response
+}
+
+async fn finish_oidc_login(
+    state: &AppState,
+    session: &Session,
+    client: &ClientInfo,
+    pending: Option<PendingLogin>,
+    query: OidcCallbackQuery,
+) -> Response {
+    if let Some(error) = query.error {
+        let detail = query.error_description.unwrap_or(error);
+        return sso_error(state, format!("Sign-in was not completed: {}", detail)).await;
+    }
+    let (Some(pending), Some(code)) = (pending, query.code) else {
+        return sso_error(state, "Sign-in session expired. Try again.".to_string()).await;
+    };
+    if query.state.as_deref() != Some(pending.state.as_str()) {
+        return sso_error(state, "Sign-in session expired. Try again.".to_string()).await;
+    }
+
+    let settings = {
+        let config = state.config.read().await;
+        match config.as_ref() {
+            Some(c) if c.oidc.is_ready() => c.oidc.clone(),
+            _ => return Redirect::to(&base_path::url("/login")).into_response(),
+        }
+    };
+
+    let identity = match oidc::complete_login(&settings, &pending, &code).await {
+        Ok(i) => i,
+        Err(e) => {
+            warn!("Single sign-on failed: {}", e);
+            return sso_error(state, "Single sign-on failed. Try again later.".to_string()).await;
+        }
+    };
+
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => c.resolve_oidc_user(&identity).and_then(|id| {
+                c.save()?;
+                Ok(id)
+            }),
+            None => return Redirect::to(&base_path::url("/setup")).into_response(),
+        }
+    };
+    match result {
+        Ok(user_id) => {
+            info!("User '{}' signed in with single sign-on", identity.username);
+            record_login(state, client, &identity.username, Some(user_id), "SSO", None).await;
+            // The identity provider handles second factors
+            let landing = start_session(session, state, user_id, false).await;
+            same_site_redirect(&landing)
+        }
+        Err(e) => {
+            record_login(state, client, &identity.username, None, "SSO", Some(&e)).await;
+            sso_error(state, e).await
+        }
+    }
+}
+
+/// Move on from a cross-site arrival with a page rather than a 303. Browsers treat a redirect
+/// chain that began on the identity provider as cross-site and leave the SameSite=Strict session
+/// cookie off it; a navigation started by this page is same-site.
+fn same_site_redirect(url: &str) -> Response {
+    let url = url.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;");
+    Html(format!(
+        r#"<!DOCTYPE html><html><head><meta http-equiv="refresh" content="0;url={0}"></head><body><a href="{0}">Continue</a></body></html>"#,
+        url
+    ))
+    .into_response()
+}
+
 /// GET /logout
 pub async fn logout(session: Session) -> Redirect {
     session.flush().await.unwrap_or_default();
-    Redirect::to("/login")
+    Redirect::to(&base_path::url("/login"))
 }
 
 // ---- 2FA Verification ----
@@ -269,6 +641,20 @@ pub async fn logout(session: Session) -> Redirect {
 #[template(path = "login_2fa.html")]
 pub struct Login2faTemplate {
     pub error: Option<String>,
+    /// Offer the authenticator code form
+    pub totp: bool,
+    /// Offer the passkey button
+    pub passkey: bool,
+}
+
+impl Login2faTemplate {
+    fn for_user(user: &User, error: Option<String>) -> Self {
+        Self {
+            error,
+            totp: user.totp_enabled && user.totp_secret.is_some(),
+            passkey: !user.passkeys.is_empty(),
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -276,6 +662,13 @@ pub struct Login2faForm {
     pub code: String,
 }
 
+/// The user waiting on a second factor, if any
+async fn get_2fa_pending_user(session: &Session, state: &AppState) -> Option<User> {
+    let user_id = get_2fa_pending_user_id(session).await?;
+    let config = state.config.read().await;
+    config.as_ref()?.find_user_by_id(user_id).filter(|u| !u.disabled).cloned()
+}
+
 /// GET /login/2fa
 pub async fn login_2fa_page(
     State(state): State<AppState>,
@@ -283,66 +676,377 @@ pub async fn login_2fa_page(
 ) -> Response {
     // If already authenticated, go to dashboard
     if is_authenticated(&session, &state).await {
-        return Redirect::to("/").into_response();
+        return Redirect::to(&base_path::url("/")).into_response();
     }
 
     // Must have pending 2FA
-    if get_2fa_pending_user_id(&session).await.is_none() {
-        return Redirect::to("/login").into_response();
+    match get_2fa_pending_user(&session, &state).await {
+        Some(user) => Login2faTemplate::for_user(&user, None).into_response(),
+        None => Redirect::to(&base_path::url("/login")).into_response(),
     }
-
-    Login2faTemplate { error: None }.into_response()
 }
 
 /// POST /login/2fa
 pub async fn login_2fa_submit(
     session: Session,
     State(state): State<AppState>,
+    client: ClientInfo,
     Form(form): Form<Login2faForm>,
 ) -> Response {
-    // Get pending user ID
-    let pending_user_id = match get_2fa_pending_user_id(&session).await {
-        Some(id) => id,
-        None => return Redirect::to("/login").into_response(),
-    };
-
-    // Get user from config
-    let config = state.config.read().await;
-    let user = match config.as_ref().and_then(|c| c.find_user_by_id(pending_user_id)) {
-        Some(u) => u.clone(),
+    let user = match get_2fa_pending_user(&session, &state).await {
+        Some(u) => u,
         None => {
-            // User no longer exists
+            // No pending login, or the user no longer exists
             session.remove::<u64>(SESSION_2FA_PENDING_KEY).await.unwrap_or_default();
-            return Redirect::to("/login").into_response();
+            return Redirect::to(&base_path::url("/login")).into_response();
         }
     };
-    drop(config);
 
     // Get TOTP secret
-    let secret = match &user.totp_secret {
+    let secret = match user.totp_secret.as_deref().filter(|_| user.totp_enabled) {
         Some(s) => s,
         None => {
-            // 2FA not properly configured
-            session.remove::<u64>(SESSION_2FA_PENDING_KEY).await.unwrap_or_default();
-            return Redirect::to("/login").into_response();
+            return Login2faTemplate::for_user(&user, Some("Use your passkey to continue.".to_string()))
+                .into_response();
         }
     };
 
+    if let Err(wait) = throttle::check(Action::Login, client.ip.as_deref()) {
+        record_login(&state, &client, &user.username, Some(user.id), "Password + TOTP", Some("Too many attempts")).await;
+        let tmpl = Login2faTemplate::for_user(&user, Some(throttle::retry_message(wait)));
+        return (StatusCode::TOO_MANY_REQUESTS, tmpl).into_response();
+    }
+
     // Verify TOTP code
     let code = form.code.trim().replace(" ", "");
     if verify_totp(&code, secret) {
         // Clear pending state
         session.remove::<u64>(SESSION_2FA_PENDING_KEY).await.unwrap_or_default();
         // Complete login
-        session
-            .insert(SESSION_USER_ID_KEY, user.id)
-            .await
-            .unwrap_or_default();
-        return Redirect::to("/").into_response();
+        let remember = session.get::<bool>(SESSION_REMEMBER_KEY).await.ok().flatten().unwrap_or(false);
+        record_login(&state, &client, &user.username, Some(user.id), "Password + TOTP", None).await;
+        let landing = start_session(&session, &state, user.id, remember).await;
+        return Redirect::to(&landing).into_response();
+    }
+
+    throttle::record(Action::Login, client.ip.as_deref());
+    record_login(&state, &client, &user.username, Some(user.id), "Password + TOTP", Some("Invalid verification code")).await;
+    Login2faTemplate::for_user(&user, Some("Invalid verification code.".to_string())).into_response()
+}
+
+// ---- Passkey Login ----
+
+fn passkey_error(status: StatusCode, message: &str) -> Response {
+    (status, Json(serde_json::json!({ "error": message }))).into_response()
+}
+
+/// POST /login/passkey/options - Challenge for passkey sign-in. After a password, only the
+/// pending user's passkeys are offered; otherwise any discoverable passkey for this site.
+pub async fn passkey_login_options(
+    State(state): State<AppState>,
+    session: Session,
+    RequestHost(host): RequestHost,
+) -> Response {
+    let Some(host) = host else {
+        return passkey_error(StatusCode::BAD_REQUEST, "Missing Host header");
+    };
+    let allow = get_2fa_pending_user(&session, &state)
+        .await
+        .map(|u| u.passkeys)
+        .unwrap_or_default();
+
+    let pending = PendingChallenge::new(&host);
+    let options = passkeys::assertion_options(&pending, &allow);
+    if session.insert(SESSION_PASSKEY_LOGIN_KEY, pending).await.is_err() {
+        return passkey_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store challenge");
     }
+    Json(options).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct PasskeyLoginRequest {
+    #[serde(flatten)]
+    pub assertion: AssertionResponse,
+    /// The login page's "remember me" box
+    #[serde(default)]
+    pub remember: bool,
+}
+
+/// POST /login/passkey - Verify a passkey assertion and log the user in
+pub async fn passkey_login(
+    State(state): State<AppState>,
+    session: Session,
+    RequestHost(host): RequestHost,
+    client: ClientInfo,
+    Json(request): Json<PasskeyLoginRequest>,
+) -> Response {
+    let response = request.assertion;
+    let pending: Option<PendingChallenge> = session.remove(SESSION_PASSKEY_LOGIN_KEY).await.ok().flatten();
+    let (Some(pending), Some(host)) = (pending, host) else {
+        return passkey_error(StatusCode::BAD_REQUEST, "Sign-in session expired. Try again.");
+    };
+    let second_factor_for = get_2fa_pending_user_id(&session).await;
+    let method = if second_factor_for.is_some() { "Password + passkey" } else { "Passkey" };
+
+    let (user, result) = {
+        let mut config = state.config.write().await;
+        let Some(ref mut c) = *config else {
+            return passkey_error(StatusCode::SERVICE_UNAVAILABLE, "Not configured");
+        };
+        match c.find_user_by_passkey(&response.id) {
+            Some(u) if u.disabled => (
+                Some((u.id, u.username.clone())),
+                Err((StatusCode::FORBIDDEN, "This account is disabled.".to_string())),
+            ),
+            Some(u) if second_factor_for.is_none_or(|id| id == u.id) => {
+                let user_id = u.id;
+                let user = c.find_user_by_id_mut(user_id).expect("user exists");
+                let username = user.username.clone();
+                let passkey = user
+                    .passkeys
+                    .iter_mut()
+                    .find(|p| p.id == response.id)
+                    .expect("passkey exists");
+                // Without a password first, the passkey must verify the user itself
+                let result = match passkeys::verify_assertion(passkey, &response, &pending, &host, second_factor_for.is_none()) {
+                    Ok(sign_count) => {
+                        passkey.sign_count = sign_count;
+                        passkey.last_used = Some(chrono::Utc::now());
+                        let _ = c.save();
+                        Ok(())
+                    }
+                    Err(e) => Err((StatusCode::UNAUTHORIZED, e)),
+                };
+                (Some((user_id, username)), result)
+            }
+            _ => (None, Err((StatusCode::UNAUTHORIZED, "This passkey is not registered.".to_string()))),
+        }
+    };
+
+    let (user_id, username) = user.unzip();
+    let username = username.unwrap_or_default();
+    match result {
+        Ok(()) => {
+            let user_id = user_id.expect("verified passkey has a user");
+            info!("User '{}' signed in with a passkey", username);
+            record_login(&state, &client, &username, Some(user_id), method, None).await;
+            session.remove::<u64>(SESSION_2FA_PENDING_KEY).await.unwrap_or_default();
+            let remember = request.remember
+                || session.get::<bool>(SESSION_REMEMBER_KEY).await.ok().flatten().unwrap_or(false);
+            let landing = start_session(&session, &state, user_id, remember).await;
+            Json(serde_json::json!({ "redirect": landing })).into_response()
+        }
+        Err((status, e)) => {
+            record_login(&state, &client, &username, user_id, method, Some(&e)).await;
+            passkey_error(status, &e)
+        }
+    }
+}
+
+// ---- Password Reset ----
+
+#[derive(askama::Template, askama_web::WebTemplate)]
+#[template(path = "forgot_password.html")]
+pub struct ForgotPasswordTemplate {
+    /// The request was accepted; shown whether or not an account matched
+    pub sent: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordForm {
+    /// Username or email address
+    pub login: String,
+}
+
+async fn password_reset_available(state: &AppState) -> bool {
+    let config = state.config.read().await;
+    config.as_ref().is_some_and(|c| c.smtp.is_ready())
+}
 
-    Login2faTemplate {
-        error: Some("Invalid verification code.".to_string()),
+/// GET /forgot-password
+pub async fn forgot_password_page(State(state): State<AppState>) -> Response {
+    if !password_reset_available(&state).await {
+        return Redirect::to(&base_path::url("/login")).into_response();
+    }
+    ForgotPasswordTemplate { sent: false }.into_response()
+}
+
+/// POST /forgot-password - Email a reset link. The response is the same whether or not the
+/// account exists, so this can't be used to discover usernames.
+pub async fn forgot_password_submit(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Form(form): Form<ForgotPasswordForm>,
+) -> Response {
+    // Answer as if it worked, so throttling doesn't reveal anything either
+    if throttle::check(Action::PasswordReset, client.ip.as_deref()).is_err() {
+        warn!("Too many password reset requests from {}", client.display_ip());
+        return (StatusCode::TOO_MANY_REQUESTS, ForgotPasswordTemplate { sent: true }).into_response();
+    }
+    throttle::record(Action::PasswordReset, client.ip.as_deref());
+
+    let login = form.login.trim();
+    let pending = {
+        let mut config = state.config.write().await;
+        config.as_mut().filter(|c| c.smtp.is_ready()).and_then(|c| {
+            let user = c.users.iter().find(|u| {
+                u.username == login
+                    || u.email.as_deref().is_some_and(|e| !login.is_empty() && e.eq_ignore_ascii_case(login))
+            })?;
+            // Single sign-on accounts without a password are managed by the provider
+            if user.disabled || user.password_hash.is_empty() {
+                return None;
+            }
+            let (user_id, username, email) = (user.id, user.username.clone(), user.email.clone()?);
+            let token = c.start_password_reset(user_id)?;
+            if let Err(e) = c.save() {
+                warn!("Failed to save password reset: {}", e);
+                return None;
+            }
+            Some((c.smtp.clone(), username, email, token))
+        })
+    };
+
+    if let Some((settings, username, to, token)) = pending {
+        info!("Password reset requested for '{}' from {}", username, client.display_ip());
+        let link = settings.link(&format!("/reset-password?token={}", token));
+        let body = format!(
+            "Someone asked to reset the password for the TierDrop account '{}'.\n\n\
+             Open this link within {} minutes to choose a new password:\n{}\n\n\
+             If this wasn't you, ignore this email. Your password has not been changed.\n",
+            username,
+            crate::password_reset::RESET_VALID_MINUTES,
+            link
+        );
+        // Send in the background so the response time doesn't reveal whether the account exists
+        state.spawn(async move {
+            if let Err(e) = crate::email::send(&settings, &to, "Reset your TierDrop password", body).await {
+                warn!("Failed to send password reset email to '{}': {}", username, e);
+            }
+        });
+    }
+
+    ForgotPasswordTemplate { sent: true }.into_response()
+}
+
+#[derive(askama::Template, askama_web::WebTemplate)]
+#[template(path = "reset_password.html")]
+pub struct ResetPasswordTemplate {
+    pub token: String,
+    /// The token matches a live reset
+    pub valid: bool,
+    pub done: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordQuery {
+    #[serde(default)]
+    pub token: String,
+}
+
+/// GET /reset-password?token=...
+pub async fn reset_password_page(
+    State(state): State<AppState>,
+    Query(query): Query<ResetPasswordQuery>,
+) -> Response {
+    let valid = {
+        let config = state.config.read().await;
+        config.as_ref().and_then(|c| c.password_reset_user(&query.token)).is_some()
+    };
+    ResetPasswordTemplate {
+        token: query.token,
+        valid,
+        done: false,
+        error: None,
+    }
+    .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordForm {
+    pub token: String,
+    pub password: String,
+    pub password_confirm: String,
+}
+
+/// POST /reset-password - Set a new password and log out every existing session
+pub async fn reset_password_submit(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Form(form): Form<ResetPasswordForm>,
+) -> Response {
+    let invalid = || {
+        ResetPasswordTemplate {
+            token: String::new(),
+            valid: false,
+            done: false,
+            error: None,
+        }
+        .into_response()
+    };
+    let form_error = |error: &str| {
+        ResetPasswordTemplate {
+            token: form.token.clone(),
+            valid: true,
+            done: false,
+            error: Some(error.to_string()),
+        }
+        .into_response()
+    };
+
+    // Guessed tokens count as failed sign-ins, and are turned away before any hashing
+    if let Err(wait) = throttle::check(Action::Login, client.ip.as_deref()) {
+        warn!("Too many password reset attempts from {}", client.display_ip());
+        return (StatusCode::TOO_MANY_REQUESTS, form_error(&throttle::retry_message(wait))).into_response();
+    }
+    let known = {
+        let config = state.config.read().await;
+        config.as_ref().and_then(|c| c.password_reset_user(&form.token)).is_some()
+    };
+    if !known {
+        throttle::record(Action::Login, client.ip.as_deref());
+        return invalid();
+    }
+
+    if form.password != form.password_confirm {
+        return form_error("Passwords do not match.");
+    }
+    if form.password.len() < 4 {
+        return form_error("Password must be at least 4 characters.");
+    }
+    let password_hash = match hash_password(&form.password) {
+        Ok(h) => h,
+        Err(e) => return form_error(&e),
+    };
+
+    let user_id = {
+        let mut config = state.config.write().await;
+        config.as_mut().and_then(|c| {
+            let user_id = c.finish_password_reset(&form.token)?;
+            let user = c.find_user_by_id_mut(user_id)?;
+            user.password_hash = password_hash;
+            let username = user.username.clone();
+            c.audit(&username, "Password reset", None, None, "Reset by email link".to_string());
+            if let Err(e) = c.save() {
+                warn!("Failed to save password reset: {}", e);
+                return None;
+            }
+            info!("User '{}' reset their password", username);
+            Some(user_id)
+        })
+    };
+
+    // The token may have been used or expired while the password was hashed
+    let Some(user_id) = user_id else {
+        return invalid();
+    };
+    state.sessions.revoke_user(user_id).await;
+    ResetPasswordTemplate {
+        token: String::new(),
+        valid: true,
+        done: true,
+        error: None,
     }
     .into_response()
 }
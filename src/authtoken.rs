@@ -0,0 +1,91 @@
+//! Follows ZeroTier's `authtoken.secret`. When zerotier-one regenerates it, for example after
+//! a reinstall, the stored token stops working; the new one is picked up from the file instead
+//! of every page failing until someone reconfigures TierDrop.
+
+use std::path::Path;
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::audit::SYSTEM_ACTOR;
+use crate::routes::backup::zerotier_data_dir;
+use crate::state::AppState;
+use crate::zt::backend::SharedBackend;
+
+/// How often the token file is read
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+fn read_token(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Whether ZeroTier answers this client as an authorized caller. A rejected request still
+/// gets a reply, but without the node's address.
+async fn accepted(client: &SharedBackend) -> bool {
+    client.get_status().await.is_ok_and(|s| s.address.is_some())
+}
+
+/// Switch to the token in `authtoken.secret` when it differs from the stored one, the stored
+/// one is rejected and the new one works. A token passed over because the stored one still
+/// works, e.g. for a remote node, isn't tried again until the file changes.
+async fn check(state: &AppState, token: String, passed_over: &mut Option<String>) {
+    let candidate = {
+        let config = state.config.read().await;
+        let Some(c) = config.as_ref() else { return };
+        if c.zt_token == token || passed_over.as_ref() == Some(&token) {
+            return;
+        }
+        c.zt_client_with_token(token.clone())
+    };
+    let current = state.zt_client.read().await.clone();
+    if let Some(current) = current {
+        if accepted(&current).await {
+            *passed_over = Some(token);
+            return;
+        }
+    }
+    // ZeroTier may still be restarting; try again next time
+    if !accepted(&candidate).await {
+        return;
+    }
+
+    {
+        let mut config = state.config.write().await;
+        let Some(c) = config.as_mut() else { return };
+        c.zt_token = token;
+        c.audit(
+            SYSTEM_ACTOR,
+            "ZeroTier token updated",
+            None,
+            None,
+            "authtoken.secret was regenerated".to_string(),
+        );
+        if let Err(e) = c.save() {
+            warn!("Failed to save the new ZeroTier token: {}", e);
+        }
+    }
+    info!("ZeroTier's auth token changed; using the new one from authtoken.secret");
+    *state.zt_client.write().await = Some(candidate);
+    state.notify_poller();
+}
+
+/// Watch `authtoken.secret` in ZeroTier's data directory for a regenerated token
+pub async fn run_token_watch(state: AppState) {
+    // Demo mode's simulated controller has no token
+    if crate::demo::enabled() {
+        return;
+    }
+    let path = zerotier_data_dir().join("authtoken.secret");
+    let mut passed_over = None;
+    loop {
+        if let Some(token) = read_token(&path) {
+            check(&state, token, &mut passed_over).await;
+        }
+        if !state.idle(CHECK_INTERVAL).await {
+            break;
+        }
+    }
+}
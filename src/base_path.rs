@@ -0,0 +1,78 @@
+//! Serving under a URL sub-path such as `https://host/tierdrop`, set with `TIERDROP_BASE_PATH`.
+//!
+//! `strip_prefix_middleware` removes the prefix before routing, so routes are written from `/`. Links
+//! and redirects add it back: templates write `{{ crate::base_path::get() }}/settings`,
+//! handlers use `url("/settings")`.
+
+use std::sync::OnceLock;
+
+use axum::body::Body;
+use axum::http::uri::PathAndQuery;
+use axum::http::{Request, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Redirect, Response};
+
+static BASE_PATH: OnceLock<String> = OnceLock::new();
+
+/// `TIERDROP_BASE_PATH` normalized to a leading slash and no trailing one; empty for `/`
+pub fn from_env() -> Result<String, String> {
+    let raw = std::env::var("TIERDROP_BASE_PATH").unwrap_or_default();
+    let trimmed = raw.trim().trim_matches('/');
+    if trimmed.is_empty() {
+        return Ok(String::new());
+    }
+    let valid = trimmed.split('/').all(|segment| {
+        !segment.is_empty()
+            && segment != "."
+            && segment != ".."
+            && segment.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~'))
+    });
+    if !valid {
+        return Err(format!(
+            "TIERDROP_BASE_PATH may only use letters, digits, '-', '_', '.' and '~' between slashes, not {:?}",
+            raw
+        ));
+    }
+    Ok(format!("/{}", trimmed))
+}
+
+/// Set once at startup, before the router is built
+pub fn set(path: String) {
+    let _ = BASE_PATH.set(path);
+}
+
+/// The prefix, e.g. `/tierdrop`, or empty when served at the root
+pub fn get() -> &'static str {
+    BASE_PATH.get().map(String::as_str).unwrap_or("")
+}
+
+/// A path within TierDrop as the browser sees it
+pub fn url(path: &str) -> String {
+    format!("{}{}", get(), path)
+}
+
+/// Wraps the whole router, since routing must see the path without the prefix. Outside the
+/// prefix, `/` redirects into it and anything else is not found.
+pub async fn strip_prefix_middleware(mut request: Request<Body>, next: Next) -> Response {
+    let base = get();
+    if base.is_empty() {
+        return next.run(request).await;
+    }
+    let path = request.uri().path();
+    let rest = match path.strip_prefix(base) {
+        Some("") => "/",
+        Some(rest) if rest.starts_with('/') => rest,
+        _ if path == "/" => return Redirect::to(&url("/")).into_response(),
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("{}?{}", rest, query),
+        None => rest.to_string(),
+    };
+    let mut parts = request.uri().clone().into_parts();
+    parts.path_and_query = PathAndQuery::try_from(path_and_query).ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *request.uri_mut() = uri;
+    }
+    next.run(request).await
+}
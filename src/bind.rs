@@ -0,0 +1,104 @@
+//! Where the web server listens, set with `TIERDROP_BIND`: a comma-separated list of `ip:port`
+//! addresses and, on Unix, `unix:/path/to/socket` sockets for a reverse proxy on the same host.
+//! Unix sockets always serve plain HTTP, since the proxy in front of them handles TLS.
+
+use std::fmt;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+pub const DEFAULT_BIND: &str = "127.0.0.1:8000";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Bind {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Bind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bind::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Bind::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// The addresses in `TIERDROP_BIND`, or the default when it's unset
+pub fn from_env() -> Result<Vec<Bind>, String> {
+    let raw = std::env::var("TIERDROP_BIND").unwrap_or_default();
+    let raw = if raw.trim().is_empty() { DEFAULT_BIND } else { raw.as_str() };
+
+    let mut binds = Vec::new();
+    for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let bind = match entry.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) if !path.is_empty() => Bind::Unix(PathBuf::from(path)),
+            #[cfg(unix)]
+            Some(_) => return Err("TIERDROP_BIND has a unix: entry without a path".to_string()),
+            #[cfg(not(unix))]
+            Some(_) => return Err("Unix sockets in TIERDROP_BIND aren't supported on this platform".to_string()),
+            None => Bind::Tcp(entry.parse().map_err(|_| {
+                format!("Invalid bind address: {} (use ip:port, [ipv6]:port or unix:/path)", entry)
+            })?),
+        };
+        if !binds.contains(&bind) {
+            binds.push(bind);
+        }
+    }
+    if binds.is_empty() {
+        return Err("TIERDROP_BIND has no addresses".to_string());
+    }
+    Ok(binds)
+}
+
+/// The TCP addresses, for commands that connect to a running TierDrop
+pub fn tcp_addrs(binds: &[Bind]) -> impl Iterator<Item = SocketAddr> + '_ {
+    binds.iter().filter_map(|b| match b {
+        Bind::Tcp(addr) => Some(*addr),
+        #[cfg(unix)]
+        Bind::Unix(_) => None,
+    })
+}
+
+pub enum Listener {
+    Tcp(tokio::net::TcpListener),
+    /// With the socket file to remove at shutdown, when TierDrop created it
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, Option<PathBuf>),
+}
+
+impl Listener {
+    /// Where it listens, for the startup message
+    pub fn address(&self) -> Option<Bind> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().ok().map(Bind::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                let addr = listener.local_addr().ok()?;
+                addr.as_pathname().map(|path| Bind::Unix(path.to_path_buf()))
+            }
+        }
+    }
+}
+
+impl Bind {
+    pub async fn listen(&self) -> std::io::Result<Listener> {
+        match self {
+            Bind::Tcp(addr) => tokio::net::TcpListener::bind(addr).await.map(Listener::Tcp),
+            #[cfg(unix)]
+            Bind::Unix(path) => {
+                // A socket left behind by a previous run would make binding fail
+                use std::os::unix::fs::FileTypeExt;
+                if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket())
+                    && std::os::unix::net::UnixStream::connect(path).is_err()
+                {
+                    std::fs::remove_file(path)?;
+                }
+                tokio::net::UnixListener::bind(path).map(|l| Listener::Unix(l, Some(path.clone())))
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! First-run configuration without the setup wizard, for containers and provisioning tools.
+//!
+//! Reads `TIERDROP_ADMIN_USER`, `TIERDROP_ADMIN_PASSWORD` and `TIERDROP_ZT_TOKEN`, falling back
+//! to the same settings in the JSON file named by `TIERDROP_BOOTSTRAP_FILE`:
+//!
+//! ```json
+//! { "admin_user": "admin", "admin_password": "...", "zt_token": "..." }
+//! ```
+//!
+//! Only used when no configuration is stored yet; afterwards the settings are ignored.
+
+use serde::Deserialize;
+
+use crate::state::Config;
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BootstrapSettings {
+    admin_user: Option<String>,
+    admin_password: Option<String>,
+    zt_token: Option<String>,
+}
+
+impl BootstrapSettings {
+    fn from_file(path: &str) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        serde_json::from_str(&data).map_err(|e| format!("Failed to parse {}: {}", path, e))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.admin_user.is_none() && self.admin_password.is_none() && self.zt_token.is_none()
+    }
+}
+
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+/// Whether any bootstrap setting is given
+pub fn is_requested() -> bool {
+    ["TIERDROP_ADMIN_USER", "TIERDROP_ADMIN_PASSWORD", "TIERDROP_ZT_TOKEN", "TIERDROP_BOOTSTRAP_FILE"]
+        .iter()
+        .any(|name| env(name).is_some())
+}
+
+/// Create and save the initial config described by the bootstrap settings, or return None if
+/// there are none. Incomplete or invalid settings are an error rather than a fall back to the
+/// wizard, which would leave a headless instance open to whoever reaches it first.
+pub fn create() -> Result<Option<Config>, String> {
+    let file = match env("TIERDROP_BOOTSTRAP_FILE") {
+        Some(path) => BootstrapSettings::from_file(&path)?,
+        None => BootstrapSettings::default(),
+    };
+    let settings = BootstrapSettings {
+        admin_user: env("TIERDROP_ADMIN_USER").or(file.admin_user),
+        admin_password: env("TIERDROP_ADMIN_PASSWORD").or(file.admin_password),
+        zt_token: env("TIERDROP_ZT_TOKEN").or(file.zt_token),
+    };
+    if settings.is_empty() {
+        return Ok(None);
+    }
+
+    let (Some(user), Some(password), Some(zt_token)) =
+        (settings.admin_user, settings.admin_password, settings.zt_token)
+    else {
+        return Err("Bootstrap needs an admin user, admin password and ZeroTier auth token".to_string());
+    };
+    let mut config = crate::auth::initial_config(&user, &password, &zt_token)
+        .map_err(|e| format!("Invalid bootstrap settings: {}", e))?;
+    config.save()?;
+    tracing::info!("Created initial configuration for admin '{}' from bootstrap settings", user.trim());
+    Ok(Some(config))
+}
@@ -0,0 +1,156 @@
+//! Instance title, logo and accent color, so several TierDrop instances, or ones run for different
+//! clients, can be told apart.
+//!
+//! The settings live in [`Config`](crate::state::Config) and are copied here whenever the config is
+//! loaded or changed, for templates to read without being handed them: `{{ crate::branding::title() }}`.
+
+use std::sync::RwLock;
+
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Title used when none is set
+pub const DEFAULT_TITLE: &str = "TierDrop";
+
+/// Largest logo accepted
+pub const MAX_LOGO_BYTES: usize = 256 * 1024;
+
+/// Logo formats accepted. SVG isn't: served from TierDrop's own origin it could run scripts.
+const LOGO_TYPES: [(&str, &[u8]); 3] = [
+    ("image/png", b"\x89PNG\r\n\x1a\n"),
+    ("image/jpeg", b"\xff\xd8\xff"),
+    ("image/webp", b"RIFF"),
+];
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Branding {
+    /// Shown in the top bar, page titles and sign-in pages; empty for "TierDrop"
+    #[serde(default)]
+    pub title: String,
+    /// `#rrggbb` replacing the orange accent; empty for the default
+    #[serde(default)]
+    pub accent_color: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logo: Option<Logo>,
+}
+
+/// An uploaded logo image
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Logo {
+    pub content_type: String,
+    /// The image, base64-encoded
+    pub data: String,
+}
+
+/// The accent color if `input` is a `#rrggbb` color; empty input means the default
+pub fn parse_accent_color(input: &str) -> Result<String, String> {
+    let color = input.trim();
+    if color.is_empty() {
+        return Ok(String::new());
+    }
+    let valid = color.len() == 7 && color.starts_with('#') && color[1..].bytes().all(|b| b.is_ascii_hexdigit());
+    if !valid {
+        return Err(format!("Accent color must look like #f77f00, not {:?}", color));
+    }
+    Ok(color.to_ascii_lowercase())
+}
+
+/// A logo from uploaded bytes, if they're a PNG, JPEG or WebP image of acceptable size
+pub fn parse_logo(bytes: &[u8]) -> Result<Logo, String> {
+    if bytes.len() > MAX_LOGO_BYTES {
+        return Err(format!("The logo must be at most {} KB", MAX_LOGO_BYTES / 1024));
+    }
+    let content_type = LOGO_TYPES
+        .iter()
+        .find(|(kind, magic)| {
+            bytes.starts_with(magic) && (*kind != "image/webp" || bytes.get(8..12) == Some(b"WEBP".as_slice()))
+        })
+        .map(|(kind, _)| kind.to_string())
+        .ok_or("The logo must be a PNG, JPEG or WebP image")?;
+    Ok(Logo {
+        content_type,
+        data: STANDARD.encode(bytes),
+    })
+}
+
+/// What templates read
+struct Current {
+    title: String,
+    accent_color: String,
+    /// Changes with the logo, so browsers fetch a new one
+    logo_version: Option<String>,
+}
+
+static CURRENT: RwLock<Option<Current>> = RwLock::new(None);
+static LOGO: RwLock<Option<(String, Vec<u8>)>> = RwLock::new(None);
+
+/// Make `branding` the one shown; called whenever the config is loaded or changed
+pub fn set(branding: &Branding) {
+    let logo = branding
+        .logo
+        .as_ref()
+        .and_then(|logo| Some((logo.content_type.clone(), STANDARD.decode(&logo.data).ok()?)));
+    let logo_version = logo.as_ref().map(|(_, bytes)| {
+        let digest = Sha256::digest(bytes);
+        digest[..6].iter().map(|b| format!("{:02x}", b)).collect()
+    });
+    *CURRENT.write().unwrap() = Some(Current {
+        title: branding.title.trim().to_string(),
+        accent_color: branding.accent_color.clone(),
+        logo_version,
+    });
+    *LOGO.write().unwrap() = logo;
+}
+
+/// The instance title
+pub fn title() -> String {
+    CURRENT
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|c| c.title.clone())
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| DEFAULT_TITLE.to_string())
+}
+
+/// Whether a title other than "TierDrop" is set, which replaces the two-tone wordmark
+pub fn has_custom_title() -> bool {
+    CURRENT.read().unwrap().as_ref().is_some_and(|c| !c.title.is_empty())
+}
+
+/// The accent color, if one is set
+pub fn accent_color() -> Option<String> {
+    CURRENT
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|c| c.accent_color.clone())
+        .filter(|c| !c.is_empty())
+}
+
+/// Path of the logo, if one is set, including a version so it can be cached for good
+pub fn logo_url() -> Option<String> {
+    let version = CURRENT.read().unwrap().as_ref()?.logo_version.clone()?;
+    Some(crate::base_path::url(&format!("/branding/logo?v={}", version)))
+}
+
+/// GET /branding/logo - The uploaded logo; public, since sign-in pages show it
+pub async fn logo() -> Response {
+    match LOGO.read().unwrap().clone() {
+        Some((content_type, bytes)) => (
+            [
+                (CONTENT_TYPE, content_type),
+                (CACHE_CONTROL, "public, max-age=31536000, immutable".to_string()),
+                (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            ],
+            bytes,
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
@@ -0,0 +1,318 @@
+//! Command-line client for a running TierDrop instance, for off-host backup jobs.
+//!
+//! ```text
+//! tierdrop [--data-dir DIR] [--demo]
+//! tierdrop backup [-o FILE]
+//! tierdrop restore FILE [--dry-run] [--restart-zerotier]
+//! tierdrop doctor
+//! ```
+//!
+//! Authenticates with an admin's API key from `TIERDROP_API_KEY` (or `--token`) against
+//! `TIERDROP_URL` (or `--url`), defaulting to the local bind address.
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use reqwest::header::{AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_TYPE};
+use reqwest::StatusCode;
+
+use crate::base_path;
+use crate::bind;
+use crate::routes::backup::ApiRestoreResponse;
+
+const USAGE: &str = "Usage:
+  tierdrop [--data-dir DIR]         Run the web server, keeping its data in DIR
+                                    (TIERDROP_DATA_DIR, default per platform)
+  tierdrop --demo                   Run with a simulated ZeroTier controller and sample
+                                    data, kept in a temporary directory unless --data-dir is given
+  tierdrop backup [-o FILE]         Download a backup (to FILE, or the server's file name)
+  tierdrop restore FILE [--dry-run] Restore a backup, or only show what it would change
+           [--restart-zerotier]     and restart ZeroTier if the restore replaced its identity
+  tierdrop doctor                   Check ZeroTier, the data directory and the port, and suggest fixes
+
+Options:
+  --url URL      TierDrop address (TIERDROP_URL, default the first ip:port in TIERDROP_BIND)
+  --token KEY    Admin API key (TIERDROP_API_KEY)";
+
+enum Command {
+    Backup { output: Option<PathBuf> },
+    Restore { file: PathBuf, dry_run: bool, restart_zerotier: bool },
+}
+
+struct Client {
+    url: String,
+    token: String,
+    http: reqwest::Client,
+}
+
+/// Run a subcommand if one was given. Returns the process exit code, or None to start the server.
+pub async fn run() -> Option<i32> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let demo = take_flag(&mut args, "--demo");
+    if demo {
+        crate::demo::enable();
+    }
+    match take_data_dir(&mut args) {
+        Ok(Some(dir)) => crate::state::set_data_dir(dir),
+        // The demo must not touch a real installation's data, even one set in the environment
+        Ok(None) if demo => crate::state::set_data_dir(crate::demo::scratch_dir()),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, USAGE);
+            return Some(1);
+        }
+    }
+    let name = args.first()?;
+    if name == "-h" || name == "--help" {
+        println!("{}", USAGE);
+        return Some(0);
+    }
+    // Runs locally rather than against a server
+    if name == "doctor" && args.len() == 1 {
+        return Some(crate::doctor::run().await);
+    }
+    let result = match parse(&args) {
+        Ok(invocation) => invocation.run().await,
+        Err(e) => Err(format!("{}\n\n{}", e, USAGE)),
+    };
+    match result {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Some(1)
+        }
+    }
+}
+
+/// Remove a flag without a value from the arguments, returning whether it was there
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let before = args.len();
+    args.retain(|a| a != flag);
+    args.len() != before
+}
+
+/// Remove `--data-dir DIR` (or `--data-dir=DIR`) from the arguments
+fn take_data_dir(args: &mut Vec<String>) -> Result<Option<PathBuf>, String> {
+    let Some(i) = args.iter().position(|a| a == "--data-dir" || a.starts_with("--data-dir=")) else {
+        return Ok(None);
+    };
+    let flag = args.remove(i);
+    let dir = match flag.strip_prefix("--data-dir=") {
+        Some(dir) => dir.to_string(),
+        None if i < args.len() => args.remove(i),
+        None => return Err("--data-dir needs a value".to_string()),
+    };
+    if dir.is_empty() {
+        return Err("--data-dir needs a value".to_string());
+    }
+    Ok(Some(PathBuf::from(dir)))
+}
+
+struct Invocation {
+    command: Command,
+    url: String,
+    token: Option<String>,
+}
+
+impl Invocation {
+    async fn run(self) -> Result<(), String> {
+        let token = self
+            .token
+            .filter(|t| !t.is_empty())
+            .ok_or("No API key. Set TIERDROP_API_KEY or pass --token.")?;
+        let client = Client {
+            url: self.url.trim_end_matches('/').to_string(),
+            token,
+            http: reqwest::Client::new(),
+        };
+        match self.command {
+            Command::Backup { output } => backup(&client, output).await,
+            Command::Restore {
+                file,
+                dry_run,
+                restart_zerotier,
+            } => restore(&client, file, dry_run, restart_zerotier).await,
+        }
+    }
+}
+
+fn parse(args: &[String]) -> Result<Invocation, String> {
+    let mut url = std::env::var("TIERDROP_URL").ok();
+    let mut token = std::env::var("TIERDROP_API_KEY").ok();
+    let mut output = None;
+    let mut dry_run = false;
+    let mut restart_zerotier = false;
+    let mut positional = Vec::new();
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = |flag: &str| iter.next().cloned().ok_or(format!("{} needs a value", flag));
+        match arg.as_str() {
+            "--url" => url = Some(value("--url")?),
+            "--token" => token = Some(value("--token")?),
+            "-o" | "--output" => output = Some(PathBuf::from(value("--output")?)),
+            "--dry-run" => dry_run = true,
+            "--restart-zerotier" => restart_zerotier = true,
+            a if a.starts_with('-') => return Err(format!("Unknown option: {}", a)),
+            a => positional.push(a.to_string()),
+        }
+    }
+
+    let command = match (args[0].as_str(), positional.as_slice()) {
+        ("backup", []) if !dry_run && !restart_zerotier => Command::Backup { output },
+        ("restore", [file]) if output.is_none() => Command::Restore {
+            file: PathBuf::from(file),
+            dry_run,
+            restart_zerotier,
+        },
+        ("backup" | "restore", _) => return Err(format!("Invalid arguments for {}", args[0])),
+        (other, _) => return Err(format!("Unknown command: {}", other)),
+    };
+
+    let url = url.unwrap_or_else(|| {
+        let addr = bind::from_env()
+            .ok()
+            .and_then(|binds| bind::tcp_addrs(&binds).next())
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| bind::DEFAULT_BIND.to_string());
+        format!("http://{}{}", addr, base_path::from_env().unwrap_or_default())
+    });
+    Ok(Invocation { command, url, token })
+}
+
+impl Client {
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.http
+            .request(method, format!("{}{}", self.url, path))
+            .header(AUTHORIZATION, format!("Bearer {}", self.token))
+    }
+}
+
+/// Turn a non-success response into its error text
+async fn check(resp: reqwest::Response) -> Result<reqwest::Response, String> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let body = resp.text().await.unwrap_or_default();
+    Err(match status {
+        StatusCode::UNAUTHORIZED => "The API key was rejected".to_string(),
+        _ if body.is_empty() => format!("Server returned {}", status),
+        _ => format!("Server returned {}: {}", status, body.trim()),
+    })
+}
+
+async fn backup(client: &Client, output: Option<PathBuf>) -> Result<(), String> {
+    let resp = client
+        .request(reqwest::Method::GET, "/api/backup")
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach {}: {}", client.url, e))?;
+    let mut resp = check(resp).await?;
+
+    let path = match output {
+        Some(p) => p,
+        None => {
+            let name = resp
+                .headers()
+                .get(CONTENT_DISPOSITION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split("filename=").nth(1))
+                .map(|n| n.trim_matches('"').to_string())
+                .filter(|n| !n.is_empty() && !n.contains(['/', '\\']))
+                .ok_or("The server did not name the backup; pass -o FILE")?;
+            PathBuf::from(name)
+        }
+    };
+
+    // Written to a side file so a failed download doesn't leave a truncated backup behind
+    let partial = path.with_extension("part");
+    let mut file = std::fs::File::create(&partial)
+        .map_err(|e| format!("Failed to create {}: {}", partial.display(), e))?;
+    let mut size = 0u64;
+    let written: Result<(), String> = async {
+        while let Some(chunk) = resp.chunk().await.map_err(|e| format!("Download failed: {}", e))? {
+            file.write_all(&chunk).map_err(|e| format!("Failed to write backup: {}", e))?;
+            size += chunk.len() as u64;
+        }
+        file.sync_all().map_err(|e| format!("Failed to write backup: {}", e))
+    }
+    .await;
+    if let Err(e) = written.and_then(|_| {
+        std::fs::rename(&partial, &path).map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+    }) {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+
+    println!("Wrote {} ({} bytes)", path.display(), size);
+    Ok(())
+}
+
+async fn restore(client: &Client, file: PathBuf, dry_run: bool, restart_zerotier: bool) -> Result<(), String> {
+    let data = std::fs::read(&file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+    let resp = client
+        .request(
+            reqwest::Method::POST,
+            &format!("/api/restore?dry_run={}&restart_zerotier={}", dry_run, restart_zerotier),
+        )
+        .header(CONTENT_TYPE, "application/gzip")
+        .body(data)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach {}: {}", client.url, e))?;
+    let resp: ApiRestoreResponse = check(resp)
+        .await?
+        .json()
+        .await
+        .map_err(|e| format!("Unexpected response: {}", e))?;
+
+    let plan = &resp.plan;
+    println!(
+        "Backup from {} ({}, TierDrop v{})",
+        plan.display_created_at(),
+        plan.backup_type,
+        plan.tierdrop_version
+    );
+    if plan.has_networks {
+        for (label, networks) in [
+            ("Overwrite", &plan.networks_overwritten),
+            ("Add", &plan.networks_added),
+            ("Remove", &plan.networks_removed),
+        ] {
+            if !networks.is_empty() {
+                println!("  {} networks: {}", label, networks.join(", "));
+            }
+        }
+    } else {
+        println!("  Controller networks are not in this backup");
+    }
+    if let Some(identity) = &plan.identity {
+        if plan.identity_changes() {
+            println!("  Replace node identity {} with {}", identity.current, identity.backup);
+        } else {
+            println!("  Rewrite node identity {} (unchanged)", identity.backup);
+        }
+    }
+    if let Some(users) = &plan.users {
+        println!("  Replace settings and {} users with: {}", users.current_count, users.restored.join(", "));
+        if !users.removed.is_empty() {
+            println!("  Users losing access: {}", users.removed.join(", "));
+        }
+    }
+
+    match resp.result {
+        Some(result) => {
+            println!("{}", result.message);
+            if result.zerotier_restarted {
+                println!("ZeroTier was restarted. Restart TierDrop to fully apply the restored configuration.");
+            } else if result.needs_restart {
+                println!("Restart ZeroTier and TierDrop for the identity change to take effect.");
+            } else {
+                println!("Restart TierDrop to fully apply the restored configuration.");
+            }
+        }
+        None => println!("Dry run: nothing was changed."),
+    }
+    Ok(())
+}
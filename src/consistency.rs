@@ -0,0 +1,227 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Duration as ChronoDuration, Local, NaiveTime, Utc};
+use tracing::{info, warn};
+
+use crate::state::{AppState, Config, ALL_NETWORKS};
+use crate::zt::models::ZtState;
+
+/// Local time of day the nightly check runs
+const NIGHTLY_RUN_HOUR: u32 = 3;
+
+/// TierDrop metadata that no longer matches anything on the controller
+#[derive(Clone, Debug, Default)]
+pub struct ConsistencyReport {
+    pub generated_at: DateTime<Utc>,
+    pub member_names: Vec<String>,
+    pub member_descriptions: Vec<String>,
+    pub monitored_members: Vec<String>,
+    pub network_descriptions: Vec<String>,
+    pub rules_sources: Vec<String>,
+    pub rule_definitions: Vec<String>,
+    /// (username, nwid)
+    pub permissions: Vec<(String, String)>,
+    /// (group name, nwid)
+    pub group_permissions: Vec<(String, String)>,
+    /// Networks with invite links
+    pub invites: Vec<String>,
+}
+
+impl ConsistencyReport {
+    pub fn total(&self) -> usize {
+        self.member_names.len()
+            + self.member_descriptions.len()
+            + self.monitored_members.len()
+            + self.network_descriptions.len()
+            + self.rules_sources.len()
+            + self.rule_definitions.len()
+            + self.permissions.len()
+            + self.group_permissions.len()
+            + self.invites.len()
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.total() == 0
+    }
+
+    pub fn display_generated_at(&self) -> String {
+        self.generated_at
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    }
+}
+
+/// Compare the config against the poller's view of the controller. Returns an error
+/// when the cached state can't be trusted, so a controller outage never looks like
+/// every network and member vanished.
+pub fn build_report(config: &Config, zt: &ZtState) -> Result<ConsistencyReport, String> {
+    if !zt.polled() {
+        return Err("Controller has not been polled yet.".to_string());
+    }
+    if let Some(e) = &zt.error {
+        return Err(format!("Controller is unreachable: {}", e));
+    }
+
+    // Networks whose detail fetch failed still have a members entry
+    let networks: HashSet<&str> = zt
+        .controller_networks
+        .iter()
+        .map(|n| n.display_id())
+        .chain(zt.controller_members.keys().map(String::as_str))
+        .collect();
+    if networks.is_empty() {
+        return Err("Controller reported no networks; skipping to avoid removing everything.".to_string());
+    }
+    let members: HashSet<&str> = zt
+        .controller_members
+        .values()
+        .flatten()
+        .map(|m| m.display_id())
+        .collect();
+    // Member metadata isn't tied to a network, so while any member list failed to load no
+    // member can be called gone; members that failed to load on their own are kept too
+    let members_loaded = zt.unloaded_networks.is_empty();
+    let unloaded: HashSet<&str> = zt.unloaded_members.values().flatten().map(String::as_str).collect();
+    let member_gone = |address: &String| {
+        members_loaded && !members.contains(address.as_str()) && !unloaded.contains(address.as_str())
+    };
+
+    let sorted = |keys: Vec<&String>| {
+        let mut v: Vec<String> = keys.into_iter().cloned().collect();
+        v.sort();
+        v
+    };
+
+    let stale_permission = |nwid: &&String| {
+        *nwid != ALL_NETWORKS
+            && !networks.contains(nwid.as_str())
+            && !config.network_groups.iter().any(|g| g.permission_key() == **nwid)
+    };
+    let mut permissions: Vec<(String, String)> = config
+        .users
+        .iter()
+        .flat_map(|u| {
+            u.network_permissions
+                .keys()
+                .filter(stale_permission)
+                .map(|nwid| (u.username.clone(), nwid.clone()))
+        })
+        .collect();
+    permissions.sort();
+    let mut group_permissions: Vec<(String, String)> = config
+        .groups
+        .iter()
+        .flat_map(|g| {
+            g.network_permissions
+                .keys()
+                .filter(stale_permission)
+                .map(|nwid| (g.name.clone(), nwid.clone()))
+        })
+        .collect();
+    group_permissions.sort();
+    group_permissions.dedup();
+
+    let mut invites: Vec<String> = config
+        .invites
+        .iter()
+        .filter(|i| !networks.contains(i.nwid.as_str()))
+        .map(|i| i.nwid.clone())
+        .collect();
+    invites.sort();
+    invites.dedup();
+
+    Ok(ConsistencyReport {
+        generated_at: Utc::now(),
+        member_names: sorted(config.member_names.keys().filter(|a| member_gone(a)).collect()),
+        member_descriptions: sorted(config.member_descriptions.keys().filter(|a| member_gone(a)).collect()),
+        monitored_members: sorted(config.monitored_members.iter().filter(|a| member_gone(a)).collect()),
+        network_descriptions: sorted(
+            config.network_descriptions.keys().filter(|n| !networks.contains(n.as_str())).collect(),
+        ),
+        rules_sources: sorted(
+            config.rules_source.keys().filter(|n| !networks.contains(n.as_str())).collect(),
+        ),
+        rule_definitions: sorted(
+            config.rule_definitions.keys().filter(|n| !networks.contains(n.as_str())).collect(),
+        ),
+        permissions,
+        group_permissions,
+        invites,
+    })
+}
+
+/// Remove every stale entry listed in the report. Returns the number of entries removed.
+pub fn apply_cleanup(config: &mut Config, report: &ConsistencyReport) -> usize {
+    for address in &report.member_names {
+        config.member_names.remove(address);
+    }
+    for address in &report.member_descriptions {
+        config.member_descriptions.remove(address);
+    }
+    for address in &report.monitored_members {
+        config.monitored_members.remove(address);
+    }
+    for nwid in &report.network_descriptions {
+        config.network_descriptions.remove(nwid);
+    }
+    for nwid in &report.rules_sources {
+        config.rules_source.remove(nwid);
+    }
+    for nwid in &report.rule_definitions {
+        config.rule_definitions.remove(nwid);
+    }
+    for (username, nwid) in &report.permissions {
+        if let Some(user) = config.users.iter_mut().find(|u| &u.username == username) {
+            user.network_permissions.remove(nwid);
+        }
+    }
+    for (name, nwid) in &report.group_permissions {
+        for group in config.groups.iter_mut().filter(|g| &g.name == name) {
+            group.network_permissions.remove(nwid);
+        }
+    }
+    config.invites.retain(|i| !report.invites.contains(&i.nwid));
+    report.total()
+}
+
+impl AppState {
+    /// Build a fresh consistency report from the current config and cached controller state,
+    /// storing it as the latest report.
+    pub async fn run_consistency_check(&self) -> Result<ConsistencyReport, String> {
+        let report = {
+            let config = self.config.read().await;
+            let config = config.as_ref().ok_or("Not configured")?;
+            let zt = self.zt_state.read().await;
+            build_report(config, &zt)?
+        };
+        *self.consistency_report.write().await = Some(report.clone());
+        Ok(report)
+    }
+}
+
+fn until_next_run() -> std::time::Duration {
+    let now = Local::now();
+    let run_time = NaiveTime::from_hms_opt(NIGHTLY_RUN_HOUR, 0, 0).unwrap_or_default();
+    let mut next = now.date_naive().and_time(run_time);
+    if next <= now.naive_local() {
+        next += ChronoDuration::days(1);
+    }
+    (next - now.naive_local())
+        .to_std()
+        .unwrap_or(std::time::Duration::from_secs(24 * 60 * 60))
+}
+
+/// Run the consistency check every night and log what it finds. Cleanup stays manual.
+pub async fn run_nightly(state: AppState) {
+    while state.idle(until_next_run()).await {
+        match state.run_consistency_check().await {
+            Ok(report) if report.is_clean() => info!("Nightly consistency check: no stale metadata"),
+            Ok(report) => info!(
+                "Nightly consistency check: {} stale metadata entries (see Settings > Maintenance)",
+                report.total()
+            ),
+            Err(e) => warn!("Nightly consistency check skipped: {}", e),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Session cookie settings, read from the environment at startup:
+//!
+//! - `TIERDROP_COOKIE_SECURE`: `auto` (default), `always` or `never`
+//! - `TIERDROP_COOKIE_SAMESITE`: `strict` (default), `lax` or `none`
+//! - `TIERDROP_COOKIE_NAME`: defaults to `id`
+//!
+//! In `auto` mode cookies are always `Secure` when TierDrop terminates TLS itself. Behind a
+//! reverse proxy they are marked `Secure` when the proxy reports that the request arrived over
+//! HTTPS. Trusting the header is safe here: a forged one can only make a cookie stricter.
+
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::header::SET_COOKIE;
+use axum::http::{HeaderMap, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use tower_sessions::cookie::SameSite;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SecureMode {
+    /// Secure when the request came through an HTTPS proxy
+    Auto,
+    Always,
+    Never,
+}
+
+pub struct CookieSettings {
+    pub name: String,
+    pub secure: SecureMode,
+    pub same_site: SameSite,
+}
+
+impl CookieSettings {
+    pub fn from_env() -> Result<Self, String> {
+        let var = |name: &str| std::env::var(name).ok().map(|v| v.trim().to_ascii_lowercase()).filter(|v| !v.is_empty());
+
+        let secure = match var("TIERDROP_COOKIE_SECURE").as_deref() {
+            None | Some("auto") => SecureMode::Auto,
+            Some("always" | "true") => SecureMode::Always,
+            Some("never" | "false") => SecureMode::Never,
+            Some(other) => return Err(format!("TIERDROP_COOKIE_SECURE must be auto, always or never, not {}", other)),
+        };
+        let same_site = match var("TIERDROP_COOKIE_SAMESITE").as_deref() {
+            None | Some("strict") => SameSite::Strict,
+            Some("lax") => SameSite::Lax,
+            Some("none") => SameSite::None,
+            Some(other) => return Err(format!("TIERDROP_COOKIE_SAMESITE must be strict, lax or none, not {}", other)),
+        };
+        // Browsers drop SameSite=None cookies that aren't Secure
+        let secure = match (same_site, secure) {
+            (SameSite::None, SecureMode::Never) => {
+                return Err("TIERDROP_COOKIE_SAMESITE=none needs secure cookies; remove TIERDROP_COOKIE_SECURE=never".to_string())
+            }
+            (SameSite::None, _) => SecureMode::Always,
+            (_, secure) => secure,
+        };
+
+        let name = std::env::var("TIERDROP_COOKIE_NAME").unwrap_or_else(|_| "id".to_string());
+        let valid_name = !name.is_empty()
+            && name.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+        if !valid_name {
+            return Err(format!("TIERDROP_COOKIE_NAME may only use letters, digits, '-', '_' and '.', not {:?}", name));
+        }
+
+        Ok(Self { name, secure, same_site })
+    }
+}
+
+/// Whether a reverse proxy says the client connected over HTTPS
+fn is_https(headers: &HeaderMap) -> bool {
+    let forwarded_proto = headers
+        .get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("https"));
+    let forwarded = headers
+        .get("forwarded")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.to_ascii_lowercase().contains("proto=https"));
+    forwarded_proto || forwarded
+}
+
+/// In `auto` mode, mark cookies `Secure` on responses to requests that came over HTTPS
+pub async fn secure_over_https_middleware(State(mode): State<SecureMode>, request: Request<Body>, next: Next) -> Response {
+    let https = mode == SecureMode::Auto && is_https(request.headers());
+    let mut response = next.run(request).await;
+    if !https {
+        return response;
+    }
+
+    let headers = response.headers_mut();
+    let cookies: Vec<HeaderValue> = headers.get_all(SET_COOKIE).iter().cloned().collect();
+    headers.remove(SET_COOKIE);
+    for cookie in cookies {
+        headers.append(SET_COOKIE, with_secure(cookie));
+    }
+    response
+}
+
+fn with_secure(cookie: HeaderValue) -> HeaderValue {
+    let Ok(text) = cookie.to_str() else {
+        return cookie;
+    };
+    if text.split(';').skip(1).any(|attr| attr.trim().eq_ignore_ascii_case("secure")) {
+        return cookie;
+    }
+    HeaderValue::from_str(&format!("{}; Secure", text)).unwrap_or(cookie)
+}
@@ -0,0 +1,46 @@
+//! `tierdrop --demo`: a simulated ZeroTier controller with sample networks and members, and a
+//! throwaway data directory, for trying TierDrop without a ZeroTier node. See `zt::demo`.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::state::Config;
+
+pub const USERNAME: &str = "demo";
+pub const PASSWORD: &str = "tierdrop-demo";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Data directory used unless `--data-dir` is given, removed again at exit
+pub fn scratch_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("tierdrop-demo-{}", std::process::id()))
+}
+
+/// Remove the scratch data directory, if that's where the demo kept its data
+pub fn clean_up() {
+    let dir = crate::state::data_dir();
+    if dir == scratch_dir() {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
+
+/// The demo account, with names and descriptions for the sample networks and members
+pub fn initial_config() -> Result<Config, String> {
+    let mut config = crate::auth::initial_config(USERNAME, PASSWORD, "demo")?;
+    config.member_names = crate::zt::demo::member_names()
+        .map(|(address, name)| (address.to_string(), name.to_string()))
+        .collect();
+    config.network_descriptions = crate::zt::demo::network_descriptions()
+        .map(|(nwid, description)| (nwid, description.to_string()))
+        .collect();
+    config.save()?;
+    Ok(config)
+}
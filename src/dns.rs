@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tokio::sync::{watch, Notify};
+use tracing::{info, warn};
+
+use crate::state::{AppState, Config};
+use crate::zt::models::{ControllerMember, ControllerNetwork, ZtState};
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u16 = 3;
+const RCODE_NOTIMP: u16 = 4;
+const RCODE_REFUSED: u16 = 5;
+const RECORD_TTL: u32 = 60;
+
+/// Built-in DNS responder settings
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DnsServerSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_bind")]
+    pub bind: String,
+}
+
+impl Default for DnsServerSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: default_bind(),
+        }
+    }
+}
+
+fn default_bind() -> String {
+    "0.0.0.0:53".to_string()
+}
+
+/// Lets the settings page restart the responder and see whether it is listening
+#[derive(Default)]
+pub struct DnsServerHandle {
+    restart: Notify,
+    status: watch::Sender<DnsServerStatus>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum DnsServerStatus {
+    #[default]
+    Stopped,
+    Listening(SocketAddr),
+    Failed(String),
+}
+
+impl DnsServerStatus {
+    pub fn label(&self) -> String {
+        match self {
+            DnsServerStatus::Stopped => "Stopped".to_string(),
+            DnsServerStatus::Listening(addr) => format!("Listening on {} (UDP)", addr),
+            DnsServerStatus::Failed(e) => format!("Failed: {}", e),
+        }
+    }
+
+    pub fn class(&self) -> &'static str {
+        match self {
+            DnsServerStatus::Stopped => "status-unknown",
+            DnsServerStatus::Listening(_) => "status-ok",
+            DnsServerStatus::Failed(_) => "status-error",
+        }
+    }
+}
+
+impl DnsServerHandle {
+    /// Rebind with the current settings, waiting briefly for the new status
+    pub async fn restart(&self) {
+        let mut rx = self.status.subscribe();
+        self.restart.notify_one();
+        let _ = tokio::time::timeout(Duration::from_secs(2), rx.changed()).await;
+    }
+
+    pub fn status(&self) -> DnsServerStatus {
+        self.status.borrow().clone()
+    }
+
+    fn set_status(&self, status: DnsServerStatus) {
+        self.status.send_replace(status);
+    }
+}
+
+/// A name the responder answers for
+#[derive(Clone, Debug)]
+pub struct DnsRecord {
+    pub name: String,
+    pub addrs: Vec<IpAddr>,
+}
+
+/// Lowercase a member name into a DNS label: letters, digits and hyphens only
+pub fn to_label(name: &str) -> String {
+    let mut label = String::new();
+    for c in name.trim().to_lowercase().chars() {
+        if c.is_ascii_alphanumeric() {
+            label.push(c);
+        } else if !label.ends_with('-') {
+            label.push('-');
+        }
+    }
+    label.trim_matches('-').chars().take(63).collect()
+}
+
+/// Managed IPs of a member, plus its RFC4193 and 6PLANE addresses when the network enables them
+pub fn member_addresses(network: &ControllerNetwork, member: &ControllerMember) -> Vec<IpAddr> {
+    let mut addrs: Vec<IpAddr> = member
+        .ip_assignments
+        .iter()
+        .filter_map(|ip| ip.parse().ok())
+        .collect();
+    let sixplane = network.v6_sixplane().then(|| member.sixplane_address()).flatten();
+    let rfc4193 = network.v6_rfc4193().then(|| member.rfc4193_address()).flatten();
+    addrs.extend(sixplane.iter().chain(rfc4193.iter()).filter_map(|ip| ip.parse::<IpAddr>().ok()));
+    addrs
+}
+
+/// Records for every named member under its network's DNS domain, plus one per member ID
+/// (like zeronsd). Networks without a DNS domain are skipped.
+pub fn build_records(config: &Config, zt: &ZtState) -> Vec<DnsRecord> {
+    let mut records: HashMap<String, Vec<IpAddr>> = HashMap::new();
+    for network in &zt.controller_networks {
+        let domain = network.dns.domain.trim_end_matches('.').to_lowercase();
+        if domain.is_empty() {
+            continue;
+        }
+        let Some(members) = zt.controller_members.get(network.display_id()) else {
+            continue;
+        };
+        for m in members.iter().filter(|m| m.is_authorized()) {
+            let addrs = member_addresses(network, m);
+            if addrs.is_empty() {
+                continue;
+            }
+
+            let id = m.display_id();
+            let mut names = vec![format!("{}.{}", id, domain)];
+            if let Some(label) = config.member_names.get(id).map(|n| to_label(n)).filter(|l| !l.is_empty()) {
+                names.push(format!("{}.{}", label, domain));
+            }
+            for name in names {
+                let entry = records.entry(name).or_default();
+                for addr in &addrs {
+                    if !entry.contains(addr) {
+                        entry.push(*addr);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut records: Vec<DnsRecord> = records
+        .into_iter()
+        .map(|(name, addrs)| DnsRecord { name, addrs })
+        .collect();
+    records.sort_by(|a, b| a.name.cmp(&b.name));
+    records
+}
+
+/// Domains the responder is authoritative for
+fn served_domains(zt: &ZtState) -> Vec<String> {
+    zt.controller_networks
+        .iter()
+        .map(|n| n.dns.domain.trim_end_matches('.').to_lowercase())
+        .filter(|d| !d.is_empty())
+        .collect()
+}
+
+/// A parsed single-question query
+struct Query {
+    id: u16,
+    flags: u16,
+    name: String,
+    qtype: u16,
+    qclass: u16,
+    /// End of the question section
+    question_end: usize,
+}
+
+fn parse_query(packet: &[u8]) -> Option<Query> {
+    if packet.len() < 12 {
+        return None;
+    }
+    let id = u16::from_be_bytes([packet[0], packet[1]]);
+    let flags = u16::from_be_bytes([packet[2], packet[3]]);
+    let qdcount = u16::from_be_bytes([packet[4], packet[5]]);
+    // Responses and queries without exactly one question are ignored
+    if flags & 0x8000 != 0 || qdcount != 1 {
+        return None;
+    }
+
+    let mut labels = Vec::new();
+    let mut pos = 12;
+    loop {
+        let len = *packet.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        // Compression pointers aren't valid in the first question name
+        if len > 63 {
+            return None;
+        }
+        let label = packet.get(pos..pos + len)?;
+        labels.push(String::from_utf8_lossy(label).to_lowercase());
+        pos += len;
+    }
+    let qtype = u16::from_be_bytes([*packet.get(pos)?, *packet.get(pos + 1)?]);
+    let qclass = u16::from_be_bytes([*packet.get(pos + 2)?, *packet.get(pos + 3)?]);
+
+    Some(Query {
+        id,
+        flags,
+        name: labels.join("."),
+        qtype,
+        qclass,
+        question_end: pos + 4,
+    })
+}
+
+fn build_response(packet: &[u8], query: &Query, rcode: u16, authoritative: bool, answers: &[IpAddr]) -> Vec<u8> {
+    let opcode = query.flags & 0x7800;
+    let rd = query.flags & 0x0100;
+    let aa = if authoritative { 0x0400 } else { 0 };
+    let flags = 0x8000 | opcode | aa | rd | rcode;
+
+    let mut out = Vec::with_capacity(query.question_end + answers.len() * 28);
+    out.extend_from_slice(&query.id.to_be_bytes());
+    out.extend_from_slice(&flags.to_be_bytes());
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    out.extend_from_slice(&0u16.to_be_bytes());
+    out.extend_from_slice(&0u16.to_be_bytes());
+    out.extend_from_slice(&packet[12..query.question_end]);
+
+    for addr in answers {
+        // Pointer to the name in the question
+        out.extend_from_slice(&0xc00cu16.to_be_bytes());
+        let (rtype, rdata): (u16, Vec<u8>) = match addr {
+            IpAddr::V4(v4) => (TYPE_A, v4.octets().to_vec()),
+            IpAddr::V6(v6) => (TYPE_AAAA, v6.octets().to_vec()),
+        };
+        out.extend_from_slice(&rtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out.extend_from_slice(&RECORD_TTL.to_be_bytes());
+        out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        out.extend_from_slice(&rdata);
+    }
+    out
+}
+
+/// Answer one query packet. Names outside the served domains are refused.
+async fn answer(state: &AppState, packet: &[u8]) -> Option<Vec<u8>> {
+    let query = parse_query(packet)?;
+    if query.flags & 0x7800 != 0 {
+        return Some(build_response(packet, &query, RCODE_NOTIMP, false, &[]));
+    }
+
+    let (domains, records) = {
+        let config = state.config.read().await;
+        let zt = state.zt_state.read().await;
+        let records = config.as_ref().map(|c| build_records(c, &zt)).unwrap_or_default();
+        (served_domains(&zt), records)
+    };
+
+    let in_zone = domains
+        .iter()
+        .any(|d| query.name == *d || query.name.ends_with(&format!(".{}", d)));
+    if !in_zone || query.qclass != CLASS_IN {
+        return Some(build_response(packet, &query, RCODE_REFUSED, false, &[]));
+    }
+
+    let Some(record) = records.iter().find(|r| r.name == query.name) else {
+        // The zone apex exists but has no addresses of its own
+        let rcode = if domains.contains(&query.name) { 0 } else { RCODE_NXDOMAIN };
+        return Some(build_response(packet, &query, rcode, true, &[]));
+    };
+    let answers: Vec<IpAddr> = record
+        .addrs
+        .iter()
+        .filter(|a| match query.qtype {
+            TYPE_A => a.is_ipv4(),
+            TYPE_AAAA => a.is_ipv6(),
+            _ => false,
+        })
+        .copied()
+        .collect();
+    Some(build_response(packet, &query, 0, true, &answers))
+}
+
+async fn serve(state: &AppState, socket: UdpSocket) {
+    let mut buf = [0u8; 512];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("DNS receive failed: {}", e);
+                continue;
+            }
+        };
+        if let Some(response) = answer(state, &buf[..len]).await {
+            if let Err(e) = socket.send_to(&response, peer).await {
+                warn!("DNS reply to {} failed: {}", peer, e);
+            }
+        }
+    }
+}
+
+/// Run the responder whenever it is enabled, rebinding when the settings change.
+pub async fn run_dns_server(state: AppState, handle: Arc<DnsServerHandle>) {
+    let shutdown = state.shutdown.clone();
+    tokio::select! {
+        _ = run_responder(&state, &handle) => {}
+        _ = shutdown.cancelled() => handle.set_status(DnsServerStatus::Stopped),
+    }
+}
+
+async fn run_responder(state: &AppState, handle: &DnsServerHandle) {
+    loop {
+        let settings = {
+            let config = state.config.read().await;
+            config.as_ref().map(|c| c.dns_server.clone()).unwrap_or_default()
+        };
+
+        if !settings.enabled {
+            handle.set_status(DnsServerStatus::Stopped);
+            handle.restart.notified().await;
+            continue;
+        }
+
+        match UdpSocket::bind(&settings.bind).await {
+            Ok(socket) => {
+                let addr = socket.local_addr().ok();
+                info!("DNS server listening on {}", settings.bind);
+                handle.set_status(match addr {
+                    Some(a) => DnsServerStatus::Listening(a),
+                    None => DnsServerStatus::Stopped,
+                });
+                tokio::select! {
+                    _ = serve(state, socket) => {}
+                    _ = handle.restart.notified() => {}
+                }
+            }
+            Err(e) => {
+                warn!("DNS server failed to bind {}: {}", settings.bind, e);
+                handle.set_status(DnsServerStatus::Failed(e.to_string()));
+                handle.restart.notified().await;
+            }
+        }
+    }
+}
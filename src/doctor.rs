@@ -0,0 +1,463 @@
+//! `tierdrop doctor`: checks what TierDrop needs to run and says how to fix what's missing.
+//!
+//! Runs against the local machine with the same environment as the server, so run it as the
+//! user the service runs as.
+
+use std::path::Path;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+use crate::acme;
+use crate::base_path;
+use crate::bind::{self, Bind};
+use crate::routes::backup::zerotier_data_dir;
+use crate::state::{self, Config};
+use crate::tls::{self, TlsSettings};
+
+const DEFAULT_ZT_BASE_URL: &str = "http://localhost:9993";
+
+#[derive(Default)]
+struct Report {
+    failures: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn ok(&self, message: impl AsRef<str>) {
+        println!("  ok    {}", message.as_ref());
+    }
+
+    fn warn(&mut self, message: impl AsRef<str>, fix: impl AsRef<str>) {
+        self.warnings += 1;
+        println!("  WARN  {}", message.as_ref());
+        println!("        -> {}", fix.as_ref());
+    }
+
+    fn fail(&mut self, message: impl AsRef<str>, fix: impl AsRef<str>) {
+        self.failures += 1;
+        println!("  FAIL  {}", message.as_ref());
+        println!("        -> {}", fix.as_ref());
+    }
+}
+
+/// Where the ZeroTier auth token comes from
+struct Token {
+    value: String,
+    source: &'static str,
+}
+
+/// Run every check and print the results. Returns the process exit code.
+pub async fn run() -> i32 {
+    println!("TierDrop v{} diagnostics\n", crate::VERSION);
+    let mut report = Report::default();
+
+    println!("TierDrop data");
+    let config = check_data(&mut report);
+
+    println!("\nZeroTier");
+    let authtoken = check_zerotier_dir(&mut report);
+    let token = config
+        .as_ref()
+        .map(|c| Token { value: c.zt_token.clone(), source: "the TierDrop configuration" })
+        .or_else(|| {
+            std::env::var("TIERDROP_ZT_TOKEN")
+                .ok()
+                .filter(|t| !t.is_empty())
+                .map(|value| Token { value, source: "TIERDROP_ZT_TOKEN" })
+        })
+        .or_else(|| authtoken.clone().map(|value| Token { value, source: "authtoken.secret" }));
+    let base_url = std::env::var("ZT_BASE_URL")
+        .ok()
+        .or_else(|| config.as_ref().map(|c| c.zt_base_url.clone()))
+        .unwrap_or_else(|| DEFAULT_ZT_BASE_URL.to_string());
+    check_zerotier_api(&mut report, &base_url, token.as_ref(), authtoken.as_deref()).await;
+
+    println!("\nWeb server");
+    let https = check_tls(&mut report);
+    check_bind(&mut report, https).await;
+
+    println!();
+    match (report.failures, report.warnings) {
+        (0, 0) => println!("Everything looks good."),
+        (0, w) => println!("No problems that stop TierDrop from running; {} warning{}.", w, plural(w)),
+        (f, _) => println!("{} problem{} to fix before TierDrop can run properly.", f, plural(f)),
+    }
+    if report.failures > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+/// Data directory, database and its permissions. Returns the stored config, if readable.
+fn check_data(report: &mut Report) -> Option<Config> {
+    let dir = state::data_dir();
+    if !dir.exists() {
+        report.ok(format!("Data directory {} will be created on first start", dir.display()));
+    } else {
+        let probe = dir.join(format!(".doctor-{}", std::process::id()));
+        match std::fs::write(&probe, b"") {
+            Ok(()) => {
+                let _ = std::fs::remove_file(&probe);
+                report.ok(format!("Data directory {} is writable", dir.display()));
+            }
+            Err(e) => report.fail(
+                format!("Data directory {} is not writable: {}", dir.display(), e),
+                "Run as the user that owns it, fix its ownership (chown -R), or point TIERDROP_DATA_DIR at a writable directory",
+            ),
+        }
+    }
+
+    let db = state::db_path();
+    let legacy = dir.join(state::LEGACY_CONFIG_FILENAME);
+    if !db.exists() {
+        if legacy.exists() {
+            report.ok(format!("{} will be moved into the database on next start", legacy.display()));
+        } else {
+            report.warn(
+                "TierDrop has not been set up yet",
+                "Open the web UI to run the setup wizard, or set TIERDROP_ADMIN_USER, TIERDROP_ADMIN_PASSWORD and TIERDROP_ZT_TOKEN",
+            );
+        }
+        return None;
+    }
+
+    let config = match Config::reload() {
+        Ok(Some(config)) => {
+            let users = config.users.len();
+            report.ok(format!("Configuration in {} is readable ({} user{})", db.display(), users, plural(users)));
+            Some(config)
+        }
+        Ok(None) => {
+            report.warn(
+                format!("{} holds no configuration", db.display()),
+                "Open the web UI to run the setup wizard",
+            );
+            None
+        }
+        Err(e) => {
+            let fix = if state::db_backup_path().exists() {
+                format!(
+                    "TierDrop restores {} on its next start; changes since the last start will be lost",
+                    state::db_backup_path().display()
+                )
+            } else {
+                "Restore the database from a backup; there is no tierdrop.db.bak to recover from".to_string()
+            };
+            report.fail(format!("Configuration can't be read: {}", e), fix);
+            None
+        }
+    };
+    check_private(report, &db);
+    config
+}
+
+/// The database holds password hashes and the ZeroTier token
+#[cfg(unix)]
+fn check_private(report: &mut Report, path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    let Ok(meta) = std::fs::metadata(path) else {
+        return;
+    };
+    if meta.permissions().mode() & 0o077 != 0 {
+        report.warn(
+            format!("{} can be read by other users; it holds password hashes and the ZeroTier token", path.display()),
+            format!("chmod 600 {}", path.display()),
+        );
+    } else {
+        report.ok(format!("{} is only accessible to its owner", path.display()));
+    }
+}
+
+#[cfg(not(unix))]
+fn check_private(_report: &mut Report, _path: &Path) {}
+
+/// ZeroTier's data directory and auth token file. Returns the token, if readable.
+fn check_zerotier_dir(report: &mut Report) -> Option<String> {
+    let dir = zerotier_data_dir();
+    if !dir.exists() {
+        report.warn(
+            format!("ZeroTier data directory {} doesn't exist", dir.display()),
+            "Backups and restores need it: run TierDrop on the machine or in the container that runs ZeroTier",
+        );
+        return None;
+    }
+    if let Err(e) = std::fs::read_dir(&dir) {
+        report.warn(
+            format!("ZeroTier data directory {} can't be read: {}", dir.display(), e),
+            "Backups and restores need it: run TierDrop as root, or as a user with read access to it",
+        );
+        return None;
+    }
+    match std::fs::read_to_string(dir.join("identity.public")) {
+        Ok(identity) => report.ok(format!(
+            "ZeroTier data directory {} is readable (node {})",
+            dir.display(),
+            identity.split(':').next().unwrap_or("?")
+        )),
+        Err(_) => report.warn(
+            format!("{} has no readable identity.public", dir.display()),
+            "Start ZeroTier once so it creates its identity, or check that this is its data directory",
+        ),
+    }
+    match std::fs::read_to_string(dir.join("authtoken.secret")) {
+        Ok(token) => Some(token.trim().to_string()),
+        Err(e) => {
+            report.warn(
+                format!("{} can't be read: {}", dir.join("authtoken.secret").display(), e),
+                "Only needed to look up the token: run as root, or copy the token into setup by hand",
+            );
+            None
+        }
+    }
+}
+
+async fn check_zerotier_api(report: &mut Report, base_url: &str, token: Option<&Token>, authtoken: Option<&str>) {
+    let http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .unwrap_or_default();
+
+    let mut request = http.get(format!("{}/status", base_url));
+    if let Some(token) = token {
+        request = request.header("X-ZT1-Auth", &token.value);
+    }
+    let status = match request.send().await {
+        Ok(resp) => resp.status(),
+        Err(e) => {
+            report.fail(
+                format!("ZeroTier API at {} is unreachable: {}", base_url, e),
+                "Start ZeroTier (systemctl start zerotier-one), or set ZT_BASE_URL if it listens elsewhere",
+            );
+            return;
+        }
+    };
+    let Some(token) = token else {
+        report.ok(format!("ZeroTier API at {} is reachable", base_url));
+        report.fail(
+            "No ZeroTier auth token found",
+            "Pass it to setup or TIERDROP_ZT_TOKEN; it is in authtoken.secret in ZeroTier's data directory",
+        );
+        return;
+    };
+    match status {
+        s if s.is_success() => report.ok(format!("ZeroTier API at {} accepts the token from {}", base_url, token.source)),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            let fix = match authtoken {
+                // Output gets pasted into support threads, so the token itself is never printed
+                Some(current) if current != token.value => format!(
+                    "ZeroTier's authtoken.secret has changed since setup. Store the new one with: sqlite3 {} \"UPDATE settings SET value = json_quote('$(cat {})') WHERE key = 'zt_token'\" and send TierDrop SIGHUP",
+                    state::db_path().display(),
+                    zerotier_data_dir().join("authtoken.secret").display()
+                ),
+                _ => "Use the token from authtoken.secret in ZeroTier's data directory".to_string(),
+            };
+            report.fail(format!("ZeroTier rejected the auth token from {}", token.source), fix);
+            return;
+        }
+        s => {
+            report.fail(
+                format!("ZeroTier API at {} returned {}", base_url, s),
+                "Check that ZT_BASE_URL points at the ZeroTier service",
+            );
+            return;
+        }
+    }
+
+    match http
+        .get(format!("{}/controller", base_url))
+        .header("X-ZT1-Auth", &token.value)
+        .send()
+        .await
+    {
+        Ok(resp) if resp.status().is_success() => report.ok("Network controller is enabled"),
+        Ok(resp) => report.fail(
+            format!("Network controller is not available ({})", resp.status()),
+            "TierDrop needs a ZeroTier build with the network controller; the standard zerotier-one packages include it",
+        ),
+        Err(e) => report.fail(format!("Network controller check failed: {}", e), "Check that ZeroTier is running"),
+    }
+}
+
+/// Whether TierDrop serves HTTPS itself
+fn check_tls(report: &mut Report) -> bool {
+    let settings = match TlsSettings::from_env() {
+        Ok(Some(settings)) => settings,
+        Ok(None) => return false,
+        Err(e) => {
+            report.fail(e, "Fix the TIERDROP_TLS_* or TIERDROP_ACME_* variables");
+            return false;
+        }
+    };
+    let days_left = |not_after: chrono::DateTime<chrono::Utc>| (not_after - chrono::Utc::now()).num_days();
+    match settings {
+        TlsSettings::Files { cert, key } => match tls::load_files(&cert, &key) {
+            Ok((_, Some(not_after))) if days_left(not_after) < 0 => report.fail(
+                format!("TLS certificate {} expired on {}", cert.display(), not_after.format("%Y-%m-%d")),
+                "Renew it, then send TierDrop SIGHUP to load the new files",
+            ),
+            Ok((_, Some(not_after))) if days_left(not_after) < 14 => report.warn(
+                format!("TLS certificate {} expires on {}", cert.display(), not_after.format("%Y-%m-%d")),
+                "Renew it, then send TierDrop SIGHUP to load the new files",
+            ),
+            Ok(_) => report.ok(format!("TLS certificate {} is valid", cert.display())),
+            Err(e) => report.fail(e, "Check TIERDROP_TLS_CERT and TIERDROP_TLS_KEY and that the service user can read them"),
+        },
+        TlsSettings::Acme(settings) => {
+            match acme::cached_certificate(&settings) {
+                Some((_, Some(not_after))) => report.ok(format!(
+                    "ACME certificate for {} valid until {}",
+                    settings.domains.join(", "),
+                    not_after.format("%Y-%m-%d")
+                )),
+                _ => report.ok(format!(
+                    "No certificate for {} yet; TierDrop requests one when it starts",
+                    settings.domains.join(", ")
+                )),
+            }
+            let binds = bind::from_env().unwrap_or_default();
+            if !bind::tcp_addrs(&binds).any(|addr| addr.port() == 443) {
+                let listening = binds.iter().map(Bind::to_string).collect::<Vec<_>>().join(", ");
+                report.warn(
+                    format!("The CA validates domains on port 443, but TierDrop listens on {}", listening),
+                    "Set TIERDROP_BIND=0.0.0.0:443, or forward port 443 to TierDrop",
+                );
+            }
+        }
+    }
+    true
+}
+
+async fn check_bind(report: &mut Report, https: bool) {
+    let binds = match bind::from_env() {
+        Ok(binds) => binds,
+        Err(e) => {
+            report.fail(e, "Use ip:port, for example 127.0.0.1:8000 or 0.0.0.0:8000, or unix:/path, separated by commas");
+            return;
+        }
+    };
+    for b in binds {
+        match b {
+            Bind::Tcp(addr) => check_tcp_bind(report, addr, https).await,
+            #[cfg(unix)]
+            Bind::Unix(path) => check_unix_bind(report, &path).await,
+        }
+    }
+}
+
+async fn check_tcp_bind(report: &mut Report, addr: std::net::SocketAddr, https: bool) {
+    match std::net::TcpListener::bind(addr) {
+        Ok(_) => report.ok(format!("{} is free to listen on", addr)),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            if serves_tierdrop(addr, https).await {
+                report.ok(format!("TierDrop is already serving on {}", addr));
+            } else {
+                report.fail(
+                    format!("{} is in use by another program", addr),
+                    "Stop it, or set TIERDROP_BIND to a free port",
+                );
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => report.fail(
+            format!("Not allowed to listen on {}", addr),
+            "Ports below 1024 need root or CAP_NET_BIND_SERVICE; use a higher port behind a reverse proxy",
+        ),
+        Err(e) => report.fail(format!("Can't listen on {}: {}", addr, e), "Set TIERDROP_BIND to an address of this machine"),
+    }
+}
+
+#[cfg(unix)]
+async fn check_unix_bind(report: &mut Report, path: &Path) {
+    use std::os::unix::fs::FileTypeExt;
+
+    let name = path.display();
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if !meta.file_type().is_socket() => {
+            report.fail(format!("{} exists and isn't a socket", name), "Remove it, or choose another path");
+            return;
+        }
+        Ok(_) if std::os::unix::net::UnixStream::connect(path).is_ok() => {
+            if serves_tierdrop_unix(path).await {
+                report.ok(format!("TierDrop is already serving on unix:{}", name));
+            } else {
+                report.fail(format!("{} is in use by another program", name), "Stop it, or choose another path");
+            }
+            return;
+        }
+        // TierDrop replaces a socket nobody is listening on
+        _ => {}
+    }
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if !dir.is_dir() {
+        report.fail(
+            format!("Directory {} for unix:{} doesn't exist", dir.display(), name),
+            "Create it, e.g. with RuntimeDirectory= in the systemd unit",
+        );
+        return;
+    }
+    let probe = dir.join(format!(".tierdrop-doctor-{}", std::process::id()));
+    match std::os::unix::net::UnixListener::bind(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            report.ok(format!("unix:{} is free to listen on", name));
+        }
+        Err(e) => report.fail(
+            format!("Can't create a socket in {}: {}", dir.display(), e),
+            "Make the directory writable by the user TierDrop runs as",
+        ),
+    }
+}
+
+/// Whether the program holding the socket is TierDrop
+#[cfg(unix)]
+async fn serves_tierdrop_unix(path: &Path) -> bool {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let request = format!(
+        "GET {}/login HTTP/1.0\r\nHost: localhost\r\n\r\n",
+        base_path::from_env().unwrap_or_default()
+    );
+    let exchange = async {
+        let mut stream = tokio::net::UnixStream::connect(path).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+    match tokio::time::timeout(Duration::from_secs(3), exchange).await {
+        Ok(Ok(response)) => String::from_utf8_lossy(&response).contains("TierDrop"),
+        _ => false,
+    }
+}
+
+/// Whether the program holding the port is TierDrop
+async fn serves_tierdrop(addr: std::net::SocketAddr, https: bool) -> bool {
+    let host = if addr.ip().is_unspecified() { "127.0.0.1".to_string() } else { addr.ip().to_string() };
+    let scheme = if https { "https" } else { "http" };
+    let base = base_path::from_env().unwrap_or_default();
+    let url = match addr {
+        std::net::SocketAddr::V6(_) if !addr.ip().is_unspecified() => {
+            format!("{}://[{}]:{}{}/login", scheme, host, addr.port(), base)
+        }
+        _ => format!("{}://{}:{}{}/login", scheme, host, addr.port(), base),
+    };
+    // The certificate is for the public name, not the local address checked here
+    let http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(3))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default();
+    match http.get(url).send().await {
+        Ok(resp) => resp.text().await.map(|body| body.contains("TierDrop")).unwrap_or(false),
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+
+/// How the SMTP connection is secured
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Plain connection upgraded with STARTTLS, usually port 587
+    #[default]
+    StartTls,
+    /// TLS from the start, usually port 465
+    Tls,
+    /// No encryption; only for a relay on the same host or network
+    None,
+}
+
+impl SmtpSecurity {
+    pub const ALL: [SmtpSecurity; 3] = [SmtpSecurity::StartTls, SmtpSecurity::Tls, SmtpSecurity::None];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SmtpSecurity::StartTls => "starttls",
+            SmtpSecurity::Tls => "tls",
+            SmtpSecurity::None => "none",
+        }
+    }
+
+    pub fn parse(s: &str) -> Self {
+        SmtpSecurity::ALL.into_iter().find(|m| m.as_str() == s).unwrap_or_default()
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SmtpSecurity::StartTls => "STARTTLS",
+            SmtpSecurity::Tls => "TLS",
+            SmtpSecurity::None => "None",
+        }
+    }
+}
+
+/// Outgoing mail server, used for password reset emails
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SmtpSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    /// Leave empty for servers that don't need authentication
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    /// Sender address, e.g. `TierDrop <tierdrop@example.com>`
+    #[serde(default)]
+    pub from: String,
+    /// Externally reachable TierDrop URL the emailed links point at
+    #[serde(default)]
+    pub base_url: String,
+}
+
+fn default_port() -> u16 {
+    587
+}
+
+impl Default for SmtpSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: String::new(),
+            port: default_port(),
+            security: SmtpSecurity::default(),
+            username: String::new(),
+            password: String::new(),
+            from: String::new(),
+            base_url: String::new(),
+        }
+    }
+}
+
+impl SmtpSettings {
+    /// Enabled and filled in enough to send links
+    pub fn is_ready(&self) -> bool {
+        self.enabled && !self.host.is_empty() && !self.from.is_empty() && !self.base_url.is_empty()
+    }
+
+    pub fn link(&self, path: &str) -> String {
+        format!("{}{}", self.base_url.trim_end_matches('/'), path)
+    }
+}
+
+/// Send a plain-text email
+pub async fn send(settings: &SmtpSettings, to: &str, subject: &str, body: String) -> Result<(), String> {
+    let from: Mailbox = settings.from.parse().map_err(|e| format!("Invalid sender address: {}", e))?;
+    let to: Mailbox = to.parse().map_err(|e| format!("Invalid recipient address: {}", e))?;
+    let message = Message::builder()
+        .from(from)
+        .to(to)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(body)
+        .map_err(|e| format!("Failed to build email: {}", e))?;
+
+    let mut builder = match settings.security {
+        SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host),
+        SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host),
+        SmtpSecurity::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host)),
+    }
+    .map_err(|e| format!("Invalid SMTP server: {}", e))?
+    .port(settings.port)
+    .timeout(Some(Duration::from_secs(15)));
+    if !settings.username.is_empty() {
+        builder = builder.credentials(Credentials::new(settings.username.clone(), settings.password.clone()));
+    }
+
+    builder
+        .build()
+        .send(message)
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("Failed to send email: {}", e))
+}
+
+/// Loose check that an address looks deliverable before saving it
+pub fn is_valid_address(address: &str) -> bool {
+    address.parse::<Mailbox>().is_ok() && address.contains('@')
+}
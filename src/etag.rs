@@ -0,0 +1,63 @@
+//! ETags for the partials HTMX keeps refreshing, so a refresh that would bring back the same
+//! table gets an empty `304 Not Modified` instead.
+//!
+//! The tag is a hash of the rendered HTML and responses are marked `no-cache`, so the browser
+//! keeps the last copy, asks with `If-None-Match` on every refresh, and hands HTMX its copy when
+//! nothing changed.
+
+use axum::body::{Body, Bytes, HttpBody};
+use axum::http::header::{CACHE_CONTROL, CONTENT_LENGTH, ETAG, IF_NONE_MATCH};
+use axum::http::{HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+/// Bigger responses are passed through without a tag
+const MAX_TAGGED_BODY: usize = 16 * 1024 * 1024;
+
+fn etag(body: &Bytes) -> String {
+    format!("\"{}\"", URL_SAFE_NO_PAD.encode(&Sha256::digest(body)[..16]))
+}
+
+/// Whether `If-None-Match` names `tag`; weak comparison, as for GETs
+fn matches(headers: &HeaderMap, tag: &str) -> bool {
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|t| t.trim())
+        .any(|t| t == "*" || t.trim_start_matches("W/") == tag)
+}
+
+pub async fn etag_middleware(request: Request<Body>, next: Next) -> Response {
+    if request.method() != Method::GET {
+        return next.run(request).await;
+    }
+    let conditions = request.headers().clone();
+    let response = next.run(request).await;
+    let is_small = response.body().size_hint().upper().is_some_and(|len| len <= MAX_TAGGED_BODY as u64);
+    if response.status() != StatusCode::OK || !is_small {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_TAGGED_BODY).await {
+        Ok(body) => body,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let tag = etag(&body);
+    if let Ok(value) = HeaderValue::from_str(&tag) {
+        parts.headers.insert(ETAG, value);
+    }
+    parts.headers.insert(CACHE_CONTROL, HeaderValue::from_static("private, no-cache"));
+
+    if matches(&conditions, &tag) {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(CONTENT_LENGTH);
+        return Response::from_parts(parts, Body::empty());
+    }
+    Response::from_parts(parts, Body::from(body))
+}
@@ -0,0 +1,106 @@
+//! Where members connect from: the physical addresses of peer paths looked up in local MaxMind
+//! DB files (GeoLite2, DB-IP Lite, ...) named by `TIERDROP_GEOIP_DB`. Nothing is looked up
+//! without one, and nothing leaves the machine.
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+use maxminddb::Reader;
+use serde::Deserialize;
+
+static READERS: OnceLock<Vec<Reader<Vec<u8>>>> = OnceLock::new();
+
+/// The fields read from any database: country databases fill in the country, ASN databases the
+/// autonomous system, and City databases carry a country too
+#[derive(Deserialize)]
+struct Record {
+    country: Option<Country>,
+    registered_country: Option<Country>,
+    autonomous_system_number: Option<u32>,
+    autonomous_system_organization: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Country {
+    iso_code: Option<String>,
+    names: Option<BTreeMap<String, String>>,
+}
+
+/// What the databases know about one address
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Location {
+    /// ISO 3166 code, e.g. `DE`
+    pub country_code: Option<String>,
+    /// English country name
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+    pub as_org: Option<String>,
+}
+
+impl Location {
+    pub fn display_country(&self) -> String {
+        match (&self.country, &self.country_code) {
+            (Some(name), Some(code)) => format!("{} ({})", name, code),
+            (Some(name), None) => name.clone(),
+            (None, Some(code)) => code.clone(),
+            (None, None) => "-".to_string(),
+        }
+    }
+
+    pub fn display_as(&self) -> String {
+        match (self.asn, &self.as_org) {
+            (Some(asn), Some(org)) => format!("AS{} {}", asn, org),
+            (Some(asn), None) => format!("AS{}", asn),
+            (None, Some(org)) => org.clone(),
+            (None, None) => "-".to_string(),
+        }
+    }
+}
+
+/// The databases in `TIERDROP_GEOIP_DB`, a comma-separated list of `.mmdb` files
+pub fn from_env() -> Result<Vec<Reader<Vec<u8>>>, String> {
+    let raw = std::env::var("TIERDROP_GEOIP_DB").unwrap_or_default();
+    raw.split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|path| {
+            Reader::open_readfile(path)
+                .map_err(|e| format!("TIERDROP_GEOIP_DB: failed to open {}: {}", path, e))
+        })
+        .collect()
+}
+
+/// Set once at startup
+pub fn set(readers: Vec<Reader<Vec<u8>>>) {
+    let _ = READERS.set(readers);
+}
+
+pub fn enabled() -> bool {
+    READERS.get().is_some_and(|r| !r.is_empty())
+}
+
+/// Look `ip` up in every database, each filling in what the others didn't
+pub fn lookup(ip: IpAddr) -> Option<Location> {
+    let mut location = Location::default();
+    for reader in READERS.get()? {
+        let Ok(record) = reader.lookup::<Record>(ip) else {
+            continue;
+        };
+        if let Some(country) = record.country.or(record.registered_country) {
+            location.country_code = location.country_code.or(country.iso_code);
+            location.country = location
+                .country
+                .or_else(|| country.names.and_then(|mut n| n.remove("en")));
+        }
+        location.asn = location.asn.or(record.autonomous_system_number);
+        location.as_org = location.as_org.or(record.autonomous_system_organization);
+    }
+    (location != Location::default()).then_some(location)
+}
+
+/// The address part of a peer path such as `203.0.113.7/9993`
+pub fn path_ip(path: &str) -> Option<IpAddr> {
+    let host = path.rsplit_once('/').map_or(path, |(host, _)| host);
+    host.parse().ok()
+}
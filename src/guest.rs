@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+use crate::audit::SYSTEM_ACTOR;
+use crate::state::{AppState, Config};
+
+/// How often expired guest access is checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A member authorized until a fixed time
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GuestAccess {
+    pub nwid: String,
+    pub member_id: String,
+    pub until: DateTime<Utc>,
+    pub granted_by: String,
+}
+
+impl Config {
+    /// Member ID -> authorized-until time for one network
+    pub fn guest_expirations(&self, nwid: &str) -> HashMap<String, DateTime<Utc>> {
+        self.guest_access
+            .iter()
+            .filter(|g| g.nwid == nwid)
+            .map(|g| (g.member_id.clone(), g.until))
+            .collect()
+    }
+
+    /// Set or clear a member's access expiry. Returns true if anything changed.
+    pub fn set_guest_access(&mut self, nwid: &str, member_id: &str, until: Option<DateTime<Utc>>, by: &str) -> bool {
+        let before = self.guest_access.len();
+        let previous = self
+            .guest_access
+            .iter()
+            .find(|g| g.nwid == nwid && g.member_id == member_id)
+            .map(|g| g.until);
+        if previous == until {
+            return false;
+        }
+        self.guest_access.retain(|g| !(g.nwid == nwid && g.member_id == member_id));
+        match until {
+            Some(until) => {
+                self.guest_access.push(GuestAccess {
+                    nwid: nwid.to_string(),
+                    member_id: member_id.to_string(),
+                    until,
+                    granted_by: by.to_string(),
+                });
+                self.audit(
+                    by,
+                    "Guest access granted",
+                    Some(nwid),
+                    Some(member_id),
+                    format!("Authorized until {}", until.format("%Y-%m-%d %H:%M UTC")),
+                );
+            }
+            None if self.guest_access.len() != before => {
+                self.audit(by, "Guest access cleared", Some(nwid), Some(member_id), String::new());
+            }
+            None => {}
+        }
+        true
+    }
+}
+
+impl AppState {
+    /// Drop a member's access expiry, e.g. once it is de-authorized or deleted by hand
+    pub async fn clear_guest_access(&self, nwid: &str, member_id: &str, by: &str) {
+        let mut config = self.config.write().await;
+        if let Some(ref mut c) = *config {
+            if c.set_guest_access(nwid, member_id, None, by) {
+                let _ = c.save();
+            }
+        }
+    }
+}
+
+/// Parse a `datetime-local` form value, taken in the user's timezone. Empty means none.
+pub fn parse_until(value: &str) -> Result<Option<DateTime<Utc>>, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+        .map_err(|_| "Invalid date and time".to_string())?;
+    let until = crate::preferences::from_local(naive).ok_or_else(|| {
+        format!("That time doesn't exist in {}", crate::preferences::timezone_name())
+    })?;
+    if until <= Utc::now() {
+        return Err("Time must be in the future".to_string());
+    }
+    Ok(Some(until))
+}
+
+/// De-authorize members whose guest access has run out.
+pub async fn run_guest_expiry(state: AppState) {
+    while state.idle(CHECK_INTERVAL).await {
+        expire_due(&state).await;
+    }
+}
+
+async fn expire_due(state: &AppState) {
+    let now = Utc::now();
+    let due: Vec<GuestAccess> = {
+        let config = state.config.read().await;
+        let Some(c) = config.as_ref() else { return };
+        c.guest_access.iter().filter(|g| g.until <= now).cloned().collect()
+    };
+    if due.is_empty() {
+        return;
+    }
+    let Some(client) = state.zt_client.read().await.clone() else { return };
+
+    let mut expired = Vec::new();
+    for g in due {
+        // Posting to a deleted member would re-create it, so check it still exists
+        let result = match client.get_controller_member(&g.nwid, &g.member_id).await {
+            Ok(m) if m.address.is_none() && m.id.is_none() => Ok(false),
+            Ok(m) if !m.is_authorized() => Ok(false),
+            Ok(_) => client
+                .update_controller_member(&g.nwid, &g.member_id, serde_json::json!({"authorized": false}))
+                .await
+                .map(|_| true),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(deauthorized) => {
+                if deauthorized {
+                    info!("Guest access for {} on {} expired; de-authorized", g.member_id, g.nwid);
+                }
+                expired.push((g, deauthorized));
+            }
+            // Leave it for the next pass
+            Err(e) => warn!("Failed to expire guest access for {} on {}: {}", g.member_id, g.nwid, e),
+        }
+    }
+
+    {
+        let mut config = state.config.write().await;
+        let Some(ref mut c) = *config else { return };
+        for (g, deauthorized) in &expired {
+            c.guest_access
+                .retain(|e| !(e.nwid == g.nwid && e.member_id == g.member_id && e.until == g.until));
+            if *deauthorized {
+                c.audit(
+                    SYSTEM_ACTOR,
+                    "Guest access expired",
+                    Some(&g.nwid),
+                    Some(&g.member_id),
+                    format!("De-authorized (granted by {})", g.granted_by),
+                );
+            }
+        }
+        if let Err(e) = c.save() {
+            warn!("Failed to save guest access changes: {}", e);
+        }
+    }
+    state.notify_poller();
+}
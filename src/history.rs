@@ -0,0 +1,218 @@
+//! Member event history: what changed on the controller between successive polls (members
+//! joining, leaving, being authorized or deauthorized, IP changes, networks created or deleted),
+//! kept for a year and shown in a network's History tab and a member's details. Unlike the
+//! audit log it also covers changes made outside TierDrop.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::state::db_path;
+use crate::zt::models::{ControllerMember, ZtState};
+
+const RETENTION: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryKind {
+    NetworkCreated,
+    NetworkDeleted,
+    MemberAdded,
+    MemberRemoved,
+    MemberAuthorized,
+    MemberDeauthorized,
+    IpsChanged,
+}
+
+impl HistoryKind {
+    const ALL: [HistoryKind; 7] = [
+        HistoryKind::NetworkCreated,
+        HistoryKind::NetworkDeleted,
+        HistoryKind::MemberAdded,
+        HistoryKind::MemberRemoved,
+        HistoryKind::MemberAuthorized,
+        HistoryKind::MemberDeauthorized,
+        HistoryKind::IpsChanged,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HistoryKind::NetworkCreated => "network_created",
+            HistoryKind::NetworkDeleted => "network_deleted",
+            HistoryKind::MemberAdded => "member_added",
+            HistoryKind::MemberRemoved => "member_removed",
+            HistoryKind::MemberAuthorized => "member_authorized",
+            HistoryKind::MemberDeauthorized => "member_deauthorized",
+            HistoryKind::IpsChanged => "ips_changed",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.as_str() == s)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            HistoryKind::NetworkCreated => "Network created",
+            HistoryKind::NetworkDeleted => "Network deleted",
+            HistoryKind::MemberAdded => "Joined",
+            HistoryKind::MemberRemoved => "Removed",
+            HistoryKind::MemberAuthorized => "Authorized",
+            HistoryKind::MemberDeauthorized => "Deauthorized",
+            HistoryKind::IpsChanged => "IPs changed",
+        }
+    }
+
+    /// Badge class in the history list
+    pub fn badge_class(&self) -> &'static str {
+        match self {
+            HistoryKind::NetworkCreated | HistoryKind::MemberAdded | HistoryKind::MemberAuthorized => "status-ok",
+            HistoryKind::NetworkDeleted | HistoryKind::MemberRemoved | HistoryKind::MemberDeauthorized => "status-denied",
+            HistoryKind::IpsChanged => "status-unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEvent {
+    /// Assigned when stored; newer events have higher IDs
+    pub id: i64,
+    /// Unix time of the poll that noticed the change
+    pub time: i64,
+    pub kind: HistoryKind,
+    pub nwid: String,
+    pub member_id: Option<String>,
+    pub detail: Option<String>,
+}
+
+impl HistoryEvent {
+    fn new(time: i64, kind: HistoryKind, nwid: &str, member_id: Option<&str>, detail: Option<String>) -> Self {
+        HistoryEvent {
+            id: 0,
+            time,
+            kind,
+            nwid: nwid.to_string(),
+            member_id: member_id.map(str::to_string),
+            detail,
+        }
+    }
+
+    pub fn display_time(&self) -> String {
+        chrono::DateTime::from_timestamp(self.time, 0)
+            .map(crate::preferences::format_time_exact)
+            .unwrap_or_default()
+    }
+}
+
+fn unix_time(t: SystemTime) -> i64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+fn ip_list(ips: &BTreeSet<&str>) -> String {
+    if ips.is_empty() {
+        "none".to_string()
+    } else {
+        ips.iter().copied().collect::<Vec<_>>().join(", ")
+    }
+}
+
+/// What changed from `old` to `new`. Like notifications, nothing is reported when either poll
+/// failed or `old` isn't from this run, so outages and restarts don't look like mass changes.
+pub fn diff(old: &ZtState, new: &ZtState) -> Vec<HistoryEvent> {
+    let mut events = Vec::new();
+    if !old.polled() || old.error.is_some() || new.error.is_some() {
+        return events;
+    }
+    let time = new.last_updated.map(unix_time).unwrap_or_default();
+
+    // A network whose detail fetch failed still has a member list entry
+    let old_networks: HashSet<&str> = old
+        .controller_networks
+        .iter()
+        .map(|n| n.display_id())
+        .chain(old.controller_members.keys().map(String::as_str))
+        .collect();
+    let new_networks: HashSet<&str> = new
+        .controller_networks
+        .iter()
+        .map(|n| n.display_id())
+        .chain(new.controller_members.keys().map(String::as_str))
+        .collect();
+
+    for nwid in new_networks.difference(&old_networks) {
+        events.push(HistoryEvent::new(time, HistoryKind::NetworkCreated, nwid, None, None));
+    }
+    for nwid in old_networks.difference(&new_networks) {
+        events.push(HistoryEvent::new(time, HistoryKind::NetworkDeleted, nwid, None, None));
+    }
+
+    for (nwid, members) in &new.controller_members {
+        // Members of a new network are part of creating it. A member list that failed to load
+        // either time says nothing, so the network counts as unchanged.
+        if !old_networks.contains(nwid.as_str()) || !old.members_loaded(nwid) || !new.members_loaded(nwid) {
+            continue;
+        }
+        let previous: HashMap<&str, &ControllerMember> = old
+            .controller_members
+            .get(nwid)
+            .map(|ms| ms.iter().map(|m| (m.display_id(), m)).collect())
+            .unwrap_or_default();
+        let current: HashSet<&str> = members.iter().map(|m| m.display_id()).collect();
+
+        for member in members {
+            let id = Some(member.display_id());
+            let ips: BTreeSet<&str> = member.ip_assignments.iter().map(String::as_str).collect();
+            let Some(prev) = previous.get(member.display_id()) else {
+                // Back after failing to load last time, not new
+                if !old.member_loaded(nwid, member.display_id()) {
+                    continue;
+                }
+                let detail = (!ips.is_empty()).then(|| ip_list(&ips));
+                events.push(HistoryEvent::new(time, HistoryKind::MemberAdded, nwid, id, detail));
+                if member.is_authorized() {
+                    events.push(HistoryEvent::new(time, HistoryKind::MemberAuthorized, nwid, id, None));
+                }
+                continue;
+            };
+            if prev.is_authorized() != member.is_authorized() {
+                let kind = if member.is_authorized() {
+                    HistoryKind::MemberAuthorized
+                } else {
+                    HistoryKind::MemberDeauthorized
+                };
+                events.push(HistoryEvent::new(time, kind, nwid, id, None));
+            }
+            let prev_ips: BTreeSet<&str> = prev.ip_assignments.iter().map(String::as_str).collect();
+            if prev_ips != ips {
+                let detail = format!("{} → {}", ip_list(&prev_ips), ip_list(&ips));
+                events.push(HistoryEvent::new(time, HistoryKind::IpsChanged, nwid, id, Some(detail)));
+            }
+        }
+        for gone in previous.keys().filter(|id| !current.contains(*id) && new.member_loaded(nwid, id)) {
+            events.push(HistoryEvent::new(time, HistoryKind::MemberRemoved, nwid, Some(gone), None));
+        }
+    }
+
+    events
+}
+
+/// Store events, dropping those older than the retention period
+pub fn record(events: &[HistoryEvent]) -> Result<(), String> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    let keep_since = unix_time(SystemTime::now() - RETENTION);
+    crate::store::record_history(&db_path(), events, keep_since)
+}
+
+/// A page of up to `limit` of a network's events, or one member's, newest first. `before` is the ID
+/// of the last event on the previous page.
+pub fn events(
+    nwid: &str,
+    member_id: Option<&str>,
+    before: Option<i64>,
+    limit: usize,
+) -> Result<Vec<HistoryEvent>, String> {
+    crate::store::history(&db_path(), nwid, member_id, before, limit)
+}
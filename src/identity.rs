@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{info, warn};
+
+use crate::notifications::{EventKind, NotificationEvent};
+use crate::sse::SseEvent;
+use crate::state::AppState;
+
+/// A member ID that reappeared with a different identity than the one first seen
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IdentityAlert {
+    pub id: u64,
+    pub member_id: String,
+    pub previous_identity: String,
+    pub new_identity: String,
+    pub detected_at: DateTime<Utc>,
+    /// Networks the member was de-authorized on when the change was detected
+    #[serde(default)]
+    pub deauthorized: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledged_by: Option<String>,
+}
+
+impl IdentityAlert {
+    pub fn is_open(&self) -> bool {
+        self.acknowledged_at.is_none()
+    }
+
+    pub fn display_detected_at(&self) -> String {
+        crate::preferences::format_time_exact(self.detected_at)
+    }
+
+    /// Public key portion of an identity, shortened for display
+    fn short(identity: &str) -> String {
+        let key = identity.splitn(3, ':').nth(2).unwrap_or(identity);
+        if key.len() > 16 {
+            format!("{}…", &key[..16])
+        } else {
+            key.to_string()
+        }
+    }
+
+    pub fn short_previous(&self) -> String {
+        Self::short(&self.previous_identity)
+    }
+
+    pub fn short_new(&self) -> String {
+        Self::short(&self.new_identity)
+    }
+}
+
+/// Where a member was seen during an identity check
+#[derive(Clone)]
+struct Membership {
+    nwid: String,
+    network_name: String,
+    authorized: bool,
+}
+
+/// Watch member lists for identity changes whenever the poller reports member changes.
+pub async fn run_identity_watch(
+    state: AppState,
+    events: mpsc::UnboundedSender<NotificationEvent>,
+) {
+    let mut rx = state.tx.subscribe();
+    loop {
+        let received = tokio::select! {
+            received = rx.recv() => received,
+            _ = state.shutdown.cancelled() => break,
+        };
+        match received {
+            Ok(SseEvent::ControllerMembersChanged { .. }) => check_identities(&state, &events).await,
+            Ok(_) => {}
+            Err(broadcast::error::RecvError::Lagged(_)) => check_identities(&state, &events).await,
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Compare every member's identity against the first one recorded for its address.
+/// New addresses are recorded; mismatches are flagged, de-authorized, and notified.
+async fn check_identities(state: &AppState, events: &mpsc::UnboundedSender<NotificationEvent>) {
+    // member address -> (identity, networks it belongs to)
+    let mut seen: HashMap<String, (String, Vec<Membership>)> = HashMap::new();
+    {
+        let zt = state.zt_state.read().await;
+        // In network order, so the same network's identity stands for an address every run
+        let mut networks: Vec<_> = zt.controller_members.iter().collect();
+        networks.sort_by_key(|(nwid, _)| *nwid);
+        for (nwid, members) in networks {
+            let network_name = zt
+                .controller_networks
+                .iter()
+                .find(|n| n.display_id() == nwid)
+                .map(|n| n.display_name().to_string())
+                .unwrap_or_default();
+            for m in members {
+                let Some(identity) = m.identity.as_deref().filter(|i| !i.is_empty()) else {
+                    continue;
+                };
+                let entry = seen
+                    .entry(m.display_id().to_string())
+                    .or_insert_with(|| (identity.to_string(), Vec::new()));
+                entry.1.push(Membership {
+                    nwid: nwid.clone(),
+                    network_name: network_name.clone(),
+                    authorized: m.is_authorized(),
+                });
+            }
+        }
+    }
+
+    let mut collisions = Vec::new();
+    {
+        let mut cfg = state.config.write().await;
+        let Some(ref mut c) = *cfg else { return };
+        let mut changed = false;
+
+        for (member_id, (identity, networks)) in &seen {
+            match c.member_identities.get(member_id) {
+                None => {
+                    c.member_identities.insert(member_id.clone(), identity.clone());
+                    changed = true;
+                }
+                Some(known) if known != identity => {
+                    let already_flagged = c.identity_alerts.iter().any(|a| {
+                        a.is_open() && &a.member_id == member_id && &a.new_identity == identity
+                    });
+                    if !already_flagged {
+                        collisions.push((member_id.clone(), known.clone(), identity.clone(), networks.clone()));
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+
+        for (member_id, previous, new, networks) in &collisions {
+            warn!("Member {} re-registered with a different identity", member_id);
+            let id = c.identity_alerts.iter().map(|a| a.id).max().unwrap_or(0) + 1;
+            c.identity_alerts.push(IdentityAlert {
+                id,
+                member_id: member_id.clone(),
+                previous_identity: previous.clone(),
+                new_identity: new.clone(),
+                detected_at: Utc::now(),
+                deauthorized: networks
+                    .iter()
+                    .filter(|n| n.authorized)
+                    .map(|n| n.nwid.clone())
+                    .collect(),
+                acknowledged_at: None,
+                acknowledged_by: None,
+            });
+            changed = true;
+        }
+
+        if changed {
+            if let Err(e) = c.save() {
+                warn!("Failed to save member identities: {}", e);
+            }
+        }
+    }
+
+    if collisions.is_empty() {
+        return;
+    }
+
+    // De-authorize pending review, outside the config lock
+    let client = state.zt_client.read().await.clone();
+    for (member_id, _, _, networks) in &collisions {
+        for n in networks {
+            if n.authorized {
+                if let Some(c) = client.as_ref() {
+                    match c
+                        .update_controller_member(&n.nwid, member_id, serde_json::json!({"authorized": false}))
+                        .await
+                    {
+                        Ok(_) => info!("De-authorized {} on {} pending identity review", member_id, n.nwid),
+                        Err(e) => warn!("Failed to de-authorize {} on {}: {}", member_id, n.nwid, e),
+                    }
+                }
+            }
+            let _ = events.send(NotificationEvent {
+                kind: EventKind::IdentityChanged,
+                nwid: n.nwid.clone(),
+                network_name: n.network_name.clone(),
+                member_id: Some(member_id.clone()),
+                detail: None,
+            });
+        }
+    }
+    state.notify_poller();
+}
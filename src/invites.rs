@@ -0,0 +1,148 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Join requests accepted through one invite link, so a leaked link can't flood the controller
+const MAX_REQUESTS_PER_INVITE: usize = 25;
+
+/// A shareable join link for a network
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Invite {
+    pub token: String,
+    pub nwid: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub single_use: bool,
+    /// Times the join details of a single-use invite were shown
+    #[serde(default)]
+    pub uses: u32,
+    /// Visitors may submit their node ID for approval
+    #[serde(default)]
+    pub allow_requests: bool,
+    /// Node IDs submitted through this link
+    #[serde(default)]
+    pub requested: Vec<String>,
+}
+
+impl Invite {
+    pub fn new(
+        nwid: &str,
+        created_by: &str,
+        valid_for: Option<Duration>,
+        single_use: bool,
+        allow_requests: bool,
+    ) -> Self {
+        let now = Utc::now();
+        Self {
+            token: generate_token(),
+            nwid: nwid.to_string(),
+            created_by: created_by.to_string(),
+            created_at: now,
+            expires_at: valid_for.map(|d| now + d),
+            single_use,
+            uses: 0,
+            allow_requests,
+            requested: Vec::new(),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|t| t <= Utc::now())
+    }
+
+    pub fn is_used_up(&self) -> bool {
+        self.single_use && self.uses > 0
+    }
+
+    pub fn is_valid(&self) -> bool {
+        !self.is_expired() && !self.is_used_up()
+    }
+
+    /// Whether a join request can still be submitted. A single-use link takes one request,
+    /// even though opening it already used it up.
+    pub fn accepts_requests(&self) -> bool {
+        let limit = if self.single_use { 1 } else { MAX_REQUESTS_PER_INVITE };
+        self.allow_requests && !self.is_expired() && self.requested.len() < limit
+    }
+
+    pub fn status_label(&self) -> &'static str {
+        if self.is_used_up() {
+            "Used"
+        } else if self.is_expired() {
+            "Expired"
+        } else {
+            "Active"
+        }
+    }
+
+    pub fn status_class(&self) -> &'static str {
+        if self.is_valid() {
+            "status-ok"
+        } else {
+            "status-unknown"
+        }
+    }
+
+    pub fn display_created_at(&self) -> String {
+        crate::preferences::format_time(self.created_at)
+    }
+
+    pub fn display_expires_at(&self) -> String {
+        match self.expires_at {
+            Some(t) => crate::preferences::format_time(t),
+            None => "Never".to_string(),
+        }
+    }
+}
+
+/// A node ID submitted through an invite, waiting for an admin to approve or reject it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JoinRequest {
+    pub nwid: String,
+    pub member_id: String,
+    #[serde(default)]
+    pub name: String,
+    /// Username of the admin who created the invite
+    pub invited_by: String,
+    pub requested_at: DateTime<Utc>,
+}
+
+impl JoinRequest {
+    pub fn display_requested_at(&self) -> String {
+        crate::preferences::format_time(self.requested_at)
+    }
+}
+
+/// Validate a ZeroTier node address: 10 hex characters, not reserved
+pub fn parse_node_id(input: &str) -> Result<String, String> {
+    let node_id = input.trim().to_lowercase();
+    if node_id.len() != 10 || !node_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("Node ID must be 10 hex characters.".to_string());
+    }
+    if node_id.starts_with("ff") || node_id == "0000000000" {
+        return Err("That node ID is reserved.".to_string());
+    }
+    Ok(node_id)
+}
+
+/// 128-bit random token, hex encoded
+fn generate_token() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parse the invite lifetime select value (hours; empty means no expiry)
+pub fn parse_valid_for(hours: &str) -> Result<Option<Duration>, String> {
+    let hours = hours.trim();
+    if hours.is_empty() {
+        return Ok(None);
+    }
+    match hours.parse::<i64>() {
+        Ok(h) if h > 0 && h <= 24 * 365 => Ok(Some(Duration::hours(h))),
+        _ => Err("Invalid expiry.".to_string()),
+    }
+}
@@ -1,16 +1,66 @@
+mod access_log;
+mod agents;
+mod acme;
+mod api_keys;
 mod app;
+mod approvals;
 mod assets;
+mod audit;
+mod authtoken;
 mod auth;
+mod base_path;
+mod bind;
+mod bootstrap;
+mod branding;
+mod cli;
+mod consistency;
+mod cookies;
+mod demo;
+mod dns;
+mod doctor;
+mod email;
+mod etag;
+mod geoip;
+mod guest;
+mod history;
+mod identity;
+mod invites;
+mod metrics;
+mod monitor;
+mod notifications;
+mod oidc;
+mod passkeys;
+mod password_reset;
 mod permissions;
+mod preferences;
+mod releases;
+mod request_id;
+mod retry;
 mod routes;
+mod schedule;
+mod sessions;
+mod shares;
 mod sse;
+mod ws;
 mod state;
+mod store;
+mod systemd;
+mod throttle;
+mod tls;
+mod trusted_proxies;
 mod zt;
 
 /// Application version from Cargo.toml
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// How long shutdown waits for a poll, scheduled job or notification in progress
+const BACKGROUND_SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
 use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum_server::tls_rustls::RustlsConfig;
+use tokio_util::sync::CancellationToken;
 use tracing_subscriber::EnvFilter;
 
 use crate::state::{AppState, Config};
@@ -20,6 +70,11 @@ async fn main() {
     // Load .env file (silently ignore if missing)
     dotenvy::dotenv().ok();
 
+    // `tierdrop backup` and friends talk to a running instance instead of serving
+    if let Some(code) = cli::run().await {
+        std::process::exit(code);
+    }
+
     // Initialize logging
     tracing_subscriber::fmt()
         .with_env_filter(
@@ -28,8 +83,93 @@ async fn main() {
         )
         .init();
 
-    // Try to load existing config
-    let config = Config::load();
+    tracing::info!("Data directory: {}", state::data_dir().display());
+
+    let base = base_path::from_env().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    base_path::set(base);
+
+    let proxies = trusted_proxies::from_env().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    trusted_proxies::set(proxies);
+
+    let geoip_dbs = geoip::from_env().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if !geoip_dbs.is_empty() {
+        tracing::info!("Loaded {} GeoIP database(s)", geoip_dbs.len());
+    }
+    geoip::set(geoip_dbs);
+
+    let concurrency = zt::poller::concurrency_from_env().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    zt::poller::set_concurrency(concurrency);
+
+    let rate_limit = zt::client::rate_limit_from_env().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    zt::client::set_rate_limit(rate_limit);
+
+    let binds = bind::from_env().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    // Sockets passed by systemd take the place of TIERDROP_BIND
+    let activated = systemd::listeners().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let tls_settings = tls::TlsSettings::from_env().unwrap_or_else(|e| {
+        eprintln!("Invalid TLS settings: {}", e);
+        std::process::exit(1);
+    });
+    let mut cookie_settings = cookies::CookieSettings::from_env().unwrap_or_else(|e| {
+        eprintln!("Invalid cookie settings: {}", e);
+        std::process::exit(1);
+    });
+    // Every request arrives over HTTPS when TierDrop terminates TLS itself
+    if tls_settings.is_some() && cookie_settings.secure == cookies::SecureMode::Auto {
+        cookie_settings.secure = cookies::SecureMode::Always;
+    }
+
+    if demo::enabled() {
+        zt::demo::start();
+        println!("Demo mode: ZeroTier is simulated. Sign in as {} / {}", demo::USERNAME, demo::PASSWORD);
+    }
+
+    // Try to load existing config.
+    // A config that exists but can't be read must not fall through to the setup wizard
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("Failed to load configuration: {}", e);
+        std::process::exit(1);
+    });
+
+    // Containers and provisioning tools can create the first admin instead of the wizard
+    let config = match config {
+        Some(config) => {
+            if bootstrap::is_requested() {
+                tracing::info!("Configuration already exists; bootstrap settings ignored");
+            }
+            Some(config)
+        }
+        None if demo::enabled() => Some(demo::initial_config().unwrap_or_else(|e| {
+            eprintln!("Failed to create the demo configuration: {}", e);
+            std::process::exit(1);
+        })),
+        None => bootstrap::create().unwrap_or_else(|e| {
+            eprintln!("Failed to bootstrap configuration: {}", e);
+            std::process::exit(1);
+        }),
+    };
     let is_configured = config.is_some();
 
     // Build app state
@@ -37,42 +177,151 @@ async fn main() {
 
     // If already configured, start ZT client + poller immediately
     if is_configured {
+        // Show what the last run saw until the first poll comes back
+        if let Some(saved) = zt::snapshot::load() {
+            *state.zt_state.write().await = saved;
+        }
         state.start_zt().await;
         tracing::info!("Loaded existing configuration");
     } else {
         tracing::info!("No configuration found — setup wizard will be shown");
     }
 
+    let (tls, rustls_config) = match tls_settings {
+        Some(settings) => {
+            let (tls, config) = tls::Tls::start(settings).unwrap_or_else(|e| {
+                eprintln!("Failed to set up TLS: {}", e);
+                std::process::exit(1);
+            });
+            (Some(Arc::new(tls)), Some(config))
+        }
+        None => (None, None),
+    };
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(state.clone(), tls.clone()));
+    #[cfg(not(unix))]
+    drop(tls);
+    if let Some(interval) = systemd::watchdog_interval() {
+        tokio::spawn(systemd::watchdog(state.clone(), interval));
+    }
+
     // Build router
-    let app = app::build_router(state);
+    let background = state.clone();
+    let app = app::build_router(state, &cookie_settings);
+
+    // Bind every address before serving, so one that can't be used stops startup
+    let listeners = match activated {
+        Some(listeners) => listeners,
+        None => {
+            let mut listeners = Vec::new();
+            for bind in &binds {
+                listeners.push(bind.listen().await.unwrap_or_else(|e| {
+                    eprintln!("Failed to bind to {}: {}", bind, e);
+                    std::process::exit(1);
+                }));
+            }
+            listeners
+        }
+    };
+    let scheme = if rustls_config.is_some() { "https" } else { "http" };
+    for listener in &listeners {
+        let address = match listener.address() {
+            Some(bind::Bind::Tcp(addr)) => format!("{}://{}{}", scheme, addr, base_path::get()),
+            #[cfg(unix)]
+            Some(bind @ bind::Bind::Unix(_)) => bind.to_string(),
+            None => "a socket from systemd".to_string(),
+        };
+        println!("TierDrop v{} listening on {}", VERSION, address);
+    }
+    // The poller was started with the state, so everything is up
+    systemd::notify("READY=1");
 
-    // Bind and serve
-    let bind_addr =
-        std::env::var("TIERDROP_BIND").unwrap_or_else(|_| "127.0.0.1:8000".to_string());
-    let addr: SocketAddr = bind_addr.parse().unwrap_or_else(|_| {
-        eprintln!("Invalid bind address: {}", bind_addr);
+    // Graceful shutdown handling: one signal stops every listener and the background jobs
+    let shutdown = background.shutdown.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        systemd::notify("STOPPING=1");
+        shutdown.cancel();
+    });
+    let servers = listeners
+        .into_iter()
+        .map(|listener| serve(listener, app.clone(), rustls_config.clone(), background.shutdown.clone()));
+    futures::future::try_join_all(servers).await.unwrap_or_else(|e| {
+        eprintln!("Server error: {}", e);
         std::process::exit(1);
     });
+    if !background.shut_down(BACKGROUND_SHUTDOWN_TIMEOUT).await {
+        tracing::warn!(
+            "Background work still running after {}s, stopping anyway",
+            BACKGROUND_SHUTDOWN_TIMEOUT.as_secs()
+        );
+    }
 
-    println!("TierDrop v{} listening on http://{}", VERSION, addr);
+    demo::clean_up();
+    tracing::info!("Shutdown complete");
+}
 
-    let listener = tokio::net::TcpListener::bind(addr)
-        .await
-        .unwrap_or_else(|e| {
-            eprintln!("Failed to bind to {}: {}", addr, e);
-            std::process::exit(1);
-        });
-
-    // Graceful shutdown handling
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await
-        .unwrap_or_else(|e| {
-            eprintln!("Server error: {}", e);
-            std::process::exit(1);
-        });
+/// Serve one listener until shutdown; TLS applies to TCP listeners only
+async fn serve(
+    listener: bind::Listener,
+    app: axum::Router,
+    rustls_config: Option<RustlsConfig>,
+    shutdown: CancellationToken,
+) -> std::io::Result<()> {
+    let stopped = shutdown.cancelled_owned();
+    match listener {
+        bind::Listener::Tcp(listener) => match rustls_config {
+            Some(rustls_config) => {
+                let handle = axum_server::Handle::new();
+                let shutdown = handle.clone();
+                tokio::spawn(async move {
+                    stopped.await;
+                    shutdown.graceful_shutdown(Some(std::time::Duration::from_secs(10)));
+                });
+                axum_server::from_tcp_rustls(listener.into_std()?, rustls_config)
+                    .handle(handle)
+                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                    .await
+            }
+            None => {
+                axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                    .with_graceful_shutdown(stopped)
+                    .await
+            }
+        },
+        // Requests over a Unix socket carry no client address; see `trusted_proxies`
+        #[cfg(unix)]
+        bind::Listener::Unix(listener, path) => {
+            let served = axum::serve(listener, app.into_make_service()).with_graceful_shutdown(stopped).await;
+            if let Some(path) = path {
+                let _ = std::fs::remove_file(path);
+            }
+            served
+        }
+    }
+}
 
-    tracing::info!("Shutdown complete");
+/// Re-read the configuration and TLS certificate files on SIGHUP, e.g. after editing the
+/// database by hand or renewing the certificate
+#[cfg(unix)]
+async fn reload_on_sighup(state: AppState, tls: Option<Arc<tls::Tls>>) {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            tracing::warn!("Failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        match state.reload_config().await {
+            Ok(()) => tracing::info!("Received SIGHUP, configuration reloaded"),
+            Err(e) => tracing::error!("Received SIGHUP, but failed to reload configuration: {}", e),
+        }
+        if let Some(Err(e)) = tls.as_ref().map(|tls| tls.reload()) {
+            tracing::error!("Failed to reload TLS certificate, keeping the current one: {}", e);
+        }
+    }
 }
 
 async fn shutdown_signal() {
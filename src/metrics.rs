@@ -0,0 +1,183 @@
+//! Member count history: once a minute the latest poll is boiled down to member, authorized and
+//! online counts per network and stored for 30 days, for charts of growth and of members dropping
+//! off all at once.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::state::{db_path, AppState};
+use crate::zt::models::ZtState;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
+const RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Points per line in a chart; longer ranges are combined into buckets
+const CHART_POINTS: usize = 240;
+
+const CHART_WIDTH: f64 = 600.0;
+const CHART_HEIGHT: f64 = 120.0;
+
+/// One network's counts at one point in time
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    /// Unix time of the poll the counts come from
+    pub time: i64,
+    pub nwid: String,
+    pub members: u32,
+    pub authorized: u32,
+    /// Members whose node the controller currently sees online
+    pub online: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub enum Range {
+    #[default]
+    #[serde(rename = "24h")]
+    Day,
+    #[serde(rename = "7d")]
+    Week,
+    #[serde(rename = "30d")]
+    Month,
+}
+
+impl Range {
+    pub const ALL: [Range; 3] = [Range::Day, Range::Week, Range::Month];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Range::Day => "24h",
+            Range::Week => "7d",
+            Range::Month => "30d",
+        }
+    }
+
+    fn seconds(&self) -> i64 {
+        match self {
+            Range::Day => 24 * 60 * 60,
+            Range::Week => 7 * 24 * 60 * 60,
+            Range::Month => 30 * 24 * 60 * 60,
+        }
+    }
+}
+
+fn unix_time(t: SystemTime) -> i64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Counts for every network in a poll
+fn take_samples(zt: &ZtState, time: i64) -> Vec<Sample> {
+    zt.controller_networks
+        .iter()
+        .map(|network| {
+            let nwid = network.display_id();
+            let members = zt.controller_members.get(nwid).map(Vec::as_slice).unwrap_or_default();
+            Sample {
+                time,
+                nwid: nwid.to_string(),
+                members: members.len() as u32,
+                authorized: members.iter().filter(|m| m.is_authorized()).count() as u32,
+                online: members
+                    .iter()
+                    .filter(|m| zt.peers.get(m.display_id()).is_some_and(|p| p.is_online()))
+                    .count() as u32,
+            }
+        })
+        .collect()
+}
+
+/// Background job recording a sample per network from each new poll, at most once a minute
+pub async fn run_recorder(state: AppState) {
+    let mut recorded: Option<SystemTime> = None;
+    while state.idle(SAMPLE_INTERVAL).await {
+        let samples = {
+            let zt = state.zt_state.read().await;
+            // Nothing new, or nothing trustworthy: an outage shouldn't read as every member leaving
+            if !zt.polled() || zt.error.is_some() || zt.last_updated == recorded {
+                continue;
+            }
+            recorded = zt.last_updated;
+            take_samples(&zt, zt.last_updated.map(unix_time).unwrap_or_default())
+        };
+        let keep_since = unix_time(SystemTime::now() - RETENTION);
+        if let Err(e) = crate::store::record_samples(&db_path(), &samples, keep_since) {
+            warn!("Failed to record metrics: {}", e);
+        }
+    }
+}
+
+/// Samples within `range` for the networks `visible` accepts, oldest first
+pub fn history(range: Range, visible: impl Fn(&str) -> bool) -> Result<Vec<Sample>, String> {
+    let since = unix_time(SystemTime::now()) - range.seconds();
+    let mut samples = crate::store::samples(&db_path(), since)?;
+    samples.retain(|s| visible(&s.nwid));
+    Ok(samples)
+}
+
+/// A line chart of member counts over a range, as SVG polyline points
+pub struct Chart {
+    pub range: Range,
+    pub members: String,
+    pub authorized: String,
+    pub online: String,
+    /// Value at the top of the chart
+    pub max: u32,
+    /// The most recent counts: members, authorized, online
+    pub latest: Option<(u32, u32, u32)>,
+}
+
+impl Chart {
+    pub const WIDTH: f64 = CHART_WIDTH;
+    pub const HEIGHT: f64 = CHART_HEIGHT;
+
+    /// Chart the sum over all networks in `samples`. Each point of a long range covers several
+    /// samples: it shows the last member counts and the lowest online count among them, so a
+    /// brief mass disconnect still shows as a dip.
+    pub fn new(range: Range, samples: &[Sample]) -> Self {
+        // Networks are sampled together, so totals are per sample time
+        let mut totals: BTreeMap<i64, (u32, u32, u32)> = BTreeMap::new();
+        for s in samples {
+            let t = totals.entry(s.time).or_default();
+            t.0 += s.members;
+            t.1 += s.authorized;
+            t.2 += s.online;
+        }
+
+        let end = unix_time(SystemTime::now());
+        let start = end - range.seconds();
+        let bucket = (range.seconds() / CHART_POINTS as i64).max(1);
+        let mut points: BTreeMap<i64, (u32, u32, u32)> = BTreeMap::new();
+        for (&time, &(members, authorized, online)) in &totals {
+            let slot = (time - start) / bucket;
+            points
+                .entry(slot)
+                .and_modify(|p| *p = (members, authorized, p.2.min(online)))
+                .or_insert((members, authorized, online));
+        }
+
+        let max = points.values().map(|p| p.0.max(p.1).max(p.2)).max().unwrap_or(0).max(1);
+        let line = |value: fn(&(u32, u32, u32)) -> u32| -> String {
+            points
+                .iter()
+                .map(|(slot, p)| {
+                    let x = ((slot * bucket) as f64 / range.seconds() as f64 * CHART_WIDTH).clamp(0.0, CHART_WIDTH);
+                    let y = CHART_HEIGHT - value(p) as f64 / max as f64 * CHART_HEIGHT;
+                    format!("{:.1},{:.1}", x, y)
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        Chart {
+            range,
+            members: line(|p| p.0),
+            authorized: line(|p| p.1),
+            online: line(|p| p.2),
+            max,
+            latest: totals.values().next_back().copied(),
+        }
+    }
+}
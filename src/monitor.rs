@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
+use tracing::info;
+
+use crate::notifications::{self, EventKind, NotificationEvent};
+use crate::state::AppState;
+
+/// How often monitored members are checked against the peer list
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Offline tracking for one monitored member
+struct Outage {
+    since: Instant,
+    alerted: bool,
+}
+
+/// Report monitored members that stay offline longer than the configured threshold,
+/// and again when they come back.
+pub async fn run_member_monitor(state: AppState, events: mpsc::UnboundedSender<NotificationEvent>) {
+    let mut outages: HashMap<String, Outage> = HashMap::new();
+    while state.idle(CHECK_INTERVAL).await {
+        check_members(&state, &events, &mut outages).await;
+    }
+}
+
+async fn check_members(
+    state: &AppState,
+    events: &mpsc::UnboundedSender<NotificationEvent>,
+    outages: &mut HashMap<String, Outage>,
+) {
+    let (monitored, threshold) = {
+        let config = state.config.read().await;
+        let Some(c) = config.as_ref() else { return };
+        (
+            c.monitored_members.clone(),
+            Duration::from_secs(u64::from(c.notifications.offline_after_minutes) * 60),
+        )
+    };
+    outages.retain(|address, _| monitored.contains(address));
+
+    let zt = state.zt_state.read().await;
+    // Without a fresh poll every member would look offline; the controller alert covers that
+    if !zt.polled() || zt.error.is_some() {
+        return;
+    }
+
+    for address in &monitored {
+        let online = zt.peers.get(address).is_some_and(|p| p.is_online());
+        let (kind, detail) = if online {
+            match outages.remove(address) {
+                Some(outage) if outage.alerted => {
+                    info!("Monitored member {} is back online", address);
+                    let secs = outage.since.elapsed().as_secs();
+                    (EventKind::MemberOnline, format!("offline for {}", notifications::format_duration(secs)))
+                }
+                _ => continue,
+            }
+        } else {
+            let outage = outages
+                .entry(address.clone())
+                .or_insert_with(|| Outage { since: Instant::now(), alerted: false });
+            if outage.alerted || outage.since.elapsed() < threshold {
+                continue;
+            }
+            outage.alerted = true;
+            info!("Monitored member {} is offline", address);
+            let secs = outage.since.elapsed().as_secs();
+            let detail = format!("offline for {}", notifications::format_duration(secs));
+            (EventKind::MemberOffline, detail)
+        };
+
+        // Attribute the event to one of the member's networks so network routes apply
+        let nwid = zt
+            .controller_members
+            .iter()
+            .filter(|(_, members)| members.iter().any(|m| m.display_id() == address))
+            .map(|(nwid, _)| nwid.clone())
+            .min()
+            .unwrap_or_default();
+        let network_name = zt
+            .controller_networks
+            .iter()
+            .find(|n| n.display_id() == nwid)
+            .map(|n| n.display_name().to_string())
+            .unwrap_or_default();
+        let _ = events.send(NotificationEvent {
+            kind,
+            nwid,
+            network_name,
+            member_id: Some(address.clone()),
+            detail: Some(detail),
+        });
+    }
+}
@@ -0,0 +1,541 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, RwLock};
+use tracing::{debug, warn};
+
+use crate::approvals::{self, ApprovalLinkSettings};
+use crate::state::Config;
+use crate::zt::models::{ControllerMember, ZtState};
+
+/// Types of events that can be routed to notification channels
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    MemberPending,
+    MemberAuthorized,
+    MemberDeauthorized,
+    NetworkCreated,
+    NetworkDeleted,
+    IdentityChanged,
+    JoinRequested,
+    BackupCompleted,
+    ControllerUnreachable,
+    ControllerRecovered,
+    MemberOffline,
+    MemberOnline,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 12] = [
+        EventKind::MemberPending,
+        EventKind::MemberAuthorized,
+        EventKind::MemberDeauthorized,
+        EventKind::NetworkCreated,
+        EventKind::NetworkDeleted,
+        EventKind::IdentityChanged,
+        EventKind::JoinRequested,
+        EventKind::BackupCompleted,
+        EventKind::ControllerUnreachable,
+        EventKind::ControllerRecovered,
+        EventKind::MemberOffline,
+        EventKind::MemberOnline,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::MemberPending => "member_pending",
+            EventKind::MemberAuthorized => "member_authorized",
+            EventKind::MemberDeauthorized => "member_deauthorized",
+            EventKind::NetworkCreated => "network_created",
+            EventKind::NetworkDeleted => "network_deleted",
+            EventKind::IdentityChanged => "identity_changed",
+            EventKind::JoinRequested => "join_requested",
+            EventKind::BackupCompleted => "backup_completed",
+            EventKind::ControllerUnreachable => "controller_unreachable",
+            EventKind::ControllerRecovered => "controller_recovered",
+            EventKind::MemberOffline => "member_offline",
+            EventKind::MemberOnline => "member_online",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            EventKind::MemberPending => "Member pending authorization",
+            EventKind::MemberAuthorized => "Member authorized",
+            EventKind::MemberDeauthorized => "Member deauthorized",
+            EventKind::NetworkCreated => "Network created",
+            EventKind::NetworkDeleted => "Network deleted",
+            EventKind::IdentityChanged => "Member identity changed",
+            EventKind::JoinRequested => "Join request received",
+            EventKind::BackupCompleted => "Backup completed",
+            EventKind::ControllerUnreachable => "Controller unreachable",
+            EventKind::ControllerRecovered => "Controller reachable again",
+            EventKind::MemberOffline => "Monitored member offline",
+            EventKind::MemberOnline => "Monitored member back online",
+        }
+    }
+
+    /// Embed colour for chat channels: green for good news, red for removals, orange for
+    /// anything waiting on someone
+    fn color(&self) -> u32 {
+        match self {
+            EventKind::MemberAuthorized
+            | EventKind::NetworkCreated
+            | EventKind::BackupCompleted
+            | EventKind::ControllerRecovered
+            | EventKind::MemberOnline => 0x43a047,
+            EventKind::MemberDeauthorized
+            | EventKind::NetworkDeleted
+            | EventKind::IdentityChanged
+            | EventKind::ControllerUnreachable
+            | EventKind::MemberOffline => 0xe53935,
+            EventKind::MemberPending | EventKind::JoinRequested => 0xfb8c00,
+        }
+    }
+}
+
+/// A single occurrence of an event, produced by the poller. Events that aren't about a
+/// network, like backups, leave `nwid` empty.
+#[derive(Clone, Debug)]
+pub struct NotificationEvent {
+    pub kind: EventKind,
+    pub nwid: String,
+    pub network_name: String,
+    pub member_id: Option<String>,
+    /// Extra context, e.g. the backup file name
+    pub detail: Option<String>,
+}
+
+impl NotificationEvent {
+    pub fn network_label(&self) -> Option<String> {
+        match (self.nwid.is_empty(), self.network_name.is_empty()) {
+            (true, _) => None,
+            (false, true) => Some(self.nwid.clone()),
+            (false, false) => Some(format!("{} ({})", self.network_name, self.nwid)),
+        }
+    }
+
+    /// The member, with its local name where one is set
+    pub fn member_label(&self, config: &Config) -> Option<String> {
+        let mid = self.member_id.as_ref()?;
+        Some(match config.member_names.get(mid) {
+            Some(name) => format!("{} ({})", name, mid),
+            None => mid.clone(),
+        })
+    }
+
+    /// Human readable summary, using local member names where available
+    pub fn message(&self, config: &Config) -> String {
+        let mut message = match (self.member_label(config), self.network_label()) {
+            (Some(member), Some(network)) => format!("{}: {} on {}", self.kind.label(), member, network),
+            (None, Some(network)) => format!("{}: {}", self.kind.label(), network),
+            _ => self.kind.label().to_string(),
+        };
+        if let Some(detail) = &self.detail {
+            message.push_str(&format!(" ({})", detail));
+        }
+        message
+    }
+}
+
+/// Supported delivery mechanisms for a channel
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelKind {
+    /// Generic JSON POST
+    Webhook,
+    /// Slack incoming webhook
+    Slack,
+    /// Discord channel webhook
+    Discord,
+}
+
+impl ChannelKind {
+    pub const ALL: [ChannelKind; 3] = [ChannelKind::Webhook, ChannelKind::Slack, ChannelKind::Discord];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelKind::Webhook => "webhook",
+            ChannelKind::Slack => "slack",
+            ChannelKind::Discord => "discord",
+        }
+    }
+
+    pub fn parse(s: &str) -> Self {
+        ChannelKind::ALL.into_iter().find(|k| k.as_str() == s).unwrap_or(ChannelKind::Webhook)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ChannelKind::Webhook => "Webhook",
+            ChannelKind::Slack => "Slack",
+            ChannelKind::Discord => "Discord",
+        }
+    }
+}
+
+/// A configured notification destination
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NotificationChannel {
+    pub id: u64,
+    pub name: String,
+    pub kind: ChannelKind,
+    pub url: String,
+    /// Events this channel accepts; empty accepts all of them
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+}
+
+impl NotificationChannel {
+    pub fn accepts(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+
+    pub fn display_events(&self) -> String {
+        if self.events.is_empty() {
+            return "All events".to_string();
+        }
+        self.events
+            .iter()
+            .map(|e| e.label())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Routes matching events to a channel. Empty `events` or `networks` match everything.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NotificationRoute {
+    pub id: u64,
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+    #[serde(default)]
+    pub networks: Vec<String>,
+    pub channel_id: u64,
+}
+
+impl NotificationRoute {
+    pub fn matches(&self, event: &NotificationEvent) -> bool {
+        (self.events.is_empty() || self.events.contains(&event.kind))
+            && (self.networks.is_empty() || self.networks.iter().any(|n| n == &event.nwid))
+    }
+
+    pub fn display_events(&self) -> String {
+        if self.events.is_empty() {
+            return "All events".to_string();
+        }
+        self.events
+            .iter()
+            .map(|e| e.label())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn display_networks(&self) -> String {
+        if self.networks.is_empty() {
+            "All networks".to_string()
+        } else {
+            self.networks.join(", ")
+        }
+    }
+}
+
+/// Notification channels and routing rules, persisted in the config
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NotificationSettings {
+    #[serde(default)]
+    pub channels: Vec<NotificationChannel>,
+    #[serde(default)]
+    pub routes: Vec<NotificationRoute>,
+    #[serde(default = "default_next_id")]
+    pub next_id: u64,
+    #[serde(default)]
+    pub approval_links: ApprovalLinkSettings,
+    /// How long a monitored member may be offline before it is reported
+    #[serde(default = "default_offline_after_minutes")]
+    pub offline_after_minutes: u32,
+}
+
+fn default_next_id() -> u64 {
+    1
+}
+
+fn default_offline_after_minutes() -> u32 {
+    5
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            channels: Vec::new(),
+            routes: Vec::new(),
+            next_id: default_next_id(),
+            approval_links: ApprovalLinkSettings::default(),
+            offline_after_minutes: default_offline_after_minutes(),
+        }
+    }
+}
+
+impl NotificationSettings {
+    /// Allocate an ID shared by channels and routes
+    pub fn allocate_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn find_channel(&self, id: u64) -> Option<&NotificationChannel> {
+        self.channels.iter().find(|c| c.id == id)
+    }
+
+    /// Channels that should receive an event. With no routes configured, every
+    /// event goes to every channel that accepts it.
+    pub fn channels_for(&self, event: &NotificationEvent) -> Vec<&NotificationChannel> {
+        if self.routes.is_empty() {
+            return self.channels.iter().filter(|c| c.accepts(event.kind)).collect();
+        }
+        let mut ids: Vec<u64> = self
+            .routes
+            .iter()
+            .filter(|r| r.matches(event))
+            .map(|r| r.channel_id)
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.into_iter()
+            .filter_map(|id| self.find_channel(id))
+            .filter(|c| c.accepts(event.kind))
+            .collect()
+    }
+}
+
+/// Short human duration for event details, e.g. "4m 10s"
+pub fn format_duration(secs: u64) -> String {
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m {}s", s / 60, s % 60),
+        s => format!("{}h {}m", s / 3600, (s % 3600) / 60),
+    }
+}
+
+/// Compare two poller snapshots and return the events that happened in between.
+pub fn diff_events(old: &ZtState, new: &ZtState) -> Vec<NotificationEvent> {
+    let mut events = Vec::new();
+
+    // Skip the first poll so startup doesn't replay every existing member, and
+    // failed polls so an unreachable controller doesn't look like mass deletion
+    if !old.polled() || old.error.is_some() || new.error.is_some() {
+        return events;
+    }
+
+    let old_names: HashMap<&str, &str> = old
+        .controller_networks
+        .iter()
+        .map(|n| (n.display_id(), n.display_name()))
+        .collect();
+    let new_names: HashMap<&str, &str> = new
+        .controller_networks
+        .iter()
+        .map(|n| (n.display_id(), n.display_name()))
+        .collect();
+
+    for (nwid, name) in &new_names {
+        if !old_names.contains_key(nwid) && !old.controller_members.contains_key(*nwid) {
+            events.push(NotificationEvent {
+                kind: EventKind::NetworkCreated,
+                nwid: nwid.to_string(),
+                network_name: name.to_string(),
+                member_id: None,
+                detail: None,
+            });
+        }
+    }
+    for (nwid, name) in &old_names {
+        // A network whose detail fetch failed still has a member list entry
+        if !new_names.contains_key(nwid) && !new.controller_members.contains_key(*nwid) {
+            events.push(NotificationEvent {
+                kind: EventKind::NetworkDeleted,
+                nwid: nwid.to_string(),
+                network_name: name.to_string(),
+                member_id: None,
+                detail: None,
+            });
+        }
+    }
+
+    for (nwid, members) in &new.controller_members {
+        let Some(name) = new_names.get(nwid.as_str()) else {
+            continue;
+        };
+        // A member list that failed to load either time says nothing about who joined
+        if !old.members_loaded(nwid) || !new.members_loaded(nwid) {
+            continue;
+        }
+        let previous: HashMap<&str, &ControllerMember> = old
+            .controller_members
+            .get(nwid)
+            .map(|ms| ms.iter().map(|m| (m.display_id(), m)).collect())
+            .unwrap_or_default();
+
+        for member in members {
+            let kind = match previous.get(member.display_id()) {
+                // Back after failing to load last time, so not new
+                None if !old.member_loaded(nwid, member.display_id()) => continue,
+                None if !member.is_authorized() => EventKind::MemberPending,
+                None => continue,
+                Some(prev) if prev.is_authorized() == member.is_authorized() => continue,
+                Some(_) if member.is_authorized() => EventKind::MemberAuthorized,
+                Some(_) => EventKind::MemberDeauthorized,
+            };
+            events.push(NotificationEvent {
+                kind,
+                nwid: nwid.clone(),
+                network_name: name.to_string(),
+                member_id: Some(member.display_id().to_string()),
+                detail: None,
+            });
+        }
+    }
+
+    events
+}
+
+/// What a channel needs to describe an event, resolved against the config up front
+struct Formatted<'a> {
+    event: &'a NotificationEvent,
+    message: String,
+    member: Option<String>,
+    approve_url: Option<String>,
+}
+
+impl Formatted<'_> {
+    /// JSON body for the channel's kind of webhook
+    fn payload(&self, kind: ChannelKind) -> serde_json::Value {
+        let event = self.event;
+        match kind {
+            ChannelKind::Webhook => serde_json::json!({
+                "event": event.kind.as_str(),
+                "network_id": event.nwid,
+                "network_name": event.network_name,
+                "member_id": event.member_id,
+                "message": self.message,
+                "approve_url": self.approve_url,
+            }),
+            ChannelKind::Slack => {
+                let mut lines = vec![format!("*{}*", event.kind.label())];
+                if let Some(network) = event.network_label() {
+                    lines.push(format!("Network: `{}`", network));
+                }
+                if let Some(member) = &self.member {
+                    lines.push(format!("Member: `{}`", member));
+                }
+                if let Some(detail) = &event.detail {
+                    lines.push(detail.clone());
+                }
+                if let Some(url) = &self.approve_url {
+                    lines.push(format!("<{}|Approve>", url));
+                }
+                serde_json::json!({
+                    "text": self.message,
+                    "blocks": [{
+                        "type": "section",
+                        "text": { "type": "mrkdwn", "text": lines.join("\n") },
+                    }],
+                })
+            }
+            ChannelKind::Discord => {
+                let mut fields = Vec::new();
+                if let Some(network) = event.network_label() {
+                    fields.push(serde_json::json!({ "name": "Network", "value": network, "inline": true }));
+                }
+                if let Some(member) = &self.member {
+                    fields.push(serde_json::json!({ "name": "Member", "value": member, "inline": true }));
+                }
+                let mut embed = serde_json::json!({
+                    "title": event.kind.label(),
+                    "color": event.kind.color(),
+                    "fields": fields,
+                    "timestamp": chrono::Utc::now().to_rfc3339(),
+                });
+                if let Some(detail) = &event.detail {
+                    embed["description"] = detail.clone().into();
+                }
+                if let Some(url) = &self.approve_url {
+                    embed["url"] = url.clone().into();
+                    embed["description"] = format!("[Approve]({})", url).into();
+                }
+                serde_json::json!({ "username": "TierDrop", "embeds": [embed] })
+            }
+        }
+    }
+}
+
+async fn send(http: &reqwest::Client, channel: &NotificationChannel, formatted: &Formatted<'_>) -> Result<(), String> {
+    let resp = http
+        .post(&channel.url)
+        .json(&formatted.payload(channel.kind))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("HTTP {}", resp.status()));
+    }
+    Ok(())
+}
+
+/// Deliver events from the poller to the channels selected by the routing rules.
+pub async fn run_dispatcher(
+    config: Arc<RwLock<Option<Config>>>,
+    mut rx: mpsc::UnboundedReceiver<NotificationEvent>,
+) {
+    let http = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .unwrap_or_default();
+
+    while let Some(event) = rx.recv().await {
+        // Resolve targets up front so the config lock isn't held across sends
+        let (formatted, channels) = {
+            let cfg = config.read().await;
+            let Some(c) = cfg.as_ref() else { continue };
+            // Join requests are announced by the join page with more context
+            if event.kind == EventKind::MemberPending
+                && c.join_requests.iter().any(|r| {
+                    r.nwid == event.nwid && Some(&r.member_id) == event.member_id.as_ref()
+                })
+            {
+                continue;
+            }
+            let channels: Vec<NotificationChannel> = c
+                .notifications
+                .channels_for(&event)
+                .into_iter()
+                .cloned()
+                .collect();
+            let approve_url = match (&event.kind, &event.member_id) {
+                (EventKind::MemberPending | EventKind::JoinRequested, Some(mid)) => approvals::approval_url(
+                    &c.notifications.approval_links,
+                    &c.approval_secret,
+                    &event.nwid,
+                    mid,
+                ),
+                _ => None,
+            };
+            let mut message = event.message(c);
+            if let Some(url) = &approve_url {
+                message.push_str(&format!("\nApprove: {}", url));
+            }
+            let member = event.member_label(c);
+            (Formatted { event: &event, message, member, approve_url }, channels)
+        };
+
+        for channel in &channels {
+            debug!("Sending {} notification to '{}'", event.kind.as_str(), channel.name);
+            if let Err(e) = send(&http, channel, &formatted).await {
+                warn!("Failed to send notification to '{}': {}", channel.name, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,413 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::http::header::COOKIE;
+use axum::http::HeaderMap;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tower_sessions::cookie::time::Duration;
+use tower_sessions::cookie::{Cookie, SameSite};
+
+use crate::state::{Config, User};
+
+/// OpenID Connect single sign-on, e.g. Authentik or Keycloak
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OidcSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Issuer URL; `/.well-known/openid-configuration` is appended when missing
+    #[serde(default)]
+    pub discovery_url: String,
+    #[serde(default)]
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: String,
+    /// Externally reachable TierDrop URL; the callback is `{base_url}/login/oidc/callback`
+    #[serde(default)]
+    pub base_url: String,
+    #[serde(default = "default_button_label")]
+    pub button_label: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: String,
+    #[serde(default = "default_username_claim")]
+    pub username_claim: String,
+    #[serde(default = "default_groups_claim")]
+    pub groups_claim: String,
+    /// Members of this group are TierDrop admins. Empty leaves the admin flag to the Users tab.
+    #[serde(default)]
+    pub admin_group: String,
+    /// Only members of this group (or the admin group) may sign in. Empty allows anyone.
+    #[serde(default)]
+    pub allowed_group: String,
+    /// Create a TierDrop user on first sign-in
+    #[serde(default = "default_true")]
+    pub auto_create: bool,
+    /// Attach first sign-ins to an existing local user with the same username
+    #[serde(default)]
+    pub link_by_username: bool,
+}
+
+fn default_button_label() -> String {
+    "Sign in with SSO".to_string()
+}
+
+fn default_scopes() -> String {
+    "openid profile email".to_string()
+}
+
+fn default_username_claim() -> String {
+    "preferred_username".to_string()
+}
+
+fn default_groups_claim() -> String {
+    "groups".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for OidcSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            discovery_url: String::new(),
+            client_id: String::new(),
+            client_secret: String::new(),
+            base_url: String::new(),
+            button_label: default_button_label(),
+            scopes: default_scopes(),
+            username_claim: default_username_claim(),
+            groups_claim: default_groups_claim(),
+            admin_group: String::new(),
+            allowed_group: String::new(),
+            auto_create: true,
+            link_by_username: false,
+        }
+    }
+}
+
+impl OidcSettings {
+    /// Enabled and filled in enough to attempt a login
+    pub fn is_ready(&self) -> bool {
+        self.enabled && !self.discovery_url.is_empty() && !self.client_id.is_empty() && !self.base_url.is_empty()
+    }
+
+    pub fn redirect_uri(&self) -> String {
+        format!("{}/login/oidc/callback", self.base_url.trim_end_matches('/'))
+    }
+
+    fn discovery_document_url(&self) -> String {
+        let url = self.discovery_url.trim().trim_end_matches('/');
+        if url.ends_with("/.well-known/openid-configuration") {
+            url.to_string()
+        } else {
+            format!("{}/.well-known/openid-configuration", url)
+        }
+    }
+}
+
+/// The parts of the provider's discovery document we use
+#[derive(Deserialize)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    #[serde(default)]
+    pub userinfo_endpoint: Option<String>,
+}
+
+/// Per-login values kept between redirect and callback
+pub struct PendingLogin {
+    pub state: String,
+    pub nonce: String,
+    pub verifier: String,
+}
+
+/// Cookie carrying a `PendingLogin` to the callback. The session cookie can't: it is
+/// SameSite=Strict by default, and browsers leave it off the provider's redirect back.
+const PENDING_COOKIE: &str = "tierdrop_oidc";
+
+/// How long the user has at the provider before the login has to start over
+const PENDING_MINUTES: i64 = 10;
+
+impl PendingLogin {
+    /// Set-Cookie value holding this login, sent only to the callback
+    pub fn cookie(&self, settings: &OidcSettings) -> String {
+        let value = format!("{}.{}.{}", self.state, self.nonce, self.verifier);
+        let mut cookie = pending_cookie(settings, value);
+        cookie.set_max_age(Duration::minutes(PENDING_MINUTES));
+        cookie.to_string()
+    }
+
+    /// Set-Cookie value removing the login cookie
+    pub fn clear_cookie(settings: &OidcSettings) -> String {
+        let mut cookie = pending_cookie(settings, String::new());
+        cookie.make_removal();
+        cookie.to_string()
+    }
+
+    /// The login the request's cookie carries
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let value = headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(Cookie::split_parse)
+            .filter_map(Result::ok)
+            .find(|c| c.name() == PENDING_COOKIE)?
+            .value()
+            .to_string();
+        let mut parts = value.splitn(3, '.');
+        let (state, nonce, verifier) = (parts.next()?, parts.next()?, parts.next()?);
+        if state.is_empty() || nonce.is_empty() || verifier.is_empty() {
+            return None;
+        }
+        Some(Self { state: state.to_string(), nonce: nonce.to_string(), verifier: verifier.to_string() })
+    }
+}
+
+/// The login cookie, Lax so it survives the cross-site redirect back and scoped to the callback
+fn pending_cookie(settings: &OidcSettings, value: String) -> Cookie<'static> {
+    let redirect_uri = reqwest::Url::parse(&settings.redirect_uri()).ok();
+    let path = redirect_uri
+        .as_ref()
+        .map(|u| u.path().to_string())
+        .unwrap_or_else(|| crate::base_path::url("/login/oidc/callback"));
+    Cookie::build((PENDING_COOKIE, value))
+        .path(path)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(redirect_uri.is_some_and(|u| u.scheme() == "https"))
+        .build()
+}
+
+/// Who the provider says signed in
+pub struct OidcIdentity {
+    /// `issuer|sub`, stable across username changes
+    pub subject: String,
+    pub username: String,
+    pub groups: Vec<String>,
+}
+
+fn http() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .unwrap_or_default()
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub async fn discover(settings: &OidcSettings) -> Result<Discovery, String> {
+    http()
+        .get(settings.discovery_document_url())
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Discovery request failed: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid discovery document: {}", e))
+}
+
+/// Build the provider authorization URL for a new login
+pub async fn begin_login(settings: &OidcSettings) -> Result<(String, PendingLogin), String> {
+    let discovery = discover(settings).await?;
+    let pending = PendingLogin {
+        state: random_token(),
+        nonce: random_token(),
+        verifier: random_token(),
+    };
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(pending.verifier.as_bytes()));
+    let url = reqwest::Url::parse_with_params(
+        &discovery.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", settings.client_id.as_str()),
+            ("redirect_uri", settings.redirect_uri().as_str()),
+            ("scope", settings.scopes.as_str()),
+            ("state", pending.state.as_str()),
+            ("nonce", pending.nonce.as_str()),
+            ("code_challenge", challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err(|e| format!("Invalid authorization endpoint: {}", e))?;
+    Ok((url.to_string(), pending))
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+    #[serde(default)]
+    access_token: Option<String>,
+}
+
+/// Exchange the authorization code and validate the ID token.
+///
+/// The ID token comes straight from the token endpoint over TLS, so its signature is not
+/// checked (OIDC Core 3.1.3.7); issuer, audience, expiry and nonce are.
+pub async fn complete_login(
+    settings: &OidcSettings,
+    pending: &PendingLogin,
+    code: &str,
+) -> Result<OidcIdentity, String> {
+    let discovery = discover(settings).await?;
+    let client = http();
+
+    let tokens: TokenResponse = client
+        .post(&discovery.token_endpoint)
+        .basic_auth(&settings.client_id, Some(&settings.client_secret))
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", settings.redirect_uri().as_str()),
+            ("code_verifier", pending.verifier.as_str()),
+        ])
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Token request failed: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid token response: {}", e))?;
+
+    let mut claims = decode_id_token(&tokens.id_token)?;
+    check_claims(&claims, &discovery.issuer, &settings.client_id, &pending.nonce)?;
+
+    // Group and profile claims are often only in userinfo
+    if let (Some(endpoint), Some(access_token)) = (&discovery.userinfo_endpoint, &tokens.access_token) {
+        let userinfo = client
+            .get(endpoint)
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        if let Ok(response) = userinfo {
+            if let Ok(serde_json::Value::Object(extra)) = response.json::<serde_json::Value>().await {
+                // userinfo must describe the same subject
+                if extra.get("sub") == claims.get("sub") {
+                    for (k, v) in extra {
+                        claims.entry(k).or_insert(v);
+                    }
+                }
+            }
+        }
+    }
+
+    let sub = claims.get("sub").and_then(|v| v.as_str()).ok_or("ID token has no subject")?;
+    let username = claims
+        .get(&settings.username_claim)
+        .and_then(|v| v.as_str())
+        .or_else(|| claims.get("email").and_then(|v| v.as_str()))
+        .unwrap_or(sub)
+        .trim()
+        .to_string();
+    let groups = match claims.get(&settings.groups_claim) {
+        Some(serde_json::Value::Array(items)) => {
+            items.iter().filter_map(|v| v.as_str().map(str::to_string)).collect()
+        }
+        Some(serde_json::Value::String(s)) => vec![s.clone()],
+        _ => Vec::new(),
+    };
+
+    Ok(OidcIdentity {
+        subject: format!("{}|{}", discovery.issuer, sub),
+        username,
+        groups,
+    })
+}
+
+fn decode_id_token(token: &str) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+    let payload = token.split('.').nth(1).ok_or("Malformed ID token")?;
+    let bytes = URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .map_err(|_| "Malformed ID token")?;
+    serde_json::from_slice(&bytes).map_err(|_| "Malformed ID token".to_string())
+}
+
+fn check_claims(
+    claims: &serde_json::Map<String, serde_json::Value>,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+) -> Result<(), String> {
+    if claims.get("iss").and_then(|v| v.as_str()) != Some(issuer) {
+        return Err("ID token issuer does not match".to_string());
+    }
+    let audience_ok = match claims.get("aud") {
+        Some(serde_json::Value::String(aud)) => aud == client_id,
+        Some(serde_json::Value::Array(auds)) => auds.iter().any(|a| a.as_str() == Some(client_id)),
+        _ => false,
+    };
+    if !audience_ok {
+        return Err("ID token was issued for a different client".to_string());
+    }
+    let exp = claims.get("exp").and_then(|v| v.as_i64()).unwrap_or(0);
+    if exp < chrono::Utc::now().timestamp() {
+        return Err("ID token has expired".to_string());
+    }
+    if claims.get("nonce").and_then(|v| v.as_str()) != Some(nonce) {
+        return Err("ID token nonce does not match".to_string());
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Find or create the TierDrop user for an SSO identity, syncing the admin flag from groups.
+    /// Returns the user ID. The caller saves the config.
+    pub fn resolve_oidc_user(&mut self, identity: &OidcIdentity) -> Result<u64, String> {
+        let settings = &self.oidc;
+        let in_group = |group: &str| !group.is_empty() && identity.groups.iter().any(|g| g == group);
+        let is_admin = in_group(&settings.admin_group);
+        if !settings.allowed_group.is_empty() && !is_admin && !in_group(&settings.allowed_group) {
+            return Err("Your account is not allowed to sign in to TierDrop.".to_string());
+        }
+        let sync_admin = !settings.admin_group.is_empty();
+        let auto_create = settings.auto_create;
+        let link_by_username = settings.link_by_username;
+
+        let existing = self
+            .users
+            .iter()
+            .position(|u| u.oidc_subject.as_deref() == Some(identity.subject.as_str()))
+            .or_else(|| {
+                self.users
+                    .iter()
+                    .position(|u| u.username == identity.username && u.oidc_subject.is_none())
+                    .filter(|_| link_by_username)
+            });
+        if let Some(pos) = existing {
+            let user = &mut self.users[pos];
+            if user.disabled {
+                return Err("This account is disabled.".to_string());
+            }
+            user.oidc_subject = Some(identity.subject.clone());
+            if sync_admin {
+                user.is_admin = is_admin;
+            }
+            return Ok(user.id);
+        }
+
+        if !auto_create {
+            return Err("No TierDrop user is linked to this account. Ask an administrator.".to_string());
+        }
+        if identity.username.is_empty() || self.find_user_by_username(&identity.username).is_some() {
+            return Err(format!(
+                "A TierDrop user named '{}' already exists. Ask an administrator to link it.",
+                identity.username
+            ));
+        }
+        let mut user = User::new(self.next_user_id, identity.username.clone(), String::new(), is_admin);
+        user.oidc_subject = Some(identity.subject.clone());
+        self.next_user_id += 1;
+        let id = user.id;
+        self.users.push(user);
+        Ok(id)
+    }
+}
@@ -0,0 +1,328 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::header::HOST;
+use axum::http::request::Parts;
+use axum::http::HeaderMap;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use ring::signature::{self, UnparsedPublicKey, VerificationAlgorithm};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::state::User;
+use crate::trusted_proxies;
+
+/// COSE algorithm IDs we can verify: ES256, EdDSA, RS256
+pub const SUPPORTED_ALGORITHMS: [i64; 3] = [-7, -8, -257];
+
+/// How long a browser has to answer a challenge, in milliseconds
+pub const TIMEOUT_MS: u64 = 120_000;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+
+/// A WebAuthn credential registered to a user
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Passkey {
+    /// Credential ID, base64url
+    pub id: String,
+    pub name: String,
+    /// SubjectPublicKeyInfo DER, base64url
+    pub public_key: String,
+    /// COSE algorithm ID
+    pub algorithm: i64,
+    /// Relying party ID (host name) the credential is scoped to
+    pub rp_id: String,
+    #[serde(default)]
+    pub sign_count: u32,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl Passkey {
+    pub fn display_created_at(&self) -> String {
+        crate::preferences::format_time(self.created_at)
+    }
+
+    pub fn display_last_used(&self) -> String {
+        self.last_used
+            .map(crate::preferences::format_time)
+            .unwrap_or_else(|| "Never".to_string())
+    }
+}
+
+/// A challenge handed to the browser, kept in the session until it answers
+#[derive(Serialize, Deserialize)]
+pub struct PendingChallenge {
+    pub challenge: String,
+    pub rp_id: String,
+}
+
+impl PendingChallenge {
+    pub fn new(host: &str) -> Self {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        Self {
+            challenge: URL_SAFE_NO_PAD.encode(bytes),
+            rp_id: rp_id_for(host),
+        }
+    }
+}
+
+/// Browser answer to `navigator.credentials.create()`, binary fields base64url
+#[derive(Deserialize)]
+pub struct RegistrationResponse {
+    pub id: String,
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    /// From `getPublicKey()`: SubjectPublicKeyInfo DER
+    pub public_key: String,
+    pub public_key_algorithm: i64,
+    #[serde(default)]
+    pub name: String,
+}
+
+/// Browser answer to `navigator.credentials.get()`, binary fields base64url
+#[derive(Deserialize)]
+pub struct AssertionResponse {
+    pub id: String,
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+}
+
+/// Host the browser sees. A reverse proxy's X-Forwarded-Host is preferred when the connection
+/// comes from a trusted proxy; anyone else could use it to pick the relying party.
+fn request_host(headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<String> {
+    let forwarded = headers.get("x-forwarded-host").filter(|_| trusted_proxies::peer_is_trusted(peer));
+    forwarded
+        .or_else(|| headers.get(HOST))
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// The host the browser sees, as an extractor for the passkey handlers
+pub struct RequestHost(pub Option<String>);
+
+impl<S: Send + Sync> FromRequestParts<S> for RequestHost {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|c| c.0);
+        Ok(Self(request_host(&parts.headers, peer)))
+    }
+}
+
+/// Host name without port, used as the relying party ID
+pub fn rp_id_for(host: &str) -> String {
+    if let Some(rest) = host.strip_prefix('[') {
+        // [v6]:port
+        return rest.split(']').next().unwrap_or(rest).to_string();
+    }
+    host.rsplit_once(':')
+        .filter(|(_, port)| port.chars().all(|c| c.is_ascii_digit()))
+        .map(|(h, _)| h)
+        .unwrap_or(host)
+        .to_lowercase()
+}
+
+/// `publicKey` options for `navigator.credentials.create()`, binary fields base64url
+pub fn registration_options(pending: &PendingChallenge, user: &User) -> serde_json::Value {
+    serde_json::json!({
+        "challenge": pending.challenge,
+        "rp": { "name": "TierDrop", "id": pending.rp_id },
+        "user": {
+            "id": URL_SAFE_NO_PAD.encode(user.id.to_be_bytes()),
+            "name": user.username,
+            "displayName": user.username,
+        },
+        "pubKeyCredParams": SUPPORTED_ALGORITHMS
+            .iter()
+            .map(|alg| serde_json::json!({ "type": "public-key", "alg": alg }))
+            .collect::<Vec<_>>(),
+        "excludeCredentials": user.passkeys
+            .iter()
+            .map(|p| serde_json::json!({ "type": "public-key", "id": p.id }))
+            .collect::<Vec<_>>(),
+        "authenticatorSelection": { "residentKey": "preferred", "userVerification": "preferred" },
+        "attestation": "none",
+        "timeout": TIMEOUT_MS,
+    })
+}
+
+/// `publicKey` options for `navigator.credentials.get()`. An empty list lets the browser
+/// offer any discoverable passkey for this site.
+pub fn assertion_options(pending: &PendingChallenge, allow: &[Passkey]) -> serde_json::Value {
+    serde_json::json!({
+        "challenge": pending.challenge,
+        "rpId": pending.rp_id,
+        "allowCredentials": allow
+            .iter()
+            .map(|p| serde_json::json!({ "type": "public-key", "id": p.id }))
+            .collect::<Vec<_>>(),
+        "userVerification": if allow.is_empty() { "required" } else { "preferred" },
+        "timeout": TIMEOUT_MS,
+    })
+}
+
+fn decode(field: &str, value: &str) -> Result<Vec<u8>, String> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| format!("Malformed {}", field))
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+/// Check the browser-signed client data is for this ceremony, challenge and host
+fn check_client_data(raw: &[u8], kind: &str, pending: &PendingChallenge, host: &str) -> Result<(), String> {
+    let data: ClientData = serde_json::from_slice(raw).map_err(|_| "Malformed client data".to_string())?;
+    if data.kind != kind {
+        return Err("Unexpected WebAuthn operation".to_string());
+    }
+    if data.challenge.trim_end_matches('=') != pending.challenge {
+        return Err("Passkey challenge does not match. Try again.".to_string());
+    }
+    let origin_host = data
+        .origin
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or_default();
+    if !origin_host.eq_ignore_ascii_case(host) {
+        return Err("Passkey was used from a different site".to_string());
+    }
+    Ok(())
+}
+
+struct AuthenticatorData {
+    flags: u8,
+    sign_count: u32,
+}
+
+fn check_authenticator_data(raw: &[u8], rp_id: &str) -> Result<AuthenticatorData, String> {
+    if raw.len() < 37 {
+        return Err("Malformed authenticator data".to_string());
+    }
+    if raw[..32] != Sha256::digest(rp_id.as_bytes())[..] {
+        return Err("Passkey belongs to a different site".to_string());
+    }
+    let flags = raw[32];
+    if flags & FLAG_USER_PRESENT == 0 {
+        return Err("Passkey did not confirm user presence".to_string());
+    }
+    Ok(AuthenticatorData {
+        flags,
+        sign_count: u32::from_be_bytes([raw[33], raw[34], raw[35], raw[36]]),
+    })
+}
+
+/// Validate a registration and build the passkey to store
+pub fn register(response: &RegistrationResponse, pending: &PendingChallenge, host: &str) -> Result<Passkey, String> {
+    check_client_data(&decode("client data", &response.client_data_json)?, "webauthn.create", pending, host)?;
+    let auth_data = check_authenticator_data(&decode("authenticator data", &response.authenticator_data)?, &pending.rp_id)?;
+
+    let public_key = decode("public key", &response.public_key)?;
+    verification_algorithm(response.public_key_algorithm)?;
+    spki_key(&public_key).ok_or("Unsupported public key")?;
+    decode("credential ID", &response.id)?;
+
+    let name = response.name.trim();
+    Ok(Passkey {
+        id: response.id.trim_end_matches('=').to_string(),
+        name: if name.is_empty() { "Passkey".to_string() } else { name.chars().take(64).collect() },
+        public_key: URL_SAFE_NO_PAD.encode(&public_key),
+        algorithm: response.public_key_algorithm,
+        rp_id: pending.rp_id.clone(),
+        sign_count: auth_data.sign_count,
+        created_at: Utc::now(),
+        last_used: None,
+    })
+}
+
+/// Check a login assertion against a stored passkey. Returns the new signature counter.
+pub fn verify_assertion(
+    passkey: &Passkey,
+    response: &AssertionResponse,
+    pending: &PendingChallenge,
+    host: &str,
+    require_verification: bool,
+) -> Result<u32, String> {
+    let client_data = decode("client data", &response.client_data_json)?;
+    check_client_data(&client_data, "webauthn.get", pending, host)?;
+    if passkey.rp_id != pending.rp_id {
+        return Err("Passkey belongs to a different site".to_string());
+    }
+    let raw_auth_data = decode("authenticator data", &response.authenticator_data)?;
+    let auth_data = check_authenticator_data(&raw_auth_data, &passkey.rp_id)?;
+    if require_verification && auth_data.flags & FLAG_USER_VERIFIED == 0 {
+        return Err("Passkey sign-in needs a PIN or biometric check".to_string());
+    }
+
+    let public_key = decode("public key", &passkey.public_key)?;
+    let key = spki_key(&public_key).ok_or("Unsupported public key")?;
+    let mut signed = raw_auth_data;
+    signed.extend_from_slice(&Sha256::digest(&client_data));
+    UnparsedPublicKey::new(verification_algorithm(passkey.algorithm)?, key)
+        .verify(&signed, &decode("signature", &response.signature)?)
+        .map_err(|_| "Passkey signature is not valid".to_string())?;
+
+    // A counter that stops increasing suggests a cloned authenticator
+    if (auth_data.sign_count != 0 || passkey.sign_count != 0) && auth_data.sign_count <= passkey.sign_count {
+        return Err("Passkey counter went backwards; it may have been cloned".to_string());
+    }
+    Ok(auth_data.sign_count)
+}
+
+fn verification_algorithm(cose: i64) -> Result<&'static dyn VerificationAlgorithm, String> {
+    match cose {
+        -7 => Ok(&signature::ECDSA_P256_SHA256_ASN1),
+        -8 => Ok(&signature::ED25519),
+        -257 => Ok(&signature::RSA_PKCS1_2048_8192_SHA256),
+        _ => Err("Unsupported passkey algorithm".to_string()),
+    }
+}
+
+/// Read one DER element: (tag, contents, rest)
+pub fn der_read(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n].iter().fold(0usize, |acc, &b| (acc << 8) | b as usize);
+        (len, &rest[n..])
+    };
+    (rest.len() >= len).then(|| (tag, &rest[..len], &rest[len..]))
+}
+
+/// The key bytes ring expects, taken from a SubjectPublicKeyInfo: the EC point, Ed25519 key,
+/// or PKCS#1 RSAPublicKey
+fn spki_key(spki: &[u8]) -> Option<&[u8]> {
+    let (tag, sequence, _) = der_read(spki)?;
+    if tag != 0x30 {
+        return None;
+    }
+    let (_, _algorithm, rest) = der_read(sequence)?;
+    let (tag, bits, _) = der_read(rest)?;
+    if tag != 0x03 {
+        return None;
+    }
+    let (&unused_bits, key) = bits.split_first()?;
+    (unused_bits == 0).then_some(key)
+}
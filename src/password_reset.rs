@@ -0,0 +1,72 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::state::Config;
+
+/// How long an emailed reset link works
+pub const RESET_VALID_MINUTES: i64 = 60;
+
+/// Minimum gap between reset emails to the same user
+const RESEND_AFTER_MINUTES: i64 = 2;
+
+/// An outstanding "forgot password" request. Only a hash of the emailed token is kept.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PasswordReset {
+    pub user_id: u64,
+    pub token_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+impl Config {
+    /// Start a reset for a user, replacing any earlier one, and return the token to email.
+    /// None if a link was sent moments ago.
+    pub fn start_password_reset(&mut self, user_id: u64) -> Option<String> {
+        let now = Utc::now();
+        self.password_resets.retain(|r| r.expires_at > now);
+        let recent = self
+            .password_resets
+            .iter()
+            .any(|r| r.user_id == user_id && now - r.created_at < Duration::minutes(RESEND_AFTER_MINUTES));
+        if recent {
+            return None;
+        }
+        self.password_resets.retain(|r| r.user_id != user_id);
+
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+        self.password_resets.push(PasswordReset {
+            user_id,
+            token_hash: hash_token(&token),
+            created_at: now,
+            expires_at: now + Duration::minutes(RESET_VALID_MINUTES),
+        });
+        Some(token)
+    }
+
+    /// The user a live reset token belongs to
+    pub fn password_reset_user(&self, token: &str) -> Option<u64> {
+        let hash = hash_token(token);
+        let now = Utc::now();
+        self.password_resets
+            .iter()
+            .find(|r| r.token_hash == hash && r.expires_at > now)
+            .map(|r| r.user_id)
+    }
+
+    /// Use up a reset token, returning its user
+    pub fn finish_password_reset(&mut self, token: &str) -> Option<u64> {
+        let user_id = self.password_reset_user(token)?;
+        self.password_resets.retain(|r| r.user_id != user_id);
+        Some(user_id)
+    }
+}
@@ -1,11 +1,23 @@
-use crate::state::User;
+use crate::state::{NetworkPermissions, User, ALL_NETWORKS};
+
+/// A user's permissions on a network: their own plus everything their role and groups grant,
+/// each including the `*` entry for all networks
+pub fn effective_permissions(user: &User, nwid: &str) -> NetworkPermissions {
+    [nwid, ALL_NETWORKS].iter().fold(user.role.permissions(), |acc, key| {
+        let acc = acc.union(&user.get_network_permissions(key));
+        match user.group_permissions.get(*key) {
+            Some(inherited) => acc.union(inherited),
+            None => acc,
+        }
+    })
+}
 
 /// Check if user can read a network (view details and members)
 pub fn can_read(user: &User, nwid: &str) -> bool {
     if user.is_admin {
         return true;
     }
-    user.get_network_permissions(nwid).read
+    effective_permissions(user, nwid).read
 }
 
 /// Check if user can authorize/deauthorize members
@@ -13,7 +25,7 @@ pub fn can_authorize(user: &User, nwid: &str) -> bool {
     if user.is_admin {
         return true;
     }
-    user.get_network_permissions(nwid).authorize
+    effective_permissions(user, nwid).authorize
 }
 
 /// Check if user can modify network settings (IP pools, routes, DNS, etc.)
@@ -21,7 +33,7 @@ pub fn can_modify(user: &User, nwid: &str) -> bool {
     if user.is_admin {
         return true;
     }
-    user.get_network_permissions(nwid).modify
+    effective_permissions(user, nwid).modify
 }
 
 /// Check if user can delete the network itself
@@ -29,7 +41,7 @@ pub fn can_delete(user: &User, nwid: &str) -> bool {
     if user.is_admin {
         return true;
     }
-    user.get_network_permissions(nwid).delete
+    effective_permissions(user, nwid).delete
 }
 
 /// Check if user is an admin (can manage users, create networks, etc.)
@@ -37,10 +49,15 @@ pub fn is_admin(user: &User) -> bool {
     user.is_admin
 }
 
+/// Check if user can create networks
+pub fn can_create_network(user: &User) -> bool {
+    user.is_admin || user.can_create_networks
+}
+
 /// Check if user has any permission on a network
 pub fn _has_any_permission(user: &User, nwid: &str) -> bool {
     if user.is_admin {
         return true;
     }
-    user.get_network_permissions(nwid).has_any()
+    effective_permissions(user, nwid).has_any()
 }
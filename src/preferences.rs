@@ -0,0 +1,265 @@
+//! Per-user UI preferences, stored with the user so they follow them across browsers.
+//!
+//! The auth middleware makes the signed-in user's preferences current for the rest of the
+//! request, so templates and display helpers can read them without having them passed in:
+//! `{{ crate::preferences::theme() }}`. Pages outside a session use the defaults.
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+tokio::task_local! {
+    static CURRENT: Preferences;
+}
+
+/// Choices offered for rows per page
+pub const ROWS_PER_PAGE: [usize; 4] = [25, 50, 100, 250];
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "page")]
+pub enum Landing {
+    #[default]
+    Dashboard,
+    Network { nwid: String },
+    Settings,
+}
+
+impl Landing {
+    /// The path to open after signing in
+    pub fn path(&self) -> String {
+        match self {
+            Landing::Dashboard => "/".to_string(),
+            Landing::Network { nwid } => format!("/controller/{}", nwid),
+            Landing::Settings => "/settings".to_string(),
+        }
+    }
+
+    /// Form value: `dashboard`, `settings` or a network ID
+    pub fn value(&self) -> &str {
+        match self {
+            Landing::Dashboard => "dashboard",
+            Landing::Network { nwid } => nwid,
+            Landing::Settings => "settings",
+        }
+    }
+
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "settings" => Landing::Settings,
+            nwid if nwid.len() == 16 && nwid.bytes().all(|b| b.is_ascii_hexdigit()) => Landing::Network {
+                nwid: nwid.to_ascii_lowercase(),
+            },
+            _ => Landing::Dashboard,
+        }
+    }
+}
+
+/// How dates are written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DateFormat {
+    /// 2025-03-14 15:09
+    #[default]
+    Iso,
+    /// 03/14/2025 3:09 PM
+    Us,
+    /// 14.03.2025 15:09
+    European,
+}
+
+impl DateFormat {
+    pub const ALL: [DateFormat; 3] = [DateFormat::Iso, DateFormat::Us, DateFormat::European];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DateFormat::Iso => "iso",
+            DateFormat::Us => "us",
+            DateFormat::European => "european",
+        }
+    }
+
+    pub fn parse(s: &str) -> Self {
+        DateFormat::ALL.into_iter().find(|f| f.as_str() == s).unwrap_or_default()
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DateFormat::Iso => "2025-03-14 15:09",
+            DateFormat::Us => "03/14/2025 3:09 PM",
+            DateFormat::European => "14.03.2025 15:09",
+        }
+    }
+
+    fn pattern(&self, seconds: bool) -> &'static str {
+        match (self, seconds) {
+            (DateFormat::Iso, false) => "%Y-%m-%d %H:%M",
+            (DateFormat::Iso, true) => "%Y-%m-%d %H:%M:%S",
+            (DateFormat::Us, false) => "%m/%d/%Y %-I:%M %p",
+            (DateFormat::Us, true) => "%m/%d/%Y %-I:%M:%S %p",
+            (DateFormat::European, false) => "%d.%m.%Y %H:%M",
+            (DateFormat::European, true) => "%d.%m.%Y %H:%M:%S",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Theme {
+    /// Whatever was last picked in this browser
+    #[default]
+    Browser,
+    Dark,
+    Light,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::Browser, Theme::Dark, Theme::Light];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Theme::Browser => "browser",
+            Theme::Dark => "dark",
+            Theme::Light => "light",
+        }
+    }
+
+    pub fn parse(s: &str) -> Self {
+        Theme::ALL.into_iter().find(|t| t.as_str() == s).unwrap_or_default()
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Theme::Browser => "Per browser",
+            Theme::Dark => "Dark",
+            Theme::Light => "Light",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Preferences {
+    /// Page opened after signing in
+    #[serde(default)]
+    pub landing: Landing,
+    /// Entries per page of the history and audit log
+    #[serde(default = "default_rows_per_page")]
+    pub rows_per_page: usize,
+    #[serde(default)]
+    pub date_format: DateFormat,
+    /// IANA name such as `Europe/Berlin` that times are shown in; empty for UTC
+    #[serde(default)]
+    pub timezone: String,
+    #[serde(default)]
+    pub theme: Theme,
+    /// Add each member's current physical address to member lists
+    #[serde(default)]
+    pub show_physical_address: bool,
+}
+
+fn default_rows_per_page() -> usize {
+    100
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            landing: Landing::default(),
+            rows_per_page: default_rows_per_page(),
+            date_format: DateFormat::default(),
+            timezone: String::new(),
+            theme: Theme::default(),
+            show_physical_address: false,
+        }
+    }
+}
+
+/// The timezone named `input`, as written in the tz database; empty input means UTC
+pub fn parse_timezone(input: &str) -> Result<String, String> {
+    let name = input.trim();
+    if name.is_empty() {
+        return Ok(String::new());
+    }
+    timezones()
+        .find(|tz| tz.eq_ignore_ascii_case(name))
+        .map(str::to_string)
+        .ok_or_else(|| format!("Unknown timezone {:?}; use a name such as Europe/Berlin", name))
+}
+
+/// Names offered for the timezone
+pub fn timezones() -> impl Iterator<Item = &'static str> {
+    chrono_tz::TZ_VARIANTS.iter().map(|tz| tz.name())
+}
+
+/// Run `f` with `preferences` as the current ones
+pub async fn scope<F: std::future::Future>(preferences: Preferences, f: F) -> F::Output {
+    CURRENT.scope(preferences, f).await
+}
+
+/// `dark` or `light` when the user chose one, empty to leave it to the browser
+pub fn theme() -> &'static str {
+    match CURRENT.try_with(|p| p.theme).unwrap_or_default() {
+        Theme::Browser => "",
+        theme => theme.as_str(),
+    }
+}
+
+/// Whether member lists get a physical address column
+pub fn show_physical_address() -> bool {
+    CURRENT.try_with(|p| p.show_physical_address).unwrap_or(false)
+}
+
+/// A time written the way the user prefers, to the minute
+pub fn format_time(time: DateTime<Utc>) -> String {
+    format(time, false)
+}
+
+/// A time written the way the user prefers, to the second
+pub fn format_time_exact(time: DateTime<Utc>) -> String {
+    format(time, true)
+}
+
+/// A time given in milliseconds since the epoch, as ZeroTier reports them, to the second
+pub fn format_epoch_ms(ms: f64) -> String {
+    DateTime::from_timestamp_millis(ms as i64).map(format_time_exact).unwrap_or_default()
+}
+
+/// Name of the timezone the user's times are in, e.g. for labelling a date input
+pub fn timezone_name() -> String {
+    match current_timezone() {
+        Some(tz) => tz.name().to_string(),
+        None => "UTC".to_string(),
+    }
+}
+
+/// A time as a `datetime-local` input value in the user's timezone
+pub fn input_value(time: DateTime<Utc>) -> String {
+    let pattern = "%Y-%m-%dT%H:%M";
+    match current_timezone() {
+        Some(tz) => time.with_timezone(&tz).format(pattern).to_string(),
+        None => time.format(pattern).to_string(),
+    }
+}
+
+/// A wall-clock time in the user's timezone as UTC. A time repeated when the clocks go back is
+/// the first of the two; one skipped when they go forward is None.
+pub fn from_local(naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+    match current_timezone() {
+        Some(tz) => tz.from_local_datetime(&naive).earliest().map(|t| t.with_timezone(&Utc)),
+        None => Some(naive.and_utc()),
+    }
+}
+
+fn current_timezone() -> Option<Tz> {
+    CURRENT.try_with(|p| p.timezone.parse::<Tz>().ok()).ok().flatten()
+}
+
+fn format(time: DateTime<Utc>, seconds: bool) -> String {
+    let (date_format, tz) = CURRENT
+        .try_with(|p| (p.date_format, p.timezone.parse::<Tz>().ok()))
+        .unwrap_or_default();
+    let pattern = date_format.pattern(seconds);
+    match tz {
+        Some(tz) => time.with_timezone(&tz).format(&format!("{} %Z", pattern)).to_string(),
+        None => format!("{} UTC", time.format(pattern)),
+    }
+}
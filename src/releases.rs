@@ -0,0 +1,156 @@
+//! Opt-in check of ZeroTier's GitHub releases, so an outdated node, or one missing controller
+//! fixes, is noticed before it causes trouble. Only the release list is fetched; nothing about
+//! this installation is sent.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::state::AppState;
+
+/// ZeroTier's releases on GitHub; `ZT_RELEASES_URL` overrides it
+const RELEASES_URL: &str = "https://api.github.com/repos/zerotier/ZeroTierOne/releases?per_page=30";
+
+/// How often the releases are fetched while the check is on
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// A ZeroTier release as listed by GitHub
+#[derive(Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+    html_url: String,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    prerelease: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Release {
+    pub version: String,
+    pub url: String,
+    /// The release notes mention the controller
+    pub controller_changes: bool,
+}
+
+/// The stable releases, newest first, as of the last check
+#[derive(Clone, Debug)]
+pub struct ReleaseInfo {
+    pub releases: Vec<Release>,
+    pub checked_at: DateTime<Utc>,
+}
+
+/// What an admin should know about the node's version
+pub struct UpdateNotice {
+    pub current: String,
+    pub latest: Release,
+    /// Newer releases whose notes mention the controller
+    pub controller_releases: Vec<String>,
+}
+
+/// `1.14.2` as numbers, ignoring a leading `v` and anything after the patch number
+fn parse_version(v: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = v.trim().trim_start_matches('v').split(['.', '-']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    let patch = parts.next().and_then(|p| p.parse().ok()).unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+impl ReleaseInfo {
+    pub fn display_checked_at(&self) -> String {
+        crate::preferences::format_time(self.checked_at)
+    }
+
+    pub fn latest(&self) -> Option<&Release> {
+        self.releases.first()
+    }
+
+    /// What's newer than `node_version`, if anything
+    pub fn notice(&self, node_version: &str) -> Option<UpdateNotice> {
+        let current = parse_version(node_version)?;
+        let newer: Vec<&Release> = self
+            .releases
+            .iter()
+            .filter(|r| parse_version(&r.version).is_some_and(|v| v > current))
+            .collect();
+        let latest = (*newer.first()?).clone();
+        Some(UpdateNotice {
+            current: node_version.to_string(),
+            latest,
+            controller_releases: newer
+                .iter()
+                .filter(|r| r.controller_changes)
+                .map(|r| r.version.clone())
+                .collect(),
+        })
+    }
+}
+
+/// Fetch the stable releases, newest first
+pub async fn fetch() -> Result<ReleaseInfo, String> {
+    let url = std::env::var("ZT_RELEASES_URL").unwrap_or_else(|_| RELEASES_URL.to_string());
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .user_agent(format!("TierDrop/{}", crate::VERSION))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let resp = client
+        .get(&url)
+        .header("Accept", "application/vnd.github+json")
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach GitHub: {}", e))?;
+    if !resp.status().is_success() {
+        return Err(format!("GitHub returned {}", resp.status()));
+    }
+    let list: Vec<GithubRelease> = resp
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse the release list: {}", e))?;
+
+    let mut releases: Vec<Release> = list
+        .into_iter()
+        .filter(|r| !r.draft && !r.prerelease && parse_version(&r.tag_name).is_some())
+        .map(|r| Release {
+            version: r.tag_name.trim_start_matches('v').to_string(),
+            url: r.html_url,
+            controller_changes: r.body.is_some_and(|b| b.to_lowercase().contains("controller")),
+        })
+        .collect();
+    releases.sort_by_key(|r| std::cmp::Reverse(parse_version(&r.version)));
+    Ok(ReleaseInfo {
+        releases,
+        checked_at: Utc::now(),
+    })
+}
+
+/// Fetch the releases and keep them for the dashboard banner
+pub async fn check(state: &AppState) -> Result<(), String> {
+    let info = fetch().await?;
+    debug!(
+        "ZeroTier release check: latest is {}",
+        info.latest().map_or("unknown", |r| r.version.as_str())
+    );
+    *state.release_info.write().await = Some(info);
+    Ok(())
+}
+
+/// Check the releases every 12 hours while the check is turned on
+pub async fn run_release_check(state: AppState) {
+    loop {
+        let enabled = state.config.read().await.as_ref().is_some_and(|c| c.release_check);
+        if enabled {
+            if let Err(e) = check(&state).await {
+                warn!("ZeroTier release check failed: {}", e);
+            }
+        }
+        if !state.idle(CHECK_INTERVAL).await {
+            break;
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Request IDs, so a failure a user reports can be matched to the server's log.
+//!
+//! Each request gets an ID, taken from an `X-Request-Id` header set by a reverse proxy or
+//! otherwise generated. Log lines written while handling the request carry it, the response
+//! returns it in `X-Request-Id`, and plain-text server errors end with `(req <id>)`.
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::body::{Body, HttpBody};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::{HeaderName, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::Instrument;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Error messages are short; anything bigger is passed through untouched
+const MAX_ERROR_BODY: usize = 64 * 1024;
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// A ULID: 26 characters that sort by creation time
+fn generate() -> String {
+    let millis = chrono::Utc::now().timestamp_millis() as u128 & ((1 << 48) - 1);
+    let mut random = [0u8; 10];
+    OsRng.fill_bytes(&mut random);
+    let mut value = millis << 80;
+    for (i, byte) in random.iter().enumerate() {
+        value |= (*byte as u128) << (8 * (9 - i));
+    }
+    let mut id = [0u8; 26];
+    for c in id.iter_mut().rev() {
+        *c = CROCKFORD[(value & 31) as usize];
+        value >>= 5;
+    }
+    String::from_utf8_lossy(&id).into_owned()
+}
+
+/// An ID passed in by a proxy, if it is safe to put in logs and headers
+fn incoming(request: &Request<Body>) -> Option<String> {
+    let id = request.headers().get(&REQUEST_ID_HEADER)?.to_str().ok()?;
+    let valid = !id.is_empty()
+        && id.len() <= 64
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+    valid.then(|| id.to_string())
+}
+
+pub async fn request_id_middleware(request: Request<Body>, next: Next) -> Response {
+    let id = incoming(&request).unwrap_or_else(generate);
+    let span = tracing::info_span!(
+        "request",
+        id = %id,
+        method = %request.method(),
+        path = %request.uri().path(),
+    );
+
+    let mut response = next.run(request).instrument(span.clone()).await;
+
+    if response.status().is_server_error() {
+        response = tag_error(response, &id, &span).await;
+    }
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// Log a server error and add the request ID to its message
+async fn tag_error(response: Response, id: &str, span: &tracing::Span) -> Response {
+    let status = response.status();
+    let headers = response.headers();
+    let is_text = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/plain"));
+    let is_short = response.body().size_hint().upper().is_some_and(|len| len <= MAX_ERROR_BODY as u64);
+    if !(is_text && is_short) {
+        span.in_scope(|| tracing::error!("Responded {}", status));
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let message = match axum::body::to_bytes(body, MAX_ERROR_BODY).await {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).trim_end().to_string(),
+        Err(_) => {
+            span.in_scope(|| tracing::error!("Responded {}", status));
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    span.in_scope(|| tracing::error!("Responded {}: {}", status, message));
+
+    parts.headers.remove(CONTENT_LENGTH);
+    let tagged = if message.is_empty() {
+        format!("req {}", id)
+    } else {
+        format!("{} (req {})", message, id)
+    };
+    Response::from_parts(parts, Body::from(tagged))
+}
@@ -0,0 +1,287 @@
+//! Edits that couldn't reach ZeroTier, typically because zerotier-one was restarting, are queued
+//! here and tried again every few seconds for a while instead of failing. Each edit keeps its
+//! intent, so it's worked out again from the network as it is when it's finally applied, and the
+//! revision it was made to, so it's dropped rather than applied over someone else's change.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::{info, warn};
+
+use crate::sse::SseEvent;
+use crate::state::AppState;
+use crate::zt::backend::ZtBackend;
+use crate::zt::models::{ControllerMember, ControllerNetwork};
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a queued edit is retried before it's given up
+pub const RETRY_FOR: Duration = Duration::from_secs(5 * 60);
+
+/// How long the outcome of a queued edit stays on screen
+const KEEP_FINISHED: Duration = Duration::from_secs(10 * 60);
+
+/// Turns the network as it is into the update to send
+pub type NetworkEdit = Box<dyn Fn(&ControllerNetwork) -> Result<serde_json::Value, String> + Send + Sync>;
+
+pub enum Change {
+    Network { edit: NetworkEdit },
+    /// Member updates only set fields, so they don't depend on the member's current state
+    Member { member_id: String, body: serde_json::Value },
+}
+
+impl Change {
+    /// Whether both edits change the same object within a network
+    fn same_target(&self, other: &Change) -> bool {
+        match (self, other) {
+            (Change::Network { .. }, Change::Network { .. }) => true,
+            (Change::Member { member_id: a, .. }, Change::Member { member_id: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Why an edit wasn't applied
+pub enum Failure {
+    /// ZeroTier didn't answer; worth trying again
+    Unreachable(String),
+    /// Changed since the revision the edit was made to
+    Conflict,
+    /// The edit doesn't apply to the network as it is now
+    Invalid(String),
+    /// ZeroTier answered and refused or failed
+    Failed(String),
+}
+
+/// A failed request is only worth retrying if the service itself is down, not when it turned the
+/// request down
+async fn classify(client: &dyn ZtBackend, error: String) -> Failure {
+    if client.get_status().await.is_err() {
+        Failure::Unreachable(error)
+    } else {
+        Failure::Failed(error)
+    }
+}
+
+/// Apply `edit` to the network, unless it changed since revision `seen`
+pub async fn apply_network(
+    client: &dyn ZtBackend,
+    nwid: &str,
+    seen: Option<u64>,
+    edit: &NetworkEdit,
+) -> Result<ControllerNetwork, Failure> {
+    let current = match client.get_controller_network(nwid).await {
+        Ok(n) => n,
+        Err(e) => return Err(classify(client, e).await),
+    };
+    if seen.is_some_and(|seen| current.revision.is_some_and(|r| r != seen)) {
+        return Err(Failure::Conflict);
+    }
+    let body = edit(&current).map_err(Failure::Invalid)?;
+    match client.update_controller_network(nwid, body).await {
+        Ok(network) => Ok(network),
+        Err(e) => Err(classify(client, e).await),
+    }
+}
+
+/// Apply `body` to the member, unless it changed since revision `seen`
+pub async fn apply_member(
+    client: &dyn ZtBackend,
+    nwid: &str,
+    member_id: &str,
+    seen: Option<u64>,
+    body: serde_json::Value,
+) -> Result<ControllerMember, Failure> {
+    if seen.is_some() {
+        let current = match client.get_controller_member(nwid, member_id).await {
+            Ok(m) => m,
+            Err(e) => return Err(classify(client, e).await),
+        };
+        if seen.is_some_and(|seen| current.revision.is_some_and(|r| r != seen)) {
+            return Err(Failure::Conflict);
+        }
+    }
+    match client.update_controller_member(nwid, member_id, body).await {
+        Ok(member) => Ok(member),
+        Err(e) => Err(classify(client, e).await),
+    }
+}
+
+struct Pending {
+    id: u64,
+    nwid: String,
+    change: Change,
+    /// Revision of the network or member the edit was made to
+    seen: Option<u64>,
+    description: String,
+    username: String,
+    queued: SystemTime,
+    give_up: Instant,
+}
+
+/// An edit in the queue or recently taken out of it, for display
+#[derive(Debug, Clone)]
+pub struct QueuedEdit {
+    pub id: u64,
+    pub nwid: String,
+    pub description: String,
+    pub username: String,
+    pub queued: SystemTime,
+    /// `None` while still waiting; otherwise whether it was applied, or why not
+    pub outcome: Option<Result<(), String>>,
+    finished: Option<Instant>,
+}
+
+impl QueuedEdit {
+    pub fn display_queued(&self) -> String {
+        chrono::DateTime::<chrono::Utc>::from(self.queued).format("%H:%M:%S UTC").to_string()
+    }
+}
+
+#[derive(Default)]
+pub struct RetryQueue {
+    next_id: AtomicU64,
+    pending: Mutex<Vec<Pending>>,
+    finished: Mutex<VecDeque<QueuedEdit>>,
+}
+
+impl RetryQueue {
+    fn push(&self, nwid: &str, change: Change, seen: Option<u64>, description: String, username: &str) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        info!("Queued \"{}\" on network {} until ZeroTier is reachable", description, nwid);
+        self.pending.lock().unwrap().push(Pending {
+            id,
+            nwid: nwid.to_string(),
+            change,
+            seen,
+            description,
+            username: username.to_string(),
+            queued: SystemTime::now(),
+            give_up: Instant::now() + RETRY_FOR,
+        });
+    }
+
+    /// `username`'s edits still waiting and recently finished, oldest first
+    pub fn edits_of(&self, username: &str) -> Vec<QueuedEdit> {
+        let pending = self.pending.lock().unwrap();
+        let mut edits: Vec<QueuedEdit> = self
+            .finished
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| e.username == username && e.finished.is_some_and(|t| t.elapsed() < KEEP_FINISHED))
+            .cloned()
+            .collect();
+        edits.extend(pending.iter().filter(|p| p.username == username).map(|p| QueuedEdit {
+            id: p.id,
+            nwid: p.nwid.clone(),
+            description: p.description.clone(),
+            username: p.username.clone(),
+            queued: p.queued,
+            outcome: None,
+            finished: None,
+        }));
+        edits.sort_by_key(|e| e.id);
+        edits
+    }
+
+    /// Stop showing a finished edit
+    pub fn dismiss(&self, id: u64, username: &str) {
+        self.finished.lock().unwrap().retain(|e| e.id != id || e.username != username);
+    }
+
+    fn finish(&self, pending: Pending, outcome: Result<(), String>) {
+        match &outcome {
+            Ok(()) => info!("Applied queued \"{}\" on network {}", pending.description, pending.nwid),
+            Err(e) => warn!("Gave up on queued \"{}\" on network {}: {}", pending.description, pending.nwid, e),
+        }
+        let mut finished = self.finished.lock().unwrap();
+        finished.retain(|e| e.finished.is_some_and(|t| t.elapsed() < KEEP_FINISHED));
+        finished.push_back(QueuedEdit {
+            id: pending.id,
+            nwid: pending.nwid,
+            description: pending.description,
+            username: pending.username,
+            queued: pending.queued,
+            outcome: Some(outcome),
+            finished: Some(Instant::now()),
+        });
+    }
+}
+
+/// Queue an edit that failed because ZeroTier couldn't be reached, and tell `username`'s pages
+pub fn queue(state: &AppState, nwid: &str, change: Change, seen: Option<u64>, description: String, username: &str) {
+    state.retries.push(nwid, change, seen, description, username);
+    let _ = state.tx.send(SseEvent::RetriesChanged { networks: vec![nwid.to_string()] });
+}
+
+/// Background job working through the queue in order
+pub async fn run_retries(state: AppState) {
+    while state.idle(RETRY_INTERVAL).await {
+        let queued = std::mem::take(&mut *state.retries.pending.lock().unwrap());
+        if queued.is_empty() {
+            continue;
+        }
+        let Some(client) = state.zt_client.read().await.clone() else {
+            state.retries.pending.lock().unwrap().splice(0..0, queued);
+            continue;
+        };
+
+        let mut waiting = Vec::new();
+        let mut changed = Vec::new();
+        let mut applied = false;
+        let mut queued = queued.into_iter();
+        while let Some(pending) = queued.next() {
+            let result = match &pending.change {
+                Change::Network { edit } => apply_network(client.as_ref(), &pending.nwid, pending.seen, edit)
+                    .await
+                    .map(|n| n.revision),
+                Change::Member { member_id, body } => {
+                    apply_member(client.as_ref(), &pending.nwid, member_id, pending.seen, body.clone())
+                        .await
+                        .map(|m| m.revision)
+                }
+            };
+            let outcome = match result {
+                Ok(revision) => {
+                    // Later edits to the same network or member were made to the revision this
+                    // one replaced
+                    for later in waiting.iter_mut().chain(queued.as_mut_slice().iter_mut()) {
+                        if later.nwid == pending.nwid
+                            && later.change.same_target(&pending.change)
+                            && later.seen == pending.seen
+                        {
+                            later.seen = revision;
+                        }
+                    }
+                    applied = true;
+                    Ok(())
+                }
+                Err(Failure::Unreachable(e)) => {
+                    if Instant::now() < pending.give_up {
+                        waiting.push(pending);
+                        continue;
+                    }
+                    Err(format!("ZeroTier stayed unreachable: {}", e))
+                }
+                Err(Failure::Conflict) => Err("Changed by someone else while waiting for ZeroTier".to_string()),
+                Err(Failure::Invalid(e)) | Err(Failure::Failed(e)) => Err(e),
+            };
+            changed.push(pending.nwid.clone());
+            state.retries.finish(pending, outcome);
+        }
+
+        // Edits queued meanwhile go after the ones still waiting
+        state.retries.pending.lock().unwrap().splice(0..0, waiting);
+        if applied {
+            state.notify_poller();
+        }
+        if !changed.is_empty() {
+            changed.sort();
+            changed.dedup();
+            let _ = state.tx.send(SseEvent::RetriesChanged { networks: changed });
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use axum::extract::State;
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use tracing::warn;
+
+use crate::agents::{self, ReportRequest};
+use crate::routes::error::error_response;
+use crate::sse::{MemberChange, SseEvent};
+use crate::state::AppState;
+
+/// POST /api/v1/agent/report - A member's agent reporting hostname, OS and ZeroTier version,
+/// authenticated with its network's agent token
+pub async fn report(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<ReportRequest>,
+) -> Response {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|t| t.starts_with(agents::TOKEN_PREFIX));
+    let nwid = {
+        let config = state.config.read().await;
+        config.as_ref().zip(token).and_then(|(c, token)| c.agent_network(token))
+    };
+    let Some(nwid) = nwid else {
+        return error_response(StatusCode::UNAUTHORIZED, "Invalid agent token");
+    };
+
+    let member_id = request.member_id.trim().to_ascii_lowercase();
+    let is_member = state
+        .zt_state
+        .read()
+        .await
+        .controller_members
+        .get(&nwid)
+        .is_some_and(|members| members.iter().any(|m| m.display_id() == member_id));
+    if !is_member {
+        return error_response(StatusCode::NOT_FOUND, format!("{} is not a member of {}", member_id, nwid));
+    }
+
+    let changed = {
+        let mut config = state.config.write().await;
+        let Some(c) = config.as_mut() else {
+            return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured");
+        };
+        let changed = c.record_agent_report(&nwid, &request);
+        if changed {
+            if let Err(e) = c.save() {
+                warn!("Failed to save agent report for {}: {}", member_id, e);
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR, e);
+            }
+        }
+        changed
+    };
+    if changed {
+        let _ = state.tx.send(SseEvent::MemberChanged {
+            nwid,
+            member_id,
+            change: MemberChange::Updated,
+        });
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
@@ -0,0 +1,185 @@
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::approvals;
+use crate::state::AppState;
+
+#[derive(Template, WebTemplate)]
+#[template(path = "approve.html")]
+pub struct ApproveTemplate {
+    pub nwid: String,
+    pub member_id: String,
+    pub network_name: String,
+    pub member_name: String,
+    pub expires: i64,
+    pub sig: String,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ApproveQuery {
+    pub expires: i64,
+    pub sig: String,
+}
+
+/// Verify the link and build the page for it
+async fn approve_page(
+    state: &AppState,
+    nwid: String,
+    member_id: String,
+    query: ApproveQuery,
+) -> Result<ApproveTemplate, Response> {
+    let (result, member_name) = {
+        let config = state.config.read().await;
+        match config.as_ref() {
+            Some(c) => (
+                approvals::verify(&c.approval_secret, &nwid, &member_id, query.expires, &query.sig).and_then(|_| {
+                    if c.approval_link_used(&query.sig) {
+                        Err("This approval link has already been used.".to_string())
+                    } else {
+                        Ok(())
+                    }
+                }),
+                c.member_names.get(&member_id).cloned().unwrap_or_default(),
+            ),
+            None => (Err("TierDrop is not configured.".to_string()), String::new()),
+        }
+    };
+
+    let network_name = state
+        .zt_state
+        .read()
+        .await
+        .controller_networks
+        .iter()
+        .find(|n| n.display_id() == nwid)
+        .map(|n| n.display_name().to_string())
+        .unwrap_or_default();
+
+    let page = ApproveTemplate {
+        nwid,
+        member_id,
+        network_name,
+        member_name,
+        expires: query.expires,
+        sig: query.sig,
+        message: None,
+        error: None,
+    };
+    match result {
+        Ok(()) => Ok(page),
+        Err(e) => Err((StatusCode::FORBIDDEN, ApproveTemplate { error: Some(e), ..page }).into_response()),
+    }
+}
+
+/// GET /approve/{nwid}/{member_id} - Confirmation page for a signed approval link.
+/// Approving takes a POST so link previews can't authorize anyone.
+pub async fn approve_link(
+    State(state): State<AppState>,
+    Path((nwid, member_id)): Path<(String, String)>,
+    Query(query): Query<ApproveQuery>,
+) -> Response {
+    match approve_page(&state, nwid, member_id, query).await {
+        Ok(page) => page.into_response(),
+        Err(response) => response,
+    }
+}
+
+/// POST /approve/{nwid}/{member_id} - Authorize the member named in a signed link
+pub async fn approve_confirm(
+    State(state): State<AppState>,
+    Path((nwid, member_id)): Path<(String, String)>,
+    Query(query): Query<ApproveQuery>,
+) -> Response {
+    let page = match approve_page(&state, nwid, member_id, query).await {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+
+    let client = state.zt_client.read().await.clone();
+    let Some(client) = client else {
+        return ApproveTemplate {
+            error: Some("The controller is not available. Try again later.".to_string()),
+            ..page
+        }.into_response();
+    };
+
+    // Only approve a member that still exists and is waiting; never toggle
+    let member = match client.get_controller_member(&page.nwid, &page.member_id).await {
+        Ok(m) if m.address.is_some() || m.id.is_some() => m,
+        Ok(_) => {
+            return ApproveTemplate {
+                error: Some("This member is no longer on the network.".to_string()),
+                ..page
+            }.into_response();
+        }
+        Err(e) => {
+            return ApproveTemplate {
+                error: Some(format!("Failed: {}", e)),
+                ..page
+            }.into_response();
+        }
+    };
+    if member.is_authorized() {
+        return ApproveTemplate {
+            message: Some("This member is already authorized.".to_string()),
+            ..page
+        }.into_response();
+    }
+
+    // Each link works once: claim it before authorizing, and give it back if that fails
+    let claimed = {
+        let mut config = state.config.write().await;
+        config.as_mut().is_some_and(|c| c.claim_approval_link(&page.sig, page.expires))
+    };
+    if !claimed {
+        return ApproveTemplate {
+            error: Some("This approval link has already been used.".to_string()),
+            ..page
+        }.into_response();
+    }
+
+    match client
+        .update_controller_member(&page.nwid, &page.member_id, serde_json::json!({"authorized": true}))
+        .await
+    {
+        Ok(_) => {
+            info!("Member {} on {} approved via approval link", page.member_id, page.nwid);
+            {
+                let mut config = state.config.write().await;
+                if let Some(ref mut c) = *config {
+                    c.join_requests.retain(|r| !(r.nwid == page.nwid && r.member_id == page.member_id));
+                    c.audit(
+                        "approval link",
+                        "Member authorized",
+                        Some(&page.nwid),
+                        Some(&page.member_id),
+                        String::new(),
+                    );
+                    let _ = c.save();
+                }
+            }
+            state.notify_poller();
+            ApproveTemplate {
+                message: Some("Member authorized.".to_string()),
+                ..page
+            }.into_response()
+        }
+        Err(e) => {
+            warn!("Approval link for {} on {} failed: {}", page.member_id, page.nwid, e);
+            if let Some(c) = state.config.write().await.as_mut() {
+                c.release_approval_link(&page.sig);
+            }
+            ApproveTemplate {
+                error: Some(format!("Failed: {}", e)),
+                ..page
+            }.into_response()
+        }
+    }
+}
@@ -1,22 +1,31 @@
-use axum::body::Body;
-use axum::extract::State;
+use axum::body::{Body, Bytes};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::extract::{Path as AxumPath, Query, State};
 use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
-use axum::Extension;
+use axum::{Extension, Form, Json};
 use axum_extra::extract::Multipart;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use chrono::{DateTime, Utc};
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use serde::{Deserialize, Serialize};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use tar::{Archive, Builder};
 use tempfile::TempDir;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 
+use crate::notifications::{EventKind, NotificationEvent};
 use crate::permissions;
+use crate::routes::error::error_response;
 use crate::state::{AppState, User};
-use crate::zt::client::ZtClient;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Manifest {
@@ -26,10 +35,179 @@ pub struct Manifest {
     pub backup_type: String,
     pub network_count: usize,
     pub tierdrop_version: String,
+    /// ZeroTier files left out under the backup contents settings
+    #[serde(default)]
+    pub excluded: Vec<String>,
+}
+
+/// Parts of the ZeroTier directory a backup can leave out, with a label for each.
+/// Restore never reads them back; ZeroTier rebuilds them on its own.
+pub const OPTIONAL_ZT_PATHS: [(&str, &str); 3] = [
+    ("peers.d", "Peer cache"),
+    ("networks.d", "Joined network configs"),
+    ("metrics.prom", "Metrics"),
+];
+
+/// What goes into a backup
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BackupContents {
+    /// Copy the whole ZeroTier directory, ignoring the exclusions below
+    #[serde(default = "default_true")]
+    pub full_snapshot: bool,
+    /// Entries of OPTIONAL_ZT_PATHS to leave out when not taking a full snapshot
+    #[serde(default = "default_excluded")]
+    pub excluded: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_excluded() -> Vec<String> {
+    OPTIONAL_ZT_PATHS.iter().map(|(path, _)| path.to_string()).collect()
+}
+
+impl Default for BackupContents {
+    fn default() -> Self {
+        Self {
+            full_snapshot: true,
+            excluded: default_excluded(),
+        }
+    }
+}
+
+impl BackupContents {
+    /// Top-level ZeroTier entries to skip
+    pub fn skipped(&self) -> &[String] {
+        if self.full_snapshot {
+            &[]
+        } else {
+            &self.excluded
+        }
+    }
+
+    pub fn is_excluded(&self, path: &str) -> bool {
+        self.excluded.iter().any(|p| p == path)
+    }
+
+    pub fn summary(&self) -> String {
+        if self.skipped().is_empty() {
+            "Full snapshot".to_string()
+        } else {
+            format!("Excludes {}", self.skipped().join(", "))
+        }
+    }
+}
+
+/// Oldest history entries are dropped beyond this
+const MAX_BACKUP_RECORDS: usize = 500;
+
+/// Where a backup went
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupTarget {
+    /// Downloaded through the browser
+    Download,
+    /// Written to the backups folder by the scheduler
+    Local,
+}
+
+impl BackupTarget {
+    pub fn label(&self) -> &'static str {
+        match self {
+            BackupTarget::Download => "Download",
+            BackupTarget::Local => "Local",
+        }
+    }
+}
+
+/// A backup that was taken, manually or by the scheduler
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BackupRecord {
+    pub id: u64,
+    pub file_name: String,
+    pub created_at: DateTime<Utc>,
+    pub size: u64,
+    /// "full" or "partial"
+    pub backup_type: String,
+    pub target: BackupTarget,
+    /// Username, or "scheduler"
+    pub created_by: String,
+}
+
+impl BackupRecord {
+    pub fn display_created_at(&self) -> String {
+        crate::preferences::format_time_exact(self.created_at)
+    }
+
+    pub fn display_size(&self) -> String {
+        match self.size {
+            s if s < 1024 => format!("{} B", s),
+            s if s < 1024 * 1024 => format!("{:.1} KB", s as f64 / 1024.0),
+            s => format!("{:.1} MB", s as f64 / (1024.0 * 1024.0)),
+        }
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.target == BackupTarget::Local
+    }
+
+    /// The archive on disk, for local backups that haven't been pruned
+    pub fn local_path(&self) -> Option<PathBuf> {
+        if !self.is_local() {
+            return None;
+        }
+        let path = crate::schedule::backups_dir().join(&self.file_name);
+        path.is_file().then_some(path)
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.local_path().is_some()
+    }
+}
+
+impl AppState {
+    /// Add a finished backup to the history, save, and announce it
+    pub async fn record_backup(&self, archive: &BackupArchive, size: u64, target: BackupTarget, created_by: &str) {
+        {
+            let mut config = self.config.write().await;
+            let Some(ref mut c) = *config else { return };
+            let id = c.backup_history.iter().map(|r| r.id).max().unwrap_or(0) + 1;
+            c.backup_history.push(BackupRecord {
+                id,
+                file_name: archive.file_name(),
+                created_at: Utc::now(),
+                size,
+                backup_type: archive.backup_type.clone(),
+                target,
+                created_by: created_by.to_string(),
+            });
+            if c.backup_history.len() > MAX_BACKUP_RECORDS {
+                let excess = c.backup_history.len() - MAX_BACKUP_RECORDS;
+                c.backup_history.drain(..excess);
+            }
+            if let Err(e) = c.save() {
+                tracing::warn!("Failed to save backup history: {}", e);
+            }
+        }
+
+        self.send_notification(NotificationEvent {
+            kind: EventKind::BackupCompleted,
+            nwid: String::new(),
+            network_name: String::new(),
+            member_id: None,
+            detail: Some(format!("{}, {} networks", archive.file_name(), archive.network_count)),
+        })
+        .await;
+    }
 }
 
 /// Returns the platform-appropriate ZeroTier data directory
-fn zerotier_data_dir() -> PathBuf {
+pub fn zerotier_data_dir() -> PathBuf {
+    // Demo backups and restores must leave the real node's identity alone
+    if crate::demo::enabled() {
+        return crate::state::data_dir().join("zerotier-one");
+    }
     #[cfg(target_os = "windows")]
     {
         PathBuf::from(r"C:\ProgramData\ZeroTier\One")
@@ -63,22 +241,60 @@ fn copy_dir_recursive(src: &Path, dst: &Path) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Copy the ZeroTier directory, leaving out the given top-level entries
+fn copy_zt_dir(src: &Path, dst: &Path, skip: &[String]) -> std::io::Result<()> {
+    if !src.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(dst)?;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if skip.iter().any(|s| *s == name.to_string_lossy()) {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            copy_dir_recursive(&path, &dst.join(&name))?;
+        } else {
+            std::fs::copy(&path, dst.join(&name))?;
+        }
+    }
+    Ok(())
+}
+
+/// Network IDs stored in a ZeroTier data directory's controller.d
+fn backup_network_ids(zt_dir: &Path) -> Vec<String> {
+    let controller_d = zt_dir.join("controller.d");
+    let mut ids: Vec<String> = [controller_d.join("network"), controller_d]
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let path = e.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                path.file_stem().map(|s| s.to_string_lossy().to_string())
+            } else {
+                None
+            }
+        })
+        .filter(|id| id.len() == 16 && id.chars().all(|c| c.is_ascii_hexdigit()))
+        .collect();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
 /// Count networks in the backup (from controller.d directory)
 fn count_networks(temp_dir: &Path) -> usize {
-    let controller_d = temp_dir.join("zerotier-one").join("controller.d");
-    if !controller_d.exists() {
-        return 0;
-    }
-    std::fs::read_dir(&controller_d)
-        .map(|entries| {
-            entries
-                .filter_map(|e| e.ok())
-                .filter(|e| {
-                    e.path().extension().map(|ext| ext == "json").unwrap_or(false)
-                })
-                .count()
-        })
-        .unwrap_or(0)
+    backup_network_ids(&temp_dir.join("zerotier-one")).len()
+}
+
+/// Node address from an identity file, e.g. `fd1234abcd` from `fd1234abcd:0:...`
+fn identity_address(path: &Path) -> Option<String> {
+    let identity = std::fs::read_to_string(path).ok()?;
+    identity.split(':').next().map(|a| a.trim().to_string()).filter(|a| !a.is_empty())
 }
 
 /// Determine backup type based on presence of identity files
@@ -91,29 +307,100 @@ fn determine_backup_type(temp_dir: &Path) -> &'static str {
     }
 }
 
-/// Create a tar.gz archive from a directory
-fn create_tar_gz(source_dir: &Path, archive_name: &str) -> std::io::Result<Vec<u8>> {
-    let mut buffer = Vec::new();
-    {
-        let encoder = GzEncoder::new(&mut buffer, Compression::default());
-        let mut tar = Builder::new(encoder);
+/// Write a tar.gz archive of a directory into `writer`, returning the writer
+fn create_tar_gz<W: Write>(source_dir: &Path, archive_name: &str, writer: W) -> std::io::Result<W> {
+    let encoder = GzEncoder::new(writer, Compression::default());
+    let mut tar = Builder::new(encoder);
 
-        // Add all files from the temp directory under the archive name prefix
-        for entry in std::fs::read_dir(source_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            let name = format!("{}/{}", archive_name, entry.file_name().to_string_lossy());
+    // Add all files from the temp directory under the archive name prefix
+    for entry in std::fs::read_dir(source_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = format!("{}/{}", archive_name, entry.file_name().to_string_lossy());
 
-            if path.is_dir() {
-                tar.append_dir_all(&name, &path)?;
-            } else {
-                tar.append_path_with_name(&path, &name)?;
-            }
+        if path.is_dir() {
+            tar.append_dir_all(&name, &path)?;
+        } else {
+            tar.append_path_with_name(&path, &name)?;
         }
+    }
+
+    tar.into_inner()?.finish()
+}
 
-        tar.into_inner()?.finish()?;
+/// Counts bytes passing through to the inner writer
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Size of the chunks handed to the response body
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Run a blocking writer on its own thread, streaming what it writes as a response body.
+/// An error ends the body with that error, so clients don't keep a truncated file.
+fn blocking_body<T, F>(f: F) -> (Body, tokio::task::JoinHandle<std::io::Result<T>>)
+where
+    T: Send + 'static,
+    F: FnOnce(&mut ChannelWriter) -> std::io::Result<T> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(4);
+    let handle = tokio::task::spawn_blocking(move || {
+        let mut writer = ChannelWriter {
+            tx: tx.clone(),
+            buffer: Vec::with_capacity(STREAM_CHUNK_SIZE),
+        };
+        let result = f(&mut writer).and_then(|value| writer.flush().map(|_| value));
+        if let Err(e) = &result {
+            let _ = tx.blocking_send(Err(std::io::Error::new(e.kind(), e.to_string())));
+        }
+        result
+    });
+    (Body::from_stream(ReceiverStream::new(rx)), handle)
+}
+
+/// Blocking writer feeding a response body stream in chunks. Fails once the client goes away.
+struct ChannelWriter {
+    tx: mpsc::Sender<std::io::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send_buffer(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(&mut self.buffer, Vec::with_capacity(STREAM_CHUNK_SIZE)));
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Download cancelled"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= STREAM_CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.send_buffer()
     }
-    Ok(buffer)
 }
 
 /// Extract a tar.gz archive to a temporary directory
@@ -135,25 +422,85 @@ pub async fn export_backup(
 ) -> Response {
     // Only admins can export backups
     if !permissions::is_admin(&user) {
-        return (StatusCode::FORBIDDEN, "Only administrators can export backups").into_response();
+        return error_response(StatusCode::FORBIDDEN, "Only administrators can export backups");
     }
 
-    // Create temp directory for staging
-    let temp_dir = match tempfile::tempdir() {
-        Ok(d) => d,
-        Err(e) => {
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to create temp directory: {}", e),
-            )
-                .into_response();
+    let archive = match create_backup_archive(&state).await {
+        Ok(a) => a,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    };
+    let filename = archive.file_name();
+
+    // Pack straight into the response, so the archive is never held in memory
+    let (body, packing) = blocking_body(move |writer| {
+        let size = archive.write_to(writer)?;
+        Ok((archive, size))
+    });
+    let username = user.username.clone();
+    let recorder = state.clone();
+    state.spawn(async move {
+        match packing.await {
+            Ok(Ok((archive, size))) => {
+                recorder.record_backup(&archive, size, BackupTarget::Download, &username).await;
+            }
+            Ok(Err(e)) => tracing::warn!("Backup download failed: {}", e),
+            Err(e) => tracing::error!("Backup task failed: {}", e),
         }
+    });
+
+    download_response(&filename, body)
+}
+
+fn download_response(filename: &str, body: Body) -> Response {
+    Response::builder()
+        .header(CONTENT_TYPE, "application/gzip")
+        .header(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .body(body)
+        .unwrap()
+}
+
+/// A backup staged in a temporary directory, ready to be packed as a tar.gz
+pub struct BackupArchive {
+    /// Archive name without extension, e.g. tierdrop-backup-20260101-120000
+    pub name: String,
+    /// "full" or "partial"
+    pub backup_type: String,
+    pub network_count: usize,
+    staging: TempDir,
+}
+
+impl BackupArchive {
+    pub fn file_name(&self) -> String {
+        format!("{}.tar.gz", self.name)
+    }
+
+    /// Pack the staged files into `writer`, returning the compressed size. Blocks.
+    pub fn write_to<W: Write>(&self, writer: W) -> std::io::Result<u64> {
+        let mut counted = create_tar_gz(self.staging.path(), &self.name, CountingWriter { inner: writer, count: 0 })?;
+        counted.flush()?;
+        Ok(counted.count)
+    }
+}
+
+/// Stage a backup of the ZeroTier directory and TierDrop config.
+pub async fn create_backup_archive(state: &AppState) -> Result<BackupArchive, String> {
+    // Create temp directory for staging
+    let temp_dir = tempfile::tempdir()
+        .map_err(|e| format!("Failed to create temp directory: {}", e))?;
+
+    let contents = {
+        let config = state.config.read().await;
+        config.as_ref().map(|c| c.backup_contents.clone()).unwrap_or_default()
     };
+    let excluded = contents.skipped().to_vec();
 
     // Copy ZeroTier directory
     let zt_dir = zerotier_data_dir();
     let zt_dest = temp_dir.path().join("zerotier-one");
-    if let Err(e) = copy_dir_recursive(&zt_dir, &zt_dest) {
+    if let Err(e) = copy_zt_dir(&zt_dir, &zt_dest, &excluded) {
         tracing::warn!("Failed to copy ZeroTier directory: {}", e);
         // Continue anyway - might be permission issues
     }
@@ -162,24 +509,10 @@ pub async fn export_backup(
     {
         let config = state.config.read().await;
         if let Some(ref c) = *config {
-            let config_json = match serde_json::to_string_pretty(c) {
-                Ok(j) => j,
-                Err(e) => {
-                    return (
-                        axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                        format!("Failed to serialize config: {}", e),
-                    )
-                        .into_response();
-                }
-            };
-            if let Err(e) = std::fs::write(temp_dir.path().join("tierdrop-config.json"), config_json)
-            {
-                return (
-                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("Failed to write config: {}", e),
-                )
-                    .into_response();
-            }
+            let config_json = serde_json::to_string_pretty(c)
+                .map_err(|e| format!("Failed to serialize config: {}", e))?;
+            std::fs::write(temp_dir.path().join("tierdrop-config.json"), config_json)
+                .map_err(|e| format!("Failed to write config: {}", e))?;
         }
     }
 
@@ -196,63 +529,201 @@ pub async fn export_backup(
         backup_type: determine_backup_type(temp_dir.path()).to_string(),
         network_count: count_networks(temp_dir.path()),
         tierdrop_version: env!("CARGO_PKG_VERSION").to_string(),
+        excluded,
     };
 
-    let manifest_json = match serde_json::to_string_pretty(&manifest) {
-        Ok(j) => j,
-        Err(e) => {
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to serialize manifest: {}", e),
-            )
-                .into_response();
-        }
-    };
-    if let Err(e) = std::fs::write(temp_dir.path().join("manifest.json"), manifest_json) {
-        return (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to write manifest: {}", e),
-        )
-            .into_response();
-    }
+    let manifest_json = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| format!("Failed to serialize manifest: {}", e))?;
+    std::fs::write(temp_dir.path().join("manifest.json"), manifest_json)
+        .map_err(|e| format!("Failed to write manifest: {}", e))?;
 
-    // Create archive
     let timestamp = Utc::now().format("%Y%m%d-%H%M%S");
-    let archive_name = format!("tierdrop-backup-{}", timestamp);
-    let archive_data = match create_tar_gz(temp_dir.path(), &archive_name) {
-        Ok(d) => d,
-        Err(e) => {
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to create archive: {}", e),
-            )
-                .into_response();
-        }
-    };
-
-    let filename = format!("{}.tar.gz", archive_name);
-
-    Response::builder()
-        .header(CONTENT_TYPE, "application/gzip")
-        .header(
-            CONTENT_DISPOSITION,
-            format!("attachment; filename=\"{}\"", filename),
-        )
-        .body(Body::from(archive_data))
-        .unwrap()
+    Ok(BackupArchive {
+        name: format!("tierdrop-backup-{}", timestamp),
+        backup_type: manifest.backup_type,
+        network_count: manifest.network_count,
+        staging: temp_dir,
+    })
 }
 
-#[derive(Debug)]
+/// Outcome of applying a backup
+#[derive(Debug, Serialize, Deserialize)]
 pub struct RestoreResult {
-    pub _success: bool,
     pub message: String,
-    pub _manifest: Option<Manifest>,
-    pub _identity_restored: bool,
-    pub _config_restored: bool,
+    pub identity_restored: bool,
+    pub config_restored: bool,
     pub needs_restart: bool,
+    /// Whether ZeroTier was restarted afterwards, as asked with `restart_zerotier`
+    #[serde(default)]
+    pub zerotier_restarted: bool,
+}
+
+/// How long an uploaded backup waits for confirmation before it must be uploaded again
+const PENDING_RESTORE_MINUTES: i64 = 15;
+
+/// Largest backup accepted for restore
+pub const MAX_RESTORE_UPLOAD: usize = 256 * 1024 * 1024;
+
+/// An extracted, validated backup
+pub struct StagedRestore {
+    backup_path: PathBuf,
+    // Keeps the extracted files on disk until the restore is applied or discarded
+    _temp_dir: TempDir,
+}
+
+/// An uploaded backup waiting for the admin to confirm the restore
+pub struct PendingRestore {
+    token: String,
+    created_at: DateTime<Utc>,
+    staged: StagedRestore,
 }
 
-/// Restore backup handler - processes uploaded tar.gz backup
+/// What applying a backup would change
+#[derive(Serialize, Deserialize)]
+pub struct RestorePlan {
+    pub created_at: DateTime<Utc>,
+    pub backup_type: String,
+    pub tierdrop_version: String,
+    /// Whether the backup carries controller.d; without it networks are left alone
+    pub has_networks: bool,
+    pub networks_overwritten: Vec<String>,
+    pub networks_added: Vec<String>,
+    pub networks_removed: Vec<String>,
+    /// Present when the backup carries a node identity
+    pub identity: Option<IdentityChange>,
+    pub replaces_authtoken: bool,
+    /// Present when the backup carries a TierDrop config
+    pub users: Option<UsersChange>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IdentityChange {
+    pub current: String,
+    pub backup: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UsersChange {
+    pub current_count: usize,
+    pub restored: Vec<String>,
+    /// Current users missing from the backup, who lose access
+    pub removed: Vec<String>,
+}
+
+impl RestorePlan {
+    pub fn display_created_at(&self) -> String {
+        crate::preferences::format_time(self.created_at)
+    }
+
+    pub fn identity_changes(&self) -> bool {
+        self.identity.as_ref().is_some_and(|i| i.current != i.backup)
+    }
+}
+
+/// Restore preview shown before anything is written
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/restore_preview.html")]
+pub struct RestorePreviewTemplate {
+    pub token: String,
+    pub plan: RestorePlan,
+}
+
+/// Extract and validate an uploaded backup, and work out what restoring it would change
+async fn stage_restore(state: &AppState, data: &[u8]) -> Result<(StagedRestore, RestorePlan), String> {
+    // Extract archive
+    let temp_dir = extract_tar_gz(data).map_err(|e| format!("Failed to extract archive: {}", e))?;
+
+    // Find the backup directory (it's usually named tierdrop-backup-TIMESTAMP)
+    let backup_path = find_backup_dir(temp_dir.path())
+        .ok_or("Invalid backup archive: no backup directory found")?;
+
+    // Read and validate manifest
+    let manifest_path = backup_path.join("manifest.json");
+    if !manifest_path.exists() {
+        return Err("Invalid backup: manifest.json not found".to_string());
+    }
+    let content = std::fs::read_to_string(&manifest_path).map_err(|e| format!("Failed to read manifest: {}", e))?;
+    let manifest: Manifest = serde_json::from_str(&content).map_err(|e| format!("Invalid manifest: {}", e))?;
+
+    // Compare against what is live now
+    let zt_source = backup_path.join("zerotier-one");
+    let zt_dest = zerotier_data_dir();
+    let has_networks = zt_source.join("controller.d").exists();
+    let backup_networks = backup_network_ids(&zt_source);
+    let (live_networks, current_address) = {
+        let zt = state.zt_state.read().await;
+        let mut live: Vec<(String, String)> = zt
+            .controller_networks
+            .iter()
+            .map(|n| (n.display_id().to_string(), n.display_name().to_string()))
+            .collect();
+        live.sort();
+        (live, zt.status.as_ref().and_then(|s| s.address.clone()))
+    };
+    let network_label = |nwid: &str| match live_networks.iter().find(|(id, _)| id == nwid) {
+        Some((_, name)) if !name.is_empty() => format!("{} ({})", name, nwid),
+        _ => nwid.to_string(),
+    };
+    let (networks_overwritten, networks_added): (Vec<String>, Vec<String>) = backup_networks
+        .iter()
+        .cloned()
+        .partition(|nwid| live_networks.iter().any(|(id, _)| id == nwid));
+    let networks_removed: Vec<String> = if has_networks {
+        live_networks
+            .iter()
+            .filter(|(id, _)| !backup_networks.contains(id))
+            .map(|(id, _)| network_label(id))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let identity = if zt_source.join("identity.secret").exists() {
+        identity_address(&zt_source.join("identity.public")).map(|backup| IdentityChange {
+            current: identity_address(&zt_dest.join("identity.public"))
+                .or(current_address)
+                .unwrap_or_else(|| "unknown".to_string()),
+            backup,
+        })
+    } else {
+        None
+    };
+
+    let users = match std::fs::read_to_string(backup_path.join("tierdrop-config.json")) {
+        Ok(content) => {
+            let restored = serde_json::from_str::<crate::state::Config>(&content)
+                .map_err(|e| format!("Invalid TierDrop config in backup: {}", e))?;
+            let config = state.config.read().await;
+            let current: Vec<String> = config
+                .as_ref()
+                .map(|c| c.users.iter().map(|u| u.username.clone()).collect())
+                .unwrap_or_default();
+            let restored: Vec<String> = restored.users.into_iter().map(|u| u.username).collect();
+            Some(UsersChange {
+                current_count: current.len(),
+                removed: current.into_iter().filter(|u| !restored.contains(u)).collect(),
+                restored,
+            })
+        }
+        Err(_) => None,
+    };
+
+    let plan = RestorePlan {
+        created_at: manifest.created_at,
+        backup_type: manifest.backup_type,
+        tierdrop_version: manifest.tierdrop_version,
+        has_networks,
+        networks_overwritten: networks_overwritten.iter().map(|n| network_label(n)).collect(),
+        networks_added,
+        networks_removed,
+        identity,
+        replaces_authtoken: zt_source.join("authtoken.secret").exists(),
+        users,
+    };
+    Ok((StagedRestore { backup_path, _temp_dir: temp_dir }, plan))
+}
+
+/// Restore backup handler - extracts the uploaded tar.gz and shows what restoring it would change
 pub async fn restore_backup(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
@@ -260,7 +731,7 @@ pub async fn restore_backup(
 ) -> Response {
     // Only admins can restore backups
     if !permissions::is_admin(&user) {
-        return (StatusCode::FORBIDDEN, "Only administrators can restore backups").into_response();
+        return error_response(StatusCode::FORBIDDEN, "Only administrators can restore backups");
     }
 
     // Read the uploaded file
@@ -287,41 +758,122 @@ pub async fn restore_backup(
         }
     };
 
-    // Extract archive
-    let temp_dir = match extract_tar_gz(&file_data) {
-        Ok(d) => d,
-        Err(e) => {
-            return restore_error_response(&format!("Failed to extract archive: {}", e));
-        }
+    let (staged, plan) = match stage_restore(&state, &file_data).await {
+        Ok(s) => s,
+        Err(e) => return restore_error_response(&e),
     };
 
-    // Find the backup directory (it's usually named tierdrop-backup-TIMESTAMP)
-    let backup_dir = find_backup_dir(temp_dir.path());
-    let backup_path = match backup_dir {
-        Some(p) => p,
-        None => {
-            return restore_error_response("Invalid backup archive: no backup directory found");
+    let mut token_bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut token_bytes);
+    let token = URL_SAFE_NO_PAD.encode(token_bytes);
+
+    // Only the latest upload can be confirmed
+    *state.pending_restore.write().await = Some(PendingRestore {
+        token: token.clone(),
+        created_at: Utc::now(),
+        staged,
+    });
+
+    RestorePreviewTemplate { token, plan }.into_response()
+}
+
+#[derive(Deserialize)]
+pub struct RestoreConfirmForm {
+    token: String,
+}
+
+/// POST /settings/backup/restore/confirm - Apply the previewed backup
+pub async fn confirm_restore(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Form(form): Form<RestoreConfirmForm>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Only administrators can restore backups");
+    }
+
+    let pending = {
+        let mut pending = state.pending_restore.write().await;
+        match pending.take() {
+            Some(p) if p.token == form.token => p,
+            other => {
+                *pending = other;
+                return restore_error_response("This restore preview is no longer current. Upload the backup again.");
+            }
         }
     };
+    if Utc::now() - pending.created_at > chrono::Duration::minutes(PENDING_RESTORE_MINUTES) {
+        return restore_error_response("This restore preview has expired. Upload the backup again.");
+    }
 
-    // Read and validate manifest
-    let manifest_path = backup_path.join("manifest.json");
-    let manifest: Manifest = if manifest_path.exists() {
-        match std::fs::read_to_string(&manifest_path) {
-            Ok(content) => match serde_json::from_str(&content) {
-                Ok(m) => m,
-                Err(e) => {
-                    return restore_error_response(&format!("Invalid manifest: {}", e));
-                }
-            },
-            Err(e) => {
-                return restore_error_response(&format!("Failed to read manifest: {}", e));
+    let result = apply_restore(&state, &pending.staged.backup_path).await;
+    restore_success_response(result)
+}
+
+#[derive(Deserialize)]
+pub struct ApiRestoreQuery {
+    #[serde(default)]
+    dry_run: bool,
+    /// Restart the ZeroTier service when the restore replaced its identity
+    #[serde(default)]
+    restart_zerotier: bool,
+}
+
+/// Response to an API restore: the plan, and the result unless it was a dry run
+#[derive(Serialize, Deserialize)]
+pub struct ApiRestoreResponse {
+    pub plan: RestorePlan,
+    pub result: Option<RestoreResult>,
+}
+
+/// POST /api/restore - Restore a tar.gz sent as the request body. With `?dry_run=true`
+/// only reports what would change.
+pub async fn api_restore(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(query): Query<ApiRestoreQuery>,
+    body: Bytes,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Only administrators can restore backups");
+    }
+    if body.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "No backup provided");
+    }
+
+    let (staged, plan) = match stage_restore(&state, &body).await {
+        Ok(s) => s,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let result = if query.dry_run {
+        None
+    } else {
+        let mut result = apply_restore(&state, &staged.backup_path).await;
+        if query.restart_zerotier && result.needs_restart {
+            match crate::routes::zerotier::restart_and_wait(&state, &user).await {
+                Ok(_) => result.zerotier_restarted = true,
+                Err(e) => result.message = format!("{}. ZeroTier couldn't be restarted: {}", result.message, e),
             }
         }
-    } else {
-        return restore_error_response("Invalid backup: manifest.json not found");
+        Some(result)
     };
+    Json(ApiRestoreResponse { plan, result }).into_response()
+}
+
+/// POST /settings/backup/restore/cancel - Discard the previewed backup
+pub async fn cancel_restore(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Only administrators can restore backups");
+    }
+    *state.pending_restore.write().await = None;
+    StatusCode::OK.into_response()
+}
 
+/// Write an extracted backup over the live ZeroTier directory and TierDrop config
+async fn apply_restore(state: &AppState, backup_path: &Path) -> RestoreResult {
     // Restore ZeroTier directory
     let zt_source = backup_path.join("zerotier-one");
     let zt_dest = zerotier_data_dir();
@@ -378,6 +930,7 @@ pub async fn restore_backup(
                         // Update state and save
                         {
                             let mut cfg = state.config.write().await;
+                            crate::branding::set(&restored_config.branding);
                             *cfg = Some(restored_config.clone());
                         }
                         match restored_config.save() {
@@ -412,7 +965,7 @@ pub async fn restore_backup(
         false
     };
 
-    // Reinitialize ZtClient with the new auth token
+    // Reconnect to ZeroTier with the new auth token
     if let Some(ref token) = new_auth_token {
         let base_url = {
             let cfg = state.config.read().await;
@@ -421,7 +974,7 @@ pub async fn restore_backup(
                 .unwrap_or_else(|| "http://localhost:9993".to_string())
         };
         let base_url = std::env::var("ZT_BASE_URL").unwrap_or(base_url);
-        let new_client = ZtClient::new(base_url, token.clone());
+        let new_client = crate::zt::backend::connect(base_url, token.clone());
         {
             let mut client = state.zt_client.write().await;
             *client = Some(new_client);
@@ -445,16 +998,13 @@ pub async fn restore_backup(
     // Needs restart if identity was restored (ZeroTier service needs to pick up new identity)
     let needs_restart = identity_restored;
 
-    let result = RestoreResult {
-        _success: true,
+    RestoreResult {
         message: messages.join(". "),
-        _manifest: Some(manifest),
-        _identity_restored: identity_restored,
-        _config_restored: config_restored,
+        identity_restored,
+        config_restored,
         needs_restart,
-    };
-
-    restore_success_response(result)
+        zerotier_restarted: false,
+    }
 }
 
 fn find_backup_dir(temp_dir: &Path) -> Option<PathBuf> {
@@ -493,10 +1043,24 @@ fn restore_error_response(message: &str) -> Response {
 }
 
 fn restore_success_response(result: RestoreResult) -> Response {
-    let restart_notice = if result.needs_restart {
-        r#"<p class="restore-notice"><strong>Important:</strong> ZeroTier service and TierDrop both need to be restarted for identity changes to take effect.</p>"#
+    let restart_notice = if result.needs_restart && crate::zt::service::available() {
+        format!(
+            r##"<div id="zerotier-restart" class="restore-notice">
+                <p><strong>Important:</strong> ZeroTier service and TierDrop both need to be restarted for identity changes to take effect.</p>
+                <button class="btn btn-sm btn-secondary"
+                        hx-post="{}/settings/backup/restart-zerotier"
+                        hx-target="#zerotier-restart"
+                        hx-swap="outerHTML"
+                        hx-confirm="Restart ZeroTier now? Members lose their connection for a few seconds.">
+                    <span class="htmx-hide-on-request">Restart ZeroTier Now</span><span class="spinner htmx-indicator"></span>
+                </button>
+            </div>"##,
+            crate::base_path::get()
+        )
+    } else if result.needs_restart {
+        r#"<p class="restore-notice"><strong>Important:</strong> ZeroTier service and TierDrop both need to be restarted for identity changes to take effect.</p>"#.to_string()
     } else {
-        r#"<p class="restore-notice"><strong>Note:</strong> Restart TierDrop to fully apply the restored configuration.</p>"#
+        r#"<p class="restore-notice"><strong>Note:</strong> Restart TierDrop to fully apply the restored configuration.</p>"#.to_string()
     };
 
     let html = format!(
@@ -514,6 +1078,83 @@ fn restore_success_response(result: RestoreResult) -> Response {
         .unwrap()
 }
 
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/zerotier_restart.html")]
+pub struct ZerotierRestartTemplate {
+    pub service: String,
+    pub error: Option<String>,
+    pub online: bool,
+}
+
+/// POST /settings/backup/restart-zerotier - Restart ZeroTier after a restore replaced its identity
+pub async fn restart_zerotier(State(state): State<AppState>, Extension(user): Extension<User>) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Only administrators can restart ZeroTier");
+    }
+    let result = crate::routes::zerotier::restart_and_wait(&state, &user).await;
+    let (error, online) = match result {
+        Ok(online) => (None, online),
+        Err(e) => (Some(e), false),
+    };
+    ZerotierRestartTemplate {
+        service: crate::zt::service::service_name(),
+        error,
+        online,
+    }
+    .into_response()
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/backup_history.html")]
+pub struct BackupHistoryTemplate {
+    pub records: Vec<BackupRecord>,
+}
+
+/// GET /settings/backup/history - Every backup taken, newest first
+pub async fn backup_history(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Only administrators can view backups");
+    }
+    let records = {
+        let config = state.config.read().await;
+        config
+            .as_ref()
+            .map(|c| c.backup_history.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    };
+    BackupHistoryTemplate { records }.into_response()
+}
+
+/// GET /settings/backup/history/{id}/download - Download a backup kept in the backups folder
+pub async fn download_backup(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    AxumPath(id): AxumPath<u64>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Only administrators can download backups");
+    }
+    let record = {
+        let config = state.config.read().await;
+        config
+            .as_ref()
+            .and_then(|c| c.backup_history.iter().find(|r| r.id == id).cloned())
+    };
+    let Some((record, path)) = record.and_then(|r| r.local_path().map(|p| (r, p))) else {
+        return error_response(StatusCode::NOT_FOUND, "Backup file is no longer available");
+    };
+    match std::fs::File::open(&path) {
+        Ok(mut file) => {
+            let (body, _) = blocking_body(move |writer| std::io::copy(&mut file, writer));
+            download_response(&record.file_name, body)
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read backup: {}", e)),
+    }
+}
+
 /// Get backup status info for the settings page
 pub struct BackupStatus {
     pub node_address: Option<String>,
@@ -532,7 +1173,7 @@ impl BackupStatus {
         let zt_dir = zerotier_data_dir();
         let can_backup_identity = zt_dir.join("identity.secret").exists()
             && std::fs::metadata(zt_dir.join("identity.secret"))
-                .map(|m| m.permissions().readonly() == false)
+                .map(|m| !m.permissions().readonly())
                 .unwrap_or(false);
 
         Self {
@@ -1,15 +1,30 @@
+use std::collections::{HashMap, HashSet};
+
 use askama::Template;
 use askama_web::WebTemplate;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Redirect, Response};
-use axum::Form;
-use axum::Extension;
-use serde::Deserialize;
-
+use axum::{Extension, Form, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::agents::{self, AgentReport};
+use crate::base_path;
+use crate::dns;
+use crate::geoip;
+use crate::history::{self, HistoryEvent};
+use crate::invites::{self, Invite, JoinRequest};
 use crate::permissions;
-use crate::state::{AppState, User};
-use crate::zt::models::{ControllerMember, ControllerNetwork, ControllerRoute, IpAssignmentPool};
+use crate::retry::{self, Change, Failure};
+use crate::routes::error::error_response;
+use crate::shares::{self, ShareLink};
+use crate::state::{AppState, Config, NetworkPermissions, User};
+use crate::zt::diff::{self, DiffEntry, MemberDiff};
+use crate::zt::models::{ControllerMember, ControllerNetwork, ControllerRoute, IpAssignmentPool, Peer};
+use crate::zt::rules;
+use crate::zt::simulate::{self, Simulation};
+use crate::zt::snippets::{CustomRuleSnippet, RuleSnippet, BUILTIN_SNIPPETS};
 
 // ---- Default Flow Rules ----
 
@@ -33,17 +48,9 @@ accept;
 
 /// Returns the compiled default rules as JSON array
 fn default_compiled_rules() -> Vec<serde_json::Value> {
-    // Pre-compiled default rules (drop non-IP traffic, accept everything else)
-    serde_json::from_str(
-        r#"[
-        {"not":true,"or":false,"type":"MATCH_ETHERTYPE","etherType":2048},
-        {"not":true,"or":false,"type":"MATCH_ETHERTYPE","etherType":2054},
-        {"not":true,"or":false,"type":"MATCH_ETHERTYPE","etherType":34525},
-        {"type":"ACTION_DROP"},
-        {"type":"ACTION_ACCEPT"}
-    ]"#,
-    )
-    .unwrap_or_default()
+    rules::compile(DEFAULT_RULES_SOURCE)
+        .map(|c| c.rules)
+        .unwrap_or_default()
 }
 
 // ---- Display row with enriched data ----
@@ -54,13 +61,51 @@ pub struct MemberDisplayRow {
     pub description: String,
     pub rfc4193_addr: Option<String>,
     pub sixplane_addr: Option<String>,
+    /// Member re-registered with a different identity and is pending review
+    pub identity_flagged: bool,
+    /// Guest access end, e.g. "2026-10-20 12:00 UTC"
+    pub authorized_until: Option<String>,
+    /// What the member agent last reported about the device
+    pub agent: Option<AgentReport>,
+    /// Where the controller's node currently reaches the member, e.g. "203.0.113.7/9993"
+    pub physical_address: Option<String>,
+    /// Assigned addresses outside every pool and managed route of the network
+    pub stray_ips: Vec<String>,
+}
+
+impl MemberDisplayRow {
+    pub fn is_stray(&self, ip: &str) -> bool {
+        self.stray_ips.iter().any(|s| s == ip)
+    }
+}
+
+/// What TierDrop keeps about a network's members, to enrich their rows with
+struct MemberLookups {
+    names: HashMap<String, String>,
+    descriptions: HashMap<String, String>,
+    flagged: HashSet<String>,
+    guest_expirations: HashMap<String, chrono::DateTime<chrono::Utc>>,
+    agent_reports: HashMap<String, AgentReport>,
 }
 
-/// Build enriched member rows from raw members + local names and descriptions.
+impl MemberLookups {
+    fn new(config: Option<&Config>, nwid: &str) -> Self {
+        Self {
+            names: config.map(|c| c.member_names.clone()).unwrap_or_default(),
+            descriptions: config.map(|c| c.member_descriptions.clone()).unwrap_or_default(),
+            flagged: config.map(|c| c.flagged_member_ids()).unwrap_or_default(),
+            guest_expirations: config.map(|c| c.guest_expirations(nwid)).unwrap_or_default(),
+            agent_reports: config.map(|c| c.agent_reports_for(nwid)).unwrap_or_default(),
+        }
+    }
+}
+
+/// Build enriched member rows from raw members, what TierDrop keeps about them and the peers
+/// the controller's node is in contact with.
 fn enrich_members(
     members: &[ControllerMember],
-    member_names: &std::collections::HashMap<String, String>,
-    member_descriptions: &std::collections::HashMap<String, String>,
+    lookups: &MemberLookups,
+    peers: &HashMap<String, Peer>,
     network: &ControllerNetwork,
 ) -> Vec<MemberDisplayRow> {
     let show_rfc4193 = network.v6_rfc4193();
@@ -69,17 +114,27 @@ fn enrich_members(
     members
         .iter()
         .map(|m| {
-            let name = member_names
+            let name = lookups
+                .names
                 .get(m.display_id())
                 .cloned()
                 .unwrap_or_default();
-            let description = member_descriptions
+            let description = lookups
+                .descriptions
                 .get(m.display_id())
                 .cloned()
                 .unwrap_or_default();
             MemberDisplayRow {
                 rfc4193_addr: if show_rfc4193 { m.rfc4193_address() } else { None },
                 sixplane_addr: if show_sixplane { m.sixplane_address() } else { None },
+                identity_flagged: lookups.flagged.contains(m.display_id()),
+                authorized_until: lookups
+                    .guest_expirations
+                    .get(m.display_id())
+                    .map(|t| crate::preferences::format_time(*t)),
+                agent: lookups.agent_reports.get(m.display_id()).cloned(),
+                physical_address: peers.get(m.display_id()).and_then(|p| p.physical_address()),
+                stray_ips: network.stray_ips(m),
                 member: m.clone(),
                 name,
                 description,
@@ -88,6 +143,88 @@ fn enrich_members(
         .collect()
 }
 
+/// An edit made to an older revision than the controller now has would silently undo the changes
+/// in between, such as another admin's, so it's refused. ZeroTier has no conditional writes, so
+/// this narrows the window for lost updates rather than closing it.
+fn conflict_response(what: &str) -> Response {
+    error_response(
+        StatusCode::CONFLICT,
+        format!(
+            "This {} was changed by someone else since the page loaded. Reload to see the changes, then try again.",
+            what
+        ),
+    )
+}
+
+/// An edit queued because ZeroTier couldn't be reached. The page stays as it is; the queued
+/// edits banner follows the retries.
+fn queued_response(description: &str) -> Response {
+    (
+        StatusCode::ACCEPTED,
+        [("HX-Reswap", "none")],
+        format!(
+            "ZeroTier can't be reached right now. \"{}\" will be retried for the next {} minutes.",
+            description,
+            retry::RETRY_FOR.as_secs() / 60
+        ),
+    )
+        .into_response()
+}
+
+/// Whether an edit's local parts, such as a description, should be kept: it was applied or queued
+fn edit_kept(result: &Result<impl Sized, Response>) -> bool {
+    result.as_ref().err().is_none_or(|r| r.status() == StatusCode::ACCEPTED)
+}
+
+/// Apply `edit` to the network as it is now, unless it changed since revision `seen`. `edit` turns
+/// the current network into the update to send. If ZeroTier can't be reached, the edit is queued
+/// and retried instead, and the error response says so.
+async fn edit_network<F>(
+    state: &AppState,
+    user: &User,
+    nwid: &str,
+    seen: Option<u64>,
+    description: impl Into<String>,
+    edit: F,
+) -> Result<ControllerNetwork, Response>
+where
+    F: Fn(&ControllerNetwork) -> Result<serde_json::Value, String> + Send + Sync + 'static,
+{
+    let Some(client) = state.zt_client.read().await.clone() else {
+        return Err(error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured"));
+    };
+    let edit: retry::NetworkEdit = Box::new(edit);
+    match retry::apply_network(client.as_ref(), nwid, seen, &edit).await {
+        Ok(network) => {
+            state.notify_poller();
+            Ok(network)
+        }
+        Err(Failure::Unreachable(_)) => {
+            let description = description.into();
+            let response = queued_response(&description);
+            retry::queue(state, nwid, Change::Network { edit }, seen, description, &user.username);
+            Err(response)
+        }
+        Err(Failure::Conflict) => Err(conflict_response("network")),
+        Err(Failure::Invalid(e)) => Err(error_response(StatusCode::BAD_REQUEST, e)),
+        Err(Failure::Failed(e)) => Err(error_response(StatusCode::BAD_GATEWAY, format!("Failed: {}", e))),
+    }
+}
+
+/// The IP assignment panel after an edit
+fn ip_pools_partial(nwid: String, network: ControllerNetwork) -> Response {
+    let pools = network.ip_assignment_pools.clone();
+    let routes = network.routes.clone();
+    CtrlIpPoolsPartial {
+        nwid,
+        network,
+        pools,
+        routes,
+        can_modify: true,
+    }
+    .into_response()
+}
+
 // ---- Page Templates ----
 
 #[derive(Template, WebTemplate)]
@@ -99,6 +236,8 @@ pub struct ControllerNetworkDetailTemplate {
     pub authorized_count: usize,
     pub nwid: String,
     pub description: String,
+    /// Username of whoever created the network, when known
+    pub owner: Option<String>,
     pub pools: Vec<IpAssignmentPool>,
     pub routes: Vec<ControllerRoute>,
     pub rules_source: String,
@@ -108,6 +247,7 @@ pub struct ControllerNetworkDetailTemplate {
     pub can_authorize: bool,
     pub can_modify: bool,
     pub can_delete: bool,
+    pub is_admin: bool,
 }
 
 // ---- Partial Templates ----
@@ -132,6 +272,13 @@ pub struct CtrlNetworkSettingsPartial {
     pub can_modify: bool,
 }
 
+#[derive(Template, WebTemplate)]
+#[template(path = "controller/partials/sso_settings.html")]
+pub struct CtrlSsoSettingsPartial {
+    pub network: ControllerNetwork,
+    pub can_modify: bool,
+}
+
 #[derive(Template, WebTemplate)]
 #[template(path = "controller/partials/ip_pools.html")]
 pub struct CtrlIpPoolsPartial {
@@ -151,6 +298,19 @@ pub struct CtrlMemberRowPartial {
     pub can_modify: bool,
 }
 
+/// A member's row after a poll changed it, with the member counts. Without a row the member is
+/// gone and its row is removed.
+#[derive(Template, WebTemplate)]
+#[template(path = "controller/partials/member_row_update.html")]
+pub struct CtrlMemberRowUpdatePartial {
+    pub nwid: String,
+    pub row: Option<MemberDisplayRow>,
+    pub member_count: usize,
+    pub authorized_count: usize,
+    pub can_authorize: bool,
+    pub can_modify: bool,
+}
+
 #[derive(Template, WebTemplate)]
 #[template(path = "controller/partials/member_modal.html")]
 pub struct CtrlMemberModalPartial {
@@ -160,9 +320,247 @@ pub struct CtrlMemberModalPartial {
     pub description: String,
     pub rfc4193_addr: Option<String>,
     pub sixplane_addr: Option<String>,
+    pub tag_fields: Vec<MemberTagField>,
+    pub capability_fields: Vec<MemberCapabilityField>,
+    /// Guest access end as a datetime-local value in the user's timezone, empty when unlimited
+    pub authorized_until: String,
+    /// Send a notification when the member stays offline
+    pub monitored: bool,
+    /// Connection state as seen by the controller's node
+    pub peer_status: String,
+    /// What the member agent last reported about the device
+    pub agent: Option<AgentReport>,
+    /// How the controller's node reaches the member, while it's online
+    pub connection: Option<PeerConnection>,
+    /// Addresses outside the network's pools and routes, and what would replace them
+    pub ip_fix: Option<IpFix>,
     pub can_modify: bool,
 }
 
+/// Moving a member's addresses back inside the network's pools and routes
+pub struct IpFix {
+    pub stray: Vec<String>,
+    /// Free pool addresses for families that would be left without one
+    pub replacements: Vec<String>,
+    /// The member's addresses after the fix
+    pub fixed: Vec<String>,
+}
+
+impl IpFix {
+    /// What fixing the member's addresses takes, or None when none are stray
+    fn plan(network: &ControllerNetwork, member: &ControllerMember, members: &[ControllerMember]) -> Option<Self> {
+        let stray = network.stray_ips(member);
+        if stray.is_empty() {
+            return None;
+        }
+        let mut fixed: Vec<String> = member
+            .ip_assignments
+            .iter()
+            .filter(|ip| !stray.contains(ip))
+            .cloned()
+            .collect();
+        let taken: HashSet<String> = members
+            .iter()
+            .filter(|m| m.display_id() != member.display_id())
+            .flat_map(|m| m.ip_assignments.iter().cloned())
+            .collect();
+        let mut replacements = Vec::new();
+        for v6 in [false, true] {
+            let had_family = stray.iter().any(|ip| ip.contains(':') == v6);
+            let has_family = fixed.iter().any(|ip| ip.contains(':') == v6);
+            if had_family && !has_family {
+                if let Some(ip) = network.free_pool_ip(v6, &taken) {
+                    replacements.push(ip.clone());
+                    fixed.push(ip);
+                }
+            }
+        }
+        Some(Self { stray, replacements, fixed })
+    }
+}
+
+/// A member offered for IP reassignment
+pub struct ReassignCandidate {
+    pub id: String,
+    pub name: String,
+    pub ips: Vec<String>,
+    /// Addresses outside the network's pools and routes. Members with any start out selected.
+    pub stray_ips: Vec<String>,
+    /// Has auto-assign turned off, so its addresses are left alone
+    pub manual: bool,
+}
+
+impl ReassignCandidate {
+    pub fn is_stray(&self, ip: &str) -> bool {
+        self.stray_ips.iter().any(|s| s == ip)
+    }
+}
+
+/// What reassigning one member's addresses came to
+pub struct ReassignOutcome {
+    pub id: String,
+    pub name: String,
+    pub ips: Vec<String>,
+    /// Applied once ZeroTier can be reached again
+    pub queued: bool,
+    pub error: Option<String>,
+}
+
+/// The address families the network assigns from its pools, as (is IPv6, label)
+fn reissued_families(network: &ControllerNetwork) -> Vec<(bool, &'static str)> {
+    [(false, "IPv4", network.v4_auto_assign()), (true, "IPv6", network.v6_zt_auto_assign())]
+        .into_iter()
+        .filter(|(v6, _, on)| *on && network.ip_assignment_pools.iter().any(|p| p.is_ipv6() == *v6))
+        .map(|(v6, label, _)| (v6, label))
+        .collect()
+}
+
+/// A member's addresses re-issued from the pools: those of each family the network assigns are
+/// dropped and replaced with the first free pool address. Other addresses are kept.
+fn reissue_ips(
+    network: &ControllerNetwork,
+    member: &ControllerMember,
+    taken: &HashSet<String>,
+) -> Result<Vec<String>, String> {
+    let families = reissued_families(network);
+    let mut ips: Vec<String> = member
+        .ip_assignments
+        .iter()
+        .filter(|ip| !families.iter().any(|(v6, _)| ip.contains(':') == *v6))
+        .cloned()
+        .collect();
+    for (v6, label) in families {
+        let ip = network
+            .free_pool_ip(v6, taken)
+            .ok_or_else(|| format!("The {} pools have no free address left", label))?;
+        ips.push(ip);
+    }
+    Ok(ips)
+}
+
+pub struct PeerConnection {
+    /// No direct path, so traffic goes through a ZeroTier root
+    pub relayed: bool,
+    pub latency_ms: Option<i64>,
+    /// The live physical paths
+    pub paths: Vec<MemberPath>,
+}
+
+/// One of a member's physical paths, located when GeoIP databases are configured
+pub struct MemberPath {
+    pub address: String,
+    pub preferred: bool,
+    pub last_receive: String,
+    pub location: Option<geoip::Location>,
+}
+
+/// A tag the member can be assigned, with its current value
+pub struct MemberTagField {
+    pub id: u32,
+    pub name: String,
+    pub enums: Vec<rules::NamedValue>,
+    pub flags: Vec<rules::NamedValue>,
+    pub value: Option<u32>,
+}
+
+impl MemberTagField {
+    pub fn is_selected(&self, value: &u32) -> bool {
+        self.value == Some(*value)
+    }
+
+    pub fn has_flag(&self, mask: &u32) -> bool {
+        self.value.is_some_and(|v| v & mask == *mask)
+    }
+
+    /// Current value that isn't one of the enums, shown as an extra option
+    pub fn other_value(&self) -> Option<u32> {
+        self.value
+            .filter(|v| self.flags.is_empty() && !self.enums.iter().any(|e| e.value == *v))
+    }
+}
+
+/// A capability the member can be granted
+pub struct MemberCapabilityField {
+    pub id: u32,
+    pub name: String,
+    pub granted: bool,
+}
+
+/// Tag and capability fields for a member, using names declared in the network's rules
+/// source. Falls back to the IDs in the network's compiled rules, and always includes
+/// whatever the member already has so saving never drops an assignment.
+fn member_rule_fields(
+    member: &ControllerMember,
+    network: &ControllerNetwork,
+    rules_source: Option<&str>,
+) -> (Vec<MemberTagField>, Vec<MemberCapabilityField>) {
+    let declared = rules_source
+        .and_then(|src| rules::declarations(src).ok())
+        .unwrap_or_default();
+    let mut tags: Vec<MemberTagField> = declared
+        .tags
+        .into_iter()
+        .map(|t| MemberTagField {
+            id: t.id,
+            name: t.name,
+            enums: t.enums,
+            flags: t.flags,
+            value: None,
+        })
+        .collect();
+    let mut caps: Vec<MemberCapabilityField> = declared
+        .capabilities
+        .into_iter()
+        .map(|c| MemberCapabilityField {
+            id: c.id,
+            name: c.name,
+            granted: false,
+        })
+        .collect();
+
+    let json_ids = |values: &[serde_json::Value]| -> Vec<u32> {
+        values
+            .iter()
+            .filter_map(|v| v.get("id").and_then(|id| id.as_u64()))
+            .map(|id| id as u32)
+            .collect()
+    };
+    let tag_ids = json_ids(&network.tags)
+        .into_iter()
+        .chain(member.tags.iter().map(|(id, _)| *id));
+    for id in tag_ids {
+        if !tags.iter().any(|t| t.id == id) {
+            tags.push(MemberTagField {
+                id,
+                name: format!("tag {}", id),
+                enums: Vec::new(),
+                flags: Vec::new(),
+                value: None,
+            });
+        }
+    }
+    let cap_ids = json_ids(&network.capabilities)
+        .into_iter()
+        .chain(member.capabilities.iter().copied());
+    for id in cap_ids {
+        if !caps.iter().any(|c| c.id == id) {
+            caps.push(MemberCapabilityField {
+                id,
+                name: format!("capability {}", id),
+                granted: false,
+            });
+        }
+    }
+
+    for tag in &mut tags {
+        tag.value = member.tags.iter().find(|(id, _)| *id == tag.id).map(|(_, v)| *v);
+    }
+    for cap in &mut caps {
+        cap.granted = member.capabilities.contains(&cap.id);
+    }
+    (tags, caps)
+}
+
 #[derive(Template, WebTemplate)]
 #[template(path = "controller/partials/flow_rules.html")]
 pub struct CtrlFlowRulesPartial {
@@ -170,6 +568,35 @@ pub struct CtrlFlowRulesPartial {
     pub network: ControllerNetwork,
     pub rules_source: String,
     pub can_modify: bool,
+    pub is_admin: bool,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "controller/partials/rule_simulator.html")]
+pub struct CtrlRuleSimulatorPartial {
+    pub nwid: String,
+    /// (member id, display label)
+    pub members: Vec<(String, String)>,
+    pub can_modify: bool,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "controller/partials/rule_simulation.html")]
+pub struct CtrlRuleSimulationPartial {
+    pub simulation: Simulation,
+    pub rule_set_label: &'static str,
+    pub source_summary: String,
+    pub dest_summary: String,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "controller/partials/state_diff.html")]
+pub struct CtrlStateDiffPartial {
+    pub cache_age: String,
+    pub network_diff: Vec<DiffEntry>,
+    pub member_diffs: Vec<MemberDiff>,
+    pub cached_member_count: usize,
+    pub fresh_member_count: usize,
 }
 
 // ---- Handlers: Pages ----
@@ -181,7 +608,7 @@ pub async fn controller_network_detail(
 ) -> Response {
     // Check read permission
     if !permissions::can_read(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to view this network").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to view this network");
     }
 
     let client = state.zt_client.read().await;
@@ -208,14 +635,7 @@ pub async fn controller_network_detail(
     drop(client);
 
     let config = state.config.read().await;
-    let member_names = config
-        .as_ref()
-        .map(|c| c.member_names.clone())
-        .unwrap_or_default();
-    let member_descriptions = config
-        .as_ref()
-        .map(|c| c.member_descriptions.clone())
-        .unwrap_or_default();
+    let lookups = MemberLookups::new(config.as_ref(), &nwid);
     let rules_source = config
         .as_ref()
         .and_then(|c| c.rules_source.get(&nwid).cloned())
@@ -224,6 +644,10 @@ pub async fn controller_network_detail(
         .as_ref()
         .and_then(|c| c.network_descriptions.get(&nwid).cloned())
         .unwrap_or_default();
+    let owner = config.as_ref().and_then(|c| {
+        let id = c.network_owners.get(&nwid)?;
+        c.find_user_by_id(*id).map(|u| u.username.clone())
+    });
     drop(config);
 
     // Get user permissions for this network
@@ -238,10 +662,12 @@ pub async fn controller_network_detail(
             let authorized_count = members.iter().filter(|m| m.is_authorized()).count();
             let pools = network.ip_assignment_pools.clone();
             let routes = network.routes.clone();
-            let rows = enrich_members(&members, &member_names, &member_descriptions, &network);
+            let peers = state.zt_state.read().await.peers.clone();
+            let rows = enrich_members(&members, &lookups, &peers, &network);
             ControllerNetworkDetailTemplate {
                 nwid,
                 description: network_description,
+                owner,
                 pools,
                 routes,
                 network,
@@ -254,6 +680,7 @@ pub async fn controller_network_detail(
                 can_authorize,
                 can_modify,
                 can_delete,
+                is_admin: permissions::is_admin(&user),
             }
             .into_response()
         }
@@ -274,10 +701,11 @@ pub async fn controller_network_detail(
                 let authorized_count = members.iter().filter(|m| m.is_authorized()).count();
                 let pools = nw.ip_assignment_pools.clone();
                 let routes = nw.routes.clone();
-                let rows = enrich_members(&members, &member_names, &member_descriptions, nw);
+                let rows = enrich_members(&members, &lookups, &zt.peers, nw);
                 ControllerNetworkDetailTemplate {
                     nwid,
                     description: network_description,
+                    owner,
                     pools,
                     routes,
                     network: nw.clone(),
@@ -290,24 +718,109 @@ pub async fn controller_network_detail(
                     can_authorize,
                     can_modify,
                     can_delete,
+                    is_admin: permissions::is_admin(&user),
                 }
                 .into_response()
             } else {
-                (StatusCode::NOT_FOUND, "Controller network not found").into_response()
+                error_response(StatusCode::NOT_FOUND, "Controller network not found")
             }
         }
     }
 }
 
+/// Admin diagnostics: compare the poller's cached state with a fresh controller fetch
+pub async fn state_diff(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    // Snapshot the cache before fetching so the comparison reflects what the UI is serving
+    let (cached_network, cached_members, cache_not_loaded, last_updated) = {
+        let zt = state.zt_state.read().await;
+        // A member list that failed to load leaves every member missing from the cache
+        let not_loaded: Option<HashSet<String>> = if zt.members_loaded(&nwid) {
+            Some(zt.unloaded_members.get(&nwid).cloned().unwrap_or_default())
+        } else {
+            None
+        };
+        (
+            zt.controller_networks
+                .iter()
+                .find(|n| n.display_id() == nwid)
+                .cloned(),
+            zt.controller_members.get(&nwid).cloned().unwrap_or_default(),
+            not_loaded,
+            zt.last_updated,
+        )
+    };
+
+    let client = state.zt_client.read().await;
+    let result = match client.as_ref() {
+        Some(c) => {
+            let nw = c.get_controller_network(&nwid).await;
+            let members = match c.get_controller_members(&nwid).await {
+                Ok(ids) => {
+                    let mut mems = Vec::new();
+                    let mut failed = HashSet::new();
+                    for mid in ids.keys() {
+                        match c.get_controller_member(&nwid, mid).await {
+                            Ok(m) => mems.push(m),
+                            Err(_) => {
+                                failed.insert(mid.clone());
+                            }
+                        }
+                    }
+                    mems.sort_by(|a, b| a.display_id().cmp(b.display_id()));
+                    Ok((mems, failed))
+                }
+                Err(e) => Err(e),
+            };
+            Some((nw, members))
+        }
+        None => None,
+    };
+    drop(client);
+
+    let (fresh_network, (fresh_members, mut not_loaded)) = match result {
+        Some((Ok(nw), Ok(members))) => (nw, members),
+        Some((Err(e), _)) | Some((_, Err(e))) => {
+            return error_response(StatusCode::BAD_GATEWAY, format!("Failed: {}", e));
+        }
+        None => return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured"),
+    };
+
+    match cache_not_loaded {
+        Some(ids) => not_loaded.extend(ids),
+        None => not_loaded.extend(fresh_members.iter().map(|m| m.display_id().to_string())),
+    }
+
+    let cache_age = match last_updated.and_then(|t| t.elapsed().ok()) {
+        Some(age) => format!("{}s ago", age.as_secs()),
+        None => "never".to_string(),
+    };
+
+    CtrlStateDiffPartial {
+        cache_age,
+        network_diff: diff::diff_network(cached_network.as_ref(), &fresh_network),
+        member_diffs: diff::diff_members(&cached_members, &fresh_members, &not_loaded),
+        cached_member_count: cached_members.len(),
+        fresh_member_count: fresh_members.len(),
+    }
+    .into_response()
+}
+
 // ---- Handlers: Network Actions ----
 
 pub async fn create_network(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
 ) -> Response {
-    // Only admins can create networks
-    if !permissions::is_admin(&user) {
-        return (StatusCode::FORBIDDEN, "Only administrators can create networks").into_response();
+    if !permissions::can_create_network(&user) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to create networks");
     }
 
     let zt = state.zt_state.read().await;
@@ -352,12 +865,28 @@ pub async fn create_network(
                 .save_rules_source(&nwid, DEFAULT_RULES_SOURCE)
                 .await;
 
+            // Record the creator as owner; non-admins need explicit permissions to see it at all
+            {
+                let mut config = state.config.write().await;
+                if let Some(ref mut c) = *config {
+                    c.network_owners.insert(nwid.clone(), user.id);
+                    if !user.is_admin {
+                        if let Some(u) = c.find_user_by_id_mut(user.id) {
+                            u.network_permissions.insert(nwid.clone(), NetworkPermissions::full());
+                        }
+                    }
+                    if let Err(e) = c.save() {
+                        tracing::error!("Failed to save owner of network {}: {}", nwid, e);
+                    }
+                }
+            }
+
             state.notify_poller();
-            Redirect::to(&format!("/controller/{}", nwid)).into_response()
+            Redirect::to(&base_path::url(&format!("/controller/{}", nwid))).into_response()
         }
         Some(Err(e)) => {
             drop(client);
-            (StatusCode::BAD_GATEWAY, format!("Failed to create: {}", e)).into_response()
+            error_response(StatusCode::BAD_GATEWAY, format!("Failed to create: {}", e))
         }
         None => {
             drop(client);
@@ -377,7 +906,7 @@ pub async fn delete_network(
 ) -> Response {
     // Check delete permission
     if !permissions::can_delete(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to delete this network").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to delete this network");
     }
 
     let client = state.zt_client.read().await;
@@ -389,13 +918,26 @@ pub async fn delete_network(
 
     match result {
         Some(Ok(_)) => {
+            {
+                let mut config = state.config.write().await;
+                if let Some(ref mut c) = *config {
+                    let owned = c.network_owners.remove(&nwid).is_some();
+                    let shares = c.share_links.len();
+                    c.share_links.retain(|l| l.nwid != nwid);
+                    let agents = c.agent_tokens.remove(&nwid).is_some();
+                    let reports = c.drop_agent_reports(&nwid);
+                    if c.ungroup_network(&nwid) || owned || agents || reports || c.share_links.len() != shares {
+                        let _ = c.save();
+                    }
+                }
+            }
             state.notify_poller();
             // Brief delay to let poller update cached state before redirect
             tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-            Redirect::to("/").into_response()
+            Redirect::to(&base_path::url("/")).into_response()
         }
         Some(Err(e)) => {
-            (StatusCode::BAD_GATEWAY, format!("Failed to delete: {}", e)).into_response()
+            error_response(StatusCode::BAD_GATEWAY, format!("Failed to delete: {}", e))
         }
         None => (
             StatusCode::SERVICE_UNAVAILABLE,
@@ -412,8 +954,15 @@ pub struct UpdateSettingsForm {
     pub name: Option<String>,
     pub description: Option<String>,
     pub private: Option<String>,
+    pub mtu: Option<String>,
+    /// The network revision the page showed
+    pub revision: Option<u64>,
 }
 
+/// Valid MTU range accepted by the ZeroTier controller
+const MTU_MIN: u32 = 1280;
+const MTU_MAX: u32 = 10000;
+
 pub async fn update_settings(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
@@ -422,35 +971,48 @@ pub async fn update_settings(
 ) -> Response {
     // Check modify permission
     if !permissions::can_modify(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to modify this network").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
     }
 
-    // Save description locally
-    let description = form.description.as_deref().unwrap_or("").trim().to_string();
-    if let Err(e) = state.save_network_description(&nwid, &description).await {
-        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save description: {}", e))
-            .into_response();
-    }
+    // Validate MTU (empty means leave unchanged)
+    let mtu = match form.mtu.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+        Some(raw) => match raw.parse::<u32>() {
+            Ok(m) if (MTU_MIN..=MTU_MAX).contains(&m) => Some(m),
+            _ => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("MTU must be between {} and {}", MTU_MIN, MTU_MAX),
+                )
+                    .into_response();
+            }
+        },
+        None => None,
+    };
 
-    let body = serde_json::json!({
+    let mut body = serde_json::json!({
         "name": form.name.unwrap_or_default(),
         "private": form.private.is_some(),
     });
+    if let Some(mtu) = mtu {
+        body["mtu"] = serde_json::json!(mtu);
+    }
 
-    let client = state.zt_client.read().await;
-    let result = match client.as_ref() {
-        Some(c) => Some(c.update_controller_network(&nwid, body).await),
-        None => None,
-    };
-    drop(client);
+    let result = edit_network(&state, &user, &nwid, form.revision, "Update network settings", move |_| {
+        Ok(body.clone())
+    })
+    .await;
 
-    match result {
-        Some(Ok(network)) => {
-            state.notify_poller();
-            CtrlNetworkSettingsPartial { network, description, can_modify: true }.into_response()
+    // Save description locally
+    let description = form.description.as_deref().unwrap_or("").trim().to_string();
+    if edit_kept(&result) {
+        if let Err(e) = state.save_network_description(&nwid, &description).await {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save description: {}", e));
         }
-        Some(Err(e)) => (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
-        None => (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
+    }
+
+    match result {
+        Ok(network) => CtrlNetworkSettingsPartial { network, description, can_modify: true }.into_response(),
+        Err(response) => response,
     }
 }
 
@@ -460,6 +1022,8 @@ pub async fn update_settings(
 pub struct UpdateBroadcastForm {
     pub enable_broadcast: Option<String>,
     pub multicast_limit: Option<u32>,
+    /// The network revision the page showed
+    pub revision: Option<u64>,
 }
 
 pub async fn update_broadcast_settings(
@@ -469,7 +1033,7 @@ pub async fn update_broadcast_settings(
     Form(form): Form<UpdateBroadcastForm>,
 ) -> Response {
     if !permissions::can_modify(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to modify this network").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
     }
 
     let body = serde_json::json!({
@@ -477,29 +1041,71 @@ pub async fn update_broadcast_settings(
         "multicastLimit": form.multicast_limit.unwrap_or(32),
     });
 
-    let client = state.zt_client.read().await;
-    let result = match client.as_ref() {
-        Some(c) => Some(c.update_controller_network(&nwid, body).await),
-        None => None,
-    };
-    drop(client);
+    match edit_network(&state, &user, &nwid, form.revision, "Update multicast settings", move |_| {
+        Ok(body.clone())
+    })
+    .await
+    {
+        Ok(network) => ip_pools_partial(nwid, network),
+        Err(response) => response,
+    }
+}
 
-    match result {
-        Some(Ok(network)) => {
-            state.notify_poller();
-            let pools = network.ip_assignment_pools.clone();
-            let routes = network.routes.clone();
-            CtrlIpPoolsPartial {
-                nwid,
-                network,
-                pools,
-                routes,
-                can_modify: true,
-            }
-            .into_response()
+// ---- Handlers: SSO Settings ----
+
+#[derive(Deserialize)]
+pub struct UpdateSsoForm {
+    pub sso_enabled: Option<String>,
+    #[serde(default)]
+    pub client_id: String,
+    #[serde(default)]
+    pub authorization_endpoint: String,
+    #[serde(default)]
+    pub provider: String,
+    /// The network revision the page showed
+    pub revision: Option<u64>,
+}
+
+pub async fn update_sso_settings(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+    Form(form): Form<UpdateSsoForm>,
+) -> Response {
+    if !permissions::can_modify(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
+    }
+
+    let client_id = form.client_id.trim();
+    let authorization_endpoint = form.authorization_endpoint.trim();
+    let provider = form.provider.trim();
+    if form.sso_enabled.is_some() {
+        if client_id.is_empty() {
+            return error_response(StatusCode::BAD_REQUEST, "Client ID is required to enable SSO");
+        }
+        if !(authorization_endpoint.starts_with("https://") || authorization_endpoint.starts_with("http://")) {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Authorization endpoint must be an http:// or https:// URL",
+            )
+                .into_response();
         }
-        Some(Err(e)) => (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
-        None => (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
+    }
+
+    let body = serde_json::json!({
+        "ssoEnabled": form.sso_enabled.is_some(),
+        "clientId": client_id,
+        "authorizationEndpoint": authorization_endpoint,
+        "provider": if provider.is_empty() { "default" } else { provider },
+    });
+
+    match edit_network(&state, &user, &nwid, form.revision, "Update single sign-on settings", move |_| {
+        Ok(body.clone())
+    })
+    .await
+    {
+        Ok(network) => CtrlSsoSettingsPartial { network, can_modify: true }.into_response(),
+        Err(response) => response,
     }
 }
 
@@ -511,6 +1117,8 @@ pub struct UpdateAssignModesForm {
     pub v6_rfc4193: Option<String>,
     pub v6_sixplane: Option<String>,
     pub v6_auto_assign: Option<String>,
+    /// The network revision the page showed
+    pub revision: Option<u64>,
 }
 
 pub async fn update_assign_modes(
@@ -520,7 +1128,7 @@ pub async fn update_assign_modes(
     Form(form): Form<UpdateAssignModesForm>,
 ) -> Response {
     if !permissions::can_modify(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to modify this network").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
     }
 
     let body = serde_json::json!({
@@ -532,38 +1140,26 @@ pub async fn update_assign_modes(
         },
     });
 
-    let client = state.zt_client.read().await;
-    let result = match client.as_ref() {
-        Some(c) => Some(c.update_controller_network(&nwid, body).await),
-        None => None,
-    };
-    drop(client);
-
-    match result {
-        Some(Ok(network)) => {
-            state.notify_poller();
-            let pools = network.ip_assignment_pools.clone();
-            let routes = network.routes.clone();
-            CtrlIpPoolsPartial {
-                nwid,
-                network,
-                pools,
-                routes,
-                can_modify: true,
-            }
-            .into_response()
-        }
-        Some(Err(e)) => (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
-        None => (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
-    }
-}
+    match edit_network(&state, &user, &nwid, form.revision, "Update address assignment", move |_| {
+        Ok(body.clone())
+    })
+    .await
+    {
+        Ok(network) => ip_pools_partial(nwid, network),
+        Err(response) => response,
+    }
+}
 
 // ---- Handlers: IP Pools ----
 
 #[derive(Deserialize)]
 pub struct AddPoolForm {
     pub range_start: String,
+    /// Left out when `range_start` is a prefix
+    #[serde(default)]
     pub range_end: String,
+    /// The network revision the page showed
+    pub revision: Option<u64>,
 }
 
 pub async fn add_pool(
@@ -573,55 +1169,52 @@ pub async fn add_pool(
     Form(form): Form<AddPoolForm>,
 ) -> Response {
     if !permissions::can_modify(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to modify this network").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
     }
 
-    let client = state.zt_client.read().await;
-    let client_ref = match client.as_ref() {
-        Some(c) => c.clone(),
-        None => return (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
-    };
-    drop(client);
-
-    let current = match client_ref.get_controller_network(&nwid).await {
-        Ok(n) => n,
-        Err(e) => return (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
+    let pool = match IpAssignmentPool::parse(&form.range_start, &form.range_end) {
+        Ok(p) => p,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
     };
-
-    let mut pools: Vec<serde_json::Value> = current
-        .ip_assignment_pools
-        .iter()
-        .map(|p| {
-            serde_json::json!({"ipRangeStart": p.ip_range_start, "ipRangeEnd": p.ip_range_end})
-        })
-        .collect();
-    pools.push(serde_json::json!({
-        "ipRangeStart": form.range_start.trim(),
-        "ipRangeEnd": form.range_end.trim(),
-    }));
-
-    let body = serde_json::json!({"ipAssignmentPools": pools});
-    match client_ref.update_controller_network(&nwid, body).await {
-        Ok(network) => {
-            state.notify_poller();
-            let pools = network.ip_assignment_pools.clone();
-            let routes = network.routes.clone();
-            CtrlIpPoolsPartial {
-                nwid,
-                network,
-                pools,
-                routes,
-                can_modify: true,
-            }
-            .into_response()
+    let description = format!("Add IP pool {} - {}", pool.display_start(), pool.display_end());
+    let result = edit_network(&state, &user, &nwid, form.revision, description, move |current| {
+        let (family, auto_assign) = if pool.is_ipv6() {
+            ("IPv6", current.v6_zt_auto_assign())
+        } else {
+            ("IPv4", current.v4_auto_assign())
+        };
+        if !auto_assign {
+            return Err(format!("Turn on {} Auto-Assign from Range before adding {} pools", family, family));
         }
-        Err(e) => (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
+        if current.ip_assignment_pools.contains(&pool) {
+            return Err(format!("The pool {} - {} already exists", pool.display_start(), pool.display_end()));
+        }
+        let mut pools: Vec<serde_json::Value> = current
+            .ip_assignment_pools
+            .iter()
+            .map(|p| {
+                serde_json::json!({"ipRangeStart": p.ip_range_start, "ipRangeEnd": p.ip_range_end})
+            })
+            .collect();
+        pools.push(serde_json::json!({
+            "ipRangeStart": pool.ip_range_start,
+            "ipRangeEnd": pool.ip_range_end,
+        }));
+        Ok(serde_json::json!({"ipAssignmentPools": pools}))
+    })
+    .await;
+
+    match result {
+        Ok(network) => ip_pools_partial(nwid, network),
+        Err(response) => response,
     }
 }
 
 #[derive(Deserialize)]
 pub struct RemovePoolForm {
     pub index: usize,
+    /// The network revision the page showed
+    pub revision: Option<u64>,
 }
 
 pub async fn remove_pool(
@@ -631,47 +1224,27 @@ pub async fn remove_pool(
     Form(form): Form<RemovePoolForm>,
 ) -> Response {
     if !permissions::can_modify(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to modify this network").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
     }
 
-    let client = state.zt_client.read().await;
-    let client_ref = match client.as_ref() {
-        Some(c) => c.clone(),
-        None => return (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
-    };
-    drop(client);
-
-    let current = match client_ref.get_controller_network(&nwid).await {
-        Ok(n) => n,
-        Err(e) => return (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
-    };
-
-    let pools: Vec<serde_json::Value> = current
-        .ip_assignment_pools
-        .iter()
-        .enumerate()
-        .filter(|(i, _)| *i != form.index)
-        .map(|(_, p)| {
-            serde_json::json!({"ipRangeStart": p.ip_range_start, "ipRangeEnd": p.ip_range_end})
-        })
-        .collect();
+    let index = form.index;
+    let result = edit_network(&state, &user, &nwid, form.revision, "Remove IP pool", move |current| {
+        let pools: Vec<serde_json::Value> = current
+            .ip_assignment_pools
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .map(|(_, p)| {
+                serde_json::json!({"ipRangeStart": p.ip_range_start, "ipRangeEnd": p.ip_range_end})
+            })
+            .collect();
+        Ok(serde_json::json!({"ipAssignmentPools": pools}))
+    })
+    .await;
 
-    let body = serde_json::json!({"ipAssignmentPools": pools});
-    match client_ref.update_controller_network(&nwid, body).await {
-        Ok(network) => {
-            state.notify_poller();
-            let pools = network.ip_assignment_pools.clone();
-            let routes = network.routes.clone();
-            CtrlIpPoolsPartial {
-                nwid,
-                network,
-                pools,
-                routes,
-                can_modify: true,
-            }
-            .into_response()
-        }
-        Err(e) => (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
+    match result {
+        Ok(network) => ip_pools_partial(nwid, network),
+        Err(response) => response,
     }
 }
 
@@ -681,6 +1254,8 @@ pub async fn remove_pool(
 pub struct AddRouteForm {
     pub target: String,
     pub via: Option<String>,
+    /// The network revision the page showed
+    pub revision: Option<u64>,
 }
 
 pub async fn add_route(
@@ -690,55 +1265,38 @@ pub async fn add_route(
     Form(form): Form<AddRouteForm>,
 ) -> Response {
     if !permissions::can_modify(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to modify this network").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
     }
 
-    let client = state.zt_client.read().await;
-    let client_ref = match client.as_ref() {
-        Some(c) => c.clone(),
-        None => return (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
-    };
-    drop(client);
-
-    let current = match client_ref.get_controller_network(&nwid).await {
-        Ok(n) => n,
-        Err(e) => return (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
-    };
-
-    let mut routes: Vec<serde_json::Value> = current
-        .routes
-        .iter()
-        .map(|r| serde_json::json!({"target": r.target, "via": r.via}))
-        .collect();
+    let target = form.target.trim().to_string();
     let via = form
         .via
         .as_deref()
         .filter(|s| !s.trim().is_empty())
         .map(|s| s.trim().to_string());
-    routes.push(serde_json::json!({"target": form.target.trim(), "via": via}));
+    let description = format!("Add route {}", target);
+    let result = edit_network(&state, &user, &nwid, form.revision, description, move |current| {
+        let mut routes: Vec<serde_json::Value> = current
+            .routes
+            .iter()
+            .map(|r| serde_json::json!({"target": r.target, "via": r.via}))
+            .collect();
+        routes.push(serde_json::json!({"target": target, "via": via}));
+        Ok(serde_json::json!({"routes": routes}))
+    })
+    .await;
 
-    let body = serde_json::json!({"routes": routes});
-    match client_ref.update_controller_network(&nwid, body).await {
-        Ok(network) => {
-            state.notify_poller();
-            let pools = network.ip_assignment_pools.clone();
-            let routes = network.routes.clone();
-            CtrlIpPoolsPartial {
-                nwid,
-                network,
-                pools,
-                routes,
-                can_modify: true,
-            }
-            .into_response()
-        }
-        Err(e) => (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
+    match result {
+        Ok(network) => ip_pools_partial(nwid, network),
+        Err(response) => response,
     }
 }
 
 #[derive(Deserialize)]
 pub struct RemoveRouteForm {
     pub index: usize,
+    /// The network revision the page showed
+    pub revision: Option<u64>,
 }
 
 pub async fn remove_route(
@@ -748,54 +1306,39 @@ pub async fn remove_route(
     Form(form): Form<RemoveRouteForm>,
 ) -> Response {
     if !permissions::can_modify(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to modify this network").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
     }
 
-    let client = state.zt_client.read().await;
-    let client_ref = match client.as_ref() {
-        Some(c) => c.clone(),
-        None => return (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
-    };
-    drop(client);
-
-    let current = match client_ref.get_controller_network(&nwid).await {
-        Ok(n) => n,
-        Err(e) => return (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
-    };
-
-    let routes: Vec<serde_json::Value> = current
-        .routes
-        .iter()
-        .enumerate()
-        .filter(|(i, _)| *i != form.index)
-        .map(|(_, r)| serde_json::json!({"target": r.target, "via": r.via}))
-        .collect();
+    let index = form.index;
+    let result = edit_network(&state, &user, &nwid, form.revision, "Remove route", move |current| {
+        let routes: Vec<serde_json::Value> = current
+            .routes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .map(|(_, r)| serde_json::json!({"target": r.target, "via": r.via}))
+            .collect();
+        Ok(serde_json::json!({"routes": routes}))
+    })
+    .await;
 
-    let body = serde_json::json!({"routes": routes});
-    match client_ref.update_controller_network(&nwid, body).await {
-        Ok(network) => {
-            state.notify_poller();
-            let pools = network.ip_assignment_pools.clone();
-            let routes = network.routes.clone();
-            CtrlIpPoolsPartial {
-                nwid,
-                network,
-                pools,
-                routes,
-                can_modify: true,
-            }
-            .into_response()
-        }
-        Err(e) => (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
+    match result {
+        Ok(network) => ip_pools_partial(nwid, network),
+        Err(response) => response,
     }
 }
 
 // ---- Handlers: DNS ----
 
+/// ZT_MAX_DNS_SERVERS in the ZeroTier core
+const DNS_MAX_SERVERS: usize = 4;
+
 #[derive(Deserialize)]
 pub struct AddDnsForm {
     pub domain: Option<String>,
     pub server: String,
+    /// The network revision the page showed
+    pub revision: Option<u64>,
 }
 
 pub async fn add_dns(
@@ -805,62 +1348,40 @@ pub async fn add_dns(
     Form(form): Form<AddDnsForm>,
 ) -> Response {
     if !permissions::can_modify(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to modify this network").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
     }
 
-    let client = state.zt_client.read().await;
-    let client_ref = match client.as_ref() {
-        Some(c) => c.clone(),
-        None => return (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
-    };
-    drop(client);
-
-    let current = match client_ref.get_controller_network(&nwid).await {
-        Ok(n) => n,
-        Err(e) => return (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
-    };
-
-    let mut servers = current.dns.servers.clone();
     let server = form.server.trim().to_string();
-    if !server.is_empty() && !servers.contains(&server) {
-        servers.push(server);
-    }
-
-    let domain = form.domain
-        .as_ref()
-        .map(|d| d.trim())
-        .filter(|d| !d.is_empty())
-        .unwrap_or(&current.dns.domain)
-        .to_string();
-
-    let body = serde_json::json!({
-        "dns": {
-            "domain": domain,
-            "servers": servers,
-        }
-    });
-
-    match client_ref.update_controller_network(&nwid, body).await {
-        Ok(network) => {
-            state.notify_poller();
-            let pools = network.ip_assignment_pools.clone();
-            let routes = network.routes.clone();
-            CtrlIpPoolsPartial {
-                nwid,
-                network,
-                pools,
-                routes,
-                can_modify: true,
+    let domain = form.domain.map(|d| d.trim().to_string()).filter(|d| !d.is_empty());
+    let description = format!("Add DNS server {}", server);
+    let result = edit_network(&state, &user, &nwid, form.revision, description, move |current| {
+        let mut servers = current.dns.servers.clone();
+        if !server.is_empty() && !servers.contains(&server) {
+            if servers.len() >= DNS_MAX_SERVERS {
+                return Err(format!("ZeroTier supports at most {} DNS servers", DNS_MAX_SERVERS));
             }
-            .into_response()
+            servers.push(server.clone());
         }
-        Err(e) => (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
+        Ok(serde_json::json!({
+            "dns": {
+                "domain": domain.as_ref().unwrap_or(&current.dns.domain),
+                "servers": servers,
+            }
+        }))
+    })
+    .await;
+
+    match result {
+        Ok(network) => ip_pools_partial(nwid, network),
+        Err(response) => response,
     }
 }
 
 #[derive(Deserialize)]
 pub struct RemoveDnsForm {
     pub index: usize,
+    /// The network revision the page showed
+    pub revision: Option<u64>,
 }
 
 pub async fn remove_dns(
@@ -870,59 +1391,95 @@ pub async fn remove_dns(
     Form(form): Form<RemoveDnsForm>,
 ) -> Response {
     if !permissions::can_modify(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to modify this network").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
     }
 
-    let client = state.zt_client.read().await;
-    let client_ref = match client.as_ref() {
-        Some(c) => c.clone(),
-        None => return (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
-    };
-    drop(client);
+    let index = form.index;
+    let result = edit_network(&state, &user, &nwid, form.revision, "Remove DNS server", move |current| {
+        let servers: Vec<String> = current
+            .dns
+            .servers
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .map(|(_, s)| s.clone())
+            .collect();
+        Ok(serde_json::json!({
+            "dns": {
+                "domain": current.dns.domain,
+                "servers": servers,
+            }
+        }))
+    })
+    .await;
 
-    let current = match client_ref.get_controller_network(&nwid).await {
-        Ok(n) => n,
-        Err(e) => return (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
-    };
+    match result {
+        Ok(network) => ip_pools_partial(nwid, network),
+        Err(response) => response,
+    }
+}
 
-    let servers: Vec<String> = current
-        .dns
-        .servers
-        .iter()
-        .enumerate()
-        .filter(|(i, _)| *i != form.index)
-        .map(|(_, s)| s.clone())
-        .collect();
+#[derive(Deserialize)]
+pub struct SetDnsDomainForm {
+    pub domain: String,
+    /// The network revision the page showed
+    pub revision: Option<u64>,
+}
 
-    // Clear domain if no servers left
-    let domain = if servers.is_empty() {
-        String::new()
-    } else {
-        current.dns.domain.clone()
-    };
+/// Set the network's DNS search domain, keeping the current servers. The controller
+/// pushes a single search domain per network (ZT_VirtualNetworkDNS has one `domain`),
+/// so this replaces rather than appends.
+pub async fn set_dns_domain(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+    Form(form): Form<SetDnsDomainForm>,
+) -> Response {
+    if !permissions::can_modify(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
+    }
 
-    let body = serde_json::json!({
-        "dns": {
-            "domain": domain,
-            "servers": servers,
-        }
-    });
+    let domain = form.domain.trim().trim_end_matches('.').to_string();
+    if domain.is_empty() || domain.contains(char::is_whitespace) {
+        return error_response(StatusCode::BAD_REQUEST, "Enter a valid search domain");
+    }
+    let description = format!("Set DNS search domain {}", domain);
+    update_dns_domain(state, user, nwid, domain, form.revision, description).await
+}
 
-    match client_ref.update_controller_network(&nwid, body).await {
-        Ok(network) => {
-            state.notify_poller();
-            let pools = network.ip_assignment_pools.clone();
-            let routes = network.routes.clone();
-            CtrlIpPoolsPartial {
-                nwid,
-                network,
-                pools,
-                routes,
-                can_modify: true,
+/// Clear the network's DNS search domain, keeping the current servers
+pub async fn remove_dns_domain(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+) -> Response {
+    if !permissions::can_modify(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
+    }
+    update_dns_domain(state, user, nwid, String::new(), None, "Clear DNS search domain".to_string()).await
+}
+
+async fn update_dns_domain(
+    state: AppState,
+    user: User,
+    nwid: String,
+    domain: String,
+    revision: Option<u64>,
+    description: String,
+) -> Response {
+    let result = edit_network(&state, &user, &nwid, revision, description, move |current| {
+        Ok(serde_json::json!({
+            "dns": {
+                "domain": domain,
+                "servers": current.dns.servers,
             }
-            .into_response()
-        }
-        Err(e) => (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
+        }))
+    })
+    .await;
+
+    match result {
+        Ok(network) => ip_pools_partial(nwid, network),
+        Err(response) => response,
     }
 }
 
@@ -934,24 +1491,24 @@ pub async fn toggle_member_auth(
     Path((nwid, member_id)): Path<(String, String)>,
 ) -> Response {
     if !permissions::can_authorize(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to authorize members").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to authorize members");
     }
 
     let client = state.zt_client.read().await;
     let client_ref = match client.as_ref() {
         Some(c) => c.clone(),
-        None => return (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
+        None => return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured"),
     };
     drop(client);
 
     let current = match client_ref.get_controller_member(&nwid, &member_id).await {
         Ok(m) => m,
-        Err(e) => return (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
+        Err(e) => return error_response(StatusCode::BAD_GATEWAY, format!("Failed: {}", e)),
     };
 
     let network = match client_ref.get_controller_network(&nwid).await {
         Ok(n) => n,
-        Err(e) => return (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
+        Err(e) => return error_response(StatusCode::BAD_GATEWAY, format!("Failed: {}", e)),
     };
 
     let new_auth = !current.is_authorized();
@@ -961,18 +1518,15 @@ pub async fn toggle_member_auth(
         .await
     {
         Ok(member) => {
+            if !new_auth {
+                state.clear_guest_access(&nwid, &member_id, &user.username).await;
+            }
             state.notify_poller();
             let config = state.config.read().await;
-            let member_names = config
-                .as_ref()
-                .map(|c| c.member_names.clone())
-                .unwrap_or_default();
-            let member_descriptions = config
-                .as_ref()
-                .map(|c| c.member_descriptions.clone())
-                .unwrap_or_default();
+            let lookups = MemberLookups::new(config.as_ref(), &nwid);
             drop(config);
-            let rows = enrich_members(&[member], &member_names, &member_descriptions, &network);
+            let peers = state.zt_state.read().await.peers.clone();
+            let rows = enrich_members(&[member], &lookups, &peers, &network);
             CtrlMemberRowPartial {
                 nwid: nwid.clone(),
                 row: rows.into_iter().next().unwrap(),
@@ -981,7 +1535,7 @@ pub async fn toggle_member_auth(
             }
             .into_response()
         }
-        Err(e) => (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
+        Err(e) => error_response(StatusCode::BAD_GATEWAY, format!("Failed: {}", e)),
     }
 }
 
@@ -991,7 +1545,7 @@ pub async fn delete_member(
     Path((nwid, member_id)): Path<(String, String)>,
 ) -> Response {
     if !permissions::can_modify(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to remove members").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to remove members");
     }
 
     let client = state.zt_client.read().await;
@@ -1003,13 +1557,22 @@ pub async fn delete_member(
 
     match result {
         Some(Ok(_)) => {
+            state.clear_guest_access(&nwid, &member_id, &user.username).await;
+            {
+                let mut config = state.config.write().await;
+                if let Some(ref mut c) = *config {
+                    if c.drop_agent_report(&nwid, &member_id) {
+                        let _ = c.save();
+                    }
+                }
+            }
             state.notify_poller();
             (StatusCode::OK, "").into_response()
         }
         Some(Err(e)) => {
-            (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response()
+            error_response(StatusCode::BAD_GATEWAY, format!("Failed: {}", e))
         }
-        None => (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
+        None => error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured"),
     }
 }
 
@@ -1027,20 +1590,20 @@ pub async fn add_member(
     Form(form): Form<AddMemberForm>,
 ) -> Response {
     if !permissions::can_authorize(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to add members").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to add members");
     }
 
     let node_id = form.node_id.trim().to_lowercase();
 
     // Validate: 10 hex characters
     if node_id.len() != 10 || !node_id.chars().all(|c| c.is_ascii_hexdigit()) {
-        return (StatusCode::BAD_REQUEST, "Node ID must be 10 hex characters").into_response();
+        return error_response(StatusCode::BAD_REQUEST, "Node ID must be 10 hex characters");
     }
 
     let client = state.zt_client.read().await;
     let client_ref = match client.as_ref() {
         Some(c) => c.clone(),
-        None => return (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
+        None => return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured"),
     };
     drop(client);
 
@@ -1050,26 +1613,19 @@ pub async fn add_member(
         .update_controller_member(&nwid, &node_id, body)
         .await
     {
-        return (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response();
+        return error_response(StatusCode::BAD_GATEWAY, format!("Failed: {}", e));
     }
 
     state.notify_poller();
 
     // Fetch fresh member list (the newly added member won't be in poller cache yet)
     let config = state.config.read().await;
-    let member_names = config
-        .as_ref()
-        .map(|c| c.member_names.clone())
-        .unwrap_or_default();
-    let member_descriptions = config
-        .as_ref()
-        .map(|c| c.member_descriptions.clone())
-        .unwrap_or_default();
+    let lookups = MemberLookups::new(config.as_ref(), &nwid);
     drop(config);
 
     let network = match client_ref.get_controller_network(&nwid).await {
         Ok(n) => n,
-        Err(e) => return (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
+        Err(e) => return error_response(StatusCode::BAD_GATEWAY, format!("Failed: {}", e)),
     };
 
     let member_ids = client_ref.get_controller_members(&nwid).await;
@@ -1089,7 +1645,8 @@ pub async fn add_member(
 
     let member_count = fresh_members.len();
     let authorized_count = fresh_members.iter().filter(|m| m.is_authorized()).count();
-    let rows = enrich_members(&fresh_members, &member_names, &member_descriptions, &network);
+    let peers = state.zt_state.read().await.peers.clone();
+    let rows = enrich_members(&fresh_members, &lookups, &peers, &network);
     CtrlMemberListPartial {
         nwid: nwid.clone(),
         rows,
@@ -1109,24 +1666,24 @@ pub async fn member_modal(
     Path((nwid, member_id)): Path<(String, String)>,
 ) -> Response {
     if !permissions::can_read(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to view this network").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to view this network");
     }
 
     let client = state.zt_client.read().await;
     let client_ref = match client.as_ref() {
         Some(c) => c.clone(),
-        None => return (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
+        None => return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured"),
     };
     drop(client);
 
     let member = match client_ref.get_controller_member(&nwid, &member_id).await {
         Ok(m) => m,
-        Err(e) => return (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
+        Err(e) => return error_response(StatusCode::BAD_GATEWAY, format!("Failed: {}", e)),
     };
 
     let network = match client_ref.get_controller_network(&nwid).await {
         Ok(n) => n,
-        Err(e) => return (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
+        Err(e) => return error_response(StatusCode::BAD_GATEWAY, format!("Failed: {}", e)),
     };
 
     let config = state.config.read().await;
@@ -1138,11 +1695,61 @@ pub async fn member_modal(
         .as_ref()
         .and_then(|c| c.member_descriptions.get(&member_id).cloned())
         .unwrap_or_default();
+    let rules_source = config
+        .as_ref()
+        .and_then(|c| c.rules_source.get(&nwid).cloned());
+    let authorized_until = config
+        .as_ref()
+        .and_then(|c| c.guest_expirations(&nwid).remove(&member_id))
+        .map(crate::preferences::input_value)
+        .unwrap_or_default();
+    let monitored = config
+        .as_ref()
+        .is_some_and(|c| c.monitored_members.contains(&member_id));
+    let agent = config
+        .as_ref()
+        .and_then(|c| c.agent_report(&nwid, &member_id).cloned());
     drop(config);
 
+    let (peer_status, connection) = {
+        let zt = state.zt_state.read().await;
+        let peer = zt.peers.get(&member_id);
+        let status = match peer {
+            Some(peer) if peer.is_online() => "Currently online".to_string(),
+            Some(peer) if peer.last_receive().is_some() => format!("Offline, last seen {}", peer.display_last_seen()),
+            _ => "Not currently connected to the controller".to_string(),
+        };
+        let connection = peer.filter(|p| p.is_online()).map(|peer| PeerConnection {
+            relayed: peer.is_relayed(),
+            latency_ms: peer.latency_ms(),
+            paths: peer
+                .paths
+                .iter()
+                .filter(|path| path.is_live())
+                .filter_map(|path| {
+                    let address = path.address.clone()?;
+                    Some(MemberPath {
+                        location: geoip::path_ip(&address).and_then(geoip::lookup),
+                        address,
+                        preferred: path.preferred.unwrap_or(false),
+                        last_receive: path.display_last_receive(),
+                    })
+                })
+                .collect(),
+        });
+        (status, connection)
+    };
+
+    let ip_fix = {
+        let zt = state.zt_state.read().await;
+        let members = zt.controller_members.get(&nwid).map(Vec::as_slice).unwrap_or_default();
+        IpFix::plan(&network, &member, members)
+    };
     let rfc4193_addr = if network.v6_rfc4193() { member.rfc4193_address() } else { None };
     let sixplane_addr = if network.v6_sixplane() { member.sixplane_address() } else { None };
     let can_modify = permissions::can_modify(&user, &nwid);
+    let (tag_fields, capability_fields) =
+        member_rule_fields(&member, &network, rules_source.as_deref());
 
     CtrlMemberModalPartial {
         nwid,
@@ -1151,6 +1758,14 @@ pub async fn member_modal(
         description,
         rfc4193_addr,
         sixplane_addr,
+        tag_fields,
+        capability_fields,
+        authorized_until,
+        monitored,
+        peer_status,
+        agent,
+        connection,
+        ip_fix,
         can_modify,
     }
     .into_response()
@@ -1166,6 +1781,59 @@ pub struct UpdateMemberForm {
     pub active_bridge: Option<String>,
     pub no_auto_assign_ips: Option<String>,
     pub ip_assignments: Option<String>,
+    pub authorized_until: Option<String>,
+    pub monitored: Option<String>,
+    /// Set when the modal rendered the tag and capability controls
+    pub rule_fields: Option<String>,
+    /// The member revision the modal showed. A string, as numbers don't survive `flatten`.
+    pub revision: Option<String>,
+    // tag_<id>, tagflag_<id>_<mask>, cap_<id>
+    #[serde(flatten)]
+    pub fields: std::collections::HashMap<String, String>,
+}
+
+impl UpdateMemberForm {
+    /// Member tags as (id, value) pairs. A tag is set when it has an enum/numeric value
+    /// or any flag checked; flags are OR-ed onto the value.
+    fn tags(&self) -> Result<Vec<(u32, u32)>, String> {
+        let mut tags: std::collections::BTreeMap<u32, u32> = std::collections::BTreeMap::new();
+        for (key, value) in &self.fields {
+            if let Some(id) = key.strip_prefix("tag_") {
+                let value = value.trim();
+                if value.is_empty() {
+                    continue;
+                }
+                let id = id.parse::<u32>().map_err(|_| format!("Invalid tag id: {}", id))?;
+                let value = parse_u32(value).ok_or_else(|| format!("Invalid value for tag {}", id))?;
+                *tags.entry(id).or_default() |= value;
+            } else if let Some(rest) = key.strip_prefix("tagflag_") {
+                let (id, mask) = rest.split_once('_').unwrap_or((rest, ""));
+                let id = id.parse::<u32>().map_err(|_| format!("Invalid tag id: {}", id))?;
+                let mask = mask.parse::<u32>().map_err(|_| format!("Invalid flag for tag {}", id))?;
+                *tags.entry(id).or_default() |= mask;
+            }
+        }
+        Ok(tags.into_iter().collect())
+    }
+
+    fn capabilities(&self) -> Result<Vec<u32>, String> {
+        let mut caps: Vec<u32> = self
+            .fields
+            .keys()
+            .filter_map(|k| k.strip_prefix("cap_"))
+            .map(|id| id.parse::<u32>().map_err(|_| format!("Invalid capability id: {}", id)))
+            .collect::<Result<_, _>>()?;
+        caps.sort();
+        Ok(caps)
+    }
+}
+
+/// Parse a decimal or 0x-prefixed hex value
+fn parse_u32(value: &str) -> Option<u32> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
 }
 
 pub async fn update_member(
@@ -1175,75 +1843,348 @@ pub async fn update_member(
     Form(form): Form<UpdateMemberForm>,
 ) -> Response {
     if !permissions::can_modify(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to modify members").into_response();
-    }
-
-    // Save name locally
-    let name = form.name.as_deref().unwrap_or("").trim().to_string();
-    if let Err(e) = state.save_member_name(&member_id, &name).await {
-        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save name: {}", e))
-            .into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify members");
     }
 
-    // Save description locally
-    let description = form.description.as_deref().unwrap_or("").trim().to_string();
-    if let Err(e) = state.save_member_description(&member_id, &description).await {
-        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save description: {}", e))
-            .into_response();
-    }
+    // Guest access only applies while authorized; de-authorizing clears it
+    let authorized_until = match form.authorized_until.as_deref().map(crate::guest::parse_until) {
+        Some(Ok(until)) if form.authorized.is_some() => Some(until),
+        Some(Err(e)) if form.authorized.is_some() => return error_response(StatusCode::BAD_REQUEST, e),
+        _ if form.authorized.is_none() => Some(None),
+        _ => None,
+    };
 
     // Parse IP assignments: comma or newline separated
     let ip_list: Vec<String> = form
         .ip_assignments
         .as_deref()
         .unwrap_or("")
-        .split(|c: char| c == ',' || c == '\n')
+        .split([',', '\n'])
         .map(|s| s.trim().to_string())
         .filter(|s| !s.is_empty())
         .collect();
 
     // Update member via ZT API
-    let body = serde_json::json!({
+    let mut body = serde_json::json!({
         "authorized": form.authorized.is_some(),
         "activeBridge": form.active_bridge.is_some(),
         "noAutoAssignIps": form.no_auto_assign_ips.is_some(),
         "ipAssignments": ip_list,
     });
+    if form.rule_fields.is_some() {
+        let (tags, capabilities) = match (form.tags(), form.capabilities()) {
+            (Ok(t), Ok(c)) => (t, c),
+            (Err(e), _) | (_, Err(e)) => return error_response(StatusCode::BAD_REQUEST, e),
+        };
+        body["tags"] = serde_json::json!(tags);
+        body["capabilities"] = serde_json::json!(capabilities);
+    }
 
     let client = state.zt_client.read().await;
     let client_ref = match client.as_ref() {
         Some(c) => c.clone(),
-        None => return (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
+        None => return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured"),
     };
     drop(client);
 
-    match client_ref
-        .update_controller_member(&nwid, &member_id, body)
-        .await
-    {
-        Ok(_) => {
-            state.notify_poller();
-            // Return empty response with HX-Trigger to close modal and refresh
-            Response::builder()
-                .status(StatusCode::OK)
-                .header("HX-Trigger", "member-updated")
-                .body(axum::body::Body::empty())
-                .unwrap()
-                .into_response()
+    let seen = form.revision.as_deref().and_then(|r| r.parse::<u64>().ok());
+    let queued = match retry::apply_member(client_ref.as_ref(), &nwid, &member_id, seen, body.clone()).await {
+        Ok(_) => false,
+        Err(Failure::Unreachable(_)) => {
+            let description = format!("Update member {}", member_id);
+            let change = Change::Member { member_id: member_id.clone(), body };
+            retry::queue(&state, &nwid, change, seen, description, &user.username);
+            true
         }
-        Err(e) => (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
-    }
-}
+        Err(Failure::Conflict) => return conflict_response("member"),
+        Err(Failure::Invalid(e)) | Err(Failure::Failed(e)) => {
+            return error_response(StatusCode::BAD_GATEWAY, format!("Failed: {}", e))
+        }
+    };
 
-// ---- Handlers: SSE Partials ----
+    // Save name locally
+    let name = form.name.as_deref().unwrap_or("").trim().to_string();
+    if let Err(e) = state.save_member_name(&member_id, &name).await {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save name: {}", e));
+    }
 
-pub async fn ctrl_member_list_partial(
-    State(state): State<AppState>,
+    // Save description locally
+    let description = form.description.as_deref().unwrap_or("").trim().to_string();
+    if let Err(e) = state.save_member_description(&member_id, &description).await {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save description: {}", e));
+    }
+
+    if let Err(e) = state.save_member_monitored(&member_id, form.monitored.is_some()).await {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save monitoring: {}", e));
+    }
+
+    if let Some(until) = authorized_until {
+        let mut config = state.config.write().await;
+        if let Some(ref mut c) = *config {
+            if c.set_guest_access(&nwid, &member_id, until, &user.username) {
+                let _ = c.save();
+            }
+        }
+    }
+
+    if queued {
+        let mut response = queued_response(&format!("Update member {}", member_id));
+        response.headers_mut().insert("HX-Trigger", axum::http::HeaderValue::from_static("member-updated"));
+        return response;
+    }
+    state.notify_poller();
+    // Return empty response with HX-Trigger to close modal and refresh
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("HX-Trigger", "member-updated")
+        .body(axum::body::Body::empty())
+        .unwrap()
+        .into_response()
+}
+
+/// POST /controller/{nwid}/members/{member_id}/fix-ips - Drop the member's addresses that are
+/// outside the network's pools and routes, giving it a free pool address where that leaves a
+/// family without one, then show the modal again
+pub async fn fix_member_ips(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((nwid, member_id)): Path<(String, String)>,
+) -> Response {
+    if !permissions::can_modify(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify members");
+    }
+
+    let client = state.zt_client.read().await;
+    let client_ref = match client.as_ref() {
+        Some(c) => c.clone(),
+        None => return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured"),
+    };
+    drop(client);
+
+    let (member, network) = match (
+        client_ref.get_controller_member(&nwid, &member_id).await,
+        client_ref.get_controller_network(&nwid).await,
+    ) {
+        (Ok(m), Ok(n)) => (m, n),
+        (Err(e), _) | (_, Err(e)) => return error_response(StatusCode::BAD_GATEWAY, format!("Failed: {}", e)),
+    };
+    let fix = {
+        let zt = state.zt_state.read().await;
+        let members = zt.controller_members.get(&nwid).map(Vec::as_slice).unwrap_or_default();
+        IpFix::plan(&network, &member, members)
+    };
+    let Some(fix) = fix else {
+        return member_modal(State(state), Extension(user), Path((nwid, member_id))).await;
+    };
+
+    let body = serde_json::json!({ "ipAssignments": fix.fixed });
+    match retry::apply_member(client_ref.as_ref(), &nwid, &member_id, member.revision, body.clone()).await {
+        Ok(_) => {}
+        Err(Failure::Unreachable(_)) => {
+            let description = format!("Fix IP assignments of member {}", member_id);
+            let change = Change::Member { member_id: member_id.clone(), body };
+            retry::queue(&state, &nwid, change, member.revision, description.clone(), &user.username);
+            return queued_response(&description);
+        }
+        Err(Failure::Conflict) => return conflict_response("member"),
+        Err(Failure::Invalid(e)) | Err(Failure::Failed(e)) => {
+            return error_response(StatusCode::BAD_GATEWAY, format!("Failed: {}", e))
+        }
+    }
+    state.notify_poller();
+    member_modal(State(state), Extension(user), Path((nwid, member_id))).await
+}
+
+// ---- Handlers: Reassign Member IPs ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "controller/partials/reassign_ips_modal.html")]
+pub struct CtrlReassignIpsModal {
+    pub nwid: String,
+    pub candidates: Vec<ReassignCandidate>,
+    /// The families whose addresses are re-issued, such as "IPv4"
+    pub families: Vec<&'static str>,
+    /// Set once the reassignment ran
+    pub outcomes: Option<Vec<ReassignOutcome>>,
+}
+
+#[derive(Deserialize)]
+pub struct ReassignIpsForm {
+    // member_<id> for each selected member
+    #[serde(flatten)]
+    pub fields: HashMap<String, String>,
+}
+
+/// The network from ZeroTier and its members as last polled, with their names
+async fn reassign_context(
+    state: &AppState,
+    nwid: &str,
+) -> Result<(ControllerNetwork, Vec<ControllerMember>, HashMap<String, String>), Response> {
+    let client = state.zt_client.read().await.clone();
+    let Some(client) = client else {
+        return Err(error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured"));
+    };
+    let network = client
+        .get_controller_network(nwid)
+        .await
+        .map_err(|e| error_response(StatusCode::BAD_GATEWAY, format!("Failed: {}", e)))?;
+    let members = state
+        .zt_state
+        .read()
+        .await
+        .controller_members
+        .get(nwid)
+        .cloned()
+        .unwrap_or_default();
+    let names = state
+        .config
+        .read()
+        .await
+        .as_ref()
+        .map(|c| c.member_names.clone())
+        .unwrap_or_default();
+    Ok((network, members, names))
+}
+
+/// GET /controller/{nwid}/reassign-ips - Choose members whose addresses are re-issued from the pools
+pub async fn reassign_ips_modal(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+) -> Response {
+    if !permissions::can_modify(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify members");
+    }
+    let (network, members, names) = match reassign_context(&state, &nwid).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let candidates = members
+        .iter()
+        .map(|m| ReassignCandidate {
+            id: m.display_id().to_string(),
+            name: names.get(m.display_id()).cloned().unwrap_or_default(),
+            ips: m.ip_assignments.clone(),
+            stray_ips: network.stray_ips(m),
+            manual: m.no_auto_assign_ips,
+        })
+        .collect();
+    CtrlReassignIpsModal {
+        nwid,
+        candidates,
+        families: reissued_families(&network).into_iter().map(|(_, label)| label).collect(),
+        outcomes: None,
+    }
+    .into_response()
+}
+
+/// POST /controller/{nwid}/reassign-ips - Clear the selected members' managed addresses and issue
+/// new ones from the current pools
+pub async fn reassign_ips(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+    Form(form): Form<ReassignIpsForm>,
+) -> Response {
+    if !permissions::can_modify(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify members");
+    }
+    let (network, members, names) = match reassign_context(&state, &nwid).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let families = reissued_families(&network);
+    if families.is_empty() {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "Turn on auto-assign and add a pool before reassigning addresses",
+        );
+    }
+    let selected: Vec<&ControllerMember> = members
+        .iter()
+        .filter(|m| form.fields.contains_key(&format!("member_{}", m.display_id())))
+        .collect();
+    if selected.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "Select at least one member");
+    }
+    let Some(client) = state.zt_client.read().await.clone() else {
+        return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured");
+    };
+
+    // Every current address stays taken, the selected members' included: a member that is
+    // skipped or fails keeps its addresses, so none may be handed to another member meanwhile
+    let mut taken: HashSet<String> = members
+        .iter()
+        .flat_map(|m| m.ip_assignments.iter().cloned())
+        .collect();
+    let mut outcomes = Vec::new();
+    for cached in selected {
+        let member_id = cached.display_id().to_string();
+        let mut outcome = ReassignOutcome {
+            name: names.get(&member_id).cloned().unwrap_or_default(),
+            id: member_id.clone(),
+            ips: Vec::new(),
+            queued: false,
+            error: None,
+        };
+        let member = match client.get_controller_member(&nwid, &member_id).await {
+            Ok(m) => m,
+            Err(_) => cached.clone(),
+        };
+        taken.extend(member.ip_assignments.iter().cloned());
+        if member.no_auto_assign_ips {
+            outcome.error = Some("Auto-assign is turned off for this member".to_string());
+            outcomes.push(outcome);
+            continue;
+        }
+        let ips = match reissue_ips(&network, &member, &taken) {
+            Ok(ips) => ips,
+            Err(e) => {
+                outcome.error = Some(e);
+                outcomes.push(outcome);
+                continue;
+            }
+        };
+        let body = serde_json::json!({ "ipAssignments": ips });
+        match retry::apply_member(client.as_ref(), &nwid, &member_id, member.revision, body.clone()).await {
+            Ok(_) => {}
+            Err(Failure::Unreachable(_)) => {
+                let description = format!("Reassign IP addresses of member {}", member_id);
+                let change = Change::Member { member_id: member_id.clone(), body };
+                retry::queue(&state, &nwid, change, member.revision, description, &user.username);
+                outcome.queued = true;
+            }
+            Err(Failure::Conflict) => {
+                outcome.error = Some("Changed by someone else meanwhile, so it was left alone".to_string());
+            }
+            Err(Failure::Invalid(e)) | Err(Failure::Failed(e)) => outcome.error = Some(e),
+        }
+        if outcome.error.is_none() {
+            taken.extend(ips.iter().cloned());
+            outcome.ips = ips;
+        }
+        outcomes.push(outcome);
+    }
+    state.notify_poller();
+
+    CtrlReassignIpsModal {
+        nwid,
+        candidates: Vec::new(),
+        families: families.into_iter().map(|(_, label)| label).collect(),
+        outcomes: Some(outcomes),
+    }
+    .into_response()
+}
+
+// ---- Handlers: SSE Partials ----
+
+pub async fn ctrl_member_list_partial(
+    State(state): State<AppState>,
     Extension(user): Extension<User>,
     Path(nwid): Path<String>,
 ) -> Response {
     if !permissions::can_read(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to view this network").into_response();
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to view this network");
     }
 
     let zt = state.zt_state.read().await;
@@ -1261,19 +2202,13 @@ pub async fn ctrl_member_list_partial(
     drop(zt);
 
     let config = state.config.read().await;
-    let member_names = config
-        .as_ref()
-        .map(|c| c.member_names.clone())
-        .unwrap_or_default();
-    let member_descriptions = config
-        .as_ref()
-        .map(|c| c.member_descriptions.clone())
-        .unwrap_or_default();
+    let lookups = MemberLookups::new(config.as_ref(), &nwid);
     drop(config);
 
     let member_count = members.len();
     let authorized_count = members.iter().filter(|m| m.is_authorized()).count();
-    let rows = enrich_members(&members, &member_names, &member_descriptions, &network);
+    let peers = state.zt_state.read().await.peers.clone();
+    let rows = enrich_members(&members, &lookups, &peers, &network);
     CtrlMemberListPartial {
         nwid: nwid.clone(),
         rows,
@@ -1285,66 +2220,1318 @@ pub async fn ctrl_member_list_partial(
     }.into_response()
 }
 
-// ---- Handlers: Flow Rules ----
+/// GET /controller/partials/{nwid}/members/{member_id} - One member's row, swapped in when the
+/// event stream reports a change to that member
+pub async fn ctrl_member_row_partial(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((nwid, member_id)): Path<(String, String)>,
+) -> Response {
+    if !permissions::can_read(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to view this network");
+    }
 
-#[derive(Deserialize)]
-pub struct UpdateFlowRulesForm {
-    pub rules_source: String,
-    pub compiled_rules: String,
+    let zt = state.zt_state.read().await;
+    let network = zt
+        .controller_networks
+        .iter()
+        .find(|n| n.display_id() == nwid)
+        .cloned()
+        .unwrap_or_default();
+    let members = zt
+        .controller_members
+        .get(&nwid)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let member_count = members.len();
+    let authorized_count = members.iter().filter(|m| m.is_authorized()).count();
+    let member = members.iter().find(|m| m.display_id() == member_id).cloned();
+    drop(zt);
+
+    let row = match member {
+        Some(member) => {
+            let config = state.config.read().await;
+            let lookups = MemberLookups::new(config.as_ref(), &nwid);
+            drop(config);
+            let peers = state.zt_state.read().await.peers.clone();
+            enrich_members(&[member], &lookups, &peers, &network)
+                .into_iter()
+                .next()
+        }
+        None => None,
+    };
+    CtrlMemberRowUpdatePartial {
+        nwid: nwid.clone(),
+        row,
+        member_count,
+        authorized_count,
+        can_authorize: permissions::can_authorize(&user, &nwid),
+        can_modify: permissions::can_modify(&user, &nwid),
+    }
+    .into_response()
 }
 
-#[derive(Deserialize)]
-struct CompiledRules {
-    rules: Vec<serde_json::Value>,
-    #[serde(default)]
-    capabilities: Vec<serde_json::Value>,
+/// GET /controller/{nwid}/members/hosts - Authorized members as /etc/hosts lines.
+/// Members without a name use their node ID; names are qualified with the DNS domain when set.
+pub async fn export_members_hosts(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+) -> Response {
+    if !permissions::can_read(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to view this network");
+    }
+
+    let zt = state.zt_state.read().await;
+    let Some(network) = zt.controller_networks.iter().find(|n| n.display_id() == nwid).cloned() else {
+        return error_response(StatusCode::NOT_FOUND, "Network not found");
+    };
+    let mut members: Vec<ControllerMember> = zt
+        .controller_members
+        .get(&nwid)
+        .map(|m| m.iter().filter(|m| m.is_authorized()).cloned().collect())
+        .unwrap_or_default();
+    drop(zt);
+
+    let member_names = {
+        let config = state.config.read().await;
+        config.as_ref().map(|c| c.member_names.clone()).unwrap_or_default()
+    };
+
+    // Names and the domain become whole lines and fields of a hosts file, so only label
+    // characters are kept from them
+    let domain = network
+        .dns
+        .domain
+        .split('.')
+        .map(dns::to_label)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join(".");
+    let hostname = |m: &ControllerMember| {
+        member_names
+            .get(m.display_id())
+            .map(|n| dns::to_label(n))
+            .filter(|l| !l.is_empty())
+            .unwrap_or_else(|| m.display_id().to_string())
+    };
+    members.sort_by_key(|m| hostname(m));
+
+    let title: String = network.display_name().chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+    let mut out = format!(
+        "# {} ({}) - generated by TierDrop {}\n",
+        title,
+        nwid,
+        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    for m in &members {
+        let host = hostname(m);
+        let names = if domain.is_empty() {
+            host
+        } else {
+            format!("{}.{} {}", host, domain, host)
+        };
+        for addr in dns::member_addresses(&network, m) {
+            out.push_str(&format!("{}\t{}\n", addr, names));
+        }
+    }
+
+    (
+        [
+            (CONTENT_TYPE, "text/plain; charset=utf-8".to_string()),
+            (CONTENT_DISPOSITION, format!("attachment; filename=\"hosts-{}.txt\"", nwid)),
+        ],
+        out,
+    )
+        .into_response()
+}
+
+/// A CSV field, quoted when it needs to be. Text a spreadsheet would run as a formula is
+/// prefixed with `'`, since member names come from whoever can edit them.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// GET /controller/{nwid}/members/csv - Every member as a CSV row, with where the controller's
+/// node currently reaches it
+pub async fn export_members_csv(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+) -> Response {
+    if !permissions::can_read(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to view this network");
+    }
+
+    let zt = state.zt_state.read().await;
+    if !zt.controller_networks.iter().any(|n| n.display_id() == nwid) {
+        return error_response(StatusCode::NOT_FOUND, "Network not found");
+    }
+    let mut members: Vec<ControllerMember> = zt.controller_members.get(&nwid).cloned().unwrap_or_default();
+    let peers = zt.peers.clone();
+    drop(zt);
+    members.sort_by(|a, b| a.display_id().cmp(b.display_id()));
+
+    let (member_names, member_descriptions) = {
+        let config = state.config.read().await;
+        config
+            .as_ref()
+            .map(|c| (c.member_names.clone(), c.member_descriptions.clone()))
+            .unwrap_or_default()
+    };
+
+    let mut out = String::from("node_id,name,description,authorized,ip_assignments,version,online,physical_address\r\n");
+    for m in &members {
+        let id = m.display_id();
+        let peer = peers.get(id);
+        let fields = [
+            id.to_string(),
+            member_names.get(id).cloned().unwrap_or_default(),
+            member_descriptions.get(id).cloned().unwrap_or_default(),
+            m.is_authorized().to_string(),
+            m.ip_assignments.join(" "),
+            m.display_version(),
+            peer.is_some_and(|p| p.is_online()).to_string(),
+            peer.and_then(|p| p.physical_address()).unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        out.push_str(&row.join(","));
+        out.push_str("\r\n");
+    }
+
+    (
+        [
+            (CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (CONTENT_DISPOSITION, format!("attachment; filename=\"members-{}.csv\"", nwid)),
+        ],
+        out,
+    )
+        .into_response()
+}
+
+/// Format tag of a single-network export file
+pub const NETWORK_EXPORT_FORMAT: &str = "tierdrop-network";
+
+/// One network's controller records plus what TierDrop keeps about it
+#[derive(Serialize, Deserialize)]
+pub struct NetworkExport {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: chrono::DateTime<chrono::Utc>,
+    pub tierdrop_version: String,
+    /// The controller.d network object, as the controller API returns it
+    pub network: serde_json::Value,
+    /// Member objects, ordered by address
+    pub members: Vec<serde_json::Value>,
     #[serde(default)]
-    tags: Vec<serde_json::Value>,
+    pub tierdrop: NetworkMetadata,
 }
 
-pub async fn update_flow_rules(
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkMetadata {
+    pub description: Option<String>,
+    pub rules_source: Option<String>,
+    pub rule_definitions: Option<rules::RuleDefinitions>,
+    pub member_names: std::collections::BTreeMap<String, String>,
+    pub member_descriptions: std::collections::BTreeMap<String, String>,
+    pub monitored_members: Vec<String>,
+}
+
+/// GET /controller/{nwid}/export - The network's controller JSON, members and TierDrop metadata
+/// as one file, for moving a single network to another controller.
+pub async fn export_network(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
     Path(nwid): Path<String>,
-    Form(form): Form<UpdateFlowRulesForm>,
 ) -> Response {
-    if !permissions::can_modify(&user, &nwid) {
-        return (StatusCode::FORBIDDEN, "You don't have permission to modify this network").into_response();
+    if !permissions::can_read(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to view this network");
+    }
+    let known = state.zt_state.read().await.controller_networks.iter().any(|n| n.display_id() == nwid);
+    if !known {
+        return error_response(StatusCode::NOT_FOUND, "Network not found");
     }
 
-    // Parse the compiled rules JSON from the hidden field
-    let compiled: CompiledRules = match serde_json::from_str(&form.compiled_rules) {
-        Ok(r) => r,
-        Err(e) => {
-            return (StatusCode::BAD_REQUEST, format!("Invalid compiled rules: {}", e)).into_response();
+    let (network, ids, members) = {
+        let client = state.zt_client.read().await;
+        let Some(c) = client.as_ref() else {
+            return error_response(StatusCode::SERVICE_UNAVAILABLE, "ZeroTier client not initialized");
+        };
+        let network = match c.get_controller_network_json(&nwid).await {
+            Ok(n) => n,
+            Err(e) => return error_response(StatusCode::BAD_GATEWAY, e),
+        };
+        let mut ids: Vec<String> = match c.get_controller_members(&nwid).await {
+            Ok(ids) => ids.into_keys().collect(),
+            Err(e) => return error_response(StatusCode::BAD_GATEWAY, e),
+        };
+        ids.sort();
+        let mut members = Vec::with_capacity(ids.len());
+        for id in &ids {
+            // A partial export would silently lose members on import
+            match c.get_controller_member_json(&nwid, id).await {
+                Ok(m) => members.push(m),
+                Err(e) => return error_response(StatusCode::BAD_GATEWAY, format!("Member {}: {}", id, e)),
+            }
         }
+        (network, ids, members)
     };
 
-    // Build the body with rules, capabilities, and tags
-    let body = serde_json::json!({
-        "rules": compiled.rules,
-        "capabilities": compiled.capabilities,
-        "tags": compiled.tags,
-    });
+    let tierdrop = {
+        let config = state.config.read().await;
+        let Some(c) = config.as_ref() else {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "No configuration found");
+        };
+        let pick = |map: &std::collections::HashMap<String, String>| -> std::collections::BTreeMap<String, String> {
+            ids.iter().filter_map(|id| map.get(id).map(|v| (id.clone(), v.clone()))).collect()
+        };
+        NetworkMetadata {
+            description: c.network_descriptions.get(&nwid).cloned(),
+            rules_source: c.rules_source.get(&nwid).cloned(),
+            rule_definitions: c.rule_definitions.get(&nwid).cloned(),
+            member_names: pick(&c.member_names),
+            member_descriptions: pick(&c.member_descriptions),
+            monitored_members: ids.iter().filter(|id| c.monitored_members.contains(*id)).cloned().collect(),
+        }
+    };
 
-    let client = state.zt_client.read().await;
-    let result = match client.as_ref() {
-        Some(c) => Some(c.update_controller_network(&nwid, body).await),
-        None => None,
+    let now = chrono::Utc::now();
+    let export = NetworkExport {
+        format: NETWORK_EXPORT_FORMAT.to_string(),
+        version: 1,
+        exported_at: now,
+        tierdrop_version: crate::VERSION.to_string(),
+        network,
+        members,
+        tierdrop,
+    };
+    let body = match serde_json::to_string_pretty(&export) {
+        Ok(b) => b,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize export: {}", e)),
     };
-    drop(client);
 
-    match result {
-        Some(Ok(network)) => {
-            state.notify_poller();
-            // Save the DSL source locally (ZT API only stores compiled JSON)
-            if let Err(e) = state.save_rules_source(&nwid, &form.rules_source).await {
-                tracing::warn!("Failed to save rules source: {}", e);
-            }
-            let rules_source = form.rules_source;
-            CtrlFlowRulesPartial { nwid, network, rules_source, can_modify: true }.into_response()
+    (
+        [
+            (CONTENT_TYPE, "application/json".to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"tierdrop-network-{}-{}.json\"", nwid, now.format("%Y%m%d-%H%M%S")),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+// ---- Handlers: Invites ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "controller/partials/invites.html")]
+pub struct CtrlInvitesPartial {
+    pub nwid: String,
+    pub invites: Vec<Invite>,
+    /// Pending join requests submitted through invite links
+    pub join_requests: Vec<JoinRequest>,
+    pub error: Option<String>,
+}
+
+async fn render_invites(state: &AppState, nwid: &str, error: Option<String>) -> Response {
+    let (mut invites, mut join_requests): (Vec<Invite>, Vec<JoinRequest>) = {
+        let config = state.config.read().await;
+        match config.as_ref() {
+            Some(c) => (
+                c.invites.iter().filter(|i| i.nwid == nwid).cloned().collect(),
+                c.join_requests.iter().filter(|r| r.nwid == nwid).cloned().collect(),
+            ),
+            None => (Vec::new(), Vec::new()),
+        }
+    };
+    invites.sort_by_key(|i| std::cmp::Reverse(i.created_at));
+
+    // Requests authorized from the member list are no longer pending
+    {
+        let zt = state.zt_state.read().await;
+        if let Some(members) = zt.controller_members.get(nwid) {
+            join_requests.retain(|r| {
+                !members.iter().any(|m| m.display_id() == r.member_id && m.is_authorized())
+            });
         }
-        Some(Err(e)) => (StatusCode::BAD_GATEWAY, format!("Failed: {}", e)).into_response(),
-        None => (StatusCode::SERVICE_UNAVAILABLE, "Not configured").into_response(),
     }
+    join_requests.sort_by_key(|r| r.requested_at);
+
+    CtrlInvitesPartial {
+        nwid: nwid.to_string(),
+        invites,
+        join_requests,
+        error,
+    }.into_response()
+}
+
+/// GET /controller/{nwid}/invites - Join links for a network
+pub async fn invites_panel(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+) -> Response {
+    if !permissions::can_authorize(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to manage invites");
+    }
+    render_invites(&state, &nwid, None).await
+}
+
+#[derive(Deserialize)]
+pub struct CreateInviteForm {
+    /// Hours until the link expires; empty for no expiry
+    #[serde(default)]
+    pub expires_hours: String,
+    pub single_use: Option<String>,
+    pub allow_requests: Option<String>,
+}
+
+/// POST /controller/{nwid}/invites - Create a join link
+pub async fn create_invite(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+    Form(form): Form<CreateInviteForm>,
+) -> Response {
+    if !permissions::can_authorize(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to manage invites");
+    }
+
+    let valid_for = match invites::parse_valid_for(&form.expires_hours) {
+        Ok(v) => v,
+        Err(e) => return render_invites(&state, &nwid, Some(e)).await,
+    };
+    let invite = Invite::new(
+        &nwid,
+        &user.username,
+        valid_for,
+        form.single_use.is_some(),
+        form.allow_requests.is_some(),
+    );
+
+    let error = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => {
+                c.invites.push(invite);
+                c.save().err().map(|e| format!("Failed to save: {}", e))
+            }
+            None => Some("Not configured".to_string()),
+        }
+    };
+    render_invites(&state, &nwid, error).await
+}
+
+/// DELETE /controller/{nwid}/invites/{token} - Revoke a join link
+pub async fn revoke_invite(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((nwid, token)): Path<(String, String)>,
+) -> Response {
+    if !permissions::can_authorize(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to manage invites");
+    }
+
+    let error = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => {
+                c.invites.retain(|i| !(i.nwid == nwid && i.token == token));
+                c.save().err().map(|e| format!("Failed to save: {}", e))
+            }
+            None => Some("Not configured".to_string()),
+        }
+    };
+    render_invites(&state, &nwid, error).await
+}
+
+/// Remove a join request once it has been decided
+async fn resolve_join_request(state: &AppState, nwid: &str, member_id: &str) -> Option<String> {
+    let mut config = state.config.write().await;
+    let c = config.as_mut()?;
+    c.join_requests.retain(|r| !(r.nwid == nwid && r.member_id == member_id));
+    c.save().err().map(|e| format!("Failed to save: {}", e))
+}
+
+/// POST /controller/{nwid}/join-requests/{member_id}/approve - Authorize a requested member
+pub async fn approve_join_request(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((nwid, member_id)): Path<(String, String)>,
+) -> Response {
+    if !permissions::can_authorize(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to authorize members");
+    }
+
+    let client = state.zt_client.read().await.clone();
+    let Some(client) = client else {
+        return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured");
+    };
+    if let Err(e) = client
+        .update_controller_member(&nwid, &member_id, serde_json::json!({"authorized": true}))
+        .await
+    {
+        return render_invites(&state, &nwid, Some(format!("Failed: {}", e))).await;
+    }
+
+    let error = resolve_join_request(&state, &nwid, &member_id).await;
+    state.notify_poller();
+    render_invites(&state, &nwid, error).await
+}
+
+/// POST /controller/{nwid}/join-requests/{member_id}/reject - Remove a requested member.
+/// Members authorized in the meantime are left alone.
+pub async fn reject_join_request(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((nwid, member_id)): Path<(String, String)>,
+) -> Response {
+    if !permissions::can_authorize(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to authorize members");
+    }
+
+    let client = state.zt_client.read().await.clone();
+    let Some(client) = client else {
+        return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured");
+    };
+    if let Ok(member) = client.get_controller_member(&nwid, &member_id).await {
+        // Unknown members come back as an empty object
+        if member.address.is_some() && !member.is_authorized() {
+            if let Err(e) = client.delete_controller_member(&nwid, &member_id).await {
+                return render_invites(&state, &nwid, Some(format!("Failed: {}", e))).await;
+            }
+        }
+    }
+
+    let error = resolve_join_request(&state, &nwid, &member_id).await;
+    state.notify_poller();
+    render_invites(&state, &nwid, error).await
+}
+
+// ---- Handlers: Share Links ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "controller/partials/share_links.html")]
+pub struct CtrlShareLinksPartial {
+    pub nwid: String,
+    /// Links with their signed paths
+    pub links: Vec<(ShareLink, String)>,
+    pub error: Option<String>,
+}
+
+async fn render_share_links(state: &AppState, nwid: &str, error: Option<String>) -> Response {
+    let mut links: Vec<(ShareLink, String)> = {
+        let config = state.config.read().await;
+        match config.as_ref() {
+            Some(c) => c
+                .share_links
+                .iter()
+                .filter(|l| l.nwid == nwid)
+                .map(|l| (l.clone(), l.path(&c.approval_secret)))
+                .collect(),
+            None => Vec::new(),
+        }
+    };
+    links.sort_by_key(|(l, _)| std::cmp::Reverse(l.created_at));
+
+    CtrlShareLinksPartial {
+        nwid: nwid.to_string(),
+        links,
+        error,
+    }
+    .into_response()
+}
+
+/// GET /controller/{nwid}/shares - Read-only links to the member list
+pub async fn share_links_panel(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    render_share_links(&state, &nwid, None).await
+}
+
+#[derive(Deserialize)]
+pub struct CreateShareLinkForm {
+    #[serde(default)]
+    pub label: String,
+    pub expires_hours: String,
+}
+
+/// POST /controller/{nwid}/shares - Create a read-only link
+pub async fn create_share_link(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+    Form(form): Form<CreateShareLinkForm>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let valid_for = match shares::parse_valid_for(&form.expires_hours) {
+        Ok(v) => v,
+        Err(e) => return render_share_links(&state, &nwid, Some(e)).await,
+    };
+
+    let error = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => {
+                // Expired links are of no use to anyone
+                c.share_links.retain(|l| !l.is_expired());
+                let link = ShareLink::new(c.next_share_link_id, &nwid, &form.label, &user.username, valid_for);
+                c.next_share_link_id += 1;
+                let detail = match link.label.as_str() {
+                    "" => format!("Expires {}", link.expires_at.format("%Y-%m-%d %H:%M UTC")),
+                    label => format!("For {}, expires {}", label, link.expires_at.format("%Y-%m-%d %H:%M UTC")),
+                };
+                c.audit(&user.username, "Share link created", Some(&nwid), None, detail);
+                c.share_links.push(link);
+                c.save().err().map(|e| format!("Failed to save: {}", e))
+            }
+            None => Some("Not configured".to_string()),
+        }
+    };
+    render_share_links(&state, &nwid, error).await
+}
+
+/// DELETE /controller/{nwid}/shares/{id} - Revoke a read-only link
+pub async fn revoke_share_link(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((nwid, id)): Path<(String, u64)>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let error = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => {
+                let before = c.share_links.len();
+                c.share_links.retain(|l| !(l.nwid == nwid && l.id == id));
+                if c.share_links.len() != before {
+                    c.audit(&user.username, "Share link revoked", Some(&nwid), None, format!("Link #{}", id));
+                }
+                c.save().err().map(|e| format!("Failed to save: {}", e))
+            }
+            None => Some("Not configured".to_string()),
+        }
+    };
+    render_share_links(&state, &nwid, error).await
+}
+
+// ---- Handlers: Member Agent ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "controller/partials/member_agent.html")]
+pub struct CtrlMemberAgentPartial {
+    pub nwid: String,
+    pub has_token: bool,
+    /// A token just generated, shown this once
+    pub new_token: Option<String>,
+    /// Members that have reported
+    pub reporting: usize,
+    pub error: Option<String>,
+}
+
+async fn render_member_agent(state: &AppState, nwid: &str, new_token: Option<String>, error: Option<String>) -> Response {
+    let (has_token, reporting) = {
+        let config = state.config.read().await;
+        config.as_ref().map_or((false, 0), |c| {
+            (
+                c.agent_tokens.contains_key(nwid),
+                c.agent_reports.values().filter(|r| r.nwid == nwid).count(),
+            )
+        })
+    };
+    CtrlMemberAgentPartial {
+        nwid: nwid.to_string(),
+        has_token,
+        new_token,
+        reporting,
+        error,
+    }
+    .into_response()
+}
+
+/// GET /controller/{nwid}/agent-token - Whether member agents can report for this network
+pub async fn member_agent_panel(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+) -> Response {
+    if !permissions::can_modify(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
+    }
+    render_member_agent(&state, &nwid, None, None).await
+}
+
+/// POST /controller/{nwid}/agent-token - Generate the network's agent token, replacing any
+/// earlier one
+pub async fn generate_agent_token(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+) -> Response {
+    if !permissions::can_modify(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
+    }
+
+    let (hash, token) = agents::generate_token();
+    let error = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => {
+                c.agent_tokens.insert(nwid.clone(), hash);
+                c.audit(&user.username, "Agent token generated", Some(&nwid), None, String::new());
+                c.save().err().map(|e| format!("Failed to save: {}", e))
+            }
+            None => Some("Not configured".to_string()),
+        }
+    };
+    match error {
+        Some(e) => render_member_agent(&state, &nwid, None, Some(e)).await,
+        None => render_member_agent(&state, &nwid, Some(token), None).await,
+    }
+}
+
+/// DELETE /controller/{nwid}/agent-token - Stop accepting agent reports for this network
+pub async fn revoke_agent_token(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+) -> Response {
+    if !permissions::can_modify(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
+    }
+
+    let error = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => {
+                if c.agent_tokens.remove(&nwid).is_some() {
+                    c.audit(&user.username, "Agent token revoked", Some(&nwid), None, String::new());
+                }
+                // Reports the token sent can't be refreshed any more
+                c.drop_agent_reports(&nwid);
+                c.save().err().map(|e| format!("Failed to save: {}", e))
+            }
+            None => Some("Not configured".to_string()),
+        }
+    };
+    render_member_agent(&state, &nwid, None, error).await
+}
+
+// ---- Handlers: Flow Rules ----
+
+#[derive(Deserialize)]
+pub struct UpdateFlowRulesForm {
+    pub rules_source: String,
+    /// The network revision the page showed
+    pub revision: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct FlowRulesValidation {
+    pub valid: bool,
+    pub errors: Vec<rules::CompileError>,
+    pub compiled: Option<rules::CompiledRules>,
+}
+
+/// Compile the DSL without applying it, returning line-level diagnostics as JSON
+pub async fn validate_flow_rules(
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+    Form(form): Form<UpdateFlowRulesForm>,
+) -> Response {
+    if !permissions::can_read(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to view this network");
+    }
+
+    let validation = match rules::compile(&form.rules_source) {
+        Ok(compiled) => FlowRulesValidation {
+            valid: true,
+            errors: Vec::new(),
+            compiled: Some(compiled),
+        },
+        Err(e) => FlowRulesValidation {
+            valid: false,
+            errors: vec![e],
+            compiled: None,
+        },
+    };
+    Json(validation).into_response()
+}
+
+pub async fn update_flow_rules(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+    Form(form): Form<UpdateFlowRulesForm>,
+) -> Response {
+    if !permissions::can_modify(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
+    }
+
+    // Compile the DSL source server-side so the stored source always matches the pushed rules
+    let compiled = match rules::compile(&form.rules_source) {
+        Ok(r) => r,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+
+    // Build the body with rules, capabilities, and tags
+    let body = serde_json::json!({
+        "rules": compiled.rules,
+        "capabilities": compiled.capabilities,
+        "tags": compiled.tags,
+    });
+
+    let result = edit_network(&state, &user, &nwid, form.revision, "Update flow rules", move |_| {
+        Ok(body.clone())
+    })
+    .await;
+
+    // Save the DSL source locally (ZT API only stores compiled JSON)
+    if edit_kept(&result) {
+        if let Err(e) = state.save_rules_source(&nwid, &form.rules_source).await {
+            tracing::warn!("Failed to save rules source: {}", e);
+        }
+    }
+
+    match result {
+        Ok(network) => {
+            let rules_source = form.rules_source;
+            CtrlFlowRulesPartial {
+                nwid,
+                network,
+                rules_source,
+                can_modify: true,
+                is_admin: permissions::is_admin(&user),
+            }.into_response()
+        }
+        Err(response) => response,
+    }
+}
+
+// ---- Handlers: Rule Simulator ----
+
+/// GET /controller/{nwid}/flow-rules/simulator - Packet simulator form
+pub async fn rule_simulator(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+) -> Response {
+    if !permissions::can_read(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to view this network");
+    }
+
+    let members = {
+        let zt = state.zt_state.read().await;
+        let config = state.config.read().await;
+        let mut members: Vec<(String, String)> = zt
+            .controller_members
+            .get(&nwid)
+            .into_iter()
+            .flatten()
+            .map(|m| {
+                let id = m.display_id().to_string();
+                let label = match config.as_ref().and_then(|c| c.member_names.get(&id)) {
+                    Some(name) if !name.is_empty() => format!("{} ({})", name, id),
+                    _ => id.clone(),
+                };
+                (id, label)
+            })
+            .collect();
+        members.sort_by_key(|m| m.1.to_lowercase());
+        members
+    };
+
+    CtrlRuleSimulatorPartial {
+        nwid: nwid.clone(),
+        members,
+        can_modify: permissions::can_modify(&user, &nwid),
+    }.into_response()
+}
+
+#[derive(Deserialize)]
+pub struct SimulateRulesForm {
+    pub source: String,
+    pub dest: String,
+    pub ether_type: String,
+    #[serde(default)]
+    pub ip_protocol: String,
+    #[serde(default)]
+    pub source_port: String,
+    #[serde(default)]
+    pub dest_port: String,
+    #[serde(default)]
+    pub icmp_type: String,
+    #[serde(default)]
+    pub tcp_flags: String,
+    #[serde(default)]
+    pub frame_size: String,
+    #[serde(default)]
+    pub source_tags: String,
+    #[serde(default)]
+    pub dest_tags: String,
+    /// Present when testing the unsaved editor contents
+    pub use_editor: Option<String>,
+    pub rules_source: Option<String>,
+}
+
+fn optional_number<T: std::str::FromStr>(value: &str, label: &str) -> Result<Option<T>, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value.parse().map(Some).map_err(|_| format!("Invalid {}: {}", label, value))
+}
+
+/// Parse "name=value, ..." tag overrides. Names and values may be declared names or numbers.
+fn parse_tag_overrides(input: &str, declared: &rules::Declarations) -> Result<Vec<(u32, u32)>, String> {
+    input
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|pair| {
+            let (name, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Expected name=value, got \"{}\"", pair))?;
+            let (name, value) = (name.trim().to_lowercase(), value.trim().to_lowercase());
+            let tag = declared.tags.iter().find(|t| t.name == name);
+            let id = match tag {
+                Some(t) => t.id,
+                None => parse_u32(&name).ok_or_else(|| format!("Unknown tag \"{}\"", name))?,
+            };
+            let named = tag.and_then(|t| {
+                t.enums
+                    .iter()
+                    .chain(t.flags.iter())
+                    .find(|v| v.name == value)
+                    .map(|v| v.value)
+            });
+            let value = named
+                .or_else(|| parse_u32(&value))
+                .ok_or_else(|| format!("Invalid value for tag \"{}\"", name))?;
+            Ok((id, value))
+        })
+        .collect()
+}
+
+fn tag_summary(tags: &std::collections::HashMap<u32, u32>, declared: &rules::Declarations) -> String {
+    let mut parts: Vec<String> = tags
+        .iter()
+        .map(|(id, value)| {
+            let name = declared
+                .tags
+                .iter()
+                .find(|t| t.id == *id)
+                .map(|t| t.name.clone())
+                .unwrap_or_else(|| id.to_string());
+            format!("{}={}", name, value)
+        })
+        .collect();
+    parts.sort();
+    if parts.is_empty() {
+        "no tags".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+/// Build the synthetic packet from the simulator form. Member tags start from the
+/// network's tag defaults, then the member's assignments, then the form overrides.
+fn simulation_packet(
+    form: &SimulateRulesForm,
+    source: &ControllerMember,
+    dest: &ControllerMember,
+    nwid: &str,
+    compiled_tags: &[serde_json::Value],
+    declared: &rules::Declarations,
+) -> Result<simulate::Packet, String> {
+    let ether_type = rules::ethertype_value(&form.ether_type)
+        .ok_or_else(|| format!("Invalid ethertype: {}", form.ether_type))?;
+    let ip_protocol = match form.ip_protocol.trim() {
+        "" => None,
+        p => Some(rules::ip_protocol_value(p).ok_or_else(|| format!("Invalid IP protocol: {}", p))?),
+    };
+    let tcp_flags = rules::characteristics_mask(&form.tcp_flags)
+        .ok_or_else(|| format!("Invalid TCP flags: {}", form.tcp_flags))?
+        & 0xfff;
+
+    let tag_defaults: Vec<(u32, u32)> = compiled_tags
+        .iter()
+        .filter_map(|t| {
+            let id = t.get("id")?.as_u64()? as u32;
+            let default = t.get("default")?.as_u64()? as u32;
+            Some((id, default))
+        })
+        .collect();
+    let endpoint = |m: &ControllerMember, overrides: &str| -> Result<simulate::Endpoint, String> {
+        let mut tags: std::collections::HashMap<u32, u32> = tag_defaults.iter().copied().collect();
+        tags.extend(m.tags.iter().copied());
+        tags.extend(parse_tag_overrides(overrides, declared)?);
+        let want_v6 = ether_type == 0x86dd;
+        let ip = m
+            .ip_assignments
+            .iter()
+            .filter_map(|ip| ip.parse::<std::net::IpAddr>().ok())
+            .find(|ip| ip.is_ipv6() == want_v6);
+        Ok(simulate::Endpoint {
+            address: m.display_id().to_string(),
+            mac: simulate::member_mac(m.display_id(), nwid),
+            ip,
+            tags,
+        })
+    };
+
+    Ok(simulate::Packet {
+        source: endpoint(source, &form.source_tags)?,
+        dest: endpoint(dest, &form.dest_tags)?,
+        ether_type,
+        ip_protocol,
+        source_port: optional_number(&form.source_port, "source port")?,
+        dest_port: optional_number(&form.dest_port, "destination port")?,
+        icmp_type: optional_number(&form.icmp_type, "ICMP type")?,
+        frame_size: optional_number(&form.frame_size, "frame size")?.unwrap_or(64),
+        tcp_flags,
+    })
+}
+
+/// POST /controller/{nwid}/flow-rules/simulate - Run a synthetic packet through the rules
+pub async fn simulate_rules(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+    Form(form): Form<SimulateRulesForm>,
+) -> Response {
+    if !permissions::can_read(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to view this network");
+    }
+
+    let (network, members) = {
+        let zt = state.zt_state.read().await;
+        let network = zt.controller_networks.iter().find(|n| n.display_id() == nwid).cloned();
+        (network, zt.controller_members.get(&nwid).cloned().unwrap_or_default())
+    };
+    let Some(network) = network else {
+        return error_response(StatusCode::NOT_FOUND, "Network not found");
+    };
+    let saved_source = {
+        let config = state.config.read().await;
+        config.as_ref().and_then(|c| c.rules_source.get(&nwid).cloned())
+    };
+
+    // Test the editor contents if asked, otherwise the rules deployed on the controller
+    let editor_source = form
+        .rules_source
+        .as_deref()
+        .filter(|s| form.use_editor.is_some() && !s.trim().is_empty());
+    let (rule_set_label, compiled, declared) = match editor_source {
+        Some(src) => match rules::compile(src) {
+            Ok(c) => ("editor (unsaved)", c, rules::declarations(src).unwrap_or_default()),
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, e.to_string()),
+        },
+        None => (
+            "deployed",
+            rules::CompiledRules {
+                rules: network.rules.clone(),
+                capabilities: network.capabilities.clone(),
+                tags: network.tags.clone(),
+            },
+            saved_source
+                .as_deref()
+                .and_then(|s| rules::declarations(s).ok())
+                .unwrap_or_default(),
+        ),
+    };
+
+    let find_member = |id: &str| members.iter().find(|m| m.display_id() == id.trim());
+    let (Some(source), Some(dest)) = (find_member(&form.source), find_member(&form.dest)) else {
+        return error_response(StatusCode::BAD_REQUEST, "Select a source and destination member");
+    };
+
+    let packet = simulation_packet(&form, source, dest, &nwid, &compiled.tags, &declared);
+    let packet = match packet {
+        Ok(p) => p,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+
+    // Only the sender's capabilities are evaluated, on both sides
+    let capabilities: Vec<simulate::Capability> = compiled
+        .capabilities
+        .iter()
+        .filter_map(|c| {
+            let id = c.get("id")?.as_u64()? as u32;
+            if !source.capabilities.contains(&id) {
+                return None;
+            }
+            let name = declared
+                .capabilities
+                .iter()
+                .find(|d| d.id == id)
+                .map(|d| d.name.clone())
+                .unwrap_or_else(|| id.to_string());
+            let rules = c.get("rules")?.as_array()?.clone();
+            Some(simulate::Capability { id, name, rules })
+        })
+        .collect();
+
+    let summary = |ep: &simulate::Endpoint| {
+        let ip = ep.ip.map(|ip| ip.to_string()).unwrap_or_else(|| "no IP".to_string());
+        format!("{} · {} · {} · {}", ep.address, ip, ep.mac, tag_summary(&ep.tags, &declared))
+    };
+    CtrlRuleSimulationPartial {
+        simulation: simulate::simulate(&compiled.rules, &capabilities, &packet),
+        rule_set_label,
+        source_summary: summary(&packet.source),
+        dest_summary: summary(&packet.dest),
+    }.into_response()
+}
+
+// ---- Handlers: Tags & Capabilities Builder ----
+
+/// GET /controller/{nwid}/flow-rules/definitions - Builder tags and capabilities as JSON
+pub async fn get_rule_definitions(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+) -> Response {
+    if !permissions::can_read(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to view this network");
+    }
+
+    let definitions = {
+        let config = state.config.read().await;
+        config
+            .as_ref()
+            .and_then(|c| c.rule_definitions.get(&nwid).cloned())
+            .unwrap_or_default()
+    };
+    Json(definitions).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct SaveRuleDefinitionsRequest {
+    pub definitions: rules::RuleDefinitions,
+    pub rules_source: String,
+}
+
+#[derive(Serialize)]
+pub struct SaveRuleDefinitionsResponse {
+    pub rules_source: String,
+}
+
+/// POST /controller/{nwid}/flow-rules/definitions - Save builder definitions and
+/// return the editor source with the generated region updated. Nothing is pushed
+/// to the controller until the rules are applied.
+pub async fn save_rule_definitions(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+    Json(req): Json<SaveRuleDefinitionsRequest>,
+) -> Response {
+    if !permissions::can_modify(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to modify this network");
+    }
+
+    if let Err(e) = req.definitions.validate() {
+        return error_response(StatusCode::BAD_REQUEST, e);
+    }
+
+    {
+        let mut config = state.config.write().await;
+        let Some(ref mut c) = *config else {
+            return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured");
+        };
+        if req.definitions.is_empty() {
+            c.rule_definitions.remove(&nwid);
+        } else {
+            c.rule_definitions.insert(nwid.clone(), req.definitions.clone());
+        }
+        if let Err(e) = c.save() {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save: {}", e));
+        }
+    }
+
+    Json(SaveRuleDefinitionsResponse {
+        rules_source: req.definitions.merge_into(&req.rules_source),
+    })
+    .into_response()
+}
+
+// ---- Handlers: Flow Rules Snippets ----
+
+#[derive(Serialize)]
+pub struct RuleSnippetList {
+    pub builtin: &'static [RuleSnippet],
+    pub custom: Vec<CustomRuleSnippet>,
+}
+
+async fn rule_snippet_list(state: &AppState) -> Json<RuleSnippetList> {
+    let custom = {
+        let config = state.config.read().await;
+        config.as_ref().map(|c| c.rule_snippets.clone()).unwrap_or_default()
+    };
+    Json(RuleSnippetList {
+        builtin: BUILTIN_SNIPPETS,
+        custom,
+    })
+}
+
+/// GET /controller/rule-snippets - Built-in and saved snippets for the rules editor
+pub async fn list_rule_snippets(State(state): State<AppState>) -> Response {
+    rule_snippet_list(&state).await.into_response()
+}
+
+#[derive(Deserialize)]
+pub struct CreateRuleSnippetForm {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub source: String,
+}
+
+/// POST /controller/rule-snippets - Save a custom snippet (admin only)
+pub async fn create_rule_snippet(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Form(form): Form<CreateRuleSnippetForm>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let name = form.name.trim().to_string();
+    if name.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "Snippet name is required");
+    }
+    if form.source.trim().is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "Snippet source is empty");
+    }
+
+    {
+        let mut config = state.config.write().await;
+        let Some(ref mut c) = *config else {
+            return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured");
+        };
+        let id = c.rule_snippets.iter().map(|s| s.id).max().unwrap_or(0) + 1;
+        c.rule_snippets.push(CustomRuleSnippet {
+            id,
+            name,
+            description: form.description.trim().to_string(),
+            source: form.source,
+        });
+        if let Err(e) = c.save() {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save: {}", e));
+        }
+    }
+
+    rule_snippet_list(&state).await.into_response()
+}
+
+/// DELETE /controller/rule-snippets/{id} - Remove a custom snippet (admin only)
+pub async fn delete_rule_snippet(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    {
+        let mut config = state.config.write().await;
+        let Some(ref mut c) = *config else {
+            return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured");
+        };
+        let before = c.rule_snippets.len();
+        c.rule_snippets.retain(|s| s.id != id);
+        if c.rule_snippets.len() == before {
+            return error_response(StatusCode::NOT_FOUND, "Snippet not found");
+        }
+        if let Err(e) = c.save() {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save: {}", e));
+        }
+    }
+
+    rule_snippet_list(&state).await.into_response()
+}
+
+// ---- Handlers: History ----
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    member: Option<String>,
+    before: Option<i64>,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "controller/partials/history.html")]
+pub struct HistoryPartial {
+    pub nwid: String,
+    /// Set when showing a single member's events
+    pub member: Option<String>,
+    pub events: Vec<HistoryEvent>,
+    /// Member names for the member column
+    pub names: HashMap<String, String>,
+    pub before: Option<i64>,
+    /// Where the next page starts, when there may be one
+    pub older: Option<i64>,
+}
+
+/// A page of changes seen on the network, or on one of its members
+pub async fn network_history(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(nwid): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Response {
+    if !permissions::can_read(&user, &nwid) {
+        return error_response(StatusCode::FORBIDDEN, "You don't have permission to view this network");
+    }
+
+    let page_size = user.preferences.rows_per_page;
+    let events = match history::events(&nwid, query.member.as_deref(), query.before, page_size) {
+        Ok(events) => events,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    };
+    let names = {
+        let config = state.config.read().await;
+        let all = config.as_ref().map(|c| &c.member_names);
+        events
+            .iter()
+            .filter_map(|e| e.member_id.as_ref())
+            .filter_map(|id| Some((id.clone(), all?.get(id)?.clone())))
+            .collect()
+    };
+    let older = (events.len() == page_size).then(|| events.last().map(|e| e.id)).flatten();
+
+    HistoryPartial {
+        nwid,
+        member: query.member,
+        events,
+        names,
+        before: query.before,
+        older,
+    }
+    .into_response()
 }
@@ -1,30 +1,106 @@
 use askama::Template;
 use askama_web::WebTemplate;
-use axum::extract::State;
-use axum::response::IntoResponse;
-use axum::Extension;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use serde::Deserialize;
 
+use crate::identity::IdentityAlert;
+use crate::metrics::{self, Chart, Range, Sample};
 use crate::permissions;
-use crate::state::{AppState, User};
-use crate::zt::models::{ControllerNetwork, NodeStatus};
+use crate::releases::UpdateNotice;
+use crate::retry::QueuedEdit;
+use crate::routes::error::error_response;
+use crate::state::{AppState, Config, User};
+use crate::zt::models::{ControllerNetwork, NodeStatus, Peer, ZtState};
 
 /// Network row data passed to the dashboard template
 pub struct NetworkRow {
     pub network: ControllerNetwork,
     pub member_count: usize,
+    pub authorized_count: usize,
+    /// Authorized members the controller heard from recently
+    pub online_count: usize,
     pub description: String,
 }
 
+impl NetworkRow {
+    /// Badge class summing up whether the authorized members are reachable
+    pub fn online_class(&self) -> &'static str {
+        if self.authorized_count == 0 {
+            "status-unknown"
+        } else if self.online_count == self.authorized_count {
+            "status-ok"
+        } else if self.online_count == 0 {
+            "status-denied"
+        } else {
+            "status-pending"
+        }
+    }
+}
+
+/// Networks listed under one heading: a network group, or `None` for the ungrouped ones
+pub struct NetworkSection {
+    pub name: Option<String>,
+    pub rows: Vec<NetworkRow>,
+}
+
+/// The networks `user` can read, by network group in the configured order, ungrouped ones last
+fn network_sections(zt: &ZtState, config: Option<&Config>, user: &User) -> Vec<NetworkSection> {
+    let network_groups = config.map(|c| c.network_groups.as_slice()).unwrap_or_default();
+    let row = |net: &ControllerNetwork| {
+        let nwid = net.display_id();
+        let members = zt.controller_members.get(nwid).map(Vec::as_slice).unwrap_or_default();
+        let authorized = || members.iter().filter(|m| m.is_authorized());
+        NetworkRow {
+            network: net.clone(),
+            member_count: members.len(),
+            authorized_count: authorized().count(),
+            online_count: authorized()
+                .filter(|m| zt.peers.get(m.display_id()).is_some_and(|p| p.is_online()))
+                .count(),
+            description: config
+                .and_then(|c| c.network_descriptions.get(nwid))
+                .cloned()
+                .unwrap_or_default(),
+        }
+    };
+    let visible = || {
+        zt.controller_networks
+            .iter()
+            .filter(|net| permissions::can_read(user, net.display_id()))
+    };
+
+    let mut sections: Vec<NetworkSection> = network_groups
+        .iter()
+        .map(|group| NetworkSection {
+            name: Some(group.name.clone()),
+            rows: visible().filter(|net| group.contains(net.display_id())).map(row).collect(),
+        })
+        .collect();
+    sections.push(NetworkSection {
+        name: None,
+        rows: visible()
+            .filter(|net| !network_groups.iter().any(|g| g.contains(net.display_id())))
+            .map(row)
+            .collect(),
+    });
+    sections.retain(|s| !s.rows.is_empty());
+    sections
+}
+
 #[derive(Template, WebTemplate)]
 #[template(path = "dashboard.html")]
 pub struct DashboardTemplate {
     pub status: Option<NodeStatus>,
-    pub network_rows: Vec<NetworkRow>,
+    pub network_sections: Vec<NetworkSection>,
     pub network_count: usize,
     pub total_members: usize,
     pub authorized_members: usize,
     pub error: Option<String>,
     pub version: &'static str,
+    pub can_create_network: bool,
 }
 
 pub async fn dashboard(
@@ -34,13 +110,6 @@ pub async fn dashboard(
     let zt = state.zt_state.read().await;
     let cfg = state.config.read().await;
 
-    // Get network descriptions from config
-    let network_descriptions = cfg
-        .as_ref()
-        .map(|c| &c.network_descriptions)
-        .cloned()
-        .unwrap_or_default();
-
     // Filter networks based on user permissions
     let visible_networks: Vec<&ControllerNetwork> = zt
         .controller_networks
@@ -70,34 +139,16 @@ pub async fn dashboard(
         .filter(|m| m.is_authorized())
         .count();
 
-    let network_rows: Vec<NetworkRow> = visible_networks
-        .iter()
-        .map(|net| {
-            let nwid = net.display_id().to_string();
-            let member_count = zt
-                .controller_members
-                .get(&nwid)
-                .map(|v| v.len())
-                .unwrap_or(0);
-            let description = network_descriptions
-                .get(&nwid)
-                .cloned()
-                .unwrap_or_default();
-            NetworkRow {
-                network: (*net).clone(),
-                member_count,
-                description,
-            }
-        })
-        .collect();
+    let network_sections = network_sections(&zt, cfg.as_ref(), &user);
     DashboardTemplate {
         status: zt.status.clone(),
         network_count: visible_networks.len(),
-        network_rows,
+        network_sections,
         total_members,
         authorized_members,
         error: zt.error.clone(),
         version: crate::VERSION,
+        can_create_network: permissions::can_create_network(&user),
     }
 }
 
@@ -158,7 +209,7 @@ pub async fn dashboard_partial(
 #[derive(Template, WebTemplate)]
 #[template(path = "partials/dashboard_networks.html")]
 pub struct DashboardNetworksPartial {
-    pub network_rows: Vec<NetworkRow>,
+    pub network_sections: Vec<NetworkSection>,
 }
 
 pub async fn dashboard_networks_partial(
@@ -168,33 +219,225 @@ pub async fn dashboard_networks_partial(
     let zt = state.zt_state.read().await;
     let cfg = state.config.read().await;
 
-    let network_descriptions = cfg
-        .as_ref()
-        .map(|c| &c.network_descriptions)
-        .cloned()
-        .unwrap_or_default();
+    DashboardNetworksPartial {
+        network_sections: network_sections(&zt, cfg.as_ref(), &user),
+    }
+}
 
-    let network_rows: Vec<NetworkRow> = zt
-        .controller_networks
-        .iter()
-        .filter(|net| permissions::can_read(&user, net.display_id()))
-        .map(|net| {
-            let nwid = net.display_id().to_string();
-            let member_count = zt
-                .controller_members
-                .get(&nwid)
-                .map(|v| v.len())
-                .unwrap_or(0);
-            let description = network_descriptions
-                .get(&nwid)
-                .cloned()
-                .unwrap_or_default();
-            NetworkRow {
-                network: net.clone(),
-                member_count,
-                description,
-            }
-        })
-        .collect();
-    DashboardNetworksPartial { network_rows }
+/// The roots the controller's node knows, from its peers (admin only)
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/roots.html")]
+pub struct RootsPartial {
+    pub visible: bool,
+    pub roots: Vec<Peer>,
+    /// Whether the node's peers could be read at all
+    pub peers_known: bool,
+}
+
+impl RootsPartial {
+    pub fn reachable_count(&self) -> usize {
+        self.roots.iter().filter(|r| r.is_online()).count()
+    }
+}
+
+pub async fn roots_partial(State(state): State<AppState>, Extension(user): Extension<User>) -> Response {
+    if !permissions::is_admin(&user) {
+        return RootsPartial {
+            visible: false,
+            roots: Vec::new(),
+            peers_known: false,
+        }
+        .into_response();
+    }
+    let zt = state.zt_state.read().await;
+    let mut roots: Vec<Peer> = zt.peers.values().filter(|p| p.is_root()).cloned().collect();
+    roots.sort_by_key(|r| (r.role.as_deref() != Some("PLANET"), r.address.clone()));
+    RootsPartial {
+        visible: true,
+        roots,
+        peers_known: !zt.peers.is_empty(),
+    }
+    .into_response()
+}
+
+/// Banner about a newer ZeroTier release than the node runs (admin only)
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/release_notice.html")]
+pub struct ReleaseNoticePartial {
+    pub notice: Option<UpdateNotice>,
+}
+
+pub async fn release_notice_partial(State(state): State<AppState>, Extension(user): Extension<User>) -> Response {
+    let notice = if permissions::is_admin(&user) {
+        let version = state.zt_state.read().await.status.as_ref().and_then(|s| s.version.clone());
+        let info = state.release_info.read().await;
+        version.zip(info.as_ref()).and_then(|(v, info)| info.notice(&v))
+    } else {
+        None
+    };
+    ReleaseNoticePartial { notice }.into_response()
+}
+
+/// Open identity alerts banner (admin only)
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/identity_alerts.html")]
+pub struct IdentityAlertsPartial {
+    pub alerts: Vec<IdentityAlert>,
+}
+
+async fn open_identity_alerts(state: &AppState) -> Vec<IdentityAlert> {
+    let cfg = state.config.read().await;
+    cfg.as_ref()
+        .map(|c| c.identity_alerts.iter().filter(|a| a.is_open()).cloned().collect())
+        .unwrap_or_default()
+}
+
+pub async fn identity_alerts_partial(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return IdentityAlertsPartial { alerts: Vec::new() }.into_response();
+    }
+    IdentityAlertsPartial {
+        alerts: open_identity_alerts(&state).await,
+    }
+    .into_response()
+}
+
+/// Accept a member's new identity. The member stays de-authorized until an admin re-authorizes it.
+pub async fn acknowledge_identity_alert(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(alert_id): Path<u64>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    {
+        let mut cfg = state.config.write().await;
+        let Some(ref mut c) = *cfg else {
+            return error_response(StatusCode::SERVICE_UNAVAILABLE, "Not configured");
+        };
+        let Some(alert) = c.identity_alerts.iter_mut().find(|a| a.id == alert_id) else {
+            return error_response(StatusCode::NOT_FOUND, "Alert not found");
+        };
+        alert.acknowledged_at = Some(chrono::Utc::now());
+        alert.acknowledged_by = Some(user.username.clone());
+        let (member_id, identity) = (alert.member_id.clone(), alert.new_identity.clone());
+        c.member_identities.insert(member_id, identity);
+        if let Err(e) = c.save() {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save: {}", e));
+        }
+    }
+
+    IdentityAlertsPartial {
+        alerts: open_identity_alerts(&state).await,
+    }
+    .into_response()
+}
+
+/// Banner shown on every page while the controller can't be polled
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/controller_error.html")]
+pub struct ControllerErrorPartial {
+    pub error: Option<String>,
+    /// When polls started failing, and how long ago that was
+    pub since: String,
+    pub duration: String,
+}
+
+pub async fn controller_error_partial(State(state): State<AppState>) -> ControllerErrorPartial {
+    let zt = state.zt_state.read().await;
+    let since = zt.error_since.filter(|_| zt.error.is_some());
+    ControllerErrorPartial {
+        error: zt.error.clone(),
+        since: since
+            .map(|t| crate::preferences::format_time_exact(t.into()))
+            .unwrap_or_default(),
+        duration: since
+            .and_then(|t| t.elapsed().ok())
+            .map(|d| crate::notifications::format_duration(d.as_secs()))
+            .unwrap_or_default(),
+    }
+}
+
+/// Banners for the user's edits queued while ZeroTier was unreachable, and how they turned out
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/queued_edits.html")]
+pub struct QueuedEditsPartial {
+    pub edits: Vec<QueuedEdit>,
+}
+
+pub async fn queued_edits_partial(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> QueuedEditsPartial {
+    QueuedEditsPartial { edits: state.retries.edits_of(&user.username) }
+}
+
+/// POST /queued-edits/{id}/dismiss - Stop showing how a queued edit turned out
+pub async fn dismiss_queued_edit(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<u64>,
+) -> QueuedEditsPartial {
+    state.retries.dismiss(id, &user.username);
+    QueuedEditsPartial { edits: state.retries.edits_of(&user.username) }
+}
+
+#[derive(Deserialize)]
+pub struct MetricsQuery {
+    /// One network's history instead of the total over all visible networks
+    network: Option<String>,
+    #[serde(default)]
+    range: Range,
+}
+
+/// Member count history chart for the dashboard or a network page
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/metrics_chart.html")]
+pub struct MetricsChartPartial {
+    pub chart: Chart,
+    pub network: Option<String>,
+    pub ranges: [Range; 3],
+}
+
+/// Samples for the query's range, limited to what the user may see
+fn metrics_history(user: &User, query: &MetricsQuery) -> Result<Vec<Sample>, (StatusCode, String)> {
+    if let Some(nwid) = &query.network {
+        if !permissions::can_read(user, nwid) {
+            return Err((StatusCode::FORBIDDEN, "You don't have access to this network".to_string()));
+        }
+    }
+    metrics::history(query.range, |nwid| match &query.network {
+        Some(network) => network == nwid,
+        None => permissions::can_read(user, nwid),
+    })
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+pub async fn metrics_partial(Extension(user): Extension<User>, Query(query): Query<MetricsQuery>) -> Response {
+    match metrics_history(&user, &query) {
+        Ok(samples) => MetricsChartPartial {
+            chart: Chart::new(query.range, &samples),
+            network: query.network,
+            ranges: Range::ALL,
+        }
+        .into_response(),
+        Err((status, message)) => error_response(status, message),
+    }
+}
+
+/// The raw samples as JSON, for charting elsewhere
+pub async fn metrics_json(Extension(user): Extension<User>, Query(query): Query<MetricsQuery>) -> Response {
+    match metrics_history(&user, &query) {
+        Ok(samples) => Json(serde_json::json!({
+            "range": query.range.as_str(),
+            "samples": samples,
+        }))
+        .into_response(),
+        Err((status, message)) => error_response(status, message),
+    }
 }
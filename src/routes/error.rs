@@ -0,0 +1,96 @@
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::body::Body;
+use axum::http::header::{ACCEPT, AUTHORIZATION};
+use axum::http::{HeaderMap, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::request_id::REQUEST_ID_HEADER;
+
+#[derive(Template, WebTemplate)]
+#[template(path = "error.html")]
+pub struct ErrorTemplate {
+    pub status: u16,
+    pub title: &'static str,
+    pub message: String,
+    pub request_id: Option<String>,
+    pub version: &'static str,
+}
+
+/// Message of a failed request, kept so a page load can show it on an error page
+#[derive(Clone)]
+struct ErrorMessage(String);
+
+/// A failed request. htmx and scripts get the message as plain text;
+/// `error_page_middleware` shows it as a page when a browser navigated here.
+pub fn error_response(status: StatusCode, message: impl Into<String>) -> Response {
+    let message = message.into();
+    let mut response = (status, message.clone()).into_response();
+    response.extensions_mut().insert(ErrorMessage(message));
+    response
+}
+
+/// Fallback for paths no route matches
+pub async fn not_found() -> Response {
+    error_response(StatusCode::NOT_FOUND, "There is nothing at this address.")
+}
+
+/// Page loads and boosted links get an error page; htmx fragments, API calls and
+/// scripts keep the plain message
+fn wants_page(headers: &HeaderMap) -> bool {
+    if headers.contains_key(AUTHORIZATION) {
+        return false;
+    }
+    if headers.contains_key("hx-request") {
+        return headers.contains_key("hx-boosted");
+    }
+    headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/html"))
+}
+
+fn title(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::FORBIDDEN => "Access denied",
+        StatusCode::NOT_FOUND => "Not found",
+        StatusCode::BAD_GATEWAY => "ZeroTier request failed",
+        StatusCode::SERVICE_UNAVAILABLE => "Not available",
+        s if s.is_server_error() => "Something went wrong",
+        _ => "Request failed",
+    }
+}
+
+pub async fn error_page_middleware(request: Request<Body>, next: Next) -> Response {
+    let wants_page = wants_page(request.headers());
+    let response = next.run(request).await;
+    if !wants_page {
+        return response;
+    }
+    let Some(ErrorMessage(message)) = response.extensions().get::<ErrorMessage>().cloned() else {
+        return response;
+    };
+
+    let status = response.status();
+    // Only server errors are worth quoting when reporting a problem
+    let request_id = status
+        .is_server_error()
+        .then(|| response.headers().get(&REQUEST_ID_HEADER))
+        .flatten()
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let page = ErrorTemplate {
+        status: status.as_u16(),
+        title: title(status),
+        message,
+        request_id,
+        version: crate::VERSION,
+    };
+
+    let mut page = (status, page).into_response();
+    if let Some(id) = response.headers().get(&REQUEST_ID_HEADER) {
+        page.headers_mut().insert(REQUEST_ID_HEADER, id.clone());
+    }
+    page
+}
@@ -1,33 +1,158 @@
-use axum::extract::State;
+use std::time::{Duration, Instant, SystemTime};
+
+use axum::extract::{Query, State};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::state::AppState;
+use crate::zt::poller;
+
+/// How long the deep check waits for ZeroTier to answer
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How far behind its schedule the poller may fall before it counts as stalled
+const MAX_POLL_LAG: Duration = Duration::from_secs(60);
 
 #[derive(Serialize)]
 pub struct HealthResponse {
     pub status: &'static str,
     pub zt_connected: bool,
     pub version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checks: Option<DeepChecks>,
+}
+
+/// What `?deep=1` checks, each on its own so monitoring can tell which part failed
+#[derive(Serialize)]
+pub struct DeepChecks {
+    pub zerotier: ZeroTierCheck,
+    pub config: ConfigCheck,
+    pub poller: PollerCheck,
 }
 
-pub async fn health_check(State(state): State<AppState>) -> Response {
-    // Check if ZtClient can reach ZeroTier API by checking if we have status
-    let zt = state.zt_state.read().await;
-    let zt_connected = zt.status.is_some() && zt.error.is_none();
+#[derive(Serialize)]
+pub struct ZeroTierCheck {
+    pub ok: bool,
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ConfigCheck {
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PollerCheck {
+    pub ok: bool,
+    /// Seconds since the last poll finished, none before the first
+    pub last_poll_secs: Option<u64>,
+    /// Seconds expected between polls right now
+    pub interval_secs: u64,
+    /// Seconds the next poll is overdue
+    pub lag_secs: u64,
+}
+
+impl DeepChecks {
+    fn ok(&self) -> bool {
+        self.zerotier.ok && self.config.ok && self.poller.ok
+    }
+}
+
+#[derive(Deserialize)]
+pub struct HealthQuery {
+    #[serde(default)]
+    deep: Option<String>,
+}
+
+/// GET /health - Whether the last poll reached ZeroTier; with `?deep=1`, also asks ZeroTier
+/// directly, checks the database takes writes and that the poller is keeping up
+pub async fn health_check(State(state): State<AppState>, Query(query): Query<HealthQuery>) -> Response {
+    // Check if the backend can reach ZeroTier API by checking if we have status
+    let zt_connected = {
+        let zt = state.zt_state.read().await;
+        zt.status.is_some() && zt.error.is_none()
+    };
+
+    let deep = query.deep.is_some_and(|d| d != "0" && d != "false");
+    let checks = if deep { Some(deep_checks(&state).await) } else { None };
+    let healthy = zt_connected && checks.as_ref().is_none_or(DeepChecks::ok);
 
     let response = HealthResponse {
-        status: if zt_connected { "healthy" } else { "degraded" },
+        status: if healthy { "healthy" } else { "degraded" },
         zt_connected,
         version: crate::VERSION,
+        checks,
     };
 
-    // Return 200 if healthy, 503 if ZT unreachable
-    if zt_connected {
+    // Return 200 if healthy, 503 if ZT unreachable or a deep check failed
+    if healthy {
         Json(response).into_response()
     } else {
         (StatusCode::SERVICE_UNAVAILABLE, Json(response)).into_response()
     }
 }
+
+async fn deep_checks(state: &AppState) -> DeepChecks {
+    let (zerotier, config) = tokio::join!(probe_zerotier(state), check_config(state));
+    DeepChecks {
+        zerotier,
+        config,
+        poller: check_poller(state).await,
+    }
+}
+
+async fn probe_zerotier(state: &AppState) -> ZeroTierCheck {
+    let Some(client) = state.zt_client.read().await.clone() else {
+        return ZeroTierCheck {
+            ok: false,
+            latency_ms: None,
+            error: Some("Not configured".to_string()),
+        };
+    };
+    let started = Instant::now();
+    let error = match tokio::time::timeout(PROBE_TIMEOUT, client.get_status()).await {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(e),
+        Err(_) => Some(format!("No answer within {}s", PROBE_TIMEOUT.as_secs())),
+    };
+    ZeroTierCheck {
+        ok: error.is_none(),
+        latency_ms: error.is_none().then(|| started.elapsed().as_millis() as u64),
+        error,
+    }
+}
+
+async fn check_config(state: &AppState) -> ConfigCheck {
+    if !state.is_configured().await {
+        return ConfigCheck {
+            ok: false,
+            error: Some("Not configured".to_string()),
+        };
+    }
+    let error = tokio::task::spawn_blocking(|| crate::store::check_writable(&crate::state::db_path()))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+        .err();
+    ConfigCheck { ok: error.is_none(), error }
+}
+
+async fn check_poller(state: &AppState) -> PollerCheck {
+    let interval = poller::poll_interval(&state.viewers);
+    let last_updated = {
+        let zt = state.zt_state.read().await;
+        zt.last_updated.filter(|_| zt.polled())
+    };
+    let age = last_updated.map(|t| SystemTime::now().duration_since(t).unwrap_or_default());
+    let lag = age.map_or(Duration::ZERO, |age| age.saturating_sub(interval));
+    PollerCheck {
+        // Before the first poll there is nothing to lag behind yet
+        ok: lag <= MAX_POLL_LAG,
+        last_poll_secs: age.map(|a| a.as_secs()),
+        interval_secs: interval.as_secs(),
+        lag_secs: lag.as_secs(),
+    }
+}
@@ -0,0 +1,257 @@
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Form;
+use chrono::Utc;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::invites::{self, JoinRequest};
+use crate::notifications::{EventKind, NotificationEvent};
+use crate::state::AppState;
+
+/// What an invite link reveals once it is opened
+#[derive(Clone)]
+pub struct JoinDetails {
+    pub nwid: String,
+    pub network_name: String,
+    pub is_private: bool,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "join.html")]
+pub struct JoinTemplate {
+    pub token: String,
+    /// Single-use links ask for a click first so link previews don't consume them
+    pub needs_confirm: bool,
+    pub details: Option<JoinDetails>,
+    /// Show the node ID form
+    pub can_request: bool,
+    /// A join request was just accepted
+    pub submitted: bool,
+    pub request_error: Option<String>,
+    pub error: Option<String>,
+}
+
+impl JoinTemplate {
+    fn new(token: String) -> Self {
+        Self {
+            token,
+            needs_confirm: false,
+            details: None,
+            can_request: false,
+            submitted: false,
+            request_error: None,
+            error: None,
+        }
+    }
+}
+
+fn invalid_invite(token: String) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        JoinTemplate {
+            error: Some("This invite link is invalid, has expired, or has already been used.".to_string()),
+            ..JoinTemplate::new(token)
+        },
+    )
+        .into_response()
+}
+
+async fn join_details(state: &AppState, nwid: String) -> JoinDetails {
+    let zt = state.zt_state.read().await;
+    let network = zt.controller_networks.iter().find(|n| n.display_id() == nwid);
+    JoinDetails {
+        network_name: network.map(|n| n.display_name().to_string()).unwrap_or_default(),
+        is_private: network.is_none_or(|n| n.is_private()),
+        nwid,
+    }
+}
+
+/// Return the join details, or None if the link can't be used. Single-use invites are used up
+/// here; viewing a multi-use invite changes nothing, so public page views don't write the config.
+async fn redeem(state: &AppState, token: &str) -> Option<(JoinDetails, bool)> {
+    let (nwid, can_request) = {
+        let mut config = state.config.write().await;
+        let c = config.as_mut()?;
+        let invite = c.invites.iter_mut().find(|i| i.token == token && i.is_valid())?;
+        let single_use = invite.single_use;
+        if single_use {
+            invite.uses += 1;
+        }
+        let found = (invite.nwid.clone(), invite.accepts_requests());
+        if single_use {
+            if let Err(e) = c.save() {
+                warn!("Failed to record invite use: {}", e);
+            }
+        }
+        found
+    };
+    Some((join_details(state, nwid).await, can_request))
+}
+
+/// GET /join/{token} - Public invite page
+pub async fn join_page(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Response {
+    let single_use = {
+        let config = state.config.read().await;
+        match config.as_ref().and_then(|c| c.invites.iter().find(|i| i.token == token && i.is_valid())) {
+            Some(invite) => invite.single_use,
+            None => return invalid_invite(token),
+        }
+    };
+
+    if single_use {
+        return JoinTemplate {
+            needs_confirm: true,
+            ..JoinTemplate::new(token)
+        }.into_response();
+    }
+
+    join_confirm(State(state), Path(token)).await
+}
+
+/// POST /join/{token} - Reveal the network for a single-use invite
+pub async fn join_confirm(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Response {
+    match redeem(&state, &token).await {
+        Some((details, can_request)) => JoinTemplate {
+            details: Some(details),
+            can_request,
+            ..JoinTemplate::new(token)
+        }.into_response(),
+        None => invalid_invite(token),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JoinRequestForm {
+    pub node_id: String,
+    #[serde(default)]
+    pub name: String,
+}
+
+/// POST /join/{token}/request - Submit a node ID for approval. The member is created
+/// unauthorized and an admin approves or rejects it from the network page.
+pub async fn join_request(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Form(form): Form<JoinRequestForm>,
+) -> Response {
+    let accepting = {
+        let config = state.config.read().await;
+        config
+            .as_ref()
+            .and_then(|c| c.invites.iter().find(|i| i.token == token && i.accepts_requests()))
+            .map(|i| i.nwid.clone())
+    };
+    let Some(nwid) = accepting else {
+        return invalid_invite(token);
+    };
+    let details = join_details(&state, nwid.clone()).await;
+    let name: String = form.name.trim().chars().take(64).collect();
+
+    let rejected = |message: String| {
+        JoinTemplate {
+            details: Some(details.clone()),
+            can_request: true,
+            request_error: Some(message),
+            ..JoinTemplate::new(token.clone())
+        }.into_response()
+    };
+
+    let node_id = match invites::parse_node_id(&form.node_id) {
+        Ok(n) => n,
+        Err(e) => return rejected(e),
+    };
+
+    let client = state.zt_client.read().await.clone();
+    let Some(client) = client else {
+        return rejected("The controller is not available. Try again later.".to_string());
+    };
+
+    // Never touch an existing member: re-posting authorized=false would de-authorize it.
+    // The controller answers unknown members with an empty object.
+    match client.get_controller_member(&nwid, &node_id).await {
+        Ok(m) if m.address.is_some() || m.id.is_some() => {
+            return rejected("This device has already joined or requested to join this network.".to_string());
+        }
+        Ok(_) => {}
+        Err(e) => {
+            warn!("Join request for {} on {} failed: {}", node_id, nwid, e);
+            return rejected("The controller is not available. Try again later.".to_string());
+        }
+    }
+
+    // Claim a request slot before creating the member so concurrent submissions
+    // can't exceed the invite's limit
+    let invited_by = {
+        let mut config = state.config.write().await;
+        let invite = config
+            .as_mut()
+            .and_then(|c| c.invites.iter_mut().find(|i| i.token == token && i.accepts_requests()));
+        match invite {
+            Some(invite) => {
+                invite.requested.push(node_id.clone());
+                invite.created_by.clone()
+            }
+            None => return invalid_invite(token),
+        }
+    };
+
+    if let Err(e) = client
+        .update_controller_member(&nwid, &node_id, serde_json::json!({"authorized": false}))
+        .await
+    {
+        warn!("Join request for {} on {} failed: {}", node_id, nwid, e);
+        let mut config = state.config.write().await;
+        if let Some(invite) = config.as_mut().and_then(|c| c.invites.iter_mut().find(|i| i.token == token)) {
+            invite.requested.retain(|n| *n != node_id);
+        }
+        return rejected("The request could not be submitted. Try again later.".to_string());
+    }
+    info!("Join request from {} on {} via invite by {}", node_id, nwid, invited_by);
+
+    {
+        let mut config = state.config.write().await;
+        if let Some(ref mut c) = *config {
+            if !name.is_empty() && !c.member_names.contains_key(&node_id) {
+                c.member_names.insert(node_id.clone(), name.clone());
+            }
+            c.join_requests.retain(|r| !(r.nwid == nwid && r.member_id == node_id));
+            c.join_requests.push(JoinRequest {
+                nwid: nwid.clone(),
+                member_id: node_id.clone(),
+                name,
+                invited_by,
+                requested_at: Utc::now(),
+            });
+            if let Err(e) = c.save() {
+                warn!("Failed to save join request: {}", e);
+            }
+        }
+    }
+
+    state
+        .send_notification(NotificationEvent {
+            kind: EventKind::JoinRequested,
+            nwid: nwid.clone(),
+            network_name: details.network_name.clone(),
+            member_id: Some(node_id),
+            detail: None,
+        })
+        .await;
+    state.notify_poller();
+
+    JoinTemplate {
+        details: Some(details),
+        submitted: true,
+        ..JoinTemplate::new(token)
+    }.into_response()
+}
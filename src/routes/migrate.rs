@@ -0,0 +1,646 @@
+//! Moving a network onto this controller from another TierDrop's export or from ZeroTier Central
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use axum::{Extension, Form};
+use axum_extra::extract::Multipart;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::permissions;
+use crate::routes::controller::{NetworkExport, NetworkMetadata, NETWORK_EXPORT_FORMAT};
+use crate::routes::error::error_response;
+use crate::state::{AppState, User};
+use crate::zt::central::{CentralClient, CentralNetwork};
+
+/// How long an uploaded export waits for the import to be confirmed
+const PENDING_MIGRATION_MINUTES: i64 = 15;
+
+/// Network fields the controller computes itself; sending them back is pointless or rejected
+const NETWORK_READ_ONLY: [&str; 8] = [
+    "id",
+    "nwid",
+    "objtype",
+    "revision",
+    "creationTime",
+    "authorizedMemberCount",
+    "activeMemberCount",
+    "totalMemberCount",
+];
+
+/// Member fields carried over to the new network
+const MEMBER_FIELDS: [&str; 8] = [
+    "authorized",
+    "activeBridge",
+    "noAutoAssignIps",
+    "ipAssignments",
+    "tags",
+    "capabilities",
+    "name",
+    "ssoExempt",
+];
+
+/// An uploaded export waiting for the admin to confirm the import
+pub struct PendingMigration {
+    token: String,
+    created_at: DateTime<Utc>,
+    export: NetworkExport,
+}
+
+/// Migration preview shown before anything is created
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/migrate_preview.html")]
+pub struct MigratePreviewTemplate {
+    pub token: String,
+    pub source_nwid: String,
+    pub name: String,
+    /// "exported" or "fetched from ZeroTier Central", followed by the time
+    pub origin: &'static str,
+    pub exported_at: String,
+    pub node_address: String,
+    /// Last six hex digits of the new network ID, initially those of the old one
+    pub suffix: String,
+    pub member_count: usize,
+    pub authorized_count: usize,
+    pub pools: Vec<String>,
+    pub route_count: usize,
+    pub rule_count: usize,
+    pub has_rules_source: bool,
+    pub named_members: usize,
+    /// Why the suggested network ID can't be used as is
+    pub conflict: Option<String>,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/migrate_result.html")]
+pub struct MigrateResultTemplate {
+    pub error: Option<String>,
+    pub nwid: String,
+    pub name: String,
+    pub imported: usize,
+    /// Members the controller refused, with the reason
+    pub failed: Vec<String>,
+}
+
+impl MigrateResultTemplate {
+    fn error(message: impl Into<String>) -> Response {
+        Self {
+            error: Some(message.into()),
+            nwid: String::new(),
+            name: String::new(),
+            imported: 0,
+            failed: Vec::new(),
+        }
+        .into_response()
+    }
+}
+
+fn str_field<'a>(value: &'a serde_json::Value, key: &str) -> &'a str {
+    value.get(key).and_then(|v| v.as_str()).unwrap_or("")
+}
+
+fn array_len(value: &serde_json::Value, key: &str) -> usize {
+    value.get(key).and_then(|v| v.as_array()).map_or(0, |a| a.len())
+}
+
+fn is_hex(s: &str, len: usize) -> bool {
+    s.len() == len && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Read and check an uploaded export
+fn parse_export(data: &[u8]) -> Result<NetworkExport, String> {
+    let export: NetworkExport =
+        serde_json::from_slice(data).map_err(|e| format!("Not a TierDrop network export: {}", e))?;
+    if export.format != NETWORK_EXPORT_FORMAT {
+        return Err("Not a TierDrop network export. Use Download JSON on the network's page.".to_string());
+    }
+    if export.version != 1 {
+        return Err(format!("Unsupported export version {}", export.version));
+    }
+    if !is_hex(str_field(&export.network, "id"), 16) {
+        return Err("The export has no valid network ID".to_string());
+    }
+    Ok(export)
+}
+
+/// Why a network ID can't be created here, if it can't
+async fn nwid_conflict(state: &AppState, nwid: &str) -> Option<String> {
+    let zt = state.zt_state.read().await;
+    if zt.controller_networks.iter().any(|n| n.display_id() == nwid) {
+        Some(format!("Network {} already exists on this controller", nwid))
+    } else {
+        None
+    }
+}
+
+async fn node_address(state: &AppState) -> Option<String> {
+    let zt = state.zt_state.read().await;
+    zt.status.as_ref().and_then(|s| s.address.clone())
+}
+
+/// POST /settings/migrate/preview - Upload a network export and show what importing it creates
+pub async fn preview_migration(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    mut multipart: Multipart,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Only administrators can migrate networks");
+    }
+
+    let mut data = None;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() == Some("network_file") {
+            match field.bytes().await {
+                Ok(bytes) => data = Some(bytes),
+                Err(e) => return MigrateResultTemplate::error(format!("Failed to read upload: {}", e)),
+            }
+            break;
+        }
+    }
+    let Some(data) = data else {
+        return MigrateResultTemplate::error("No export file provided");
+    };
+    match parse_export(&data) {
+        Ok(export) => stage_migration(&state, export, "exported").await,
+        Err(e) => MigrateResultTemplate::error(e),
+    }
+}
+
+/// Keep an export for import and show its preview. `origin` says where it came from.
+async fn stage_migration(state: &AppState, export: NetworkExport, origin: &'static str) -> Response {
+    let Some(node_address) = node_address(state).await else {
+        return MigrateResultTemplate::error("This node's address isn't known yet. Check the ZeroTier connection.");
+    };
+
+    let network = &export.network;
+    let source_nwid = str_field(network, "id").to_lowercase();
+    let suffix = source_nwid[10..].to_string();
+    let conflict = nwid_conflict(state, &format!("{}{}", node_address, suffix)).await;
+    let pools = network
+        .get("ipAssignmentPools")
+        .and_then(|p| p.as_array())
+        .map(|pools| {
+            pools
+                .iter()
+                .map(|p| format!("{} - {}", str_field(p, "ipRangeStart"), str_field(p, "ipRangeEnd")))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut token_bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut token_bytes);
+    let token = URL_SAFE_NO_PAD.encode(token_bytes);
+
+    let template = MigratePreviewTemplate {
+        token: token.clone(),
+        name: str_field(network, "name").to_string(),
+        origin,
+        exported_at: crate::preferences::format_time(export.exported_at),
+        node_address,
+        suffix,
+        member_count: export.members.len(),
+        authorized_count: export
+            .members
+            .iter()
+            .filter(|m| m.get("authorized").and_then(|a| a.as_bool()).unwrap_or(false))
+            .count(),
+        pools,
+        route_count: array_len(network, "routes"),
+        rule_count: array_len(network, "rules"),
+        has_rules_source: export.tierdrop.rules_source.is_some(),
+        named_members: export.tierdrop.member_names.len(),
+        conflict,
+        source_nwid,
+    };
+
+    // Only the latest upload can be imported
+    *state.pending_migration.write().await = Some(PendingMigration {
+        token,
+        created_at: Utc::now(),
+        export,
+    });
+
+    template.into_response()
+}
+
+#[derive(Deserialize)]
+pub struct MigrateImportForm {
+    token: String,
+    suffix: String,
+    /// "mirror" keeps each member's authorization, "unauthorized" pre-creates them all unauthorized
+    #[serde(default)]
+    member_auth: String,
+}
+
+/// POST /settings/migrate/import - Create the previewed network on this controller
+pub async fn import_migration(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Form(form): Form<MigrateImportForm>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Only administrators can migrate networks");
+    }
+
+    let suffix = form.suffix.trim().to_lowercase();
+    if !is_hex(&suffix, 6) {
+        return MigrateResultTemplate::error("The network ID suffix must be 6 hex digits");
+    }
+    let Some(node_address) = node_address(&state).await else {
+        return MigrateResultTemplate::error("This node's address isn't known yet. Check the ZeroTier connection.");
+    };
+    let nwid = format!("{}{}", node_address, suffix);
+    if let Some(conflict) = nwid_conflict(&state, &nwid).await {
+        return MigrateResultTemplate::error(format!("{}. Choose another suffix.", conflict));
+    }
+
+    let pending = {
+        let mut pending = state.pending_migration.write().await;
+        match pending.take() {
+            Some(p) if p.token == form.token => p,
+            other => {
+                *pending = other;
+                return MigrateResultTemplate::error("This preview is no longer current. Upload the export again.");
+            }
+        }
+    };
+    if Utc::now() - pending.created_at > chrono::Duration::minutes(PENDING_MIGRATION_MINUTES) {
+        return MigrateResultTemplate::error("This preview has expired. Upload the export again.");
+    }
+    let export = pending.export;
+    let source_nwid = str_field(&export.network, "id").to_lowercase();
+
+    let mut network = export.network.clone();
+    if let Some(obj) = network.as_object_mut() {
+        for key in NETWORK_READ_ONLY {
+            obj.remove(key);
+        }
+    }
+
+    let client = state.zt_client.read().await;
+    let Some(c) = client.as_ref() else {
+        return MigrateResultTemplate::error("ZeroTier client not initialized");
+    };
+    // Posting to an unused ID under this controller's address creates the network
+    if let Err(e) = c.update_controller_network(&nwid, network).await {
+        return MigrateResultTemplate::error(format!("Failed to create network {}: {}", nwid, e));
+    }
+
+    let mut imported = Vec::new();
+    let mut failed = Vec::new();
+    for member in &export.members {
+        let address = str_field(member, "address");
+        let address = if address.is_empty() { str_field(member, "id") } else { address };
+        if !is_hex(address, 10) {
+            failed.push("member without a valid address".to_string());
+            continue;
+        }
+        let mut body: serde_json::Map<String, serde_json::Value> = MEMBER_FIELDS
+            .iter()
+            .filter_map(|key| member.get(*key).map(|v| (key.to_string(), v.clone())))
+            .collect();
+        if form.member_auth == "unauthorized" {
+            body.insert("authorized".to_string(), false.into());
+        }
+        match c.update_controller_member(&nwid, address, body.into()).await {
+            Ok(_) => imported.push(address.to_string()),
+            Err(e) => failed.push(format!("{}: {}", address, e)),
+        }
+    }
+    drop(client);
+
+    let meta = export.tierdrop;
+    let name = str_field(&export.network, "name").to_string();
+    {
+        let mut config = state.config.write().await;
+        if let Some(ref mut c) = *config {
+            if let Some(description) = meta.description {
+                c.network_descriptions.insert(nwid.clone(), description);
+            }
+            if let Some(source) = meta.rules_source {
+                c.rules_source.insert(nwid.clone(), source);
+            }
+            if let Some(definitions) = meta.rule_definitions {
+                c.rule_definitions.insert(nwid.clone(), definitions);
+            }
+            // Names and descriptions are per node, so they carry over for imported members only
+            for (address, name) in meta.member_names {
+                if imported.contains(&address) {
+                    c.member_names.insert(address, name);
+                }
+            }
+            for (address, description) in meta.member_descriptions {
+                if imported.contains(&address) {
+                    c.member_descriptions.insert(address, description);
+                }
+            }
+            for address in meta.monitored_members {
+                if imported.contains(&address) {
+                    c.monitored_members.insert(address);
+                }
+            }
+            c.network_owners.insert(nwid.clone(), user.id);
+            c.audit(
+                &user.username,
+                "Migrate network",
+                Some(&nwid),
+                None,
+                format!("Imported from {} with {} of {} members", source_nwid, imported.len(), export.members.len()),
+            );
+            if let Err(e) = c.save() {
+                tracing::error!("Failed to save metadata for migrated network {}: {}", nwid, e);
+            }
+        }
+    }
+
+    state.notify_poller();
+    MigrateResultTemplate {
+        error: None,
+        nwid,
+        name,
+        imported: imported.len(),
+        failed,
+    }
+    .into_response()
+}
+
+/// POST /settings/migrate/cancel - Discard the uploaded export
+pub async fn cancel_migration(State(state): State<AppState>, Extension(user): Extension<User>) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Only administrators can migrate networks");
+    }
+    *state.pending_migration.write().await = None;
+    StatusCode::OK.into_response()
+}
+
+// ---- ZeroTier Central ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/central_networks.html")]
+pub struct CentralNetworksTemplate {
+    pub networks: Vec<CentralNetwork>,
+}
+
+#[derive(Deserialize)]
+pub struct CentralForm {
+    token: String,
+    #[serde(default)]
+    network_id: String,
+}
+
+/// Turn a Central network and its members into the shape of a TierDrop network export.
+/// Central wraps the controller fields in `config` and keeps names and notes beside them.
+fn central_export(network: serde_json::Value, members: Vec<serde_json::Value>) -> Result<NetworkExport, String> {
+    let nwid = str_field(&network, "id").to_lowercase();
+    if !is_hex(&nwid, 16) {
+        return Err("ZeroTier Central returned a network without a valid ID".to_string());
+    }
+    let mut config = network.get("config").cloned().unwrap_or_else(|| serde_json::json!({}));
+    config["id"] = nwid.clone().into();
+
+    let mut metadata = NetworkMetadata {
+        description: non_empty(&network, "description"),
+        rules_source: non_empty(&network, "rulesSource"),
+        ..Default::default()
+    };
+    let mut exported_members = Vec::with_capacity(members.len());
+    for member in members {
+        let address = str_field(&member, "nodeId").to_lowercase();
+        if !is_hex(&address, 10) {
+            continue;
+        }
+        let mut member_config = member.get("config").cloned().unwrap_or_else(|| serde_json::json!({}));
+        member_config["address"] = address.clone().into();
+        if let Some(name) = non_empty(&member, "name") {
+            member_config["name"] = name.clone().into();
+            metadata.member_names.insert(address.clone(), name);
+        }
+        if let Some(description) = non_empty(&member, "description") {
+            metadata.member_descriptions.insert(address, description);
+        }
+        exported_members.push(member_config);
+    }
+    exported_members.sort_by(|a, b| str_field(a, "address").cmp(str_field(b, "address")));
+
+    Ok(NetworkExport {
+        format: NETWORK_EXPORT_FORMAT.to_string(),
+        version: 1,
+        exported_at: Utc::now(),
+        tierdrop_version: crate::VERSION.to_string(),
+        network: config,
+        members: exported_members,
+        tierdrop: metadata,
+    })
+}
+
+/// POST /settings/migrate/central/networks - List the networks a Central API token can see
+pub async fn central_networks(Extension(user): Extension<User>, Form(form): Form<CentralForm>) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Only administrators can migrate networks");
+    }
+    let token = form.token.trim();
+    if token.is_empty() {
+        return MigrateResultTemplate::error("Enter a ZeroTier Central API token");
+    }
+    match CentralClient::new(token.to_string()).list_networks().await {
+        Ok(mut networks) => {
+            networks.sort_by(|a, b| a.config.name.cmp(&b.config.name));
+            CentralNetworksTemplate { networks }.into_response()
+        }
+        Err(e) => MigrateResultTemplate::error(e),
+    }
+}
+
+/// POST /settings/migrate/central/preview - Fetch a Central network and preview importing it
+pub async fn central_preview(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Form(form): Form<CentralForm>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Only administrators can migrate networks");
+    }
+    let nwid = form.network_id.trim().to_lowercase();
+    if !is_hex(&nwid, 16) {
+        return MigrateResultTemplate::error("Choose a network to import");
+    }
+
+    let central = CentralClient::new(form.token.trim().to_string());
+    let fetched = async {
+        let network = central.get_network(&nwid).await?;
+        let members = central.get_members(&nwid).await?;
+        central_export(network, members)
+    }
+    .await;
+    match fetched {
+        Ok(export) => stage_migration(&state, export, "fetched from ZeroTier Central").await,
+        Err(e) => MigrateResultTemplate::error(e),
+    }
+}
+
+// ---- Names from other controller UIs ----
+
+/// Names and descriptions read from a ztncui or ZeroUI export
+#[derive(Default)]
+struct ImportedNames {
+    member_names: Vec<(String, String)>,
+    member_descriptions: Vec<(String, String)>,
+    network_descriptions: Vec<(String, String)>,
+}
+
+fn non_empty(value: &serde_json::Value, key: &str) -> Option<String> {
+    let s = str_field(value, key).trim();
+    (!s.is_empty()).then(|| s.to_string())
+}
+
+/// ZeroUI keeps everything in one lowdb `db.json` with a `networks` array
+fn parse_zeroui(db: &serde_json::Value, names: &mut ImportedNames) -> bool {
+    let Some(networks) = db.get("networks").and_then(|n| n.as_array()) else {
+        return false;
+    };
+    for network in networks {
+        let nwid = str_field(network, "id").to_lowercase();
+        if !is_hex(&nwid, 16) {
+            continue;
+        }
+        if let Some(description) = non_empty(network, "description") {
+            names.network_descriptions.push((nwid.clone(), description));
+        }
+        for member in network.get("members").and_then(|m| m.as_array()).into_iter().flatten() {
+            let address = str_field(member, "id").to_lowercase();
+            if !is_hex(&address, 10) || member.get("deleted").and_then(|d| d.as_bool()).unwrap_or(false) {
+                continue;
+            }
+            if let Some(name) = non_empty(member, "name") {
+                names.member_names.push((address.clone(), name));
+            }
+            if let Some(description) = non_empty(member, "description") {
+                names.member_descriptions.push((address, description));
+            }
+        }
+    }
+    true
+}
+
+/// ztncui keeps each member name in its own node-persist file: `{"key": "<member id>", "value": "<name>"}`
+fn parse_ztncui(entry: &serde_json::Value, names: &mut ImportedNames) -> bool {
+    let (Some(key), Some(value)) = (entry.get("key").and_then(|k| k.as_str()), entry.get("value")) else {
+        return false;
+    };
+    // Some versions prefix the key with the network ID
+    let address = key.rsplit(['_', ':']).next().unwrap_or(key).to_lowercase();
+    if !is_hex(&address, 10) {
+        return false;
+    }
+    if let Some(name) = value.as_str().map(str::trim).filter(|n| !n.is_empty()) {
+        names.member_names.push((address, name.to_string()));
+    }
+    true
+}
+
+fn count(n: usize, noun: &str) -> String {
+    format!("{} {}{}", n, noun, if n == 1 { "" } else { "s" })
+}
+
+/// Insert imported values, keeping existing ones unless told to overwrite. Returns (set, kept).
+fn merge_names(
+    target: &mut std::collections::HashMap<String, String>,
+    imported: Vec<(String, String)>,
+    overwrite: bool,
+) -> (usize, usize) {
+    let (mut set, mut kept) = (0, 0);
+    for (key, value) in imported {
+        match target.get(&key) {
+            Some(existing) if *existing == value => {}
+            Some(_) if !overwrite => kept += 1,
+            _ => {
+                target.insert(key, value);
+                set += 1;
+            }
+        }
+    }
+    (set, kept)
+}
+
+/// POST /settings/migrate/names - Member names and network descriptions from ztncui or ZeroUI
+pub async fn import_names(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    mut multipart: Multipart,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Only administrators can import names");
+    }
+
+    let mut names = ImportedNames::default();
+    let mut overwrite = false;
+    let mut files = 0;
+    let mut unrecognized = 0;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        match field.name() {
+            Some("overwrite") => overwrite = true,
+            Some("names_file") => {
+                let Ok(data) = field.bytes().await else {
+                    unrecognized += 1;
+                    continue;
+                };
+                if data.is_empty() {
+                    continue;
+                }
+                files += 1;
+                let recognized = serde_json::from_slice::<serde_json::Value>(&data)
+                    .is_ok_and(|v| parse_zeroui(&v, &mut names) || parse_ztncui(&v, &mut names));
+                if !recognized {
+                    unrecognized += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if files == 0 {
+        return Html(r#"<div class="password-result error">Choose a ZeroUI db.json or ztncui storage files.</div>"#.to_string()).into_response();
+    }
+    if files == unrecognized {
+        return Html(r#"<div class="password-result error">Not a ZeroUI db.json or ztncui storage file.</div>"#.to_string())
+            .into_response();
+    }
+
+    let mut config = state.config.write().await;
+    let Some(ref mut c) = *config else {
+        return Html(r#"<div class="password-result error">No configuration found.</div>"#.to_string()).into_response();
+    };
+    let (names_set, names_kept) = merge_names(&mut c.member_names, names.member_names, overwrite);
+    let (descriptions_set, descriptions_kept) =
+        merge_names(&mut c.member_descriptions, names.member_descriptions, overwrite);
+    let (networks_set, networks_kept) =
+        merge_names(&mut c.network_descriptions, names.network_descriptions, overwrite);
+    let summary = format!(
+        "{}, {} and {}",
+        count(names_set, "member name"),
+        count(descriptions_set, "member description"),
+        count(networks_set, "network description")
+    );
+    c.audit(&user.username, "Import names", None, None, summary.clone());
+    if let Err(e) = c.save() {
+        return Html(format!(r#"<div class="password-result error">Failed to save: {}</div>"#, e)).into_response();
+    }
+    drop(config);
+
+    let mut message = format!("Imported {}.", summary);
+    let kept = names_kept + descriptions_kept + networks_kept;
+    if kept > 0 {
+        message.push_str(&format!(" Kept {} already set in TierDrop.", count(kept, "different value")));
+    }
+    if unrecognized > 0 {
+        message.push_str(&format!(" Skipped {}.", count(unrecognized, "unrecognized file")));
+    }
+    Html(format!(r#"<div class="password-result success">{}</div>"#, message)).into_response()
+}
@@ -1,5 +1,13 @@
+pub mod agent;
+pub mod approve;
 pub mod backup;
 pub mod controller;
 pub mod dashboard;
+pub mod error;
 pub mod health;
+pub mod join;
+pub mod migrate;
+pub mod planet;
 pub mod settings;
+pub mod share;
+pub mod zerotier;
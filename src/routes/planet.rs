@@ -0,0 +1,269 @@
+//! Custom roots for fully self-hosted deployments: replacing ZeroTier's `planet` file and adding
+//! or removing moons in `moons.d`. Files are written into the ZeroTier data directory found by
+//! `backup::zerotier_data_dir`; whatever they replace is kept next to them with a `.bak` suffix.
+//! ZeroTier only reads them when it starts.
+
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::extract::{Path as AxumPath, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use axum_extra::extract::Multipart;
+use chrono::{DateTime, Utc};
+use std::path::{Path, PathBuf};
+
+use crate::permissions;
+use crate::routes::backup::zerotier_data_dir;
+use crate::routes::error::error_response;
+use crate::state::{AppState, User};
+
+/// World type byte of a planet file
+const TYPE_PLANET: u8 = 1;
+/// World type byte of a moon file
+const TYPE_MOON: u8 = 127;
+
+/// Type, ID, timestamp, signing key and signature come before the roots
+const MIN_WORLD_LEN: usize = 1 + 8 + 8 + 64 + 96 + 1;
+/// ZeroTier refuses worlds larger than this
+const MAX_WORLD_LEN: usize = 4096;
+
+/// A planet or moon definition, read from the header of its file
+pub struct World {
+    /// Planet or moon ID as 16 hex digits, the moon file's name in `moons.d`
+    pub id: String,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub size: usize,
+}
+
+impl World {
+    /// Parse the header of a world file of the given type
+    fn parse(data: &[u8], kind: u8) -> Result<Self, String> {
+        let expected = if kind == TYPE_PLANET { "planet" } else { "moon" };
+        if data.len() < MIN_WORLD_LEN || data.len() > MAX_WORLD_LEN {
+            return Err(format!("This isn't a ZeroTier {} file: it is {} bytes", expected, data.len()));
+        }
+        match data[0] {
+            t if t == kind => {}
+            TYPE_PLANET => return Err(format!("This is a planet file, not a {} file", expected)),
+            TYPE_MOON => return Err(format!("This is a moon file, not a {} file", expected)),
+            _ => return Err(format!("This isn't a ZeroTier {} file", expected)),
+        }
+        let id = u64::from_be_bytes(data[1..9].try_into().unwrap_or_default());
+        let timestamp = u64::from_be_bytes(data[9..17].try_into().unwrap_or_default());
+        Ok(Self {
+            id: format!("{:016x}", id),
+            timestamp: DateTime::from_timestamp_millis(timestamp as i64),
+            size: data.len(),
+        })
+    }
+
+    fn read(path: &Path, kind: u8) -> Option<Self> {
+        std::fs::read(path).ok().and_then(|data| Self::parse(&data, kind).ok())
+    }
+
+    pub fn display_timestamp(&self) -> String {
+        self.timestamp
+            .map(crate::preferences::format_time)
+            .unwrap_or_else(|| "-".to_string())
+    }
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/planet.html")]
+pub struct PlanetTemplate {
+    /// The planet file in use, unless ZeroTier is on its built-in one
+    pub planet: Option<World>,
+    /// Whether an earlier planet was kept as `planet.bak`
+    pub planet_backup: bool,
+    pub moons: Vec<World>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+fn planet_path(zt_dir: &Path) -> PathBuf {
+    zt_dir.join("planet")
+}
+
+fn moons_dir(zt_dir: &Path) -> PathBuf {
+    zt_dir.join("moons.d")
+}
+
+/// Move `path` aside to `<path>.bak`, replacing an older backup
+fn back_up(path: &Path) -> std::io::Result<()> {
+    if path.exists() {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(".bak");
+        std::fs::rename(path, backup)?;
+    }
+    Ok(())
+}
+
+/// Write `data` to `path` through a temporary file, so ZeroTier never sees half a file
+fn replace_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, data)?;
+    back_up(path)?;
+    std::fs::rename(&tmp, path)
+}
+
+fn missing_dir(zt_dir: &Path) -> String {
+    format!("The ZeroTier data directory {} doesn't exist on this host", zt_dir.display())
+}
+
+fn render(message: Option<String>, error: Option<String>) -> Response {
+    let zt_dir = zerotier_data_dir();
+    let mut moons: Vec<World> = std::fs::read_dir(moons_dir(&zt_dir))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "moon"))
+        .filter_map(|p| World::read(&p, TYPE_MOON))
+        .collect();
+    moons.sort_by(|a, b| a.id.cmp(&b.id));
+    PlanetTemplate {
+        planet: World::read(&planet_path(&zt_dir), TYPE_PLANET),
+        planet_backup: zt_dir.join("planet.bak").exists(),
+        moons,
+        message,
+        error,
+    }
+    .into_response()
+}
+
+/// The uploaded file in `field`, if any
+async fn read_upload(multipart: &mut Multipart, field_name: &str) -> Result<Vec<u8>, String> {
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() == Some(field_name) {
+            return field
+                .bytes()
+                .await
+                .map(|b| b.to_vec())
+                .map_err(|e| format!("Failed to read upload: {}", e));
+        }
+    }
+    Err("No file provided".to_string())
+}
+
+async fn audit(state: &AppState, user: &User, action: &str, detail: String) {
+    let mut config = state.config.write().await;
+    if let Some(c) = config.as_mut() {
+        c.audit(&user.username, action, None, None, detail);
+        if let Err(e) = c.save() {
+            tracing::warn!("Failed to save audit log: {}", e);
+        }
+    }
+}
+
+/// GET /settings/planet - The custom planet and moons in the ZeroTier directory
+pub async fn planet_panel(Extension(user): Extension<User>) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    render(None, None)
+}
+
+/// POST /settings/planet - Replace the planet file, keeping the old one as `planet.bak`
+pub async fn upload_planet(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    mut multipart: Multipart,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    let data = match read_upload(&mut multipart, "planet_file").await {
+        Ok(d) => d,
+        Err(e) => return render(None, Some(e)),
+    };
+    let world = match World::parse(&data, TYPE_PLANET) {
+        Ok(w) => w,
+        Err(e) => return render(None, Some(e)),
+    };
+    let zt_dir = zerotier_data_dir();
+    if !zt_dir.is_dir() {
+        return render(None, Some(missing_dir(&zt_dir)));
+    }
+    let path = planet_path(&zt_dir);
+    if let Err(e) = replace_file(&path, &data) {
+        return render(None, Some(format!("Failed to write {}: {}", path.display(), e)));
+    }
+    audit(&state, &user, "Planet replaced", format!("Planet {}", world.id)).await;
+    render(
+        Some("Planet saved. Restart ZeroTier to use it, and give members the same planet file.".to_string()),
+        None,
+    )
+}
+
+/// POST /settings/planet/restore - Put the planet that was replaced last back
+pub async fn restore_planet(State(state): State<AppState>, Extension(user): Extension<User>) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    let zt_dir = zerotier_data_dir();
+    let backup = zt_dir.join("planet.bak");
+    let Ok(data) = std::fs::read(&backup) else {
+        return render(None, Some("There is no earlier planet to go back to".to_string()));
+    };
+    if let Err(e) = replace_file(&planet_path(&zt_dir), &data) {
+        return render(None, Some(format!("Failed to restore the planet: {}", e)));
+    }
+    audit(&state, &user, "Planet restored", "Previous planet file".to_string()).await;
+    render(Some("Previous planet restored. Restart ZeroTier to use it.".to_string()), None)
+}
+
+/// POST /settings/planet/moons - Add a moon, or replace one with the same ID
+pub async fn upload_moon(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    mut multipart: Multipart,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    let data = match read_upload(&mut multipart, "moon_file").await {
+        Ok(d) => d,
+        Err(e) => return render(None, Some(e)),
+    };
+    let world = match World::parse(&data, TYPE_MOON) {
+        Ok(w) => w,
+        Err(e) => return render(None, Some(e)),
+    };
+    let zt_dir = zerotier_data_dir();
+    if !zt_dir.is_dir() {
+        return render(None, Some(missing_dir(&zt_dir)));
+    }
+    let dir = moons_dir(&zt_dir);
+    let path = dir.join(format!("{}.moon", world.id));
+    let created = if dir.is_dir() { Ok(()) } else { std::fs::create_dir(&dir) };
+    if let Err(e) = created.and_then(|_| replace_file(&path, &data)) {
+        return render(None, Some(format!("Failed to write {}: {}", path.display(), e)));
+    }
+    audit(&state, &user, "Moon added", format!("Moon {}", world.id)).await;
+    render(Some(format!("Moon {} saved. Restart ZeroTier to use it.", world.id)), None)
+}
+
+/// DELETE /settings/planet/moons/{id} - Remove a moon, keeping its file as `<id>.moon.bak`
+pub async fn remove_moon(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    AxumPath(id): AxumPath<String>,
+) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    if id.len() != 16 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return error_response(StatusCode::BAD_REQUEST, "Invalid moon ID");
+    }
+    let path = moons_dir(&zerotier_data_dir()).join(format!("{}.moon", id.to_ascii_lowercase()));
+    if !path.exists() {
+        return render(None, Some(format!("Moon {} isn't installed", id)));
+    }
+    if let Err(e) = back_up(&path) {
+        return render(None, Some(format!("Failed to remove moon {}: {}", id, e)));
+    }
+    audit(&state, &user, "Moon removed", format!("Moon {}", id)).await;
+    render(Some(format!("Moon {} removed. Restart ZeroTier to stop using it.", id)), None)
+}
@@ -2,16 +2,39 @@ use std::collections::HashMap;
 
 use askama::Template;
 use askama_web::WebTemplate;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::response::{Html, IntoResponse, Response};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
 use axum::Form;
 use axum::Extension;
-use serde::Deserialize;
+use axum_extra::extract::Multipart;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
+use crate::api_keys::ApiKey;
+use crate::approvals::ApprovalLinkSettings;
 use crate::auth::{hash_password, verify_password};
-use crate::routes::backup::BackupStatus;
-use crate::state::{AppState, NetworkPermissions, User};
+use crate::audit::{AuditEntry, LoginEvent};
+use crate::base_path;
+use crate::branding::{self, Branding};
+use crate::consistency::{self, ConsistencyReport};
+use crate::dns::{self, DnsRecord, DnsServerSettings, DnsServerStatus};
+use crate::email::{self, SmtpSecurity, SmtpSettings};
+use crate::oidc::{self, OidcSettings};
+use crate::passkeys::{self, Passkey, PendingChallenge, RegistrationResponse, RequestHost};
+use crate::permissions;
+use crate::preferences::{self, DateFormat, Landing, Preferences, Theme};
+use crate::releases::{self, ReleaseInfo};
+use crate::notifications::{
+    ChannelKind, EventKind, NotificationChannel, NotificationRoute, NotificationSettings,
+};
+use crate::routes::backup::{BackupContents, BackupStatus, OPTIONAL_ZT_PATHS};
+use crate::routes::error::error_response;
+use crate::schedule::{self, BackupRetention, CronExpr, JobAction, JobSchedule, ScheduledJob};
+use crate::sessions::ActiveSession;
+use crate::sse::SseEvent;
+use crate::state::{AppState, NetworkGroup, NetworkPermissions, Role, User, UserGroup, ALL_NETWORKS};
 use crate::zt::models::ControllerNetwork;
 
 #[derive(Template, WebTemplate)]
@@ -25,6 +48,17 @@ pub struct SettingsTemplate {
     pub users: Vec<User>,
     pub current_username: String,
     pub totp_enabled: bool,
+    pub require_2fa: bool,
+    pub remember_me_days: u32,
+    /// "Require 2FA" is on and this user still has to set up a second factor
+    pub enrollment_required: bool,
+    pub email: String,
+    /// Whether the user has a password to confirm account changes with
+    pub has_password: bool,
+    pub backup_retention: BackupRetention,
+    pub backup_contents: BackupContents,
+    pub optional_zt_paths: [(&'static str, &'static str); 3],
+    pub access_log: bool,
 }
 
 pub async fn settings_page(
@@ -34,9 +68,20 @@ pub async fn settings_page(
     let status = BackupStatus::fetch(&state).await;
     let backup_type = status.backup_type().to_string();
 
-    let users = {
+    let (users, require_2fa, remember_me_days, enrollment_required, backup_retention, backup_contents, access_log) = {
         let config = state.config.read().await;
-        config.as_ref().map(|c| c.users.clone()).unwrap_or_default()
+        match config.as_ref() {
+            Some(c) => (
+                c.users.clone(),
+                c.require_2fa,
+                c.remember_me_days,
+                current_user.needs_2fa_enrollment(c),
+                c.backup_retention.clone(),
+                c.backup_contents.clone(),
+                c.access_log,
+            ),
+            None => (Vec::new(), false, 0, false, BackupRetention::default(), BackupContents::default(), false),
+        }
     };
 
     SettingsTemplate {
@@ -48,7 +93,189 @@ pub async fn settings_page(
         users,
         current_username: current_user.username.clone(),
         totp_enabled: current_user.totp_enabled,
+        require_2fa,
+        remember_me_days,
+        enrollment_required,
+        email: current_user.email.clone().unwrap_or_default(),
+        has_password: !current_user.is_sso_only(),
+        backup_retention,
+        backup_contents,
+        optional_zt_paths: OPTIONAL_ZT_PATHS,
+        access_log,
+    }
+}
+
+#[derive(Deserialize)]
+pub struct EmailForm {
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    current_password: String,
+}
+
+/// Whether `password` is the user's current password. Single sign-on-only accounts have none
+/// to give, so they pass.
+fn confirms_password(user: &User, password: &str) -> bool {
+    user.is_sso_only() || verify_password(password, &user.password_hash)
+}
+
+/// An email address from a form; empty clears it
+fn parse_email(value: &str) -> Result<Option<String>, &'static str> {
+    let email = value.trim();
+    if email.is_empty() {
+        Ok(None)
+    } else if email::is_valid_address(email) {
+        Ok(Some(email.to_string()))
+    } else {
+        Err("That doesn't look like an email address.")
+    }
+}
+
+/// POST /settings/email - Set the current user's email address
+pub async fn update_email(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<EmailForm>,
+) -> impl IntoResponse {
+    let email = match parse_email(&form.email) {
+        Ok(e) => e,
+        Err(e) => return Html(format!(r#"<div class="password-result error">{}</div>"#, e)),
+    };
+    // The address receives password reset links, so changing it takes the password
+    if !confirms_password(&current_user, &form.current_password) {
+        return Html(r#"<div class="password-result error">Current password is incorrect.</div>"#.to_string());
+    }
+
+    let mut config = state.config.write().await;
+    if let Some(ref mut c) = *config {
+        if let Some(user) = c.find_user_by_id_mut(current_user.id) {
+            user.email = email;
+            if let Err(e) = c.save() {
+                return Html(format!(r#"<div class="password-result error">Failed to save config: {}</div>"#, e));
+            }
+        } else {
+            return Html(r#"<div class="password-result error">User not found.</div>"#.to_string());
+        }
+    }
+
+    Html(r#"<div class="password-result success">Email saved.</div>"#.to_string())
+}
+
+// ---- Preferences ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/preferences.html")]
+pub struct PreferencesTemplate {
+    pub preferences: Preferences,
+    /// Networks the user can open, as (ID, name), for the landing page choice
+    pub networks: Vec<(String, String)>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+async fn render_preferences(
+    state: &AppState,
+    user: &User,
+    preferences: Preferences,
+    message: Option<String>,
+    error: Option<String>,
+) -> Response {
+    let networks = {
+        let zt = state.zt_state.read().await;
+        zt.controller_networks
+            .iter()
+            .filter(|n| permissions::can_read(user, n.display_id()))
+            .map(|n| (n.display_id().to_string(), n.display_name().to_string()))
+            .collect()
+    };
+    PreferencesTemplate {
+        preferences,
+        networks,
+        message,
+        error,
+    }
+    .into_response()
+}
+
+/// GET /settings/preferences - The current user's UI preferences
+pub async fn preferences_panel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    let preferences = current_user.preferences.clone();
+    render_preferences(&state, &current_user, preferences, None, None).await
+}
+
+#[derive(Deserialize)]
+pub struct PreferencesForm {
+    landing: String,
+    rows_per_page: usize,
+    date_format: String,
+    #[serde(default)]
+    timezone: String,
+    theme: String,
+    show_physical_address: Option<String>,
+}
+
+/// POST /settings/preferences - Save the current user's UI preferences
+pub async fn update_preferences(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<PreferencesForm>,
+) -> Response {
+    let timezone = match preferences::parse_timezone(&form.timezone) {
+        Ok(tz) => tz,
+        Err(e) => {
+            let preferences = current_user.preferences.clone();
+            return render_preferences(&state, &current_user, preferences, None, Some(e)).await;
+        }
+    };
+    let preferences = Preferences {
+        landing: Landing::parse(&form.landing),
+        rows_per_page: if preferences::ROWS_PER_PAGE.contains(&form.rows_per_page) {
+            form.rows_per_page
+        } else {
+            Preferences::default().rows_per_page
+        },
+        date_format: DateFormat::parse(&form.date_format),
+        timezone,
+        theme: Theme::parse(&form.theme),
+        show_physical_address: form.show_physical_address.is_some(),
+    };
+    if let Err(e) = save_preferences(&state, current_user.id, preferences.clone()).await {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, e);
     }
+    let message = "Preferences saved. Reload the page to see them everywhere.".to_string();
+    render_preferences(&state, &current_user, preferences, Some(message), None).await
+}
+
+#[derive(Deserialize)]
+pub struct ThemeForm {
+    theme: String,
+}
+
+/// POST /settings/preferences/theme - Keep a saved theme in step with the toggle in the top bar
+pub async fn update_theme_preference(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<ThemeForm>,
+) -> Response {
+    let preferences = Preferences {
+        theme: Theme::parse(&form.theme),
+        ..current_user.preferences
+    };
+    match save_preferences(&state, current_user.id, preferences).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+async fn save_preferences(state: &AppState, user_id: u64, preferences: Preferences) -> Result<(), String> {
+    let mut config = state.config.write().await;
+    let c = config.as_mut().ok_or("Not configured")?;
+    let user = c.find_user_by_id_mut(user_id).ok_or("User not found")?;
+    user.preferences = preferences;
+    c.save().map_err(|e| format!("Failed to save config: {}", e))
 }
 
 #[derive(Deserialize)]
@@ -153,6 +380,55 @@ pub async fn change_username(
 pub struct UsersListTemplate {
     pub users: Vec<User>,
     pub current_user_id: u64,
+    pub group_names: HashMap<u64, String>,
+}
+
+/// Group ID -> name, for the users list
+async fn group_names(state: &AppState) -> HashMap<u64, String> {
+    let config = state.config.read().await;
+    config
+        .as_ref()
+        .map(|c| c.groups.iter().map(|g| (g.id, g.name.clone())).collect())
+        .unwrap_or_default()
+}
+
+/// What permissions can be set on: each network, then each network group, by
+/// `network_permissions` key
+async fn permission_keys(state: &AppState) -> Vec<String> {
+    let mut keys: Vec<String> = {
+        let zt = state.zt_state.read().await;
+        zt.controller_networks.iter().map(|n| n.display_id().to_string()).collect()
+    };
+    let config = state.config.read().await;
+    if let Some(c) = config.as_ref() {
+        keys.extend(c.network_groups.iter().map(NetworkGroup::permission_key));
+    }
+    keys
+}
+
+/// Per-network permissions from `perm_{key}_{read,authorize,modify,delete}` checkboxes
+fn permissions_from_form(
+    fields: &HashMap<String, String>,
+    networks: &[String],
+) -> HashMap<String, NetworkPermissions> {
+    let mut permissions = HashMap::new();
+    let keys = networks.iter().map(String::as_str).chain([ALL_NETWORKS]);
+    for nwid in keys {
+        let read = fields.contains_key(&format!("perm_{}_read", nwid));
+        let authorize = fields.contains_key(&format!("perm_{}_authorize", nwid));
+        let modify = fields.contains_key(&format!("perm_{}_modify", nwid));
+        let delete = fields.contains_key(&format!("perm_{}_delete", nwid));
+
+        if read || authorize || modify || delete {
+            permissions.insert(nwid.to_string(), NetworkPermissions {
+                read,
+                authorize,
+                modify,
+                delete,
+            });
+        }
+    }
+    permissions
 }
 
 /// GET /settings/users - Users list partial
@@ -161,7 +437,7 @@ pub async fn users_list(
     Extension(current_user): Extension<User>,
 ) -> Response {
     if !current_user.is_admin {
-        return (StatusCode::FORBIDDEN, "Admin access required").into_response();
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
     }
 
     let users = {
@@ -172,6 +448,7 @@ pub async fn users_list(
     UsersListTemplate {
         users,
         current_user_id: current_user.id,
+        group_names: group_names(&state).await,
     }.into_response()
 }
 
@@ -190,7 +467,7 @@ pub async fn create_user(
     Form(form): Form<CreateUserForm>,
 ) -> Response {
     if !current_user.is_admin {
-        return (StatusCode::FORBIDDEN, "Admin access required").into_response();
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
     }
 
     let username = form.username.trim().to_string();
@@ -237,6 +514,7 @@ pub async fn create_user(
     UsersListTemplate {
         users,
         current_user_id: current_user.id,
+        group_names: group_names(&state).await,
     }.into_response()
 }
 
@@ -245,6 +523,9 @@ pub async fn create_user(
 pub struct UserModalTemplate {
     pub user: User,
     pub networks: Vec<ControllerNetwork>,
+    pub network_groups: Vec<NetworkGroup>,
+    pub groups: Vec<UserGroup>,
+    pub roles: [Role; 3],
 }
 
 /// GET /settings/users/{id}/modal - User edit modal
@@ -254,23 +535,25 @@ pub async fn user_modal(
     Path(user_id): Path<u64>,
 ) -> Response {
     if !current_user.is_admin {
-        return (StatusCode::FORBIDDEN, "Admin access required").into_response();
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
     }
 
-    let (user, networks) = {
+    let (user, networks, network_groups, groups) = {
         let config = state.config.read().await;
         let user = config.as_ref()
             .and_then(|c| c.find_user_by_id(user_id).cloned());
+        let groups = config.as_ref().map(|c| c.groups.clone()).unwrap_or_default();
+        let network_groups = config.as_ref().map(|c| c.network_groups.clone()).unwrap_or_default();
 
         let zt = state.zt_state.read().await;
         let networks = zt.controller_networks.clone();
 
-        (user, networks)
+        (user, networks, network_groups, groups)
     };
 
     match user {
-        Some(user) => UserModalTemplate { user, networks }.into_response(),
-        None => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Some(user) => UserModalTemplate { user, networks, network_groups, groups, roles: Role::ALL }.into_response(),
+        None => error_response(StatusCode::NOT_FOUND, "User not found"),
     }
 }
 
@@ -280,7 +563,15 @@ pub struct UpdateUserForm {
     #[serde(default)]
     password: String,
     #[serde(default)]
+    email: String,
+    #[serde(default)]
     is_admin: Option<String>,
+    #[serde(default)]
+    disabled: Option<String>,
+    #[serde(default)]
+    role: String,
+    #[serde(default)]
+    can_create_networks: Option<String>,
     #[serde(flatten)]
     permissions: HashMap<String, String>,
 }
@@ -293,19 +584,24 @@ pub async fn update_user(
     Form(form): Form<UpdateUserForm>,
 ) -> Response {
     if !current_user.is_admin {
-        return (StatusCode::FORBIDDEN, "Admin access required").into_response();
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
     }
 
     let username = form.username.trim().to_string();
     if username.is_empty() {
         return Html(r#"<div class="alert alert-error">Username is required.</div>"#.to_string()).into_response();
     }
-
-    // Get networks for building permissions (use display_id which handles Option<String>)
-    let networks: Vec<String> = {
-        let zt = state.zt_state.read().await;
-        zt.controller_networks.iter().map(|n| n.display_id().to_string()).collect()
+    let email = match parse_email(&form.email) {
+        Ok(e) => e,
+        Err(e) => return Html(format!(r#"<div class="alert alert-error">{}</div>"#, e)).into_response(),
     };
+    let disabled = form.disabled.as_deref() == Some("true");
+    if disabled && user_id == current_user.id {
+        return Html(r#"<div class="alert alert-error">Cannot disable your own account.</div>"#.to_string()).into_response();
+    }
+
+    // Networks and network groups for building permissions
+    let networks = permission_keys(&state).await;
 
     let users = {
         let mut config = state.config.write().await;
@@ -317,8 +613,10 @@ pub async fn update_user(
                 }
             }
 
+            let group_ids: Vec<u64> = c.groups.iter().map(|g| g.id).collect();
             if let Some(user) = c.find_user_by_id_mut(user_id) {
                 user.username = username;
+                user.email = email;
 
                 // Update password if provided
                 if !form.password.is_empty() {
@@ -334,31 +632,41 @@ pub async fn update_user(
                 }
 
                 user.is_admin = form.is_admin.as_deref() == Some("true");
+                user.role = Role::parse(&form.role);
+                user.can_create_networks = form.can_create_networks.as_deref() == Some("true");
+                if !disabled && !user.has_sign_in() {
+                    return Html(r#"<div class="alert alert-error">Set a password before enabling this user.</div>"#.to_string()).into_response();
+                }
+                let disabled_changed = user.disabled != disabled;
+                user.disabled = disabled;
 
                 // Build network permissions from form
                 // Form fields are like: perm_NWID_read, perm_NWID_authorize, etc.
-                user.network_permissions.clear();
-                for nwid in &networks {
-                    let read = form.permissions.contains_key(&format!("perm_{}_read", nwid));
-                    let authorize = form.permissions.contains_key(&format!("perm_{}_authorize", nwid));
-                    let modify = form.permissions.contains_key(&format!("perm_{}_modify", nwid));
-                    let delete = form.permissions.contains_key(&format!("perm_{}_delete", nwid));
-
-                    if read || authorize || modify || delete {
-                        user.network_permissions.insert(nwid.clone(), NetworkPermissions {
-                            read,
-                            authorize,
-                            modify,
-                            delete,
-                        });
-                    }
+                user.network_permissions = permissions_from_form(&form.permissions, &networks);
+
+                // Group checkboxes are group_ID
+                user.groups = group_ids
+                    .iter()
+                    .copied()
+                    .filter(|id| form.permissions.contains_key(&format!("group_{}", id)))
+                    .collect();
+
+                if disabled_changed {
+                    let username = user.username.clone();
+                    c.audit(
+                        &current_user.username,
+                        if disabled { "User disabled" } else { "User enabled" },
+                        None,
+                        None,
+                        username,
+                    );
                 }
 
                 if let Err(e) = c.save() {
                     return Html(format!(r#"<div class="alert alert-error">Failed to save: {}</div>"#, e)).into_response();
                 }
             } else {
-                return (StatusCode::NOT_FOUND, "User not found").into_response();
+                return error_response(StatusCode::NOT_FOUND, "User not found");
             }
             c.users.clone()
         } else {
@@ -366,10 +674,18 @@ pub async fn update_user(
         }
     };
 
+    if disabled {
+        let count = state.sessions.revoke_user(user_id).await;
+        if count > 0 {
+            tracing::info!("Revoked {} session(s) of disabled user {}", count, user_id);
+        }
+    }
+
     // Return updated users list with HX-Trigger to close modal
     let html = UsersListTemplate {
         users,
         current_user_id: current_user.id,
+        group_names: group_names(&state).await,
     };
 
     (
@@ -378,6 +694,60 @@ pub async fn update_user(
     ).into_response()
 }
 
+/// POST /settings/users/{id}/toggle-disabled - Disable or re-enable an account, keeping its
+/// permissions. Disabling logs the user out everywhere.
+pub async fn toggle_user_disabled(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Path(user_id): Path<u64>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    if user_id == current_user.id {
+        return Html(r#"<div class="alert alert-error">Cannot disable your own account.</div>"#.to_string()).into_response();
+    }
+
+    let (users, disabled) = {
+        let mut config = state.config.write().await;
+        let Some(ref mut c) = *config else {
+            return Html(r#"<div class="alert alert-error">No configuration found.</div>"#.to_string()).into_response();
+        };
+        let Some(user) = c.find_user_by_id_mut(user_id) else {
+            return error_response(StatusCode::NOT_FOUND, "User not found");
+        };
+        if user.disabled && !user.has_sign_in() {
+            return Html(r#"<div class="alert alert-error">Set a password before enabling this user.</div>"#.to_string()).into_response();
+        }
+        user.disabled = !user.disabled;
+        let (disabled, username) = (user.disabled, user.username.clone());
+        c.audit(
+            &current_user.username,
+            if disabled { "User disabled" } else { "User enabled" },
+            None,
+            None,
+            username,
+        );
+        if let Err(e) = c.save() {
+            return Html(format!(r#"<div class="alert alert-error">Failed to save: {}</div>"#, e)).into_response();
+        }
+        (c.users.clone(), disabled)
+    };
+
+    if disabled {
+        state.sessions.revoke_user(user_id).await;
+    }
+
+    (
+        [("HX-Trigger", "usersChanged")],
+        UsersListTemplate {
+            users,
+            current_user_id: current_user.id,
+            group_names: group_names(&state).await,
+        },
+    ).into_response()
+}
+
 /// DELETE /settings/users/{id} - Delete user
 pub async fn delete_user(
     State(state): State<AppState>,
@@ -385,7 +755,7 @@ pub async fn delete_user(
     Path(user_id): Path<u64>,
 ) -> Response {
     if !current_user.is_admin {
-        return (StatusCode::FORBIDDEN, "Admin access required").into_response();
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
     }
 
     // Prevent self-deletion
@@ -405,8 +775,9 @@ pub async fn delete_user(
             }
 
             if !c.remove_user(user_id) {
-                return (StatusCode::NOT_FOUND, "User not found").into_response();
+                return error_response(StatusCode::NOT_FOUND, "User not found");
             }
+            state.sessions.revoke_user(user_id).await;
 
             if let Err(e) = c.save() {
                 return Html(format!(r#"<div class="alert alert-error">Failed to save: {}</div>"#, e)).into_response();
@@ -420,155 +791,2280 @@ pub async fn delete_user(
     UsersListTemplate {
         users,
         current_user_id: current_user.id,
+        group_names: group_names(&state).await,
     }.into_response()
 }
 
-// ---- 2FA Settings ----
+#[derive(Deserialize)]
+pub struct Require2faForm {
+    require_2fa: Option<String>,
+}
 
-use totp_rs::{Algorithm, Secret, TOTP};
-use tower_sessions::Session;
+/// POST /settings/users/require-2fa - Make every user set up a second factor
+pub async fn update_require_2fa(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<Require2faForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
 
-const SESSION_TOTP_SETUP_SECRET: &str = "totp_setup_secret";
+    let require = form.require_2fa.is_some();
+    let mut config = state.config.write().await;
+    let Some(ref mut c) = *config else {
+        return Html(r#"<div class="password-result error">No configuration found.</div>"#.to_string()).into_response();
+    };
+    c.require_2fa = require;
+    let pending = c.users.iter().filter(|u| u.needs_2fa_enrollment(c)).count();
+    c.audit(
+        &current_user.username,
+        if require { "Two-factor authentication required" } else { "Two-factor authentication made optional" },
+        None,
+        None,
+        String::new(),
+    );
+    if let Err(e) = c.save() {
+        return Html(format!(r#"<div class="password-result error">Failed to save: {}</div>"#, e)).into_response();
+    }
 
-#[derive(Template, WebTemplate)]
-#[template(path = "partials/2fa_setup_modal.html")]
-pub struct TotpSetupModalTemplate {
-    pub qr_code_data_uri: String,
-    pub secret: String,
+    let message = match (require, pending) {
+        (false, _) => "Two-factor authentication is optional.".to_string(),
+        (true, 0) => "Two-factor authentication is required. Every user is already enrolled.".to_string(),
+        (true, n) => format!(
+            "Two-factor authentication is required. {} user{} must set it up before they can continue.",
+            n,
+            if n == 1 { "" } else { "s" }
+        ),
+    };
+    Html(format!(r#"<div class="password-result success">{}</div>"#, message)).into_response()
 }
 
-#[derive(Template, WebTemplate)]
-#[template(path = "partials/2fa_disable_modal.html")]
-pub struct TotpDisableModalTemplate;
-
-#[derive(Template, WebTemplate)]
-#[template(path = "partials/2fa_status.html")]
-pub struct TotpStatusTemplate {
-    pub totp_enabled: bool,
+#[derive(Deserialize)]
+pub struct AccessLogForm {
+    access_log: Option<String>,
 }
 
-/// GET /settings/2fa/setup - Show 2FA setup modal with QR code
-pub async fn totp_setup_modal(
-    session: Session,
+/// POST /settings/audit/access-log - Log every request with its user
+pub async fn update_access_log(
+    State(state): State<AppState>,
     Extension(current_user): Extension<User>,
+    Form(form): Form<AccessLogForm>,
 ) -> Response {
-    // Generate a new TOTP secret
-    let secret = Secret::generate_secret();
-    let secret_base32 = secret.to_encoded().to_string();
-
-    // Store secret in session for verification
-    if session.insert(SESSION_TOTP_SETUP_SECRET, &secret_base32).await.is_err() {
-        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store setup secret").into_response();
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
     }
 
-    // Create TOTP for QR code generation
-    let totp = match TOTP::new(
-        Algorithm::SHA1,
-        6,
-        1,
-        30,
-        secret.to_bytes().unwrap(),
-        Some("TierDrop".to_string()),
-        current_user.username.clone(),
-    ) {
-        Ok(t) => t,
-        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create TOTP").into_response(),
+    let enabled = form.access_log.is_some();
+    let mut config = state.config.write().await;
+    let Some(ref mut c) = *config else {
+        return Html(r#"<div class="password-result error">No configuration found.</div>"#.to_string()).into_response();
     };
+    if c.access_log != enabled {
+        c.access_log = enabled;
+        c.audit(
+            &current_user.username,
+            if enabled { "Access log turned on" } else { "Access log turned off" },
+            None,
+            None,
+            String::new(),
+        );
+        if let Err(e) = c.save() {
+            return Html(format!(r#"<div class="password-result error">Failed to save: {}</div>"#, e)).into_response();
+        }
+    }
 
-    // Generate QR code as data URI
-    let qr_code_data_uri = match totp.get_qr_base64() {
-        Ok(qr) => format!("data:image/png;base64,{}", qr),
-        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate QR code").into_response(),
+    let message = if enabled {
+        "Every request is now written to the server log."
+    } else {
+        "Requests are no longer logged."
     };
-
-    TotpSetupModalTemplate {
-        qr_code_data_uri,
-        secret: secret_base32,
-    }.into_response()
+    Html(format!(r#"<div class="password-result success">{}</div>"#, message)).into_response()
 }
 
 #[derive(Deserialize)]
-pub struct TotpEnableForm {
-    code: String,
+pub struct SessionSettingsForm {
+    remember_me_days: String,
 }
 
-/// POST /settings/2fa/enable - Verify code and enable 2FA
-pub async fn totp_enable(
-    session: Session,
+/// POST /settings/users/sessions - How long "remember me" logins last
+pub async fn update_session_settings(
     State(state): State<AppState>,
     Extension(current_user): Extension<User>,
-    Form(form): Form<TotpEnableForm>,
+    Form(form): Form<SessionSettingsForm>,
 ) -> Response {
-    // Get the setup secret from session
-    let secret_base32: String = match session.get(SESSION_TOTP_SETUP_SECRET).await {
-        Ok(Some(s)) => s,
-        _ => return (
-            [("HX-Trigger", r#"{"2fa-error":{"message":"Setup session expired. Please try again."}}"#)],
-            StatusCode::UNPROCESSABLE_ENTITY,
-        ).into_response(),
-    };
-
-    // Verify the code
-    let code = form.code.trim().replace(" ", "");
-    if !crate::auth::verify_totp(&code, &secret_base32) {
-        return (
-            [("HX-Trigger", r#"{"2fa-error":{"message":"Invalid verification code. Please try again."}}"#)],
-            StatusCode::UNPROCESSABLE_ENTITY,
-        ).into_response();
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
     }
 
-    // Code is valid - save secret to user and enable 2FA
-    {
-        let mut config = state.config.write().await;
-        if let Some(ref mut c) = *config {
-            if let Some(user) = c.find_user_by_id_mut(current_user.id) {
-                user.totp_enabled = true;
-                user.totp_secret = Some(secret_base32);
-                if let Err(e) = c.save() {
-                    let trigger = format!(r#"{{"2fa-error":{{"message":"Failed to save: {}"}}}}"#, e);
-                    return (
-                        [("HX-Trigger", trigger)],
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                    ).into_response();
-                }
-            } else {
-                return (
-                    [("HX-Trigger", r#"{"2fa-error":{"message":"User not found."}}"#)],
-                    StatusCode::NOT_FOUND,
-                ).into_response();
-            }
+    let days = match form.remember_me_days.trim().parse::<u32>() {
+        Ok(d) if d <= 365 => d,
+        _ => {
+            return Html(r#"<div class="password-result error">Enter a number of days from 0 to 365.</div>"#.to_string()).into_response();
         }
-    }
-
-    // Clear the setup secret from session
-    let _ = session.remove::<String>(SESSION_TOTP_SETUP_SECRET).await;
+    };
 
-    // Trigger success event - modal will close and refresh status
-    (
-        [("HX-Trigger", "2fa-enabled")],
-        StatusCode::OK,
-    ).into_response()
-}
+    let mut config = state.config.write().await;
+    let Some(ref mut c) = *config else {
+        return Html(r#"<div class="password-result error">No configuration found.</div>"#.to_string()).into_response();
+    };
+    c.remember_me_days = days;
+    if let Err(e) = c.save() {
+        return Html(format!(r#"<div class="password-result error">Failed to save: {}</div>"#, e)).into_response();
+    }
 
-/// GET /settings/2fa/disable-modal - Show disable 2FA confirmation modal
-pub async fn totp_disable_modal() -> Response {
-    TotpDisableModalTemplate.into_response()
+    let message = if days == 0 {
+        "\"Remember me\" is turned off. Sessions end after 30 minutes of inactivity.".to_string()
+    } else {
+        format!("\"Remember me\" sessions last {} day{} without activity.", days, if days == 1 { "" } else { "s" })
+    };
+    Html(format!(r#"<div class="password-result success">{}</div>"#, message)).into_response()
 }
 
 #[derive(Deserialize)]
-pub struct TotpDisableForm {
-    password: String,
+pub struct BackupRetentionForm {
+    keep_last: String,
+    keep_daily_days: String,
+    keep_weekly_weeks: String,
 }
 
-/// POST /settings/2fa/disable - Disable 2FA (requires password)
-pub async fn totp_disable(
+/// POST /settings/backup/retention - Which scheduled backups to keep
+pub async fn update_backup_retention(
     State(state): State<AppState>,
     Extension(current_user): Extension<User>,
-    Form(form): Form<TotpDisableForm>,
+    Form(form): Form<BackupRetentionForm>,
 ) -> Response {
-    // Verify password
-    if !verify_password(&form.password, &current_user.password_hash) {
-        return (
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let parse = |v: &str| v.trim().parse::<u32>().ok().filter(|n| *n <= 365);
+    let retention = match (parse(&form.keep_last), parse(&form.keep_daily_days), parse(&form.keep_weekly_weeks)) {
+        (Some(keep_last), Some(keep_daily_days), Some(keep_weekly_weeks)) if keep_last > 0 => BackupRetention {
+            keep_last,
+            keep_daily_days,
+            keep_weekly_weeks,
+        },
+        (Some(0), ..) => {
+            return Html(r#"<div class="password-result error">Keep at least the latest backup.</div>"#.to_string()).into_response();
+        }
+        _ => {
+            return Html(r#"<div class="password-result error">Enter whole numbers from 0 to 365.</div>"#.to_string()).into_response();
+        }
+    };
+
+    let mut config = state.config.write().await;
+    let Some(ref mut c) = *config else {
+        return Html(r#"<div class="password-result error">No configuration found.</div>"#.to_string()).into_response();
+    };
+    c.backup_retention = retention;
+    if let Err(e) = c.save() {
+        return Html(format!(r#"<div class="password-result error">Failed to save: {}</div>"#, e)).into_response();
+    }
+    Html(r#"<div class="password-result success">Retention saved. It applies after the next scheduled backup.</div>"#.to_string()).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct BackupContentsForm {
+    mode: String,
+    // One checkbox per OPTIONAL_ZT_PATHS entry, named after the path
+    #[serde(rename = "peers.d")]
+    peers_d: Option<String>,
+    #[serde(rename = "networks.d")]
+    networks_d: Option<String>,
+    #[serde(rename = "metrics.prom")]
+    metrics_prom: Option<String>,
+}
+
+/// POST /settings/backup/contents - What goes into backups
+pub async fn update_backup_contents(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<BackupContentsForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let checked = [form.peers_d, form.networks_d, form.metrics_prom];
+    let contents = BackupContents {
+        full_snapshot: form.mode != "custom",
+        excluded: OPTIONAL_ZT_PATHS
+            .iter()
+            .zip(checked)
+            .filter(|(_, on)| on.is_some())
+            .map(|((path, _), _)| path.to_string())
+            .collect(),
+    };
+
+    let mut config = state.config.write().await;
+    let Some(ref mut c) = *config else {
+        return Html(r#"<div class="password-result error">No configuration found.</div>"#.to_string()).into_response();
+    };
+    let summary = contents.summary();
+    c.backup_contents = contents;
+    if let Err(e) = c.save() {
+        return Html(format!(r#"<div class="password-result error">Failed to save: {}</div>"#, e)).into_response();
+    }
+    Html(format!(r#"<div class="password-result success">Saved. New backups: {}.</div>"#, summary)).into_response()
+}
+
+// ---- Groups (Admin only) ----
+
+pub struct GroupRow {
+    pub group: UserGroup,
+    /// Usernames of the members
+    pub members: Vec<String>,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/groups_list.html")]
+pub struct GroupsListTemplate {
+    pub groups: Vec<GroupRow>,
+    pub error: Option<String>,
+}
+
+async fn render_groups(state: &AppState, error: Option<String>) -> Response {
+    let groups = {
+        let config = state.config.read().await;
+        config
+            .as_ref()
+            .map(|c| {
+                c.groups
+                    .iter()
+                    .map(|g| GroupRow {
+                        group: g.clone(),
+                        members: c
+                            .users
+                            .iter()
+                            .filter(|u| u.groups.contains(&g.id))
+                            .map(|u| u.username.clone())
+                            .collect(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    GroupsListTemplate { groups, error }.into_response()
+}
+
+/// GET /settings/groups - Groups list partial
+pub async fn groups_list(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    render_groups(&state, None).await
+}
+
+#[derive(Deserialize)]
+pub struct CreateGroupForm {
+    name: String,
+}
+
+/// POST /settings/groups/create - Create an empty group
+pub async fn create_group(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<CreateGroupForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let name = form.name.trim().to_string();
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(_) if name.is_empty() => Err("Group name is required.".to_string()),
+            Some(ref c) if c.groups.iter().any(|g| g.name.eq_ignore_ascii_case(&name)) => {
+                Err("A group with that name already exists.".to_string())
+            }
+            Some(ref mut c) => {
+                c.add_group(name);
+                c.save().map_err(|e| format!("Failed to save: {}", e))
+            }
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+    render_groups(&state, result.err()).await
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/group_modal.html")]
+pub struct GroupModalTemplate {
+    pub group: UserGroup,
+    pub networks: Vec<ControllerNetwork>,
+    pub network_groups: Vec<NetworkGroup>,
+}
+
+/// GET /settings/groups/{id}/modal - Group edit modal
+pub async fn group_modal(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Path(group_id): Path<u64>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let (group, network_groups) = {
+        let config = state.config.read().await;
+        let group = config
+            .as_ref()
+            .and_then(|c| c.groups.iter().find(|g| g.id == group_id).cloned());
+        let network_groups = config.as_ref().map(|c| c.network_groups.clone()).unwrap_or_default();
+        (group, network_groups)
+    };
+    let networks = state.zt_state.read().await.controller_networks.clone();
+
+    match group {
+        Some(group) => GroupModalTemplate { group, networks, network_groups }.into_response(),
+        None => error_response(StatusCode::NOT_FOUND, "Group not found"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateGroupForm {
+    name: String,
+    #[serde(flatten)]
+    permissions: HashMap<String, String>,
+}
+
+/// POST /settings/groups/{id}/update - Rename a group and set its permissions
+pub async fn update_group(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Path(group_id): Path<u64>,
+    Form(form): Form<UpdateGroupForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let name = form.name.trim().to_string();
+    if name.is_empty() {
+        return Html(r#"<div class="alert alert-error">Group name is required.</div>"#.to_string()).into_response();
+    }
+
+    let networks = permission_keys(&state).await;
+
+    {
+        let mut config = state.config.write().await;
+        let Some(ref mut c) = *config else {
+            return Html(r#"<div class="alert alert-error">No configuration found.</div>"#.to_string()).into_response();
+        };
+        if c.groups.iter().any(|g| g.id != group_id && g.name.eq_ignore_ascii_case(&name)) {
+            return Html(r#"<div class="alert alert-error">A group with that name already exists.</div>"#.to_string()).into_response();
+        }
+        let Some(group) = c.find_group_by_id_mut(group_id) else {
+            return error_response(StatusCode::NOT_FOUND, "Group not found");
+        };
+        group.name = name;
+        group.network_permissions = permissions_from_form(&form.permissions, &networks);
+        if let Err(e) = c.save() {
+            return Html(format!(r#"<div class="alert alert-error">Failed to save: {}</div>"#, e)).into_response();
+        }
+    }
+
+    ([("HX-Trigger", "closeModal")], render_groups(&state, None).await).into_response()
+}
+
+/// DELETE /settings/groups/{id} - Delete a group and remove its memberships
+pub async fn delete_group(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Path(group_id): Path<u64>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => {
+                if c.remove_group(group_id) {
+                    c.save().map_err(|e| format!("Failed to save: {}", e))
+                } else {
+                    Err("Group not found.".to_string())
+                }
+            }
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+    ([("HX-Trigger", "usersChanged")], render_groups(&state, result.err()).await).into_response()
+}
+
+// ---- Network Groups (Admin only) ----
+
+/// A network group in the network groups list
+pub struct NetworkGroupRow {
+    pub group: NetworkGroup,
+    /// Names of the group's networks that still exist
+    pub networks: Vec<String>,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/network_groups_list.html")]
+pub struct NetworkGroupsListTemplate {
+    pub groups: Vec<NetworkGroupRow>,
+    pub error: Option<String>,
+}
+
+async fn render_network_groups(state: &AppState, error: Option<String>) -> Response {
+    let names: HashMap<String, String> = {
+        let zt = state.zt_state.read().await;
+        zt.controller_networks
+            .iter()
+            .map(|n| (n.display_id().to_string(), n.display_name().to_string()))
+            .collect()
+    };
+    let groups = {
+        let config = state.config.read().await;
+        config
+            .as_ref()
+            .map(|c| {
+                c.network_groups
+                    .iter()
+                    .map(|g| NetworkGroupRow {
+                        group: g.clone(),
+                        networks: g.networks.iter().filter_map(|n| names.get(n).cloned()).collect(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    NetworkGroupsListTemplate { groups, error }.into_response()
+}
+
+/// GET /settings/network-groups - Network groups list partial
+pub async fn network_groups_list(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    render_network_groups(&state, None).await
+}
+
+/// POST /settings/network-groups/create - Create an empty network group
+pub async fn create_network_group(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<CreateGroupForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let name = form.name.trim().to_string();
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(_) if name.is_empty() => Err("Group name is required.".to_string()),
+            Some(ref c) if c.network_groups.iter().any(|g| g.name.eq_ignore_ascii_case(&name)) => {
+                Err("A network group with that name already exists.".to_string())
+            }
+            Some(ref mut c) => {
+                c.add_network_group(name);
+                c.save().map_err(|e| format!("Failed to save: {}", e))
+            }
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+    render_network_groups(&state, result.err()).await
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/network_group_modal.html")]
+pub struct NetworkGroupModalTemplate {
+    pub group: NetworkGroup,
+    pub networks: Vec<ControllerNetwork>,
+    /// Network ID -> name of the other group it's in
+    pub grouped_elsewhere: HashMap<String, String>,
+}
+
+/// GET /settings/network-groups/{id}/modal - Network group edit modal
+pub async fn network_group_modal(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Path(group_id): Path<u64>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let (group, grouped_elsewhere) = {
+        let config = state.config.read().await;
+        let groups = config.as_ref().map(|c| c.network_groups.as_slice()).unwrap_or_default();
+        let group = groups.iter().find(|g| g.id == group_id).cloned();
+        let grouped_elsewhere = groups
+            .iter()
+            .filter(|g| g.id != group_id)
+            .flat_map(|g| g.networks.iter().map(|n| (n.clone(), g.name.clone())))
+            .collect();
+        (group, grouped_elsewhere)
+    };
+    let networks = state.zt_state.read().await.controller_networks.clone();
+
+    match group {
+        Some(group) => NetworkGroupModalTemplate { group, networks, grouped_elsewhere }.into_response(),
+        None => error_response(StatusCode::NOT_FOUND, "Network group not found"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateNetworkGroupForm {
+    name: String,
+    #[serde(flatten)]
+    networks: HashMap<String, String>,
+}
+
+/// POST /settings/network-groups/{id}/update - Rename a network group and choose its networks
+pub async fn update_network_group(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Path(group_id): Path<u64>,
+    Form(form): Form<UpdateNetworkGroupForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let name = form.name.trim().to_string();
+    if name.is_empty() {
+        return Html(r#"<div class="alert alert-error">Group name is required.</div>"#.to_string()).into_response();
+    }
+
+    // Network checkboxes are network_NWID
+    let networks: Vec<String> = {
+        let zt = state.zt_state.read().await;
+        zt.controller_networks
+            .iter()
+            .map(|n| n.display_id().to_string())
+            .filter(|nwid| form.networks.contains_key(&format!("network_{}", nwid)))
+            .collect()
+    };
+
+    {
+        let mut config = state.config.write().await;
+        let Some(ref mut c) = *config else {
+            return Html(r#"<div class="alert alert-error">No configuration found.</div>"#.to_string()).into_response();
+        };
+        if c.network_groups.iter().any(|g| g.id != group_id && g.name.eq_ignore_ascii_case(&name)) {
+            return Html(r#"<div class="alert alert-error">A network group with that name already exists.</div>"#.to_string()).into_response();
+        }
+        let Some(group) = c.find_network_group_by_id_mut(group_id) else {
+            return error_response(StatusCode::NOT_FOUND, "Network group not found");
+        };
+        group.name = name;
+        c.set_network_group_members(group_id, networks);
+        if let Err(e) = c.save() {
+            return Html(format!(r#"<div class="alert alert-error">Failed to save: {}</div>"#, e)).into_response();
+        }
+    }
+
+    // Access to the group's networks may have changed for its grantees
+    let _ = state.tx.send(SseEvent::ControllerNetworksChanged);
+    ([("HX-Trigger", "closeModal")], render_network_groups(&state, None).await).into_response()
+}
+
+/// DELETE /settings/network-groups/{id} - Delete a network group and the permissions granted on it
+pub async fn delete_network_group(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Path(group_id): Path<u64>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => {
+                if c.remove_network_group(group_id) {
+                    c.save().map_err(|e| format!("Failed to save: {}", e))
+                } else {
+                    Err("Network group not found.".to_string())
+                }
+            }
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+    let _ = state.tx.send(SseEvent::ControllerNetworksChanged);
+    ([("HX-Trigger", "usersChanged")], render_network_groups(&state, result.err()).await).into_response()
+}
+
+// ---- Users Export / Import (Admin only) ----
+
+/// Current version of the users export format. Version 2 added groups.
+const USERS_EXPORT_VERSION: u32 = 2;
+
+/// Portable authorization model. Contains no password hashes or TOTP secrets.
+#[derive(Serialize, Deserialize)]
+pub struct UsersExport {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub tierdrop_version: String,
+    pub users: Vec<ExportedUser>,
+    #[serde(default)]
+    pub groups: Vec<ExportedGroup>,
+}
+
+/// A user group, matched by name on import
+#[derive(Serialize, Deserialize)]
+pub struct ExportedGroup {
+    pub name: String,
+    #[serde(default)]
+    pub network_permissions: HashMap<String, NetworkPermissions>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedUser {
+    pub username: String,
+    pub is_admin: bool,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default)]
+    pub can_create_networks: bool,
+    #[serde(default)]
+    pub network_permissions: HashMap<String, NetworkPermissions>,
+    /// Names of the user's groups. Missing from version 1 files, whose users keep their groups.
+    #[serde(default)]
+    pub groups: Option<Vec<String>>,
+}
+
+impl ExportedUser {
+    fn new(user: &User, groups: &[UserGroup]) -> Self {
+        Self {
+            username: user.username.clone(),
+            is_admin: user.is_admin,
+            role: user.role,
+            can_create_networks: user.can_create_networks,
+            network_permissions: user.network_permissions.clone(),
+            groups: Some(
+                groups
+                    .iter()
+                    .filter(|g| user.groups.contains(&g.id))
+                    .map(|g| g.name.clone())
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// GET /settings/users/export - Download users and permissions as JSON
+pub async fn export_users(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let (users, groups) = {
+        let config = state.config.read().await;
+        match config.as_ref() {
+            Some(c) => (
+                c.users.iter().map(|u| ExportedUser::new(u, &c.groups)).collect(),
+                c.groups
+                    .iter()
+                    .map(|g| ExportedGroup {
+                        name: g.name.clone(),
+                        network_permissions: g.network_permissions.clone(),
+                    })
+                    .collect(),
+            ),
+            None => (Vec::new(), Vec::new()),
+        }
+    };
+
+    let export = UsersExport {
+        version: USERS_EXPORT_VERSION,
+        exported_at: Utc::now(),
+        tierdrop_version: crate::VERSION.to_string(),
+        users,
+        groups,
+    };
+
+    let json = match serde_json::to_string_pretty(&export) {
+        Ok(j) => j,
+        Err(e) => {
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize users: {}", e));
+        }
+    };
+
+    let filename = format!("tierdrop-users-{}.json", Utc::now().format("%Y%m%d-%H%M%S"));
+    (
+        [
+            (CONTENT_TYPE, "application/json".to_string()),
+            (CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+        ],
+        json,
+    )
+        .into_response()
+}
+
+/// How an imported users file is applied to the existing users
+#[derive(Clone, Copy, PartialEq)]
+enum ImportMode {
+    /// Update matching users and add new ones; leave everything else alone
+    Merge,
+    /// Make the user list match the import (the importing admin is always kept)
+    Replace,
+}
+
+fn import_result(class: &str, message: &str) -> Response {
+    Html(format!(r#"<div class="users-import-result {}">{}</div>"#, class, message)).into_response()
+}
+
+/// POST /settings/users/import - Merge or replace users and permissions from an export
+pub async fn import_users(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    mut multipart: Multipart,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let mut file_data: Option<Vec<u8>> = None;
+    let mut mode = ImportMode::Merge;
+
+    while let Ok(Some(field)) = multipart.next_field().await {
+        match field.name() {
+            Some("users_file") => match field.bytes().await {
+                Ok(bytes) => file_data = Some(bytes.to_vec()),
+                Err(e) => return import_result("error", &format!("Failed to read upload: {}", e)),
+            },
+            Some("mode") => {
+                if let Ok("replace") = field.text().await.as_deref() {
+                    mode = ImportMode::Replace;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let file_data = match file_data {
+        Some(d) => d,
+        None => return import_result("error", "No users file provided."),
+    };
+
+    let export: UsersExport = match serde_json::from_slice(&file_data) {
+        Ok(e) => e,
+        Err(e) => return import_result("error", &format!("Invalid users file: {}", e)),
+    };
+
+    if export.version > USERS_EXPORT_VERSION {
+        return import_result(
+            "error",
+            &format!("Unsupported users file version {}.", export.version),
+        );
+    }
+
+    let mut added = 0;
+    let mut updated = 0;
+    let mut removed = 0;
+    let (mut groups_added, mut groups_updated, mut groups_removed) = (0, 0, 0);
+    {
+        let mut config = state.config.write().await;
+        let c = match config.as_mut() {
+            Some(c) => c,
+            None => return import_result("error", "No configuration found."),
+        };
+
+        // Groups first, so memberships can be resolved by name
+        for imported in &export.groups {
+            let name = imported.name.trim();
+            if name.is_empty() {
+                continue;
+            }
+            let id = match c.groups.iter().find(|g| g.name == name) {
+                Some(g) => {
+                    groups_updated += 1;
+                    g.id
+                }
+                None => {
+                    groups_added += 1;
+                    c.add_group(name.to_string())
+                }
+            };
+            if let Some(group) = c.find_group_by_id_mut(id) {
+                group.network_permissions = imported.network_permissions.clone();
+            }
+        }
+        if mode == ImportMode::Replace {
+            let stale: Vec<u64> = c
+                .groups
+                .iter()
+                .filter(|g| !export.groups.iter().any(|i| i.name.trim() == g.name))
+                .map(|g| g.id)
+                .collect();
+            for id in stale {
+                c.remove_group(id);
+                groups_removed += 1;
+            }
+        }
+        let group_ids = |names: &Option<Vec<String>>, groups: &[UserGroup]| -> Option<Vec<u64>> {
+            names.as_ref().map(|names| {
+                groups
+                    .iter()
+                    .filter(|g| names.iter().any(|n| n.trim() == g.name))
+                    .map(|g| g.id)
+                    .collect()
+            })
+        };
+
+        for imported in &export.users {
+            let username = imported.username.trim();
+            if username.is_empty() {
+                continue;
+            }
+            let existing_id = c.find_user_by_username(username).map(|u| u.id);
+            match existing_id {
+                Some(id) => {
+                    // Never let an import demote or restrict the admin running it
+                    if id == current_user.id {
+                        continue;
+                    }
+                    let groups = group_ids(&imported.groups, &c.groups);
+                    if let Some(user) = c.find_user_by_id_mut(id) {
+                        user.is_admin = imported.is_admin;
+                        user.role = imported.role;
+                        user.can_create_networks = imported.can_create_networks;
+                        user.network_permissions = imported.network_permissions.clone();
+                        if let Some(groups) = groups {
+                            user.groups = groups;
+                        }
+                        updated += 1;
+                    }
+                }
+                None => {
+                    // Exports carry no passwords, so new users are created disabled with none.
+                    // They stay disabled until an admin sets a password and enables them.
+                    let groups = group_ids(&imported.groups, &c.groups);
+                    let user_id = c.add_user(username.to_string(), String::new(), imported.is_admin).id;
+                    if let Some(user) = c.find_user_by_id_mut(user_id) {
+                        user.disabled = true;
+                        user.role = imported.role;
+                        user.can_create_networks = imported.can_create_networks;
+                        user.network_permissions = imported.network_permissions.clone();
+                        user.groups = groups.unwrap_or_default();
+                    }
+                    added += 1;
+                }
+            }
+        }
+
+        if mode == ImportMode::Replace {
+            let before = c.users.len();
+            c.users.retain(|u| {
+                u.id == current_user.id
+                    || export.users.iter().any(|i| i.username.trim() == u.username)
+            });
+            removed = before - c.users.len();
+        }
+
+        if let Err(e) = c.save() {
+            return import_result("error", &format!("Failed to save: {}", e));
+        }
+    }
+
+    let message = format!(
+        "Import complete: {} added, {} updated, {} removed. Groups: {} added, {} updated, {} removed.{}",
+        added,
+        updated,
+        removed,
+        groups_added,
+        groups_updated,
+        groups_removed,
+        if added > 0 { " New users are disabled until you set a password and enable them." } else { "" }
+    );
+    (
+        [("HX-Trigger", "usersChanged")],
+        import_result("success", &message),
+    )
+        .into_response()
+}
+
+// ---- Notifications (Admin only) ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/notifications.html")]
+pub struct NotificationsTemplate {
+    pub settings: NotificationSettings,
+    pub networks: Vec<ControllerNetwork>,
+    pub event_kinds: [EventKind; 12],
+    pub channel_kinds: [ChannelKind; 3],
+    pub error: Option<String>,
+}
+
+impl NotificationsTemplate {
+    pub fn channel_name(&self, id: &u64) -> &str {
+        self.settings
+            .find_channel(*id)
+            .map(|c| c.name.as_str())
+            .unwrap_or("(deleted)")
+    }
+}
+
+async fn render_notifications(state: &AppState, error: Option<String>) -> Response {
+    let settings = {
+        let config = state.config.read().await;
+        config.as_ref().map(|c| c.notifications.clone()).unwrap_or_default()
+    };
+    let networks = state.zt_state.read().await.controller_networks.clone();
+
+    NotificationsTemplate {
+        settings,
+        networks,
+        event_kinds: EventKind::ALL,
+        channel_kinds: ChannelKind::ALL,
+        error,
+    }.into_response()
+}
+
+/// Apply a change to the notification settings and save, returning an error message on failure
+async fn update_notifications<F>(state: &AppState, f: F) -> Option<String>
+where
+    F: FnOnce(&mut NotificationSettings) -> Result<(), String>,
+{
+    let mut config = state.config.write().await;
+    match *config {
+        Some(ref mut c) => {
+            if let Err(e) = f(&mut c.notifications) {
+                return Some(e);
+            }
+            c.save().err().map(|e| format!("Failed to save: {}", e))
+        }
+        None => Some("No configuration found.".to_string()),
+    }
+}
+
+/// GET /settings/notifications - Channels and routing rules partial
+pub async fn notifications_panel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    render_notifications(&state, None).await
+}
+
+#[derive(Deserialize)]
+pub struct CreateChannelForm {
+    name: String,
+    #[serde(default)]
+    kind: String,
+    url: String,
+    // Checkbox fields: event_<kind>
+    #[serde(flatten)]
+    fields: HashMap<String, String>,
+}
+
+/// POST /settings/notifications/channels - Add a notification channel
+pub async fn create_notification_channel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<CreateChannelForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let name = form.name.trim().to_string();
+    let url = form.url.trim().to_string();
+    let events: Vec<EventKind> = EventKind::ALL
+        .into_iter()
+        .filter(|k| form.fields.contains_key(&format!("event_{}", k.as_str())))
+        .collect();
+    let error = if name.is_empty() {
+        Some("Channel name is required.".to_string())
+    } else if !(url.starts_with("http://") || url.starts_with("https://")) {
+        Some("Webhook URL must start with http:// or https://".to_string())
+    } else {
+        update_notifications(&state, |n| {
+            let id = n.allocate_id();
+            n.channels.push(NotificationChannel {
+                id,
+                name,
+                kind: ChannelKind::parse(&form.kind),
+                url,
+                events,
+            });
+            Ok(())
+        }).await
+    };
+
+    render_notifications(&state, error).await
+}
+
+/// DELETE /settings/notifications/channels/{id} - Remove a channel and the routes using it
+pub async fn delete_notification_channel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Path(channel_id): Path<u64>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let error = update_notifications(&state, |n| {
+        n.channels.retain(|c| c.id != channel_id);
+        n.routes.retain(|r| r.channel_id != channel_id);
+        Ok(())
+    }).await;
+
+    render_notifications(&state, error).await
+}
+
+#[derive(Deserialize)]
+pub struct CreateRouteForm {
+    channel_id: String,
+    // Checkbox fields: event_<kind>, network_<nwid>
+    #[serde(flatten)]
+    fields: HashMap<String, String>,
+}
+
+/// POST /settings/notifications/routes - Add a routing rule
+pub async fn create_notification_route(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<CreateRouteForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let events: Vec<EventKind> = EventKind::ALL
+        .into_iter()
+        .filter(|k| form.fields.contains_key(&format!("event_{}", k.as_str())))
+        .collect();
+    let mut networks: Vec<String> = form
+        .fields
+        .keys()
+        .filter_map(|k| k.strip_prefix("network_"))
+        .map(str::to_string)
+        .collect();
+    networks.sort();
+
+    let error = match form.channel_id.parse::<u64>() {
+        Ok(channel_id) => update_notifications(&state, |n| {
+            if n.find_channel(channel_id).is_none() {
+                return Err("Channel not found.".to_string());
+            }
+            let id = n.allocate_id();
+            n.routes.push(NotificationRoute {
+                id,
+                events,
+                networks,
+                channel_id,
+            });
+            Ok(())
+        }).await,
+        Err(_) => Some("Select a channel for this rule.".to_string()),
+    };
+
+    render_notifications(&state, error).await
+}
+
+/// DELETE /settings/notifications/routes/{id} - Remove a routing rule
+pub async fn delete_notification_route(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Path(route_id): Path<u64>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let error = update_notifications(&state, |n| {
+        n.routes.retain(|r| r.id != route_id);
+        Ok(())
+    }).await;
+
+    render_notifications(&state, error).await
+}
+
+#[derive(Deserialize)]
+pub struct ApprovalLinksForm {
+    enabled: Option<String>,
+    #[serde(default)]
+    base_url: String,
+    valid_hours: u32,
+}
+
+/// POST /settings/notifications/approval-links - Configure one-click approval links
+pub async fn update_approval_links(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<ApprovalLinksForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let enabled = form.enabled.is_some();
+    let base_url = form.base_url.trim().trim_end_matches('/').to_string();
+    let error = if enabled && !(base_url.starts_with("http://") || base_url.starts_with("https://")) {
+        Some("TierDrop URL must start with http:// or https://".to_string())
+    } else if !(1..=720).contains(&form.valid_hours) {
+        Some("Links must be valid for 1 to 720 hours.".to_string())
+    } else {
+        update_notifications(&state, |n| {
+            n.approval_links = ApprovalLinkSettings {
+                enabled,
+                base_url,
+                valid_hours: form.valid_hours,
+            };
+            Ok(())
+        }).await
+    };
+
+    render_notifications(&state, error).await
+}
+
+#[derive(Deserialize)]
+pub struct MonitoringForm {
+    offline_after_minutes: u32,
+}
+
+/// POST /settings/notifications/monitoring - Set how long monitored members may be offline
+pub async fn update_monitoring(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<MonitoringForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let error = if !(1..=1440).contains(&form.offline_after_minutes) {
+        Some("Offline time must be between 1 and 1440 minutes.".to_string())
+    } else {
+        update_notifications(&state, |n| {
+            n.offline_after_minutes = form.offline_after_minutes;
+            Ok(())
+        }).await
+    };
+
+    render_notifications(&state, error).await
+}
+
+// ---- ZeroTier Release Check (Admin only) ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/releases.html")]
+pub struct ReleasesTemplate {
+    pub enabled: bool,
+    pub info: Option<ReleaseInfo>,
+    /// The node's ZeroTier version, when known
+    pub node_version: Option<String>,
+    pub error: Option<String>,
+}
+
+async fn render_releases(state: &AppState, error: Option<String>) -> Response {
+    let enabled = state.config.read().await.as_ref().is_some_and(|c| c.release_check);
+    let node_version = state.zt_state.read().await.status.as_ref().and_then(|s| s.version.clone());
+    ReleasesTemplate {
+        enabled,
+        info: state.release_info.read().await.clone(),
+        node_version,
+        error,
+    }
+    .into_response()
+}
+
+/// GET /settings/releases - Whether ZeroTier releases are checked, and what was found
+pub async fn releases_panel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    render_releases(&state, None).await
+}
+
+#[derive(Deserialize)]
+pub struct ReleaseCheckForm {
+    release_check: Option<String>,
+}
+
+/// POST /settings/releases - Turn the release check on or off. Turning it on checks right away.
+pub async fn update_release_check(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<ReleaseCheckForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let enabled = form.release_check.is_some();
+    {
+        let mut config = state.config.write().await;
+        let Some(ref mut c) = *config else {
+            return render_releases(&state, Some("No configuration found".to_string())).await;
+        };
+        if c.release_check != enabled {
+            c.release_check = enabled;
+            c.audit(
+                &current_user.username,
+                if enabled { "Release check turned on" } else { "Release check turned off" },
+                None,
+                None,
+                String::new(),
+            );
+            if let Err(e) = c.save() {
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save config: {}", e));
+            }
+        }
+    }
+
+    let error = if enabled {
+        releases::check(&state).await.err()
+    } else {
+        *state.release_info.write().await = None;
+        None
+    };
+    render_releases(&state, error).await
+}
+
+// ---- Consistency Check (Admin only) ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/consistency.html")]
+pub struct ConsistencyTemplate {
+    pub report: Option<ConsistencyReport>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+/// GET /settings/consistency - Latest stale-metadata report
+pub async fn consistency_panel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    ConsistencyTemplate {
+        report: state.consistency_report.read().await.clone(),
+        message: None,
+        error: None,
+    }.into_response()
+}
+
+/// POST /settings/consistency/check - Run the consistency check now
+pub async fn run_consistency_check(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    let error = state.run_consistency_check().await.err();
+    ConsistencyTemplate {
+        report: state.consistency_report.read().await.clone(),
+        message: None,
+        error,
+    }.into_response()
+}
+
+/// POST /settings/consistency/cleanup - Remove stale metadata. The report is rebuilt
+/// first so nothing that reappeared since the last check is removed.
+pub async fn cleanup_consistency(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => {
+                let zt = state.zt_state.read().await;
+                consistency::build_report(c, &zt).and_then(|report| {
+                    let removed = consistency::apply_cleanup(c, &report);
+                    c.save().map_err(|e| format!("Failed to save: {}", e))?;
+                    Ok(removed)
+                })
+            }
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+
+    let (message, error) = match result {
+        Ok(removed) => {
+            // Refresh the stored report so it reflects the cleanup
+            let _ = state.run_consistency_check().await;
+            (Some(format!("Removed {} stale entries.", removed)), None)
+        }
+        Err(e) => (None, Some(e)),
+    };
+
+    ConsistencyTemplate {
+        report: state.consistency_report.read().await.clone(),
+        message,
+        error,
+    }.into_response()
+}
+
+// ---- DNS Server (Admin only) ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/dns_server.html")]
+pub struct DnsServerTemplate {
+    pub settings: DnsServerSettings,
+    pub status: DnsServerStatus,
+    pub records: Vec<DnsRecord>,
+    pub error: Option<String>,
+}
+
+async fn render_dns_server(state: &AppState, error: Option<String>) -> Response {
+    let (settings, records) = {
+        let config = state.config.read().await;
+        let zt = state.zt_state.read().await;
+        match config.as_ref() {
+            Some(c) => (c.dns_server.clone(), dns::build_records(c, &zt)),
+            None => (DnsServerSettings::default(), Vec::new()),
+        }
+    };
+    DnsServerTemplate {
+        settings,
+        status: state.dns_server.status(),
+        records,
+        error,
+    }.into_response()
+}
+
+/// GET /settings/dns - Built-in DNS server settings and served records
+pub async fn dns_server_panel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    render_dns_server(&state, None).await
+}
+
+#[derive(Deserialize)]
+pub struct UpdateDnsServerForm {
+    enabled: Option<String>,
+    bind: String,
+}
+
+/// POST /settings/dns - Save DNS server settings and rebind
+pub async fn update_dns_server(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<UpdateDnsServerForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let bind = form.bind.trim().to_string();
+    if bind.parse::<std::net::SocketAddr>().is_err() {
+        return render_dns_server(&state, Some("Listen address must be an IP and port, e.g. 0.0.0.0:53".to_string())).await;
+    }
+
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => {
+                c.dns_server = DnsServerSettings {
+                    enabled: form.enabled.is_some(),
+                    bind,
+                };
+                c.save().map_err(|e| format!("Failed to save: {}", e))
+            }
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+    if let Err(e) = result {
+        return render_dns_server(&state, Some(e)).await;
+    }
+
+    state.dns_server.restart().await;
+    render_dns_server(&state, None).await
+}
+
+// ---- Single Sign-On (Admin only) ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/oidc.html")]
+pub struct OidcTemplate {
+    pub settings: OidcSettings,
+    pub has_secret: bool,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+async fn render_oidc(state: &AppState, message: Option<String>, error: Option<String>) -> Response {
+    let mut settings = {
+        let config = state.config.read().await;
+        config.as_ref().map(|c| c.oidc.clone()).unwrap_or_default()
+    };
+    // Never send the secret back to the browser
+    let has_secret = !settings.client_secret.is_empty();
+    settings.client_secret.clear();
+    OidcTemplate {
+        settings,
+        has_secret,
+        message,
+        error,
+    }.into_response()
+}
+
+/// GET /settings/oidc - Single sign-on settings
+pub async fn oidc_panel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    render_oidc(&state, None, None).await
+}
+
+#[derive(Deserialize)]
+pub struct UpdateOidcForm {
+    enabled: Option<String>,
+    discovery_url: String,
+    client_id: String,
+    #[serde(default)]
+    client_secret: String,
+    base_url: String,
+    button_label: String,
+    scopes: String,
+    username_claim: String,
+    groups_claim: String,
+    #[serde(default)]
+    admin_group: String,
+    #[serde(default)]
+    allowed_group: String,
+    auto_create: Option<String>,
+    link_by_username: Option<String>,
+}
+
+/// POST /settings/oidc - Save single sign-on settings and check the provider is reachable
+pub async fn update_oidc(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<UpdateOidcForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let is_url = |s: &str| s.starts_with("https://") || s.starts_with("http://");
+    let discovery_url = form.discovery_url.trim().to_string();
+    let base_url = form.base_url.trim().trim_end_matches('/').to_string();
+    let enabled = form.enabled.is_some();
+    if enabled {
+        if !is_url(&discovery_url) {
+            return render_oidc(&state, None, Some("Issuer URL must start with https:// or http://".to_string())).await;
+        }
+        if !is_url(&base_url) {
+            return render_oidc(&state, None, Some("TierDrop URL must start with https:// or http://".to_string())).await;
+        }
+        if form.client_id.trim().is_empty() {
+            return render_oidc(&state, None, Some("Client ID is required".to_string())).await;
+        }
+    }
+    let defaults = OidcSettings::default();
+    let or_default = |value: &str, default: &str| {
+        let value = value.trim();
+        if value.is_empty() { default.to_string() } else { value.to_string() }
+    };
+
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => {
+                let client_secret = if form.client_secret.is_empty() {
+                    std::mem::take(&mut c.oidc.client_secret)
+                } else {
+                    form.client_secret.trim().to_string()
+                };
+                c.oidc = OidcSettings {
+                    enabled,
+                    discovery_url,
+                    client_id: form.client_id.trim().to_string(),
+                    client_secret,
+                    base_url,
+                    button_label: or_default(&form.button_label, &defaults.button_label),
+                    scopes: or_default(&form.scopes, &defaults.scopes),
+                    username_claim: or_default(&form.username_claim, &defaults.username_claim),
+                    groups_claim: or_default(&form.groups_claim, &defaults.groups_claim),
+                    admin_group: form.admin_group.trim().to_string(),
+                    allowed_group: form.allowed_group.trim().to_string(),
+                    auto_create: form.auto_create.is_some(),
+                    link_by_username: form.link_by_username.is_some(),
+                };
+                c.save().map(|_| c.oidc.clone()).map_err(|e| format!("Failed to save: {}", e))
+            }
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+    let settings = match result {
+        Ok(s) => s,
+        Err(e) => return render_oidc(&state, None, Some(e)).await,
+    };
+
+    if !settings.enabled {
+        return render_oidc(&state, Some("Saved.".to_string()), None).await;
+    }
+    match oidc::discover(&settings).await {
+        Ok(discovery) => {
+            render_oidc(&state, Some(format!("Saved. Connected to {}.", discovery.issuer)), None).await
+        }
+        Err(e) => render_oidc(&state, None, Some(format!("Saved, but the provider could not be reached: {}", e))).await,
+    }
+}
+
+// ---- Branding (Admin only) ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/branding.html")]
+pub struct BrandingTemplate {
+    pub branding: Branding,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+async fn render_branding(state: &AppState, message: Option<String>, error: Option<String>) -> Response {
+    let branding = {
+        let config = state.config.read().await;
+        config.as_ref().map(|c| c.branding.clone()).unwrap_or_default()
+    };
+    BrandingTemplate { branding, message, error }.into_response()
+}
+
+/// GET /settings/branding - Instance title, logo and accent color
+pub async fn branding_panel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    render_branding(&state, None, None).await
+}
+
+/// POST /settings/branding - Save the branding; a logo is only replaced when a file is uploaded
+pub async fn update_branding(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    mut multipart: Multipart,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let mut title = String::new();
+    let mut accent_color = String::new();
+    let mut remove_logo = false;
+    let mut logo = None;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        let name = field.name().unwrap_or_default().to_string();
+        let result = match name.as_str() {
+            "logo" => field.bytes().await.map(|bytes| {
+                if !bytes.is_empty() {
+                    logo = Some(branding::parse_logo(&bytes));
+                }
+            }),
+            _ => field.text().await.map(|text| match name.as_str() {
+                "title" => title = text.trim().to_string(),
+                "accent_color" => accent_color = text,
+                "remove_logo" => remove_logo = true,
+                _ => {}
+            }),
+        };
+        if let Err(e) = result {
+            return render_branding(&state, None, Some(format!("Failed to read upload: {}", e))).await;
+        }
+    }
+
+    if title.chars().count() > 60 {
+        return render_branding(&state, None, Some("The title must be at most 60 characters".to_string())).await;
+    }
+    let accent_color = match branding::parse_accent_color(&accent_color) {
+        Ok(color) => color,
+        Err(e) => return render_branding(&state, None, Some(e)).await,
+    };
+    let logo = match logo.transpose() {
+        Ok(logo) => logo,
+        Err(e) => return render_branding(&state, None, Some(e)).await,
+    };
+
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => {
+                c.branding.title = title;
+                c.branding.accent_color = accent_color;
+                if logo.is_some() || remove_logo {
+                    c.branding.logo = logo;
+                }
+                c.audit(&current_user.username, "Branding changed", None, None, String::new());
+                match c.save() {
+                    Ok(()) => {
+                        branding::set(&c.branding);
+                        Ok(())
+                    }
+                    Err(e) => Err(format!("Failed to save: {}", e)),
+                }
+            }
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+    match result {
+        Ok(()) => render_branding(&state, Some("Saved. Reload the page to see the change.".to_string()), None).await,
+        Err(e) => render_branding(&state, None, Some(e)).await,
+    }
+}
+
+// ---- Email (Admin only) ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/smtp.html")]
+pub struct SmtpTemplate {
+    pub settings: SmtpSettings,
+    pub has_password: bool,
+    pub security_modes: [SmtpSecurity; 3],
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+async fn render_smtp(state: &AppState, message: Option<String>, error: Option<String>) -> Response {
+    let mut settings = {
+        let config = state.config.read().await;
+        config.as_ref().map(|c| c.smtp.clone()).unwrap_or_default()
+    };
+    // Never send the password back to the browser
+    let has_password = !settings.password.is_empty();
+    settings.password.clear();
+    SmtpTemplate {
+        settings,
+        has_password,
+        security_modes: SmtpSecurity::ALL,
+        message,
+        error,
+    }.into_response()
+}
+
+/// GET /settings/smtp - Outgoing mail settings
+pub async fn smtp_panel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    render_smtp(&state, None, None).await
+}
+
+#[derive(Deserialize)]
+pub struct UpdateSmtpForm {
+    enabled: Option<String>,
+    host: String,
+    port: String,
+    security: String,
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    password: String,
+    from: String,
+    base_url: String,
+}
+
+/// POST /settings/smtp - Save outgoing mail settings
+pub async fn update_smtp(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<UpdateSmtpForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let enabled = form.enabled.is_some();
+    let host = form.host.trim().to_string();
+    let from = form.from.trim().to_string();
+    let base_url = form.base_url.trim().trim_end_matches('/').to_string();
+    let Ok(port) = form.port.trim().parse::<u16>() else {
+        return render_smtp(&state, None, Some("Port must be a number between 1 and 65535".to_string())).await;
+    };
+    if enabled {
+        if host.is_empty() {
+            return render_smtp(&state, None, Some("SMTP server is required".to_string())).await;
+        }
+        if !email::is_valid_address(&from) {
+            return render_smtp(&state, None, Some("From must be an email address, e.g. TierDrop <tierdrop@example.com>".to_string())).await;
+        }
+        if !(base_url.starts_with("https://") || base_url.starts_with("http://")) {
+            return render_smtp(&state, None, Some("TierDrop URL must start with https:// or http://".to_string())).await;
+        }
+    }
+
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => {
+                let password = if form.password.is_empty() {
+                    std::mem::take(&mut c.smtp.password)
+                } else {
+                    form.password.clone()
+                };
+                c.smtp = SmtpSettings {
+                    enabled,
+                    host,
+                    port,
+                    security: SmtpSecurity::parse(&form.security),
+                    username: form.username.trim().to_string(),
+                    password,
+                    from,
+                    base_url,
+                };
+                c.save().map_err(|e| format!("Failed to save: {}", e))
+            }
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+    match result {
+        Ok(()) => render_smtp(&state, Some("Saved.".to_string()), None).await,
+        Err(e) => render_smtp(&state, None, Some(e)).await,
+    }
+}
+
+/// POST /settings/smtp/test - Email the current admin to check the settings
+pub async fn test_smtp(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    let Some(to) = current_user.email.clone() else {
+        return render_smtp(&state, None, Some("Set your own email address in the Account tab first.".to_string())).await;
+    };
+    let settings = {
+        let config = state.config.read().await;
+        config.as_ref().map(|c| c.smtp.clone()).unwrap_or_default()
+    };
+    if settings.host.is_empty() {
+        return render_smtp(&state, None, Some("Save an SMTP server first.".to_string())).await;
+    }
+    let body = format!(
+        "This is a test email from TierDrop, sent by {}.\n\nPassword reset emails will be sent the same way.\n",
+        current_user.username
+    );
+    match email::send(&settings, &to, "TierDrop test email", body).await {
+        Ok(()) => render_smtp(&state, Some(format!("Test email sent to {}.", to)), None).await,
+        Err(e) => render_smtp(&state, None, Some(e)).await,
+    }
+}
+
+// ---- Scheduled Jobs (Admin only) ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/schedule.html")]
+pub struct ScheduleTemplate {
+    pub jobs: Vec<ScheduledJob>,
+    pub networks: Vec<ControllerNetwork>,
+    /// (nwid, members taken offline)
+    pub disabled_networks: Vec<(String, usize)>,
+    pub error: Option<String>,
+}
+
+async fn render_schedule(state: &AppState, error: Option<String>) -> Response {
+    let (mut jobs, mut disabled_networks) = {
+        let config = state.config.read().await;
+        match config.as_ref() {
+            Some(c) => (
+                c.scheduled_jobs.clone(),
+                c.disabled_networks.iter().map(|(n, m)| (n.clone(), m.len())).collect::<Vec<_>>(),
+            ),
+            None => (Vec::new(), Vec::new()),
+        }
+    };
+    // Upcoming first, finished one-off jobs last
+    jobs.sort_by_key(|j| (j.next_run().is_none(), j.next_run(), j.id));
+    disabled_networks.sort();
+    let networks = state.zt_state.read().await.controller_networks.clone();
+
+    ScheduleTemplate {
+        jobs,
+        networks,
+        disabled_networks,
+        error,
+    }.into_response()
+}
+
+/// GET /settings/schedule - Scheduled jobs, soonest first
+pub async fn schedule_panel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    render_schedule(&state, None).await
+}
+
+#[derive(Deserialize)]
+pub struct CreateJobForm {
+    action: String,
+    #[serde(default)]
+    nwid: String,
+    #[serde(default)]
+    member_id: String,
+    when: String,
+    #[serde(default)]
+    run_at: String,
+    #[serde(default)]
+    cron: String,
+}
+
+impl CreateJobForm {
+    fn action(&self, networks: &[ControllerNetwork]) -> Result<JobAction, String> {
+        if self.action == "backup" {
+            return Ok(JobAction::Backup);
+        }
+        let nwid = self.nwid.trim().to_string();
+        if !networks.iter().any(|n| n.display_id() == nwid) {
+            return Err("Choose a network".to_string());
+        }
+        let member_id = || crate::invites::parse_node_id(&self.member_id);
+        match self.action.as_str() {
+            "authorize_member" => Ok(JobAction::AuthorizeMember { nwid, member_id: member_id()? }),
+            "deauthorize_member" => Ok(JobAction::DeauthorizeMember { nwid, member_id: member_id()? }),
+            "disable_network" => Ok(JobAction::DisableNetwork { nwid }),
+            "enable_network" => Ok(JobAction::EnableNetwork { nwid }),
+            _ => Err("Unknown action".to_string()),
+        }
+    }
+
+    fn schedule(&self) -> Result<JobSchedule, String> {
+        if self.when == "cron" {
+            let expr = self.cron.split_whitespace().collect::<Vec<_>>().join(" ");
+            CronExpr::parse(&expr)?;
+            Ok(JobSchedule::Cron { expr })
+        } else {
+            match crate::guest::parse_until(&self.run_at)? {
+                Some(at) => Ok(JobSchedule::Once { at }),
+                None => Err("Choose when to run the job".to_string()),
+            }
+        }
+    }
+}
+
+/// POST /settings/schedule - Add a scheduled job
+pub async fn create_scheduled_job(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<CreateJobForm>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let networks = state.zt_state.read().await.controller_networks.clone();
+    let (action, schedule) = match (form.action(&networks), form.schedule()) {
+        (Ok(a), Ok(s)) => (a, s),
+        (Err(e), _) | (_, Err(e)) => return render_schedule(&state, Some(e)).await,
+    };
+
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => {
+                let detail = schedule.label();
+                let id = c.add_scheduled_job(action.clone(), schedule, &current_user.username);
+                c.audit(
+                    &current_user.username,
+                    "Scheduled job added",
+                    action.nwid(),
+                    action.member_id(),
+                    format!("Job #{}: {}, {}", id, action.label(), detail),
+                );
+                c.save().map_err(|e| format!("Failed to save: {}", e))
+            }
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+    render_schedule(&state, result.err()).await
+}
+
+/// DELETE /settings/schedule/{id} - Remove a scheduled job
+pub async fn delete_scheduled_job(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Path(id): Path<u64>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => match c.scheduled_jobs.iter().position(|j| j.id == id) {
+                Some(pos) => {
+                    let job = c.scheduled_jobs.remove(pos);
+                    c.audit(
+                        &current_user.username,
+                        "Scheduled job removed",
+                        job.action.nwid(),
+                        job.action.member_id(),
+                        format!("Job #{}: {}", job.id, job.action.label()),
+                    );
+                    c.save().map_err(|e| format!("Failed to save: {}", e))
+                }
+                None => Ok(()),
+            },
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+    render_schedule(&state, result.err()).await
+}
+
+/// POST /settings/schedule/networks/{nwid}/enable - Restore a network a job disabled, now
+pub async fn enable_disabled_network(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Path(nwid): Path<String>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+
+    let action = JobAction::EnableNetwork { nwid: nwid.clone() };
+    let result = schedule::run_action(&state, &action).await;
+    {
+        let mut config = state.config.write().await;
+        if let Some(ref mut c) = *config {
+            let detail = match &result {
+                Ok(detail) => detail.clone(),
+                Err(e) => format!("Failed: {}", e),
+            };
+            c.audit(&current_user.username, &action.label(), Some(&nwid), None, detail);
+            let _ = c.save();
+        }
+    }
+    render_schedule(&state, result.err()).await
+}
+
+// ---- Audit Log (Admin only) ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/audit_log.html")]
+pub struct AuditLogTemplate {
+    pub entries: Vec<AuditEntry>,
+    /// Zero-based, newest first
+    pub page: usize,
+    pub has_older: bool,
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    #[serde(default)]
+    page: usize,
+}
+
+/// GET /settings/audit - A page of recorded changes, newest first
+pub async fn audit_log_panel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Query(query): Query<AuditQuery>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    let page_size = current_user.preferences.rows_per_page;
+    let (entries, total) = {
+        let config = state.config.read().await;
+        config
+            .as_ref()
+            .map(|c| {
+                let entries = c.audit_log.iter().rev().skip(query.page * page_size).take(page_size).cloned().collect();
+                (entries, c.audit_log.len())
+            })
+            .unwrap_or_default()
+    };
+    AuditLogTemplate {
+        entries,
+        page: query.page,
+        has_older: (query.page + 1) * page_size < total,
+    }
+    .into_response()
+}
+
+// ---- Sign-in History ----
+
+/// Entries shown on the account and security panels
+const LOGIN_HISTORY_LIMIT: usize = 200;
+
+/// Failed sign-ins from one address
+pub struct FailedLoginSummary {
+    pub ip: String,
+    pub failures: usize,
+    pub usernames: Vec<String>,
+    pub last_attempt: String,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/login_history.html")]
+pub struct LoginHistoryTemplate {
+    pub events: Vec<LoginEvent>,
+    /// Admin view across all users, with the failure summary
+    pub all: bool,
+    pub failures_only: bool,
+    pub suspects: Vec<FailedLoginSummary>,
+}
+
+#[derive(Deserialize)]
+pub struct SecurityQuery {
+    #[serde(default)]
+    failed: Option<String>,
+}
+
+/// GET /settings/logins - The current user's recent sign-ins
+pub async fn login_history_panel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    let events = {
+        let config = state.config.read().await;
+        config
+            .as_ref()
+            .map(|c| {
+                c.login_history
+                    .iter()
+                    .rev()
+                    .filter(|e| e.user_id == Some(current_user.id))
+                    .take(20)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    LoginHistoryTemplate {
+        events,
+        all: false,
+        failures_only: false,
+        suspects: Vec::new(),
+    }.into_response()
+}
+
+/// GET /settings/security - Sign-ins across all users, and addresses with repeated failures
+pub async fn security_panel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Query(query): Query<SecurityQuery>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    let failures_only = query.failed.is_some();
+    let history = {
+        let config = state.config.read().await;
+        config.as_ref().map(|c| c.login_history.clone()).unwrap_or_default()
+    };
+
+    let since = Utc::now() - chrono::Duration::hours(24);
+    let mut by_ip: HashMap<String, FailedLoginSummary> = HashMap::new();
+    for event in history.iter().filter(|e| !e.success && e.at >= since) {
+        let ip = event.client.display_ip().to_string();
+        let summary = by_ip.entry(ip.clone()).or_insert_with(|| FailedLoginSummary {
+            ip,
+            failures: 0,
+            usernames: Vec::new(),
+            last_attempt: String::new(),
+        });
+        summary.failures += 1;
+        if !summary.usernames.contains(&event.username) {
+            summary.usernames.push(event.username.clone());
+        }
+        // History is oldest first
+        summary.last_attempt = event.display_at();
+    }
+    let mut suspects: Vec<FailedLoginSummary> = by_ip.into_values().collect();
+    suspects.sort_by(|a, b| b.failures.cmp(&a.failures).then_with(|| a.ip.cmp(&b.ip)));
+
+    let events = history
+        .into_iter()
+        .rev()
+        .filter(|e| !failures_only || !e.success)
+        .take(LOGIN_HISTORY_LIMIT)
+        .collect();
+    LoginHistoryTemplate {
+        events,
+        all: true,
+        failures_only,
+        suspects,
+    }.into_response()
+}
+
+// ---- 2FA Settings ----
+
+use totp_rs::{Algorithm, Secret, TOTP};
+use tower_sessions::Session;
+
+const SESSION_TOTP_SETUP_SECRET: &str = "totp_setup_secret";
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/2fa_setup_modal.html")]
+pub struct TotpSetupModalTemplate {
+    pub qr_code_data_uri: String,
+    pub secret: String,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/2fa_disable_modal.html")]
+pub struct TotpDisableModalTemplate;
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/2fa_status.html")]
+pub struct TotpStatusTemplate {
+    pub totp_enabled: bool,
+}
+
+/// GET /settings/2fa/setup - Show 2FA setup modal with QR code
+pub async fn totp_setup_modal(
+    session: Session,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    // Generate a new TOTP secret
+    let secret = Secret::generate_secret();
+    let secret_base32 = secret.to_encoded().to_string();
+
+    // Store secret in session for verification
+    if session.insert(SESSION_TOTP_SETUP_SECRET, &secret_base32).await.is_err() {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store setup secret");
+    }
+
+    // Create TOTP for QR code generation
+    let totp = match TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret.to_bytes().unwrap(),
+        Some("TierDrop".to_string()),
+        current_user.username.clone(),
+    ) {
+        Ok(t) => t,
+        Err(_) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create TOTP"),
+    };
+
+    // Generate QR code as data URI
+    let qr_code_data_uri = match totp.get_qr_base64() {
+        Ok(qr) => format!("data:image/png;base64,{}", qr),
+        Err(_) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate QR code"),
+    };
+
+    TotpSetupModalTemplate {
+        qr_code_data_uri,
+        secret: secret_base32,
+    }.into_response()
+}
+
+#[derive(Deserialize)]
+pub struct TotpEnableForm {
+    code: String,
+}
+
+/// POST /settings/2fa/enable - Verify code and enable 2FA
+pub async fn totp_enable(
+    session: Session,
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<TotpEnableForm>,
+) -> Response {
+    // Get the setup secret from session
+    let secret_base32: String = match session.get(SESSION_TOTP_SETUP_SECRET).await {
+        Ok(Some(s)) => s,
+        _ => return (
+            [("HX-Trigger", r#"{"2fa-error":{"message":"Setup session expired. Please try again."}}"#)],
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ).into_response(),
+    };
+
+    // Verify the code
+    let code = form.code.trim().replace(" ", "");
+    if !crate::auth::verify_totp(&code, &secret_base32) {
+        return (
+            [("HX-Trigger", r#"{"2fa-error":{"message":"Invalid verification code. Please try again."}}"#)],
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ).into_response();
+    }
+
+    // Code is valid - save secret to user and enable 2FA
+    {
+        let mut config = state.config.write().await;
+        if let Some(ref mut c) = *config {
+            if let Some(user) = c.find_user_by_id_mut(current_user.id) {
+                user.totp_enabled = true;
+                user.totp_secret = Some(secret_base32);
+                if let Err(e) = c.save() {
+                    let trigger = format!(r#"{{"2fa-error":{{"message":"Failed to save: {}"}}}}"#, e);
+                    return (
+                        [("HX-Trigger", trigger)],
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ).into_response();
+                }
+            } else {
+                return (
+                    [("HX-Trigger", r#"{"2fa-error":{"message":"User not found."}}"#)],
+                    StatusCode::NOT_FOUND,
+                ).into_response();
+            }
+        }
+    }
+
+    // Clear the setup secret from session
+    let _ = session.remove::<String>(SESSION_TOTP_SETUP_SECRET).await;
+
+    // Trigger success event - modal will close and refresh status
+    (
+        [("HX-Trigger", "2fa-enabled")],
+        StatusCode::OK,
+    ).into_response()
+}
+
+/// GET /settings/2fa/disable-modal - Show disable 2FA confirmation modal
+pub async fn totp_disable_modal() -> Response {
+    TotpDisableModalTemplate.into_response()
+}
+
+#[derive(Deserialize)]
+pub struct TotpDisableForm {
+    password: String,
+}
+
+/// POST /settings/2fa/disable - Disable 2FA (requires password)
+pub async fn totp_disable(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<TotpDisableForm>,
+) -> Response {
+    // Verify password
+    if !verify_password(&form.password, &current_user.password_hash) {
+        return (
             [("HX-Trigger", r#"{"2fa-disable-error":{"message":"Incorrect password."}}"#)],
             StatusCode::UNPROCESSABLE_ENTITY,
         ).into_response();
@@ -612,3 +3108,327 @@ pub async fn totp_status(
         totp_enabled: current_user.totp_enabled,
     }.into_response()
 }
+
+// ---- Passkeys ----
+
+const SESSION_PASSKEY_REGISTER_KEY: &str = "passkey_register";
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/passkeys.html")]
+pub struct PasskeysTemplate {
+    pub passkeys: Vec<Passkey>,
+    /// Whether adding a passkey asks for the user's password
+    pub has_password: bool,
+    pub error: Option<String>,
+}
+
+async fn render_passkeys(state: &AppState, user_id: u64, error: Option<String>) -> Response {
+    let (passkeys, has_password) = {
+        let config = state.config.read().await;
+        config
+            .as_ref()
+            .and_then(|c| c.find_user_by_id(user_id))
+            .map(|u| (u.passkeys.clone(), !u.is_sso_only()))
+            .unwrap_or_default()
+    };
+    PasskeysTemplate { passkeys, has_password, error }.into_response()
+}
+
+/// A new passkey, with the current password confirming it
+#[derive(Deserialize)]
+pub struct PasskeyRegisterForm {
+    #[serde(flatten)]
+    response: RegistrationResponse,
+    #[serde(default)]
+    current_password: String,
+}
+
+/// GET /settings/passkeys - The current user's passkeys
+pub async fn passkeys_panel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    render_passkeys(&state, current_user.id, None).await
+}
+
+/// POST /settings/passkeys/options - Challenge for registering a new passkey
+pub async fn passkey_register_options(
+    session: Session,
+    Extension(current_user): Extension<User>,
+    RequestHost(host): RequestHost,
+) -> Response {
+    let Some(host) = host else {
+        return error_response(StatusCode::BAD_REQUEST, "Missing Host header");
+    };
+    let pending = PendingChallenge::new(&host);
+    let options = passkeys::registration_options(&pending, &current_user);
+    if session.insert(SESSION_PASSKEY_REGISTER_KEY, pending).await.is_err() {
+        return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store challenge");
+    }
+    axum::Json(options).into_response()
+}
+
+/// POST /settings/passkeys - Verify and save a new passkey
+pub async fn passkey_register(
+    session: Session,
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    RequestHost(host): RequestHost,
+    axum::Json(form): axum::Json<PasskeyRegisterForm>,
+) -> Response {
+    let pending: Option<PendingChallenge> = session.remove(SESSION_PASSKEY_REGISTER_KEY).await.ok().flatten();
+    let (Some(pending), Some(host)) = (pending, host) else {
+        return render_passkeys(&state, current_user.id, Some("Registration expired. Try again.".to_string())).await;
+    };
+    // A passkey signs in without the password, so adding one takes the password
+    if !confirms_password(&current_user, &form.current_password) {
+        return render_passkeys(&state, current_user.id, Some("Current password is incorrect.".to_string())).await;
+    }
+    let passkey = match passkeys::register(&form.response, &pending, &host) {
+        Ok(p) => p,
+        Err(e) => return render_passkeys(&state, current_user.id, Some(e)).await,
+    };
+
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) if c.find_user_by_passkey(&passkey.id).is_some() => {
+                Err("This passkey is already registered.".to_string())
+            }
+            Some(ref mut c) => match c.find_user_by_id_mut(current_user.id) {
+                Some(user) => {
+                    user.passkeys.push(passkey);
+                    c.save().map_err(|e| format!("Failed to save: {}", e))
+                }
+                None => Err("User not found.".to_string()),
+            },
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+    render_passkeys(&state, current_user.id, result.err()).await
+}
+
+/// DELETE /settings/passkeys/{id} - Remove one of the current user's passkeys
+pub async fn delete_passkey(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Path(id): Path<String>,
+) -> Response {
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => match c.find_user_by_id_mut(current_user.id) {
+                Some(user) => {
+                    user.passkeys.retain(|p| p.id != id);
+                    c.save().map_err(|e| format!("Failed to save: {}", e))
+                }
+                None => Err("User not found.".to_string()),
+            },
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+    render_passkeys(&state, current_user.id, result.err()).await
+}
+
+// ---- API Keys ----
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/api_keys.html")]
+pub struct ApiKeysTemplate {
+    pub keys: Vec<ApiKey>,
+    /// A key that was just created, shown this once
+    pub new_key: Option<String>,
+    pub error: Option<String>,
+}
+
+async fn render_api_keys(
+    state: &AppState,
+    user_id: u64,
+    new_key: Option<String>,
+    error: Option<String>,
+) -> Response {
+    let keys = {
+        let config = state.config.read().await;
+        config
+            .as_ref()
+            .and_then(|c| c.find_user_by_id(user_id))
+            .map(|u| u.api_keys.clone())
+            .unwrap_or_default()
+    };
+    ApiKeysTemplate { keys, new_key, error }.into_response()
+}
+
+/// GET /settings/api-keys - The current user's API keys
+pub async fn api_keys_panel(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    render_api_keys(&state, current_user.id, None, None).await
+}
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyForm {
+    name: String,
+}
+
+/// POST /settings/api-keys - Create an API key for the current user
+pub async fn create_api_key(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Form(form): Form<CreateApiKeyForm>,
+) -> Response {
+    let name = form.name.trim().to_string();
+    if name.is_empty() {
+        return render_api_keys(&state, current_user.id, None, Some("Name is required.".to_string())).await;
+    }
+
+    let (api_key, key) = ApiKey::generate(name);
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => match c.find_user_by_id_mut(current_user.id) {
+                Some(user) => {
+                    user.api_keys.push(api_key);
+                    c.save().map_err(|e| format!("Failed to save: {}", e))
+                }
+                None => Err("User not found.".to_string()),
+            },
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+    match result {
+        Ok(()) => render_api_keys(&state, current_user.id, Some(key), None).await,
+        Err(e) => render_api_keys(&state, current_user.id, None, Some(e)).await,
+    }
+}
+
+/// DELETE /settings/api-keys/{id} - Revoke one of the current user's API keys
+pub async fn delete_api_key(
+    State(state): State<AppState>,
+    Extension(current_user): Extension<User>,
+    Path(id): Path<String>,
+) -> Response {
+    let result = {
+        let mut config = state.config.write().await;
+        match *config {
+            Some(ref mut c) => match c.find_user_by_id_mut(current_user.id) {
+                Some(user) => {
+                    user.api_keys.retain(|k| k.id != id);
+                    c.save().map_err(|e| format!("Failed to save: {}", e))
+                }
+                None => Err("User not found.".to_string()),
+            },
+            None => Err("No configuration found.".to_string()),
+        }
+    };
+    render_api_keys(&state, current_user.id, None, result.err()).await
+}
+
+// ---- Active Sessions ----
+
+pub struct SessionRow {
+    pub session: ActiveSession,
+    pub username: String,
+    pub current: bool,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/sessions.html")]
+pub struct SessionsTemplate {
+    pub rows: Vec<SessionRow>,
+    /// Admin view of every user's sessions
+    pub all: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SessionsQuery {
+    #[serde(default)]
+    all: bool,
+}
+
+async fn render_sessions(
+    state: &AppState,
+    session: &Session,
+    current_user: &User,
+    all: bool,
+    error: Option<String>,
+) -> Response {
+    let current_sid = crate::auth::get_session_sid(session).await;
+    let sessions = state
+        .sessions
+        .list(if all { None } else { Some(current_user.id) })
+        .await;
+    let usernames: HashMap<u64, String> = {
+        let config = state.config.read().await;
+        config
+            .as_ref()
+            .map(|c| c.users.iter().map(|u| (u.id, u.username.clone())).collect())
+            .unwrap_or_default()
+    };
+    let rows = sessions
+        .into_iter()
+        .map(|s| SessionRow {
+            username: usernames.get(&s.user_id).cloned().unwrap_or_default(),
+            current: current_sid.as_deref() == Some(s.id.as_str()),
+            session: s,
+        })
+        .collect();
+    SessionsTemplate { rows, all, error }.into_response()
+}
+
+/// GET /settings/sessions - The current user's sessions
+pub async fn sessions_panel(
+    State(state): State<AppState>,
+    session: Session,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    render_sessions(&state, &session, &current_user, false, None).await
+}
+
+/// GET /settings/sessions/all - Every user's sessions
+pub async fn all_sessions_panel(
+    State(state): State<AppState>,
+    session: Session,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    if !current_user.is_admin {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    render_sessions(&state, &session, &current_user, true, None).await
+}
+
+/// DELETE /settings/sessions/{id} - Revoke a session. Admins may revoke anyone's.
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    session: Session,
+    Extension(current_user): Extension<User>,
+    Path(id): Path<String>,
+    Query(query): Query<SessionsQuery>,
+) -> Response {
+    let all = query.all && current_user.is_admin;
+    let error = match state.sessions.get(&id).await {
+        Some(s) if s.user_id == current_user.id || current_user.is_admin => {
+            state.sessions.revoke(&id).await;
+            if crate::auth::get_session_sid(&session).await.as_deref() == Some(id.as_str()) {
+                session.flush().await.unwrap_or_default();
+                return ([("HX-Redirect", base_path::url("/login"))], StatusCode::OK).into_response();
+            }
+            None
+        }
+        _ => Some("Session not found.".to_string()),
+    };
+    render_sessions(&state, &session, &current_user, all, error).await
+}
+
+/// POST /settings/sessions/logout-all - Revoke every session of the current user
+pub async fn logout_everywhere(
+    State(state): State<AppState>,
+    session: Session,
+    Extension(current_user): Extension<User>,
+) -> Response {
+    let count = state.sessions.revoke_user(current_user.id).await;
+    tracing::info!("User '{}' logged out of {} session(s)", current_user.username, count);
+    session.flush().await.unwrap_or_default();
+    ([("HX-Redirect", base_path::url("/login"))], StatusCode::OK).into_response()
+}
@@ -0,0 +1,93 @@
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+
+use crate::shares;
+use crate::state::AppState;
+
+/// A member as a share link shows it: no descriptions or settings
+pub struct SharedMember {
+    pub id: String,
+    pub name: String,
+    pub ips: String,
+    pub authorized: bool,
+    pub online: bool,
+    pub last_seen: String,
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "share.html")]
+pub struct ShareTemplate {
+    pub nwid: String,
+    pub network_name: String,
+    pub members: Vec<SharedMember>,
+    pub expires_at: String,
+    pub error: Option<String>,
+}
+
+/// GET /share/{id}/{sig} - A network's member list, read-only, for whoever holds the link
+pub async fn share_page(State(state): State<AppState>, Path((id, sig)): Path<(u64, String)>) -> Response {
+    let verified = {
+        let config = state.config.read().await;
+        config.as_ref().and_then(|c| {
+            let link = c.share_links.iter().find(|l| l.id == id)?;
+            Some((
+                shares::verify(&c.approval_secret, link, &sig).map(|_| link.clone()),
+                c.member_names.clone(),
+            ))
+        })
+    };
+    let (link, names) = match verified {
+        Some((Ok(link), names)) => (link, names),
+        Some((Err(e), _)) => return invalid_link(e),
+        None => return invalid_link("This share link is not valid.".to_string()),
+    };
+
+    let zt = state.zt_state.read().await;
+    let Some(network) = zt.controller_networks.iter().find(|n| n.display_id() == link.nwid) else {
+        return invalid_link("This network no longer exists.".to_string());
+    };
+    let mut members: Vec<SharedMember> = zt
+        .controller_members
+        .get(&link.nwid)
+        .into_iter()
+        .flatten()
+        .map(|m| {
+            let peer = zt.peers.get(m.display_id());
+            SharedMember {
+                id: m.display_id().to_string(),
+                name: names.get(m.display_id()).cloned().unwrap_or_default(),
+                ips: m.display_ip_assignments(),
+                authorized: m.is_authorized(),
+                online: peer.is_some_and(|p| p.is_online()),
+                last_seen: peer.map(|p| p.display_last_seen()).unwrap_or_else(|| "-".to_string()),
+            }
+        })
+        .collect();
+    members.sort_by(|a, b| b.authorized.cmp(&a.authorized).then_with(|| a.name.cmp(&b.name)).then_with(|| a.id.cmp(&b.id)));
+
+    ShareTemplate {
+        nwid: link.nwid.clone(),
+        network_name: network.display_name().to_string(),
+        members,
+        expires_at: link.display_expires_at(),
+        error: None,
+    }
+    .into_response()
+}
+
+fn invalid_link(error: String) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        ShareTemplate {
+            nwid: String::new(),
+            network_name: String::new(),
+            members: Vec::new(),
+            expires_at: String::new(),
+            error: Some(error),
+        },
+    )
+        .into_response()
+}
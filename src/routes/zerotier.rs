@@ -0,0 +1,133 @@
+//! Settings > Maintenance > ZeroTier Service: the local zerotier-one service's state and recent
+//! log, with buttons to start or restart it. See `zt::service` for how each platform's service
+//! manager is driven.
+
+use std::future::Future;
+use std::time::Duration;
+
+use askama::Template;
+use askama_web::WebTemplate;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+
+use crate::permissions;
+use crate::routes::error::error_response;
+use crate::state::{AppState, User};
+use crate::zt::service::{self, ServiceStatus};
+
+/// How long to wait for ZeroTier to answer after starting or restarting it
+const START_WAIT: Duration = Duration::from_secs(30);
+
+/// Run a service manager action, audit it, then wait for ZeroTier to answer and poll again.
+/// Returns whether it answered in time.
+async fn control_and_wait(
+    state: &AppState,
+    user: &User,
+    action: &str,
+    control: impl Future<Output = Result<(), String>>,
+) -> Result<bool, String> {
+    let result = control.await;
+    {
+        let mut config = state.config.write().await;
+        if let Some(c) = config.as_mut() {
+            let detail = match &result {
+                Ok(_) => service::service_name(),
+                Err(e) => format!("Failed: {}", e),
+            };
+            c.audit(&user.username, action, None, None, detail);
+            if let Err(e) = c.save() {
+                tracing::warn!("Failed to save audit log: {}", e);
+            }
+        }
+    }
+    result?;
+    let client = state.zt_client.read().await.clone();
+    let online = match client {
+        Some(c) => service::wait_until_answering(&c, START_WAIT).await,
+        None => false,
+    };
+    state.notify_poller();
+    Ok(online)
+}
+
+/// Restart the ZeroTier service and wait for it to come back
+pub async fn restart_and_wait(state: &AppState, user: &User) -> Result<bool, String> {
+    control_and_wait(state, user, "ZeroTier restarted", service::restart()).await
+}
+
+#[derive(Template, WebTemplate)]
+#[template(path = "partials/zerotier_service.html")]
+pub struct ZerotierServiceTemplate {
+    pub available: bool,
+    pub service: String,
+    pub status: Option<ServiceStatus>,
+    /// Known to be stopped, so Start is offered instead of Restart
+    pub stopped: bool,
+    pub status_error: Option<String>,
+    /// The recent log, None where the platform has none to read
+    pub log: Option<String>,
+    pub log_error: Option<String>,
+    pub log_lines: usize,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+async fn render(message: Option<String>, error: Option<String>) -> Response {
+    let (status, status_error) = match service::status().await {
+        Ok(s) => (Some(s), None),
+        Err(e) => (None, Some(e)),
+    };
+    let (log, log_error) = match service::log_tail().await {
+        Ok(log) => (log, None),
+        Err(e) => (None, Some(e)),
+    };
+    ZerotierServiceTemplate {
+        available: service::available(),
+        service: service::service_name(),
+        stopped: status.as_ref().is_some_and(|s| !s.running),
+        status,
+        status_error,
+        log,
+        log_error,
+        log_lines: service::LOG_LINES,
+        message,
+        error,
+    }
+    .into_response()
+}
+
+/// Describe the outcome of starting or restarting ZeroTier
+async fn render_outcome(done: &str, result: Result<bool, String>) -> Response {
+    match result {
+        Ok(true) => render(Some(format!("ZeroTier {} and is answering again.", done)), None).await,
+        Ok(false) => render(None, Some(format!("ZeroTier {} but isn't answering yet.", done))).await,
+        Err(e) => render(None, Some(e)).await,
+    }
+}
+
+/// GET /settings/zerotier-service - The service's state and recent log
+pub async fn service_panel(Extension(user): Extension<User>) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    render(None, None).await
+}
+
+/// POST /settings/zerotier-service/restart - Restart the service
+pub async fn restart_service(State(state): State<AppState>, Extension(user): Extension<User>) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    render_outcome("restarted", restart_and_wait(&state, &user).await).await
+}
+
+/// POST /settings/zerotier-service/start - Start the service when it is stopped
+pub async fn start_service(State(state): State<AppState>, Extension(user): Extension<User>) -> Response {
+    if !permissions::is_admin(&user) {
+        return error_response(StatusCode::FORBIDDEN, "Admin access required");
+    }
+    let result = control_and_wait(&state, &user, "ZeroTier started", service::start()).await;
+    render_outcome("started", result).await
+}
@@ -0,0 +1,519 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Datelike, Duration, DurationRound, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::routes::backup::BackupTarget;
+use crate::state::{data_dir, AppState, Config};
+use crate::zt::backend::ZtBackend;
+
+/// How often due jobs are checked
+const TICK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Actor recorded in the audit log for scheduled actions
+const SCHEDULER_ACTOR: &str = "scheduler";
+
+/// Which scheduled backups to keep in the data directory. Anything matched by none of
+/// the rules is deleted after each scheduled backup; 0 turns a rule off.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BackupRetention {
+    /// Most recent backups, whatever their age
+    #[serde(default = "default_keep_last")]
+    pub keep_last: u32,
+    /// Newest backup of each day for this many days
+    #[serde(default = "default_keep_daily_days")]
+    pub keep_daily_days: u32,
+    /// Newest backup of each week for this many weeks
+    #[serde(default = "default_keep_weekly_weeks")]
+    pub keep_weekly_weeks: u32,
+}
+
+fn default_keep_last() -> u32 {
+    7
+}
+
+fn default_keep_daily_days() -> u32 {
+    7
+}
+
+fn default_keep_weekly_weeks() -> u32 {
+    4
+}
+
+impl Default for BackupRetention {
+    fn default() -> Self {
+        Self {
+            keep_last: default_keep_last(),
+            keep_daily_days: default_keep_daily_days(),
+            keep_weekly_weeks: default_keep_weekly_weeks(),
+        }
+    }
+}
+
+impl BackupRetention {
+    /// Backups the policy no longer covers. `backups` pairs each file with when it was taken.
+    fn prunable<'a>(&self, backups: &'a [(PathBuf, DateTime<Utc>)], now: DateTime<Utc>) -> Vec<&'a Path> {
+        let mut newest_first: Vec<&(PathBuf, DateTime<Utc>)> = backups.iter().collect();
+        newest_first.sort_by_key(|b| std::cmp::Reverse(b.1));
+
+        let daily_from = now - Duration::days(i64::from(self.keep_daily_days));
+        let weekly_from = now - Duration::weeks(i64::from(self.keep_weekly_weeks));
+        let mut days_seen = HashSet::new();
+        let mut weeks_seen = HashSet::new();
+
+        let mut prunable = Vec::new();
+        for (i, (path, taken)) in newest_first.into_iter().enumerate() {
+            let recent = i < self.keep_last as usize;
+            // Insert unconditionally so only the newest backup of a day or week counts
+            let first_of_day = days_seen.insert(taken.date_naive());
+            let first_of_week = weeks_seen.insert(taken.iso_week());
+            let daily = self.keep_daily_days > 0 && first_of_day && *taken > daily_from;
+            let weekly = self.keep_weekly_weeks > 0 && first_of_week && *taken > weekly_from;
+            if !(recent || daily || weekly) {
+                prunable.push(path.as_path());
+            }
+        }
+        prunable
+    }
+}
+
+/// Something the scheduler can do
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobAction {
+    AuthorizeMember { nwid: String, member_id: String },
+    DeauthorizeMember { nwid: String, member_id: String },
+    /// De-authorize every member, remembering them for EnableNetwork
+    DisableNetwork { nwid: String },
+    /// Re-authorize the members DisableNetwork took offline
+    EnableNetwork { nwid: String },
+    Backup,
+}
+
+impl JobAction {
+    pub fn label(&self) -> String {
+        match self {
+            JobAction::AuthorizeMember { nwid, member_id } => format!("Authorize member {} on {}", member_id, nwid),
+            JobAction::DeauthorizeMember { nwid, member_id } => format!("De-authorize member {} on {}", member_id, nwid),
+            JobAction::DisableNetwork { nwid } => format!("Disable network {}", nwid),
+            JobAction::EnableNetwork { nwid } => format!("Enable network {}", nwid),
+            JobAction::Backup => "Back up to the data directory".to_string(),
+        }
+    }
+
+    pub fn nwid(&self) -> Option<&str> {
+        match self {
+            JobAction::AuthorizeMember { nwid, .. }
+            | JobAction::DeauthorizeMember { nwid, .. }
+            | JobAction::DisableNetwork { nwid }
+            | JobAction::EnableNetwork { nwid } => Some(nwid),
+            JobAction::Backup => None,
+        }
+    }
+
+    pub fn member_id(&self) -> Option<&str> {
+        match self {
+            JobAction::AuthorizeMember { member_id, .. } | JobAction::DeauthorizeMember { member_id, .. } => {
+                Some(member_id)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// When a job runs. Times are UTC.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobSchedule {
+    Once { at: DateTime<Utc> },
+    /// Five-field cron expression: minute hour day-of-month month day-of-week
+    Cron { expr: String },
+}
+
+impl JobSchedule {
+    pub fn label(&self) -> String {
+        match self {
+            JobSchedule::Once { at } => format!("Once at {}", at.format("%Y-%m-%d %H:%M UTC")),
+            JobSchedule::Cron { expr } => format!("Cron: {}", expr),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduledJob {
+    pub id: u64,
+    pub action: JobAction,
+    pub schedule: JobSchedule,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_run: Option<DateTime<Utc>>,
+    /// "OK" or the error from the last run
+    #[serde(default)]
+    pub last_result: Option<String>,
+}
+
+impl ScheduledJob {
+    /// Next time this job is due, or None if it has nothing left to do
+    pub fn next_run(&self) -> Option<DateTime<Utc>> {
+        match &self.schedule {
+            JobSchedule::Once { at } => self.last_run.is_none().then_some(*at),
+            JobSchedule::Cron { expr } => CronExpr::parse(expr)
+                .ok()?
+                .next_after(self.last_run.unwrap_or(self.created_at)),
+        }
+    }
+
+    pub fn display_next_run(&self) -> String {
+        self.next_run()
+            .map(crate::preferences::format_time)
+            .unwrap_or_else(|| "-".to_string())
+    }
+
+    pub fn display_last_run(&self) -> String {
+        self.last_run
+            .map(crate::preferences::format_time)
+            .unwrap_or_else(|| "Never".to_string())
+    }
+
+    pub fn last_failed(&self) -> bool {
+        self.last_result.as_deref().is_some_and(|r| r != "OK")
+    }
+}
+
+/// A parsed cron expression. Each field is a bitmask of allowed values.
+pub struct CronExpr {
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    /// Day-of-month and day-of-week were both restricted; either may match
+    either_day: bool,
+}
+
+impl CronExpr {
+    pub fn parse(expr: &str) -> Result<CronExpr, String> {
+        let expanded = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err("Cron expression needs five fields: minute hour day month weekday".to_string());
+        }
+        let minutes = parse_field(fields[0], 0, 59, "minute")?;
+        let hours = parse_field(fields[1], 0, 23, "hour")?;
+        let days = parse_field(fields[2], 1, 31, "day of month")?;
+        let months = parse_field(fields[3], 1, 12, "month")?;
+        let mut weekdays = parse_field(fields[4], 0, 7, "day of week")?;
+        // 7 is Sunday too
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(CronExpr {
+            minutes,
+            hours: hours as u32,
+            days: days as u32,
+            months: months as u16,
+            weekdays: (weekdays & 0x7f) as u8,
+            either_day: fields[2] != "*" && fields[4] != "*",
+        })
+    }
+
+    fn day_matches(&self, t: DateTime<Utc>) -> bool {
+        let dom = self.days & (1 << t.day()) != 0;
+        let dow = self.weekdays & (1 << t.weekday().num_days_from_sunday()) != 0;
+        if self.either_day { dom || dow } else { dom && dow }
+    }
+
+    /// First matching minute strictly after `after`, searching up to five years ahead
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut t = after.duration_trunc(Duration::minutes(1)).ok()? + Duration::minutes(1);
+        let limit = after + Duration::days(5 * 366);
+        while t <= limit {
+            if self.months & (1 << t.month()) == 0 {
+                // Jump to the first minute of the next month
+                let (y, m) = if t.month() == 12 { (t.year() + 1, 1) } else { (t.year(), t.month() + 1) };
+                t = Utc.with_ymd_and_hms(y, m, 1, 0, 0, 0).single()?;
+            } else if !self.day_matches(t) {
+                t = t.with_hour(0)?.with_minute(0)? + Duration::days(1);
+            } else if self.hours & (1 << t.hour()) == 0 {
+                t = t.with_minute(0)? + Duration::hours(1);
+            } else if self.minutes & (1 << t.minute()) == 0 {
+                t += Duration::minutes(1);
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+/// Parse one cron field: `*`, `N`, `A-B`, `*/S`, `A-B/S`, or a comma list of those
+fn parse_field(field: &str, min: u32, max: u32, name: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid {} field '{}'", name, field);
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((r, s)) => (r, s.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (a.parse().map_err(|_| invalid())?, b.parse().map_err(|_| invalid())?)
+        } else {
+            let n: u32 = range.parse().map_err(|_| invalid())?;
+            // "N/S" means every S starting at N
+            (n, if part.contains('/') { max } else { n })
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("{} must be between {} and {}", name, min, max));
+        }
+        for v in (start..=end).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+    Ok(mask)
+}
+
+impl Config {
+    pub fn add_scheduled_job(&mut self, action: JobAction, schedule: JobSchedule, created_by: &str) -> u64 {
+        let id = self.next_job_id;
+        self.next_job_id += 1;
+        self.scheduled_jobs.push(ScheduledJob {
+            id,
+            action,
+            schedule,
+            created_by: created_by.to_string(),
+            created_at: Utc::now(),
+            last_run: None,
+            last_result: None,
+        });
+        id
+    }
+}
+
+/// Run due jobs.
+pub async fn run_scheduler(state: AppState) {
+    while state.idle(TICK_INTERVAL).await {
+        run_due_jobs(&state).await;
+    }
+}
+
+async fn run_due_jobs(state: &AppState) {
+    let now = Utc::now();
+    let due: Vec<ScheduledJob> = {
+        let config = state.config.read().await;
+        let Some(c) = config.as_ref() else { return };
+        c.scheduled_jobs
+            .iter()
+            .filter(|j| j.next_run().is_some_and(|t| t <= now))
+            .cloned()
+            .collect()
+    };
+
+    for job in due {
+        // Jobs not started yet are still due when TierDrop next runs
+        if state.shutdown.is_cancelled() {
+            break;
+        }
+        let result = run_action(state, &job.action).await;
+        match &result {
+            Ok(detail) => info!("Scheduled job #{} ({}): {}", job.id, job.action.label(), detail),
+            Err(e) => warn!("Scheduled job #{} ({}) failed: {}", job.id, job.action.label(), e),
+        }
+
+        let mut config = state.config.write().await;
+        let Some(ref mut c) = *config else { return };
+        // The job may have been deleted while it ran
+        if let Some(j) = c.scheduled_jobs.iter_mut().find(|j| j.id == job.id) {
+            j.last_run = Some(now);
+            j.last_result = Some(result.as_ref().map(|_| "OK".to_string()).unwrap_or_else(|e| e.clone()));
+        }
+        let detail = match &result {
+            Ok(detail) => format!("Job #{}: {}", job.id, detail),
+            Err(e) => format!("Job #{} failed: {}", job.id, e),
+        };
+        c.audit(SCHEDULER_ACTOR, &job.action.label(), job.action.nwid(), job.action.member_id(), detail);
+        if let Err(e) = c.save() {
+            warn!("Failed to save scheduled job result: {}", e);
+        }
+    }
+}
+
+/// Carry out one action. Returns a short description of what happened.
+pub async fn run_action(state: &AppState, action: &JobAction) -> Result<String, String> {
+    if let JobAction::Backup = action {
+        return write_backup(state).await;
+    }
+
+    let client = state.zt_client.read().await.clone().ok_or("Not configured")?;
+    let result = match action {
+        JobAction::AuthorizeMember { nwid, member_id } | JobAction::DeauthorizeMember { nwid, member_id } => {
+            let authorize = matches!(action, JobAction::AuthorizeMember { .. });
+            // Posting to a missing member would create it
+            let member = client.get_controller_member(nwid, member_id).await?;
+            if member.address.is_none() && member.id.is_none() {
+                return Err("Member not found".to_string());
+            }
+            if member.is_authorized() == authorize {
+                return Ok("Already in that state".to_string());
+            }
+            client
+                .update_controller_member(nwid, member_id, serde_json::json!({"authorized": authorize}))
+                .await?;
+            if !authorize {
+                state.clear_guest_access(nwid, member_id, SCHEDULER_ACTOR).await;
+            }
+            Ok(if authorize { "Authorized" } else { "De-authorized" }.to_string())
+        }
+        JobAction::DisableNetwork { nwid } => disable_network(state, client.as_ref(), nwid).await,
+        JobAction::EnableNetwork { nwid } => enable_network(state, client.as_ref(), nwid).await,
+        JobAction::Backup => unreachable!(),
+    };
+    state.notify_poller();
+    result
+}
+
+async fn disable_network(state: &AppState, client: &dyn ZtBackend, nwid: &str) -> Result<String, String> {
+    {
+        let config = state.config.read().await;
+        if config.as_ref().is_some_and(|c| c.disabled_networks.contains_key(nwid)) {
+            return Ok("Already disabled".to_string());
+        }
+    }
+
+    let ids = client.get_controller_members(nwid).await?;
+    let mut deauthorized = Vec::new();
+    let mut error = None;
+    for member_id in ids.keys() {
+        match client.get_controller_member(nwid, member_id).await {
+            Ok(m) if m.is_authorized() => {}
+            Ok(_) => continue,
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
+        }
+        match client
+            .update_controller_member(nwid, member_id, serde_json::json!({"authorized": false}))
+            .await
+        {
+            Ok(_) => deauthorized.push(member_id.clone()),
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
+        }
+    }
+
+    // Remember whoever went offline, even on partial failure, so enabling restores them
+    let count = deauthorized.len();
+    if count > 0 || error.is_none() {
+        let mut config = state.config.write().await;
+        if let Some(ref mut c) = *config {
+            c.disabled_networks.insert(nwid.to_string(), deauthorized);
+            c.save()?;
+        }
+    }
+    match error {
+        Some(e) => Err(format!("Stopped after de-authorizing {} members: {}", count, e)),
+        None => Ok(format!("De-authorized {} members", count)),
+    }
+}
+
+async fn enable_network(state: &AppState, client: &dyn ZtBackend, nwid: &str) -> Result<String, String> {
+    let members = {
+        let config = state.config.read().await;
+        config.as_ref().and_then(|c| c.disabled_networks.get(nwid).cloned())
+    };
+    let Some(members) = members else {
+        return Ok("Not disabled".to_string());
+    };
+
+    let mut restored = 0;
+    for member_id in &members {
+        let member = client.get_controller_member(nwid, member_id).await?;
+        // Skip members deleted while the network was disabled
+        if member.address.is_none() && member.id.is_none() {
+            continue;
+        }
+        client
+            .update_controller_member(nwid, member_id, serde_json::json!({"authorized": true}))
+            .await?;
+        restored += 1;
+    }
+
+    {
+        let mut config = state.config.write().await;
+        if let Some(ref mut c) = *config {
+            c.disabled_networks.remove(nwid);
+            c.save()?;
+        }
+    }
+    Ok(format!("Re-authorized {} members", restored))
+}
+
+/// Where scheduled backups are written
+pub fn backups_dir() -> PathBuf {
+    data_dir().join("backups")
+}
+
+/// Write a backup archive under the data directory and prune old ones
+async fn write_backup(state: &AppState) -> Result<String, String> {
+    let archive = crate::routes::backup::create_backup_archive(state).await?;
+    let dir = backups_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+    let path = dir.join(archive.file_name());
+    let target = path.clone();
+    let (archive, size) = tokio::task::spawn_blocking(move || {
+        let result = std::fs::File::create(&target).and_then(|file| archive.write_to(std::io::BufWriter::new(file)));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&target);
+        }
+        result.map(|size| (archive, size))
+    })
+    .await
+    .map_err(|e| format!("Backup task failed: {}", e))?
+    .map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+    state.record_backup(&archive, size, BackupTarget::Local, SCHEDULER_ACTOR).await;
+
+    let retention = {
+        let config = state.config.read().await;
+        config.as_ref().map(|c| c.backup_retention.clone()).unwrap_or_default()
+    };
+    // Files whose names don't carry a timestamp aren't ours to delete
+    let backups: Vec<_> = std::fs::read_dir(&dir)
+        .map_err(|e| format!("Failed to read {:?}: {}", dir, e))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter_map(|p| {
+            let name = p.file_name()?.to_str()?;
+            let stamp = name.strip_prefix("tierdrop-backup-")?.strip_suffix(".tar.gz")?;
+            let taken = chrono::NaiveDateTime::parse_from_str(stamp, "%Y%m%d-%H%M%S").ok()?.and_utc();
+            Some((p, taken))
+        })
+        .collect();
+    let prunable = retention.prunable(&backups, Utc::now());
+    for old in &prunable {
+        if let Err(e) = std::fs::remove_file(old) {
+            warn!("Failed to remove old backup {:?}: {}", old, e);
+        }
+    }
+    if !prunable.is_empty() {
+        info!("Pruned {} old backups", prunable.len());
+        return Ok(format!("Wrote {}, pruned {} old backups", path.display(), prunable.len()));
+    }
+    Ok(format!("Wrote {}", path.display()))
+}
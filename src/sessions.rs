@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::header::USER_AGENT;
+use axum::http::request::Parts;
+use axum::http::HeaderMap;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::trusted_proxies;
+
+/// Inactivity window for sessions without "remember me"
+pub const DEFAULT_IDLE_MINUTES: i64 = 30;
+
+/// A logged-in browser, as shown on the sessions panel
+#[derive(Clone, Debug)]
+pub struct ActiveSession {
+    pub id: String,
+    pub user_id: u64,
+    pub created_at: DateTime<Utc>,
+    pub last_active: DateTime<Utc>,
+    pub client: ClientInfo,
+    /// Inactivity window, longer for "remember me" logins
+    pub idle_timeout: Duration,
+}
+
+impl ActiveSession {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        now - self.last_active > self.idle_timeout
+    }
+
+    pub fn remembered(&self) -> bool {
+        self.idle_timeout > Duration::minutes(DEFAULT_IDLE_MINUTES)
+    }
+
+    pub fn display_created_at(&self) -> String {
+        crate::preferences::format_time(self.created_at)
+    }
+
+    pub fn display_last_active(&self) -> String {
+        crate::preferences::format_time(self.last_active)
+    }
+}
+
+/// Every session that has logged in since startup. Sessions live in memory, so this does too;
+/// a session whose ID is missing here has been revoked.
+#[derive(Default)]
+pub struct SessionRegistry {
+    sessions: RwLock<HashMap<String, ActiveSession>>,
+}
+
+impl SessionRegistry {
+    /// Record a new login and return the ID to keep in the session
+    pub async fn register(&self, user_id: u64, client: ClientInfo, idle_timeout: Duration) -> String {
+        let mut bytes = [0u8; 18];
+        OsRng.fill_bytes(&mut bytes);
+        let id = URL_SAFE_NO_PAD.encode(bytes);
+        let now = Utc::now();
+        let mut sessions = self.sessions.write().await;
+        sessions.retain(|_, s| !s.is_expired(now));
+        sessions.insert(
+            id.clone(),
+            ActiveSession {
+                id: id.clone(),
+                user_id,
+                created_at: now,
+                last_active: now,
+                client,
+                idle_timeout,
+            },
+        );
+        id
+    }
+
+    /// Mark a session as used. False if it was revoked or belongs to someone else.
+    pub async fn touch(&self, id: &str, user_id: u64) -> bool {
+        let mut sessions = self.sessions.write().await;
+        match sessions.get_mut(id) {
+            Some(s) if s.user_id == user_id => {
+                s.last_active = Utc::now();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub async fn get(&self, id: &str) -> Option<ActiveSession> {
+        self.sessions.read().await.get(id).cloned()
+    }
+
+    pub async fn revoke(&self, id: &str) -> bool {
+        self.sessions.write().await.remove(id).is_some()
+    }
+
+    /// Revoke all of a user's sessions. Returns how many were open.
+    pub async fn revoke_user(&self, user_id: u64) -> usize {
+        let mut sessions = self.sessions.write().await;
+        let before = sessions.len();
+        sessions.retain(|_, s| s.user_id != user_id);
+        before - sessions.len()
+    }
+
+    /// Live sessions, most recently active first; `None` lists every user's
+    pub async fn list(&self, user_id: Option<u64>) -> Vec<ActiveSession> {
+        let now = Utc::now();
+        let mut list: Vec<ActiveSession> = self
+            .sessions
+            .read()
+            .await
+            .values()
+            .filter(|s| !s.is_expired(now) && user_id.is_none_or(|id| s.user_id == id))
+            .cloned()
+            .collect();
+        list.sort_by_key(|s| std::cmp::Reverse(s.last_active));
+        list
+    }
+}
+
+/// Where a request came from, for session and sign-in records
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ClientInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    /// The client address comes from a reverse proxy's headers only when it is a trusted one
+    pub fn new(headers: &HeaderMap, peer: Option<SocketAddr>) -> Self {
+        let ip = trusted_proxies::client_ip(headers, peer).map(|ip| ip.to_string());
+        let user_agent = headers
+            .get(USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.chars().take(300).collect());
+        Self { ip, user_agent }
+    }
+
+    pub fn display_ip(&self) -> &str {
+        self.ip.as_deref().unwrap_or("Unknown")
+    }
+
+    /// Short browser and OS description from the user agent
+    pub fn device(&self) -> String {
+        let Some(ua) = self.user_agent.as_deref() else {
+            return "Unknown device".to_string();
+        };
+        let browser = [
+            ("Edg/", "Edge"),
+            ("OPR/", "Opera"),
+            ("Firefox/", "Firefox"),
+            ("Chrome/", "Chrome"),
+            ("Safari/", "Safari"),
+            ("curl/", "curl"),
+        ]
+        .iter()
+        .find(|(needle, _)| ua.contains(needle))
+        .map(|(_, name)| *name);
+        let os = [
+            ("Windows", "Windows"),
+            ("iPhone", "iOS"),
+            ("iPad", "iPadOS"),
+            ("Android", "Android"),
+            ("Mac OS X", "macOS"),
+            ("Linux", "Linux"),
+        ]
+        .iter()
+        .find(|(needle, _)| ua.contains(needle))
+        .map(|(_, name)| *name);
+        match (browser, os) {
+            (Some(b), Some(o)) => format!("{} on {}", b, o),
+            (Some(b), None) => b.to_string(),
+            (None, Some(o)) => o.to_string(),
+            (None, None) => ua.chars().take(60).collect(),
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|c| c.0);
+        Ok(Self::new(&parts.headers, peer))
+    }
+}
@@ -0,0 +1,86 @@
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Lifetimes offered for a share link, in hours
+pub const VALID_HOURS: [(i64, &str); 4] = [(1, "1 hour"), (24, "24 hours"), (168, "7 days"), (720, "30 days")];
+
+/// A link showing a network's members, read-only, to someone without an account.
+/// The URL carries a signature made with the approval link key; the link is kept so it can be
+/// listed and revoked before it expires.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShareLink {
+    pub id: u64,
+    pub nwid: String,
+    /// Who it is for, e.g. a customer's name
+    #[serde(default)]
+    pub label: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl ShareLink {
+    pub fn new(id: u64, nwid: &str, label: &str, created_by: &str, valid_for: Duration) -> Self {
+        let now = Utc::now();
+        Self {
+            id,
+            nwid: nwid.to_string(),
+            label: label.trim().to_string(),
+            created_by: created_by.to_string(),
+            created_at: now,
+            expires_at: now + valid_for,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+
+    /// Path of the shared page, signed with `secret`
+    pub fn path(&self, secret: &str) -> String {
+        format!("/share/{}/{}", self.id, sign(secret, self))
+    }
+
+    pub fn display_created_at(&self) -> String {
+        crate::preferences::format_time(self.created_at)
+    }
+
+    pub fn display_expires_at(&self) -> String {
+        crate::preferences::format_time(self.expires_at)
+    }
+}
+
+fn mac(secret: &str, link: &ShareLink) -> HmacSha256 {
+    // HMAC accepts keys of any length
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC key");
+    mac.update(format!("share:{}:{}:{}", link.id, link.nwid, link.expires_at.timestamp()).as_bytes());
+    mac
+}
+
+fn sign(secret: &str, link: &ShareLink) -> String {
+    mac(secret, link).finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Check a link's signature and expiry
+pub fn verify(secret: &str, link: &ShareLink, signature: &str) -> Result<(), String> {
+    let valid = crate::approvals::decode_signature(signature).is_some_and(|b| mac(secret, link).verify_slice(&b).is_ok());
+    if secret.is_empty() || !valid {
+        return Err("This share link is not valid.".to_string());
+    }
+    if link.is_expired() {
+        return Err("This share link has expired.".to_string());
+    }
+    Ok(())
+}
+
+/// Parse the lifetime select value (hours)
+pub fn parse_valid_for(hours: &str) -> Result<Duration, String> {
+    match hours.trim().parse::<i64>() {
+        Ok(h) if VALID_HOURS.iter().any(|(v, _)| *v == h) => Ok(Duration::hours(h)),
+        _ => Err("Invalid expiry.".to_string()),
+    }
+}
@@ -1,38 +1,194 @@
-use axum::extract::State;
+use axum::extract::{Query, State};
 use axum::response::sse::{Event, KeepAlive, Sse};
 use futures::stream::Stream;
+use axum::Extension;
+use serde::{Deserialize, Serialize};
 use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
 use tokio_stream::wrappers::BroadcastStream;
 use tokio_stream::StreamExt;
 
-use crate::state::AppState;
+use crate::permissions;
+use crate::state::{AppState, User};
 
 #[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum SseEvent {
     StatusChanged,
     ControllerNetworksChanged,
-    ControllerMembersChanged,
+    /// Members changed in these networks
+    ControllerMembersChanged { networks: Vec<String> },
+    /// The controller became unreachable, recovered, or failed differently
+    ControllerErrorChanged,
+    /// Queued edits to these networks were applied or given up
+    RetriesChanged { networks: Vec<String> },
+    /// Members joined or left a network, or its settings changed how members are shown
+    NetworkMembersChanged { nwid: String },
+    /// One member of a network changed
+    MemberChanged { nwid: String, member_id: String, change: MemberChange },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MemberChange {
+    Added,
+    Updated,
+    Removed,
 }
 
 impl SseEvent {
-    pub fn event_name(&self) -> &'static str {
+    /// Network and member events are named after what they concern, so an element can listen for
+    /// exactly the network or member it shows, e.g. `sse:member-<nwid>-<member id>`.
+    pub fn event_name(&self) -> String {
+        match self {
+            SseEvent::StatusChanged => "status-changed".to_string(),
+            SseEvent::ControllerNetworksChanged => "ctrl-networks-changed".to_string(),
+            SseEvent::ControllerMembersChanged { .. } => "ctrl-members-changed".to_string(),
+            SseEvent::ControllerErrorChanged => "ctrl-error-changed".to_string(),
+            SseEvent::RetriesChanged { .. } => "retries-changed".to_string(),
+            SseEvent::NetworkMembersChanged { nwid } => format!("network-members-{}", nwid),
+            SseEvent::MemberChanged { nwid, member_id, .. } => format!("member-{}-{}", nwid, member_id),
+        }
+    }
+
+    /// What changed, or `None` for events about everything
+    pub fn payload(&self) -> Option<serde_json::Value> {
+        let payload = match self {
+            SseEvent::ControllerMembersChanged { networks } | SseEvent::RetriesChanged { networks } => {
+                serde_json::json!({ "networks": networks })
+            }
+            SseEvent::NetworkMembersChanged { nwid } => serde_json::json!({ "nwid": nwid }),
+            SseEvent::MemberChanged { nwid, member_id, change } => serde_json::json!({
+                "nwid": nwid,
+                "member_id": member_id,
+                "change": change,
+            }),
+            _ => return None,
+        };
+        Some(payload)
+    }
+
+    /// Event data: the payload as JSON, or nothing
+    pub fn data(&self) -> String {
+        self.payload().map(|p| p.to_string()).unwrap_or_default()
+    }
+
+    /// The network this event concerns, if it's about a single one
+    fn network(&self) -> Option<&str> {
         match self {
-            SseEvent::StatusChanged => "status-changed",
-            SseEvent::ControllerNetworksChanged => "ctrl-networks-changed",
-            SseEvent::ControllerMembersChanged => "ctrl-members-changed",
+            SseEvent::NetworkMembersChanged { nwid } | SseEvent::MemberChanged { nwid, .. } => Some(nwid),
+            _ => None,
         }
     }
+
+    /// The event as `user` may see it: events about networks they can't read are dropped, and
+    /// those networks are left out of events about several, so changes there can't be inferred
+    fn visible_to(&self, user: &User) -> Option<SseEvent> {
+        match self {
+            SseEvent::ControllerMembersChanged { networks } | SseEvent::RetriesChanged { networks } => {
+                let networks: Vec<String> = networks
+                    .iter()
+                    .filter(|nwid| permissions::can_read(user, nwid))
+                    .cloned()
+                    .collect();
+                if networks.is_empty() {
+                    return None;
+                }
+                Some(match self {
+                    SseEvent::RetriesChanged { .. } => SseEvent::RetriesChanged { networks },
+                    _ => SseEvent::ControllerMembersChanged { networks },
+                })
+            }
+            _ if self.network().is_some_and(|nwid| !permissions::can_read(user, nwid)) => None,
+            _ => Some(self.clone()),
+        }
+    }
+
+    /// The event as sent to a page of `user`'s that shows `network`, if it's sent at all. Only the
+    /// page showing a network needs that network's member events.
+    pub fn for_viewer(&self, user: &User, network: Option<&str>) -> Option<SseEvent> {
+        let event = self.visible_to(user)?;
+        if event.network().is_some_and(|nwid| network != Some(nwid)) {
+            return None;
+        }
+        Some(event)
+    }
+}
+
+/// Pages with the event stream open, which decide how often the poller asks ZeroTier for updates
+#[derive(Default)]
+pub struct Viewers {
+    connected: AtomicUsize,
+    /// Of those, the ones showing a network's members
+    focused: AtomicUsize,
+    changed: Notify,
+}
+
+impl Viewers {
+    pub fn connected(&self) -> usize {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    pub fn focused(&self) -> usize {
+        self.focused.load(Ordering::Relaxed)
+    }
+
+    /// Wait until a page opens or closes its event stream
+    pub async fn changed(&self) {
+        self.changed.notified().await
+    }
+
+    pub fn join(self: &Arc<Self>, focused: bool) -> Viewer {
+        self.connected.fetch_add(1, Ordering::Relaxed);
+        if focused {
+            self.focused.fetch_add(1, Ordering::Relaxed);
+        }
+        self.changed.notify_waiters();
+        Viewer { viewers: self.clone(), focused }
+    }
+}
+
+/// Counted in `Viewers` until the event stream holding it is dropped
+pub struct Viewer {
+    viewers: Arc<Viewers>,
+    focused: bool,
+}
+
+impl Drop for Viewer {
+    fn drop(&mut self) {
+        self.viewers.connected.fetch_sub(1, Ordering::Relaxed);
+        if self.focused {
+            self.viewers.focused.fetch_sub(1, Ordering::Relaxed);
+        }
+        self.viewers.changed.notify_waiters();
+    }
+}
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    /// Set by the network detail page
+    pub network: Option<String>,
 }
 
 pub async fn sse_handler(
     State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(query): Query<EventsQuery>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     let rx = state.tx.subscribe();
-    let stream = BroadcastStream::new(rx).filter_map(|result| match result {
+    let viewer = state.viewers.join(query.network.is_some());
+    // End the stream on shutdown, or the open connection would hold up graceful shutdown
+    let events = futures::StreamExt::take_until(BroadcastStream::new(rx), state.shutdown.cancelled_owned());
+    let stream = events.filter_map(move |result| match result {
         Ok(event) => {
+            // Holding the viewer here keeps the page counted until the stream closes
+            let _ = &viewer;
+            let event = event.for_viewer(&user, query.network.as_deref())?;
             let sse_event = Event::default()
                 .event(event.event_name())
-                .data("");
+                .data(event.data());
             Some(Ok(sse_event))
         }
         Err(_) => None, // Lagged — skip, next poll cycle will catch up
@@ -1,16 +1,50 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
 use chrono::{DateTime, Utc};
-use tokio::sync::{broadcast, Notify, RwLock};
+use tokio::sync::{broadcast, mpsc, Notify, RwLock};
 use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
-use crate::sse::SseEvent;
-use crate::zt::client::ZtClient;
+use crate::agents::AgentReport;
+use crate::api_keys::ApiKey;
+use crate::audit::{AuditEntry, LoginEvent};
+use crate::branding::Branding;
+use crate::consistency::ConsistencyReport;
+use crate::dns::{DnsServerHandle, DnsServerSettings};
+use crate::email::SmtpSettings;
+use crate::guest::GuestAccess;
+use crate::identity::IdentityAlert;
+use crate::invites::{Invite, JoinRequest};
+use crate::notifications::{NotificationEvent, NotificationSettings};
+use crate::oidc::OidcSettings;
+use crate::passkeys::Passkey;
+use crate::password_reset::PasswordReset;
+use crate::preferences::Preferences;
+use crate::releases::ReleaseInfo;
+use crate::routes::backup::{BackupContents, BackupRecord, PendingRestore};
+use crate::routes::migrate::PendingMigration;
+use crate::schedule::{BackupRetention, ScheduledJob};
+use crate::retry::RetryQueue;
+use crate::sessions::SessionRegistry;
+use crate::shares::ShareLink;
+use crate::sse::{SseEvent, Viewers};
+use crate::zt::backend::{self, SharedBackend};
 use crate::zt::models::ZtState;
+use crate::zt::rules::RuleDefinitions;
+use crate::zt::snippets::CustomRuleSnippet;
 
 const APP_NAME: &str = "tierdrop";
-const CONFIG_FILENAME: &str = "config.json";
+const DB_FILENAME: &str = "tierdrop.db";
+/// Where the config lived before the SQLite store; read once to migrate
+pub const LEGACY_CONFIG_FILENAME: &str = "config.json";
+
+/// Key in `network_permissions` whose permissions apply to every network, including future ones
+pub const ALL_NETWORKS: &str = "*";
+
+/// Prefix of `network_permissions` keys whose permissions apply to each network of a network group
+pub const NETWORK_GROUP_PREFIX: &str = "group:";
 
 /// Per-network permissions for a user
 #[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
@@ -36,6 +70,118 @@ impl NetworkPermissions {
     pub fn has_any(&self) -> bool {
         self.read || self.authorize || self.modify || self.delete
     }
+
+    /// Everything granted by either set
+    pub fn union(&self, other: &NetworkPermissions) -> Self {
+        Self {
+            read: self.read || other.read,
+            authorize: self.authorize || other.authorize,
+            modify: self.modify || other.modify,
+            delete: self.delete || other.delete,
+        }
+    }
+}
+
+/// Preset permissions a non-admin user gets on every network, including ones created later
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Only the per-network permissions that were ticked
+    #[default]
+    Custom,
+    /// Read everywhere
+    Auditor,
+    /// Read, authorize and modify everywhere
+    Operator,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Custom, Role::Auditor, Role::Operator];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Custom => "custom",
+            Role::Auditor => "auditor",
+            Role::Operator => "operator",
+        }
+    }
+
+    pub fn parse(s: &str) -> Self {
+        Role::ALL.into_iter().find(|r| r.as_str() == s).unwrap_or_default()
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Role::Custom => "Custom",
+            Role::Auditor => "Auditor",
+            Role::Operator => "Operator",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Role::Custom => "Per-network permissions only",
+            Role::Auditor => "Read every network",
+            Role::Operator => "Read, authorize and modify every network",
+        }
+    }
+
+    /// What the role grants on any network
+    pub fn permissions(&self) -> NetworkPermissions {
+        match self {
+            Role::Custom => NetworkPermissions::default(),
+            Role::Auditor => NetworkPermissions {
+                read: true,
+                ..Default::default()
+            },
+            Role::Operator => NetworkPermissions {
+                read: true,
+                authorize: true,
+                modify: true,
+                delete: false,
+            },
+        }
+    }
+}
+
+/// A named set of network permissions shared by its members
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct UserGroup {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub network_permissions: HashMap<String, NetworkPermissions>,
+}
+
+impl UserGroup {
+    pub fn get_network_permissions(&self, nwid: &str) -> NetworkPermissions {
+        self.network_permissions.get(nwid).cloned().unwrap_or_default()
+    }
+
+    pub fn accessible_network_count(&self) -> usize {
+        self.network_permissions.values().filter(|p| p.has_any()).count()
+    }
+}
+
+/// A named folder of networks, shown together on the dashboard and granted permissions as one
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct NetworkGroup {
+    pub id: u64,
+    pub name: String,
+    /// IDs of the networks in the group; a network is in at most one group
+    #[serde(default)]
+    pub networks: Vec<String>,
+}
+
+impl NetworkGroup {
+    /// Key of the group's entry in `network_permissions`
+    pub fn permission_key(&self) -> String {
+        format!("{}{}", NETWORK_GROUP_PREFIX, self.id)
+    }
+
+    pub fn contains(&self, nwid: &str) -> bool {
+        self.networks.iter().any(|n| n == nwid)
+    }
 }
 
 /// A user account
@@ -44,7 +190,16 @@ pub struct User {
     pub id: u64,
     pub username: String,
     pub password_hash: String,
+    /// Where password reset links are sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
     pub is_admin: bool,
+    /// Preset applied to every network on top of `network_permissions`
+    #[serde(default)]
+    pub role: Role,
+    /// Non-admins with this can create networks and get full permissions on the ones they create
+    #[serde(default)]
+    pub can_create_networks: bool,
     #[serde(default)]
     pub network_permissions: HashMap<String, NetworkPermissions>,
     pub created_at: DateTime<Utc>,
@@ -54,6 +209,26 @@ pub struct User {
     /// Base32-encoded TOTP secret (only set if totp_enabled is true)
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub totp_secret: Option<String>,
+    /// `issuer|sub` of the single sign-on identity linked to this user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oidc_subject: Option<String>,
+    #[serde(default)]
+    pub passkeys: Vec<Passkey>,
+    /// Personal API keys for scripts acting as this user
+    #[serde(default)]
+    pub api_keys: Vec<ApiKey>,
+    /// Disabled users cannot sign in
+    #[serde(default)]
+    pub disabled: bool,
+    /// IDs of the groups this user belongs to
+    #[serde(default)]
+    pub groups: Vec<u64>,
+    #[serde(default)]
+    pub preferences: Preferences,
+    /// Permissions inherited from user groups and network groups, filled in by `Config::load_user`
+    /// for permission checks
+    #[serde(skip)]
+    pub group_permissions: HashMap<String, NetworkPermissions>,
 }
 
 impl User {
@@ -63,11 +238,21 @@ impl User {
             id,
             username,
             password_hash,
+            email: None,
             is_admin: true,
+            role: Role::Custom,
+            can_create_networks: false,
             network_permissions: HashMap::new(),
             created_at: Utc::now(),
             totp_enabled: false,
             totp_secret: None,
+            oidc_subject: None,
+            passkeys: Vec::new(),
+            api_keys: Vec::new(),
+            disabled: false,
+            groups: Vec::new(),
+            preferences: Preferences::default(),
+            group_permissions: HashMap::new(),
         }
     }
 
@@ -77,14 +262,44 @@ impl User {
             id,
             username,
             password_hash,
+            email: None,
             is_admin,
+            role: Role::Custom,
+            can_create_networks: false,
             network_permissions: HashMap::new(),
             created_at: Utc::now(),
             totp_enabled: false,
             totp_secret: None,
+            oidc_subject: None,
+            passkeys: Vec::new(),
+            api_keys: Vec::new(),
+            disabled: false,
+            groups: Vec::new(),
+            preferences: Preferences::default(),
+            group_permissions: HashMap::new(),
         }
     }
 
+    /// Whether the user has TOTP or a passkey to complete a password login with
+    pub fn has_second_factor(&self) -> bool {
+        (self.totp_enabled && self.totp_secret.is_some()) || !self.passkeys.is_empty()
+    }
+
+    /// Whether the account signs in only through single sign-on, with no password of its own
+    pub fn is_sso_only(&self) -> bool {
+        self.oidc_subject.is_some() && self.password_hash.is_empty()
+    }
+
+    /// Whether the account has a password or single sign-on identity to sign in with
+    pub fn has_sign_in(&self) -> bool {
+        !self.password_hash.is_empty() || self.oidc_subject.is_some()
+    }
+
+    /// Whether "require 2FA" applies. Single sign-on-only accounts are left to the provider's MFA.
+    pub fn needs_2fa_enrollment(&self, config: &Config) -> bool {
+        config.require_2fa && !self.is_sso_only() && !self.has_second_factor()
+    }
+
     /// Get permissions for a specific network
     pub fn get_network_permissions(&self, nwid: &str) -> NetworkPermissions {
         if self.is_admin {
@@ -111,18 +326,37 @@ impl User {
     }
 }
 
-/// Returns the platform-appropriate data directory:
+/// Set by `--data-dir`, which takes precedence over `TIERDROP_DATA_DIR`
+static DATA_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_data_dir(path: PathBuf) {
+    let _ = DATA_DIR_OVERRIDE.set(path);
+}
+
+/// Returns `--data-dir` or `TIERDROP_DATA_DIR` if given, otherwise the platform-appropriate
+/// data directory:
 /// - Linux: ~/.local/share/tierdrop/
 /// - Windows: %APPDATA%\tierdrop\
 /// - macOS: ~/Library/Application Support/tierdrop/
-fn data_dir() -> PathBuf {
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = DATA_DIR_OVERRIDE.get() {
+        return dir.clone();
+    }
+    if let Some(dir) = std::env::var_os("TIERDROP_DATA_DIR").filter(|d| !d.is_empty()) {
+        return PathBuf::from(dir);
+    }
     dirs::data_dir()
         .unwrap_or_else(|| PathBuf::from("."))
         .join(APP_NAME)
 }
 
-fn config_path() -> PathBuf {
-    data_dir().join(CONFIG_FILENAME)
+pub fn db_path() -> PathBuf {
+    data_dir().join(DB_FILENAME)
+}
+
+/// Copy of the database taken at each start, to recover from if it gets damaged
+pub fn db_backup_path() -> PathBuf {
+    data_dir().join(format!("{}.bak", DB_FILENAME))
 }
 
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
@@ -138,6 +372,14 @@ pub struct Config {
     pub users: Vec<User>,
     #[serde(default = "default_next_user_id")]
     pub next_user_id: u64,
+    #[serde(default)]
+    pub groups: Vec<UserGroup>,
+    #[serde(default = "default_next_user_id")]
+    pub next_group_id: u64,
+    #[serde(default)]
+    pub network_groups: Vec<NetworkGroup>,
+    #[serde(default = "default_next_user_id")]
+    pub next_network_group_id: u64,
 
     pub zt_token: String,
     #[serde(default = "default_zt_base_url")]
@@ -147,27 +389,118 @@ pub struct Config {
     #[serde(default)]
     pub member_descriptions: HashMap<String, String>,  // member address -> description
     #[serde(default)]
+    pub monitored_members: HashSet<String>,  // member addresses to alert on when offline
+    #[serde(default)]
     pub network_descriptions: HashMap<String, String>,  // nwid -> description
     #[serde(default)]
+    pub network_owners: HashMap<String, u64>,  // nwid -> ID of the user who created it
+    #[serde(default)]
     pub rules_source: HashMap<String, String>,  // nwid -> DSL source
+    #[serde(default)]
+    pub notifications: NotificationSettings,
+    #[serde(default)]
+    pub rule_snippets: Vec<CustomRuleSnippet>,
+    #[serde(default)]
+    pub rule_definitions: HashMap<String, RuleDefinitions>,  // nwid -> builder tags/caps
+    #[serde(default)]
+    pub member_identities: HashMap<String, String>,  // member address -> first seen identity
+    #[serde(default)]
+    pub identity_alerts: Vec<IdentityAlert>,
+    #[serde(default)]
+    pub dns_server: DnsServerSettings,
+    #[serde(default)]
+    pub invites: Vec<Invite>,
+    #[serde(default)]
+    pub join_requests: Vec<JoinRequest>,
+    /// Read-only links to networks' member lists
+    #[serde(default)]
+    pub share_links: Vec<ShareLink>,
+    #[serde(default = "default_next_user_id")]
+    pub next_share_link_id: u64,
+    /// nwid -> hash of the token member agents on that network report with
+    #[serde(default)]
+    pub agent_tokens: HashMap<String, String>,
+    /// "<network ID>-<member address>" -> what the member's agent last reported there
+    #[serde(default)]
+    pub agent_reports: HashMap<String, AgentReport>,
+    /// Key for signing one-click approval links
+    #[serde(default)]
+    pub approval_secret: String,
+    /// Signatures of approval links already used, with when each expires
+    #[serde(default)]
+    pub used_approval_links: HashMap<String, i64>,
+    /// Members authorized only until a set time
+    #[serde(default)]
+    pub guest_access: Vec<GuestAccess>,
+    #[serde(default)]
+    pub audit_log: Vec<AuditEntry>,
+    #[serde(default)]
+    pub login_history: Vec<LoginEvent>,
+    #[serde(default)]
+    pub scheduled_jobs: Vec<ScheduledJob>,
+    #[serde(default)]
+    pub backup_retention: BackupRetention,
+    #[serde(default)]
+    pub backup_contents: BackupContents,
+    #[serde(default)]
+    pub backup_history: Vec<BackupRecord>,
+    #[serde(default = "default_next_job_id")]
+    pub next_job_id: u64,
+    /// nwid -> members de-authorized by a scheduled DisableNetwork
+    #[serde(default)]
+    pub disabled_networks: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub oidc: OidcSettings,
+    /// Users must enroll a second factor before they can use TierDrop
+    #[serde(default)]
+    pub require_2fa: bool,
+    /// How long a "remember me" login lasts without activity; 0 turns the option off
+    #[serde(default = "default_remember_me_days")]
+    pub remember_me_days: u32,
+    /// Outgoing mail for password resets
+    #[serde(default)]
+    pub smtp: SmtpSettings,
+    #[serde(default)]
+    pub password_resets: Vec<PasswordReset>,
+    /// Log every HTTP request with its user
+    #[serde(default)]
+    pub access_log: bool,
+    /// Check GitHub for newer ZeroTier releases
+    #[serde(default)]
+    pub release_check: bool,
+    /// Instance title, logo and accent color
+    #[serde(default)]
+    pub branding: Branding,
 }
 
 fn default_next_user_id() -> u64 {
     1
 }
 
+fn default_next_job_id() -> u64 {
+    1
+}
+
+fn default_remember_me_days() -> u32 {
+    30
+}
+
 fn default_zt_base_url() -> String {
     "http://localhost:9993".to_string()
 }
 
 impl Config {
-    pub fn load() -> Option<Config> {
-        let path = config_path();
-        if !path.exists() {
-            return None;
-        }
-        let data = std::fs::read_to_string(&path).ok()?;
-        let mut config: Config = serde_json::from_str(&data).ok()?;
+    /// The saved config; None before setup. An error means a config exists but can't be read,
+    /// which must not be mistaken for a fresh install.
+    pub fn load() -> Result<Option<Config>, String> {
+        let mut config = match Self::read_db(&db_path()) {
+            Ok(Some(config)) => config,
+            Ok(None) => match Self::migrate_json()? {
+                Some(config) => config,
+                None => return Ok(None),
+            },
+            Err(e) => Self::recover(e)?,
+        };
 
         // Migration: if old username/password_hash exist but no users, create admin
         if config.users.is_empty() {
@@ -183,6 +516,12 @@ impl Config {
             }
         }
 
+        // Migration: configs from before approval links have no signing key
+        if config.approval_secret.is_empty() {
+            config.approval_secret = crate::approvals::generate_secret();
+            let _ = config.save();
+        }
+
         // Migration: ensure next_user_id is greater than all existing user IDs
         if let Some(max_id) = config.users.iter().map(|u| u.id).max() {
             if config.next_user_id <= max_id {
@@ -191,19 +530,107 @@ impl Config {
             }
         }
 
-        Some(config)
+        if let Err(e) = crate::store::snapshot(&db_path(), &db_backup_path()) {
+            tracing::warn!("Failed to back up the configuration database: {}", e);
+        }
+
+        Ok(Some(config))
     }
 
-    pub fn save(&self) -> Result<(), String> {
+    /// Read the stored config again, dropping what the store remembers of earlier saves
+    pub fn reload() -> Result<Option<Config>, String> {
+        crate::store::close();
+        Self::read_db(&db_path())
+    }
+
+    /// A client for the ZeroTier service; `ZT_BASE_URL` overrides the stored address
+    pub fn zt_client(&self) -> SharedBackend {
+        self.zt_client_with_token(self.zt_token.clone())
+    }
+
+    /// A client for the same ZeroTier service using another token
+    pub fn zt_client_with_token(&self, token: String) -> SharedBackend {
+        let base_url = std::env::var("ZT_BASE_URL").unwrap_or_else(|_| self.zt_base_url.clone());
+        backend::connect(base_url, token)
+    }
+
+    fn read_db(path: &Path) -> Result<Option<Config>, String> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        crate::store::load(path)?
+            .map(|value| serde_json::from_value(value).map_err(|e| format!("Failed to read stored configuration: {}", e)))
+            .transpose()
+    }
+
+    /// Put the start-up backup back in place of a database that can't be read.
+    /// The damaged files are kept beside it.
+    fn recover(error: String) -> Result<Config, String> {
+        let db = db_path();
+        let backup = db_backup_path();
+        if !backup.exists() {
+            return Err(format!("{} (no backup at {:?} to recover from)", error, backup));
+        }
+        tracing::error!("{}; recovering from {:?}", error, backup);
+
+        crate::store::close();
+        let suffix = format!("broken-{}", Utc::now().format("%Y%m%d-%H%M%S"));
+        for ext in ["", "-wal", "-shm"] {
+            let file = data_dir().join(format!("{}{}", DB_FILENAME, ext));
+            if file.exists() {
+                let aside = data_dir().join(format!("{}{}.{}", DB_FILENAME, ext, suffix));
+                std::fs::rename(&file, &aside).map_err(|e| format!("Failed to move {:?} aside: {}", file, e))?;
+            }
+        }
+        std::fs::copy(&backup, &db).map_err(|e| format!("Failed to restore {:?}: {}", backup, e))?;
+
+        let config = Self::read_db(&db)
+            .map_err(|e| format!("The backup can't be read either: {}", e))?
+            .ok_or_else(|| "The backup is empty".to_string())?;
+        tracing::warn!(
+            "Configuration restored from the backup taken at the last start; later changes are lost. The damaged database was kept as {}.{}",
+            DB_FILENAME,
+            suffix
+        );
+        Ok(config)
+    }
+
+    /// One-time move of a config.json from before the SQLite store. The file is kept,
+    /// renamed, in case the upgrade has to be rolled back.
+    fn migrate_json() -> Result<Option<Config>, String> {
+        let path = data_dir().join(LEGACY_CONFIG_FILENAME);
+        let Ok(data) = std::fs::read_to_string(&path) else {
+            return Ok(None);
+        };
+        let mut config: Config = serde_json::from_str(&data).map_err(|e| format!("Failed to parse {:?}: {}", path, e))?;
+        config
+            .save()
+            .map_err(|e| format!("Failed to migrate {:?} to the database: {}", path, e))?;
+        let migrated = path.with_extension("json.migrated");
+        match std::fs::rename(&path, &migrated) {
+            Ok(()) => tracing::info!("Moved configuration into {:?}; the old file is kept as {:?}", db_path(), migrated),
+            Err(e) => tracing::warn!("Migrated configuration but could not rename {:?}: {}", path, e),
+        }
+        Ok(Some(config))
+    }
+
+    pub fn save(&mut self) -> Result<(), String> {
         let dir = data_dir();
         std::fs::create_dir_all(&dir)
             .map_err(|e| format!("Failed to create data dir {:?}: {}", dir, e))?;
-        let path = config_path();
-        let json = serde_json::to_string_pretty(self)
-            .map_err(|e| format!("Failed to serialize config: {}", e))?;
-        std::fs::write(&path, json)
-            .map_err(|e| format!("Failed to write config {:?}: {}", path, e))?;
-        Ok(())
+        // The audit log and sign-in history are only appended to and trimmed from the front, so
+        // they are handed over apart and only their new entries serialized
+        let audit_log = std::mem::take(&mut self.audit_log);
+        let login_history = std::mem::take(&mut self.login_history);
+        let value = serde_json::to_value(&*self);
+        self.audit_log = audit_log;
+        self.login_history = login_history;
+        let value = value.map_err(|e| format!("Failed to serialize config: {}", e))?;
+        crate::store::save(
+            &db_path(),
+            value,
+            &[("audit_log", &self.audit_log), ("login_history", &self.login_history)],
+        )
     }
 
     /// Find a user by username
@@ -216,11 +643,44 @@ impl Config {
         self.users.iter().find(|u| u.id == id)
     }
 
+    /// A user with their group permissions resolved, as used for permission checks
+    pub fn load_user(&self, id: u64) -> Option<User> {
+        let mut user = self.find_user_by_id(id)?.clone();
+        for group in self.groups.iter().filter(|g| user.groups.contains(&g.id)) {
+            for (nwid, perms) in &group.network_permissions {
+                let merged = user.group_permissions.get(nwid).cloned().unwrap_or_default().union(perms);
+                user.group_permissions.insert(nwid.clone(), merged);
+            }
+        }
+        // Permissions on a network group, the user's own or a user group's, apply to its networks
+        for network_group in &self.network_groups {
+            let key = network_group.permission_key();
+            let granted = user.get_network_permissions(&key);
+            let granted = match user.group_permissions.get(&key) {
+                Some(inherited) => granted.union(inherited),
+                None => granted,
+            };
+            if !granted.has_any() {
+                continue;
+            }
+            for nwid in &network_group.networks {
+                let merged = user.group_permissions.get(nwid).cloned().unwrap_or_default().union(&granted);
+                user.group_permissions.insert(nwid.clone(), merged);
+            }
+        }
+        Some(user)
+    }
+
     /// Find a user by ID (mutable)
     pub fn find_user_by_id_mut(&mut self, id: u64) -> Option<&mut User> {
         self.users.iter_mut().find(|u| u.id == id)
     }
 
+    /// Find the user owning a passkey credential ID
+    pub fn find_user_by_passkey(&self, credential_id: &str) -> Option<&User> {
+        self.users.iter().find(|u| u.passkeys.iter().any(|p| p.id == credential_id))
+    }
+
     /// Add a new user with auto-generated ID
     pub fn add_user(&mut self, username: String, password_hash: String, is_admin: bool) -> &User {
         let user = User::new(self.next_user_id, username, password_hash, is_admin);
@@ -236,6 +696,94 @@ impl Config {
         self.users.len() < len_before
     }
 
+    /// Add a group with no permissions
+    pub fn add_group(&mut self, name: String) -> u64 {
+        let id = self.next_group_id;
+        self.next_group_id += 1;
+        self.groups.push(UserGroup {
+            id,
+            name,
+            network_permissions: HashMap::new(),
+        });
+        id
+    }
+
+    pub fn find_group_by_id_mut(&mut self, id: u64) -> Option<&mut UserGroup> {
+        self.groups.iter_mut().find(|g| g.id == id)
+    }
+
+    /// Remove a group and its memberships (returns true if removed)
+    pub fn remove_group(&mut self, id: u64) -> bool {
+        let len_before = self.groups.len();
+        self.groups.retain(|g| g.id != id);
+        for user in &mut self.users {
+            user.groups.retain(|g| *g != id);
+        }
+        self.groups.len() < len_before
+    }
+
+    /// Add an empty network group
+    pub fn add_network_group(&mut self, name: String) -> u64 {
+        let id = self.next_network_group_id;
+        self.next_network_group_id += 1;
+        self.network_groups.push(NetworkGroup {
+            id,
+            name,
+            networks: Vec::new(),
+        });
+        id
+    }
+
+    pub fn find_network_group_by_id_mut(&mut self, id: u64) -> Option<&mut NetworkGroup> {
+        self.network_groups.iter_mut().find(|g| g.id == id)
+    }
+
+    /// Remove a network group and the permissions granted on it (returns true if removed)
+    pub fn remove_network_group(&mut self, id: u64) -> bool {
+        let Some(position) = self.network_groups.iter().position(|g| g.id == id) else {
+            return false;
+        };
+        let key = self.network_groups.remove(position).permission_key();
+        for user in &mut self.users {
+            user.network_permissions.remove(&key);
+        }
+        for group in &mut self.groups {
+            group.network_permissions.remove(&key);
+        }
+        true
+    }
+
+    /// Put networks in a network group, taking them out of any other
+    pub fn set_network_group_members(&mut self, id: u64, networks: Vec<String>) {
+        for group in &mut self.network_groups {
+            if group.id == id {
+                group.networks = networks.clone();
+            } else {
+                group.networks.retain(|n| !networks.contains(n));
+            }
+        }
+    }
+
+    /// Take a network out of its network group, if it's in one
+    pub fn ungroup_network(&mut self, nwid: &str) -> bool {
+        let mut removed = false;
+        for group in &mut self.network_groups {
+            let before = group.networks.len();
+            group.networks.retain(|n| n != nwid);
+            removed |= group.networks.len() < before;
+        }
+        removed
+    }
+
+    /// Member IDs with an unacknowledged identity alert
+    pub fn flagged_member_ids(&self) -> HashSet<String> {
+        self.identity_alerts
+            .iter()
+            .filter(|a| a.is_open())
+            .map(|a| a.member_id.clone())
+            .collect()
+    }
+
     /// Check if there's at least one admin user
     pub fn _has_admin(&self) -> bool {
         self.users.iter().any(|u| u.is_admin)
@@ -247,19 +795,88 @@ pub struct AppState {
     pub zt_state: Arc<RwLock<ZtState>>,
     pub tx: broadcast::Sender<SseEvent>,
     pub config: Arc<RwLock<Option<Config>>>,
-    pub zt_client: Arc<RwLock<Option<ZtClient>>>,
+    pub zt_client: Arc<RwLock<Option<SharedBackend>>>,
     pub poll_notify: Arc<Notify>,
+    /// Pages following live updates, for the poller to adapt its pace to
+    pub viewers: Arc<Viewers>,
+    /// Latest stale-metadata report from the consistency check
+    pub consistency_report: Arc<RwLock<Option<ConsistencyReport>>>,
+    /// Control and status of the built-in DNS responder
+    pub dns_server: Arc<DnsServerHandle>,
+    /// Notification dispatcher input, set once the ZT client starts
+    pub notification_tx: Arc<RwLock<Option<mpsc::UnboundedSender<NotificationEvent>>>>,
+    /// Logged-in browsers, for listing and revoking sessions
+    pub sessions: Arc<SessionRegistry>,
+    /// Uploaded backup awaiting confirmation on the restore preview
+    pub pending_restore: Arc<RwLock<Option<PendingRestore>>>,
+    /// Uploaded network export awaiting confirmation on the migration preview
+    pub pending_migration: Arc<RwLock<Option<PendingMigration>>>,
+    /// Edits waiting for ZeroTier to be reachable again
+    pub retries: Arc<RetryQueue>,
+    /// ZeroTier releases from the last release check
+    pub release_info: Arc<RwLock<Option<ReleaseInfo>>>,
+    /// Cancelled when the server begins shutting down
+    pub shutdown: CancellationToken,
+    /// Background work that shutdown waits for
+    tasks: TaskTracker,
 }
 
 impl AppState {
     pub fn new(config: Option<Config>) -> Self {
-        let (tx, _rx) = broadcast::channel::<SseEvent>(64);
+        if let Some(c) = &config {
+            crate::branding::set(&c.branding);
+        }
+        let (tx, _rx) = broadcast::channel::<SseEvent>(256);
         Self {
             zt_state: Arc::new(RwLock::new(ZtState::default())),
             tx,
             config: Arc::new(RwLock::new(config)),
             zt_client: Arc::new(RwLock::new(None)),
             poll_notify: Arc::new(Notify::new()),
+            viewers: Arc::new(Viewers::default()),
+            consistency_report: Arc::new(RwLock::new(None)),
+            dns_server: Arc::new(DnsServerHandle::default()),
+            notification_tx: Arc::new(RwLock::new(None)),
+            sessions: Arc::new(SessionRegistry::default()),
+            pending_restore: Arc::new(RwLock::new(None)),
+            pending_migration: Arc::new(RwLock::new(None)),
+            retries: Arc::new(RetryQueue::default()),
+            release_info: Arc::new(RwLock::new(None)),
+            shutdown: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+        }
+    }
+
+    /// Run background work that shutdown lets finish
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task);
+    }
+
+    /// Wait between runs of a background job. Returns false once shutdown has begun.
+    pub async fn idle(&self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => true,
+            _ = self.shutdown.cancelled() => false,
+        }
+    }
+
+    /// Stop background jobs and wait up to `timeout` for the ones in progress, such as a poll or
+    /// a scheduled backup. Returns false if some were still running.
+    pub async fn shut_down(&self, timeout: Duration) -> bool {
+        self.shutdown.cancel();
+        // Queued notifications still go out; the dispatcher stops once its senders are gone
+        *self.notification_tx.write().await = None;
+        self.tasks.close();
+        tokio::time::timeout(timeout, self.tasks.wait()).await.is_ok()
+    }
+
+    /// Queue an event for the notification dispatcher. Dropped if the ZT client hasn't started.
+    pub async fn send_notification(&self, event: NotificationEvent) {
+        if let Some(tx) = self.notification_tx.read().await.as_ref() {
+            let _ = tx.send(event);
         }
     }
 
@@ -272,40 +889,45 @@ impl AppState {
         self.config.read().await.is_some()
     }
 
-    /// Initialize ZtClient from the stored config and start the background poller.
+    /// Initialize the ZeroTier backend from the stored config and start the background poller.
     pub async fn start_zt(&self) {
-        let base_url;
-        let zt_token;
-        {
-            let config = self.config.read().await;
-            let config = match config.as_ref() {
-                Some(c) => c,
-                None => return,
-            };
-            base_url = std::env::var("ZT_BASE_URL")
-                .unwrap_or_else(|_| config.zt_base_url.clone());
-            zt_token = config.zt_token.clone();
-        }
-
-        let client = ZtClient::new(base_url, zt_token);
+        let client = match self.config.read().await.as_ref() {
+            Some(config) => config.zt_client(),
+            None => return,
+        };
         {
             let mut w = self.zt_client.write().await;
-            *w = Some(client.clone());
+            *w = Some(client);
         }
 
+        let client = self.zt_client.clone();
         let poller_state = self.zt_state.clone();
         let poller_tx = self.tx.clone();
         let poller_notify = self.poll_notify.clone();
-        tokio::spawn(async move {
-            crate::zt::poller::start_poller(
-                client,
-                poller_state,
-                poller_tx,
-                poller_notify,
-                Duration::from_secs(5),
-            )
-            .await;
-        });
+
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        *self.notification_tx.write().await = Some(events_tx.clone());
+        self.spawn(crate::notifications::run_dispatcher(self.config.clone(), events_rx));
+        self.spawn(crate::identity::run_identity_watch(self.clone(), events_tx.clone()));
+        self.spawn(crate::monitor::run_member_monitor(self.clone(), events_tx.clone()));
+        self.spawn(crate::consistency::run_nightly(self.clone()));
+        self.spawn(crate::dns::run_dns_server(self.clone(), self.dns_server.clone()));
+        self.spawn(crate::guest::run_guest_expiry(self.clone()));
+        self.spawn(crate::schedule::run_scheduler(self.clone()));
+        self.spawn(crate::metrics::run_recorder(self.clone()));
+        self.spawn(crate::retry::run_retries(self.clone()));
+        self.spawn(crate::releases::run_release_check(self.clone()));
+        self.spawn(crate::authtoken::run_token_watch(self.clone()));
+
+        self.spawn(crate::zt::poller::start_poller(
+            client,
+            poller_state,
+            poller_tx,
+            poller_notify,
+            self.viewers.clone(),
+            events_tx,
+            self.shutdown.clone(),
+        ));
     }
 
     /// Save or remove a member display name. Empty name removes the entry.
@@ -336,6 +958,22 @@ impl AppState {
         Ok(())
     }
 
+    /// Turn offline alerts for a member on or off
+    pub async fn save_member_monitored(&self, address: &str, monitored: bool) -> Result<(), String> {
+        let mut cfg = self.config.write().await;
+        if let Some(ref mut c) = *cfg {
+            let changed = if monitored {
+                c.monitored_members.insert(address.to_string())
+            } else {
+                c.monitored_members.remove(address)
+            };
+            if changed {
+                c.save()?;
+            }
+        }
+        Ok(())
+    }
+
     /// Save or remove a network description. Empty description removes the entry.
     pub async fn save_network_description(&self, nwid: &str, description: &str) -> Result<(), String> {
         let mut cfg = self.config.write().await;
@@ -370,9 +1008,43 @@ impl AppState {
         cfg.as_ref().and_then(|c| c.rules_source.get(nwid).cloned())
     }
 
+    /// Re-read the stored config after it was changed outside TierDrop (on SIGHUP).
+    /// Sessions stay valid; the ZT client is replaced only if its address or token changed.
+    pub async fn reload_config(&self) -> Result<(), String> {
+        let config = Config::reload()?.ok_or("No configuration is stored")?;
+        let (client, dns_changed) = {
+            let mut w = self.config.write().await;
+            let old = w.replace(config);
+            let new = w.as_ref().expect("config set above");
+            crate::branding::set(&new.branding);
+            match old {
+                None => {
+                    drop(w);
+                    self.start_zt().await;
+                    return Ok(());
+                }
+                Some(old) => {
+                    let client_changed = old.zt_token != new.zt_token || old.zt_base_url != new.zt_base_url;
+                    let dns_changed = serde_json::to_value(&old.dns_server).ok()
+                        != serde_json::to_value(&new.dns_server).ok();
+                    (client_changed.then(|| new.zt_client()), dns_changed)
+                }
+            }
+        };
+        if let Some(client) = client {
+            *self.zt_client.write().await = Some(client);
+            self.notify_poller();
+        }
+        if dns_changed {
+            self.dns_server.restart().await;
+        }
+        Ok(())
+    }
+
     /// Save config, update state, start ZT client + poller.
-    pub async fn configure(&self, config: Config) -> Result<(), String> {
+    pub async fn configure(&self, mut config: Config) -> Result<(), String> {
         config.save()?;
+        crate::branding::set(&config.branding);
         {
             let mut w = self.config.write().await;
             *w = Some(config);
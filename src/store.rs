@@ -0,0 +1,534 @@
+//! SQLite persistence for [`Config`](crate::state::Config).
+//!
+//! Collections that grow with use (users, names, audit entries, ...) each get a table with
+//! one row per entry; every other field is a row in `settings`. Rows hold the entry's JSON,
+//! so the layout follows the serde representation of `Config` and new fields need no schema
+//! change. A save only writes the rows that changed since the previous one, in one transaction.
+//!
+//! The same database also holds the member count history of [`metrics`](crate::metrics) and the
+//! event log of [`history`](crate::history), in plain tables of their own since they aren't part
+//! of `Config`.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::history::{HistoryEvent, HistoryKind};
+use crate::metrics::Sample;
+
+/// How a Config field is spread over rows
+#[derive(Clone, Copy)]
+enum Layout {
+    /// JSON object; one row per key
+    Map,
+    /// Array of strings in no particular order; one row per element
+    Set,
+    /// Array of objects identified by a field; one row per element, kept in order
+    List(&'static str),
+    /// Array mostly appended to and trimmed from the front; one row per element
+    Log,
+}
+
+/// Config fields stored in their own table, named after the field
+const TABLES: [(&str, Layout); 24] = [
+    ("users", Layout::List("id")),
+    ("groups", Layout::List("id")),
+    ("network_groups", Layout::List("id")),
+    ("member_names", Layout::Map),
+    ("member_descriptions", Layout::Map),
+    ("monitored_members", Layout::Set),
+    ("network_descriptions", Layout::Map),
+    ("network_owners", Layout::Map),
+    ("rules_source", Layout::Map),
+    ("rule_snippets", Layout::List("id")),
+    ("rule_definitions", Layout::Map),
+    ("member_identities", Layout::Map),
+    ("identity_alerts", Layout::List("id")),
+    ("invites", Layout::List("token")),
+    ("join_requests", Layout::Log),
+    ("share_links", Layout::List("id")),
+    ("agent_tokens", Layout::Map),
+    ("agent_reports", Layout::Map),
+    ("guest_access", Layout::Log),
+    ("audit_log", Layout::Log),
+    ("login_history", Layout::Log),
+    ("scheduled_jobs", Layout::List("id")),
+    ("backup_history", Layout::List("id")),
+    ("disabled_networks", Layout::Map),
+];
+
+/// Everything else
+const SETTINGS_TABLE: &str = "settings";
+
+/// A row's key, its place in the collection, and its JSON
+type Rows = HashMap<String, (i64, String)>;
+
+struct Store {
+    conn: Connection,
+    /// What each table held after the last save, to work out what changed
+    written: HashMap<&'static str, Rows>,
+    /// The sequence numbers of each log table's rows, oldest first
+    cursors: HashMap<&'static str, VecDeque<i64>>,
+}
+
+/// A log collection handed to [`save`] apart from the rest of the config, so only entries that
+/// aren't stored yet are serialized. Entries must only be appended or trimmed from the front.
+pub trait LogEntries {
+    fn count(&self) -> usize;
+    fn entry_json(&self, index: usize) -> Result<String, String>;
+}
+
+impl<T: Serialize> LogEntries for Vec<T> {
+    fn count(&self) -> usize {
+        self.len()
+    }
+
+    fn entry_json(&self, index: usize) -> Result<String, String> {
+        serde_json::to_string(&self[index]).map_err(|e| format!("Failed to serialize log entry: {}", e))
+    }
+}
+
+static STORE: Mutex<Option<Store>> = Mutex::new(None);
+
+fn db_err(e: rusqlite::Error) -> String {
+    format!("Database error: {}", e)
+}
+
+fn table_names() -> impl Iterator<Item = &'static str> {
+    TABLES.iter().map(|(name, _)| *name).chain(std::iter::once(SETTINGS_TABLE))
+}
+
+fn open(path: &Path) -> Result<Store, String> {
+    let conn = Connection::open(path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+    conn.pragma_update(None, "journal_mode", "WAL").map_err(db_err)?;
+    conn.pragma_update(None, "synchronous", "NORMAL").map_err(db_err)?;
+    for table in table_names() {
+        conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (key TEXT PRIMARY KEY, position INTEGER NOT NULL, value TEXT NOT NULL)",
+                table
+            ),
+            [],
+        )
+        .map_err(db_err)?;
+    }
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS metrics (time INTEGER NOT NULL, nwid TEXT NOT NULL, members INTEGER NOT NULL, \
+         authorized INTEGER NOT NULL, online INTEGER NOT NULL, PRIMARY KEY (nwid, time))",
+        [],
+    )
+    .map_err(db_err)?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS member_events (id INTEGER PRIMARY KEY AUTOINCREMENT, time INTEGER NOT NULL, \
+         kind TEXT NOT NULL, nwid TEXT NOT NULL, member_id TEXT, detail TEXT); \
+         CREATE INDEX IF NOT EXISTS member_events_network ON member_events (nwid, member_id, id)",
+    )
+    .map_err(db_err)?;
+    let mut store = Store { conn, written: HashMap::new(), cursors: HashMap::new() };
+    for table in table_names() {
+        let rows = store.read(table)?;
+        store.written.insert(table, rows);
+    }
+    for (field, layout) in TABLES {
+        if let Layout::Log = layout {
+            let mut seqs: Vec<i64> = store.written[field].values().map(|(p, _)| *p).collect();
+            seqs.sort();
+            store.cursors.insert(field, seqs.into());
+        }
+    }
+    Ok(store)
+}
+
+impl Store {
+    fn read(&self, table: &str) -> Result<Rows, String> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT key, position, value FROM {}", table))
+            .map_err(db_err)?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, (row.get::<_, i64>(1)?, row.get::<_, String>(2)?))))
+            .map_err(db_err)?
+            .collect::<Result<Rows, _>>()
+            .map_err(db_err)?;
+        Ok(rows)
+    }
+}
+
+/// Run `f` against the store at `path`, opening it on first use
+fn with_store<T>(path: &Path, f: impl FnOnce(&mut Store) -> Result<T, String>) -> Result<T, String> {
+    let mut guard = STORE.lock().unwrap_or_else(|e| e.into_inner());
+    if guard.is_none() {
+        *guard = Some(open(path)?);
+    }
+    f(guard.as_mut().expect("store opened above"))
+}
+
+fn sorted_values(rows: &Rows) -> Vec<(&String, &String)> {
+    let mut sorted: Vec<(&i64, &String, &String)> = rows.iter().map(|(k, (p, v))| (p, k, v)).collect();
+    sorted.sort();
+    sorted.into_iter().map(|(_, k, v)| (k, v)).collect()
+}
+
+fn parse(value: &str) -> Result<Value, String> {
+    serde_json::from_str(value).map_err(|e| format!("Corrupt row in database: {}", e))
+}
+
+/// The stored config as one JSON object, or None if nothing has been saved yet
+pub fn load(path: &Path) -> Result<Option<Value>, String> {
+    with_store(path, |store| {
+        let settings = &store.written[SETTINGS_TABLE];
+        if settings.is_empty() {
+            return Ok(None);
+        }
+        let mut config = Map::new();
+        for (key, value) in sorted_values(settings) {
+            config.insert(key.clone(), parse(value)?);
+        }
+        for (field, layout) in TABLES {
+            let rows = &store.written[field];
+            let value = match layout {
+                Layout::Map => {
+                    let mut map = Map::new();
+                    for (key, value) in sorted_values(rows) {
+                        map.insert(key.clone(), parse(value)?);
+                    }
+                    Value::Object(map)
+                }
+                Layout::Set => Value::Array(sorted_values(rows).into_iter().map(|(k, _)| Value::String(k.clone())).collect()),
+                Layout::List(_) | Layout::Log => Value::Array(
+                    sorted_values(rows)
+                        .into_iter()
+                        .map(|(_, v)| parse(v))
+                        .collect::<Result<Vec<_>, _>>()?,
+                ),
+            };
+            config.insert(field.to_string(), value);
+        }
+        Ok(Some(Value::Object(config)))
+    })
+}
+
+/// Whether the database at `path` accepts writes, by taking its write lock and letting it go
+pub fn check_writable(path: &Path) -> Result<(), String> {
+    with_store(path, |store| {
+        store.conn.execute_batch("BEGIN IMMEDIATE; ROLLBACK;").map_err(|e| {
+            let _ = store.conn.execute_batch("ROLLBACK");
+            db_err(e)
+        })
+    })
+}
+
+/// Close the database, e.g. before its file is replaced
+pub fn close() {
+    *STORE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Copy the whole database to `dest`. The copy is written beside it and renamed into place,
+/// so `dest` is always a complete database.
+pub fn snapshot(path: &Path, dest: &Path) -> Result<(), String> {
+    let partial = dest.with_extension("partial");
+    let _ = std::fs::remove_file(&partial);
+    with_store(path, |store| {
+        store
+            .conn
+            .execute("VACUUM INTO ?1", params![partial.to_string_lossy()])
+            .map_err(db_err)
+    })?;
+    std::fs::File::open(&partial)
+        .and_then(|f| f.sync_all())
+        .map_err(|e| format!("Failed to write {:?}: {}", partial, e))?;
+    std::fs::rename(&partial, dest).map_err(|e| format!("Failed to write {:?}: {}", dest, e))
+}
+
+fn key_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Split a collection into rows
+fn to_rows(value: Value, layout: Layout, previous: &Rows, seqs: Option<&VecDeque<i64>>) -> Rows {
+    match (layout, value) {
+        (Layout::Map, Value::Object(map)) => map.into_iter().map(|(k, v)| (k, (0, v.to_string()))).collect(),
+        (Layout::Set, Value::Array(items)) => items.iter().map(|v| (key_string(v), (0, String::new()))).collect(),
+        (Layout::List(id), Value::Array(items)) => items
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let key = v.get(id).map(key_string).unwrap_or_else(|| format!("#{}", i));
+                (key, (i as i64, v.to_string()))
+            })
+            .collect(),
+        (Layout::Log, Value::Array(items)) => log_rows(
+            items.into_iter().map(|v| v.to_string()).collect(),
+            previous,
+            seqs.unwrap_or(&VecDeque::new()),
+        ),
+        _ => Rows::new(),
+    }
+}
+
+/// How many of a log's last written entries it still starts with, found by looking back from
+/// its end for the last entry written. The entries looked at on the way, which are new, are
+/// returned serialized and in order. Nothing is kept when the last entry written is gone.
+fn log_kept(
+    seqs: &VecDeque<i64>,
+    previous: &Rows,
+    count: usize,
+    entry: impl Fn(usize) -> Result<String, String>,
+) -> Result<(usize, Vec<String>), String> {
+    let last = seqs.back().and_then(|seq| previous.get(&seq.to_string())).map(|(_, v)| v);
+    let mut new = Vec::new();
+    for index in (0..count).rev() {
+        let value = entry(index)?;
+        if last == Some(&value) {
+            new.reverse();
+            return Ok(((index + 1).min(seqs.len()), new));
+        }
+        new.push(value);
+    }
+    new.reverse();
+    Ok((0, new))
+}
+
+/// Rows for an append-mostly collection. Entries still present keep their sequence number, so
+/// appending and trimming the oldest entries only touch the rows involved.
+fn log_rows(entries: Vec<String>, previous: &Rows, seqs: &VecDeque<i64>) -> Rows {
+    let count = entries.len();
+    let (kept, _) = log_kept(seqs, previous, count, |i| Ok(entries[i].clone())).unwrap_or((0, Vec::new()));
+    let first_new = seqs.back().map_or(0, |seq| seq + 1);
+
+    entries
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let seq = if i < kept { seqs[seqs.len() - kept + i] } else { first_new + (i - kept) as i64 };
+            (seq.to_string(), (seq, value))
+        })
+        .collect()
+}
+
+/// The rows a log table lost from its front and gained at its end in a save
+struct LogChange {
+    dropped: Vec<i64>,
+    added: Vec<(i64, String)>,
+}
+
+/// Bring a log table written apart from the config up to date: drop the rows trimmed from the
+/// front and add the new entries, without looking at the rest
+fn write_log(
+    tx: &rusqlite::Transaction,
+    table: &str,
+    previous: &Rows,
+    seqs: &VecDeque<i64>,
+    entries: &dyn LogEntries,
+) -> Result<LogChange, String> {
+    let (kept, new) = log_kept(seqs, previous, entries.count(), |i| entries.entry_json(i))?;
+    let dropped: Vec<i64> = seqs.iter().take(seqs.len() - kept).copied().collect();
+    let first_new = seqs.back().map_or(0, |seq| seq + 1);
+    let added: Vec<(i64, String)> = new.into_iter().enumerate().map(|(i, v)| (first_new + i as i64, v)).collect();
+
+    let mut delete = tx.prepare_cached(&format!("DELETE FROM {} WHERE key = ?1", table)).map_err(db_err)?;
+    for seq in &dropped {
+        delete.execute(params![seq.to_string()]).map_err(db_err)?;
+    }
+    let mut insert = tx
+        .prepare_cached(&format!("INSERT OR REPLACE INTO {} (key, position, value) VALUES (?1, ?2, ?3)", table))
+        .map_err(db_err)?;
+    for (seq, value) in &added {
+        insert.execute(params![seq.to_string(), seq, value]).map_err(db_err)?;
+    }
+    Ok(LogChange { dropped, added })
+}
+
+/// Write the rows of `rows` that differ from `previous` and drop the ones that are gone
+fn write_rows(tx: &rusqlite::Transaction, table: &str, previous: &Rows, rows: &Rows) -> Result<(), String> {
+    let mut upsert = tx
+        .prepare_cached(&format!(
+            "INSERT INTO {} (key, position, value) VALUES (?1, ?2, ?3) \
+             ON CONFLICT(key) DO UPDATE SET position = excluded.position, value = excluded.value",
+            table
+        ))
+        .map_err(db_err)?;
+    for (key, row) in rows {
+        if previous.get(key) != Some(row) {
+            upsert.execute(params![key, row.0, row.1]).map_err(db_err)?;
+        }
+    }
+    let mut delete = tx.prepare_cached(&format!("DELETE FROM {} WHERE key = ?1", table)).map_err(db_err)?;
+    for key in previous.keys().filter(|k| !rows.contains_key(*k)) {
+        delete.execute(params![key]).map_err(db_err)?;
+    }
+    Ok(())
+}
+
+/// Persist a serialized config. The log tables in `logs` are taken from there rather than
+/// from `config`.
+pub fn save(path: &Path, config: Value, logs: &[(&'static str, &dyn LogEntries)]) -> Result<(), String> {
+    let Value::Object(mut fields) = config else {
+        return Err("Config did not serialize to an object".to_string());
+    };
+    with_store(path, |store| {
+        let mut tables: Vec<(&'static str, Rows)> = Vec::with_capacity(TABLES.len() + 1);
+        for (field, layout) in TABLES {
+            let value = fields.remove(field).unwrap_or(Value::Null);
+            if logs.iter().any(|(name, _)| *name == field) {
+                continue;
+            }
+            tables.push((field, to_rows(value, layout, &store.written[field], store.cursors.get(field))));
+        }
+        let settings: Rows = fields.into_iter().map(|(k, v)| (k, (0, v.to_string()))).collect();
+        tables.push((SETTINGS_TABLE, settings));
+
+        let tx = store.conn.transaction().map_err(db_err)?;
+        for (table, rows) in &tables {
+            write_rows(&tx, table, &store.written[*table], rows)?;
+        }
+        let mut log_changes = Vec::with_capacity(logs.len());
+        for (table, entries) in logs {
+            let change = write_log(&tx, table, &store.written[*table], &store.cursors[*table], *entries)?;
+            log_changes.push((*table, change));
+        }
+        tx.commit().map_err(db_err)?;
+
+        for (table, rows) in tables {
+            if let Some(seqs) = store.cursors.get_mut(table) {
+                let mut sorted: Vec<i64> = rows.values().map(|(p, _)| *p).collect();
+                sorted.sort();
+                *seqs = sorted.into();
+            }
+            store.written.insert(table, rows);
+        }
+        for (table, LogChange { dropped, added }) in log_changes {
+            let (Some(rows), Some(seqs)) = (store.written.get_mut(table), store.cursors.get_mut(table)) else {
+                continue;
+            };
+            for seq in dropped {
+                rows.remove(&seq.to_string());
+                seqs.pop_front();
+            }
+            for (seq, value) in added {
+                rows.insert(seq.to_string(), (seq, value));
+                seqs.push_back(seq);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Add samples to the metrics history and drop the ones taken before `keep_since`
+pub fn record_samples(path: &Path, samples: &[Sample], keep_since: i64) -> Result<(), String> {
+    with_store(path, |store| {
+        let tx = store.conn.transaction().map_err(db_err)?;
+        {
+            let mut insert = tx
+                .prepare_cached(
+                    "INSERT OR REPLACE INTO metrics (time, nwid, members, authorized, online) VALUES (?1, ?2, ?3, ?4, ?5)",
+                )
+                .map_err(db_err)?;
+            for s in samples {
+                insert
+                    .execute(params![s.time, s.nwid, s.members, s.authorized, s.online])
+                    .map_err(db_err)?;
+            }
+            tx.execute("DELETE FROM metrics WHERE time < ?1", params![keep_since])
+                .map_err(db_err)?;
+        }
+        tx.commit().map_err(db_err)
+    })
+}
+
+/// Samples taken at or after `since`, oldest first
+pub fn samples(path: &Path, since: i64) -> Result<Vec<Sample>, String> {
+    with_store(path, |store| {
+        let mut stmt = store
+            .conn
+            .prepare_cached(
+                "SELECT time, nwid, members, authorized, online FROM metrics WHERE time >= ?1 ORDER BY time, nwid",
+            )
+            .map_err(db_err)?;
+        let rows = stmt
+            .query_map(params![since], |row| {
+                Ok(Sample {
+                    time: row.get(0)?,
+                    nwid: row.get(1)?,
+                    members: row.get(2)?,
+                    authorized: row.get(3)?,
+                    online: row.get(4)?,
+                })
+            })
+            .map_err(db_err)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_err)?;
+        Ok(rows)
+    })
+}
+
+/// Add events to the member history and drop the ones from before `keep_since`
+pub fn record_history(path: &Path, events: &[HistoryEvent], keep_since: i64) -> Result<(), String> {
+    with_store(path, |store| {
+        let tx = store.conn.transaction().map_err(db_err)?;
+        {
+            let mut insert = tx
+                .prepare_cached(
+                    "INSERT INTO member_events (time, kind, nwid, member_id, detail) VALUES (?1, ?2, ?3, ?4, ?5)",
+                )
+                .map_err(db_err)?;
+            for e in events {
+                insert
+                    .execute(params![e.time, e.kind.as_str(), e.nwid, e.member_id, e.detail])
+                    .map_err(db_err)?;
+            }
+            tx.execute("DELETE FROM member_events WHERE time < ?1", params![keep_since])
+                .map_err(db_err)?;
+        }
+        tx.commit().map_err(db_err)
+    })
+}
+
+/// Up to `limit` events of a network, or of one of its members, with IDs below `before`,
+/// newest first
+pub fn history(
+    path: &Path,
+    nwid: &str,
+    member_id: Option<&str>,
+    before: Option<i64>,
+    limit: usize,
+) -> Result<Vec<HistoryEvent>, String> {
+    with_store(path, |store| {
+        let mut stmt = store
+            .conn
+            .prepare_cached(
+                "SELECT id, time, kind, nwid, member_id, detail FROM member_events \
+                 WHERE nwid = ?1 AND (?2 IS NULL OR member_id = ?2) AND (?3 IS NULL OR id < ?3) \
+                 ORDER BY id DESC LIMIT ?4",
+            )
+            .map_err(db_err)?;
+        let rows = stmt
+            .query_map(params![nwid, member_id, before, limit as i64], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                ))
+            })
+            .map_err(db_err)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_err)?;
+        // Kinds a newer version added are skipped rather than failing the page
+        Ok(rows
+            .into_iter()
+            .filter_map(|(id, time, kind, nwid, member_id, detail)| {
+                Some(HistoryEvent { id, time, kind: HistoryKind::parse(&kind)?, nwid, member_id, detail })
+            })
+            .collect())
+    })
+}
@@ -0,0 +1,113 @@
+//! Running as a systemd service: readiness and watchdog messages for `Type=notify` units, and
+//! listening sockets passed by a `.socket` unit. Everything here does nothing when TierDrop isn't
+//! started by systemd, and needs no library: the protocol is a few environment variables and a
+//! datagram socket.
+
+use std::time::Duration;
+
+use crate::bind::Listener;
+use crate::state::AppState;
+
+/// Send a state such as `READY=1` to systemd; a no-op outside a `Type=notify` service
+pub fn notify(message: &str) {
+    #[cfg(unix)]
+    if let Err(e) = send(message) {
+        tracing::warn!("Failed to notify systemd: {}", e);
+    }
+    #[cfg(not(unix))]
+    let _ = message;
+}
+
+#[cfg(unix)]
+fn send(message: &str) -> std::io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let socket = UnixDatagram::unbound()?;
+    let bytes = path.as_encoded_bytes();
+    // A leading '@' names a socket in Linux's abstract namespace
+    if let Some(name) = bytes.strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(message.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = name;
+    } else {
+        socket.send_to(message.as_bytes(), path)?;
+    }
+    Ok(())
+}
+
+/// How often systemd expects to hear from TierDrop, from `WatchdogSec=` in the unit
+pub fn watchdog_interval() -> Option<Duration> {
+    let for_us = std::env::var("WATCHDOG_PID").map_or(true, |pid| pid.parse() == Ok(std::process::id()));
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (for_us && usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Ping the watchdog at half its interval while the server is responsive. A ping is skipped when
+/// the configuration lock can't be taken in time, so a deadlock gets the service restarted.
+pub async fn watchdog(state: AppState, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval / 2);
+    loop {
+        ticker.tick().await;
+        match tokio::time::timeout(interval / 4, state.config.read()).await {
+            Ok(_) => notify("WATCHDOG=1"),
+            Err(_) => tracing::warn!("Configuration lock held too long; skipping the systemd watchdog ping"),
+        }
+    }
+}
+
+/// Sockets passed by systemd socket activation, which replace `TIERDROP_BIND`; `None` when
+/// TierDrop wasn't socket-activated
+pub fn listeners() -> Result<Option<Vec<Listener>>, String> {
+    let for_us = std::env::var("LISTEN_PID").is_ok_and(|pid| pid.parse() == Ok(std::process::id()));
+    let count = match std::env::var("LISTEN_FDS") {
+        Ok(count) if for_us => count.parse::<i32>().map_err(|_| format!("Invalid LISTEN_FDS from systemd: {}", count))?,
+        _ => return Ok(None),
+    };
+    // Child processes must not take the sockets for theirs
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+    if count <= 0 {
+        return Ok(None);
+    }
+
+    #[cfg(unix)]
+    {
+        const SD_LISTEN_FDS_START: i32 = 3;
+        (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count).map(adopt).collect::<Result<_, _>>().map(Some)
+    }
+    #[cfg(not(unix))]
+    Err("Socket activation is only supported on Unix".to_string())
+}
+
+/// Take over a listening socket systemd passed as file descriptor `fd`
+#[cfg(unix)]
+fn adopt(fd: i32) -> Result<Listener, String> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    // SAFETY: with LISTEN_PID naming this process, systemd guarantees descriptors 3 up to
+    // 3 + LISTEN_FDS are open sockets, and nothing else in TierDrop uses them
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let tcp = std::net::TcpListener::from(fd);
+    // Only a TCP socket has an address std understands; anything else is a Unix socket
+    if tcp.local_addr().is_ok() {
+        tcp.set_nonblocking(true).map_err(|e| e.to_string())?;
+        return tokio::net::TcpListener::from_std(tcp)
+            .map(Listener::Tcp)
+            .map_err(|e| format!("Unusable socket from systemd: {}", e));
+    }
+    let unix = std::os::unix::net::UnixListener::from(OwnedFd::from(tcp));
+    unix.set_nonblocking(true).map_err(|e| e.to_string())?;
+    // systemd owns the socket file, so it stays in place at shutdown
+    tokio::net::UnixListener::from_std(unix)
+        .map(|listener| Listener::Unix(listener, None))
+        .map_err(|e| format!("Unusable socket from systemd: {}", e))
+}
@@ -0,0 +1,86 @@
+//! Per-client throttling of sign-in and password reset attempts. Clients are told apart by the
+//! address `trusted_proxies::client_ip` gives, so behind a trusted proxy each real client has
+//! its own allowance rather than all of them sharing the proxy's.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// How far back attempts are counted
+const WINDOW: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// Failed password or verification code sign-ins
+    Login,
+    /// "Forgot password" requests
+    PasswordReset,
+}
+
+impl Action {
+    /// Attempts allowed per client within `WINDOW`
+    fn limit(self) -> usize {
+        match self {
+            Action::Login => 10,
+            Action::PasswordReset => 5,
+        }
+    }
+}
+
+/// When each client made its recent attempts, per action
+type Attempts = HashMap<(Action, String), Vec<Instant>>;
+
+static ATTEMPTS: OnceLock<Mutex<Attempts>> = OnceLock::new();
+
+fn attempts() -> &'static Mutex<Attempts> {
+    ATTEMPTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Whether the client may try again, or how long until it may. Clients with no known address
+/// aren't throttled, as they would all share one allowance.
+pub fn check(action: Action, ip: Option<&str>) -> Result<(), Duration> {
+    let Some(ip) = ip else {
+        return Ok(());
+    };
+    let now = Instant::now();
+    let mut attempts = attempts().lock().unwrap();
+    let Some(times) = attempts.get_mut(&(action, ip.to_string())) else {
+        return Ok(());
+    };
+    times.retain(|t| now.duration_since(*t) < WINDOW);
+    if times.len() < action.limit() {
+        return Ok(());
+    }
+    let oldest = times.first().copied().unwrap_or(now);
+    Err(WINDOW.saturating_sub(now.duration_since(oldest)))
+}
+
+/// Count an attempt against the client
+pub fn record(action: Action, ip: Option<&str>) {
+    let Some(ip) = ip else {
+        return;
+    };
+    let now = Instant::now();
+    let mut attempts = attempts().lock().unwrap();
+    // Forget clients that have gone quiet, so the map doesn't grow without bound
+    attempts.retain(|_, times| times.last().is_some_and(|t| now.duration_since(*t) < WINDOW));
+    attempts.entry((action, ip.to_string())).or_default().push(now);
+}
+
+/// Forget the client's attempts, after it signed in
+pub fn clear(action: Action, ip: Option<&str>) {
+    if let Some(ip) = ip {
+        let mut attempts = attempts().lock().unwrap();
+        attempts.remove(&(action, ip.to_string()));
+    }
+}
+
+/// "Try again in N minutes" for a wait from `check`
+pub fn retry_message(wait: Duration) -> String {
+    let minutes = wait.as_secs().div_ceil(60).max(1);
+    format!(
+        "Too many attempts from your address. Try again in {} minute{}.",
+        minutes,
+        if minutes == 1 { "" } else { "s" }
+    )
+}
@@ -0,0 +1,184 @@
+//! HTTPS without a reverse proxy, read from the environment at startup:
+//!
+//! - `TIERDROP_TLS_CERT` and `TIERDROP_TLS_KEY`: PEM certificate chain and private key,
+//!   re-read on SIGHUP
+//! - `TIERDROP_ACME_DOMAINS`: comma-separated names to get a certificate for from
+//!   Let's Encrypt, with `TIERDROP_ACME_EMAIL` and `TIERDROP_ACME_DIRECTORY` optional
+//!
+//! Both kinds of certificate are served through `CertResolver`, so a renewed or reloaded
+//! certificate takes effect for new connections without a restart.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use axum_server::tls_rustls::RustlsConfig;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+
+use crate::acme;
+use crate::passkeys::der_read;
+
+/// ALPN protocol of TLS-ALPN-01 validation handshakes (RFC 8737)
+pub const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+pub enum TlsSettings {
+    Files { cert: PathBuf, key: PathBuf },
+    Acme(acme::AcmeSettings),
+}
+
+impl TlsSettings {
+    /// `None` when TLS isn't configured and TierDrop serves plain HTTP
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let var = |name: &str| std::env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        let files = match (var("TIERDROP_TLS_CERT"), var("TIERDROP_TLS_KEY")) {
+            (Some(cert), Some(key)) => Some(TlsSettings::Files { cert: cert.into(), key: key.into() }),
+            (None, None) => None,
+            _ => return Err("Set both TIERDROP_TLS_CERT and TIERDROP_TLS_KEY".to_string()),
+        };
+        let acme = acme::AcmeSettings::from_env()?.map(TlsSettings::Acme);
+        match (files, acme) {
+            (Some(_), Some(_)) => Err("Use either TIERDROP_TLS_CERT/TIERDROP_TLS_KEY or TIERDROP_ACME_DOMAINS, not both".to_string()),
+            (files, acme) => Ok(files.or(acme)),
+        }
+    }
+}
+
+/// Picks the certificate for each handshake: the current one, or a TLS-ALPN-01 challenge
+/// certificate while an ACME order is being validated
+#[derive(Debug, Default)]
+pub struct CertResolver {
+    current: RwLock<Option<Arc<CertifiedKey>>>,
+    challenges: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl CertResolver {
+    pub fn set(&self, key: CertifiedKey) {
+        *self.current.write().unwrap() = Some(Arc::new(key));
+    }
+
+    pub fn add_challenge(&self, domain: &str, key: CertifiedKey) {
+        self.challenges.write().unwrap().insert(domain.to_ascii_lowercase(), Arc::new(key));
+    }
+
+    pub fn clear_challenges(&self) {
+        self.challenges.write().unwrap().clear();
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let is_challenge = client_hello.alpn().is_some_and(|mut protocols| protocols.any(|p| p == ACME_TLS_ALPN));
+        if is_challenge {
+            let domain = client_hello.server_name()?.to_ascii_lowercase();
+            return self.challenges.read().unwrap().get(&domain).cloned();
+        }
+        self.current.read().unwrap().clone()
+    }
+}
+
+/// Running TLS setup, kept so SIGHUP can re-read certificate files
+pub struct Tls {
+    settings: TlsSettings,
+    resolver: Arc<CertResolver>,
+}
+
+impl Tls {
+    /// Load the certificate (or a cached ACME one) and, for ACME, start obtaining and renewing it
+    pub fn start(settings: TlsSettings) -> Result<(Self, RustlsConfig), String> {
+        let resolver = Arc::new(CertResolver::default());
+        match &settings {
+            TlsSettings::Files { cert, key } => {
+                let (certified, not_after) = load_files(cert, key)?;
+                log_expiry(&cert.display().to_string(), not_after);
+                resolver.set(certified);
+            }
+            TlsSettings::Acme(acme_settings) => {
+                if let Some((certified, not_after)) = acme::cached_certificate(acme_settings) {
+                    log_expiry(&acme_settings.domains.join(", "), not_after);
+                    resolver.set(certified);
+                }
+                tokio::spawn(acme::maintain(acme_settings.clone(), resolver.clone()));
+            }
+        }
+
+        let mut config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), ACME_TLS_ALPN.to_vec()];
+
+        Ok((Self { settings, resolver }, RustlsConfig::from_config(Arc::new(config))))
+    }
+
+    /// Re-read certificate files, e.g. after a renewal by certbot. ACME certificates renew themselves.
+    pub fn reload(&self) -> Result<(), String> {
+        if let TlsSettings::Files { cert, key } = &self.settings {
+            let (certified, not_after) = load_files(cert, key)?;
+            log_expiry(&cert.display().to_string(), not_after);
+            self.resolver.set(certified);
+        }
+        Ok(())
+    }
+}
+
+fn log_expiry(name: &str, not_after: Option<DateTime<Utc>>) {
+    match not_after {
+        Some(t) => tracing::info!("TLS certificate for {} valid until {}", name, t.format("%Y-%m-%d %H:%M UTC")),
+        None => tracing::info!("TLS certificate loaded from {}", name),
+    }
+}
+
+pub fn load_files(cert: &Path, key: &Path) -> Result<(CertifiedKey, Option<DateTime<Utc>>), String> {
+    let cert_pem = std::fs::read(cert).map_err(|e| format!("Failed to read {}: {}", cert.display(), e))?;
+    let key_pem = std::fs::read(key).map_err(|e| format!("Failed to read {}: {}", key.display(), e))?;
+    load_pem(&cert_pem, &key_pem)
+}
+
+/// A certificate chain and key in PEM form, with the certificate's expiry when readable
+pub fn load_pem(cert_pem: &[u8], key_pem: &[u8]) -> Result<(CertifiedKey, Option<DateTime<Utc>>), String> {
+    let chain = CertificateDer::pem_slice_iter(cert_pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid certificate: {}", e))?;
+    let first = chain.first().ok_or("No certificate found in PEM file")?;
+    let not_after = not_after(first);
+    let certified = certified_key(chain, key_pem)?;
+    certified.keys_match().map_err(|e| match e {
+        rustls::Error::InconsistentKeys(_) => "The private key does not belong to the certificate".to_string(),
+        e => format!("Invalid certificate: {}", e),
+    })?;
+    Ok((certified, not_after))
+}
+
+/// Pair a chain with its key, without the checks `load_pem` makes
+pub fn certified_key(chain: Vec<CertificateDer<'static>>, key_pem: &[u8]) -> Result<CertifiedKey, String> {
+    let key = PrivateKeyDer::from_pem_slice(key_pem).map_err(|e| format!("Invalid private key: {}", e))?;
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .map_err(|e| format!("Unsupported private key: {}", e))?;
+    Ok(CertifiedKey::new(chain, signing_key))
+}
+
+/// `notAfter` of an X.509 certificate
+pub fn not_after(cert: &[u8]) -> Option<DateTime<Utc>> {
+    let (_, certificate, _) = der_read(cert)?;
+    let (_, tbs, _) = der_read(certificate)?;
+    let (tag, _, mut rest) = der_read(tbs)?;
+    // Skip the optional [0] version, then serial number, signature algorithm and issuer
+    let skip = if tag == 0xa0 { 3 } else { 2 };
+    for _ in 0..skip {
+        rest = der_read(rest)?.2;
+    }
+    let (_, validity, _) = der_read(rest)?;
+    let (_, _not_before, rest) = der_read(validity)?;
+    let (tag, time, _) = der_read(rest)?;
+    let time = std::str::from_utf8(time).ok()?;
+    let parsed = match tag {
+        0x17 => NaiveDateTime::parse_from_str(time, "%y%m%d%H%M%SZ").ok()?,
+        0x18 => NaiveDateTime::parse_from_str(time, "%Y%m%d%H%M%SZ").ok()?,
+        _ => return None,
+    };
+    Some(parsed.and_utc())
+}
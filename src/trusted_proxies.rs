@@ -0,0 +1,146 @@
+//! Which reverse proxies may report the client's address, set with `TIERDROP_TRUSTED_PROXIES`:
+//! comma-separated addresses and CIDR ranges, plus `loopback` and `private` for the usual ones,
+//! or `none`. The default is `loopback`, for a proxy on the same host.
+//!
+//! `X-Forwarded-For` and `X-Real-IP` are only read when the connection comes from a trusted
+//! proxy, so a client talking to TierDrop directly can't put another address in the sign-in
+//! history or access log. Connections over a Unix socket have no address and count as trusted,
+//! since only processes on the same host can open one.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::OnceLock;
+
+use axum::http::HeaderMap;
+
+static TRUSTED: OnceLock<Vec<Cidr>> = OnceLock::new();
+
+/// An address range such as `10.0.0.0/8`; a bare address is a range of one
+#[derive(Clone, Copy, Debug)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.trim_start_matches('[').trim_end_matches(']').parse().ok()?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse().ok().filter(|p| *p <= max)?,
+            None => max,
+        };
+        Some(Self { addr, prefix })
+    }
+
+    fn v4(a: u8, b: u8, c: u8, d: u8, prefix: u8) -> Self {
+        Self { addr: IpAddr::V4(Ipv4Addr::new(a, b, c, d)), prefix }
+    }
+
+    fn v6(addr: Ipv6Addr, prefix: u8) -> Self {
+        Self { addr: IpAddr::V6(addr), prefix }
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                masked(u32::from(net).into(), self.prefix, 32) == masked(u32::from(ip).into(), self.prefix, 32)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                masked(u128::from(net), self.prefix, 128) == masked(u128::from(ip), self.prefix, 128)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The top `prefix` bits of a `bits`-wide address
+fn masked(addr: u128, prefix: u8, bits: u8) -> u128 {
+    if prefix == 0 {
+        0
+    } else {
+        addr >> (bits - prefix)
+    }
+}
+
+/// `TIERDROP_TRUSTED_PROXIES` as a list of ranges; empty trusts no proxy
+pub fn from_env() -> Result<Vec<Cidr>, String> {
+    let raw = std::env::var("TIERDROP_TRUSTED_PROXIES").unwrap_or_default();
+    let raw = if raw.trim().is_empty() { "loopback" } else { raw.as_str() };
+
+    let mut ranges = Vec::new();
+    for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        match entry.to_ascii_lowercase().as_str() {
+            "none" => {}
+            "loopback" => {
+                ranges.push(Cidr::v4(127, 0, 0, 0, 8));
+                ranges.push(Cidr::v6(Ipv6Addr::LOCALHOST, 128));
+            }
+            "private" => {
+                ranges.push(Cidr::v4(10, 0, 0, 0, 8));
+                ranges.push(Cidr::v4(172, 16, 0, 0, 12));
+                ranges.push(Cidr::v4(192, 168, 0, 0, 16));
+                ranges.push(Cidr::v6(Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0), 7));
+            }
+            _ => ranges.push(Cidr::parse(entry).ok_or_else(|| {
+                format!(
+                    "TIERDROP_TRUSTED_PROXIES entries must be addresses, CIDR ranges, loopback, private or none, not {:?}",
+                    entry
+                )
+            })?),
+        }
+    }
+    Ok(ranges)
+}
+
+/// Set once at startup, before the router is built
+pub fn set(ranges: Vec<Cidr>) {
+    let _ = TRUSTED.set(ranges);
+}
+
+fn is_trusted(ip: IpAddr) -> bool {
+    TRUSTED.get().is_some_and(|ranges| ranges.iter().any(|r| r.contains(ip)))
+}
+
+/// Whether the connection comes from a trusted proxy, whose forwarding headers may be believed.
+/// Unix socket connections have no peer address and count as trusted.
+pub fn peer_is_trusted(peer: Option<SocketAddr>) -> bool {
+    peer.is_none_or(|p| is_trusted(p.ip().to_canonical()))
+}
+
+/// An address in a forwarding header, which may carry a port
+fn parse_hop(s: &str) -> Option<IpAddr> {
+    let s = s.trim();
+    s.parse::<IpAddr>().ok().or_else(|| s.parse::<SocketAddr>().ok().map(|a| a.ip()))
+}
+
+/// The client's address: the connection's peer, or when that is a trusted proxy, the nearest
+/// address it forwarded for that isn't one of our proxies
+pub fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<IpAddr> {
+    if !peer_is_trusted(peer) {
+        return peer.map(|p| p.ip().to_canonical());
+    }
+    let peer = peer.map(|p| p.ip().to_canonical());
+
+    let header = |name: &str| headers.get_all(name).iter().filter_map(|v| v.to_str().ok()).collect::<Vec<_>>().join(",");
+    let forwarded_for = header("x-forwarded-for");
+    let forwarded_for = if forwarded_for.trim().is_empty() { header("x-real-ip") } else { forwarded_for };
+
+    // Each proxy appends the address it saw, so read from the right and stop at the first
+    // hop we don't trust; anything further left was written by the client
+    let mut client = peer;
+    for hop in forwarded_for.rsplit(',').filter(|h| !h.trim().is_empty()) {
+        let Some(ip) = parse_hop(hop) else {
+            break;
+        };
+        let ip = ip.to_canonical();
+        client = Some(ip);
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+    client
+}
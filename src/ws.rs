@@ -0,0 +1,61 @@
+//! `/ws`: the event stream of `/events` over a WebSocket, for networks whose proxies buffer or cut
+//! off Server-Sent Events. Each event is a text message `{"event": <name>, "data": <payload or
+//! null>}`; a text message `ping` is answered with `pong` so clients can keep the connection alive.
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::response::Response;
+use axum::Extension;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::sse::EventsQuery;
+use crate::state::{AppState, User};
+
+pub async fn ws_handler(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(query): Query<EventsQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    upgrade.on_upgrade(move |socket| run(socket, state, user, query.network))
+}
+
+async fn run(mut socket: WebSocket, state: AppState, user: User, network: Option<String>) {
+    let mut rx = state.tx.subscribe();
+    let _viewer = state.viewers.join(network.is_some());
+    loop {
+        tokio::select! {
+            received = rx.recv() => match received {
+                Ok(event) => {
+                    let Some(event) = event.for_viewer(&user, network.as_deref()) else {
+                        continue;
+                    };
+                    let message = serde_json::json!({
+                        "event": event.event_name(),
+                        "data": event.payload(),
+                    });
+                    if socket.send(Message::text(message.to_string())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(_)) => {} // The next poll cycle will catch up
+                Err(RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) if text.trim() == "ping" => {
+                    if socket.send(Message::text("pong")).await.is_err() {
+                        break;
+                    }
+                }
+                // Protocol pings are answered by the WebSocket library
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            // Close on shutdown, or the open connection would hold up graceful shutdown
+            _ = state.shutdown.cancelled() => {
+                let _ = socket.send(Message::Close(None)).await;
+                break;
+            }
+        }
+    }
+}
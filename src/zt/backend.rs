@@ -0,0 +1,69 @@
+//! The ZeroTier controller as the rest of TierDrop sees it. Handlers, the poller and background
+//! jobs only use `ZtBackend`, so the service API client can be swapped for another
+//! implementation, such as a mock or a different controller API, without touching them.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::client::ZtClient;
+use super::models::{ControllerMember, ControllerNetwork, NodeStatus, Peer};
+
+/// The backend held in `AppState`
+pub type SharedBackend = Arc<dyn ZtBackend>;
+
+#[async_trait]
+pub trait ZtBackend: Send + Sync {
+    async fn get_status(&self) -> Result<NodeStatus, String>;
+
+    async fn get_peers(&self) -> Result<Vec<Peer>, String>;
+
+    // ---- Controller Network methods ----
+
+    async fn get_controller_networks(&self) -> Result<Vec<String>, String>;
+
+    async fn get_controller_network(&self, nwid: &str) -> Result<ControllerNetwork, String> {
+        self.get_controller_network_json(nwid)
+            .await
+            .and_then(|v| serde_json::from_value(v).map_err(|e| format!("Failed to parse controller network: {}", e)))
+    }
+
+    /// The network exactly as the controller stores it, including fields TierDrop doesn't model
+    async fn get_controller_network_json(&self, nwid: &str) -> Result<serde_json::Value, String>;
+
+    /// Create a network with a random ID on the controller with address `node_id`
+    async fn create_controller_network(&self, node_id: &str) -> Result<ControllerNetwork, String>;
+
+    async fn update_controller_network(&self, nwid: &str, body: serde_json::Value) -> Result<ControllerNetwork, String>;
+
+    async fn delete_controller_network(&self, nwid: &str) -> Result<(), String>;
+
+    // ---- Controller Member methods ----
+
+    /// Member addresses with their revision numbers
+    async fn get_controller_members(&self, nwid: &str) -> Result<HashMap<String, i64>, String>;
+
+    async fn get_controller_member(&self, nwid: &str, member_id: &str) -> Result<ControllerMember, String> {
+        self.get_controller_member_json(nwid, member_id)
+            .await
+            .and_then(|v| serde_json::from_value(v).map_err(|e| format!("Failed to parse member: {}", e)))
+    }
+
+    /// The member exactly as the controller stores it
+    async fn get_controller_member_json(&self, nwid: &str, member_id: &str) -> Result<serde_json::Value, String>;
+
+    async fn update_controller_member(
+        &self,
+        nwid: &str,
+        member_id: &str,
+        body: serde_json::Value,
+    ) -> Result<ControllerMember, String>;
+
+    async fn delete_controller_member(&self, nwid: &str, member_id: &str) -> Result<(), String>;
+}
+
+/// The backend for a ZeroTier service at `base_url`, or the simulated controller in demo mode
+pub fn connect(base_url: String, auth_token: String) -> SharedBackend {
+    Arc::new(ZtClient::new(base_url, auth_token))
+}
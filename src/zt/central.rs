@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+
+/// ZeroTier Central's API; `ZT_CENTRAL_URL` overrides it
+const CENTRAL_API_URL: &str = "https://api.zerotier.com/api/v1";
+
+/// Read-only client for networks hosted on ZeroTier Central
+pub struct CentralClient {
+    client: Client,
+    base_url: String,
+    token: String,
+}
+
+/// A hosted network as listed by Central
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CentralNetwork {
+    pub id: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub total_member_count: usize,
+    #[serde(default)]
+    pub config: CentralNetworkConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CentralNetworkConfig {
+    #[serde(default)]
+    pub name: String,
+}
+
+impl CentralClient {
+    pub fn new(token: String) -> Self {
+        Self {
+            client: Client::builder().timeout(Duration::from_secs(30)).build().unwrap_or_default(),
+            base_url: std::env::var("ZT_CENTRAL_URL").unwrap_or_else(|_| CENTRAL_API_URL.to_string()),
+            token,
+        }
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, String> {
+        let resp = self
+            .client
+            .get(format!("{}{}", self.base_url, path))
+            .header("Authorization", format!("token {}", self.token))
+            .send()
+            .await
+            .map_err(|e| format!("Failed to reach ZeroTier Central: {}", e))?;
+        match resp.status() {
+            s if s.is_success() => {}
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                return Err("ZeroTier Central rejected the API token".to_string())
+            }
+            StatusCode::NOT_FOUND => return Err("Network not found on ZeroTier Central".to_string()),
+            s => return Err(format!("ZeroTier Central returned {}", s)),
+        }
+        resp.json().await.map_err(|e| format!("Failed to parse ZeroTier Central response: {}", e))
+    }
+
+    pub async fn list_networks(&self) -> Result<Vec<CentralNetwork>, String> {
+        self.get("/network").await
+    }
+
+    /// The network with its controller settings under `config`
+    pub async fn get_network(&self, nwid: &str) -> Result<serde_json::Value, String> {
+        self.get(&format!("/network/{}", nwid)).await
+    }
+
+    /// Members with their controller settings under `config`
+    pub async fn get_members(&self, nwid: &str) -> Result<Vec<serde_json::Value>, String> {
+        self.get(&format!("/network/{}/member", nwid)).await
+    }
+}
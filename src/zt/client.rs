@@ -1,190 +1,322 @@
-use reqwest::Client;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 
-use super::models::{ControllerMember, ControllerNetwork, NodeStatus};
+use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use tokio::time::Instant;
+
+use reqwest::{Client, Method, StatusCode};
+use serde::de::DeserializeOwned;
+
+use super::backend::ZtBackend;
+use super::demo::{self, DemoController};
+use super::models::{ControllerMember, ControllerNetwork, NodeStatus, Peer};
+
+/// Requests a second each client sends ZeroTier when `TIERDROP_ZT_RATE_LIMIT` is unset
+pub const DEFAULT_RATE_LIMIT: u32 = 100;
+
+static RATE_LIMIT: OnceLock<u32> = OnceLock::new();
+
+/// `TIERDROP_ZT_RATE_LIMIT`: how many requests a second TierDrop sends the ZeroTier service, so
+/// busy pages and polls of large networks don't swamp it
+pub fn rate_limit_from_env() -> Result<u32, String> {
+    match std::env::var("TIERDROP_ZT_RATE_LIMIT") {
+        Ok(raw) if !raw.trim().is_empty() => match raw.trim().parse() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("TIERDROP_ZT_RATE_LIMIT must be a positive number, not {:?}", raw)),
+        },
+        _ => Ok(DEFAULT_RATE_LIMIT),
+    }
+}
+
+/// Set once at startup, before any client is made
+pub fn set_rate_limit(limit: u32) {
+    let _ = RATE_LIMIT.set(limit);
+}
+
+/// Token bucket: up to `rate` requests go straight out, after that they're spaced `1 / rate` apart
+struct RateLimiter {
+    rate: f64,
+    /// Tokens left, negative when requests are already waiting for ones to come, and when counted
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    fn new(rate: u32) -> Self {
+        let rate = f64::from(rate);
+        Self {
+            rate,
+            bucket: Mutex::new((rate, Instant::now())),
+        }
+    }
+
+    /// Wait for this request's turn
+    async fn acquire(&self) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let tokens = (bucket.0 + now.duration_since(bucket.1).as_secs_f64() * self.rate).min(self.rate);
+            *bucket = (tokens - 1.0, now);
+            (tokens < 1.0).then(|| Duration::from_secs_f64((1.0 - tokens) / self.rate))
+        };
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+type PendingReply = Shared<BoxFuture<'static, Result<Reply, String>>>;
 
 #[derive(Clone)]
 pub struct ZtClient {
-    client: Client,
-    base_url: String,
-    auth_token: String,
+    backend: Backend,
+    limiter: Arc<RateLimiter>,
+    /// GETs on their way, by path, so identical ones made meanwhile share the answer
+    in_flight: Arc<Mutex<HashMap<String, PendingReply>>>,
+}
+
+/// Where requests go: the ZeroTier service, or the simulated controller of demo mode
+#[derive(Clone)]
+enum Backend {
+    Http {
+        client: Client,
+        base_url: String,
+        auth_token: String,
+    },
+    Demo(Arc<DemoController>),
+}
+
+/// A response from the service API
+#[derive(Clone)]
+pub struct Reply {
+    pub status: StatusCode,
+    pub body: Result<serde_json::Value, String>,
 }
 
 impl ZtClient {
+    /// In demo mode every client talks to the same simulated controller instead
     pub fn new(base_url: String, auth_token: String) -> Self {
+        let backend = match demo::controller() {
+            Some(controller) => Backend::Demo(controller),
+            None => Backend::Http {
+                client: Client::new(),
+                base_url,
+                auth_token,
+            },
+        };
         Self {
-            client: Client::new(),
-            base_url,
-            auth_token,
+            backend,
+            limiter: Arc::new(RateLimiter::new(*RATE_LIMIT.get().unwrap_or(&DEFAULT_RATE_LIMIT))),
+            in_flight: Arc::default(),
         }
     }
 
-    fn request(&self, path: &str) -> reqwest::RequestBuilder {
-        self.client
-            .get(format!("{}{}", self.base_url, path))
-            .header("X-ZT1-Auth", &self.auth_token)
-    }
+    /// Send one request, or for a GET join an identical one already on its way. Errors only when
+    /// the service can't be reached.
+    async fn call(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> Result<Reply, String> {
+        if method != Method::GET {
+            let reply = self.send(method, path, body).await;
+            // GETs started before this change went through may answer with what it replaced
+            self.in_flight.lock().unwrap().clear();
+            return reply;
+        }
 
-    pub async fn get_status(&self) -> Result<NodeStatus, String> {
-        self.request("/status")
-            .send()
-            .await
-            .map_err(|e| format!("Failed to connect to ZeroTier: {}", e))?
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse status: {}", e))
+        let pending = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(path) {
+                Some(pending) => pending.clone(),
+                None => {
+                    let client = self.clone();
+                    let owned = path.to_string();
+                    let pending = async move { client.send(Method::GET, &owned, None).await }.boxed().shared();
+                    in_flight.insert(path.to_string(), pending.clone());
+                    pending
+                }
+            }
+        };
+        let reply = pending.clone().await;
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight.get(path).is_some_and(|p| p.ptr_eq(&pending)) {
+            in_flight.remove(path);
+        }
+        reply
     }
 
-    // ---- Controller Network methods ----
+    async fn send(&self, method: Method, path: &str, body: Option<serde_json::Value>) -> Result<Reply, String> {
+        match &self.backend {
+            Backend::Http { client, base_url, auth_token } => {
+                self.limiter.acquire().await;
+                let mut request = client
+                    .request(method, format!("{}{}", base_url, path))
+                    .header("X-ZT1-Auth", auth_token);
+                if let Some(body) = &body {
+                    request = request.json(body);
+                }
+                let resp = request.send().await.map_err(|e| e.to_string())?;
+                let status = resp.status();
+                let body = resp.json().await.map_err(|e| e.to_string());
+                Ok(Reply { status, body })
+            }
+            Backend::Demo(controller) => Ok(controller.handle(&method, path, body)),
+        }
+    }
 
-    pub async fn get_controller_networks(&self) -> Result<Vec<String>, String> {
-        self.request("/controller/network")
-            .send()
+    /// Send a request and parse the response, describing failures with `failed` and `unparsable`
+    async fn fetch<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+        failed: &str,
+        unparsable: &str,
+    ) -> Result<T, String> {
+        let reply = self
+            .call(method, path, body)
             .await
-            .map_err(|e| format!("Failed to fetch controller networks: {}", e))?
-            .json()
+            .map_err(|e| format!("{}: {}", failed, e))?;
+        reply
+            .body
+            .and_then(|v| serde_json::from_value(v).map_err(|e| e.to_string()))
+            .map_err(|e| format!("{}: {}", unparsable, e))
+    }
+
+    async fn delete(&self, path: &str, failed: &str, what: &str) -> Result<(), String> {
+        let reply = self
+            .call(Method::DELETE, path, None)
             .await
-            .map_err(|e| format!("Failed to parse controller networks: {}", e))
+            .map_err(|e| format!("{}: {}", failed, e))?;
+        if reply.status.is_success() {
+            Ok(())
+        } else {
+            Err(format!("{} failed with status: {}", what, reply.status))
+        }
     }
+}
 
-    pub async fn get_controller_network(&self, nwid: &str) -> Result<ControllerNetwork, String> {
-        self.request(&format!("/controller/network/{}", nwid))
-            .send()
+#[async_trait]
+impl ZtBackend for ZtClient {
+    async fn get_status(&self) -> Result<NodeStatus, String> {
+        self.fetch(Method::GET, "/status", None, "Failed to connect to ZeroTier", "Failed to parse status")
             .await
-            .map_err(|e| format!("Failed to fetch controller network: {}", e))?
-            .json()
+    }
+
+    async fn get_peers(&self) -> Result<Vec<Peer>, String> {
+        self.fetch(Method::GET, "/peer", None, "Failed to connect to ZeroTier", "Failed to parse peers")
             .await
-            .map_err(|e| format!("Failed to parse controller network: {}", e))
     }
 
-    pub async fn create_controller_network(
+    async fn get_controller_networks(&self) -> Result<Vec<String>, String> {
+        self.fetch(
+            Method::GET,
+            "/controller/network",
+            None,
+            "Failed to fetch controller networks",
+            "Failed to parse controller networks",
+        )
+        .await
+    }
+
+    async fn get_controller_network_json(&self, nwid: &str) -> Result<serde_json::Value, String> {
+        self.fetch(
+            Method::GET,
+            &format!("/controller/network/{}", nwid),
+            None,
+            "Failed to fetch controller network",
+            "Failed to parse controller network",
+        )
+        .await
+    }
+
+    async fn create_controller_network(
         &self,
         node_id: &str,
     ) -> Result<ControllerNetwork, String> {
-        self.client
-            .post(format!(
-                "{}/controller/network/{}______",
-                self.base_url, node_id
-            ))
-            .header("X-ZT1-Auth", &self.auth_token)
-            .json(&serde_json::json!({}))
-            .send()
-            .await
-            .map_err(|e| format!("Failed to create network: {}", e))?
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse create response: {}", e))
+        self.fetch(
+            Method::POST,
+            &format!("/controller/network/{}______", node_id),
+            Some(serde_json::json!({})),
+            "Failed to create network",
+            "Failed to parse create response",
+        )
+        .await
     }
 
-    pub async fn update_controller_network(
+    async fn update_controller_network(
         &self,
         nwid: &str,
         body: serde_json::Value,
     ) -> Result<ControllerNetwork, String> {
-        self.client
-            .post(format!("{}/controller/network/{}", self.base_url, nwid))
-            .header("X-ZT1-Auth", &self.auth_token)
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to update network: {}", e))?
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse update response: {}", e))
+        self.fetch(
+            Method::POST,
+            &format!("/controller/network/{}", nwid),
+            Some(body),
+            "Failed to update network",
+            "Failed to parse update response",
+        )
+        .await
     }
 
-    pub async fn delete_controller_network(&self, nwid: &str) -> Result<(), String> {
-        let resp = self
-            .client
-            .delete(format!("{}/controller/network/{}", self.base_url, nwid))
-            .header("X-ZT1-Auth", &self.auth_token)
-            .send()
+    async fn delete_controller_network(&self, nwid: &str) -> Result<(), String> {
+        self.delete(&format!("/controller/network/{}", nwid), "Failed to delete network", "Delete network")
             .await
-            .map_err(|e| format!("Failed to delete network: {}", e))?;
-        if resp.status().is_success() {
-            Ok(())
-        } else {
-            Err(format!(
-                "Delete network failed with status: {}",
-                resp.status()
-            ))
-        }
     }
 
-    // ---- Controller Member methods ----
-
-    pub async fn get_controller_members(
+    async fn get_controller_members(
         &self,
         nwid: &str,
-    ) -> Result<std::collections::HashMap<String, i64>, String> {
-        self.request(&format!("/controller/network/{}/member", nwid))
-            .send()
-            .await
-            .map_err(|e| format!("Failed to fetch members: {}", e))?
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse members: {}", e))
+    ) -> Result<HashMap<String, i64>, String> {
+        self.fetch(
+            Method::GET,
+            &format!("/controller/network/{}/member", nwid),
+            None,
+            "Failed to fetch members",
+            "Failed to parse members",
+        )
+        .await
     }
 
-    pub async fn get_controller_member(
+    async fn get_controller_member_json(
         &self,
         nwid: &str,
         member_id: &str,
-    ) -> Result<ControllerMember, String> {
-        self.request(&format!(
-            "/controller/network/{}/member/{}",
-            nwid, member_id
-        ))
-        .send()
-        .await
-        .map_err(|e| format!("Failed to fetch member: {}", e))?
-        .json()
+    ) -> Result<serde_json::Value, String> {
+        self.fetch(
+            Method::GET,
+            &format!("/controller/network/{}/member/{}", nwid, member_id),
+            None,
+            "Failed to fetch member",
+            "Failed to parse member",
+        )
         .await
-        .map_err(|e| format!("Failed to parse member: {}", e))
     }
 
-    pub async fn update_controller_member(
+    async fn update_controller_member(
         &self,
         nwid: &str,
         member_id: &str,
         body: serde_json::Value,
     ) -> Result<ControllerMember, String> {
-        self.client
-            .post(format!(
-                "{}/controller/network/{}/member/{}",
-                self.base_url, nwid, member_id
-            ))
-            .header("X-ZT1-Auth", &self.auth_token)
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to update member: {}", e))?
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse member update: {}", e))
+        self.fetch(
+            Method::POST,
+            &format!("/controller/network/{}/member/{}", nwid, member_id),
+            Some(body),
+            "Failed to update member",
+            "Failed to parse member update",
+        )
+        .await
     }
 
-    pub async fn delete_controller_member(
+    async fn delete_controller_member(
         &self,
         nwid: &str,
         member_id: &str,
     ) -> Result<(), String> {
-        let resp = self
-            .client
-            .delete(format!(
-                "{}/controller/network/{}/member/{}",
-                self.base_url, nwid, member_id
-            ))
-            .header("X-ZT1-Auth", &self.auth_token)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to delete member: {}", e))?;
-        if resp.status().is_success() {
-            Ok(())
-        } else {
-            Err(format!(
-                "Delete member failed with status: {}",
-                resp.status()
-            ))
-        }
+        self.delete(
+            &format!("/controller/network/{}/member/{}", nwid, member_id),
+            "Failed to delete member",
+            "Delete member",
+        )
+        .await
     }
 }
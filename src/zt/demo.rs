@@ -0,0 +1,395 @@
+//! Simulated ZeroTier controller for demo mode. It answers the same service API paths as
+//! zerotier-one, from networks and members kept in memory and seeded with sample data, so the
+//! whole UI works without a ZeroTier node.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+use super::client::Reply;
+
+static CONTROLLER: OnceLock<Arc<DemoController>> = OnceLock::new();
+
+/// Address of the simulated controller node; network IDs start with it
+const NODE_ID: &str = "8056c2e21c";
+const VERSION: &str = "1.14.2";
+
+/// Root servers the demo node reports as peers, with their latency in milliseconds
+const DEMO_ROOTS: [(&str, i64); 4] = [("cafe04eba9", 24), ("cafe9efeb9", 87), ("cafefd6717", 142), ("cafe80ed74", 190)];
+
+struct SeedNetwork {
+    suffix: &'static str,
+    name: &'static str,
+    description: &'static str,
+    /// First three octets of the /24 the network assigns from
+    subnet: &'static str,
+    members: &'static [SeedMember],
+}
+
+struct SeedMember {
+    address: &'static str,
+    name: &'static str,
+    host: u8,
+    version: (i32, i32, i32),
+    authorized: bool,
+    online: bool,
+    bridge: bool,
+    days_ago: u32,
+}
+
+const fn member(address: &'static str, name: &'static str, host: u8, online: bool) -> SeedMember {
+    SeedMember { address, name, host, version: (1, 14, 2), authorized: true, online, bridge: false, days_ago: 30 }
+}
+
+const SEED: &[SeedNetwork] = &[
+    SeedNetwork {
+        suffix: "000001",
+        name: "office",
+        description: "Laptops and desktops at the main office",
+        subnet: "10.147.17",
+        members: &[
+            SeedMember { days_ago: 210, ..member("a1b2c3d4e5", "reception-desktop", 11, true) },
+            SeedMember { days_ago: 180, ..member("b7e91f2a30", "alice-laptop", 12, true) },
+            SeedMember { version: (1, 12, 2), days_ago: 95, ..member("c40d5e6f71", "bob-laptop", 13, false) },
+            SeedMember { days_ago: 60, ..member("d5f60718a9", "meeting-room-tv", 14, true) },
+            SeedMember { version: (1, 10, 6), days_ago: 400, ..member("e6a7b8c9d0", "old-nas", 15, false) },
+            SeedMember { authorized: false, days_ago: 0, ..member("f7081a2b3c", "", 0, true) },
+        ],
+    },
+    SeedNetwork {
+        suffix: "000002",
+        name: "homelab",
+        description: "Servers, reachable with the home LAN routed through the gateway",
+        subnet: "10.244.0",
+        members: &[
+            SeedMember { bridge: true, days_ago: 300, ..member("1a2b3c4d5e", "gateway", 1, true) },
+            SeedMember { days_ago: 120, ..member("2b3c4d5e6f", "proxmox", 10, true) },
+            SeedMember { days_ago: 120, ..member("3c4d5e6f7a", "backup-server", 11, true) },
+            SeedMember { version: (1, 12, 2), days_ago: 45, ..member("b7e91f2a30", "alice-laptop", 20, true) },
+        ],
+    },
+    SeedNetwork {
+        suffix: "000003",
+        name: "iot",
+        description: "Sensors and cameras, isolated from everything else",
+        subnet: "172.23.5",
+        members: &[
+            SeedMember { version: (1, 10, 6), days_ago: 500, ..member("4d5e6f7a8b", "garage-camera", 21, true) },
+            SeedMember { version: (1, 10, 6), days_ago: 500, ..member("5e6f7a8b9c", "weather-station", 22, false) },
+            SeedMember { version: (1, 12, 2), days_ago: 80, ..member("6f7a8b9cad", "thermostat", 23, true) },
+        ],
+    },
+];
+
+/// Display names for the seeded members, by address
+pub fn member_names() -> impl Iterator<Item = (&'static str, &'static str)> {
+    SEED.iter()
+        .flat_map(|n| n.members)
+        .filter(|m| !m.name.is_empty())
+        .map(|m| (m.address, m.name))
+}
+
+/// Descriptions for the seeded networks, by network ID
+pub fn network_descriptions() -> impl Iterator<Item = (String, &'static str)> {
+    SEED.iter().map(|n| (format!("{}{}", NODE_ID, n.suffix), n.description))
+}
+
+/// Start the simulated controller; ZeroTier clients created afterwards use it
+pub fn start() {
+    let _ = CONTROLLER.set(Arc::new(DemoController::seeded()));
+}
+
+/// The simulated controller, when running in demo mode
+pub fn controller() -> Option<Arc<DemoController>> {
+    CONTROLLER.get().cloned()
+}
+
+pub struct DemoController {
+    inner: Mutex<Controller>,
+}
+
+#[derive(Default)]
+struct Controller {
+    networks: BTreeMap<String, Value>,
+    members: BTreeMap<String, BTreeMap<String, Value>>,
+    /// Member addresses that show up as online peers
+    online: BTreeSet<String>,
+    next_network: u32,
+}
+
+fn now_ms() -> f64 {
+    chrono::Utc::now().timestamp_millis() as f64
+}
+
+/// A stand-in public key, the same for an address every time
+fn identity(address: &str) -> String {
+    let key: String = address.bytes().cycle().take(128).map(|b| format!("{:x}", b % 16)).collect();
+    format!("{}:0:{}", address, key)
+}
+
+fn new_network(nwid: &str, name: &str, subnet: &str) -> Value {
+    json!({
+        "id": nwid,
+        "nwid": nwid,
+        "name": name,
+        "private": true,
+        "enableBroadcast": true,
+        "v4AssignMode": { "zt": !subnet.is_empty() },
+        "v6AssignMode": { "zt": false, "6plane": false, "rfc4193": false },
+        "mtu": 2800,
+        "multicastLimit": 32,
+        "creationTime": now_ms(),
+        "revision": 1,
+        "routes": if subnet.is_empty() { json!([]) } else { json!([{ "target": format!("{}.0/24", subnet), "via": null }]) },
+        "ipAssignmentPools": if subnet.is_empty() {
+            json!([])
+        } else {
+            json!([{ "ipRangeStart": format!("{}.1", subnet), "ipRangeEnd": format!("{}.254", subnet) }])
+        },
+        "rules": [{ "type": "ACTION_ACCEPT" }],
+        "capabilities": [],
+        "tags": [],
+        "dns": { "domain": "", "servers": [] },
+        "ssoEnabled": false,
+    })
+}
+
+fn new_member(nwid: &str, address: &str) -> Value {
+    json!({
+        "id": address,
+        "address": address,
+        "nwid": nwid,
+        "authorized": false,
+        "activeBridge": false,
+        "identity": identity(address),
+        "ipAssignments": [],
+        "revision": 1,
+        "vMajor": 1, "vMinor": 14, "vRev": 2, "vProto": 12,
+        "noAutoAssignIps": false,
+        "tags": [],
+        "capabilities": [],
+        "creationTime": now_ms(),
+        "lastAuthorizedTime": 0,
+        "lastDeauthorizedTime": 0,
+    })
+}
+
+/// Replace the fields present in `update`, as the controller does, keeping IDs fixed
+fn merge(target: &mut Value, update: Value) {
+    let (Some(target), Value::Object(update)) = (target.as_object_mut(), update) else {
+        return;
+    };
+    for (key, value) in update {
+        if !matches!(key.as_str(), "id" | "nwid" | "address" | "identity" | "revision" | "creationTime") {
+            target.insert(key, value);
+        }
+    }
+    let revision = target.get("revision").and_then(Value::as_u64).unwrap_or(0);
+    target.insert("revision".to_string(), json!(revision + 1));
+}
+
+fn not_found() -> Reply {
+    Reply { status: StatusCode::NOT_FOUND, body: Err("404 Not Found".to_string()) }
+}
+
+fn ok(body: Value) -> Reply {
+    Reply { status: StatusCode::OK, body: Ok(body) }
+}
+
+impl DemoController {
+    fn seeded() -> Self {
+        let mut controller = Controller::default();
+        let day_ms = 24.0 * 60.0 * 60.0 * 1000.0;
+        for seed in SEED {
+            let nwid = format!("{}{}", NODE_ID, seed.suffix);
+            let mut network = new_network(&nwid, seed.name, seed.subnet);
+            network["creationTime"] = json!(now_ms() - 600.0 * day_ms);
+            controller.networks.insert(nwid.clone(), network);
+
+            let members = controller.members.entry(nwid.clone()).or_default();
+            for m in seed.members {
+                let created = now_ms() - f64::from(m.days_ago) * day_ms;
+                let mut member = new_member(&nwid, m.address);
+                member["authorized"] = json!(m.authorized);
+                member["activeBridge"] = json!(m.bridge);
+                member["creationTime"] = json!(created);
+                member["vMajor"] = json!(m.version.0);
+                member["vMinor"] = json!(m.version.1);
+                member["vRev"] = json!(m.version.2);
+                if m.authorized {
+                    member["ipAssignments"] = json!([format!("{}.{}", seed.subnet, m.host)]);
+                    member["lastAuthorizedTime"] = json!(created);
+                }
+                members.insert(m.address.to_string(), member);
+                if m.online {
+                    controller.online.insert(m.address.to_string());
+                }
+            }
+        }
+        // The homelab gateway routes the home LAN into the network
+        if let Some(homelab) = controller.networks.get_mut(&format!("{}000002", NODE_ID)) {
+            homelab["routes"] = json!([
+                { "target": "10.244.0.0/24", "via": null },
+                { "target": "192.168.1.0/24", "via": "10.244.0.1" },
+            ]);
+        }
+        controller.next_network = SEED.len() as u32 + 1;
+        Self { inner: Mutex::new(controller) }
+    }
+
+    /// Answer one service API request
+    pub fn handle(&self, method: &Method, path: &str, body: Option<Value>) -> Reply {
+        let mut c = self.inner.lock().unwrap();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (method.as_str(), segments.as_slice()) {
+            ("GET", ["status"]) => ok(json!({
+                "address": NODE_ID,
+                "publicIdentity": identity(NODE_ID),
+                "online": true,
+                "tcpFallbackActive": false,
+                "version": VERSION,
+                "clock": now_ms() as i64,
+                "config": {},
+            })),
+            ("GET", ["peer"]) => ok(c.peers()),
+            ("GET", ["controller"]) => ok(json!({ "controller": true, "apiVersion": 4, "clock": now_ms() as i64 })),
+            ("GET", ["controller", "network"]) => ok(json!(c.networks.keys().collect::<Vec<_>>())),
+            ("GET", ["controller", "network", nwid]) => c.networks.get(*nwid).cloned().map_or_else(not_found, ok),
+            ("POST", ["controller", "network", nwid]) if nwid.ends_with("______") => {
+                let nwid = format!("{}{:06x}", NODE_ID, c.next_network);
+                c.next_network += 1;
+                let mut network = new_network(&nwid, "", "");
+                merge(&mut network, body.unwrap_or_default());
+                c.networks.insert(nwid.clone(), network.clone());
+                c.members.insert(nwid, BTreeMap::new());
+                ok(network)
+            }
+            ("POST", ["controller", "network", nwid]) => match c.networks.get_mut(*nwid) {
+                Some(network) => {
+                    merge(network, body.unwrap_or_default());
+                    ok(network.clone())
+                }
+                None => not_found(),
+            },
+            ("DELETE", ["controller", "network", nwid]) => match c.networks.remove(*nwid) {
+                Some(network) => {
+                    c.members.remove(*nwid);
+                    ok(network)
+                }
+                None => not_found(),
+            },
+            ("GET", ["controller", "network", nwid, "member"]) => match c.members.get(*nwid) {
+                Some(members) => ok(json!(members
+                    .iter()
+                    .map(|(id, m)| (id.clone(), m["revision"].clone()))
+                    .collect::<serde_json::Map<_, _>>())),
+                None => not_found(),
+            },
+            ("GET", ["controller", "network", nwid, "member", id]) => {
+                c.members.get(*nwid).and_then(|m| m.get(*id)).cloned().map_or_else(not_found, ok)
+            }
+            ("POST", ["controller", "network", nwid, "member", id]) => c.update_member(nwid, id, body.unwrap_or_default()),
+            ("DELETE", ["controller", "network", nwid, "member", id]) => {
+                match c.members.get_mut(*nwid).and_then(|m| m.remove(*id)) {
+                    Some(member) => ok(member),
+                    None => not_found(),
+                }
+            }
+            _ => not_found(),
+        }
+    }
+}
+
+impl Controller {
+    /// Online members as peers of the controller node, heard from a moment ago, and the roots
+    fn peers(&self) -> Value {
+        let now = now_ms();
+        let roots = DEMO_ROOTS.iter().enumerate().map(|(i, (address, latency))| {
+            json!({
+                "address": address,
+                "latency": latency,
+                "role": "PLANET",
+                "version": VERSION,
+                "paths": [{
+                    "address": format!("198.51.100.{}/9993", 1 + i),
+                    "active": true,
+                    "preferred": true,
+                    "lastReceive": now - 2000.0,
+                }],
+            })
+        });
+        let peers: Vec<Value> = self
+            .online
+            .iter()
+            .enumerate()
+            .map(|(i, address)| {
+                json!({
+                    "address": address,
+                    "latency": 8 + (address.len() * 7 + i * 13) % 90,
+                    "role": "LEAF",
+                    "version": VERSION,
+                    "paths": [{
+                        "address": format!("203.0.113.{}/9993", 10 + i),
+                        "active": true,
+                        "preferred": true,
+                        "lastReceive": now - 1000.0,
+                    }],
+                })
+            })
+            .chain(roots)
+            .collect();
+        json!(peers)
+    }
+
+    /// Create or change a member. Like ZeroTier, authorizing a member without addresses gives it
+    /// the next free one from the network's pool.
+    fn update_member(&mut self, nwid: &str, id: &str, update: Value) -> Reply {
+        let Some(network) = self.networks.get(nwid) else {
+            return not_found();
+        };
+        let auto_assign = network["v4AssignMode"]["zt"].as_bool().unwrap_or(false);
+        let pool = network["ipAssignmentPools"]
+            .as_array()
+            .and_then(|pools| pools.iter().find(|p| !p["ipRangeStart"].as_str().unwrap_or(":").contains(':')))
+            .and_then(|p| {
+                let start: std::net::Ipv4Addr = p["ipRangeStart"].as_str()?.parse().ok()?;
+                let end: std::net::Ipv4Addr = p["ipRangeEnd"].as_str()?.parse().ok()?;
+                Some((u32::from(start), u32::from(end)))
+            });
+        let members = self.members.entry(nwid.to_string()).or_default();
+        let taken: HashSet<String> = members
+            .values()
+            .flat_map(|m| m["ipAssignments"].as_array().cloned().unwrap_or_default())
+            .filter_map(|ip| ip.as_str().map(str::to_string))
+            .collect();
+
+        let member = members.entry(id.to_string()).or_insert_with(|| new_member(nwid, id));
+        let was_authorized = member["authorized"].as_bool().unwrap_or(false);
+        merge(member, update);
+        let authorized = member["authorized"].as_bool().unwrap_or(false);
+        if authorized != was_authorized {
+            let field = if authorized { "lastAuthorizedTime" } else { "lastDeauthorizedTime" };
+            member[field] = json!(now_ms());
+        }
+
+        let unassigned = member["ipAssignments"].as_array().is_none_or(|ips| ips.is_empty());
+        let no_auto = member["noAutoAssignIps"].as_bool().unwrap_or(false);
+        if authorized && auto_assign && unassigned && !no_auto {
+            let free = pool.and_then(|(start, end)| {
+                (start..=end)
+                    .map(std::net::Ipv4Addr::from)
+                    .map(|ip| ip.to_string())
+                    .find(|ip| !taken.contains(ip))
+            });
+            if let Some(ip) = free {
+                member["ipAssignments"] = json!([ip]);
+            }
+        }
+        if authorized {
+            self.online.insert(id.to_string());
+        }
+        ok(member.clone())
+    }
+}
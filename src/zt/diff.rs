@@ -0,0 +1,122 @@
+use std::collections::HashSet;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use super::models::{ControllerMember, ControllerNetwork};
+
+/// A single field that differs between the cached and freshly fetched state
+#[derive(Debug, Clone)]
+pub struct DiffEntry {
+    pub path: String,
+    pub cached: String,
+    pub fresh: String,
+}
+
+fn display(v: Option<&Value>) -> String {
+    match v {
+        None => "(missing)".to_string(),
+        Some(v) => v.to_string(),
+    }
+}
+
+/// Recursively compare two JSON values, recording differing leaf paths.
+fn walk(path: &str, cached: Option<&Value>, fresh: Option<&Value>, out: &mut Vec<DiffEntry>) {
+    match (cached, fresh) {
+        (Some(Value::Object(a)), Some(Value::Object(b))) => {
+            let mut keys: Vec<&String> = a.keys().chain(b.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let child = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                walk(&child, a.get(key), b.get(key), out);
+            }
+        }
+        (Some(Value::Array(a)), Some(Value::Array(b))) if a.len() == b.len() => {
+            for (i, (x, y)) in a.iter().zip(b.iter()).enumerate() {
+                walk(&format!("{}[{}]", path, i), Some(x), Some(y), out);
+            }
+        }
+        (a, b) if a != b => out.push(DiffEntry {
+            path: path.to_string(),
+            cached: display(a),
+            fresh: display(b),
+        }),
+        _ => {}
+    }
+}
+
+/// Field-level differences between two serializable values
+pub fn diff<T: Serialize>(cached: &T, fresh: &T) -> Vec<DiffEntry> {
+    let a = serde_json::to_value(cached).unwrap_or(Value::Null);
+    let b = serde_json::to_value(fresh).unwrap_or(Value::Null);
+    let mut out = Vec::new();
+    walk("", Some(&a), Some(&b), &mut out);
+    out
+}
+
+/// Differences for one member, or its presence on only one side
+#[derive(Debug, Clone)]
+pub struct MemberDiff {
+    pub member_id: String,
+    /// "cache only", "controller only", "not loaded" or "changed"
+    pub status: &'static str,
+    pub entries: Vec<DiffEntry>,
+}
+
+/// Compare cached and fresh member lists by member ID. Unchanged members are omitted. A member
+/// in `not_loaded` failed to load on the side it is missing from, so it isn't reported as gone.
+pub fn diff_members(
+    cached: &[ControllerMember],
+    fresh: &[ControllerMember],
+    not_loaded: &HashSet<String>,
+) -> Vec<MemberDiff> {
+    let mut ids: Vec<&str> = cached
+        .iter()
+        .chain(fresh.iter())
+        .map(|m| m.display_id())
+        .collect();
+    ids.sort();
+    ids.dedup();
+
+    ids.into_iter()
+        .filter_map(|id| {
+            let a = cached.iter().find(|m| m.display_id() == id);
+            let b = fresh.iter().find(|m| m.display_id() == id);
+            let (status, entries) = match (a, b) {
+                (Some(a), Some(b)) => {
+                    let entries = diff(a, b);
+                    if entries.is_empty() {
+                        return None;
+                    }
+                    ("changed", entries)
+                }
+                (Some(_), None) | (None, Some(_)) if not_loaded.contains(id) => ("not loaded", Vec::new()),
+                (Some(_), None) => ("cache only", Vec::new()),
+                (None, Some(_)) => ("controller only", Vec::new()),
+                (None, None) => return None,
+            };
+            Some(MemberDiff {
+                member_id: id.to_string(),
+                status,
+                entries,
+            })
+        })
+        .collect()
+}
+
+/// Network-level differences; a missing cached network is reported as a single entry.
+pub fn diff_network(cached: Option<&ControllerNetwork>, fresh: &ControllerNetwork) -> Vec<DiffEntry> {
+    match cached {
+        Some(c) => diff(c, fresh),
+        None => vec![DiffEntry {
+            path: "(network)".to_string(),
+            cached: "(not cached)".to_string(),
+            fresh: fresh.display_id().to_string(),
+        }],
+    }
+}
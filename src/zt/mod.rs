@@ -1,3 +1,12 @@
+pub mod backend;
+pub mod central;
 pub mod client;
+pub mod demo;
+pub mod diff;
 pub mod models;
 pub mod poller;
+pub mod rules;
+pub mod service;
+pub mod simulate;
+pub mod snapshot;
+pub mod snippets;
@@ -1,6 +1,18 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
 use serde::{Deserialize, Serialize};
 
+/// A time given in milliseconds since the epoch, written out the way the user prefers
 fn format_epoch_ms(ts: Option<f64>) -> String {
+    match ts {
+        Some(ms) if ms > 0.0 => crate::preferences::format_epoch_ms(ms),
+        _ => "-".to_string(),
+    }
+}
+
+/// How long ago a time given in milliseconds since the epoch was, such as "3h ago"
+fn format_epoch_ms_ago(ts: Option<f64>) -> String {
     match ts {
         Some(ms) if ms > 0.0 => {
             let secs = (ms / 1000.0) as u64;
@@ -51,6 +63,96 @@ impl NodeStatus {
     }
 }
 
+/// How recently a peer must have sent us something to count as online
+const PEER_ONLINE_SECS: f64 = 180.0;
+
+/// A node the controller's own ZeroTier node is in contact with, from `/peer`
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Peer {
+    pub address: Option<String>,
+    pub latency: Option<i64>,
+    pub role: Option<String>,
+    #[serde(default)]
+    pub paths: Vec<PeerPath>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerPath {
+    pub address: Option<String>,
+    pub active: Option<bool>,
+    pub preferred: Option<bool>,
+    pub expired: Option<bool>,
+    pub last_receive: Option<f64>,
+}
+
+impl PeerPath {
+    /// In use: active and not yet expired
+    pub fn is_live(&self) -> bool {
+        self.active.unwrap_or(false) && !self.expired.unwrap_or(false)
+    }
+
+    pub fn display_last_receive(&self) -> String {
+        format_epoch_ms_ago(self.last_receive)
+    }
+}
+
+impl Peer {
+    /// Most recent packet received on any path, in epoch milliseconds
+    pub fn last_receive(&self) -> Option<f64> {
+        self.paths
+            .iter()
+            .filter_map(|p| p.last_receive)
+            .reduce(f64::max)
+    }
+
+    pub fn is_online(&self) -> bool {
+        let now_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as f64)
+            .unwrap_or(0.0);
+        self.last_receive()
+            .is_some_and(|t| now_ms - t < PEER_ONLINE_SECS * 1000.0)
+    }
+
+    pub fn display_last_seen(&self) -> String {
+        format_epoch_ms_ago(self.last_receive())
+    }
+
+    /// Address of the path in use: the preferred live one, else any live one
+    pub fn physical_address(&self) -> Option<String> {
+        let mut live = self.paths.iter().filter(|p| p.is_live());
+        let preferred = live.clone().find(|p| p.preferred.unwrap_or(false));
+        preferred.or_else(|| live.next())?.address.clone()
+    }
+
+    /// A root server (the planet's or a moon's) the node finds other nodes through
+    pub fn is_root(&self) -> bool {
+        matches!(self.role.as_deref(), Some("PLANET" | "MOON"))
+    }
+
+    pub fn display_role(&self) -> &str {
+        match self.role.as_deref() {
+            Some("PLANET") => "Planet",
+            Some("MOON") => "Moon",
+            Some("LEAF") => "Leaf",
+            Some(role) => role,
+            None => "-",
+        }
+    }
+
+    /// Online without a direct path, so traffic goes through a ZeroTier root
+    pub fn is_relayed(&self) -> bool {
+        self.is_online() && !self.paths.iter().any(PeerPath::is_live)
+    }
+
+    /// Round-trip time in milliseconds; ZeroTier reports -1 when it hasn't measured one
+    pub fn latency_ms(&self) -> Option<i64> {
+        self.latency.filter(|l| *l >= 0)
+    }
+}
+
 // ---- Controller Models ----
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
@@ -79,6 +181,11 @@ pub struct ControllerNetwork {
     pub tags: Vec<serde_json::Value>,
     #[serde(default)]
     pub dns: DnsConfig,
+    pub sso_enabled: Option<bool>,
+    /// OIDC client ID members authenticate with
+    pub client_id: Option<String>,
+    pub authorization_endpoint: Option<String>,
+    pub provider: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
@@ -124,7 +231,7 @@ impl ControllerNetwork {
         }
     }
 
-    pub fn _display_mtu(&self) -> String {
+    pub fn display_mtu(&self) -> String {
         self.mtu
             .map(|m| m.to_string())
             .unwrap_or_else(|| "2800".to_string())
@@ -150,10 +257,65 @@ impl ControllerNetwork {
         self.v6_assign_mode.as_ref().map(|m| m.zt).unwrap_or(false)
     }
 
+    /// The /88 every member's RFC4193 address is in: fd + network ID + 9993
+    pub fn rfc4193_prefix(&self) -> Option<String> {
+        let nwid = parse_nwid(self.display_id())?;
+        Some(format!("{}/88", ipv6_from_hex(&format!("fd{:016x}9993", nwid))?))
+    }
+
+    /// The /40 every member's 6PLANE /80 is in: fc + the network ID's halves XORed together
+    pub fn sixplane_prefix(&self) -> Option<String> {
+        let nwid = parse_nwid(self.display_id())?;
+        Some(format!("{}/40", ipv6_from_hex(&format!("fc{:08x}", sixplane_fold(nwid)))?))
+    }
+
+    /// Whether a single managed route holds all of `pool`; ZeroTier only assigns addresses
+    /// from a pool inside one
+    pub fn pool_routed(&self, pool: &IpAssignmentPool) -> bool {
+        pool.bounds()
+            .is_some_and(|(start, end)| self.routes.iter().any(|r| r.contains(start) && r.contains(end)))
+    }
+
+    /// Whether `ip` is in one of the assignment pools or managed routes
+    pub fn covers_ip(&self, ip: IpAddr) -> bool {
+        self.ip_assignment_pools.iter().any(|p| p.contains(ip)) || self.routes.iter().any(|r| r.contains(ip))
+    }
+
+    /// The member's assigned addresses that no pool or managed route covers, as left behind
+    /// by renumbering. Nothing is flagged on a network with neither pools nor routes.
+    pub fn stray_ips(&self, member: &ControllerMember) -> Vec<String> {
+        if self.ip_assignment_pools.is_empty() && self.routes.is_empty() {
+            return Vec::new();
+        }
+        member
+            .ip_assignments
+            .iter()
+            .filter(|ip| ip.parse().is_ok_and(|ip| !self.covers_ip(ip)))
+            .cloned()
+            .collect()
+    }
+
+    /// The first pool address of the given family that isn't in `taken`, skipping the network
+    /// and broadcast addresses of the IPv4 subnet the pool is routed in
+    pub fn free_pool_ip(&self, v6: bool, taken: &HashSet<String>) -> Option<String> {
+        self.ip_assignment_pools
+            .iter()
+            .filter(|p| p.is_ipv6() == v6)
+            .flat_map(|p| p.addresses().take(MAX_POOL_SCAN))
+            .filter(|ip| !self.routes.iter().any(|r| r.is_network_or_broadcast(*ip)))
+            .filter(|ip| self.routes.is_empty() || self.routes.iter().any(|r| r.contains(*ip)))
+            .map(|ip| ip.to_string())
+            .find(|ip| !taken.contains(ip))
+    }
+
     pub fn broadcast_enabled(&self) -> bool {
         self.enable_broadcast.unwrap_or(false)
     }
 
+    pub fn sso_enabled(&self) -> bool {
+        self.sso_enabled.unwrap_or(false)
+    }
+
     pub fn display_subnet(&self) -> &str {
         self.routes
             .first()
@@ -165,6 +327,10 @@ impl ControllerNetwork {
         format_epoch_ms(self.creation_time)
     }
 
+    pub fn creation_time_ago(&self) -> String {
+        format_epoch_ms_ago(self.creation_time)
+    }
+
     /// Returns the rules, capabilities, and tags as formatted JSON string
     pub fn display_rules_json(&self) -> String {
         let output = serde_json::json!({
@@ -215,8 +381,60 @@ impl ControllerRoute {
     pub fn is_ipv4(&self) -> bool {
         !self.is_ipv6()
     }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.target
+            .as_deref()
+            .is_some_and(|target| super::simulate::in_cidr(ip, target))
+    }
+
+    /// Whether `ip` is the network or broadcast address of this IPv4 route. Routes of /31 and
+    /// /32 have neither.
+    pub fn is_network_or_broadcast(&self, ip: IpAddr) -> bool {
+        let IpAddr::V4(ip) = ip else {
+            return false;
+        };
+        let Some((net, bits)) = self.target.as_deref().and_then(|t| t.split_once('/')) else {
+            return false;
+        };
+        let (Ok(net), Ok(bits)) = (net.parse::<Ipv4Addr>(), bits.parse::<u32>()) else {
+            return false;
+        };
+        if bits > 30 {
+            return false;
+        }
+        let host_mask = u32::MAX >> bits;
+        let network = u32::from(net) & !host_mask;
+        let ip = u32::from(ip);
+        ip == network || ip == network | host_mask
+    }
+}
+
+/// A network ID's 64 bits, when it is 16 hex digits
+fn parse_nwid(nwid: &str) -> Option<u64> {
+    if nwid.len() != 16 || !nwid.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u64::from_str_radix(nwid, 16).ok()
 }
 
+/// The 32 bits of a network ID in its 6PLANE addresses: the ID's two halves XORed together
+fn sixplane_fold(nwid: u64) -> u32 {
+    (nwid >> 32) as u32 ^ nwid as u32
+}
+
+/// An IPv6 address from its leading hex digits, zero-filled and in its shortest form
+fn ipv6_from_hex(hex: &str) -> Option<Ipv6Addr> {
+    if hex.is_empty() || hex.len() > 32 {
+        return None;
+    }
+    let bits = u128::from_str_radix(hex, 16).ok()?;
+    Some(Ipv6Addr::from(bits << (4 * (32 - hex.len()))))
+}
+
+/// Most addresses of a pool looked at when picking a free one
+const MAX_POOL_SCAN: usize = 65536;
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct IpAssignmentPool {
@@ -247,6 +465,84 @@ impl IpAssignmentPool {
     pub fn is_ipv4(&self) -> bool {
         !self.is_ipv6()
     }
+
+    /// A pool from a typed range: start and end addresses of the same family, or a prefix such
+    /// as `fd00:1::/64` with no end. An IPv4 prefix leaves out its network and broadcast addresses.
+    pub fn parse(start: &str, end: &str) -> Result<Self, String> {
+        let (start, end) = (start.trim(), end.trim());
+        let (first, last) = match start.split_once('/') {
+            Some((addr, prefix)) if end.is_empty() => {
+                let addr: IpAddr = addr.parse().map_err(|_| format!("Invalid address: {}", addr))?;
+                let bits = if addr.is_ipv4() { 32 } else { 128 };
+                let prefix: u32 = prefix
+                    .parse()
+                    .ok()
+                    .filter(|p| *p <= bits)
+                    .ok_or_else(|| format!("Invalid prefix length: {}", prefix))?;
+                let host_mask = u128::MAX.checked_shr(128 - bits + prefix).unwrap_or(0);
+                match addr {
+                    IpAddr::V4(a) => {
+                        let host_mask = host_mask as u32;
+                        let network = u32::from(a) & !host_mask;
+                        let (first, last) = if prefix <= 30 {
+                            (network + 1, (network | host_mask) - 1)
+                        } else {
+                            (network, network | host_mask)
+                        };
+                        (IpAddr::V4(Ipv4Addr::from(first)), IpAddr::V4(Ipv4Addr::from(last)))
+                    }
+                    IpAddr::V6(a) => {
+                        let network = u128::from(a) & !host_mask;
+                        (IpAddr::V6(Ipv6Addr::from(network)), IpAddr::V6(Ipv6Addr::from(network | host_mask)))
+                    }
+                }
+            }
+            Some(_) => return Err("Give either a prefix or a start and end address, not both".to_string()),
+            None => {
+                let first: IpAddr = start.parse().map_err(|_| format!("Invalid start address: {}", start))?;
+                let last: IpAddr = end.parse().map_err(|_| format!("Invalid end address: {}", end))?;
+                (first, last)
+            }
+        };
+        if first.is_ipv4() != last.is_ipv4() {
+            return Err("The start and end of a pool must both be IPv4 or both IPv6".to_string());
+        }
+        if first > last {
+            return Err(format!("The pool starts after it ends: {} is above {}", first, last));
+        }
+        Ok(Self {
+            ip_range_start: Some(first.to_string()),
+            ip_range_end: Some(last.to_string()),
+        })
+    }
+
+    fn bounds(&self) -> Option<(IpAddr, IpAddr)> {
+        let start = self.ip_range_start.as_deref()?.parse().ok()?;
+        let end = self.ip_range_end.as_deref()?.parse().ok()?;
+        Some((start, end))
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.bounds().is_some_and(|(start, end)| match (start, end, ip.to_canonical()) {
+            (IpAddr::V4(_), IpAddr::V4(_), ip @ IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_), ip @ IpAddr::V6(_)) => {
+                start <= ip && ip <= end
+            }
+            _ => false,
+        })
+    }
+
+    /// The pool's addresses in order
+    fn addresses(&self) -> Box<dyn Iterator<Item = IpAddr>> {
+        match self.bounds() {
+            Some((IpAddr::V4(start), IpAddr::V4(end))) => {
+                Box::new((u32::from(start)..=u32::from(end)).map(|ip| IpAddr::V4(Ipv4Addr::from(ip))))
+            }
+            Some((IpAddr::V6(start), IpAddr::V6(end))) => {
+                Box::new((u128::from(start)..=u128::from(end)).map(|ip| IpAddr::V6(Ipv6Addr::from(ip))))
+            }
+            _ => Box::new(std::iter::empty()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
@@ -267,6 +563,12 @@ pub struct ControllerMember {
     pub v_proto: Option<i32>,
     #[serde(default)]
     pub no_auto_assign_ips: bool,
+    /// (tag id, value) pairs
+    #[serde(default)]
+    pub tags: Vec<(u32, u32)>,
+    /// Capability ids
+    #[serde(default)]
+    pub capabilities: Vec<u32>,
     pub creation_time: Option<f64>,
     pub last_authorized_time: Option<f64>,
     pub last_deauthorized_time: Option<f64>,
@@ -325,6 +627,18 @@ impl ControllerMember {
         format_epoch_ms(self.last_deauthorized_time)
     }
 
+    pub fn creation_time_ago(&self) -> String {
+        format_epoch_ms_ago(self.creation_time)
+    }
+
+    pub fn last_authorized_ago(&self) -> String {
+        format_epoch_ms_ago(self.last_authorized_time)
+    }
+
+    pub fn last_deauthorized_ago(&self) -> String {
+        format_epoch_ms_ago(self.last_deauthorized_time)
+    }
+
     /// Returns IP assignments as comma-separated string, IPv4 first then IPv6
     pub fn display_ip_assignments(&self) -> String {
         let mut ipv4: Vec<&str> = Vec::new();
@@ -343,13 +657,13 @@ impl ControllerMember {
     /// Compute RFC4193 address for this member
     /// Format: fd<nwid>9993<nodeid> split into groups of 4
     pub fn rfc4193_address(&self) -> Option<String> {
-        let nwid = self.nwid.as_ref()?;
+        let nwid = parse_nwid(self.nwid.as_ref()?)?;
         let node = self.address.as_ref().or(self.id.as_ref())?;
-        if nwid.len() != 16 || node.len() != 10 {
+        if node.len() != 10 || !node.bytes().all(|b| b.is_ascii_hexdigit()) {
             return None;
         }
         // fd + nwid(16) + 9993 + node(10) = 32 hex chars after fd
-        let full = format!("fd{}9993{}", nwid, node);
+        let full = format!("fd{:016x}9993{}", nwid, node);
         // Split into groups of 4
         let parts: Vec<&str> = (0..8).map(|i| &full[i * 4..(i + 1) * 4]).collect();
         Some(parts.join(":"))
@@ -358,24 +672,13 @@ impl ControllerMember {
     /// Compute 6PLANE address for this member
     /// XOR first 4 bytes of nwid with bytes 4-7, then append node + padding
     pub fn sixplane_address(&self) -> Option<String> {
-        let nwid = self.nwid.as_ref()?;
+        let nwid = parse_nwid(self.nwid.as_ref()?)?;
         let node = self.address.as_ref().or(self.id.as_ref())?;
-        if nwid.len() != 16 || node.len() != 10 {
-            return None;
-        }
-        // Parse nwid bytes
-        let nwid_bytes: Vec<u8> = (0..8)
-            .filter_map(|i| u8::from_str_radix(&nwid[i * 2..i * 2 + 2], 16).ok())
-            .collect();
-        if nwid_bytes.len() != 8 {
+        if node.len() != 10 || !node.bytes().all(|b| b.is_ascii_hexdigit()) {
             return None;
         }
-        // XOR first 4 bytes with bytes 4-7
-        let xored: Vec<String> = (0..4)
-            .map(|i| format!("{:02x}", nwid_bytes[i] ^ nwid_bytes[i + 4]))
-            .collect();
         // Build: fc(2) + xored(8) + node(10) + padding(12) = 32 hex chars
-        let full = format!("fc{}{}{}", xored.join(""), node, "000000000001");
+        let full = format!("fc{:08x}{}000000000001", sixplane_fold(nwid), node);
         // Split into groups of 4
         let parts: Vec<&str> = (0..8).map(|i| &full[i * 4..(i + 1) * 4]).collect();
         Some(parts.join(":"))
@@ -383,11 +686,45 @@ impl ControllerMember {
 }
 
 /// Cached snapshot of all ZeroTier state
-#[derive(Debug, Clone, Default, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct ZtState {
     pub status: Option<NodeStatus>,
     pub controller_networks: Vec<ControllerNetwork>,
     pub controller_members: std::collections::HashMap<String, Vec<ControllerMember>>,
+    /// Networks whose member list failed to load. Their `controller_members` entry is empty, which
+    /// says nothing about the members themselves.
+    #[serde(default)]
+    pub unloaded_networks: std::collections::HashSet<String>,
+    /// Members that failed to load, per network. They are missing from `controller_members`
+    /// without having left.
+    #[serde(default)]
+    pub unloaded_members: std::collections::HashMap<String, std::collections::HashSet<String>>,
+    /// Peers of the controller's node, keyed by address
+    pub peers: std::collections::HashMap<String, Peer>,
     pub last_updated: Option<std::time::SystemTime>,
     pub error: Option<String>,
+    /// When polls started failing, while `error` is set
+    #[serde(default)]
+    pub error_since: Option<std::time::SystemTime>,
+    /// Loaded from the copy saved before a restart, until the first poll replaces it
+    #[serde(skip)]
+    pub restored: bool,
+}
+
+impl ZtState {
+    /// Whether this comes from a poll by the running server. A restored copy is fine to show
+    /// but not to act on, since anything may have changed while TierDrop was down.
+    pub fn polled(&self) -> bool {
+        self.last_updated.is_some() && !self.restored
+    }
+
+    /// Whether the network's member list loaded, so `controller_members` can be trusted for it
+    pub fn members_loaded(&self, nwid: &str) -> bool {
+        !self.unloaded_networks.contains(nwid)
+    }
+
+    /// Whether the member loaded, so its absence from `controller_members` means it is gone
+    pub fn member_loaded(&self, nwid: &str, member_id: &str) -> bool {
+        self.members_loaded(nwid) && !self.unloaded_members.get(nwid).is_some_and(|ids| ids.contains(member_id))
+    }
 }
@@ -1,42 +1,252 @@
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::SystemTime;
-use tokio::sync::{broadcast, Notify, RwLock};
-use tokio::time::{interval, Duration, MissedTickBehavior};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, OnceLock};
+use std::time::{Instant, SystemTime};
+use tokio::sync::{broadcast, mpsc, Notify, RwLock, Semaphore};
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, warn};
 
-use super::client::ZtClient;
-use super::models::{ControllerMember, ControllerNetwork, ZtState};
-use crate::sse::SseEvent;
+use super::backend::SharedBackend;
+use super::models::{ControllerMember, ControllerNetwork, Peer, ZtState};
+use super::snapshot;
+use crate::history;
+use crate::notifications::{self, EventKind, NotificationEvent};
+use crate::sse::{MemberChange, SseEvent, Viewers};
+
+/// Consecutive failed polls before the controller is reported unreachable, so a
+/// restart or a single slow response doesn't page anyone
+const UNREACHABLE_AFTER_POLLS: u32 = 3;
+
+/// How often to poll while someone has TierDrop open
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often to poll while a network detail page is open, so member changes show up quickly
+const FOCUSED_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often to poll with no page open. Alerts and scheduled jobs still see changes, just later.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the latest state is saved for the next start, besides at shutdown
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Changed members in one network beyond which its page reloads the whole member list instead of
+/// single rows, so a mass change doesn't overflow the event channel
+const MAX_MEMBER_EVENTS: usize = 20;
+
+/// Member requests in flight at once when `TIERDROP_POLL_CONCURRENCY` is unset
+pub const DEFAULT_CONCURRENCY: usize = 16;
+
+static CONCURRENCY: OnceLock<usize> = OnceLock::new();
+
+/// `TIERDROP_POLL_CONCURRENCY`: how many member requests a poll may have open to ZeroTier at
+/// once, across all networks, so large networks don't swamp the service
+pub fn concurrency_from_env() -> Result<usize, String> {
+    match std::env::var("TIERDROP_POLL_CONCURRENCY") {
+        Ok(raw) if !raw.trim().is_empty() => match raw.trim().parse() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("TIERDROP_POLL_CONCURRENCY must be a positive number, not {:?}", raw)),
+        },
+        _ => Ok(DEFAULT_CONCURRENCY),
+    }
+}
+
+/// Set once at startup, before the poller starts
+pub fn set_concurrency(limit: usize) {
+    let _ = CONCURRENCY.set(limit);
+}
+
+/// Members seen in earlier polls with their revision, by network and member ID. The controller
+/// bumps a member's revision whenever it changes, so only members with a new one are fetched again.
+type MemberCache = HashMap<String, HashMap<String, (i64, ControllerMember)>>;
+
+/// Time between polls, given who is watching
+pub fn poll_interval(viewers: &Viewers) -> Duration {
+    if viewers.focused() > 0 {
+        FOCUSED_POLL_INTERVAL
+    } else if viewers.connected() > 0 {
+        POLL_INTERVAL
+    } else {
+        IDLE_POLL_INTERVAL
+    }
+}
+
+/// Tracks consecutive failed polls and decides when to announce an outage or recovery
+#[derive(Default)]
+struct OutageTracker {
+    failures: u32,
+    /// When the first failure of the current run happened
+    down_since: Option<Instant>,
+    /// Whether an unreachable notice went out for the current outage
+    alerted: bool,
+}
+
+impl OutageTracker {
+    fn observe(&mut self, error: Option<&str>) -> Option<NotificationEvent> {
+        match error {
+            Some(e) => {
+                self.failures += 1;
+                self.down_since.get_or_insert_with(Instant::now);
+                if self.alerted || self.failures < UNREACHABLE_AFTER_POLLS {
+                    return None;
+                }
+                self.alerted = true;
+                Some(controller_event(EventKind::ControllerUnreachable, e.to_string()))
+            }
+            None => {
+                let down_since = self.down_since.take();
+                let alerted = std::mem::take(&mut self.alerted);
+                self.failures = 0;
+                if !alerted {
+                    return None;
+                }
+                let secs = down_since.map(|t| t.elapsed().as_secs()).unwrap_or(0);
+                Some(controller_event(
+                    EventKind::ControllerRecovered,
+                    format!("down for {}", notifications::format_duration(secs)),
+                ))
+            }
+        }
+    }
+}
+
+fn controller_event(kind: EventKind, detail: String) -> NotificationEvent {
+    NotificationEvent {
+        kind,
+        nwid: String::new(),
+        network_name: String::new(),
+        member_id: None,
+        detail: Some(detail),
+    }
+}
+
+/// Networks whose member lists differ between two polls, including ones that appeared or went away
+fn networks_with_member_changes(old: &ZtState, new: &ZtState) -> Vec<String> {
+    let mut networks: Vec<String> = new
+        .controller_members
+        .iter()
+        .filter(|(nwid, members)| old.controller_members.get(*nwid) != Some(*members))
+        .map(|(nwid, _)| nwid.clone())
+        .chain(
+            old.controller_members
+                .keys()
+                .filter(|nwid| !new.controller_members.contains_key(*nwid))
+                .cloned(),
+        )
+        .collect();
+    networks.sort();
+    networks
+}
+
+/// Events for the networks whose members changed between two polls: one per added, updated or
+/// removed member, plus one for the whole list when members came or went or the network's
+/// settings changed. Networks that appeared or disappeared are covered by `ControllerNetworksChanged`.
+fn member_events(old: &ZtState, new: &ZtState) -> Vec<SseEvent> {
+    let mut events = Vec::new();
+    for (nwid, members) in &new.controller_members {
+        let Some(old_members) = old.controller_members.get(nwid) else {
+            continue;
+        };
+        let previous: HashMap<&str, &ControllerMember> = old_members.iter().map(|m| (m.display_id(), m)).collect();
+        let mut changes: Vec<(&str, MemberChange)> = members
+            .iter()
+            .filter_map(|m| match previous.get(m.display_id()) {
+                None => Some((m.display_id(), MemberChange::Added)),
+                Some(prev) if *prev != m => Some((m.display_id(), MemberChange::Updated)),
+                Some(_) => None,
+            })
+            .collect();
+        let current: HashSet<&str> = members.iter().map(|m| m.display_id()).collect();
+        changes.extend(
+            previous
+                .keys()
+                .filter(|id| !current.contains(*id))
+                .map(|id| (*id, MemberChange::Removed)),
+        );
+
+        let network_changed = old.controller_networks.iter().find(|n| n.display_id() == nwid)
+            != new.controller_networks.iter().find(|n| n.display_id() == nwid);
+        let list_changed = network_changed
+            || changes.len() > MAX_MEMBER_EVENTS
+            || changes.iter().any(|(_, change)| *change != MemberChange::Updated);
+        if list_changed {
+            events.push(SseEvent::NetworkMembersChanged { nwid: nwid.clone() });
+        }
+        if changes.len() <= MAX_MEMBER_EVENTS {
+            events.extend(changes.into_iter().map(|(id, change)| SseEvent::MemberChanged {
+                nwid: nwid.clone(),
+                member_id: id.to_string(),
+                change,
+            }));
+        }
+    }
+    events
+}
 
 pub async fn start_poller(
-    client: ZtClient,
+    client: Arc<RwLock<Option<SharedBackend>>>,
     state: Arc<RwLock<ZtState>>,
     tx: broadcast::Sender<SseEvent>,
     notify: Arc<Notify>,
-    poll_interval: Duration,
+    viewers: Arc<Viewers>,
+    events: mpsc::UnboundedSender<NotificationEvent>,
+    shutdown: CancellationToken,
 ) {
-    let mut tick = interval(poll_interval);
-    tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut outage = OutageTracker::default();
+    let limit = Arc::new(Semaphore::new(*CONCURRENCY.get().unwrap_or(&DEFAULT_CONCURRENCY)));
+    let mut last_poll: Option<tokio::time::Instant> = None;
+    let mut members = MemberCache::new();
+    let mut polled: Option<SharedBackend> = None;
+    let mut last_saved: Option<tokio::time::Instant> = None;
+    // Whether a successful poll happened since the last save
+    let mut unsaved = false;
 
     loop {
+        // Recomputed whenever a page opens or closes, so a visitor after a quiet spell gets
+        // fresh data straight away instead of waiting out the idle interval
+        let due = last_poll.map_or_else(tokio::time::Instant::now, |t| t + poll_interval(&viewers));
         tokio::select! {
-            _ = tick.tick() => {}
+            _ = tokio::time::sleep_until(due) => {}
             _ = notify.notified() => {
                 debug!("Immediate poll triggered by handler");
             }
+            _ = viewers.changed() => continue,
+            _ = shutdown.cancelled() => {
+                if unsaved {
+                    save_snapshot(&state).await;
+                }
+                break;
+            }
         }
+        last_poll = Some(tokio::time::Instant::now());
 
-        let new_state = poll_once(&client).await;
+        // Read each time so a client replaced by a restore or reload takes effect
+        let Some(current) = client.read().await.clone() else {
+            continue;
+        };
+        // Revisions from another controller say nothing about this one's members
+        if !polled.as_ref().is_some_and(|p| Arc::ptr_eq(p, &current)) {
+            members.clear();
+            polled = Some(current.clone());
+        }
+        let mut new_state = poll_once(&current, &mut members, &limit).await;
+        let outage_notice = outage.observe(new_state.error.as_deref());
+        // A failed poll isn't worth keeping over the last good one
+        unsaved |= new_state.error.is_none();
 
         // Read old state and compare
-        let (status_changed, error_changed, ctrl_networks_changed, ctrl_members_changed) = {
+        let (status_changed, error_changed, ctrl_networks_changed, members_changed_in, member_changes, notices, changes) = {
             let old = state.read().await;
+            if new_state.error.is_some() {
+                new_state.error_since = old.error_since.or(new_state.last_updated);
+            }
             (
                 new_state.status != old.status,
                 new_state.error != old.error,
                 new_state.controller_networks != old.controller_networks,
-                new_state.controller_members != old.controller_members,
+                networks_with_member_changes(&old, &new_state),
+                member_events(&old, &new_state),
+                notifications::diff_events(&old, &new_state),
+                history::diff(&old, &new_state),
             )
         };
 
@@ -51,21 +261,47 @@ pub async fn start_poller(
             debug!("Status changed, broadcasting SSE event");
             let _ = tx.send(SseEvent::StatusChanged);
         }
+        if error_changed {
+            let _ = tx.send(SseEvent::ControllerErrorChanged);
+        }
         if ctrl_networks_changed {
             debug!("Controller networks changed, broadcasting SSE event");
             let _ = tx.send(SseEvent::ControllerNetworksChanged);
         }
-        if ctrl_members_changed {
+        if !members_changed_in.is_empty() {
             debug!("Controller members changed, broadcasting SSE event");
-            let _ = tx.send(SseEvent::ControllerMembersChanged);
+            let _ = tx.send(SseEvent::ControllerMembersChanged { networks: members_changed_in });
+        }
+        for event in member_changes {
+            let _ = tx.send(event);
+        }
+        for notice in notices.into_iter().chain(outage_notice) {
+            let _ = events.send(notice);
+        }
+        if let Err(e) = history::record(&changes) {
+            warn!("Failed to record member history: {}", e);
+        }
+
+        if unsaved && last_saved.is_none_or(|t| t.elapsed() >= SNAPSHOT_INTERVAL) {
+            save_snapshot(&state).await;
+            unsaved = false;
+            last_saved = Some(tokio::time::Instant::now());
         }
     }
 }
 
-async fn poll_once(client: &ZtClient) -> ZtState {
-    // Phase 1: Fetch node status and controller network IDs concurrently
-    let (status_res, ctrl_nw_ids_res) = tokio::join!(
+async fn save_snapshot(state: &RwLock<ZtState>) {
+    let current = state.read().await.clone();
+    if let Err(e) = snapshot::save(&current).await {
+        warn!("{}", e);
+    }
+}
+
+async fn poll_once(client: &SharedBackend, cache: &mut MemberCache, limit: &Arc<Semaphore>) -> ZtState {
+    // Phase 1: Fetch node status, peers and controller network IDs concurrently
+    let (status_res, peers_res, ctrl_nw_ids_res) = tokio::join!(
         client.get_status(),
+        client.get_peers(),
         client.get_controller_networks(),
     );
 
@@ -80,6 +316,17 @@ async fn poll_once(client: &ZtClient) -> ZtState {
         }
     };
 
+    let peers: HashMap<String, Peer> = match peers_res {
+        Ok(peers) => peers
+            .into_iter()
+            .filter_map(|p| Some((p.address.clone()?, p)))
+            .collect(),
+        Err(e) => {
+            debug!("Failed to poll peers: {}", e);
+            HashMap::new()
+        }
+    };
+
     let ctrl_nw_ids = match ctrl_nw_ids_res {
         Ok(ids) => ids,
         Err(e) => {
@@ -91,22 +338,44 @@ async fn poll_once(client: &ZtClient) -> ZtState {
     // Phase 2: Spawn a task per network for true parallelism across threads
     let mut controller_networks: Vec<ControllerNetwork> = Vec::new();
     let mut controller_members: HashMap<String, Vec<ControllerMember>> = HashMap::new();
+    let mut unloaded_networks = HashSet::new();
+    let mut unloaded_members = HashMap::new();
 
+    // Networks that are gone drop out of the cache along with their members
+    let mut previous = std::mem::take(cache);
     if !ctrl_nw_ids.is_empty() {
         let handles: Vec<_> = ctrl_nw_ids
             .into_iter()
             .map(|nwid| {
                 let client = client.clone();
-                tokio::spawn(async move { fetch_network(&client, &nwid).await })
+                let known = previous.remove(&nwid).unwrap_or_default();
+                let limit = limit.clone();
+                tokio::spawn(async move { fetch_network(&client, &nwid, known, &limit).await })
             })
             .collect();
 
         for handle in handles {
-            if let Ok((nwid, nw_result, members)) = handle.await {
+            if let Ok((nwid, nw_result, fetched)) = handle.await {
                 if let Ok(nw) = nw_result {
                     controller_networks.push(nw);
                 }
-                controller_members.insert(nwid, members);
+                let fetched = match fetched {
+                    Ok((fetched, failed)) => {
+                        if !failed.is_empty() {
+                            unloaded_members.insert(nwid.clone(), failed);
+                        }
+                        fetched
+                    }
+                    Err(_) => {
+                        unloaded_networks.insert(nwid.clone());
+                        HashMap::new()
+                    }
+                };
+                let mut members: Vec<ControllerMember> = fetched.values().map(|(_, m)| m.clone()).collect();
+                // Sort by ID for stable PartialEq comparison between polls
+                members.sort_by(|a, b| a.display_id().cmp(b.display_id()));
+                controller_members.insert(nwid.clone(), members);
+                cache.insert(nwid, fetched);
             }
         }
     }
@@ -115,20 +384,28 @@ async fn poll_once(client: &ZtClient) -> ZtState {
         status,
         controller_networks,
         controller_members,
+        unloaded_networks,
+        unloaded_members,
+        peers,
         last_updated: Some(SystemTime::now()),
         error,
+        error_since: None,
+        restored: false,
     }
 }
 
-/// Fetch a single network's details and all its members concurrently.
+/// A network's members with their revisions, and the IDs of those that failed to load. Err when
+/// the member list itself failed to load.
+type FetchedMembers = Result<(HashMap<String, (i64, ControllerMember)>, HashSet<String>), String>;
+
+/// Fetch a single network's details and, concurrently, its members whose revision differs from
+/// the one in `known`, at most `limit` at a time.
 async fn fetch_network(
-    client: &ZtClient,
+    client: &SharedBackend,
     nwid: &str,
-) -> (
-    String,
-    Result<ControllerNetwork, String>,
-    Vec<ControllerMember>,
-) {
+    mut known: HashMap<String, (i64, ControllerMember)>,
+    limit: &Arc<Semaphore>,
+) -> (String, Result<ControllerNetwork, String>, FetchedMembers) {
     // Fetch network detail and member ID list in parallel
     let (nw_result, member_ids_result) = tokio::join!(
         client.get_controller_network(nwid),
@@ -137,28 +414,50 @@ async fn fetch_network(
 
     let members = match member_ids_result {
         Ok(ids) => {
-            // Fetch all member details in parallel via spawned tasks
-            let handles: Vec<_> = ids
-                .keys()
-                .map(|mid| {
-                    let client = client.clone();
-                    let nwid = nwid.to_string();
-                    let mid = mid.clone();
-                    tokio::spawn(async move { client.get_controller_member(&nwid, &mid).await })
-                })
-                .collect();
-
-            let mut members = Vec::with_capacity(handles.len());
+            // Fetch new and changed members in parallel via spawned tasks
+            let mut members = HashMap::with_capacity(ids.len());
+            let mut handles = Vec::new();
+            for (mid, revision) in ids {
+                match known.remove(&mid) {
+                    Some(cached) if cached.0 == revision => {
+                        members.insert(mid, cached);
+                    }
+                    _ => {
+                        let client = client.clone();
+                        let nwid = nwid.to_string();
+                        let limit = limit.clone();
+                        handles.push(tokio::spawn(async move {
+                            let _permit = limit.acquire_owned().await;
+                            let member = client.get_controller_member(&nwid, &mid).await;
+                            (mid, revision, member)
+                        }));
+                    }
+                }
+            }
+            if !handles.is_empty() {
+                debug!("Fetching {} changed member(s) of {}", handles.len(), nwid);
+            }
+
+            // A member that fails to load is left out, and fetched again next poll
+            let mut failed = HashSet::new();
             for handle in handles {
-                if let Ok(Ok(m)) = handle.await {
-                    members.push(m);
+                match handle.await {
+                    Ok((mid, revision, Ok(m))) => {
+                        members.insert(mid, (revision, m));
+                    }
+                    Ok((mid, _, Err(e))) => {
+                        debug!("Failed to fetch member {} of {}: {}", mid, nwid, e);
+                        failed.insert(mid);
+                    }
+                    Err(_) => {}
                 }
             }
-            // Sort by ID for stable PartialEq comparison between polls
-            members.sort_by(|a, b| a.display_id().cmp(b.display_id()));
-            members
+            Ok((members, failed))
+        }
+        Err(e) => {
+            debug!("Failed to fetch members of {}: {}", nwid, e);
+            Err(e)
         }
-        Err(_) => vec![],
     };
 
     (nwid.to_string(), nw_result, members)
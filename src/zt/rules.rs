@@ -0,0 +1,1444 @@
+//! ZeroTier flow rules DSL compiler.
+//!
+//! A port of ZeroTier's `rule-compiler.js` (also shipped in `static/` for the
+//! live preview in the editor). The server compiles `rules_source` itself so
+//! the compiled rules pushed to the controller always match the stored source.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use serde_json::{json, Map, Value};
+
+/// Names for bits in characteristics -- 0==LSB, 63==MSB
+const CHARACTERISTIC_BITS: &[(&str, i64)] = &[
+    ("inbound", 63),
+    ("multicast", 62),
+    ("broadcast", 61),
+    ("ipauth", 60),
+    ("macauth", 59),
+    ("tcp_fin", 0),
+    ("tcp_syn", 1),
+    ("tcp_rst", 2),
+    ("tcp_psh", 3),
+    ("tcp_ack", 4),
+    ("tcp_urg", 5),
+    ("tcp_ece", 6),
+    ("tcp_cwr", 7),
+    ("tcp_ns", 8),
+    ("tcp_rs2", 9),
+    ("tcp_rs1", 10),
+    ("tcp_rs0", 11),
+];
+
+/// Shorthand names for common ethernet types
+const ETHERTYPES: &[(&str, i64)] = &[
+    ("ipv4", 0x0800),
+    ("arp", 0x0806),
+    ("wol", 0x0842),
+    ("rarp", 0x8035),
+    ("ipv6", 0x86dd),
+    ("atalk", 0x809b),
+    ("aarp", 0x80f3),
+    ("ipx_a", 0x8137),
+    ("ipx_b", 0x8138),
+];
+
+/// Shorthand names for common IP protocols
+const IP_PROTOCOLS: &[(&str, i64)] = &[
+    ("icmp", 0x01),
+    ("icmp4", 0x01),
+    ("icmpv4", 0x01),
+    ("igmp", 0x02),
+    ("ipip", 0x04),
+    ("tcp", 0x06),
+    ("egp", 0x08),
+    ("igp", 0x09),
+    ("udp", 0x11),
+    ("rdp", 0x1b),
+    ("esp", 0x32),
+    ("ah", 0x33),
+    ("icmp6", 0x3a),
+    ("icmpv6", 0x3a),
+    ("l2tp", 0x73),
+    ("sctp", 0x84),
+    ("udplite", 0x88),
+];
+
+/// Keywords that open new blocks that must be terminated by a semicolon
+const OPEN_BLOCK_KEYWORDS: &[&str] = &[
+    "macro", "tag", "cap", "drop", "accept", "tee", "watch", "redirect", "break", "priority",
+];
+
+/// Reserved words that can't be used as tag, capability, or rule set names
+const RESERVED_WORDS: &[&str] = &[
+    "macro", "tag", "cap", "default", "drop", "accept", "tee", "watch", "redirect", "break",
+    "priority", "ztsrc", "ztdest", "vlan", "vlanpcp", "vlandei", "ethertype", "macsrc", "macdest",
+    "ipsrc", "ipdest", "iptos", "ipprotocol", "icmp", "sport", "dport", "chr", "framesize",
+    "random", "tand", "tor", "txor", "tdiff", "teq", "tseq", "treq", "type", "enum", "class",
+    "define", "import", "include", "log", "not", "xor", "or", "and", "set", "var", "let",
+];
+
+/// Maps a DSL keyword to its ZeroTier API rule type
+fn keyword_to_api(keyword: &str) -> &'static str {
+    match keyword {
+        "drop" => "ACTION_DROP",
+        "accept" => "ACTION_ACCEPT",
+        "tee" => "ACTION_TEE",
+        "watch" => "ACTION_WATCH",
+        "redirect" => "ACTION_REDIRECT",
+        "break" => "ACTION_BREAK",
+        "priority" => "ACTION_PRIORITY",
+        "ztsrc" => "MATCH_SOURCE_ZEROTIER_ADDRESS",
+        "ztdest" => "MATCH_DEST_ZEROTIER_ADDRESS",
+        "vlan" => "MATCH_VLAN_ID",
+        "vlanpcp" => "MATCH_VLAN_PCP",
+        "vlandei" => "MATCH_VLAN_DEI",
+        "ethertype" => "MATCH_ETHERTYPE",
+        "macsrc" => "MATCH_MAC_SOURCE",
+        "macdest" => "MATCH_MAC_DEST",
+        "iptos" => "MATCH_IP_TOS",
+        "ipprotocol" => "MATCH_IP_PROTOCOL",
+        "icmp" => "MATCH_ICMP",
+        "sport" => "MATCH_IP_SOURCE_PORT_RANGE",
+        "dport" => "MATCH_IP_DEST_PORT_RANGE",
+        "chr" => "MATCH_CHARACTERISTICS",
+        "framesize" => "MATCH_FRAME_SIZE_RANGE",
+        "random" => "MATCH_RANDOM",
+        "tand" => "MATCH_TAGS_BITWISE_AND",
+        "tor" => "MATCH_TAGS_BITWISE_OR",
+        "txor" => "MATCH_TAGS_BITWISE_XOR",
+        "tdiff" => "MATCH_TAGS_DIFFERENCE",
+        "teq" => "MATCH_TAGS_EQUAL",
+        "tseq" => "MATCH_TAG_SENDER",
+        "treq" => "MATCH_TAG_RECEIVER",
+        _ => "",
+    }
+}
+
+/// Number of args for each match
+fn match_arg_count(keyword: &str) -> Option<usize> {
+    match keyword {
+        "ztsrc" | "ztdest" | "vlan" | "vlanpcp" | "vlandei" | "ethertype" | "macsrc"
+        | "macdest" | "ipsrc" | "ipdest" | "ipprotocol" | "sport" | "dport" | "chr"
+        | "framesize" | "random" => Some(1),
+        "iptos" | "icmp" | "tand" | "tor" | "txor" | "tdiff" | "teq" | "tseq" | "treq" => {
+            Some(2)
+        }
+        _ => None,
+    }
+}
+
+fn lookup(table: &[(&str, i64)], key: &str) -> Option<i64> {
+    table.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+}
+
+fn is_reserved(word: &str) -> bool {
+    RESERVED_WORDS.contains(&word)
+}
+
+// ---- Compiler output ----
+
+/// A compile error with the (line, column) of the offending token
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CompileError {
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl CompileError {
+    fn new(pos: (usize, usize), message: &str) -> Self {
+        Self {
+            line: pos.0,
+            col: pos.1,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Error at line {}, col {}: {}", self.line, self.col, self.message)
+    }
+}
+
+/// Compiled rules, capabilities, and tags ready to send to the controller
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CompiledRules {
+    pub rules: Vec<Value>,
+    #[serde(default)]
+    pub capabilities: Vec<Value>,
+    #[serde(default)]
+    pub tags: Vec<Value>,
+}
+
+// ---- Parse tree ----
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    col: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Tok(Token),
+    Block(Vec<Node>),
+}
+
+impl Node {
+    fn text(&self) -> Option<&str> {
+        match self {
+            Node::Tok(t) => Some(&t.text),
+            Node::Block(_) => None,
+        }
+    }
+
+    fn pos(&self) -> (usize, usize) {
+        match self {
+            Node::Tok(t) => (t.line, t.col),
+            Node::Block(children) => children.first().map(|c| c.pos()).unwrap_or((0, 0)),
+        }
+    }
+
+    fn as_block(&self) -> Option<&[Node]> {
+        match self {
+            Node::Block(children) if !children.is_empty() => Some(children),
+            _ => None,
+        }
+    }
+}
+
+struct Macro {
+    /// Parameter name -> positional index
+    params: Vec<(String, usize)>,
+    rules: Vec<Node>,
+}
+
+struct Tag {
+    name: String,
+    id: i64,
+    default: Option<i64>,
+    enums: Vec<(String, i64)>,
+    flags: Vec<(String, i64)>,
+}
+
+struct Cap {
+    name: String,
+    id: i64,
+    default: bool,
+    rules: Vec<Node>,
+}
+
+struct Context<'a> {
+    macros: &'a HashMap<String, Macro>,
+    tags: &'a [Tag],
+}
+
+// ---- Helpers ----
+
+/// Checks whether something is a valid capability, tag, or macro name
+fn is_valid_name(n: &str) -> bool {
+    match n.chars().next() {
+        None => false,
+        Some(c) if c.is_ascii_digit() => false,
+        Some(_) => n.chars().all(|c| c == '_' || c.is_alphanumeric()),
+    }
+}
+
+/// Parse a leading integer the way JavaScript's parseInt does, ignoring trailing garbage
+fn parse_int_prefix(s: &str, radix: u32) -> Option<i64> {
+    let (neg, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let end = digits
+        .char_indices()
+        .find(|(_, c)| !c.is_digit(radix))
+        .map(|(i, _)| i)
+        .unwrap_or(digits.len());
+    if end == 0 {
+        return None;
+    }
+    let value = i64::from_str_radix(&digits[..end], radix).unwrap_or(i64::MAX);
+    Some(if neg { -value } else { value })
+}
+
+/// Parse a decimal or 0x-prefixed hex number, returning -1 if invalid
+fn parse_num(n: &str) -> i64 {
+    let n = n.to_lowercase();
+    let parsed = match n.strip_prefix("0x") {
+        Some(hex) if n.len() > 2 => parse_int_prefix(hex, 16),
+        _ => parse_int_prefix(&n, 10),
+    };
+    parsed.unwrap_or(-1)
+}
+
+/// Parse a leading float the way JavaScript's parseFloat does
+fn parse_float_prefix(s: &str) -> f64 {
+    (1..=s.len())
+        .rev()
+        .filter(|&i| s.is_char_boundary(i))
+        .find_map(|i| s[..i].parse::<f64>().ok())
+        .filter(|f| f.is_finite())
+        .unwrap_or(0.0)
+}
+
+fn clean_mac(m: &str) -> String {
+    let mut out = String::new();
+    let mut charcount = 0;
+    for c in m.to_lowercase().chars() {
+        if out.len() >= 17 {
+            break;
+        }
+        if c.is_ascii_hexdigit() {
+            out.push(c);
+            charcount += 1;
+            if out.len() != 17 && charcount >= 2 {
+                out.push(':');
+                charcount = 0;
+            }
+        }
+    }
+    out
+}
+
+fn clean_hex(m: &str) -> String {
+    m.to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .collect()
+}
+
+/// Parse "a-b" or "a" into an inclusive range, validating both ends against `max`
+fn parse_range(arg: &str, max: i64) -> Option<(i64, i64)> {
+    let (from, to) = if arg.find('-').map(|i| i > 0).unwrap_or(false) {
+        let parts: Vec<&str> = arg.split('-').collect();
+        if parts.len() != 2 {
+            return None;
+        }
+        (parse_num(parts[0]), parse_num(parts[1]))
+    } else {
+        let n = parse_num(arg);
+        (n, n)
+    };
+    if from < 0 || from > max || to < 0 || to > max || to < from {
+        return None;
+    }
+    Some((from, to))
+}
+
+/// Split "name(a,b)" into ("name", ["a", "b"])
+fn split_params(name: &str) -> (&str, Vec<String>) {
+    match name.find('(') {
+        Some(idx) if idx > 0 => {
+            let params = name[idx + 1..]
+                .split([',', ')'])
+                .filter(|p| !p.is_empty())
+                .map(str::to_string)
+                .collect();
+            (&name[..idx], params)
+        }
+        _ => (name, Vec::new()),
+    }
+}
+
+fn match_rule(kind: &str, not: bool, or: bool) -> Map<String, Value> {
+    let mut r = Map::new();
+    r.insert("type".to_string(), json!(kind));
+    r.insert("not".to_string(), json!(not));
+    r.insert("or".to_string(), json!(or));
+    r
+}
+
+// ---- Pass 1: tokenize into a tree of blocks ----
+
+fn parse_tree(src: &str) -> Vec<Node> {
+    fn push_block(stack: &mut Vec<Vec<Node>>) {
+        let block = stack.pop().unwrap_or_default();
+        if let Some(parent) = stack.last_mut() {
+            parent.push(Node::Block(block));
+        }
+    }
+
+    let mut stack: Vec<Vec<Node>> = vec![Vec::new()];
+    let mut curr = Token {
+        text: String::new(),
+        line: 0,
+        col: 0,
+    };
+    let mut skip_rest_of_line = false;
+    let mut line_no = 1;
+    let mut line_idx = 0;
+
+    for ch in src.chars() {
+        if skip_rest_of_line {
+            if ch == '\n' {
+                skip_rest_of_line = false;
+                line_no += 1;
+                line_idx = 0;
+            }
+        } else {
+            match ch {
+                '\n' | '\r' | '\t' | ' ' => {
+                    if ch == '\n' {
+                        line_no += 1;
+                        line_idx = 0;
+                    }
+                    if !curr.text.is_empty() {
+                        let end_of_block = curr.text.ends_with(';');
+                        if end_of_block {
+                            curr.text.pop();
+                        }
+                        let is_open = OPEN_BLOCK_KEYWORDS.contains(&curr.text.as_str());
+                        if !curr.text.is_empty() {
+                            let tok = std::mem::replace(
+                                &mut curr,
+                                Token {
+                                    text: String::new(),
+                                    line: 0,
+                                    col: 0,
+                                },
+                            );
+                            if let Some(top) = stack.last_mut() {
+                                top.push(Node::Tok(tok));
+                            }
+                        }
+                        let top_nonempty = stack.last().map(|b| !b.is_empty()).unwrap_or(false);
+                        if end_of_block && stack.len() > 1 && top_nonempty {
+                            push_block(&mut stack);
+                        } else if is_open {
+                            stack.push(Vec::new());
+                        }
+                        curr.text.clear();
+                    }
+                }
+                _ => {
+                    if curr.text.is_empty() {
+                        if ch == '#' {
+                            skip_rest_of_line = true;
+                            line_idx += 1;
+                            continue;
+                        }
+                        curr.line = line_no;
+                        curr.col = line_idx;
+                    }
+                    curr.text.push(ch);
+                }
+            }
+        }
+        line_idx += 1;
+    }
+
+    if curr.text.ends_with(';') {
+        curr.text.pop();
+    }
+    if !curr.text.is_empty() {
+        if let Some(top) = stack.last_mut() {
+            top.push(Node::Tok(curr));
+        }
+    }
+    while stack.len() > 1 && stack.last().map(|b| !b.is_empty()).unwrap_or(false) {
+        push_block(&mut stack);
+    }
+    stack.swap_remove(0)
+}
+
+// ---- Pass 3: render low-level rules ----
+
+fn render_matches(
+    mtree: &[Node],
+    rules: &mut Vec<Value>,
+    ctx: &Context,
+    params: &HashMap<String, String>,
+) -> Result<(), CompileError> {
+    let mut not = false;
+    let mut or = false;
+    let mut k = 0;
+    while k < mtree.len() {
+        let keyword = mtree[k].text().map(str::to_lowercase).unwrap_or_default();
+        if keyword.is_empty() || keyword == "and" {
+            // AND is the default
+            k += 1;
+            continue;
+        } else if keyword == "not" {
+            not = true;
+            k += 1;
+            continue;
+        } else if keyword == "or" {
+            or = true;
+            k += 1;
+            continue;
+        }
+
+        let arg_count = match match_arg_count(&keyword) {
+            Some(n) => n,
+            None => {
+                return Err(CompileError::new(
+                    mtree[k].pos(),
+                    &format!("Unrecognized match type \"{}\".", keyword),
+                ))
+            }
+        };
+
+        // Each argument is (value, position)
+        let mut args: Vec<(String, (usize, usize))> = Vec::with_capacity(arg_count);
+        for _ in 0..arg_count {
+            k += 1;
+            if k >= mtree.len() {
+                return Err(CompileError::new(
+                    mtree[k - 1].pos(),
+                    "Missing argument(s) to match.",
+                ));
+            }
+            let arg = match mtree[k].text() {
+                Some(a) if !a.is_empty() && !is_reserved(a) => a,
+                _ => {
+                    return Err(CompileError::new(
+                        mtree[k - 1].pos(),
+                        "Missing argument(s) to match (invalid argument or argument is reserved word).",
+                    ))
+                }
+            };
+            if arg.starts_with('$') {
+                match params.get(arg) {
+                    Some(v) => args.push((v.clone(), mtree[k].pos())),
+                    None => {
+                        return Err(CompileError::new(mtree[k].pos(), "Undefined variable name."))
+                    }
+                }
+            } else {
+                args.push((arg.to_string(), mtree[k].pos()));
+            }
+        }
+
+        let api_type = keyword_to_api(&keyword);
+        let rule = match keyword.as_str() {
+            "ztsrc" | "ztdest" => {
+                let zt = clean_hex(&args[0].0);
+                if zt.len() != 10 {
+                    return Err(CompileError::new(args[0].1, "Invalid ZeroTier address."));
+                }
+                let mut r = match_rule(api_type, not, or);
+                r.insert("zt".to_string(), json!(zt));
+                r
+            }
+
+            "vlan" | "vlanpcp" | "vlandei" | "ethertype" | "ipprotocol" => {
+                let named = match keyword.as_str() {
+                    "ethertype" => lookup(ETHERTYPES, &args[0].0),
+                    "ipprotocol" => lookup(IP_PROTOCOLS, &args[0].0),
+                    _ => None,
+                };
+                let num = named.unwrap_or_else(|| parse_num(&args[0].0));
+                if !(0..=0xffff_ffff).contains(&num) {
+                    return Err(CompileError::new(args[0].1, "Invalid numeric value."));
+                }
+                let field = match keyword.as_str() {
+                    "vlan" => "vlanId",
+                    "vlanpcp" => "vlanPcp",
+                    "vlandei" => "vlanDei",
+                    "ethertype" => "etherType",
+                    _ => "ipProtocol",
+                };
+                let mut r = match_rule(api_type, not, or);
+                r.insert(field.to_string(), json!(num));
+                r
+            }
+
+            "random" => {
+                let num = parse_float_prefix(&args[0].0).clamp(0.0, 1.0);
+                let mut r = match_rule(api_type, not, or);
+                r.insert(
+                    "probability".to_string(),
+                    json!((4294967295.0 * num).floor() as u64),
+                );
+                r
+            }
+
+            "macsrc" | "macdest" => {
+                let mac = clean_mac(&args[0].0);
+                if mac.len() != 17 {
+                    return Err(CompileError::new(args[0].1, "Invalid MAC address."));
+                }
+                let mut r = match_rule(api_type, not, or);
+                r.insert("mac".to_string(), json!(mac));
+                r
+            }
+
+            "ipsrc" | "ipdest" => {
+                let ip = &args[0].0;
+                let slash = match ip.find('/') {
+                    Some(i) if i > 0 => i,
+                    _ => {
+                        return Err(CompileError::new(
+                            args[0].1,
+                            "Missing /bits netmask length designation in IP.",
+                        ))
+                    }
+                };
+                let ip_only = &ip[..slash];
+                let kind = if ip_only.parse::<Ipv6Addr>().is_ok() {
+                    if keyword == "ipsrc" {
+                        "MATCH_IPV6_SOURCE"
+                    } else {
+                        "MATCH_IPV6_DEST"
+                    }
+                } else if ip_only.parse::<Ipv4Addr>().is_ok() {
+                    if keyword == "ipsrc" {
+                        "MATCH_IPV4_SOURCE"
+                    } else {
+                        "MATCH_IPV4_DEST"
+                    }
+                } else {
+                    return Err(CompileError::new(
+                        args[0].1,
+                        "Invalid IP address (not valid IPv4 or IPv6).",
+                    ));
+                };
+                let mut r = match_rule(kind, not, or);
+                r.insert("ip".to_string(), json!(ip));
+                r
+            }
+
+            "icmp" => {
+                let icmp_type = parse_num(&args[0].0);
+                if !(0..=0xff).contains(&icmp_type) {
+                    return Err(CompileError::new(args[0].1, "Missing or invalid ICMP type."));
+                }
+                // -1 is okay, it indicates don't match code
+                let icmp_code = parse_num(&args[1].0);
+                if icmp_code > 0xff {
+                    return Err(CompileError::new(
+                        args[1].1,
+                        "Invalid ICMP code (use -1 for none).",
+                    ));
+                }
+                let mut r = match_rule("MATCH_ICMP", not, or);
+                r.insert("icmpType".to_string(), json!(icmp_type));
+                r.insert(
+                    "icmpCode".to_string(),
+                    if icmp_code < 0 { Value::Null } else { json!(icmp_code) },
+                );
+                r
+            }
+
+            "sport" | "dport" | "framesize" => {
+                let (start, end) = match parse_range(&args[0].0, 0xffff) {
+                    Some(r) => r,
+                    None => return Err(CompileError::new(args[0].1, "Invalid numeric range.")),
+                };
+                let mut r = match_rule(api_type, not, or);
+                r.insert("start".to_string(), json!(start));
+                r.insert("end".to_string(), json!(end));
+                r
+            }
+
+            "iptos" => {
+                let mask = parse_num(&args[0].0);
+                if !(0..=0xff).contains(&mask) {
+                    return Err(CompileError::new(args[0].1, "Invalid mask."));
+                }
+                let (start, end) = match parse_range(&args[1].0, 0xff) {
+                    Some(r) => r,
+                    None => return Err(CompileError::new(args[1].1, "Invalid value range.")),
+                };
+                let mut r = match_rule("MATCH_IP_TOS", not, or);
+                r.insert("mask".to_string(), json!(mask));
+                r.insert("start".to_string(), json!(start));
+                r.insert("end".to_string(), json!(end));
+                r
+            }
+
+            "chr" => {
+                let mut mask: u64 = 0;
+                for name in args[0].0.split(',').filter(|b| !b.is_empty()) {
+                    let bit = lookup(CHARACTERISTIC_BITS, name).unwrap_or_else(|| parse_num(name));
+                    if !(0..=63).contains(&bit) {
+                        return Err(CompileError::new(
+                            args[0].1,
+                            "Invalid bit index (range 0-63) or unrecognized name.",
+                        ));
+                    }
+                    mask |= 1u64 << bit;
+                }
+                let mut r = match_rule("MATCH_CHARACTERISTICS", not, or);
+                r.insert("mask".to_string(), json!(format!("{:016x}", mask)));
+                r
+            }
+
+            // tand | tor | txor | tdiff | teq | tseq | treq
+            _ => {
+                let (tag_id, tag_value) = match ctx.tags.iter().find(|t| t.name == args[0].0) {
+                    Some(tag) => {
+                        let v = &args[1].0;
+                        let value = lookup_owned(&tag.flags, v)
+                            .or_else(|| lookup_owned(&tag.enums, v))
+                            .unwrap_or_else(|| parse_num(v));
+                        (tag.id, value)
+                    }
+                    None => (parse_num(&args[0].0), parse_num(&args[1].0)),
+                };
+                if !(0..=0xffff_ffff).contains(&tag_id) {
+                    return Err(CompileError::new(
+                        args[0].1,
+                        "Undefined tag name and invalid tag value.",
+                    ));
+                }
+                if !(0..=0xffff_ffff).contains(&tag_value) {
+                    return Err(CompileError::new(
+                        args[1].1,
+                        "Invalid tag value or unrecognized flag/enum name.",
+                    ));
+                }
+                let mut r = match_rule(api_type, not, or);
+                r.insert("id".to_string(), json!(tag_id));
+                r.insert("value".to_string(), json!(tag_value));
+                r
+            }
+        };
+        rules.push(Value::Object(rule));
+
+        not = false;
+        or = false;
+        k += 1;
+    }
+    Ok(())
+}
+
+fn lookup_owned(table: &[(String, i64)], key: &str) -> Option<i64> {
+    table.iter().find(|(k, _)| k == key).map(|(_, v)| *v)
+}
+
+fn render_actions(
+    rtree: &[Node],
+    rules: &mut Vec<Value>,
+    ctx: &Context,
+    params: &HashMap<String, String>,
+) -> Result<(), CompileError> {
+    let mut k = 0;
+    while k < rtree.len() {
+        let action = rtree[k].text().map(str::to_lowercase).unwrap_or_default();
+        let next_block = rtree.get(k + 1).and_then(Node::as_block);
+
+        match action.as_str() {
+            "" => {}
+
+            "include" => {
+                if k + 1 >= rtree.len() {
+                    return Err(CompileError::new(
+                        rtree[k].pos(),
+                        "Include directive is missing a macro name.",
+                    ));
+                }
+                k += 1;
+                let (macro_name, args) = split_params(rtree[k].text().unwrap_or(""));
+                let m = match ctx.macros.get(macro_name) {
+                    Some(m) => m,
+                    None => return Err(CompileError::new(rtree[k].pos(), "Macro name not found.")),
+                };
+                let mut macro_params = HashMap::new();
+                for (name, idx) in &m.params {
+                    match args.get(*idx) {
+                        Some(v) => {
+                            macro_params.insert(name.clone(), v.clone());
+                        }
+                        None => {
+                            return Err(CompileError::new(
+                                rtree[k].pos(),
+                                "Missing one or more required macro parameter.",
+                            ))
+                        }
+                    }
+                }
+                render_actions(&m.rules, rules, ctx, &macro_params)?;
+            }
+
+            "drop" | "accept" | "break" => {
+                // actions without arguments
+                if let Some(mtree) = next_block {
+                    k += 1;
+                    render_matches(mtree, rules, ctx, params)?;
+                }
+                rules.push(json!({ "type": keyword_to_api(&action) }));
+            }
+
+            "tee" | "watch" => {
+                // actions with arguments (max length, ZeroTier address)
+                let mtree = match next_block {
+                    Some(m) => m,
+                    None => {
+                        return Err(CompileError::new(
+                            rtree[k].pos(),
+                            "The tee and watch actions require two paremters (max length or 0 for all, target).",
+                        ))
+                    }
+                };
+                k += 1;
+                let max_length = parse_num(mtree[0].text().unwrap_or(""));
+                if !(-1..=0xffff).contains(&max_length) {
+                    return Err(CompileError::new(
+                        mtree[0].pos(),
+                        "Tee/watch max packet length to forward invalid or out of range.",
+                    ));
+                }
+                let target = match mtree.get(1).and_then(Node::text) {
+                    Some(t) if t.len() == 10 => t,
+                    _ => {
+                        return Err(CompileError::new(
+                            mtree.get(1).unwrap_or(&mtree[0]).pos(),
+                            "Missing or invalid ZeroTier address target for tee/watch.",
+                        ))
+                    }
+                };
+                render_matches(&mtree[2..], rules, ctx, params)?;
+                rules.push(json!({
+                    "type": keyword_to_api(&action),
+                    "address": target,
+                    "length": max_length,
+                }));
+            }
+
+            "redirect" => {
+                let mtree = match next_block {
+                    Some(m) => m,
+                    None => {
+                        return Err(CompileError::new(
+                            rtree[k].pos(),
+                            "The redirect action requires a target parameter.",
+                        ))
+                    }
+                };
+                k += 1;
+                let target = match mtree[0].text() {
+                    Some(t) if t.len() == 10 => t,
+                    _ => {
+                        return Err(CompileError::new(
+                            mtree[0].pos(),
+                            "Missing or invalid ZeroTier address target for redirect.",
+                        ))
+                    }
+                };
+                render_matches(&mtree[1..], rules, ctx, params)?;
+                rules.push(json!({
+                    "type": keyword_to_api(&action),
+                    "address": target,
+                }));
+            }
+
+            _ => {
+                return Err(CompileError::new(
+                    rtree[k].pos(),
+                    "Unrecognized action or directive in rule set.",
+                ))
+            }
+        }
+        k += 1;
+    }
+    Ok(())
+}
+
+// ---- Pass 2: definitions ----
+
+/// Returns the name token of a definition block (the first child of the block after `keyword`)
+fn definition_block<'a>(
+    parsed: &'a [Node],
+    i: usize,
+    missing: &str,
+) -> Result<(&'a [Node], &'a Token), CompileError> {
+    let block = parsed.get(i + 1).and_then(Node::as_block);
+    match block {
+        Some(b) => match &b[0] {
+            Node::Tok(name) => Ok((b, name)),
+            Node::Block(_) => Err(CompileError::new(parsed[i].pos(), missing)),
+        },
+        None => Err(CompileError::new(parsed[i].pos(), missing)),
+    }
+}
+
+fn token_pos(t: &Token) -> (usize, usize) {
+    (t.line, t.col)
+}
+
+fn parse_tag(block: &[Node], name: &Token, tags: &[Tag]) -> Result<Tag, CompileError> {
+    let tag_name = name.text.to_lowercase();
+    if !is_valid_name(&tag_name) {
+        return Err(CompileError::new(token_pos(name), "Invalid tag name."));
+    }
+    if is_reserved(&tag_name) {
+        return Err(CompileError::new(token_pos(name), "Tag name is a reserved word."));
+    }
+    if tags.iter().any(|t| t.name == tag_name) {
+        return Err(CompileError::new(token_pos(name), "Multiple definition of tag name."));
+    }
+
+    let mut flags: Vec<(String, i64)> = Vec::new();
+    let mut enums: Vec<(String, i64)> = Vec::new();
+    let mut id: i64 = -1;
+    let mut dfl: Option<String> = None;
+
+    let mut k = 1;
+    while k < block.len() {
+        let pos = block[k].pos();
+        let keyword = match block[k].text() {
+            Some(t) => t.to_lowercase(),
+            None => {
+                return Err(CompileError::new(pos, "Unrecognized keyword in tag definition."))
+            }
+        };
+        match keyword.as_str() {
+            "id" => {
+                if id >= 0 {
+                    return Err(CompileError::new(pos, "Duplicate tag id definition."));
+                }
+                if k + 1 >= block.len() {
+                    return Err(CompileError::new(pos, "Missing numeric value for ID."));
+                }
+                k += 1;
+                id = parse_num(block[k].text().unwrap_or(""));
+                if !(0..=0xffff_ffff).contains(&id) {
+                    return Err(CompileError::new(block[k].pos(), "Invalid or out of range tag ID."));
+                }
+            }
+            "default" => {
+                if dfl.is_some() {
+                    return Err(CompileError::new(pos, "Duplicate tag default directive."));
+                }
+                if k + 1 >= block.len() {
+                    return Err(CompileError::new(pos, "Missing value for default."));
+                }
+                k += 1;
+                dfl = Some(block[k].text().unwrap_or("").to_string());
+            }
+            "flag" => {
+                if k + 2 >= block.len() {
+                    return Err(CompileError::new(pos, "Missing tag flag name or bit index."));
+                }
+                k += 1;
+                let mut mask: i64 = 0;
+                for bit in block[k].text().unwrap_or("").split(',').filter(|b| !b.is_empty()) {
+                    let b = bit.to_lowercase();
+                    if let Some(existing) = lookup_owned(&flags, &b) {
+                        mask |= existing;
+                    } else {
+                        let idx = parse_num(&b);
+                        if !(0..=31).contains(&idx) {
+                            return Err(CompileError::new(
+                                block[k].pos(),
+                                "Bit index invalid, out of range, or references an undefined flag name.",
+                            ));
+                        }
+                        mask |= 1 << idx;
+                    }
+                }
+                k += 1;
+                let flag_name = block[k].text().unwrap_or("").to_lowercase();
+                if !is_valid_name(&flag_name) {
+                    return Err(CompileError::new(block[k].pos(), "Invalid or reserved flag name."));
+                }
+                if lookup_owned(&flags, &flag_name).is_some() {
+                    return Err(CompileError::new(
+                        block[k].pos(),
+                        "Duplicate flag name in tag definition.",
+                    ));
+                }
+                flags.push((flag_name, mask));
+            }
+            "enum" => {
+                if k + 2 >= block.len() {
+                    return Err(CompileError::new(pos, "Missing tag enum name or value."));
+                }
+                k += 1;
+                let value = parse_num(block[k].text().unwrap_or(""));
+                if !(0..=0xffff_ffff).contains(&value) {
+                    return Err(CompileError::new(
+                        block[k].pos(),
+                        "Tag enum value invalid or out of range.",
+                    ));
+                }
+                k += 1;
+                let enum_name = block[k].text().unwrap_or("").to_lowercase();
+                if !is_valid_name(&enum_name) {
+                    return Err(CompileError::new(
+                        block[k].pos(),
+                        "Invalid or reserved tag enum name.",
+                    ));
+                }
+                if lookup_owned(&enums, &enum_name).is_some() {
+                    return Err(CompileError::new(
+                        block[k].pos(),
+                        "Duplicate enum name in tag definition.",
+                    ));
+                }
+                enums.push((enum_name, value));
+            }
+            _ => {
+                return Err(CompileError::new(pos, "Unrecognized keyword in tag definition."))
+            }
+        }
+        k += 1;
+    }
+
+    if id < 0 {
+        return Err(CompileError::new(
+            token_pos(name),
+            "Tag definition is missing a numeric ID.",
+        ));
+    }
+
+    let default = dfl.map(|d| {
+        lookup_owned(&enums, &d)
+            .or_else(|| lookup_owned(&flags, &d))
+            .unwrap_or_else(|| {
+                let n = match d.to_lowercase().strip_prefix("0x") {
+                    Some(hex) => parse_int_prefix(hex, 16),
+                    None => parse_int_prefix(&d, 10),
+                };
+                n.unwrap_or(0).abs() & 0xffff_ffff
+            })
+    });
+
+    Ok(Tag {
+        name: tag_name,
+        id,
+        default,
+        enums,
+        flags,
+    })
+}
+
+fn parse_cap(block: &[Node], name: &Token, caps: &[Cap]) -> Result<Cap, CompileError> {
+    let cap_name = name.text.to_lowercase();
+    if !is_valid_name(&cap_name) {
+        return Err(CompileError::new(token_pos(name), "Invalid capability name."));
+    }
+    if is_reserved(&cap_name) {
+        return Err(CompileError::new(
+            token_pos(name),
+            "Capability name is a reserved word.",
+        ));
+    }
+    if caps.iter().any(|c| c.name == cap_name) {
+        return Err(CompileError::new(
+            token_pos(name),
+            "Multiple definition of capability name.",
+        ));
+    }
+
+    let mut rules = Vec::new();
+    let mut id: i64 = -1;
+    let mut dfl = false;
+    let mut k = 1;
+    while k < block.len() {
+        let directive = block[k].text().map(str::to_lowercase);
+        match directive.as_deref() {
+            Some("id") => {
+                if id >= 0 {
+                    return Err(CompileError::new(
+                        block[k].pos(),
+                        "Duplicate id directive in capability definition.",
+                    ));
+                }
+                if k + 1 >= block.len() {
+                    return Err(CompileError::new(block[k].pos(), "Missing value for ID."));
+                }
+                k += 1;
+                id = parse_num(block[k].text().unwrap_or(""));
+                if !(0..=0xffff_ffff).contains(&id) {
+                    return Err(CompileError::new(
+                        block[k - 1].pos(),
+                        "Invalid or out of range capability ID.",
+                    ));
+                }
+                if caps.iter().any(|c| c.id == id) {
+                    return Err(CompileError::new(block[k - 1].pos(), "Duplicate capability ID."));
+                }
+            }
+            Some("default") => dfl = true,
+            _ => rules.push(block[k].clone()),
+        }
+        k += 1;
+    }
+
+    if id < 0 {
+        return Err(CompileError::new(
+            token_pos(name),
+            "Capability definition is missing a numeric ID.",
+        ));
+    }
+
+    Ok(Cap {
+        name: cap_name,
+        id,
+        default: dfl,
+        rules,
+    })
+}
+
+/// Top-level definitions split out of a parse tree
+struct Definitions {
+    base_rule_tree: Vec<Node>,
+    macros: HashMap<String, Macro>,
+    tags: Vec<Tag>,
+    caps: Vec<Cap>,
+}
+
+/// First compile pass: pull macro, tag, and cap definitions out of the parse tree.
+fn split_definitions(parsed: &[Node]) -> Result<Definitions, CompileError> {
+    let mut base_rule_tree: Vec<Node> = Vec::new();
+    let mut macros: HashMap<String, Macro> = HashMap::new();
+    let mut tags: Vec<Tag> = Vec::new();
+    let mut caps: Vec<Cap> = Vec::new();
+
+    let mut i = 0;
+    while i < parsed.len() {
+        let keyword = parsed[i].text().map(str::to_lowercase);
+        match keyword.as_deref() {
+            Some("macro") => {
+                let (block, name) =
+                    definition_block(parsed, i, "Macro definition is missing name.")?;
+                i += 1;
+                let lowered = name.text.to_lowercase();
+                let (macro_name, param_names) = split_params(&lowered);
+                let params = param_names
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, p)| (p, idx))
+                    .collect();
+
+                if !is_valid_name(macro_name) {
+                    return Err(CompileError::new(token_pos(name), "Invalid macro name."));
+                }
+                if is_reserved(macro_name) {
+                    return Err(CompileError::new(
+                        token_pos(name),
+                        "Macro name is a reserved word.",
+                    ));
+                }
+                if macros.contains_key(macro_name) {
+                    return Err(CompileError::new(
+                        token_pos(name),
+                        "Multiple definition of macro name.",
+                    ));
+                }
+                macros.insert(
+                    macro_name.to_string(),
+                    Macro {
+                        params,
+                        rules: block[1..].to_vec(),
+                    },
+                );
+            }
+            Some("tag") => {
+                let (block, name) =
+                    definition_block(parsed, i, "Tag definition is missing name.")?;
+                i += 1;
+                let tag = parse_tag(block, name, &tags)?;
+                tags.push(tag);
+            }
+            Some("cap") => {
+                let (block, name) =
+                    definition_block(parsed, i, "Capability definition is missing name.")?;
+                i += 1;
+                let cap = parse_cap(block, name, &caps)?;
+                caps.push(cap);
+            }
+            _ => base_rule_tree.push(parsed[i].clone()),
+        }
+        i += 1;
+    }
+
+    Ok(Definitions {
+        base_rule_tree,
+        macros,
+        tags,
+        caps,
+    })
+}
+
+/// Compile flow rules DSL source into ZeroTier rules, capabilities, and tags.
+pub fn compile(src: &str) -> Result<CompiledRules, CompileError> {
+    let Definitions {
+        base_rule_tree,
+        macros,
+        tags,
+        caps,
+    } = split_definitions(&parse_tree(src))?;
+
+    let ctx = Context {
+        macros: &macros,
+        tags: &tags,
+    };
+    let no_params = HashMap::new();
+
+    let mut capabilities = Vec::with_capacity(caps.len());
+    for cap in &caps {
+        let mut r = Vec::new();
+        render_actions(&cap.rules, &mut r, &ctx, &no_params)?;
+        capabilities.push(json!({
+            "id": cap.id,
+            "default": cap.default,
+            "rules": r,
+        }));
+    }
+
+    let mut rules = Vec::new();
+    render_actions(&base_rule_tree, &mut rules, &ctx, &no_params)?;
+
+    let tags = tags
+        .iter()
+        .map(|t| {
+            let enums: Map<String, Value> =
+                t.enums.iter().map(|(k, v)| (k.clone(), json!(v))).collect();
+            let flags: Map<String, Value> =
+                t.flags.iter().map(|(k, v)| (k.clone(), json!(v))).collect();
+            json!({
+                "id": t.id,
+                "default": t.default,
+                "enums": enums,
+                "flags": flags,
+            })
+        })
+        .collect();
+
+    Ok(CompiledRules {
+        rules,
+        capabilities,
+        tags,
+    })
+}
+
+/// Resolve an ethertype name (e.g. "ipv4") or number
+pub fn ethertype_value(name: &str) -> Option<u16> {
+    let name = name.trim().to_lowercase();
+    let num = lookup(ETHERTYPES, &name).unwrap_or_else(|| parse_num(&name));
+    u16::try_from(num).ok()
+}
+
+/// Resolve an IP protocol name (e.g. "tcp") or number
+pub fn ip_protocol_value(name: &str) -> Option<u8> {
+    let name = name.trim().to_lowercase();
+    let num = lookup(IP_PROTOCOLS, &name).unwrap_or_else(|| parse_num(&name));
+    u8::try_from(num).ok()
+}
+
+/// Resolve a comma-separated list of characteristic names (e.g. "tcp_syn,tcp_ack") or bit indexes
+pub fn characteristics_mask(names: &str) -> Option<u64> {
+    let mut mask = 0u64;
+    for name in names.split(',').map(|n| n.trim().to_lowercase()).filter(|n| !n.is_empty()) {
+        let bit = lookup(CHARACTERISTIC_BITS, &name).unwrap_or_else(|| parse_num(&name));
+        if !(0..=63).contains(&bit) {
+            return None;
+        }
+        mask |= 1u64 << bit;
+    }
+    Some(mask)
+}
+
+/// DSL keyword for a ZeroTier API rule type
+pub fn api_to_keyword(api_type: &str) -> &'static str {
+    match api_type {
+        "MATCH_IPV4_SOURCE" | "MATCH_IPV6_SOURCE" => "ipsrc",
+        "MATCH_IPV4_DEST" | "MATCH_IPV6_DEST" => "ipdest",
+        _ => OPEN_BLOCK_KEYWORDS
+            .iter()
+            .chain(RESERVED_WORDS.iter())
+            .find(|k| keyword_to_api(k) == api_type)
+            .copied()
+            .unwrap_or("?"),
+    }
+}
+
+/// A tag declared in DSL source, with its enum values and flag masks
+#[derive(Debug, Clone)]
+pub struct DeclaredTag {
+    pub name: String,
+    pub id: u32,
+    pub enums: Vec<NamedValue>,
+    /// Flag names with their bit masks
+    pub flags: Vec<NamedValue>,
+}
+
+/// A capability declared in DSL source
+#[derive(Debug, Clone)]
+pub struct DeclaredCapability {
+    pub name: String,
+    pub id: u32,
+}
+
+/// Tag and capability names declared in DSL source, for assigning them to members
+#[derive(Debug, Clone, Default)]
+pub struct Declarations {
+    pub tags: Vec<DeclaredTag>,
+    pub capabilities: Vec<DeclaredCapability>,
+}
+
+/// Extract tag and capability declarations without rendering the rules.
+pub fn declarations(src: &str) -> Result<Declarations, CompileError> {
+    let defs = split_definitions(&parse_tree(src))?;
+    let named = |values: &[(String, i64)]| {
+        values
+            .iter()
+            .map(|(name, value)| NamedValue {
+                name: name.clone(),
+                value: *value as u32,
+            })
+            .collect()
+    };
+    Ok(Declarations {
+        tags: defs
+            .tags
+            .iter()
+            .map(|t| DeclaredTag {
+                name: t.name.clone(),
+                id: t.id as u32,
+                enums: named(&t.enums),
+                flags: named(&t.flags),
+            })
+            .collect(),
+        capabilities: defs
+            .caps
+            .iter()
+            .map(|c| DeclaredCapability {
+                name: c.name.clone(),
+                id: c.id as u32,
+            })
+            .collect(),
+    })
+}
+
+// ---- Tags & capabilities builder ----
+
+/// Marks the start of the source region generated from [`RuleDefinitions`]
+pub const DEFINITIONS_BEGIN: &str =
+    "# --- BEGIN tags & capabilities (generated by the builder; edits here are overwritten) ---";
+/// Marks the end of the generated region
+pub const DEFINITIONS_END: &str = "# --- END tags & capabilities ---";
+
+/// A named enum value or flag bit within a tag
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct NamedValue {
+    pub name: String,
+    pub value: u32,
+}
+
+/// Structured `tag` definition
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct TagDefinition {
+    pub name: String,
+    pub id: u32,
+    /// Enum/flag name or numeric value; empty for no default
+    #[serde(default)]
+    pub default: String,
+    #[serde(default)]
+    pub enums: Vec<NamedValue>,
+    /// Flag bit indexes (0-31)
+    #[serde(default)]
+    pub flags: Vec<NamedValue>,
+}
+
+/// Structured `cap` definition. `rules` holds the capability's action lines as DSL.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct CapabilityDefinition {
+    pub name: String,
+    pub id: u32,
+    #[serde(default)]
+    pub default: bool,
+    #[serde(default)]
+    pub rules: String,
+}
+
+/// Tags and capabilities edited in the visual builder for one network
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct RuleDefinitions {
+    #[serde(default)]
+    pub tags: Vec<TagDefinition>,
+    #[serde(default)]
+    pub capabilities: Vec<CapabilityDefinition>,
+}
+
+impl RuleDefinitions {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.capabilities.is_empty()
+    }
+
+    /// Render as DSL `tag` and `cap` blocks
+    pub fn to_dsl(&self) -> String {
+        let mut out = String::new();
+        for tag in &self.tags {
+            out.push_str(&format!("tag {}\n\tid {}\n", tag.name, tag.id));
+            for e in &tag.enums {
+                out.push_str(&format!("\tenum {} {}\n", e.value, e.name));
+            }
+            for f in &tag.flags {
+                out.push_str(&format!("\tflag {} {}\n", f.value, f.name));
+            }
+            if !tag.default.trim().is_empty() {
+                out.push_str(&format!("\tdefault {}\n", tag.default.trim()));
+            }
+            out.push_str(";\n\n");
+        }
+        for cap in &self.capabilities {
+            out.push_str(&format!("cap {}\n\tid {}\n", cap.name, cap.id));
+            if cap.default {
+                out.push_str("\tdefault\n");
+            }
+            for line in cap.rules.lines().filter(|l| !l.trim().is_empty()) {
+                out.push_str(&format!("\t{}\n", line.trim_end()));
+            }
+            out.push_str(";\n\n");
+        }
+        out
+    }
+
+    /// Check the definitions compile on their own, reporting errors by tag/cap name
+    pub fn validate(&self) -> Result<(), String> {
+        for tag in &self.tags {
+            if !is_valid_name(&tag.name.to_lowercase()) || is_reserved(&tag.name.to_lowercase()) {
+                return Err(format!("Invalid tag name \"{}\"", tag.name));
+            }
+            for f in &tag.flags {
+                if f.value > 31 {
+                    return Err(format!("Flag \"{}\" bit must be between 0 and 31", f.name));
+                }
+            }
+        }
+        for cap in &self.capabilities {
+            if !is_valid_name(&cap.name.to_lowercase()) || is_reserved(&cap.name.to_lowercase()) {
+                return Err(format!("Invalid capability name \"{}\"", cap.name));
+            }
+        }
+        compile(&self.to_dsl()).map(|_| ()).map_err(|e| e.message)
+    }
+
+    /// Replace the generated region in `source`, or prepend one if it isn't there yet.
+    /// Empty definitions remove the region.
+    pub fn merge_into(&self, source: &str) -> String {
+        let region = if self.is_empty() {
+            String::new()
+        } else {
+            format!("{}\n{}{}\n", DEFINITIONS_BEGIN, self.to_dsl(), DEFINITIONS_END)
+        };
+
+        if let Some(start) = source.find(DEFINITIONS_BEGIN) {
+            if let Some(end_rel) = source[start..].find(DEFINITIONS_END) {
+                let mut end = start + end_rel + DEFINITIONS_END.len();
+                // Swallow the line break after the end marker
+                if source[end..].starts_with("\r\n") {
+                    end += 2;
+                } else if source[end..].starts_with('\n') {
+                    end += 1;
+                }
+                return format!("{}{}{}", &source[..start], region, &source[end..]);
+            }
+        }
+
+        if region.is_empty() {
+            source.to_string()
+        } else {
+            format!("{}\n{}", region, source)
+        }
+    }
+}
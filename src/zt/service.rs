@@ -0,0 +1,245 @@
+//! The local zerotier-one service: its state, starting and restarting it, and its recent log,
+//! so routine maintenance and restores don't need a shell on the host. The platform's service
+//! manager is driven through its command-line tool: `systemctl` (systemd over D-Bus), `sc.exe`
+//! or `launchctl`. `ZT_SERVICE_NAME` overrides the service name.
+
+use std::time::Duration;
+
+use tokio::process::Command;
+
+use super::backend::SharedBackend;
+
+#[cfg(target_os = "windows")]
+const DEFAULT_SERVICE: &str = "ZeroTierOneService";
+#[cfg(target_os = "macos")]
+const DEFAULT_SERVICE: &str = "com.zerotier.one";
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const DEFAULT_SERVICE: &str = "zerotier-one";
+
+/// Longest a service manager command may take
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The name of ZeroTier's service
+pub fn service_name() -> String {
+    std::env::var("ZT_SERVICE_NAME")
+        .ok()
+        .filter(|s| !s.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_SERVICE.to_string())
+}
+
+/// Whether TierDrop can offer to restart ZeroTier; the demo's simulated controller has no service
+pub fn available() -> bool {
+    !crate::demo::enabled()
+}
+
+/// Run a service manager command, describing a failure with its output
+async fn run(program: &str, args: &[&str]) -> Result<String, String> {
+    let output = tokio::time::timeout(COMMAND_TIMEOUT, Command::new(program).args(args).kill_on_drop(true).output())
+        .await
+        .map_err(|_| format!("{} didn't finish within {} seconds", program, COMMAND_TIMEOUT.as_secs()))?
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    if output.status.success() {
+        return Ok(stdout);
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let detail = if stderr.trim().is_empty() { stdout.trim() } else { stderr.trim() };
+    Err(format!("{} failed ({}): {}", program, output.status, detail))
+}
+
+/// Lines of the service log shown
+pub const LOG_LINES: usize = 50;
+
+/// What the service manager reports about ZeroTier's service
+pub struct ServiceStatus {
+    /// The service manager's word for it, e.g. `active`, `failed` or `RUNNING`
+    pub state: String,
+    pub running: bool,
+    /// When it entered that state, as the service manager puts it
+    pub since: Option<String>,
+}
+
+impl ServiceStatus {
+    pub fn class(&self) -> &'static str {
+        match self.state.to_ascii_lowercase().as_str() {
+            _ if self.running => "status-ok",
+            "failed" => "status-error",
+            "activating" | "deactivating" | "reloading" | "start_pending" | "stop_pending" => "status-pending",
+            _ => "status-unknown",
+        }
+    }
+}
+
+/// Restart ZeroTier's service, returning once the service manager has started it again
+pub async fn restart() -> Result<(), String> {
+    if !available() {
+        return Err("ZeroTier can't be restarted in demo mode".to_string());
+    }
+    let name = service_name();
+    tracing::info!("Restarting the ZeroTier service {}", name);
+    restart_service(&name).await
+}
+
+/// Start ZeroTier's service when it isn't running
+pub async fn start() -> Result<(), String> {
+    if !available() {
+        return Err("ZeroTier can't be started in demo mode".to_string());
+    }
+    let name = service_name();
+    tracing::info!("Starting the ZeroTier service {}", name);
+    start_service(&name).await
+}
+
+/// Whether ZeroTier's service is running
+pub async fn status() -> Result<ServiceStatus, String> {
+    if !available() {
+        return Err("The demo's simulated controller has no service".to_string());
+    }
+    service_status(&service_name()).await
+}
+
+/// The last `LOG_LINES` lines of the service log, or None where there is no log to read
+pub async fn log_tail() -> Result<Option<String>, String> {
+    if !available() {
+        return Ok(None);
+    }
+    service_log(&service_name()).await
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+async fn service_status(name: &str) -> Result<ServiceStatus, String> {
+    let output = run(
+        "systemctl",
+        &["show", name, "--property=LoadState,ActiveState,SubState,ActiveEnterTimestamp"],
+    )
+    .await?;
+    let property = |key: &str| {
+        output
+            .lines()
+            .find_map(|l| l.strip_prefix(key)?.strip_prefix('='))
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+    if property("LoadState").as_deref() == Some("not-found") {
+        return Err(format!("There is no {} service on this host", name));
+    }
+    let state = property("ActiveState").unwrap_or_else(|| "unknown".to_string());
+    Ok(ServiceStatus {
+        running: state == "active",
+        state: match property("SubState") {
+            Some(sub) if sub != state => format!("{} ({})", state, sub),
+            _ => state,
+        },
+        since: property("ActiveEnterTimestamp"),
+    })
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+async fn service_log(name: &str) -> Result<Option<String>, String> {
+    let lines = LOG_LINES.to_string();
+    run("journalctl", &["-u", name, "-n", &lines, "--no-pager", "-o", "short-iso"])
+        .await
+        .map(Some)
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+async fn start_service(name: &str) -> Result<(), String> {
+    run("systemctl", &["start", name]).await.map(|_| ())
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+async fn restart_service(name: &str) -> Result<(), String> {
+    run("systemctl", &["restart", name]).await.map(|_| ())
+}
+
+#[cfg(target_os = "macos")]
+async fn restart_service(name: &str) -> Result<(), String> {
+    let target = format!("system/{}", name);
+    run("launchctl", &["kickstart", "-k", &target]).await.map(|_| ())
+}
+
+#[cfg(target_os = "macos")]
+async fn start_service(name: &str) -> Result<(), String> {
+    let target = format!("system/{}", name);
+    run("launchctl", &["kickstart", &target]).await.map(|_| ())
+}
+
+#[cfg(target_os = "macos")]
+async fn service_status(name: &str) -> Result<ServiceStatus, String> {
+    let target = format!("system/{}", name);
+    let output = run("launchctl", &["print", &target])
+        .await
+        .map_err(|_| format!("launchd has no {} service loaded", name))?;
+    let state = output
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("state = "))
+        .unwrap_or("unknown")
+        .to_string();
+    Ok(ServiceStatus {
+        running: state == "running",
+        state,
+        since: None,
+    })
+}
+
+// ZeroTier writes no log of its own on macOS and Windows
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+async fn service_log(_name: &str) -> Result<Option<String>, String> {
+    Ok(None)
+}
+
+#[cfg(target_os = "windows")]
+async fn restart_service(name: &str) -> Result<(), String> {
+    // `sc stop` only asks the service to stop, and a stopped service fails with 1062
+    if let Err(e) = run("sc.exe", &["stop", name]).await {
+        if !e.contains("1062") {
+            return Err(e);
+        }
+    }
+    let mut waited = Duration::ZERO;
+    while !run("sc.exe", &["query", name]).await?.contains("STOPPED") {
+        if waited >= COMMAND_TIMEOUT {
+            return Err(format!("{} didn't stop within {} seconds", name, COMMAND_TIMEOUT.as_secs()));
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        waited += Duration::from_millis(500);
+    }
+    run("sc.exe", &["start", name]).await.map(|_| ())
+}
+
+#[cfg(target_os = "windows")]
+async fn start_service(name: &str) -> Result<(), String> {
+    run("sc.exe", &["start", name]).await.map(|_| ())
+}
+
+#[cfg(target_os = "windows")]
+async fn service_status(name: &str) -> Result<ServiceStatus, String> {
+    // "        STATE              : 4  RUNNING"
+    let output = run("sc.exe", &["query", name]).await?;
+    let state = output
+        .lines()
+        .find(|l| l.trim_start().starts_with("STATE"))
+        .and_then(|l| l.split_whitespace().nth(3))
+        .unwrap_or("UNKNOWN")
+        .to_string();
+    Ok(ServiceStatus {
+        running: state == "RUNNING",
+        state,
+        since: None,
+    })
+}
+
+/// Wait until ZeroTier answers `client` again after a restart
+pub async fn wait_until_answering(client: &SharedBackend, timeout: Duration) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if client.get_status().await.is_ok_and(|s| s.address.is_some()) {
+            return true;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
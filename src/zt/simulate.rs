@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use serde_json::Value;
+
+use super::rules;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+/// Characteristic bit set while the receiver evaluates a packet
+const CHR_INBOUND: u64 = 1 << 63;
+/// Sender's IP and MAC are ones the controller assigned (always true for simulated members)
+const CHR_IPAUTH: u64 = 1 << 60;
+const CHR_MACAUTH: u64 = 1 << 59;
+
+/// One endpoint of a simulated packet
+#[derive(Debug, Clone, Default)]
+pub struct Endpoint {
+    pub address: String,
+    pub mac: String,
+    pub ip: Option<IpAddr>,
+    /// Tag id -> value
+    pub tags: HashMap<u32, u32>,
+}
+
+/// A synthetic frame to run through the rules
+#[derive(Debug, Clone, Default)]
+pub struct Packet {
+    pub source: Endpoint,
+    pub dest: Endpoint,
+    pub ether_type: u16,
+    pub ip_protocol: Option<u8>,
+    pub source_port: Option<u16>,
+    pub dest_port: Option<u16>,
+    pub icmp_type: Option<u8>,
+    pub frame_size: u16,
+    /// TCP flag characteristics (bits 0-11)
+    pub tcp_flags: u64,
+}
+
+/// How a rule set ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Fell off the end of the rule set, or hit `break`
+    NoMatch,
+    Accept,
+    Drop,
+    Redirect(String),
+}
+
+/// Evaluation of one rule in a rule set
+#[derive(Debug, Clone)]
+pub struct TraceStep {
+    pub index: usize,
+    pub rule: String,
+    /// "match", "no match", "skipped", "taken" or "not taken"
+    pub result: &'static str,
+}
+
+impl TraceStep {
+    pub fn is_taken(&self) -> bool {
+        self.result == "taken"
+    }
+}
+
+/// Result of running one rule set
+#[derive(Debug, Clone)]
+pub struct RuleSetTrace {
+    pub name: String,
+    pub outcome: Outcome,
+    pub steps: Vec<TraceStep>,
+    pub notes: Vec<String>,
+}
+
+/// Result of one side (sender or receiver) filtering the packet
+#[derive(Debug, Clone)]
+pub struct SideResult {
+    pub title: &'static str,
+    pub accepted: bool,
+    /// Which rule decided the verdict
+    pub decided_by: String,
+    pub rule_sets: Vec<RuleSetTrace>,
+}
+
+/// Full simulation: the sender filters outbound, then the receiver filters inbound
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    pub outbound: SideResult,
+    pub inbound: Option<SideResult>,
+}
+
+impl Simulation {
+    pub fn delivered(&self) -> bool {
+        self.outbound.accepted && self.inbound.as_ref().is_some_and(|i| i.accepted)
+    }
+
+    pub fn sides(&self) -> Vec<&SideResult> {
+        std::iter::once(&self.outbound).chain(self.inbound.iter()).collect()
+    }
+}
+
+/// A capability held by the sender, as compiled
+pub struct Capability {
+    pub id: u32,
+    pub name: String,
+    pub rules: Vec<Value>,
+}
+
+/// ZeroTier's MAC for a member on a network
+pub fn member_mac(address: &str, nwid: &str) -> String {
+    let (Ok(addr), Ok(nwid)) = (u64::from_str_radix(address, 16), u64::from_str_radix(nwid, 16)) else {
+        return String::new();
+    };
+    let mut first = (((nwid >> 56) & 0xfe) | 0x02) as u8;
+    if first == 0x52 {
+        first = 0x32;
+    }
+    let mut m = ((first as u64) << 40) | (addr & 0xff_ffff_ffff);
+    m ^= ((nwid >> 8) & 0xff) << 32;
+    m ^= ((nwid >> 16) & 0xff) << 24;
+    m ^= ((nwid >> 24) & 0xff) << 16;
+    m ^= ((nwid >> 32) & 0xff) << 8;
+    m ^= (nwid >> 40) & 0xff;
+    (0..6)
+        .rev()
+        .map(|i| format!("{:02x}", (m >> (i * 8)) & 0xff))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Human readable DSL-ish form of a compiled rule
+pub fn describe(rule: &Value) -> String {
+    let kind = rule.get("type").and_then(Value::as_str).unwrap_or("");
+    let mut out = String::new();
+    if rule.get("or").and_then(Value::as_bool).unwrap_or(false) {
+        out.push_str("or ");
+    }
+    if rule.get("not").and_then(Value::as_bool).unwrap_or(false) {
+        out.push_str("not ");
+    }
+    out.push_str(rules::api_to_keyword(kind));
+
+    let field = |name: &str| rule.get(name).map(|v| match v {
+        Value::String(s) => s.clone(),
+        Value::Null => "-1".to_string(),
+        other => other.to_string(),
+    });
+    let args: Vec<String> = match kind {
+        "ACTION_TEE" | "ACTION_WATCH" => vec![field("length"), field("address")],
+        "ACTION_REDIRECT" => vec![field("address")],
+        "MATCH_ETHERTYPE" => vec![rule
+            .get("etherType")
+            .and_then(Value::as_u64)
+            .map(|e| format!("0x{:04x}", e))],
+        "MATCH_IP_PROTOCOL" => vec![field("ipProtocol")],
+        "MATCH_VLAN_ID" => vec![field("vlanId")],
+        "MATCH_VLAN_PCP" => vec![field("vlanPcp")],
+        "MATCH_VLAN_DEI" => vec![field("vlanDei")],
+        "MATCH_SOURCE_ZEROTIER_ADDRESS" | "MATCH_DEST_ZEROTIER_ADDRESS" => vec![field("zt")],
+        "MATCH_MAC_SOURCE" | "MATCH_MAC_DEST" => vec![field("mac")],
+        "MATCH_IPV4_SOURCE" | "MATCH_IPV4_DEST" | "MATCH_IPV6_SOURCE" | "MATCH_IPV6_DEST" => {
+            vec![field("ip")]
+        }
+        "MATCH_ICMP" => vec![field("icmpType"), field("icmpCode")],
+        "MATCH_IP_SOURCE_PORT_RANGE" | "MATCH_IP_DEST_PORT_RANGE" | "MATCH_FRAME_SIZE_RANGE" => {
+            match (field("start"), field("end")) {
+                (Some(s), Some(e)) if s == e => vec![Some(s)],
+                (Some(s), Some(e)) => vec![Some(format!("{}-{}", s, e))],
+                _ => Vec::new(),
+            }
+        }
+        "MATCH_IP_TOS" => vec![
+            field("mask"),
+            field("start").zip(field("end")).map(|(s, e)| format!("{}-{}", s, e)),
+        ],
+        "MATCH_CHARACTERISTICS" => vec![field("mask").map(|m| format!("0x{}", m))],
+        "MATCH_RANDOM" => vec![field("probability")],
+        k if k.starts_with("MATCH_TAG") => vec![field("id"), field("value")],
+        _ => Vec::new(),
+    }
+    .into_iter()
+    .flatten()
+    .collect();
+
+    for arg in args {
+        out.push(' ');
+        out.push_str(&arg);
+    }
+    out
+}
+
+/// Whether `ip` is in `cidr`, e.g. `10.0.0.0/24`
+pub fn in_cidr(ip: IpAddr, cidr: &str) -> bool {
+    let Some((net, bits)) = cidr.split_once('/') else {
+        return false;
+    };
+    let Ok(bits) = bits.parse::<u32>() else {
+        return false;
+    };
+    match (ip, net.parse::<IpAddr>()) {
+        (IpAddr::V4(ip), Ok(IpAddr::V4(net))) if bits <= 32 => {
+            let mask = u32::MAX.checked_shl(32 - bits).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), Ok(IpAddr::V6(net))) if bits <= 128 => {
+            let mask = u128::MAX.checked_shl(128 - bits).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+fn is_ip(packet: &Packet) -> bool {
+    packet.ether_type == ETHERTYPE_IPV4 || packet.ether_type == ETHERTYPE_IPV6
+}
+
+fn has_ports(packet: &Packet) -> bool {
+    // TCP, UDP, SCTP, UDP-Lite
+    is_ip(packet) && matches!(packet.ip_protocol, Some(0x06 | 0x11 | 0x84 | 0x88))
+}
+
+/// Evaluate a single match rule. Returns None for matches that can't be decided
+/// offline (random), which are assumed to match.
+fn evaluate_match(rule: &Value, packet: &Packet, inbound: bool) -> Option<bool> {
+    let kind = rule.get("type").and_then(Value::as_str).unwrap_or("");
+    let num = |name: &str| rule.get(name).and_then(Value::as_u64).unwrap_or(0);
+    let text = |name: &str| rule.get(name).and_then(Value::as_str).unwrap_or("");
+    let range = |v: u64| v >= num("start") && v <= num("end");
+    let tag = |ep: &Endpoint| ep.tags.get(&(num("id") as u32)).map(|v| *v as u64);
+    let tags = || tag(&packet.source).zip(tag(&packet.dest));
+
+    let matched = match kind {
+        "MATCH_SOURCE_ZEROTIER_ADDRESS" => text("zt") == packet.source.address,
+        "MATCH_DEST_ZEROTIER_ADDRESS" => text("zt") == packet.dest.address,
+        // Simulated frames are untagged
+        "MATCH_VLAN_ID" | "MATCH_VLAN_PCP" | "MATCH_VLAN_DEI" => {
+            let field = match kind {
+                "MATCH_VLAN_ID" => "vlanId",
+                "MATCH_VLAN_PCP" => "vlanPcp",
+                _ => "vlanDei",
+            };
+            num(field) == 0
+        }
+        "MATCH_MAC_SOURCE" => text("mac") == packet.source.mac,
+        "MATCH_MAC_DEST" => text("mac") == packet.dest.mac,
+        "MATCH_IPV4_SOURCE" | "MATCH_IPV6_SOURCE" => {
+            is_ip(packet) && packet.source.ip.is_some_and(|ip| in_cidr(ip, text("ip")))
+        }
+        "MATCH_IPV4_DEST" | "MATCH_IPV6_DEST" => {
+            is_ip(packet) && packet.dest.ip.is_some_and(|ip| in_cidr(ip, text("ip")))
+        }
+        // TOS is always 0 in a simulated frame
+        "MATCH_IP_TOS" => is_ip(packet) && range(0),
+        "MATCH_IP_PROTOCOL" => is_ip(packet) && packet.ip_protocol == Some(num("ipProtocol") as u8),
+        "MATCH_ETHERTYPE" => packet.ether_type as u64 == num("etherType"),
+        "MATCH_ICMP" => {
+            let icmp = match packet.ether_type {
+                ETHERTYPE_IPV4 => packet.ip_protocol == Some(0x01),
+                ETHERTYPE_IPV6 => packet.ip_protocol == Some(0x3a),
+                _ => false,
+            };
+            // Simulated ICMP messages carry code 0
+            let code_ok = rule.get("icmpCode").and_then(Value::as_u64).is_none_or(|c| c == 0);
+            icmp && packet.icmp_type == Some(num("icmpType") as u8) && code_ok
+        }
+        "MATCH_IP_SOURCE_PORT_RANGE" => {
+            has_ports(packet) && packet.source_port.is_some_and(|p| range(p as u64))
+        }
+        "MATCH_IP_DEST_PORT_RANGE" => {
+            has_ports(packet) && packet.dest_port.is_some_and(|p| range(p as u64))
+        }
+        "MATCH_CHARACTERISTICS" => {
+            let mask = u64::from_str_radix(text("mask"), 16).unwrap_or(0);
+            let mut chr = CHR_IPAUTH | CHR_MACAUTH;
+            if packet.ip_protocol == Some(0x06) && is_ip(packet) {
+                chr |= packet.tcp_flags;
+            }
+            if inbound {
+                chr |= CHR_INBOUND;
+            }
+            chr & mask != 0
+        }
+        "MATCH_FRAME_SIZE_RANGE" => range(packet.frame_size as u64),
+        "MATCH_RANDOM" => return None,
+        "MATCH_TAGS_DIFFERENCE" => tags().is_some_and(|(a, b)| a.abs_diff(b) <= num("value")),
+        "MATCH_TAGS_BITWISE_AND" => tags().is_some_and(|(a, b)| a & b == num("value")),
+        "MATCH_TAGS_BITWISE_OR" => tags().is_some_and(|(a, b)| a | b == num("value")),
+        "MATCH_TAGS_BITWISE_XOR" => tags().is_some_and(|(a, b)| a ^ b == num("value")),
+        "MATCH_TAGS_EQUAL" => tags().is_some_and(|(a, b)| a == num("value") && b == num("value")),
+        "MATCH_TAG_SENDER" => tag(&packet.source) == Some(num("value")),
+        "MATCH_TAG_RECEIVER" => tag(&packet.dest) == Some(num("value")),
+        _ => false,
+    };
+    Some(matched)
+}
+
+/// Run one rule set with ZeroTier's filter semantics: matches accumulate with AND/OR
+/// until an action, which fires if the set matched and then resets the set.
+pub fn run_rule_set(name: &str, rules: &[Value], packet: &Packet, inbound: bool) -> RuleSetTrace {
+    let mut steps = Vec::new();
+    let mut notes = Vec::new();
+    let mut set_matches = true;
+
+    for (index, rule) in rules.iter().enumerate() {
+        let kind = rule.get("type").and_then(Value::as_str).unwrap_or("");
+        let description = describe(rule);
+
+        if kind.starts_with("ACTION_") {
+            if !set_matches {
+                steps.push(TraceStep { index, rule: description, result: "not taken" });
+                set_matches = true;
+                continue;
+            }
+            steps.push(TraceStep { index, rule: description, result: "taken" });
+            let address = rule.get("address").and_then(Value::as_str).unwrap_or("").to_string();
+            let outcome = match kind {
+                "ACTION_ACCEPT" => Outcome::Accept,
+                "ACTION_DROP" => Outcome::Drop,
+                "ACTION_REDIRECT" => Outcome::Redirect(address),
+                "ACTION_BREAK" => Outcome::NoMatch,
+                "ACTION_TEE" => {
+                    notes.push(format!("Copied to {} (tee)", address));
+                    continue;
+                }
+                "ACTION_WATCH" => {
+                    notes.push(format!("Copied to {} (watch)", address));
+                    continue;
+                }
+                _ => continue,
+            };
+            return RuleSetTrace { name: name.to_string(), outcome, steps, notes };
+        }
+
+        let or = rule.get("or").and_then(Value::as_bool).unwrap_or(false);
+        if !set_matches && !or {
+            steps.push(TraceStep { index, rule: description, result: "skipped" });
+            continue;
+        }
+        let not = rule.get("not").and_then(Value::as_bool).unwrap_or(false);
+        let matched = match evaluate_match(rule, packet, inbound) {
+            Some(m) => m != not,
+            None => {
+                notes.push(format!("Rule #{} is probabilistic; assumed to match", index + 1));
+                true
+            }
+        };
+        if or {
+            set_matches |= matched;
+        } else {
+            set_matches &= matched;
+        }
+        steps.push(TraceStep {
+            index,
+            rule: description,
+            result: if matched { "match" } else { "no match" },
+        });
+    }
+
+    RuleSetTrace { name: name.to_string(), outcome: Outcome::NoMatch, steps, notes }
+}
+
+/// Filter the packet on one side. If the base rules don't decide, the sender's
+/// capabilities are tried in order; an accept (or redirect) in any of them accepts.
+fn filter(
+    title: &'static str,
+    base: &[Value],
+    capabilities: &[Capability],
+    packet: &Packet,
+    inbound: bool,
+) -> SideResult {
+    let base_trace = run_rule_set("Base rules", base, packet, inbound);
+    let decided = |trace: &RuleSetTrace| {
+        trace
+            .steps
+            .iter()
+            .rfind(|s| s.is_taken())
+            .map(|s| format!("{} #{}: {}", trace.name, s.index + 1, s.rule))
+            .unwrap_or_default()
+    };
+
+    match base_trace.outcome {
+        Outcome::Accept | Outcome::Redirect(_) => {
+            return SideResult { title, accepted: true, decided_by: decided(&base_trace), rule_sets: vec![base_trace] };
+        }
+        Outcome::Drop => {
+            return SideResult { title, accepted: false, decided_by: decided(&base_trace), rule_sets: vec![base_trace] };
+        }
+        Outcome::NoMatch => {}
+    }
+
+    let mut rule_sets = vec![base_trace];
+    for cap in capabilities {
+        let trace = run_rule_set(
+            &format!("Capability {} (#{})", cap.name, cap.id),
+            &cap.rules,
+            packet,
+            inbound,
+        );
+        let accepted = matches!(trace.outcome, Outcome::Accept | Outcome::Redirect(_));
+        let decided_by = decided(&trace);
+        rule_sets.push(trace);
+        if accepted {
+            return SideResult { title, accepted: true, decided_by, rule_sets };
+        }
+    }
+
+    SideResult {
+        title,
+        accepted: false,
+        decided_by: "No rule accepted the packet (implicit drop)".to_string(),
+        rule_sets,
+    }
+}
+
+/// Simulate the packet through the sender's outbound and receiver's inbound filters
+pub fn simulate(base: &[Value], sender_capabilities: &[Capability], packet: &Packet) -> Simulation {
+    let outbound = filter("Sender (outbound)", base, sender_capabilities, packet, false);
+    let inbound = outbound
+        .accepted
+        .then(|| filter("Receiver (inbound)", base, sender_capabilities, packet, true));
+    Simulation { outbound, inbound }
+}
@@ -0,0 +1,41 @@
+//! The poller's last view of ZeroTier, saved in the data directory so the dashboard and network
+//! pages have something to show right after a restart, while the first poll is still running.
+
+use std::path::PathBuf;
+
+use tracing::warn;
+
+use super::models::ZtState;
+use crate::state::data_dir;
+
+const FILENAME: &str = "zt-state.json";
+
+fn path() -> PathBuf {
+    data_dir().join(FILENAME)
+}
+
+/// The saved state, marked as restored; `None` when there is none or it can't be read
+pub fn load() -> Option<ZtState> {
+    let data = std::fs::read(path()).ok()?;
+    match serde_json::from_slice::<ZtState>(&data) {
+        Ok(state) => Some(ZtState { restored: true, ..state }),
+        Err(e) => {
+            warn!("Ignoring unreadable {}: {}", FILENAME, e);
+            None
+        }
+    }
+}
+
+/// Replace the saved state. Written to a temporary file first, so a crash never leaves half a file.
+pub async fn save(state: &ZtState) -> Result<(), String> {
+    let data = serde_json::to_vec(state).map_err(|e| format!("Failed to serialize ZeroTier state: {}", e))?;
+    tokio::task::spawn_blocking(move || {
+        let path = path();
+        let partial = path.with_extension("json.partial");
+        std::fs::write(&partial, data)
+            .and_then(|_| std::fs::rename(&partial, &path))
+            .map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+/// A built-in flow rules snippet shipped with TierDrop
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleSnippet {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub source: &'static str,
+}
+
+/// A snippet saved by an admin, persisted in the config
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomRuleSnippet {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub source: String,
+}
+
+/// Built-in snippets. Each is meant to be inserted above the final `accept;`.
+pub const BUILTIN_SNIPPETS: &[RuleSnippet] = &[
+    RuleSnippet {
+        id: "isolate-clients",
+        name: "Isolate clients",
+        description: "Members may only talk to a single gateway member.",
+        source: r#"# Isolate clients: members may only talk to the gateway.
+# Replace deadbeef00 with the gateway's ZeroTier address.
+drop
+	not ztsrc deadbeef00
+	and not ztdest deadbeef00
+	and not ethertype arp
+;
+"#,
+    },
+    RuleSnippet {
+        id: "servers-only",
+        name: "Allow only to servers",
+        description: "Tag model where clients can only reach members tagged as servers.",
+        source: r#"# Only allow traffic to and from members tagged as servers.
+# Assign the "role" tag to each member (client or server).
+tag role
+	id 100
+	enum 0 client
+	enum 1 server
+	default client
+;
+
+drop
+	not tseq role server
+	and not treq role server
+;
+"#,
+    },
+    RuleSnippet {
+        id: "block-smb",
+        name: "Block SMB / NetBIOS",
+        description: "Drop Windows file sharing traffic (TCP 445, TCP/UDP 137-139).",
+        source: r#"# Block SMB and NetBIOS file sharing.
+drop
+	ipprotocol tcp
+	and dport 445
+;
+drop
+	ipprotocol tcp
+	and dport 137-139
+;
+drop
+	ipprotocol udp
+	and dport 137-139
+;
+"#,
+    },
+    RuleSnippet {
+        id: "ssh-admins-only",
+        name: "SSH from admins only",
+        description: "Only members tagged as admins may open SSH connections.",
+        source: r#"# Only allow SSH from members tagged as admins.
+tag admin
+	id 101
+	enum 0 no
+	enum 1 yes
+	default no
+;
+
+drop
+	ipprotocol tcp
+	and dport 22
+	and not tseq admin yes
+;
+"#,
+    },
+    RuleSnippet {
+        id: "tee-tcp-syn",
+        name: "Observe new TCP connections",
+        description: "Copy every TCP SYN to an observer member.",
+        source: r#"# Send a copy of every new TCP connection attempt to an observer.
+# Replace deadbeef00 with the observer's ZeroTier address.
+tee -1 deadbeef00
+	ipprotocol tcp
+	and chr tcp_syn
+	and not chr tcp_ack
+;
+"#,
+    },
+    RuleSnippet {
+        id: "limit-multicast",
+        name: "Limit multicast and broadcast",
+        description: "Drop multicast/broadcast except ARP and IPv6 neighbor discovery.",
+        source: r#"# Drop all multicast and broadcast traffic except ARP and IPv6 neighbor discovery.
+drop
+	chr multicast
+	and not ethertype arp
+	and not ethertype ipv6
+;
+drop
+	chr broadcast
+	and not ethertype arp
+;
+"#,
+    },
+];
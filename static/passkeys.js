@@ -0,0 +1,107 @@
+// WebAuthn helpers. The server sends and expects binary fields as base64url strings.
+(function () {
+    // TierDrop may run under a sub-path; this script is served from <base>/static/passkeys.js
+    var basePath = document.currentScript
+        ? new URL(document.currentScript.src).pathname.replace(/\/static\/passkeys\.js$/, '')
+        : '';
+
+    function toBytes(b64url) {
+        var b64 = b64url.replace(/-/g, '+').replace(/_/g, '/');
+        while (b64.length % 4) b64 += '=';
+        var raw = atob(b64);
+        var bytes = new Uint8Array(raw.length);
+        for (var i = 0; i < raw.length; i++) bytes[i] = raw.charCodeAt(i);
+        return bytes.buffer;
+    }
+
+    function toB64url(buffer) {
+        var bytes = new Uint8Array(buffer);
+        var raw = '';
+        for (var i = 0; i < bytes.length; i++) raw += String.fromCharCode(bytes[i]);
+        return btoa(raw).replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '');
+    }
+
+    function credentialList(list) {
+        return (list || []).map(function (c) {
+            return { type: c.type, id: toBytes(c.id) };
+        });
+    }
+
+    function postJson(path, body) {
+        return fetch(basePath + path, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: body === undefined ? '{}' : JSON.stringify(body),
+            credentials: 'same-origin'
+        });
+    }
+
+    function describe(err) {
+        if (err && err.name === 'NotAllowedError') return 'Passkey request was cancelled or timed out.';
+        if (err && err.name === 'InvalidStateError') return 'This passkey is already registered.';
+        return (err && err.message) || 'Passkey request failed.';
+    }
+
+    // Settings > Account: register a passkey, then swap in the refreshed list
+    window.tierdropRegisterPasskey = async function (form) {
+        var panel = document.getElementById('passkeys-panel');
+        var errorBox = form.querySelector('.passkey-error');
+        errorBox.hidden = true;
+        try {
+            if (!window.PublicKeyCredential) throw new Error('This browser does not support passkeys.');
+            var res = await postJson('/settings/passkeys/options');
+            if (!res.ok) throw new Error(await res.text());
+            var options = await res.json();
+            options.challenge = toBytes(options.challenge);
+            options.user.id = toBytes(options.user.id);
+            options.excludeCredentials = credentialList(options.excludeCredentials);
+
+            var credential = await navigator.credentials.create({ publicKey: options });
+            var response = credential.response;
+            if (!response.getPublicKey) throw new Error('This browser cannot register passkeys with TierDrop.');
+            var html = await (await postJson('/settings/passkeys', {
+                id: credential.id,
+                client_data_json: toB64url(response.clientDataJSON),
+                authenticator_data: toB64url(response.getAuthenticatorData()),
+                public_key: toB64url(response.getPublicKey()),
+                public_key_algorithm: response.getPublicKeyAlgorithm(),
+                name: form.elements.name.value,
+                current_password: form.elements.current_password ? form.elements.current_password.value : ''
+            })).text();
+            panel.innerHTML = html;
+            htmx.process(panel);
+        } catch (err) {
+            errorBox.textContent = describe(err);
+            errorBox.hidden = false;
+        }
+    };
+
+    // Login and 2FA pages: sign in with a passkey
+    window.tierdropPasskeyLogin = async function (errorBox) {
+        errorBox.hidden = true;
+        try {
+            if (!window.PublicKeyCredential) throw new Error('This browser does not support passkeys.');
+            var res = await postJson('/login/passkey/options');
+            if (!res.ok) throw new Error((await res.json()).error);
+            var options = await res.json();
+            options.challenge = toBytes(options.challenge);
+            options.allowCredentials = credentialList(options.allowCredentials);
+
+            var credential = await navigator.credentials.get({ publicKey: options });
+            var response = credential.response;
+            res = await postJson('/login/passkey', {
+                id: credential.id,
+                client_data_json: toB64url(response.clientDataJSON),
+                authenticator_data: toB64url(response.authenticatorData),
+                signature: toB64url(response.signature),
+                remember: !!(document.getElementById('remember') || {}).checked
+            });
+            var result = await res.json();
+            if (!res.ok) throw new Error(result.error);
+            window.location.href = result.redirect;
+        } catch (err) {
+            errorBox.textContent = describe(err);
+            errorBox.hidden = false;
+        }
+    };
+})();